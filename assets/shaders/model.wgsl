@@ -0,0 +1,200 @@
+struct Camera {
+    view_proj: mat4x4<f32>,
+    position: vec4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+// Kept in step with `LIGHTING_WGSL` in graphics/lighting.rs.
+struct Light {
+    to_light: vec4<f32>,
+    color: vec4<f32>, // rgb times intensity, with the ambient share in a
+    camera_position: vec4<f32>,
+    camera_forward: vec4<f32>,
+    splits: vec4<f32>, // how far from the camera each cascade reaches
+    shadow: vec4<f32>, // cascade count, normal offset, texel size
+    cascades: array<mat4x4<f32>, 4>,
+};
+@group(1) @binding(0)
+var<uniform> light: Light;
+@group(1) @binding(1)
+var shadow_map: texture_depth_2d_array;
+@group(1) @binding(2)
+var shadow_sampler: sampler_comparison;
+
+struct LocalLight {
+    position: vec4<f32>, // with the range in w
+    color: vec4<f32>, // rgb times intensity
+    direction: vec4<f32>, // a spot's axis, with the cosine of its outer angle in w
+    cone: vec4<f32>, // the cosine of a spot's inner angle in x
+};
+struct LocalLights {
+    count: vec4<u32>,
+    lights: array<LocalLight, 16>,
+};
+@group(1) @binding(3)
+var<uniform> local_lights: LocalLights;
+
+// 1 where the light reaches `position`, 0 in full shadow, with a 3x3 PCF edge.
+fn shadow(position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    let count = i32(light.shadow.x);
+    let depth = dot(position - light.camera_position.xyz, light.camera_forward.xyz);
+    var cascade = 0;
+    loop {
+        if cascade >= count || depth <= light.splits[cascade] {
+            break;
+        }
+        cascade += 1;
+    }
+    if cascade >= count {
+        return 1.0;
+    }
+    let clip = light.cascades[cascade] * vec4<f32>(position + normal * light.shadow.y, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    if any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0 {
+        return 1.0;
+    }
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y += 1) {
+        for (var x = -1; x <= 1; x += 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * light.shadow.z;
+            lit += textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, cascade, ndc.z);
+        }
+    }
+    return lit / 9.0;
+}
+
+// What `shade` needs to know about a point on a surface.
+struct Surface {
+    albedo: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    position: vec3<f32>,
+    normal: vec3<f32>,
+};
+
+// Cook-Torrance with a GGX distribution, Smith geometry and Schlick's Fresnel, for light of
+// `radiance` arriving from `to_light`. Scaled by pi so a white light head-on lights a white matte
+// surface white, as the old lighting did.
+fn brdf(surface: Surface, normal: vec3<f32>, to_view: vec3<f32>, to_light: vec3<f32>, radiance: vec3<f32>) -> vec3<f32> {
+    let n_dot_l = max(dot(normal, to_light), 0.0);
+    let n_dot_v = max(dot(normal, to_view), 0.0001);
+    let half_vector = normalize(to_light + to_view);
+    let n_dot_h = max(dot(normal, half_vector), 0.0);
+    let roughness = clamp(surface.roughness, 0.04, 1.0);
+
+    let alpha = roughness * roughness;
+    let alpha2 = alpha * alpha;
+    let denominator = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    let distribution = alpha2 / (3.14159265 * denominator * denominator);
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    let geometry = n_dot_v / (n_dot_v * (1.0 - k) + k) * n_dot_l / (n_dot_l * (1.0 - k) + k);
+    let f0 = mix(vec3<f32>(0.04), surface.albedo, surface.metallic);
+    let fresnel = f0 + (vec3<f32>(1.0) - f0) * pow(1.0 - max(dot(half_vector, to_view), 0.0), 5.0);
+
+    let specular = distribution * geometry * fresnel / (4.0 * n_dot_v * n_dot_l + 0.0001);
+    let diffuse = (vec3<f32>(1.0) - fresnel) * (1.0 - surface.metallic) * surface.albedo;
+    return (diffuse + specular * 3.14159265) * radiance * n_dot_l;
+}
+
+fn shade(surface: Surface) -> vec3<f32> {
+    let to_view = normalize(light.camera_position.xyz - surface.position);
+    // Two-sided, since OBJ exports don't agree on winding.
+    var normal = normalize(surface.normal);
+    if dot(normal, to_view) < 0.0 {
+        normal = -normal;
+    }
+    let ambient = light.color.a;
+    let sun = light.color.rgb * shadow(surface.position, normal);
+    var color = surface.albedo * ambient + (1.0 - ambient) * brdf(surface, normal, to_view, light.to_light.xyz, sun);
+
+    for (var i = 0u; i < local_lights.count.x; i += 1u) {
+        let local = local_lights.lights[i];
+        let offset = local.position.xyz - surface.position;
+        let distance = length(offset);
+        let to_light = offset / max(distance, 0.0001);
+        // Inverse-square, windowed to reach zero at the range.
+        let window = clamp(1.0 - pow(distance / local.position.w, 4.0), 0.0, 1.0);
+        let attenuation = window * window / (distance * distance + 1.0);
+        let cone = clamp((dot(-to_light, local.direction.xyz) - local.direction.w) / max(local.cone.x - local.direction.w, 0.0001), 0.0, 1.0);
+        color += brdf(surface, normal, to_view, to_light, local.color.rgb * attenuation * cone);
+    }
+    return color;
+}
+struct Material {
+    base_color: vec4<f32>,
+    emissive: vec4<f32>,
+    factors: vec4<f32>, // metallic, roughness, normal scale
+};
+@group(2) @binding(0)
+var<uniform> material: Material;
+@group(2) @binding(1)
+var material_sampler: sampler;
+@group(2) @binding(2)
+var base_color_map: texture_2d<f32>;
+@group(2) @binding(3)
+var metallic_roughness_map: texture_2d<f32>;
+@group(2) @binding(4)
+var normal_map: texture_2d<f32>;
+@group(2) @binding(5)
+var emissive_map: texture_2d<f32>;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) normal: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) world_position: vec3<f32>,
+};
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(in.position, 1.0);
+    out.normal = in.normal;
+    out.uv = in.uv;
+    out.world_position = in.position;
+    return out;
+}
+
+// Bends `normal` by a tangent-space normal map sample. The tangent frame comes from screen-space
+// derivatives, so meshes don't need tangents.
+fn perturb_normal(normal: vec3<f32>, position: vec3<f32>, uv: vec2<f32>, sample: vec3<f32>) -> vec3<f32> {
+    let dp1 = dpdx(position);
+    let dp2 = dpdy(position);
+    let duv1 = dpdx(uv);
+    let duv2 = dpdy(uv);
+    let dp2_perp = cross(dp2, normal);
+    let dp1_perp = cross(normal, dp1);
+    let tangent = dp2_perp * duv1.x + dp1_perp * duv2.x;
+    let bitangent = dp2_perp * duv1.y + dp1_perp * duv2.y;
+    let length2 = max(dot(tangent, tangent), dot(bitangent, bitangent));
+    // Without UVs there's no frame to bend in.
+    let scale = select(0.0, inverseSqrt(length2), length2 > 1e-12);
+    let bent = normalize(mat3x3<f32>(tangent * scale, bitangent * scale, normal) * sample);
+    return select(normal, bent, length2 > 1e-12);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let base_color = material.base_color * textureSample(base_color_map, material_sampler, in.uv);
+    let metallic_roughness = textureSample(metallic_roughness_map, material_sampler, in.uv);
+    let normal_sample = textureSample(normal_map, material_sampler, in.uv).xyz * 2.0 - 1.0;
+    let emissive = material.emissive.rgb * textureSample(emissive_map, material_sampler, in.uv).rgb;
+
+    let normal = normalize(in.normal);
+    let bent = vec3<f32>(normal_sample.xy * material.factors.z, normal_sample.z);
+    var surface: Surface;
+    surface.albedo = base_color.rgb;
+    surface.metallic = material.factors.x * metallic_roughness.b;
+    surface.roughness = material.factors.y * metallic_roughness.g;
+    surface.position = in.world_position;
+    surface.normal = perturb_normal(normal, in.world_position, in.uv, bent);
+    return vec4<f32>(shade(surface) + emissive, base_color.a);
+}
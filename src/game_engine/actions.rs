@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+
+use super::gamepad::GamepadButton;
+use super::input::{KeyCode, MouseButton};
+
+// Something a player can press to trigger an action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Binding {
+  Key(KeyCode),
+  Mouse(MouseButton),
+  Gamepad(GamepadButton), // on any connected pad
+}
+
+// Named actions and what triggers them, so game code asks about "jump" instead of Space. Lives on
+// `Input` (`engine.input.actions`) and can be changed at any time, e.g. from a remapping menu.
+// Saved as RON: `{ "jump": [Key(Space), Gamepad(South)], ... }`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ActionMap {
+  bindings: BTreeMap<String, Vec<Binding>>,
+}
+
+impl ActionMap {
+  pub fn new() -> Self {
+    ActionMap::default()
+  }
+
+  // Adds another way to trigger `action`.
+  pub fn bind(&mut self, action: &str, binding: Binding) -> &mut Self {
+    let bindings = self.bindings.entry(action.to_string()).or_default();
+    if !bindings.contains(&binding) {
+      bindings.push(binding);
+    }
+    self
+  }
+
+  pub fn unbind(&mut self, action: &str, binding: Binding) {
+    if let Some(bindings) = self.bindings.get_mut(action) {
+      bindings.retain(|bound| *bound != binding);
+    }
+  }
+
+  // Swaps one binding of `action` for another, keeping its place; binds it if `old` wasn't bound.
+  pub fn rebind(&mut self, action: &str, old: Binding, new: Binding) {
+    let bindings = self.bindings.entry(action.to_string()).or_default();
+    match bindings.iter().position(|bound| *bound == old) {
+      Some(index) if !bindings.contains(&new) => bindings[index] = new,
+      Some(index) => {
+        bindings.remove(index);
+      }
+      None if !bindings.contains(&new) => bindings.push(new),
+      None => {}
+    }
+  }
+
+  // Leaves the action known but with nothing bound to it.
+  pub fn clear(&mut self, action: &str) {
+    if let Some(bindings) = self.bindings.get_mut(action) {
+      bindings.clear();
+    }
+  }
+
+  pub fn remove(&mut self, action: &str) {
+    self.bindings.remove(action);
+  }
+
+  pub fn bindings(&self, action: &str) -> &[Binding] {
+    self.bindings.get(action).map_or(&[], |bindings| bindings.as_slice())
+  }
+
+  pub fn actions(&self) -> impl Iterator<Item = &str> {
+    self.bindings.keys().map(|action| action.as_str())
+  }
+
+  // The actions `binding` triggers, for warning about conflicts when remapping.
+  pub fn actions_for(&self, binding: Binding) -> impl Iterator<Item = &str> {
+    self.bindings.iter().filter(move |(_, bindings)| bindings.contains(&binding)).map(|(action, _)| action.as_str())
+  }
+
+  // Replaces the bindings of every action in `other`, leaving the rest alone, so a saved file from
+  // an older version of the game doesn't lose actions added since.
+  pub fn merge(&mut self, other: ActionMap) {
+    self.bindings.extend(other.bindings);
+  }
+
+  pub fn from_ron(text: &str) -> Result<Self, String> {
+    ron::from_str(text).map_err(|err| format!("bad bindings: {}", err))
+  }
+
+  pub fn to_ron(&self) -> Result<String, String> {
+    ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).map_err(|err| err.to_string())
+  }
+
+  pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).map_err(|err| format!("couldn't read {}: {}", path.display(), err))?;
+    ron::from_str(&text).map_err(|err| format!("bad bindings {}: {}", path.display(), err))
+  }
+
+  pub fn save(&self, path: impl AsRef<Path>) -> Result<(), String> {
+    let path = path.as_ref();
+    std::fs::write(path, self.to_ron()?).map_err(|err| format!("couldn't write {}: {}", path.display(), err))
+  }
+}
@@ -0,0 +1,576 @@
+use std::io::Read;
+use std::path::Path;
+use flate2::read::ZlibDecoder;
+use serde::{Deserialize, Serialize};
+
+use super::store::Asset;
+
+const HEADER_MAGIC: u16 = 0xA5E0;
+const FRAME_MAGIC: u16 = 0xF1FA;
+
+const CHUNK_OLD_PALETTE: u16 = 0x0004;
+const CHUNK_LAYER: u16 = 0x2004;
+const CHUNK_CEL: u16 = 0x2005;
+const CHUNK_TAGS: u16 = 0x2018;
+const CHUNK_PALETTE: u16 = 0x2019;
+const CHUNK_SLICE: u16 = 0x2022;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum AnimationDirection {
+  #[default]
+  Forward,
+  Reverse,
+  PingPong,
+  PingPongReverse,
+}
+
+// A fully composited frame: `width * height` straight-alpha RGBA8 pixels.
+#[derive(Debug, Clone)]
+pub struct AsepriteFrame {
+  pub duration: f32, // seconds
+  pub pixels: Vec<u8>,
+}
+
+// A tag is a named frame range, which is how Aseprite artists mark up animations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsepriteTag {
+  pub name: String,
+  pub from: usize,
+  pub to: usize, // inclusive
+  pub direction: AnimationDirection,
+  pub repeat: u16, // 0 loops forever
+}
+
+// A named frame sequence ready for sprite playback.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpriteAnimation {
+  pub name: String,
+  pub frames: Vec<usize>,
+  pub durations: Vec<f32>,
+  pub direction: AnimationDirection,
+  pub repeat: u16,
+}
+
+impl SpriteAnimation {
+  // `frames` at a steady `fps`, looping forwards.
+  pub fn new(name: impl Into<String>, frames: Vec<usize>, fps: f32) -> Self {
+    let duration = 1.0 / fps.max(f32::EPSILON);
+    SpriteAnimation { name: name.into(), durations: vec![duration; frames.len()], frames, direction: AnimationDirection::Forward, repeat: 0 }
+  }
+
+  pub fn with_direction(mut self, direction: AnimationDirection) -> Self {
+    self.direction = direction;
+    self
+  }
+
+  // Plays `repeat` times and then stops on the last frame shown; 0 loops forever.
+  pub fn with_repeat(mut self, repeat: u16) -> Self {
+    self.repeat = repeat;
+    self
+  }
+
+  pub fn set_fps(&mut self, fps: f32) {
+    let duration = 1.0 / fps.max(f32::EPSILON);
+    self.durations.iter_mut().for_each(|frame| *frame = duration);
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SliceKey {
+  pub frame: usize, // the key applies from this frame onwards
+  pub x: i32,
+  pub y: i32,
+  pub width: u32,
+  pub height: u32,
+  pub center: Option<(i32, i32, u32, u32)>, // nine-slice centre, relative to the slice
+  pub pivot: Option<(i32, i32)>, // relative to the slice
+}
+
+// A named rectangle such as a hitbox, nine-slice border or attachment point.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsepriteSlice {
+  pub name: String,
+  pub keys: Vec<SliceKey>,
+}
+
+impl AsepriteSlice {
+  // The key in effect on `frame`.
+  pub fn key_at(&self, frame: usize) -> Option<&SliceKey> {
+    self.keys.iter().rev().find(|key| key.frame <= frame).or(self.keys.first())
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct AsepriteFile {
+  pub width: u32,
+  pub height: u32,
+  pub frames: Vec<AsepriteFrame>,
+  pub tags: Vec<AsepriteTag>,
+  pub slices: Vec<AsepriteSlice>,
+}
+
+struct Layer {
+  visible: bool,
+  opacity: u8,
+  child_level: u16,
+  is_group: bool,
+}
+
+enum CelPixels {
+  Image { width: u32, height: u32, data: Vec<u8> },
+  Linked(usize),
+}
+
+struct Cel {
+  layer: usize,
+  x: i32,
+  y: i32,
+  opacity: u8,
+  pixels: CelPixels,
+}
+
+impl AsepriteFile {
+  pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path).map_err(|err| format!("couldn't read {}: {}", path.display(), err))?;
+    AsepriteFile::from_bytes(&bytes)
+  }
+
+  pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+    let mut reader = Reader { bytes, position: 0 };
+
+    reader.skip(4)?; // file size
+    if reader.u16()? != HEADER_MAGIC {
+      return Err("not an aseprite file".to_string());
+    }
+    let frame_count = reader.u16()? as usize;
+    let width = reader.u16()? as u32;
+    let height = reader.u16()? as u32;
+    let color_depth = reader.u16()?;
+    let layer_opacity_valid = reader.u32()? & 1 != 0;
+    reader.skip(2 + 4 + 4)?; // speed, reserved
+    let transparent_index = reader.u8()?;
+    let bytes_per_pixel = match color_depth {
+      32 => 4,
+      16 => 2,
+      _ => 1,
+    };
+    reader.skip(128 - reader.position)?;
+
+    let mut layers: Vec<Layer> = Vec::new();
+    let mut palette = vec![[0u8; 4]; 256];
+    let mut tags = Vec::new();
+    let mut slices = Vec::new();
+    let mut frame_cels: Vec<Vec<Cel>> = Vec::with_capacity(frame_count);
+    let mut durations = Vec::with_capacity(frame_count);
+
+    for _ in 0..frame_count {
+      let frame_start = reader.position;
+      let frame_size = reader.u32()? as usize;
+      if reader.u16()? != FRAME_MAGIC {
+        return Err("corrupt frame header".to_string());
+      }
+      let old_chunk_count = reader.u16()? as u32;
+      durations.push(reader.u16()? as f32 / 1000.0);
+      reader.skip(2)?;
+      let chunk_count = match reader.u32()? {
+        0 => old_chunk_count,
+        count => count,
+      };
+
+      let mut cels = Vec::new();
+      for _ in 0..chunk_count {
+        let chunk_start = reader.position;
+        let chunk_size = reader.u32()? as usize;
+        let chunk_type = reader.u16()?;
+        let mut chunk = Reader { bytes: reader.slice(chunk_start + 6, chunk_start + chunk_size)?, position: 0 };
+
+        match chunk_type {
+          CHUNK_LAYER => {
+            let flags = chunk.u16()?;
+            let layer_type = chunk.u16()?;
+            let child_level = chunk.u16()?;
+            chunk.skip(2 + 2 + 2)?; // default size, blend mode
+            let opacity = chunk.u8()?;
+            layers.push(Layer {
+              visible: flags & 1 != 0,
+              opacity: if layer_opacity_valid { opacity } else { 255 },
+              child_level,
+              is_group: layer_type == 1,
+            });
+          }
+          CHUNK_CEL => {
+            let layer = chunk.u16()? as usize;
+            let x = chunk.i16()? as i32;
+            let y = chunk.i16()? as i32;
+            let opacity = chunk.u8()?;
+            let cel_type = chunk.u16()?;
+            chunk.skip(2 + 5)?; // z-index, reserved
+            let pixels = match cel_type {
+              0 | 2 => {
+                let width = chunk.u16()? as u32;
+                let height = chunk.u16()? as u32;
+                let expected = (width * height) as usize * bytes_per_pixel;
+                let raw = chunk.rest();
+                let data = if cel_type == 2 {
+                  let mut data = Vec::new();
+                  ZlibDecoder::new(raw).take(expected as u64).read_to_end(&mut data).map_err(|err| format!("bad cel data: {}", err))?;
+                  data
+                } else {
+                  raw.get(..expected).unwrap_or(raw).to_vec()
+                };
+                if data.len() != expected {
+                  return Err(format!("cel has {} bytes of pixels, expected {}", data.len(), expected));
+                }
+                CelPixels::Image { width, height, data }
+              }
+              1 => CelPixels::Linked(chunk.u16()? as usize),
+              _ => continue, // tilemap cels aren't supported
+            };
+            cels.push(Cel { layer, x, y, opacity, pixels });
+          }
+          CHUNK_TAGS => {
+            let count = chunk.u16()?;
+            chunk.skip(8)?;
+            for _ in 0..count {
+              let from = chunk.u16()? as usize;
+              let to = chunk.u16()? as usize;
+              let direction = match chunk.u8()? {
+                1 => AnimationDirection::Reverse,
+                2 => AnimationDirection::PingPong,
+                3 => AnimationDirection::PingPongReverse,
+                _ => AnimationDirection::Forward,
+              };
+              let repeat = chunk.u16()?;
+              chunk.skip(6 + 3 + 1)?; // reserved, deprecated color
+              tags.push(AsepriteTag { name: chunk.string()?, from, to, direction, repeat });
+            }
+          }
+          CHUNK_PALETTE => {
+            chunk.skip(4)?; // new palette size; indices are bytes, so 256 entries always cover it
+            let first = chunk.u32()? as usize;
+            let last = chunk.u32()? as usize;
+            chunk.skip(8)?;
+            if first > last || last >= palette.len() {
+              return Err(format!("bad palette range {}..={}", first, last));
+            }
+            for entry in &mut palette[first..=last] {
+              let flags = chunk.u16()?;
+              *entry = [chunk.u8()?, chunk.u8()?, chunk.u8()?, chunk.u8()?];
+              if flags & 1 != 0 {
+                chunk.string()?;
+              }
+            }
+          }
+          CHUNK_OLD_PALETTE => {
+            let packets = chunk.u16()?;
+            let mut index = 0;
+            for _ in 0..packets {
+              index += chunk.u8()? as usize;
+              let count = match chunk.u8()? {
+                0 => 256,
+                count => count as usize,
+              };
+              for _ in 0..count {
+                if index < palette.len() {
+                  palette[index] = [chunk.u8()?, chunk.u8()?, chunk.u8()?, 255];
+                }
+                index += 1;
+              }
+            }
+          }
+          CHUNK_SLICE => {
+            let key_count = chunk.u32()?;
+            let flags = chunk.u32()?;
+            chunk.skip(4)?;
+            let name = chunk.string()?;
+            let mut keys = Vec::new();
+            for _ in 0..key_count {
+              let frame = chunk.u32()? as usize;
+              let (x, y, width, height) = (chunk.i32()?, chunk.i32()?, chunk.u32()?, chunk.u32()?);
+              let center = match flags & 1 {
+                0 => None,
+                _ => Some((chunk.i32()?, chunk.i32()?, chunk.u32()?, chunk.u32()?)),
+              };
+              let pivot = match flags & 2 {
+                0 => None,
+                _ => Some((chunk.i32()?, chunk.i32()?)),
+              };
+              keys.push(SliceKey { frame, x, y, width, height, center, pivot });
+            }
+            slices.push(AsepriteSlice { name, keys });
+          }
+          _ => {}
+        }
+
+        reader.position = chunk_start + chunk_size;
+      }
+
+      frame_cels.push(cels);
+      reader.position = frame_start + frame_size;
+    }
+
+    let visible = effective_visibility(&layers);
+    let to_rgba = |data: &[u8], index: usize| -> [u8; 4] {
+      match color_depth {
+        32 => [data[index * 4], data[index * 4 + 1], data[index * 4 + 2], data[index * 4 + 3]],
+        16 => [data[index * 2], data[index * 2], data[index * 2], data[index * 2 + 1]],
+        _ => match data[index] {
+          value if value == transparent_index => [0; 4],
+          value => palette[value as usize],
+        },
+      }
+    };
+
+    let mut frames = Vec::with_capacity(frame_count);
+    for (frame, duration) in durations.iter().enumerate() {
+      let mut pixels = vec![0u8; (width * height * 4) as usize];
+      let mut cels: Vec<&Cel> = frame_cels[frame].iter().collect();
+      cels.sort_by_key(|cel| cel.layer);
+
+      for cel in cels {
+        let layer = match layers.get(cel.layer) {
+          Some(layer) if visible[cel.layer] && !layer.is_group => layer,
+          _ => continue,
+        };
+        let image = match &cel.pixels {
+          CelPixels::Linked(source) => frame_cels.get(*source)
+            .and_then(|cels| cels.iter().find(|source| source.layer == cel.layer))
+            .map(|source| &source.pixels),
+          pixels => Some(pixels),
+        };
+        let (cel_width, cel_height, data) = match image {
+          Some(CelPixels::Image { width, height, data }) => (*width, *height, data),
+          _ => continue,
+        };
+        let opacity = cel.opacity as u32 * layer.opacity as u32 / 255;
+
+        for cy in 0..cel_height {
+          for cx in 0..cel_width {
+            let (x, y) = (cel.x + cx as i32, cel.y + cy as i32);
+            if x < 0 || y < 0 || x >= width as i32 || y >= height as i32 {
+              continue;
+            }
+            let source = to_rgba(data, (cy * cel_width + cx) as usize);
+            let target = ((y as u32 * width + x as u32) * 4) as usize;
+            blend(&mut pixels[target..target + 4], source, opacity);
+          }
+        }
+      }
+
+      frames.push(AsepriteFrame { duration: *duration, pixels });
+    }
+
+    Ok(AsepriteFile { width, height, frames, tags, slices })
+  }
+
+  // Every tag as an animation. Files without tags get a single "default" animation over all frames.
+  pub fn animations(&self) -> Vec<SpriteAnimation> {
+    let animation = |name: &str, from: usize, to: usize, direction, repeat| {
+      let frames: Vec<usize> = (from..=to.min(self.frames.len().saturating_sub(1))).collect();
+      SpriteAnimation {
+        name: name.to_string(),
+        durations: frames.iter().map(|&frame| self.frames[frame].duration).collect(),
+        frames,
+        direction,
+        repeat,
+      }
+    };
+    if self.tags.is_empty() {
+      return vec![animation("default", 0, self.frames.len().saturating_sub(1), AnimationDirection::Forward, 0)];
+    }
+    self.tags.iter().map(|tag| animation(&tag.name, tag.from, tag.to, tag.direction, tag.repeat)).collect()
+  }
+
+  pub fn animation(&self, name: &str) -> Option<SpriteAnimation> {
+    self.animations().into_iter().find(|animation| animation.name == name)
+  }
+
+  pub fn slice(&self, name: &str) -> Option<&AsepriteSlice> {
+    self.slices.iter().find(|slice| slice.name == name)
+  }
+
+  // All frames side by side in one RGBA8 strip (`width * frames` wide), for uploading as a single
+  // texture; frame `n` starts at x = `n * width`.
+  pub fn sprite_sheet(&self) -> Vec<u8> {
+    let row = (self.width * 4) as usize;
+    let mut sheet = Vec::with_capacity(row * self.frames.len() * self.height as usize);
+    for y in 0..self.height as usize {
+      for frame in &self.frames {
+        sheet.extend_from_slice(&frame.pixels[y * row..(y + 1) * row]);
+      }
+    }
+    sheet
+  }
+}
+
+// A layer is only drawn if it and every group above it are visible. Layers are stored in order,
+// with each one's parent being the nearest earlier layer one level up.
+fn effective_visibility(layers: &[Layer]) -> Vec<bool> {
+  let mut parents: Vec<bool> = Vec::new();
+  layers.iter().map(|layer| {
+    parents.truncate(layer.child_level as usize);
+    let visible = layer.visible && parents.iter().all(|&parent| parent);
+    parents.push(visible);
+    visible
+  }).collect()
+}
+
+// Straight-alpha "normal" blending of `source` onto `target`.
+fn blend(target: &mut [u8], source: [u8; 4], opacity: u32) {
+  let source_alpha = source[3] as u32 * opacity / 255;
+  if source_alpha == 0 {
+    return;
+  }
+  let target_alpha = target[3] as u32;
+  let out_alpha = source_alpha + target_alpha * (255 - source_alpha) / 255;
+  for channel in 0..3 {
+    let blended = (source[channel] as u32 * source_alpha
+      + target[channel] as u32 * target_alpha * (255 - source_alpha) / 255) / out_alpha;
+    target[channel] = blended as u8;
+  }
+  target[3] = out_alpha as u8;
+}
+
+struct Reader<'a> {
+  bytes: &'a [u8],
+  position: usize,
+}
+
+impl<'a> Reader<'a> {
+  fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+    let bytes = self.slice(self.position, self.position + count)?;
+    self.position += count;
+    Ok(bytes)
+  }
+
+  fn slice(&self, start: usize, end: usize) -> Result<&'a [u8], String> {
+    self.bytes.get(start..end).ok_or_else(|| "unexpected end of file".to_string())
+  }
+
+  fn rest(&mut self) -> &'a [u8] {
+    let rest = &self.bytes[self.position.min(self.bytes.len())..];
+    self.position = self.bytes.len();
+    rest
+  }
+
+  fn skip(&mut self, count: usize) -> Result<(), String> {
+    self.take(count).map(|_| ())
+  }
+
+  fn u8(&mut self) -> Result<u8, String> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn u16(&mut self) -> Result<u16, String> {
+    Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+  }
+
+  fn i16(&mut self) -> Result<i16, String> {
+    Ok(i16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+  }
+
+  fn u32(&mut self) -> Result<u32, String> {
+    Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+  }
+
+  fn i32(&mut self) -> Result<i32, String> {
+    Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+  }
+
+  fn string(&mut self) -> Result<String, String> {
+    let length = self.u16()? as usize;
+    Ok(String::from_utf8_lossy(self.take(length)?).into_owned())
+  }
+}
+
+impl Asset for AsepriteFile {
+  fn from_bytes(bytes: &[u8], _path: &Path) -> Result<Self, String> {
+    AsepriteFile::from_bytes(bytes)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn chunk(kind: u16, data: &[u8]) -> Vec<u8> {
+    let mut bytes = ((data.len() + 6) as u32).to_le_bytes().to_vec();
+    bytes.extend_from_slice(&kind.to_le_bytes());
+    bytes.extend_from_slice(data);
+    bytes
+  }
+
+  // A one-frame, one-layer 32-bit file with the given chunks after the layer.
+  fn file(width: u16, height: u16, chunks: &[Vec<u8>]) -> Vec<u8> {
+    let mut layer = Vec::new();
+    layer.extend_from_slice(&1u16.to_le_bytes()); // visible
+    layer.extend_from_slice(&[0; 10]); // type, child level, default size, blend mode
+    layer.push(255);
+    layer.extend_from_slice(&[0; 3]);
+    layer.extend_from_slice(&0u16.to_le_bytes()); // empty name
+    let count = 1 + chunks.len();
+    let chunks: Vec<u8> = std::iter::once(chunk(CHUNK_LAYER, &layer)).chain(chunks.iter().cloned()).flatten().collect();
+
+    let mut frame = ((chunks.len() + 16) as u32).to_le_bytes().to_vec();
+    frame.extend_from_slice(&FRAME_MAGIC.to_le_bytes());
+    frame.extend_from_slice(&(count as u16).to_le_bytes());
+    frame.extend_from_slice(&100u16.to_le_bytes()); // duration
+    frame.extend_from_slice(&[0; 2]);
+    frame.extend_from_slice(&(count as u32).to_le_bytes());
+    frame.extend_from_slice(&chunks);
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+    bytes.extend_from_slice(&HEADER_MAGIC.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&width.to_le_bytes());
+    bytes.extend_from_slice(&height.to_le_bytes());
+    bytes.extend_from_slice(&32u16.to_le_bytes());
+    bytes.extend_from_slice(&1u32.to_le_bytes()); // layer opacity is valid
+    bytes.resize(128, 0);
+    bytes.extend_from_slice(&frame);
+    bytes
+  }
+
+  fn cel(width: u16, height: u16, pixels: &[u8]) -> Vec<u8> {
+    let mut data = vec![0; 4]; // layer, x
+    data.extend_from_slice(&0i16.to_le_bytes()); // y
+    data.push(255);
+    data.extend_from_slice(&0u16.to_le_bytes()); // raw image
+    data.extend_from_slice(&[0; 7]);
+    data.extend_from_slice(&width.to_le_bytes());
+    data.extend_from_slice(&height.to_le_bytes());
+    data.extend_from_slice(pixels);
+    chunk(CHUNK_CEL, &data)
+  }
+
+  fn palette(first: u32, last: u32) -> Vec<u8> {
+    let mut data = 256u32.to_le_bytes().to_vec();
+    data.extend_from_slice(&first.to_le_bytes());
+    data.extend_from_slice(&last.to_le_bytes());
+    data.extend_from_slice(&[0; 8]);
+    chunk(CHUNK_PALETTE, &data)
+  }
+
+  #[test]
+  fn reads_a_raw_cel() {
+    let pixels = [255, 0, 0, 255, 0, 0, 255, 128];
+    let file = AsepriteFile::from_bytes(&file(2, 1, &[cel(2, 1, &pixels)])).unwrap();
+    assert_eq!((file.width, file.height), (2, 1));
+    assert_eq!(file.frames.len(), 1);
+    assert_eq!(file.frames[0].duration, 0.1);
+    assert_eq!(file.frames[0].pixels, pixels);
+  }
+
+  #[test]
+  fn rejects_a_truncated_cel() {
+    assert!(AsepriteFile::from_bytes(&file(2, 2, &[cel(2, 2, &[255; 12])])).is_err());
+  }
+
+  #[test]
+  fn rejects_bad_palette_ranges() {
+    assert!(AsepriteFile::from_bytes(&file(1, 1, &[palette(3, 2)])).is_err());
+    assert!(AsepriteFile::from_bytes(&file(1, 1, &[palette(0, u32::MAX)])).is_err());
+  }
+}
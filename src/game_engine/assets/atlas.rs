@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::path::Path;
+use glam::{UVec2, Vec2};
+use serde_json::Value;
+
+use super::store::Asset;
+use super::xml::xml_elements;
+
+// One sprite packed into an atlas. `x`/`y` is where its (possibly trimmed) pixels start in the
+// texture and `width`/`height` their unrotated size; `source_size` and `offset` restore the
+// transparent border the packer trimmed away, so sprites line up the same as the untrimmed originals.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AtlasRegion {
+  pub name: String,
+  pub x: u32,
+  pub y: u32,
+  pub width: u32,
+  pub height: u32,
+  pub rotated: bool, // stored rotated 90 degrees clockwise in the texture
+  pub source_size: UVec2,
+  pub offset: UVec2, // top-left of the trimmed pixels within the original sprite
+  pub pivot: Vec2, // normalised, (0, 0) is top-left of the original sprite
+}
+
+impl AtlasRegion {
+  pub fn is_trimmed(&self) -> bool {
+    self.offset != UVec2::ZERO || self.source_size != UVec2::new(self.width, self.height)
+  }
+
+  // The region's normalised texture coordinates, as (top-left, bottom-right).
+  pub fn uv_rect(&self, texture_size: UVec2) -> (Vec2, Vec2) {
+    let (width, height) = if self.rotated { (self.height, self.width) } else { (self.width, self.height) };
+    let size = texture_size.as_vec2();
+    (Vec2::new(self.x as f32, self.y as f32) / size, Vec2::new((self.x + width) as f32, (self.y + height) as f32) / size)
+  }
+}
+
+// Sprite regions loaded from a pre-packed atlas's metadata, looked up by name.
+#[derive(Debug, Clone, Default)]
+pub struct TextureAtlas {
+  pub image: Option<String>, // texture file named by the metadata, relative to it
+  pub size: Option<UVec2>, // not every format records the texture size
+  regions: Vec<AtlasRegion>,
+  by_name: HashMap<String, usize>,
+  animations: Vec<(String, Vec<usize>)>, // named frame lists, as indices into `regions`
+}
+
+impl TextureAtlas {
+  // Picks the format from the extension: `.json` for TexturePacker's JSON (hash or array),
+  // `.xml` for Sparrow/Starling.
+  pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).map_err(|err| format!("couldn't read {}: {}", path.display(), err))?;
+    match path.extension().and_then(|extension| extension.to_str()) {
+      Some("json") => TextureAtlas::from_json(&text),
+      Some("xml") => TextureAtlas::from_xml(&text),
+      _ => Err(format!("unknown atlas format: {}", path.display())),
+    }
+  }
+
+  pub fn from_json(text: &str) -> Result<Self, String> {
+    let root: Value = serde_json::from_str(text).map_err(|err| format!("bad atlas json: {}", err))?;
+    let mut atlas = TextureAtlas::default();
+
+    if let Some(meta) = root.get("meta") {
+      atlas.image = meta.get("image").and_then(Value::as_str).map(str::to_string);
+      atlas.size = meta.get("size").map(|size| UVec2::new(field(size, "w"), field(size, "h")));
+    }
+
+    match root.get("frames") {
+      Some(Value::Object(frames)) => {
+        for (name, frame) in frames {
+          atlas.push(json_region(name, frame)?);
+        }
+      }
+      Some(Value::Array(frames)) => {
+        for frame in frames {
+          let name = frame.get("filename").and_then(Value::as_str).ok_or("atlas frame has no filename")?;
+          atlas.push(json_region(name, frame)?);
+        }
+      }
+      _ => return Err("atlas json has no frames".to_string()),
+    }
+
+    // TexturePacker's Pixi/Phaser exports list animations as frame names.
+    if let Some(Value::Object(animations)) = root.get("animations") {
+      for (name, frames) in animations {
+        let frames = frames.as_array().ok_or_else(|| format!("atlas animation {} isn't a list", name))?
+          .iter()
+          .map(|frame| {
+            let frame = frame.as_str().ok_or_else(|| format!("atlas animation {} has a frame that isn't a name", name))?;
+            atlas.by_name.get(frame).copied().ok_or_else(|| format!("atlas animation {} has unknown frame {}", name, frame))
+          })
+          .collect::<Result<Vec<_>, String>>()?;
+        atlas.animations.push((name.clone(), frames));
+      }
+    }
+    Ok(atlas)
+  }
+
+  // Sparrow/Starling XML: `<TextureAtlas imagePath="..."><SubTexture name="..." x=".." .../>`.
+  pub fn from_xml(text: &str) -> Result<Self, String> {
+    let mut atlas = TextureAtlas::default();
+    for element in xml_elements(text).into_iter().filter(|element| !element.closing) {
+      let attributes = &element.attributes;
+      let number = |key: &str| element.attribute::<f32>(key);
+      match element.name {
+        "TextureAtlas" => {
+          atlas.image = attributes.get("imagePath").map(|path| path.to_string());
+          if let (Some(width), Some(height)) = (number("width"), number("height")) {
+            atlas.size = Some(UVec2::new(width as u32, height as u32));
+          }
+        }
+        "SubTexture" => {
+          let name = attributes.get("name").ok_or("SubTexture has no name")?.to_string();
+          let (width, height) = (number("width").unwrap_or(0.0) as u32, number("height").unwrap_or(0.0) as u32);
+          // Sparrow stores the trim as a negative frame offset.
+          let offset = UVec2::new(-number("frameX").unwrap_or(0.0) as u32, -number("frameY").unwrap_or(0.0) as u32);
+          let source_size = UVec2::new(
+            number("frameWidth").map_or(width, |value| value as u32),
+            number("frameHeight").map_or(height, |value| value as u32),
+          );
+          let pivot = match (number("pivotX"), number("pivotY")) {
+            (Some(x), Some(y)) => Vec2::new(x, y) / source_size.as_vec2().max(Vec2::ONE),
+            _ => Vec2::splat(0.5),
+          };
+          atlas.push(AtlasRegion {
+            name,
+            x: number("x").unwrap_or(0.0) as u32,
+            y: number("y").unwrap_or(0.0) as u32,
+            width,
+            height,
+            rotated: attributes.get("rotated").is_some_and(|value| *value == "true"),
+            source_size,
+            offset,
+            pivot,
+          });
+        }
+        _ => {}
+      }
+    }
+    if atlas.regions.is_empty() {
+      return Err("atlas xml has no SubTextures".to_string());
+    }
+    Ok(atlas)
+  }
+
+  fn push(&mut self, region: AtlasRegion) {
+    self.by_name.insert(region.name.clone(), self.regions.len());
+    self.regions.push(region);
+  }
+
+  pub fn get(&self, name: &str) -> Option<&AtlasRegion> {
+    self.by_name.get(name).map(|&index| &self.regions[index])
+  }
+
+  pub fn regions(&self) -> &[AtlasRegion] {
+    &self.regions
+  }
+
+  // An animation's frames, in order, from the atlas's `animations`.
+  pub fn animation(&self, name: &str) -> Option<Vec<&AtlasRegion>> {
+    let (_, frames) = self.animations.iter().find(|(other, _)| other == name)?;
+    Some(frames.iter().map(|&index| &self.regions[index]).collect())
+  }
+
+  // Animations' names and frames as indices into `regions`.
+  pub fn animations(&self) -> impl Iterator<Item = (&str, &[usize])> {
+    self.animations.iter().map(|(name, frames)| (name.as_str(), frames.as_slice()))
+  }
+
+  // Regions whose names start with `prefix`, sorted by name, for the usual `run_0`, `run_1`, ...
+  // naming of animation frames.
+  pub fn sequence(&self, prefix: &str) -> Vec<&AtlasRegion> {
+    let mut frames: Vec<&AtlasRegion> = self.regions.iter().filter(|region| region.name.starts_with(prefix)).collect();
+    frames.sort_by(|a, b| natural_order(&a.name, &b.name));
+    frames
+  }
+}
+
+fn field(value: &Value, key: &str) -> u32 {
+  value.get(key).and_then(Value::as_f64).unwrap_or(0.0) as u32
+}
+
+fn json_region(name: &str, frame: &Value) -> Result<AtlasRegion, String> {
+  let rect = frame.get("frame").ok_or_else(|| format!("atlas frame {} has no rect", name))?;
+  let (width, height) = (field(rect, "w"), field(rect, "h"));
+  let rotated = frame.get("rotated").and_then(Value::as_bool).unwrap_or(false);
+  let trimmed = frame.get("spriteSourceSize");
+  let source = frame.get("sourceSize");
+  Ok(AtlasRegion {
+    name: name.to_string(),
+    x: field(rect, "x"),
+    y: field(rect, "y"),
+    width,
+    height,
+    rotated,
+    source_size: source.map_or(UVec2::new(width, height), |size| UVec2::new(field(size, "w"), field(size, "h"))),
+    offset: trimmed.map_or(UVec2::ZERO, |trim| UVec2::new(field(trim, "x"), field(trim, "y"))),
+    pivot: frame.get("pivot").map_or(Vec2::splat(0.5), |pivot| Vec2::new(
+      pivot.get("x").and_then(Value::as_f64).unwrap_or(0.5) as f32,
+      pivot.get("y").and_then(Value::as_f64).unwrap_or(0.5) as f32,
+    )),
+  })
+}
+
+// Compares names so that "run_2" sorts before "run_10".
+fn natural_order(a: &str, b: &str) -> std::cmp::Ordering {
+  let split = |name: &str| {
+    let digits = name.trim_end_matches(|c: char| !c.is_ascii_digit());
+    let start = digits.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    (name[..start].to_string(), digits[start..].parse::<u64>().unwrap_or(0), name.to_string())
+  };
+  split(a).cmp(&split(b))
+}
+
+impl Asset for TextureAtlas {
+  fn from_bytes(bytes: &[u8], path: &Path) -> Result<Self, String> {
+    let text = std::str::from_utf8(bytes).map_err(|err| format!("atlas isn't utf-8: {}", err))?;
+    match path.extension().and_then(|extension| extension.to_str()) {
+      Some("json") => TextureAtlas::from_json(text),
+      Some("xml") => TextureAtlas::from_xml(text),
+      _ => Err(format!("unknown atlas format: {}", path.display())),
+    }
+  }
+}
@@ -0,0 +1,75 @@
+use std::sync::{Arc, Mutex, Weak};
+use glam::Vec2;
+
+use crate::game_engine::graphics::color::Color;
+use crate::game_engine::ui::UiDraw;
+use super::store::{Handle, LoadState};
+
+// Follows a batch of `Assets::load_async` loads so a game can show how far along they are rather
+// than hitching on the first frame that needs them. It doesn't keep anything loaded: a load whose
+// handles are all dropped counts as done.
+#[derive(Debug, Clone, Default)]
+pub struct LoadingScreen {
+  loads: Vec<Weak<Mutex<LoadState>>>,
+}
+
+impl LoadingScreen {
+  pub fn new() -> Self {
+    LoadingScreen::default()
+  }
+
+  pub fn with<T>(mut self, handle: &Handle<T>) -> Self {
+    self.track(handle);
+    self
+  }
+
+  pub fn track<T>(&mut self, handle: &Handle<T>) {
+    self.loads.push(Arc::downgrade(handle.shared_state()));
+  }
+
+  pub fn clear(&mut self) {
+    self.loads.clear();
+  }
+
+  pub fn total(&self) -> usize {
+    self.loads.len()
+  }
+
+  fn states(&self) -> impl Iterator<Item = Option<LoadState>> + '_ {
+    self.loads.iter().map(|load| load.upgrade().map(|state| state.lock().unwrap().clone()))
+  }
+
+  // Loads that have succeeded or failed.
+  pub fn finished(&self) -> usize {
+    self.states().filter(|state| *state != Some(LoadState::Loading)).count()
+  }
+
+  // From 0 to 1; 1 with nothing to load.
+  pub fn progress(&self) -> f32 {
+    match self.total() {
+      0 => 1.0,
+      total => self.finished() as f32 / total as f32,
+    }
+  }
+
+  pub fn is_done(&self) -> bool {
+    self.finished() == self.total()
+  }
+
+  // Why each failed load failed, so far.
+  pub fn failures(&self) -> Vec<String> {
+    self.states().filter_map(|state| match state {
+      Some(LoadState::Failed(err)) => Some(err),
+      _ => None,
+    }).collect()
+  }
+
+  // A progress bar filling from the left between `min` and `max`, in UI pixels.
+  pub fn draw(&self, ui: &mut UiDraw, min: Vec2, max: Vec2, background: Color, fill: Color) {
+    ui.rect(min, max, background);
+    let progress = self.progress();
+    if progress > 0.0 {
+      ui.rect(min, Vec2::new(min.x + (max.x - min.x) * progress, max.y), fill);
+    }
+  }
+}
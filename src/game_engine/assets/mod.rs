@@ -0,0 +1,14 @@
+mod aseprite;
+mod atlas;
+mod loading;
+mod store;
+mod watcher;
+pub(crate) mod xml;
+
+pub use self::{
+  aseprite::*,
+  atlas::*,
+  loading::*,
+  store::*,
+  watcher::*
+};
@@ -0,0 +1,339 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+
+use crate::game_engine::EngineError;
+use crate::game_engine::jobs::Jobs;
+use super::watcher::FileWatcher;
+
+// Something `Assets` can load from a file. `path` is only for telling formats apart.
+pub trait Asset: Sized + Send + 'static {
+  fn from_bytes(bytes: &[u8], path: &Path) -> Result<Self, String>;
+}
+
+// A reference to an asset in `Assets`. While any clone of it is alive the asset stays loaded;
+// once the last one is dropped, the next `Assets::update` unloads it.
+pub struct Handle<T> {
+  id: u64,
+  refs: Arc<Mutex<LoadState>>,
+  marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+  pub fn id(&self) -> u64 {
+    self.id
+  }
+
+  // How loading's going, as of the last `Assets::update`.
+  pub fn state(&self) -> LoadState {
+    self.refs.lock().unwrap().clone()
+  }
+
+  pub fn is_loaded(&self) -> bool {
+    *self.refs.lock().unwrap() == LoadState::Loaded
+  }
+
+  pub(crate) fn shared_state(&self) -> &Arc<Mutex<LoadState>> {
+    &self.refs
+  }
+}
+
+impl<T> Clone for Handle<T> {
+  fn clone(&self) -> Self {
+    Handle { id: self.id, refs: self.refs.clone(), marker: PhantomData }
+  }
+}
+
+impl<T> PartialEq for Handle<T> {
+  fn eq(&self, other: &Self) -> bool {
+    self.id == other.id
+  }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.id.hash(state);
+  }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "Handle<{}>({})", std::any::type_name::<T>(), self.id)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadState {
+  Loading,
+  Loaded,
+  Failed(String),
+}
+
+struct Entry<T> {
+  asset: Option<T>,
+  path: Option<PathBuf>,
+  refs: Arc<Mutex<LoadState>>, // the store's own count, and the state handles report; they share it
+  loading: Option<Receiver<Result<T, String>>>, // a first load or a reload
+  generation: u32, // reloads so far
+}
+
+impl<T> Entry<T> {
+  fn new(asset: Option<T>, path: Option<PathBuf>, loading: Option<Receiver<Result<T, String>>>) -> Self {
+    let state = if asset.is_some() { LoadState::Loaded } else { LoadState::Loading };
+    Entry { asset, path, refs: Arc::new(Mutex::new(state)), loading, generation: 0 }
+  }
+}
+
+struct Storage<T> {
+  entries: HashMap<u64, Entry<T>>,
+  by_path: HashMap<PathBuf, u64>,
+}
+
+// Type-erased so `Assets` can update every storage without knowing its type.
+trait AnyStorage {
+  fn update(&mut self, unloaded_paths: &mut Vec<PathBuf>) -> usize;
+  fn reload(&mut self, path: &Path, jobs: Option<&Jobs>);
+  fn paths(&self) -> Vec<PathBuf>;
+  fn as_any(&self) -> &dyn Any;
+  fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Asset> AnyStorage for Storage<T> {
+  // Takes in finished background loads and unloads assets nothing refers to any more.
+  fn update(&mut self, unloaded_paths: &mut Vec<PathBuf>) -> usize {
+    for entry in self.entries.values_mut() {
+      let result = match &entry.loading {
+        Some(receiver) => match receiver.try_recv() {
+          Ok(result) => result,
+          Err(std::sync::mpsc::TryRecvError::Empty) => continue,
+          Err(std::sync::mpsc::TryRecvError::Disconnected) => Err("loader thread panicked".to_string()),
+        },
+        None => continue,
+      };
+      entry.loading = None;
+      let path = entry.path.as_deref().unwrap_or(Path::new("asset"));
+      match result {
+        Ok(asset) => {
+          if entry.asset.is_some() {
+            entry.generation += 1;
+            log::info!("reloaded {}", path.display());
+          }
+          entry.asset = Some(asset);
+          *entry.refs.lock().unwrap() = LoadState::Loaded;
+        }
+        // A broken save keeps what was there, so the game carries on until the file's fixed.
+        Err(err) if entry.asset.is_some() => log::warn!("couldn't reload {}: {}", path.display(), err),
+        Err(err) => {
+          log::warn!("couldn't load {}: {}", path.display(), err);
+          *entry.refs.lock().unwrap() = LoadState::Failed(err);
+        }
+      }
+    }
+
+    let unused: Vec<u64> = self.entries.iter()
+      .filter(|(_, entry)| Arc::strong_count(&entry.refs) == 1)
+      .map(|(id, _)| *id)
+      .collect();
+    for id in &unused {
+      if let Some(path) = self.entries.remove(id).and_then(|entry| entry.path) {
+        self.by_path.remove(&path);
+        unloaded_paths.push(path);
+      }
+    }
+    unused.len()
+  }
+
+  // Loads `path` again in the background, if it's loaded. The old asset stays until the new one's in.
+  fn reload(&mut self, path: &Path, jobs: Option<&Jobs>) {
+    if let Some(entry) = self.by_path.get(path).and_then(|id| self.entries.get_mut(id)) {
+      if entry.loading.is_none() {
+        entry.loading = Some(spawn_load(jobs, path.to_path_buf()));
+      }
+    }
+  }
+
+  fn paths(&self) -> Vec<PathBuf> {
+    self.by_path.keys().cloned().collect()
+  }
+
+  fn as_any(&self) -> &dyn Any {
+    self
+  }
+
+  fn as_any_mut(&mut self) -> &mut dyn Any {
+    self
+  }
+}
+
+// Every loaded asset of every type, on `Engine`. Loading a path that's already loaded hands out
+// another handle to the same asset.
+#[derive(Default)]
+pub struct Assets {
+  pub watcher: FileWatcher, // reloads assets whose files change, keeping their handles
+  storages: HashMap<TypeId, Box<dyn AnyStorage>>,
+  next_id: u64,
+  jobs: Option<Jobs>, // decodes background loads; otherwise each gets a thread of its own
+}
+
+impl Assets {
+  pub fn new() -> Self {
+    Assets::default()
+  }
+
+  // Decodes `load_async`s on `jobs`' workers.
+  pub fn with_jobs(jobs: Jobs) -> Self {
+    Assets { jobs: Some(jobs), ..Assets::default() }
+  }
+
+  fn storage<T: Asset>(&self) -> Option<&Storage<T>> {
+    self.storages.get(&TypeId::of::<T>()).and_then(|storage| storage.as_any().downcast_ref())
+  }
+
+  fn storage_mut<T: Asset>(&mut self) -> &mut Storage<T> {
+    self.storages.entry(TypeId::of::<T>())
+      .or_insert_with(|| Box::new(Storage::<T> { entries: HashMap::new(), by_path: HashMap::new() }))
+      .as_any_mut()
+      .downcast_mut()
+      .unwrap()
+  }
+
+  fn insert<T: Asset>(&mut self, entry: Entry<T>) -> Handle<T> {
+    let id = self.next_id;
+    self.next_id += 1;
+    let handle = Handle { id, refs: entry.refs.clone(), marker: PhantomData };
+    if let Some(path) = &entry.path {
+      self.watcher.watch(path.clone());
+    }
+    let storage = self.storage_mut::<T>();
+    if let Some(path) = &entry.path {
+      storage.by_path.insert(path.clone(), id);
+    }
+    storage.entries.insert(id, entry);
+    handle
+  }
+
+  fn existing<T: Asset>(&self, path: &Path) -> Option<Handle<T>> {
+    let storage = self.storage::<T>()?;
+    let id = *storage.by_path.get(path)?;
+    Some(Handle { id, refs: storage.entries[&id].refs.clone(), marker: PhantomData })
+  }
+
+  // Adds an asset made in code.
+  pub fn add<T: Asset>(&mut self, asset: T) -> Handle<T> {
+    self.insert(Entry::new(Some(asset), None, None))
+  }
+
+  // Reads and decodes `path` now.
+  pub fn load<T: Asset>(&mut self, path: impl AsRef<Path>) -> Result<Handle<T>, EngineError> {
+    let path = path.as_ref();
+    if let Some(handle) = self.existing(path) {
+      return Ok(handle);
+    }
+    let asset = read_asset::<T>(path).map_err(|message| EngineError::Asset { path: path.to_path_buf(), message })?;
+    Ok(self.insert(Entry::new(Some(asset), Some(path.to_path_buf()), None)))
+  }
+
+  // Reads and decodes `path` on the job pool, or a thread of its own without one. The handle works
+  // straight away; `get` returns `None` and its `state` is `Loading` until a later `update` picks up
+  // the result. On the web, where there are no threads, this loads synchronously.
+  pub fn load_async<T: Asset>(&mut self, path: impl AsRef<Path>) -> Handle<T> {
+    let path = path.as_ref().to_path_buf();
+    if let Some(handle) = self.existing(&path) {
+      return handle;
+    }
+    let receiver = spawn_load(self.jobs.as_ref(), path.clone());
+    self.insert(Entry::new(None, Some(path), Some(receiver)))
+  }
+
+  pub fn get<T: Asset>(&self, handle: &Handle<T>) -> Option<&T> {
+    self.storage::<T>()?.entries.get(&handle.id)?.asset.as_ref()
+  }
+
+  pub fn get_mut<T: Asset>(&mut self, handle: &Handle<T>) -> Option<&mut T> {
+    self.storage_mut::<T>().entries.get_mut(&handle.id)?.asset.as_mut()
+  }
+
+  pub fn state<T: Asset>(&self, handle: &Handle<T>) -> Option<LoadState> {
+    Some(self.storage::<T>()?.entries.get(&handle.id)?.refs.lock().unwrap().clone())
+  }
+
+  pub fn is_loaded<T: Asset>(&self, handle: &Handle<T>) -> bool {
+    self.state(handle) == Some(LoadState::Loaded)
+  }
+
+  pub fn path<T: Asset>(&self, handle: &Handle<T>) -> Option<&Path> {
+    self.storage::<T>()?.entries.get(&handle.id)?.path.as_deref()
+  }
+
+  // How many times hot reloading has replaced the asset, so anything built from it (GPU copies,
+  // say) can tell when to rebuild.
+  pub fn generation<T: Asset>(&self, handle: &Handle<T>) -> Option<u32> {
+    Some(self.storage::<T>()?.entries.get(&handle.id)?.generation)
+  }
+
+  pub fn count<T: Asset>(&self) -> usize {
+    self.storage::<T>().map_or(0, |storage| storage.entries.len())
+  }
+
+  // Starts reloading every asset that came from a file, whether it's changed or not. Returns how
+  // many there were.
+  pub fn reload_all(&mut self) -> usize {
+    let mut reloaded = 0;
+    for storage in self.storages.values_mut() {
+      for path in storage.paths() {
+        storage.reload(&path, self.jobs.as_ref());
+        reloaded += 1;
+      }
+    }
+    reloaded
+  }
+
+  // Starts reloading changed files, finishes background loads and unloads assets with no handles
+  // left. Returns how many were unloaded. The engine calls this at the start of every frame.
+  pub fn update(&mut self) -> usize {
+    for path in self.watcher.poll() {
+      for storage in self.storages.values_mut() {
+        storage.reload(&path, self.jobs.as_ref());
+      }
+    }
+    let mut unloaded_paths = Vec::new();
+    let unloaded = self.storages.values_mut().map(|storage| storage.update(&mut unloaded_paths)).sum();
+    for path in unloaded_paths {
+      self.watcher.unwatch(&path);
+    }
+    unloaded
+  }
+}
+
+// Reads and decodes `path` on the job pool, or a thread of its own without one. On the web, where
+// there are no threads, it's done by the time this returns.
+fn spawn_load<T: Asset>(jobs: Option<&Jobs>, path: PathBuf) -> Receiver<Result<T, String>> {
+  let (sender, receiver) = channel();
+  cfg_if::cfg_if! {
+    if #[cfg(target_arch = "wasm32")] {
+      let _ = jobs;
+      let _ = sender.send(read_asset::<T>(&path));
+    } else {
+      let load = move || {
+        let _ = sender.send(read_asset::<T>(&path));
+      };
+      match jobs {
+        Some(jobs) => drop(jobs.spawn(load)),
+        None => drop(std::thread::spawn(load)),
+      }
+    }
+  }
+  receiver
+}
+
+fn read_asset<T: Asset>(path: &Path) -> Result<T, String> {
+  let bytes = std::fs::read(path).map_err(|err| err.to_string())?;
+  T::from_bytes(&bytes, path)
+}
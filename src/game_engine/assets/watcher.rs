@@ -0,0 +1,69 @@
+use std::collections::hash_map::{Entry, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::game_engine::time::Instant;
+
+// Notices files changing on disk by checking their modification times, at most once per
+// `poll_interval`, for hot reloading. On by default in debug builds. The web has no filesystem to
+// watch, so it never reports anything there.
+#[derive(Debug, Clone)]
+pub struct FileWatcher {
+  pub enabled: bool,
+  pub poll_interval: Duration,
+  files: HashMap<PathBuf, Option<SystemTime>>,
+  last_poll: Option<Instant>,
+}
+
+impl FileWatcher {
+  pub fn new() -> Self {
+    FileWatcher {
+      enabled: cfg!(debug_assertions),
+      poll_interval: Duration::from_millis(500),
+      files: HashMap::new(),
+      last_poll: None,
+    }
+  }
+
+  pub fn watch(&mut self, path: impl Into<PathBuf>) {
+    if let Entry::Vacant(entry) = self.files.entry(path.into()) {
+      let modified = modified(entry.key());
+      entry.insert(modified);
+    }
+  }
+
+  pub fn unwatch(&mut self, path: &Path) {
+    self.files.remove(path);
+  }
+
+  pub fn is_watching(&self, path: &Path) -> bool {
+    self.files.contains_key(path)
+  }
+
+  // The watched files that changed (or appeared, or went away) since the last poll.
+  pub fn poll(&mut self) -> Vec<PathBuf> {
+    if !self.enabled || cfg!(target_arch = "wasm32") || self.last_poll.is_some_and(|last| last.elapsed() < self.poll_interval) {
+      return Vec::new();
+    }
+    self.last_poll = Some(Instant::now());
+    let mut changed = Vec::new();
+    for (path, last) in self.files.iter_mut() {
+      let modified = modified(path);
+      if modified != *last {
+        *last = modified;
+        changed.push(path.clone());
+      }
+    }
+    changed
+  }
+}
+
+impl Default for FileWatcher {
+  fn default() -> Self {
+    FileWatcher::new()
+  }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+  std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
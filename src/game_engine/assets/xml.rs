@@ -0,0 +1,48 @@
+use std::collections::HashMap;
+
+// One tag from `xml_elements`. `text` is whatever follows the tag up to the next one, untrimmed.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct XmlElement<'a> {
+  pub name: &'a str,
+  pub attributes: HashMap<&'a str, &'a str>,
+  pub text: &'a str,
+  pub closing: bool, // `</name>`; self-closing tags only appear once, as opening tags
+}
+
+impl XmlElement<'_> {
+  pub fn attribute<T: std::str::FromStr>(&self, key: &str) -> Option<T> {
+    self.attributes.get(key).and_then(|value| value.parse().ok())
+  }
+}
+
+// Just enough XML for the editor formats we read (atlases, Tiled maps): every tag in order with its
+// attributes and the text after it. Nesting is left to the caller, which can follow it through the
+// closing tags. Comments, declarations and entities aren't interpreted.
+pub(crate) fn xml_elements(text: &str) -> Vec<XmlElement<'_>> {
+  let mut elements = Vec::new();
+  for element in text.split('<').skip(1) {
+    let (element, after) = element.split_once('>').unwrap_or((element, ""));
+    if element.starts_with(['?', '!']) {
+      continue;
+    }
+    if let Some(name) = element.strip_prefix('/') {
+      elements.push(XmlElement { name: name.trim(), attributes: HashMap::new(), text: after, closing: true });
+      continue;
+    }
+    let element = element.trim_end_matches('/');
+    let (name, mut rest) = element.split_once(char::is_whitespace).unwrap_or((element, ""));
+    let mut attributes = HashMap::new();
+    while let Some((key, after)) = rest.split_once('=') {
+      let after = after.trim_start();
+      let quote = match after.chars().next() {
+        Some(quote @ ('"' | '\'')) => quote,
+        _ => break,
+      };
+      let (value, remaining) = after[1..].split_once(quote).unwrap_or((&after[1..], ""));
+      attributes.insert(key.trim(), value);
+      rest = remaining;
+    }
+    elements.push(XmlElement { name, attributes, text: after, closing: false });
+  }
+  elements
+}
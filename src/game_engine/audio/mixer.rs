@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use super::sound::Sound;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AudioChannel {
+  Music,
+  Effects,
+  Voice,
+  Ui,
+}
+
+// One playing instance of a sound, for stopping or pausing it later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SoundId(pub u64);
+
+struct Voice {
+  id: SoundId,
+  sound: Sound,
+  channel: AudioChannel,
+  position: f64, // in the sound's frames, fractional for resampling
+  volume: f32,
+  looping: bool,
+  paused: bool,
+}
+
+// Everything that's playing, as a resource. Systems start and stop sounds here; the engine's
+// `AudioOutput` pulls the mix from it every frame. Output is stereo, interleaved.
+pub struct Audio {
+  pub sample_rate: u32,
+  pub master_volume: f32,
+  volumes: HashMap<AudioChannel, f32>,
+  voices: Vec<Voice>,
+  music: Option<SoundId>,
+  paused: bool,
+  next: u64,
+  finished: Vec<SoundId>, // ended or stopped since the last `take_finished`
+}
+
+impl Audio {
+  pub fn new(sample_rate: u32) -> Self {
+    Audio { sample_rate, master_volume: 1.0, volumes: HashMap::new(), voices: Vec::new(), music: None, paused: false, next: 0, finished: Vec::new() }
+  }
+
+  // Plays once on the effects channel.
+  pub fn play_sound(&mut self, sound: &Sound) -> SoundId {
+    self.play(sound, AudioChannel::Effects, 1.0, false)
+  }
+
+  pub fn play(&mut self, sound: &Sound, channel: AudioChannel, volume: f32, looping: bool) -> SoundId {
+    let id = SoundId(self.next);
+    self.next += 1;
+    self.voices.push(Voice { id, sound: sound.clone(), channel, position: 0.0, volume, looping, paused: false });
+    id
+  }
+
+  // Replaces whatever music was playing.
+  pub fn play_music(&mut self, sound: &Sound, looping: bool) -> SoundId {
+    self.stop_music();
+    let id = self.play(sound, AudioChannel::Music, 1.0, looping);
+    self.music = Some(id);
+    id
+  }
+
+  pub fn stop_music(&mut self) {
+    if let Some(id) = self.music.take() {
+      self.stop(id);
+    }
+  }
+
+  pub fn music(&self) -> Option<SoundId> {
+    self.music.filter(|id| self.is_playing(*id))
+  }
+
+  pub fn stop(&mut self, id: SoundId) {
+    self.remove_voices(|voice| voice.id == id);
+  }
+
+  pub fn stop_channel(&mut self, channel: AudioChannel) {
+    self.remove_voices(|voice| voice.channel == channel);
+  }
+
+  pub fn stop_all(&mut self) {
+    self.remove_voices(|_| true);
+    self.music = None;
+  }
+
+  // The sounds that finished or were stopped since the last call. The engine takes them every frame
+  // to end the subtitles they voiced.
+  pub fn take_finished(&mut self) -> Vec<SoundId> {
+    std::mem::take(&mut self.finished)
+  }
+
+  fn remove_voices(&mut self, remove: impl Fn(&Voice) -> bool) {
+    let finished = &mut self.finished;
+    self.voices.retain(|voice| {
+      let removed = remove(voice);
+      if removed {
+        finished.push(voice.id);
+      }
+      !removed
+    });
+  }
+
+  // False once the sound has finished or been stopped.
+  pub fn is_playing(&self, id: SoundId) -> bool {
+    self.voices.iter().any(|voice| voice.id == id)
+  }
+
+  pub fn set_sound_volume(&mut self, id: SoundId, volume: f32) {
+    if let Some(voice) = self.voices.iter_mut().find(|voice| voice.id == id) {
+      voice.volume = volume.max(0.0);
+    }
+  }
+
+  pub fn pause_sound(&mut self, id: SoundId) {
+    if let Some(voice) = self.voices.iter_mut().find(|voice| voice.id == id) {
+      voice.paused = true;
+    }
+  }
+
+  pub fn resume_sound(&mut self, id: SoundId) {
+    if let Some(voice) = self.voices.iter_mut().find(|voice| voice.id == id) {
+      voice.paused = false;
+    }
+  }
+
+  pub fn volume(&self, channel: AudioChannel) -> f32 {
+    self.volumes.get(&channel).copied().unwrap_or(1.0)
+  }
+
+  pub fn set_volume(&mut self, channel: AudioChannel, volume: f32) {
+    self.volumes.insert(channel, volume.max(0.0));
+  }
+
+  // Pauses everything, e.g. when the game is paused or the window loses focus.
+  pub fn pause(&mut self) {
+    self.paused = true;
+  }
+
+  pub fn resume(&mut self) {
+    self.paused = false;
+  }
+
+  pub fn is_paused(&self) -> bool {
+    self.paused
+  }
+
+  // Adds the next `out.len() / 2` stereo frames into `out`, advancing every voice and dropping the
+  // ones that finished. Sounds at other sample rates are resampled linearly.
+  pub fn mix(&mut self, out: &mut [f32]) {
+    if self.paused {
+      return;
+    }
+    for voice in self.voices.iter_mut().filter(|voice| !voice.paused) {
+      let gain = voice.volume * self.volumes.get(&voice.channel).copied().unwrap_or(1.0) * self.master_volume;
+      let step = voice.sound.sample_rate as f64 / self.sample_rate as f64;
+      let length = voice.sound.frames();
+      for frame in out.chunks_exact_mut(2) {
+        if voice.position >= length as f64 {
+          if !voice.looping || length == 0 {
+            break;
+          }
+          voice.position %= length as f64;
+        }
+        let index = voice.position as usize;
+        let next = if index + 1 < length { index + 1 } else if voice.looping { 0 } else { index };
+        let t = (voice.position - index as f64) as f32;
+        for (channel, sample) in frame.iter_mut().enumerate() {
+          let a = voice.sound.sample(index, channel);
+          let b = voice.sound.sample(next, channel);
+          *sample += (a + (b - a) * t) * gain;
+        }
+        voice.position += step;
+      }
+    }
+    self.remove_voices(|voice| voice.sound.frames() == 0 || (!voice.looping && voice.position >= voice.sound.frames() as f64));
+  }
+}
+
+impl Default for Audio {
+  fn default() -> Self {
+    Audio::new(44_100)
+  }
+}
+
+// Where the mix goes. A platform backend implements this and pulls from `Audio::mix` each frame;
+// the engine calls `update` with the world's `Audio` resource. With a window, the engine plays on
+// a `DeviceOutput`; headless and on the web, where there's no backend yet, on a `NullOutput`.
+pub trait AudioOutput {
+  fn update(&mut self, audio: &mut Audio, dt: Duration);
+}
+
+// Keeps sounds moving at the right speed with nothing to play them on, so timing and finished
+// sounds behave the same without an audio device.
+#[derive(Default)]
+pub struct NullOutput {
+  buffer: Vec<f32>,
+  remainder: f64,
+}
+
+impl AudioOutput for NullOutput {
+  fn update(&mut self, audio: &mut Audio, dt: Duration) {
+    let frames = dt.as_secs_f64() * audio.sample_rate as f64 + self.remainder;
+    self.remainder = frames.fract();
+    self.buffer.clear();
+    self.buffer.resize(frames as usize * 2, 0.0);
+    audio.mix(&mut self.buffer);
+  }
+}
@@ -0,0 +1,11 @@
+mod mixer;
+#[cfg(not(target_arch = "wasm32"))]
+mod output;
+mod sound;
+
+pub use self::{
+  mixer::*,
+  sound::*
+};
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::output::*;
@@ -0,0 +1,87 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{Device, FromSample, SampleFormat, SizedSample, Stream, StreamConfig};
+
+use super::mixer::{Audio, AudioOutput, NullOutput};
+
+// How far ahead of the device the mix is kept, at the least. Long frames keep more.
+const LATENCY: f64 = 0.05;
+
+// Plays the mix on the system's default output device through cpal. The mixer is run at the
+// device's sample rate, and kept a little ahead of what the device has played.
+pub struct DeviceOutput {
+  sample_rate: u32,
+  queued: Arc<Mutex<VecDeque<f32>>>, // stereo frames, interleaved, waiting for the device
+  buffer: Vec<f32>,
+  _stream: Stream, // playing for as long as it's kept
+}
+
+impl DeviceOutput {
+  pub fn new() -> Result<Self, String> {
+    let device = cpal::default_host().default_output_device().ok_or("no audio output device")?;
+    let config = device.default_output_config().map_err(|err| err.to_string())?;
+    let queued = Arc::new(Mutex::new(VecDeque::new()));
+    let stream = match config.sample_format() {
+      SampleFormat::F32 => open::<f32>(&device, &config.config(), queued.clone()),
+      SampleFormat::I16 => open::<i16>(&device, &config.config(), queued.clone()),
+      SampleFormat::U16 => open::<u16>(&device, &config.config(), queued.clone()),
+      format => Err(format!("unsupported audio sample format {}", format)),
+    }?;
+    stream.play().map_err(|err| err.to_string())?;
+    Ok(DeviceOutput { sample_rate: config.sample_rate().0, queued, buffer: Vec::new(), _stream: stream })
+  }
+}
+
+impl AudioOutput for DeviceOutput {
+  fn update(&mut self, audio: &mut Audio, dt: Duration) {
+    audio.sample_rate = self.sample_rate;
+    let wanted = (dt.as_secs_f64() * 2.0).max(LATENCY) * self.sample_rate as f64;
+    let queued = self.queued.lock().unwrap().len() / 2;
+    let frames = (wanted as usize).saturating_sub(queued);
+    if frames == 0 {
+      return;
+    }
+    self.buffer.clear();
+    self.buffer.resize(frames * 2, 0.0);
+    audio.mix(&mut self.buffer);
+    self.queued.lock().unwrap().extend(&self.buffer);
+  }
+}
+
+// Plays the stereo mix on `device`'s channels: mono devices get both sides averaged, and any past
+// the first two get silence. Runs dry to silence if the game falls behind.
+fn open<T: SizedSample + FromSample<f32>>(device: &Device, config: &StreamConfig, queued: Arc<Mutex<VecDeque<f32>>>) -> Result<Stream, String> {
+  let channels = config.channels as usize;
+  let play = move |out: &mut [T], _: &cpal::OutputCallbackInfo| {
+    let mut queued = queued.lock().unwrap();
+    for frame in out.chunks_mut(channels) {
+      let (left, right) = match (queued.pop_front(), queued.pop_front()) {
+        (Some(left), Some(right)) => (left, right),
+        _ => (0.0, 0.0),
+      };
+      for (channel, sample) in frame.iter_mut().enumerate() {
+        let value = match (channels, channel) {
+          (1, _) => (left + right) / 2.0,
+          (_, 0) => left,
+          (_, 1) => right,
+          _ => 0.0,
+        };
+        *sample = T::from_sample(value.clamp(-1.0, 1.0));
+      }
+    }
+  };
+  device.build_output_stream(config, play, |err| log::error!("audio output: {}", err), None).map_err(|err| err.to_string())
+}
+
+// The default device's output, or a `NullOutput` if there's no device that can play.
+pub fn open_audio_output() -> Box<dyn AudioOutput> {
+  match DeviceOutput::new() {
+    Ok(output) => Box::new(output),
+    Err(err) => {
+      log::warn!("no sound: {}", err);
+      Box::new(NullOutput::default())
+    }
+  }
+}
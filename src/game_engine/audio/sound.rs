@@ -0,0 +1,108 @@
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::Arc;
+use lewton::inside_ogg::OggStreamReader;
+
+use crate::game_engine::assets::Asset;
+
+// Decoded audio: interleaved samples in -1..1. Cloning is cheap, so playing a sound many times at
+// once doesn't copy it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sound {
+  pub sample_rate: u32,
+  pub channels: u16,
+  samples: Arc<[f32]>,
+}
+
+impl Sound {
+  pub fn new(sample_rate: u32, channels: u16, samples: Vec<f32>) -> Result<Self, String> {
+    if sample_rate == 0 || channels == 0 || !samples.len().is_multiple_of(channels as usize) {
+      return Err(format!("{} samples don't make whole {}-channel frames at {}Hz", samples.len(), channels, sample_rate));
+    }
+    Ok(Sound { sample_rate, channels, samples: samples.into() })
+  }
+
+  // PCM WAV, 8, 16, 24 or 32 bit integer or 32 bit float.
+  pub fn from_wav(bytes: &[u8]) -> Result<Self, String> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+      return Err("not a wav file".to_string());
+    }
+    let mut format = None;
+    let mut data = None;
+    let mut rest = &bytes[12..];
+    while rest.len() >= 8 {
+      let id = &rest[0..4];
+      let size = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+      let body = rest.get(8..8 + size).ok_or("wav chunk runs past the end of the file")?;
+      match id {
+        b"fmt " if size >= 16 => format = Some(body),
+        b"data" => data = Some(body),
+        _ => {}
+      }
+      // Chunks are padded to an even length.
+      rest = rest.get(8 + size + size % 2..).unwrap_or(&[]);
+    }
+    let format = format.ok_or("wav has no fmt chunk")?;
+    let data = data.ok_or("wav has no data chunk")?;
+
+    let u16_at = |offset: usize| u16::from_le_bytes([format[offset], format[offset + 1]]);
+    let mut encoding = u16_at(0);
+    let channels = u16_at(2);
+    let sample_rate = u32::from_le_bytes([format[4], format[5], format[6], format[7]]);
+    let bits = u16_at(14);
+    // WAVE_FORMAT_EXTENSIBLE keeps the real format at the start of its sub-format GUID.
+    if encoding == 0xfffe && format.len() >= 26 {
+      encoding = u16_at(24);
+    }
+
+    let mut samples: Vec<f32> = match (encoding, bits) {
+      (1, 8) => data.iter().map(|sample| (*sample as f32 - 128.0) / 128.0).collect(),
+      (1, 16) => data.chunks_exact(2).map(|sample| i16::from_le_bytes([sample[0], sample[1]]) as f32 / 32768.0).collect(),
+      (1, 24) => data.chunks_exact(3).map(|sample| i32::from_le_bytes([0, sample[0], sample[1], sample[2]]) as f32 / 2147483648.0).collect(),
+      (1, 32) => data.chunks_exact(4).map(|sample| i32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]) as f32 / 2147483648.0).collect(),
+      (3, 32) => data.chunks_exact(4).map(|sample| f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]])).collect(),
+      _ => return Err(format!("unsupported wav encoding {} at {} bits", encoding, bits)),
+    };
+    let frames = samples.len() / channels.max(1) as usize;
+    samples.truncate(frames * channels as usize);
+    Sound::new(sample_rate, channels, samples)
+  }
+
+  // Ogg Vorbis.
+  pub fn from_ogg(bytes: &[u8]) -> Result<Self, String> {
+    let mut reader = OggStreamReader::new(Cursor::new(bytes)).map_err(|err| err.to_string())?;
+    let mut samples = Vec::new();
+    while let Some(packet) = reader.read_dec_packet_itl().map_err(|err| err.to_string())? {
+      samples.extend(packet.into_iter().map(|sample| sample as f32 / 32768.0));
+    }
+    Sound::new(reader.ident_hdr.audio_sample_rate, reader.ident_hdr.audio_channels as u16, samples)
+  }
+
+  pub fn samples(&self) -> &[f32] {
+    &self.samples
+  }
+
+  pub fn frames(&self) -> usize {
+    self.samples.len() / self.channels as usize
+  }
+
+  pub fn duration_seconds(&self) -> f32 {
+    self.frames() as f32 / self.sample_rate as f32
+  }
+
+  // The sample for `channel` at `frame`, with mono sounds played on both sides.
+  pub(crate) fn sample(&self, frame: usize, channel: usize) -> f32 {
+    let channel = channel.min(self.channels as usize - 1);
+    self.samples[frame * self.channels as usize + channel]
+  }
+}
+
+impl Asset for Sound {
+  fn from_bytes(bytes: &[u8], path: &Path) -> Result<Self, String> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+      Some("wav") => Sound::from_wav(bytes),
+      Some("ogg") => Sound::from_ogg(bytes),
+      _ => Err(format!("unknown sound format: {}", path.display())),
+    }
+  }
+}
@@ -0,0 +1,107 @@
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+use crate::game_engine::ecs::{Entity, System, Transform, World};
+use super::shapes::Shape;
+use super::spatial_hash::SpatialHash;
+
+// An entity's 2D collision shape, relative to its `Transform`'s translation (rotation and scale are
+// ignored). Indexed by the world's `SpatialHash<Entity>` resource once `hitbox_system` runs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hitbox {
+  pub shape: Shape,
+  pub layers: u32, // bit mask of what this entity is
+  pub mask: u32, // bit mask of layers it can hit; `hits` needs both sides to agree
+}
+
+impl Hitbox {
+  pub fn new(shape: impl Into<Shape>) -> Self {
+    Hitbox { shape: shape.into(), layers: 1, mask: u32::MAX }
+  }
+
+  pub fn with_layers(mut self, layers: u32, mask: u32) -> Self {
+    self.layers = layers;
+    self.mask = mask;
+    self
+  }
+
+  pub fn world_shape(&self, transform: Option<&Transform>) -> Shape {
+    self.shape.translated(transform.map_or(Vec2::ZERO, |transform| transform.translation.truncate()))
+  }
+
+  pub fn hits(&self, other: &Hitbox) -> bool {
+    self.mask & other.layers != 0 && other.mask & self.layers != 0
+  }
+}
+
+// A system keeping the world's `SpatialHash<Entity>` resource in step with its `Hitbox`es,
+// re-filing entities whose hitbox or transform changed since the system last ran and dropping ones
+// that lost theirs. Being a system, it judges changes by its own last run, wherever it sits in the
+// schedule. Does nothing if the world has no such resource; insert one (with a cell size to suit
+// the game) and add this to the schedule.
+pub fn hitbox_system() -> System {
+  System::new("update_hitboxes", |world: &mut World| update_hitboxes(world))
+}
+
+fn update_hitboxes(world: &World) {
+  let mut hash = match world.get_resource_mut::<SpatialHash<Entity>>() {
+    Some(hash) => hash,
+    None => return,
+  };
+  let stale: Vec<Entity> = hash.items()
+    .map(|(entity, _)| entity)
+    .filter(|&entity| !world.has::<Hitbox>(entity))
+    .collect();
+  for entity in stale {
+    hash.remove(entity);
+  }
+  world.query::<(&Hitbox, Option<&Transform>)>().for_each(|entity, (hitbox, transform)| {
+    if !hash.contains(entity) || world.is_changed::<Hitbox>(entity) || world.is_changed::<Transform>(entity) {
+      hash.insert(entity, hitbox.world_shape(transform).aabb());
+    }
+  });
+}
+
+// Entities whose hitboxes really overlap `entity`'s (not just their boxes) and whose layers match,
+// from the world's `SpatialHash<Entity>`.
+pub fn overlapping(world: &World, entity: Entity) -> Vec<Entity> {
+  let hitbox = match world.get::<Hitbox>(entity) {
+    Some(hitbox) => hitbox,
+    None => return Vec::new(),
+  };
+  let shape = hitbox.world_shape(world.get::<Transform>(entity).as_deref());
+  query_shape(world, &shape)
+    .into_iter()
+    .filter(|&other| other != entity && world.get::<Hitbox>(other).is_some_and(|other| hitbox.hits(&other)))
+    .collect()
+}
+
+// Entities whose hitboxes overlap `shape`, whatever their layers.
+pub fn query_shape(world: &World, shape: &Shape) -> Vec<Entity> {
+  let hash = match world.get_resource::<SpatialHash<Entity>>() {
+    Some(hash) => hash,
+    None => return Vec::new(),
+  };
+  hash.query(&shape.aabb())
+    .into_iter()
+    .filter(|&other| {
+      world.get::<Hitbox>(other)
+        .is_some_and(|hitbox| hitbox.world_shape(world.get::<Transform>(other).as_deref()).overlaps(shape))
+    })
+    .collect()
+}
+
+// Entities whose hitboxes contain `point`, edges included, e.g. for clicking on things.
+pub fn query_point(world: &World, point: Vec2) -> Vec<Entity> {
+  let hash = match world.get_resource::<SpatialHash<Entity>>() {
+    Some(hash) => hash,
+    None => return Vec::new(),
+  };
+  hash.query_point(point)
+    .into_iter()
+    .filter(|&other| {
+      world.get::<Hitbox>(other)
+        .is_some_and(|hitbox| hitbox.world_shape(world.get::<Transform>(other).as_deref()).contains(point))
+    })
+    .collect()
+}
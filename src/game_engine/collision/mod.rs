@@ -0,0 +1,9 @@
+mod hitbox;
+mod shapes;
+mod spatial_hash;
+
+pub use self::{
+  hitbox::*,
+  shapes::*,
+  spatial_hash::*
+};
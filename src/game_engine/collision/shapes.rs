@@ -0,0 +1,318 @@
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+// On edges: shapes only overlap when their insides do, so boxes and circles that just touch don't
+// (a platformer character standing on the ground isn't colliding with it, and can slide along it).
+// Points count as inside on the edge, though, so a click exactly on a border still hits.
+
+// An axis-aligned box, in whatever 2D space the game uses (world units or pixels).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Aabb {
+  pub min: Vec2,
+  pub max: Vec2,
+}
+
+impl Aabb {
+  pub fn new(min: Vec2, max: Vec2) -> Self {
+    Aabb { min: min.min(max), max: min.max(max) }
+  }
+
+  pub fn from_center(center: Vec2, half_size: Vec2) -> Self {
+    Aabb::new(center - half_size, center + half_size)
+  }
+
+  pub fn center(&self) -> Vec2 {
+    (self.min + self.max) / 2.0
+  }
+
+  pub fn half_size(&self) -> Vec2 {
+    (self.max - self.min) / 2.0
+  }
+
+  pub fn size(&self) -> Vec2 {
+    self.max - self.min
+  }
+
+  pub fn translated(&self, offset: Vec2) -> Aabb {
+    Aabb { min: self.min + offset, max: self.max + offset }
+  }
+
+  // Grown by `amount` on every side.
+  pub fn expanded(&self, amount: Vec2) -> Aabb {
+    Aabb { min: self.min - amount, max: self.max + amount }
+  }
+
+  pub fn union(&self, other: &Aabb) -> Aabb {
+    Aabb { min: self.min.min(other.min), max: self.max.max(other.max) }
+  }
+
+  pub fn contains(&self, point: Vec2) -> bool {
+    point.cmpge(self.min).all() && point.cmple(self.max).all()
+  }
+
+  // Boxes that only share an edge don't overlap.
+  pub fn overlaps(&self, other: &Aabb) -> bool {
+    self.min.cmplt(other.max).all() && other.min.cmplt(self.max).all()
+  }
+
+  pub fn closest_point(&self, point: Vec2) -> Vec2 {
+    point.clamp(self.min, self.max)
+  }
+
+  // The shortest move that pushes `self` out of `other`, or `None` if they don't overlap.
+  pub fn penetration(&self, other: &Aabb) -> Option<Vec2> {
+    if !self.overlaps(other) {
+      return None;
+    }
+    let left = other.min.x - self.max.x; // negative: move left to separate
+    let right = other.max.x - self.min.x;
+    let down = other.min.y - self.max.y;
+    let up = other.max.y - self.min.y;
+    let x = if -left < right { left } else { right };
+    let y = if -down < up { down } else { up };
+    Some(if x.abs() < y.abs() { Vec2::new(x, 0.0) } else { Vec2::new(0.0, y) })
+  }
+
+  // Where a ray from `origin` along `direction` enters the box, as a fraction of `direction`, and
+  // the face normal it hits. A ray starting inside reports time 0 with a zero normal.
+  pub fn ray(&self, origin: Vec2, direction: Vec2) -> Option<(f32, Vec2)> {
+    let mut entry = f32::NEG_INFINITY;
+    let mut exit = f32::INFINITY;
+    let mut normal = Vec2::ZERO;
+    for axis in 0..2 {
+      if direction[axis] == 0.0 {
+        if origin[axis] < self.min[axis] || origin[axis] > self.max[axis] {
+          return None;
+        }
+        continue;
+      }
+      let near = if direction[axis] > 0.0 { self.min[axis] } else { self.max[axis] };
+      let far = if direction[axis] > 0.0 { self.max[axis] } else { self.min[axis] };
+      let near_time = (near - origin[axis]) / direction[axis];
+      let far_time = (far - origin[axis]) / direction[axis];
+      if near_time > entry {
+        entry = near_time;
+        normal = Vec2::ZERO;
+        normal[axis] = -direction[axis].signum();
+      }
+      exit = exit.min(far_time);
+    }
+    if entry > exit || exit < 0.0 || entry > 1.0 {
+      return None;
+    }
+    // A ray starting on a face and heading in enters at 0 with that face's normal; one starting
+    // strictly inside has no face to report.
+    Some(if entry < 0.0 { (0.0, Vec2::ZERO) } else { (entry, normal) })
+  }
+
+  // Moves `self` by `velocity` against a still `other`, returning when and where it first touches.
+  // Boxes that already overlap aren't reported; separate them with `penetration` first.
+  pub fn sweep(&self, velocity: Vec2, other: &Aabb) -> Option<SweepHit> {
+    if self.overlaps(other) {
+      return None;
+    }
+    // Shrink `self` to its centre and grow `other` by its size, and it's a ray test.
+    let target = other.expanded(self.half_size());
+    let (time, normal) = target.ray(self.center(), velocity)?;
+    // Sliding along a face we're already touching isn't a hit.
+    (normal != Vec2::ZERO && normal.dot(velocity) < 0.0).then_some(SweepHit { time, normal })
+  }
+}
+
+// Where a sweep first touched: `time` is the fraction of the velocity travelled, `normal` the face
+// of the other box that was hit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SweepHit {
+  pub time: f32,
+  pub normal: Vec2,
+}
+
+impl SweepHit {
+  // The part of `velocity` left after stopping at the hit and sliding along the face, e.g. to keep
+  // walking along the floor after landing.
+  pub fn slide(&self, velocity: Vec2) -> Vec2 {
+    let remaining = velocity * (1.0 - self.time);
+    remaining - self.normal * remaining.dot(self.normal)
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Circle {
+  pub center: Vec2,
+  pub radius: f32,
+}
+
+impl Circle {
+  pub fn new(center: Vec2, radius: f32) -> Self {
+    Circle { center, radius }
+  }
+
+  pub fn translated(&self, offset: Vec2) -> Circle {
+    Circle { center: self.center + offset, radius: self.radius }
+  }
+
+  pub fn aabb(&self) -> Aabb {
+    Aabb::from_center(self.center, Vec2::splat(self.radius))
+  }
+
+  pub fn contains(&self, point: Vec2) -> bool {
+    self.center.distance_squared(point) <= self.radius * self.radius
+  }
+
+  pub fn overlaps(&self, other: &Circle) -> bool {
+    let radii = self.radius + other.radius;
+    self.center.distance_squared(other.center) < radii * radii
+  }
+
+  pub fn overlaps_aabb(&self, aabb: &Aabb) -> bool {
+    self.center.distance_squared(aabb.closest_point(self.center)) < self.radius * self.radius
+  }
+
+  // The shortest move that pushes `self` out of `other`, or `None` if they don't overlap.
+  pub fn penetration(&self, other: &Circle) -> Option<Vec2> {
+    if !self.overlaps(other) {
+      return None;
+    }
+    let offset = self.center - other.center;
+    let distance = offset.length();
+    let direction = if distance > 0.0 { offset / distance } else { Vec2::Y };
+    Some(direction * (self.radius + other.radius - distance))
+  }
+
+  pub fn penetration_aabb(&self, aabb: &Aabb) -> Option<Vec2> {
+    if !self.overlaps_aabb(aabb) {
+      return None;
+    }
+    let closest = aabb.closest_point(self.center);
+    if closest == self.center {
+      // The centre is inside the box: leave through the nearest face.
+      let inner = Aabb::from_center(self.center, Vec2::splat(self.radius));
+      return inner.penetration(aabb);
+    }
+    let offset = self.center - closest;
+    let distance = offset.length();
+    Some(offset / distance * (self.radius - distance))
+  }
+}
+
+// Either shape, for code (and `Hitbox`es) that handles both.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Shape {
+  Aabb(Aabb),
+  Circle(Circle),
+}
+
+impl Shape {
+  pub fn aabb(&self) -> Aabb {
+    match self {
+      Shape::Aabb(aabb) => *aabb,
+      Shape::Circle(circle) => circle.aabb(),
+    }
+  }
+
+  pub fn translated(&self, offset: Vec2) -> Shape {
+    match self {
+      Shape::Aabb(aabb) => Shape::Aabb(aabb.translated(offset)),
+      Shape::Circle(circle) => Shape::Circle(circle.translated(offset)),
+    }
+  }
+
+  pub fn contains(&self, point: Vec2) -> bool {
+    match self {
+      Shape::Aabb(aabb) => aabb.contains(point),
+      Shape::Circle(circle) => circle.contains(point),
+    }
+  }
+
+  pub fn overlaps(&self, other: &Shape) -> bool {
+    overlaps(self, other)
+  }
+
+  // The shortest move that pushes `self` out of `other`, or `None` if they don't overlap.
+  pub fn penetration(&self, other: &Shape) -> Option<Vec2> {
+    match (self, other) {
+      (Shape::Aabb(a), Shape::Aabb(b)) => a.penetration(b),
+      (Shape::Circle(a), Shape::Circle(b)) => a.penetration(b),
+      (Shape::Circle(circle), Shape::Aabb(aabb)) => circle.penetration_aabb(aabb),
+      (Shape::Aabb(aabb), Shape::Circle(circle)) => circle.penetration_aabb(aabb).map(|push| -push),
+    }
+  }
+}
+
+impl From<Aabb> for Shape {
+  fn from(aabb: Aabb) -> Self {
+    Shape::Aabb(aabb)
+  }
+}
+
+impl From<Circle> for Shape {
+  fn from(circle: Circle) -> Self {
+    Shape::Circle(circle)
+  }
+}
+
+pub fn overlaps(a: &Shape, b: &Shape) -> bool {
+  match (a, b) {
+    (Shape::Aabb(a), Shape::Aabb(b)) => a.overlaps(b),
+    (Shape::Circle(a), Shape::Circle(b)) => a.overlaps(b),
+    (Shape::Circle(circle), Shape::Aabb(aabb)) | (Shape::Aabb(aabb), Shape::Circle(circle)) => circle.overlaps_aabb(aabb),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn unit_box(min: Vec2) -> Aabb {
+    Aabb::new(min, min + Vec2::ONE)
+  }
+
+  #[test]
+  fn touching_shapes_dont_overlap() {
+    let a = unit_box(Vec2::ZERO);
+    assert!(!a.overlaps(&unit_box(Vec2::new(1.0, 0.0))));
+    assert!(!a.overlaps(&unit_box(Vec2::new(1.0, 1.0))));
+    assert!(a.overlaps(&unit_box(Vec2::new(0.999, 0.5))));
+    assert_eq!(a.penetration(&unit_box(Vec2::new(0.0, 1.0))), None);
+
+    let circle = Circle::new(Vec2::new(2.0, 0.5), 1.0);
+    assert!(!circle.overlaps_aabb(&a));
+    assert!(!circle.overlaps(&Circle::new(Vec2::new(4.0, 0.5), 1.0)));
+    assert!(circle.overlaps(&Circle::new(Vec2::new(3.9, 0.5), 1.0)));
+  }
+
+  #[test]
+  fn points_on_an_edge_are_inside() {
+    let a = unit_box(Vec2::ZERO);
+    assert!(a.contains(Vec2::ZERO));
+    assert!(a.contains(Vec2::new(1.0, 0.5)));
+    assert!(!a.contains(Vec2::new(1.001, 0.5)));
+    assert!(Circle::new(Vec2::ZERO, 1.0).contains(Vec2::new(0.0, 1.0)));
+    assert!(Shape::Aabb(a).contains(Vec2::ONE));
+  }
+
+  #[test]
+  fn sweep_stops_at_the_first_touch() {
+    let hit = unit_box(Vec2::ZERO).sweep(Vec2::new(4.0, 0.0), &unit_box(Vec2::new(3.0, 0.0))).unwrap();
+    assert_eq!(hit, SweepHit { time: 0.5, normal: Vec2::NEG_X });
+    assert_eq!(hit.slide(Vec2::new(4.0, 2.0)), Vec2::new(0.0, 1.0));
+    assert_eq!(unit_box(Vec2::ZERO).sweep(Vec2::new(1.0, 0.0), &unit_box(Vec2::new(3.0, 0.0))), None);
+  }
+
+  #[test]
+  fn zero_length_sweeps_never_hit() {
+    let a = unit_box(Vec2::ZERO);
+    assert_eq!(a.sweep(Vec2::ZERO, &unit_box(Vec2::new(3.0, 0.0))), None);
+    assert_eq!(a.sweep(Vec2::ZERO, &unit_box(Vec2::new(1.0, 0.0))), None);
+    assert_eq!(a.ray(Vec2::splat(0.5), Vec2::ZERO), Some((0.0, Vec2::ZERO)));
+    assert_eq!(a.ray(Vec2::splat(2.0), Vec2::ZERO), None);
+  }
+
+  #[test]
+  fn sliding_along_a_touching_face_isnt_a_hit() {
+    let floor = Aabb::new(Vec2::new(-10.0, -1.0), Vec2::new(10.0, 0.0));
+    assert_eq!(unit_box(Vec2::ZERO).sweep(Vec2::new(3.0, 0.0), &floor), None);
+    let landing = unit_box(Vec2::ZERO).sweep(Vec2::new(1.0, -1.0), &floor).unwrap();
+    assert_eq!((landing.time, landing.normal), (0.0, Vec2::Y));
+  }
+}
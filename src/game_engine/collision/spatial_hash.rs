@@ -0,0 +1,179 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use glam::{IVec2, Vec2};
+
+use super::shapes::Aabb;
+
+// A broad phase: items are filed under every grid cell their box touches, so finding what might
+// overlap a box only looks at the items in the cells it touches. Pick a cell size around the size
+// of a typical item; much smaller and big items fill many cells, much bigger and cells get crowded.
+// Answers are candidates by box; test the actual shapes afterwards.
+#[derive(Debug, Clone)]
+pub struct SpatialHash<T> {
+  cell_size: f32,
+  cells: HashMap<IVec2, Vec<T>>,
+  items: HashMap<T, Aabb>,
+}
+
+impl<T: Copy + Eq + Hash> SpatialHash<T> {
+  pub fn new(cell_size: f32) -> Self {
+    SpatialHash { cell_size: cell_size.max(f32::EPSILON), cells: HashMap::new(), items: HashMap::new() }
+  }
+
+  pub fn cell_size(&self) -> f32 {
+    self.cell_size
+  }
+
+  // Adds `item`, or moves it if it's already in.
+  pub fn insert(&mut self, item: T, aabb: Aabb) {
+    if let Some(old) = self.items.get(&item) {
+      if self.cell_range(old) == self.cell_range(&aabb) {
+        self.items.insert(item, aabb);
+        return;
+      }
+      self.remove(item);
+    }
+    let (min, max) = self.cell_range(&aabb);
+    for y in min.y..=max.y {
+      for x in min.x..=max.x {
+        self.cells.entry(IVec2::new(x, y)).or_default().push(item);
+      }
+    }
+    self.items.insert(item, aabb);
+  }
+
+  pub fn remove(&mut self, item: T) -> Option<Aabb> {
+    let aabb = self.items.remove(&item)?;
+    let (min, max) = self.cell_range(&aabb);
+    for y in min.y..=max.y {
+      for x in min.x..=max.x {
+        let cell = IVec2::new(x, y);
+        if let Some(items) = self.cells.get_mut(&cell) {
+          items.retain(|other| *other != item);
+          if items.is_empty() {
+            self.cells.remove(&cell);
+          }
+        }
+      }
+    }
+    Some(aabb)
+  }
+
+  pub fn get(&self, item: T) -> Option<Aabb> {
+    self.items.get(&item).copied()
+  }
+
+  pub fn contains(&self, item: T) -> bool {
+    self.items.contains_key(&item)
+  }
+
+  pub fn len(&self) -> usize {
+    self.items.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.items.is_empty()
+  }
+
+  pub fn clear(&mut self) {
+    self.cells.clear();
+    self.items.clear();
+  }
+
+  pub fn items(&self) -> impl Iterator<Item = (T, Aabb)> + '_ {
+    self.items.iter().map(|(item, aabb)| (*item, *aabb))
+  }
+
+  // Every item whose box overlaps `aabb`; boxes that only touch it don't.
+  pub fn query(&self, aabb: &Aabb) -> Vec<T> {
+    let mut seen = HashSet::new();
+    let (min, max) = self.cell_range(aabb);
+    let mut found = Vec::new();
+    for y in min.y..=max.y {
+      for x in min.x..=max.x {
+        for item in self.cells.get(&IVec2::new(x, y)).into_iter().flatten() {
+          if seen.insert(*item) && self.items[item].overlaps(aabb) {
+            found.push(*item);
+          }
+        }
+      }
+    }
+    found
+  }
+
+  // Every item whose box contains `point`, edges included.
+  pub fn query_point(&self, point: Vec2) -> Vec<T> {
+    self.cells.get(&self.cell(point)).into_iter().flatten()
+      .filter(|item| self.items[*item].contains(point))
+      .copied()
+      .collect()
+  }
+
+  // Every pair of items whose boxes overlap, each pair once.
+  pub fn pairs(&self) -> Vec<(T, T)> {
+    let mut seen = HashSet::new();
+    let mut pairs = Vec::new();
+    for items in self.cells.values() {
+      for (index, a) in items.iter().enumerate() {
+        for b in &items[index + 1..] {
+          if self.items[a].overlaps(&self.items[b]) && !seen.contains(&(*b, *a)) && seen.insert((*a, *b)) {
+            pairs.push((*a, *b));
+          }
+        }
+      }
+    }
+    pairs
+  }
+
+  fn cell(&self, point: Vec2) -> IVec2 {
+    (point / self.cell_size).floor().as_ivec2()
+  }
+
+  fn cell_range(&self, aabb: &Aabb) -> (IVec2, IVec2) {
+    (self.cell(aabb.min), self.cell(aabb.max))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Filed under cells (0, 0) and (1, 0), as its right edge is on the boundary between them.
+  fn hash() -> SpatialHash<u32> {
+    let mut hash = SpatialHash::new(10.0);
+    hash.insert(1, Aabb::new(Vec2::ZERO, Vec2::new(10.0, 5.0)));
+    hash
+  }
+
+  #[test]
+  fn points_on_a_cell_boundary_find_items_on_either_side() {
+    let hash = hash();
+    assert_eq!(hash.query_point(Vec2::new(10.0, 2.0)), vec![1]);
+    assert_eq!(hash.query_point(Vec2::ZERO), vec![1]);
+    assert_eq!(hash.query_point(Vec2::new(10.001, 2.0)), Vec::<u32>::new());
+  }
+
+  #[test]
+  fn boxes_touching_across_a_cell_boundary_dont_overlap() {
+    let mut hash = hash();
+    assert_eq!(hash.query(&Aabb::new(Vec2::new(10.0, 0.0), Vec2::new(20.0, 5.0))), Vec::<u32>::new());
+    assert_eq!(hash.query(&Aabb::new(Vec2::new(9.5, 0.0), Vec2::new(20.0, 5.0))), vec![1]);
+    hash.insert(2, Aabb::new(Vec2::new(10.0, 0.0), Vec2::new(15.0, 5.0)));
+    assert!(hash.pairs().is_empty());
+    hash.insert(3, Aabb::new(Vec2::new(5.0, 0.0), Vec2::new(15.0, 5.0)));
+    let mut pairs = hash.pairs();
+    pairs.iter_mut().for_each(|pair| *pair = (pair.0.min(pair.1), pair.0.max(pair.1)));
+    pairs.sort();
+    assert_eq!(pairs, vec![(1, 3), (2, 3)]);
+  }
+
+  #[test]
+  fn moving_an_item_refiles_it() {
+    let mut hash = hash();
+    hash.insert(1, Aabb::new(Vec2::new(30.0, 30.0), Vec2::new(31.0, 31.0)));
+    assert!(hash.query_point(Vec2::new(5.0, 2.0)).is_empty());
+    assert_eq!(hash.query_point(Vec2::new(30.5, 30.5)), vec![1]);
+    assert_eq!(hash.remove(1), Some(Aabb::new(Vec2::new(30.0, 30.0), Vec2::new(31.0, 31.0))));
+    assert!(hash.is_empty() && hash.query_point(Vec2::new(30.5, 30.5)).is_empty());
+  }
+}
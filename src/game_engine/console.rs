@@ -0,0 +1,425 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::rc::Rc;
+use glam::Vec2;
+use log::{Level, LevelFilter};
+
+use super::cvars::CVars;
+use super::ecs::Name;
+use super::graphics::color::Color;
+use super::input::{Input, KeyCode};
+use super::logging;
+use super::profiler;
+use super::ui::{FontId, Fonts, UiDraw};
+use super::Engine;
+
+// Lines kept in the scrollback and commands in the history.
+const MAX_LINES: usize = 500;
+const MAX_HISTORY: usize = 100;
+const PROMPT: &str = "> ";
+const PAGE_LINES: usize = 10; // scrolled by Page Up and Down
+
+// Runs a console command with the words typed after its name. An error is shown in the console.
+// Commands can be closures, so games can capture their own state in them.
+pub type ConsoleCommandFn = Rc<dyn Fn(&[&str], &mut Engine) -> Result<(), String>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleLine {
+  Command, // what was typed
+  Output,
+  Error,
+}
+
+#[derive(Clone)]
+struct Command {
+  help: String,
+  run: ConsoleCommandFn,
+}
+
+// A drop-down console for developers, on `Engine`, opened and closed with `key`. Typed lines run
+// commands registered by name, or show or set the `CVars` variable named; Up and Down go through
+// what was typed before, Tab completes names and Page Up and Down scroll. The game still sees
+// input while it's open, so games with keyboard controls should check `is_open`.
+pub struct Console {
+  pub key: Option<KeyCode>,
+  pub font: Option<FontId>, // the first loaded font when not set
+  pub font_size: f32,
+  pub height: f32, // as a fraction of the window
+  open: bool,
+  line: String, // being typed
+  cursor: usize, // a byte index into `line`
+  output: VecDeque<(String, ConsoleLine)>,
+  scroll: usize, // lines back from the newest
+  history: Vec<String>,
+  browsing: Option<usize>, // the history entry Up and Down are on
+  commands: BTreeMap<String, Command>,
+}
+
+impl Console {
+  pub fn new() -> Self {
+    let mut console = Console {
+      key: Some(KeyCode::Grave),
+      font: None,
+      font_size: 14.0,
+      height: 0.4,
+      open: false,
+      line: String::new(),
+      cursor: 0,
+      output: VecDeque::new(),
+      scroll: 0,
+      history: Vec::new(),
+      browsing: None,
+      commands: BTreeMap::new(),
+    };
+    console.register_with_help("help", "lists commands, or describes one: help [command]", help);
+    console.register_with_help("clear", "clears the console", |_, engine| {
+      engine.console.clear();
+      Ok(())
+    });
+    console.register_with_help("entities", "lists the active world's entities: entities [name filter]", entities);
+    console.register_with_help("reload", "reloads every asset loaded from a file", |_, engine| {
+      let count = engine.assets.reload_all();
+      engine.console.print(format!("reloading {} assets", count));
+      Ok(())
+    });
+    console.register_with_help("log", "shows recent log messages: log [count] [level]", log_command);
+    console.register_with_help("cvars", "lists variables: cvars [name filter]", cvars);
+    console.register_with_help("profile", "captures profiler scopes as a Chrome trace: profile start|stop|save [path]", profile);
+    console.register_with_help("reset", "puts a variable back to its default: reset <name>", |args, engine| {
+      let name = args.first().ok_or("reset which variable?")?;
+      engine.cvars.reset(name)
+    });
+    console.register_with_help("quit", "exits the game", |_, engine| {
+      engine.exit();
+      Ok(())
+    });
+    console
+  }
+
+  pub fn register(&mut self, name: impl Into<String>, run: impl Fn(&[&str], &mut Engine) -> Result<(), String> + 'static) {
+    self.register_with_help(name, "", run);
+  }
+
+  // Registers a command with a line for `help` to show. Replaces any command of the same name.
+  pub fn register_with_help(&mut self, name: impl Into<String>, help: impl Into<String>, run: impl Fn(&[&str], &mut Engine) -> Result<(), String> + 'static) {
+    self.commands.insert(name.into(), Command { help: help.into(), run: Rc::new(run) });
+  }
+
+  pub fn unregister(&mut self, name: &str) -> bool {
+    self.commands.remove(name).is_some()
+  }
+
+  pub fn commands(&self) -> impl Iterator<Item = &str> {
+    self.commands.keys().map(String::as_str)
+  }
+
+  pub fn is_open(&self) -> bool {
+    self.open
+  }
+
+  // Opens the console and starts text input for it.
+  pub fn open(&mut self, input: &mut Input) {
+    self.open = true;
+    input.start_text_input();
+  }
+
+  pub fn close(&mut self, input: &mut Input) {
+    self.open = false;
+    input.stop_text_input();
+  }
+
+  pub fn print(&mut self, text: impl Into<String>) {
+    self.push(text.into(), ConsoleLine::Output);
+  }
+
+  pub fn error(&mut self, text: impl Into<String>) {
+    self.push(text.into(), ConsoleLine::Error);
+  }
+
+  pub fn clear(&mut self) {
+    self.output.clear();
+    self.scroll = 0;
+  }
+
+  // The scrollback, oldest first.
+  pub fn lines(&self) -> impl Iterator<Item = (&str, ConsoleLine)> {
+    self.output.iter().map(|(text, kind)| (text.as_str(), *kind))
+  }
+
+  fn push(&mut self, text: String, kind: ConsoleLine) {
+    for line in text.lines() {
+      if self.output.len() == MAX_LINES {
+        self.output.pop_front();
+      }
+      self.output.push_back((line.to_string(), kind));
+    }
+  }
+
+  // Edits the line being typed from this frame's input. Returns a line to run when Enter was pressed.
+  fn edit(&mut self, input: &Input, cvars: &CVars) -> Option<String> {
+    for character in input.text().chars() {
+      self.line.insert(self.cursor, character);
+      self.cursor += character.len_utf8();
+    }
+    let previous = self.line[..self.cursor].char_indices().next_back().map_or(0, |(index, _)| index);
+    let next = self.line[self.cursor..].chars().next().map_or(self.cursor, |c| self.cursor + c.len_utf8());
+    if input.just_pressed(KeyCode::Back) && self.cursor > 0 {
+      self.line.replace_range(previous..self.cursor, "");
+      self.cursor = previous;
+    } else if input.just_pressed(KeyCode::Delete) {
+      self.line.replace_range(self.cursor..next, "");
+    } else if input.just_pressed(KeyCode::Left) {
+      self.cursor = previous;
+    } else if input.just_pressed(KeyCode::Right) {
+      self.cursor = next;
+    } else if input.just_pressed(KeyCode::Home) {
+      self.cursor = 0;
+    } else if input.just_pressed(KeyCode::End) {
+      self.cursor = self.line.len();
+    } else if input.just_pressed(KeyCode::Up) {
+      self.browse(true);
+    } else if input.just_pressed(KeyCode::Down) {
+      self.browse(false);
+    } else if input.just_pressed(KeyCode::Tab) {
+      self.complete(cvars);
+    } else if input.just_pressed(KeyCode::PageUp) {
+      self.scroll = (self.scroll + PAGE_LINES).min(self.output.len().saturating_sub(1));
+    } else if input.just_pressed(KeyCode::PageDown) {
+      self.scroll = self.scroll.saturating_sub(PAGE_LINES);
+    } else if input.just_pressed(KeyCode::Return) || input.just_pressed(KeyCode::NumpadEnter) {
+      let line = std::mem::take(&mut self.line);
+      self.cursor = 0;
+      self.browsing = None;
+      self.scroll = 0;
+      if !line.trim().is_empty() && self.history.last() != Some(&line) {
+        if self.history.len() == MAX_HISTORY {
+          self.history.remove(0);
+        }
+        self.history.push(line.clone());
+      }
+      return Some(line);
+    }
+    None
+  }
+
+  fn browse(&mut self, back: bool) {
+    let index = match (self.browsing, back) {
+      (None, true) => self.history.len().checked_sub(1),
+      (None, false) => None,
+      (Some(index), true) => Some(index.saturating_sub(1)),
+      (Some(index), false) => Some(index + 1).filter(|index| *index < self.history.len()),
+    };
+    self.browsing = index;
+    self.line = index.map_or(String::new(), |index| self.history[index].clone());
+    self.cursor = self.line.len();
+  }
+
+  // Completes the command or variable name being typed as far as it's the same for every match,
+  // listing them when there's more than one.
+  fn complete(&mut self, cvars: &CVars) {
+    if self.line[..self.cursor].contains(char::is_whitespace) {
+      return;
+    }
+    let typed = &self.line[..self.cursor];
+    let names = self.commands.keys().map(String::as_str).chain(cvars.names());
+    let matches: Vec<String> = names.filter(|name| name.starts_with(typed)).map(str::to_string).collect();
+    let Some(first) = matches.first() else { return };
+    let common = matches.iter().fold(first.len(), |common, name| {
+      first.char_indices().zip(name.chars()).take_while(|((_, a), b)| a == b).last().map_or(0, |((index, a), _)| index + a.len_utf8()).min(common)
+    });
+    let completed = if matches.len() == 1 { format!("{} ", first) } else { first[..common].to_string() };
+    self.line.replace_range(..self.cursor, &completed);
+    self.cursor = completed.len();
+    if matches.len() > 1 {
+      self.print(matches.join("  "));
+    }
+  }
+
+  // Draws the console over the top of the window, if it's open.
+  pub fn draw(&self, ui: &mut UiDraw, fonts: &Fonts, time: f32) {
+    let font_id = self.font.unwrap_or(FontId(0));
+    let (true, Some(font)) = (self.open, fonts.get(font_id)) else { return };
+    let size = self.font_size;
+    let line_height = font.line_height(size);
+    let padding = 6.0;
+    let bottom = (ui.viewport.y * self.height.clamp(0.1, 1.0)).max(line_height * 2.0 + padding * 2.0);
+    ui.rect(Vec2::ZERO, Vec2::new(ui.viewport.x, bottom), Color::rgba(0.05, 0.05, 0.08, 0.88));
+    ui.rect(Vec2::new(0.0, bottom - 1.0), Vec2::new(ui.viewport.x, bottom), Color::rgba(0.4, 0.4, 0.5, 1.0));
+
+    let input_y = bottom - padding - line_height;
+    ui.text(font_id, format!("{}{}", PROMPT, self.line), Vec2::new(padding, input_y), size, Color::WHITE);
+    // The caret blinks twice a second.
+    if time.fract() < 0.5 {
+      let x = padding + font.line_width(&format!("{}{}", PROMPT, &self.line[..self.cursor]), size);
+      ui.rect(Vec2::new(x, input_y), Vec2::new(x + 1.0, input_y + line_height), Color::WHITE);
+    }
+
+    let mut y = input_y - line_height;
+    for (text, kind) in self.output.iter().rev().skip(self.scroll) {
+      if y < padding - line_height * 0.5 {
+        break;
+      }
+      let color = match kind {
+        ConsoleLine::Command => Color::rgba(0.6, 0.6, 0.7, 1.0),
+        ConsoleLine::Output => Color::WHITE,
+        ConsoleLine::Error => Color::rgba(1.0, 0.45, 0.4, 1.0),
+      };
+      ui.text(font_id, text.clone(), Vec2::new(padding, y), size, color);
+      y -= line_height;
+    }
+  }
+}
+
+impl Default for Console {
+  fn default() -> Self {
+    Console::new()
+  }
+}
+
+// Runs a line as if it had been typed into the console, e.g. from a config file or the command line.
+pub fn run_command(engine: &mut Engine, line: &str) {
+  let words = split_words(line);
+  let Some((name, args)) = words.split_first() else { return };
+  let args: Vec<&str> = args.iter().map(String::as_str).collect();
+  let result = match engine.console.commands.get(name).cloned() {
+    Some(command) => (command.run)(&args, engine),
+    None if engine.cvars.contains(name) && args.is_empty() => {
+      let line = describe_cvar(&engine.cvars, name);
+      engine.console.print(line);
+      Ok(())
+    }
+    None if engine.cvars.contains(name) => engine.cvars.set_from_str(name, &args.join(" ")),
+    None => Err(format!("unknown command '{}'; try 'help'", name)),
+  };
+  if let Err(err) = result {
+    engine.console.error(err);
+  }
+}
+
+// Opens, closes and types into the engine's console. Called by the engine at the start of every frame.
+pub(crate) fn update_console(engine: &mut Engine) {
+  let Engine { console, input, cvars, .. } = engine;
+  let toggled = console.key.is_some_and(|key| input.just_pressed(key));
+  if toggled || (console.open && input.just_pressed(KeyCode::Escape)) {
+    if console.open {
+      console.close(input);
+    } else {
+      console.open(input);
+    }
+    return;
+  }
+  if !console.open {
+    return;
+  }
+  if let Some(line) = console.edit(input, cvars) {
+    console.push(format!("{}{}", PROMPT, line), ConsoleLine::Command);
+    run_command(engine, &line);
+  }
+}
+
+// Splits on whitespace, keeping "quoted words" together.
+fn split_words(line: &str) -> Vec<String> {
+  let mut words = Vec::new();
+  let mut word: Option<String> = None;
+  let mut quoted = false;
+  for character in line.chars() {
+    match character {
+      '"' => {
+        quoted = !quoted;
+        word.get_or_insert_with(String::new);
+      }
+      c if c.is_whitespace() && !quoted => words.extend(word.take()),
+      c => word.get_or_insert_with(String::new).push(c),
+    }
+  }
+  words.extend(word);
+  words
+}
+
+fn help(args: &[&str], engine: &mut Engine) -> Result<(), String> {
+  let console = &mut engine.console;
+  if let Some(name) = args.first() {
+    let command = console.commands.get(*name).ok_or_else(|| format!("no command called '{}'", name))?;
+    let help = if command.help.is_empty() { "no description" } else { command.help.as_str() };
+    let line = format!("{}: {}", name, help);
+    console.print(line);
+    return Ok(());
+  }
+  let lines: Vec<String> = console.commands.iter().map(|(name, command)| match command.help.as_str() {
+    "" => name.clone(),
+    help => format!("{} - {}", name, help),
+  }).collect();
+  console.print(lines.join("\n"));
+  Ok(())
+}
+
+fn entities(args: &[&str], engine: &mut Engine) -> Result<(), String> {
+  let filter = args.first().copied().unwrap_or("");
+  let world = engine.worlds.active();
+  let mut lines = Vec::new();
+  for entity in world.entities() {
+    let name = world.get::<Name>(entity).map(|name| name.as_str().to_string());
+    if !filter.is_empty() && !name.as_deref().is_some_and(|name| name.contains(filter)) {
+      continue;
+    }
+    let components: Vec<&str> = engine.registry.iter().filter(|info| info.has(world, entity)).map(|info| info.name).collect();
+    lines.push(format!("{}v{} {} [{}]", entity.index(), entity.generation(), name.as_deref().unwrap_or("-"), components.join(", ")));
+  }
+  lines.push(format!("{} entities", lines.len()));
+  engine.console.print(lines.join("\n"));
+  Ok(())
+}
+
+fn log_command(args: &[&str], engine: &mut Engine) -> Result<(), String> {
+  let mut count = 20;
+  let mut level = LevelFilter::Trace;
+  for arg in args {
+    match (arg.parse::<usize>(), arg.parse::<LevelFilter>()) {
+      (Ok(number), _) => count = number,
+      (_, Ok(filter)) => level = filter,
+      _ => return Err(format!("'{}' isn't a count or a level", arg)),
+    }
+  }
+  for record in logging::recent(count, level) {
+    match record.level {
+      Level::Error | Level::Warn => engine.console.error(record.format()),
+      _ => engine.console.print(record.format()),
+    }
+  }
+  Ok(())
+}
+
+fn profile(args: &[&str], engine: &mut Engine) -> Result<(), String> {
+  match args {
+    ["start"] => profiler::start_capture(),
+    ["stop"] => drop(profiler::stop_capture()),
+    ["save", path @ ..] if path.len() <= 1 => {
+      let path = path.first().copied().unwrap_or("profile.json");
+      if !profiler::is_capturing() {
+        return Err("nothing's being captured; profile start first".to_string());
+      }
+      let count = profiler::save_capture(path).map_err(|err| err.to_string())?;
+      engine.console.print(format!("saved {} scopes to {}", count, path));
+    }
+    _ => return Err("profile start, stop, or save [path]".to_string()),
+  }
+  Ok(())
+}
+
+fn cvars(args: &[&str], engine: &mut Engine) -> Result<(), String> {
+  let filter = args.first().copied().unwrap_or("");
+  let lines: Vec<String> = engine.cvars.names().filter(|name| name.contains(filter)).map(|name| describe_cvar(&engine.cvars, name)).collect();
+  engine.console.print(lines.join("\n"));
+  Ok(())
+}
+
+fn describe_cvar(cvars: &CVars, name: &str) -> String {
+  let (Some(value), Some(default)) = (cvars.get(name), cvars.default_value(name)) else { return String::new() };
+  let mut line = format!("{} = {}", name, value);
+  if value != default {
+    line += &format!(" (default {})", default);
+  }
+  match cvars.help(name) {
+    Some("") | None => line,
+    Some(help) => format!("{} - {}", line, help),
+  }
+}
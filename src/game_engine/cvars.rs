@@ -0,0 +1,330 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::rc::Rc;
+use serde::{Deserialize, Serialize};
+
+use super::error::EngineError;
+use super::Engine;
+
+// Runs after a variable's value changes, at the start of the next frame.
+pub type CVarChangedFn = Rc<dyn Fn(&mut Engine, &CVarValue)>;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CVarValue {
+  Bool(bool),
+  Int(i64),
+  Float(f64),
+  String(String),
+}
+
+impl CVarValue {
+  pub fn as_bool(&self) -> Option<bool> {
+    match self {
+      CVarValue::Bool(value) => Some(*value),
+      _ => None,
+    }
+  }
+
+  pub fn as_int(&self) -> Option<i64> {
+    match self {
+      CVarValue::Int(value) => Some(*value),
+      _ => None,
+    }
+  }
+
+  // Ints read as floats too.
+  pub fn as_float(&self) -> Option<f64> {
+    match self {
+      CVarValue::Float(value) => Some(*value),
+      CVarValue::Int(value) => Some(*value as f64),
+      _ => None,
+    }
+  }
+
+  pub fn as_str(&self) -> Option<&str> {
+    match self {
+      CVarValue::String(value) => Some(value),
+      _ => None,
+    }
+  }
+
+  pub fn type_name(&self) -> &'static str {
+    match self {
+      CVarValue::Bool(_) => "bool",
+      CVarValue::Int(_) => "int",
+      CVarValue::Float(_) => "float",
+      CVarValue::String(_) => "string",
+    }
+  }
+
+  fn described(&self) -> &'static str {
+    match self {
+      CVarValue::Int(_) => "an int",
+      CVarValue::Bool(_) => "a bool",
+      CVarValue::Float(_) => "a float",
+      CVarValue::String(_) => "a string",
+    }
+  }
+
+  // Reads `text` as a value of the same type as this one.
+  pub fn parse_like(&self, text: &str) -> Result<CVarValue, String> {
+    let error = || format!("'{}' isn't {}", text, self.described());
+    match self {
+      CVarValue::Bool(_) => match text {
+        "1" | "true" | "on" | "yes" => Ok(CVarValue::Bool(true)),
+        "0" | "false" | "off" | "no" => Ok(CVarValue::Bool(false)),
+        _ => Err(error()),
+      },
+      CVarValue::Int(_) => text.parse().map(CVarValue::Int).map_err(|_| error()),
+      CVarValue::Float(_) => text.parse().map(CVarValue::Float).map_err(|_| error()),
+      CVarValue::String(_) => Ok(CVarValue::String(text.to_string())),
+    }
+  }
+
+  // The value as `like`'s type, where it can be without losing anything.
+  fn convert_like(self, like: &CVarValue) -> Result<CVarValue, String> {
+    match (like, self) {
+      (like, value) if std::mem::discriminant(like) == std::mem::discriminant(&value) => Ok(value),
+      (CVarValue::Float(_), CVarValue::Int(value)) => Ok(CVarValue::Float(value as f64)),
+      (CVarValue::String(_), value) => Ok(CVarValue::String(value.to_string())),
+      (like, value) => Err(format!("{} isn't {}", value, like.described())),
+    }
+  }
+}
+
+impl fmt::Display for CVarValue {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      CVarValue::Bool(value) => write!(f, "{}", value),
+      CVarValue::Int(value) => write!(f, "{}", value),
+      CVarValue::Float(value) => write!(f, "{}", value),
+      CVarValue::String(value) => write!(f, "\"{}\"", value),
+    }
+  }
+}
+
+impl From<bool> for CVarValue {
+  fn from(value: bool) -> Self {
+    CVarValue::Bool(value)
+  }
+}
+
+impl From<i32> for CVarValue {
+  fn from(value: i32) -> Self {
+    CVarValue::Int(value as i64)
+  }
+}
+
+impl From<i64> for CVarValue {
+  fn from(value: i64) -> Self {
+    CVarValue::Int(value)
+  }
+}
+
+impl From<f32> for CVarValue {
+  fn from(value: f32) -> Self {
+    CVarValue::Float(value as f64)
+  }
+}
+
+impl From<f64> for CVarValue {
+  fn from(value: f64) -> Self {
+    CVarValue::Float(value)
+  }
+}
+
+impl From<&str> for CVarValue {
+  fn from(value: &str) -> Self {
+    CVarValue::String(value.to_string())
+  }
+}
+
+impl From<String> for CVarValue {
+  fn from(value: String) -> Self {
+    CVarValue::String(value)
+  }
+}
+
+// Where a value that arrived before its variable was registered came from.
+#[derive(Debug, Clone, PartialEq)]
+enum Pending {
+  Value(CVarValue), // from a config file
+  Text(String), // from the command line
+}
+
+struct CVar {
+  value: CVarValue,
+  default: CVarValue,
+  help: String,
+  handlers: Vec<CVarChangedFn>,
+}
+
+// Named runtime settings, on `Engine`, each of a fixed type: engine knobs like `fixed_hz` and
+// `render_scale`, and whatever the game registers. They can be set from code, from the console by
+// typing a name and a value, on the command line as `+name=value`, and from a config file, which
+// keeps the ones that differ from their defaults. Values for names that haven't been registered yet
+// are held until they are.
+#[derive(Default)]
+pub struct CVars {
+  vars: BTreeMap<String, CVar>,
+  pending: BTreeMap<String, Pending>,
+  changed: Vec<String>, // waiting for their handlers to run
+}
+
+impl CVars {
+  pub fn new() -> Self {
+    CVars::default()
+  }
+
+  // Adds a variable, which takes on any value given for it before now. Registering a name again
+  // keeps its value if it's still the same type.
+  pub fn register(&mut self, name: impl Into<String>, default: impl Into<CVarValue>, help: impl Into<String>) {
+    let (name, default) = (name.into(), default.into());
+    let (value, handlers) = match self.vars.remove(&name) {
+      Some(old) => (old.value.convert_like(&default).unwrap_or_else(|_| default.clone()), old.handlers),
+      None => (default.clone(), Vec::new()),
+    };
+    self.vars.insert(name.clone(), CVar { value, default, help: help.into(), handlers });
+    let result = match self.pending.remove(&name) {
+      Some(Pending::Value(value)) => self.set(&name, value),
+      Some(Pending::Text(text)) => self.set_from_str(&name, &text),
+      None => Ok(()),
+    };
+    if let Err(err) = result {
+      log::warn!("couldn't set {}: {}", name, err);
+    }
+  }
+
+  pub fn contains(&self, name: &str) -> bool {
+    self.vars.contains_key(name)
+  }
+
+  pub fn get(&self, name: &str) -> Option<&CVarValue> {
+    self.vars.get(name).map(|var| &var.value)
+  }
+
+  pub fn get_bool(&self, name: &str) -> Option<bool> {
+    self.get(name)?.as_bool()
+  }
+
+  pub fn get_int(&self, name: &str) -> Option<i64> {
+    self.get(name)?.as_int()
+  }
+
+  pub fn get_float(&self, name: &str) -> Option<f64> {
+    self.get(name)?.as_float()
+  }
+
+  pub fn get_str(&self, name: &str) -> Option<&str> {
+    self.get(name)?.as_str()
+  }
+
+  pub fn default_value(&self, name: &str) -> Option<&CVarValue> {
+    self.vars.get(name).map(|var| &var.default)
+  }
+
+  pub fn help(&self, name: &str) -> Option<&str> {
+    self.vars.get(name).map(|var| var.help.as_str())
+  }
+
+  pub fn names(&self) -> impl Iterator<Item = &str> {
+    self.vars.keys().map(String::as_str)
+  }
+
+  // Sets a registered variable, which has to be given a value of its type; an int will do for a float.
+  pub fn set(&mut self, name: &str, value: impl Into<CVarValue>) -> Result<(), String> {
+    let var = self.vars.get_mut(name).ok_or_else(|| format!("no variable called '{}'", name))?;
+    let value = value.into().convert_like(&var.default)?;
+    if var.value != value {
+      var.value = value;
+      if !self.changed.iter().any(|changed| changed == name) {
+        self.changed.push(name.to_string());
+      }
+    }
+    Ok(())
+  }
+
+  // Sets a registered variable from text, read as the variable's type.
+  pub fn set_from_str(&mut self, name: &str, text: &str) -> Result<(), String> {
+    let var = self.vars.get(name).ok_or_else(|| format!("no variable called '{}'", name))?;
+    let value = var.default.parse_like(text)?;
+    self.set(name, value)
+  }
+
+  pub fn reset(&mut self, name: &str) -> Result<(), String> {
+    let default = self.default_value(name).ok_or_else(|| format!("no variable called '{}'", name))?.clone();
+    self.set(name, default)
+  }
+
+  // Calls `handler` with a variable's new value whenever it changes.
+  pub fn on_change(&mut self, name: &str, handler: impl Fn(&mut Engine, &CVarValue) + 'static) {
+    match self.vars.get_mut(name) {
+      Some(var) => var.handlers.push(Rc::new(handler)),
+      None => log::warn!("can't watch '{}', which isn't registered", name),
+    }
+  }
+
+  // Applies `+name=value` arguments, as from the command line, ignoring anything else.
+  pub fn apply_args(&mut self, args: impl IntoIterator<Item = String>) {
+    for arg in args {
+      let Some((name, value)) = arg.strip_prefix('+').and_then(|arg| arg.split_once('=')) else { continue };
+      if !self.contains(name) {
+        self.pending.insert(name.to_string(), Pending::Text(value.to_string()));
+      } else if let Err(err) = self.set_from_str(name, value) {
+        log::warn!("couldn't set {} from the command line: {}", name, err);
+      }
+    }
+  }
+
+  // Reads variables saved by `save`. A file that isn't there is left for `save` to create.
+  pub fn load(&mut self, path: impl AsRef<Path>) -> Result<(), EngineError> {
+    let path = path.as_ref();
+    let text = match std::fs::read_to_string(path) {
+      Ok(text) => text,
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+      Err(err) => return Err(EngineError::Asset { path: path.to_path_buf(), message: err.to_string() }),
+    };
+    let values: BTreeMap<String, CVarValue> = ron::from_str(&text).map_err(|err| EngineError::Asset { path: path.to_path_buf(), message: err.to_string() })?;
+    for (name, value) in values {
+      if !self.contains(&name) {
+        self.pending.insert(name, Pending::Value(value));
+      } else if let Err(err) = self.set(&name, value) {
+        log::warn!("couldn't set {} from {}: {}", name, path.display(), err);
+      }
+    }
+    Ok(())
+  }
+
+  // Writes out the variables that differ from their defaults, along with any loaded but never registered.
+  pub fn save(&self, path: impl AsRef<Path>) -> Result<(), EngineError> {
+    let path = path.as_ref();
+    let error = |message: String| EngineError::Save { path: path.to_path_buf(), message };
+    let mut values: BTreeMap<&str, CVarValue> = self.pending.iter().filter_map(|(name, pending)| match pending {
+      Pending::Value(value) => Some((name.as_str(), value.clone())),
+      Pending::Text(_) => None,
+    }).collect();
+    values.extend(self.vars.iter().filter(|(_, var)| var.value != var.default).map(|(name, var)| (name.as_str(), var.value.clone())));
+    if let Some(directory) = path.parent().filter(|directory| !directory.as_os_str().is_empty()) {
+      std::fs::create_dir_all(directory).map_err(|err| error(err.to_string()))?;
+    }
+    let text = ron::ser::to_string_pretty(&values, ron::ser::PrettyConfig::default()).map_err(|err| error(err.to_string()))?;
+    std::fs::write(path, text).map_err(|err| error(err.to_string()))
+  }
+
+  fn take_changed(&mut self) -> Vec<(CVarValue, Vec<CVarChangedFn>)> {
+    std::mem::take(&mut self.changed).into_iter()
+      .filter_map(|name| self.vars.get(&name).map(|var| (var.value.clone(), var.handlers.clone())))
+      .collect()
+  }
+}
+
+// Runs the handlers of variables that changed since the last frame. Called by the engine at the
+// start of every frame.
+pub(crate) fn run_cvar_handlers(engine: &mut Engine) {
+  for (value, handlers) in engine.cvars.take_changed() {
+    for handler in handlers {
+      handler(engine, &value);
+    }
+  }
+}
@@ -0,0 +1,36 @@
+use std::ops::{Deref, DerefMut};
+
+// Mutable access to a component handed out by queries. Only writing through it (`DerefMut`) marks
+// the component as changed, so reading a `&mut T` query item doesn't trip `Changed<T>` filters.
+pub struct Mut<'a, T> {
+  pub(crate) value: &'a mut T,
+  pub(crate) changed: &'a mut u32,
+  pub(crate) tick: u32,
+}
+
+impl<'a, T> Mut<'a, T> {
+  // Writes without flagging the component as changed, for bookkeeping other systems shouldn't see.
+  pub fn bypass_change_detection(&mut self) -> &mut T {
+    self.value
+  }
+
+  pub fn into_inner(self) -> &'a mut T {
+    *self.changed = self.tick;
+    self.value
+  }
+}
+
+impl<T> Deref for Mut<'_, T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    self.value
+  }
+}
+
+impl<T> DerefMut for Mut<'_, T> {
+  fn deref_mut(&mut self) -> &mut T {
+    *self.changed = self.tick;
+    self.value
+  }
+}
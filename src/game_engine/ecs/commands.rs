@@ -0,0 +1,69 @@
+use super::entity::Entity;
+use super::storage::Component;
+use super::world::World;
+
+pub(crate) type Command = Box<dyn FnOnce(&mut World)>;
+
+// Queues world mutations while the world is only borrowed (e.g. from inside `Query::for_each`).
+// Nothing happens until the next sync point, `World::apply_commands`, which the engine runs after
+// the user task each frame.
+pub struct Commands<'w> {
+  world: &'w World,
+}
+
+impl<'w> Commands<'w> {
+  pub(crate) fn new(world: &'w World) -> Self {
+    Commands { world }
+  }
+
+  // The returned entity id is valid immediately, but the entity only comes alive once the
+  // commands are applied.
+  pub fn spawn(&self) -> EntityCommands<'_, 'w> {
+    let entity = self.world.reserve_entity();
+    EntityCommands { entity, commands: self }
+  }
+
+  pub fn entity(&self, entity: Entity) -> EntityCommands<'_, 'w> {
+    EntityCommands { entity, commands: self }
+  }
+
+  pub fn despawn(&self, entity: Entity) {
+    self.add(move |world| { world.despawn(entity); });
+  }
+
+  // Queues an arbitrary mutation.
+  pub fn add(&self, command: impl FnOnce(&mut World) + 'static) {
+    self.world.push_command(Box::new(command));
+  }
+}
+
+pub struct EntityCommands<'a, 'w> {
+  entity: Entity,
+  commands: &'a Commands<'w>,
+}
+
+impl EntityCommands<'_, '_> {
+  pub fn id(&self) -> Entity {
+    self.entity
+  }
+
+  pub fn insert<T: Component>(self, component: T) -> Self {
+    let entity = self.entity;
+    self.commands.add(move |world| {
+      if world.is_alive(entity) {
+        world.insert(entity, component);
+      }
+    });
+    self
+  }
+
+  pub fn remove<T: Component>(self) -> Self {
+    let entity = self.entity;
+    self.commands.add(move |world| { world.remove::<T>(entity); });
+    self
+  }
+
+  pub fn despawn(self) {
+    self.commands.despawn(self.entity);
+  }
+}
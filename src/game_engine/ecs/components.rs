@@ -0,0 +1,87 @@
+use glam::{Mat4, Quat, Vec3};
+use serde::{Deserialize, Serialize};
+
+use super::world::World;
+
+// Where an entity is in the world. Everything that has a position (meshes, sprites, physics
+// bodies, cameras) reads it from here.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Transform {
+  pub translation: Vec3,
+  pub rotation: Quat,
+  pub scale: Vec3,
+}
+
+impl Transform {
+  pub const IDENTITY: Transform = Transform {
+    translation: Vec3::ZERO,
+    rotation: Quat::IDENTITY,
+    scale: Vec3::ONE,
+  };
+
+  pub fn from_translation(translation: Vec3) -> Self {
+    Transform { translation, ..Transform::IDENTITY }
+  }
+
+  pub fn from_xyz(x: f32, y: f32, z: f32) -> Self {
+    Transform::from_translation(Vec3::new(x, y, z))
+  }
+
+  pub fn with_rotation(mut self, rotation: Quat) -> Self {
+    self.rotation = rotation;
+    self
+  }
+
+  pub fn with_scale(mut self, scale: Vec3) -> Self {
+    self.scale = scale;
+    self
+  }
+
+  pub fn matrix(&self) -> Mat4 {
+    Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+  }
+
+  pub fn forward(&self) -> Vec3 {
+    self.rotation * Vec3::NEG_Z
+  }
+
+  pub fn right(&self) -> Vec3 {
+    self.rotation * Vec3::X
+  }
+
+  pub fn up(&self) -> Vec3 {
+    self.rotation * Vec3::Y
+  }
+}
+
+impl Default for Transform {
+  fn default() -> Self {
+    Transform::IDENTITY
+  }
+}
+
+// How fast an entity moves, in units per second, and spins, as an axis scaled by radians per
+// second. `apply_velocities` moves the `Transform`s; bodies simulated by `PhysicsWorld` don't
+// need it. The engine calls it every fixed step, right after `fixed_schedule`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Velocity {
+  pub linear: Vec3,
+  pub angular: Vec3,
+}
+
+impl Velocity {
+  pub fn linear(linear: Vec3) -> Self {
+    Velocity { linear, angular: Vec3::ZERO }
+  }
+}
+
+pub fn apply_velocities(world: &World, dt: f32) {
+  world.query::<(&mut Transform, &Velocity)>().for_each(|_, (mut transform, velocity)| {
+    transform.translation += velocity.linear * dt;
+    if velocity.angular != Vec3::ZERO {
+      transform.rotation = (Quat::from_scaled_axis(velocity.angular * dt) * transform.rotation).normalize();
+    }
+  });
+}
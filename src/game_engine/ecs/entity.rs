@@ -0,0 +1,98 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize};
+
+thread_local! {
+  // Set while a save or scene is being loaded, so entities stored inside components point at the
+  // ones spawned for them.
+  static ENTITY_MAP: RefCell<Option<EntityMap>> = const { RefCell::new(None) };
+}
+
+// An entity is just an index into the world plus a generation, so a stale handle to a despawned
+// entity never aliases whatever gets spawned into the same slot afterwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct Entity {
+  pub(crate) index: u32,
+  pub(crate) generation: u32,
+}
+
+impl Entity {
+  // Stands in for an entity that hasn't been set yet, e.g. in a component's `Default`. Never alive.
+  pub const PLACEHOLDER: Entity = Entity { index: u32::MAX, generation: u32::MAX };
+
+  pub fn index(&self) -> u32 {
+    self.index
+  }
+
+  pub fn generation(&self) -> u32 {
+    self.generation
+  }
+
+  // The entity as a single number, `(generation << 32) | index`, for handing across a boundary
+  // that only takes numbers, like the network or a plugin.
+  pub fn to_bits(&self) -> u64 {
+    ((self.generation as u64) << 32) | self.index as u64
+  }
+
+  pub fn from_bits(bits: u64) -> Self {
+    Entity { index: bits as u32, generation: (bits >> 32) as u32 }
+  }
+}
+
+// Scenes can refer to an entity by its name instead of its id. Binary formats can't tell the two
+// apart, and only ever hold ids.
+impl<'de> Deserialize<'de> for Entity {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Saved {
+      Id { index: u32, generation: u32 },
+      Name(String),
+    }
+    #[derive(Deserialize)]
+    #[serde(rename = "Entity")]
+    struct Id {
+      index: u32,
+      generation: u32,
+    }
+    let saved = if deserializer.is_human_readable() {
+      Saved::deserialize(deserializer)?
+    } else {
+      let Id { index, generation } = Id::deserialize(deserializer)?;
+      Saved::Id { index, generation }
+    };
+    ENTITY_MAP.with(|map| {
+      let map = map.borrow();
+      match saved {
+        Saved::Id { index, generation } => {
+          let entity = Entity { index, generation };
+          Ok(map.as_ref().and_then(|map| map.ids.get(&entity).copied()).unwrap_or(entity))
+        }
+        Saved::Name(name) => map.as_ref().and_then(|map| map.names.get(&name).copied())
+          .ok_or_else(|| D::Error::custom(format!("no entity named {}", name))),
+      }
+    })
+  }
+}
+
+// What saved entity ids and names turn into while loading.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EntityMap {
+  pub ids: HashMap<Entity, Entity>, // ids missing from here are left as they are
+  pub names: HashMap<String, Entity>,
+}
+
+// Runs `f` with entities deserialized inside it translated through `map`.
+pub(crate) fn with_entity_map<R>(map: EntityMap, f: impl FnOnce() -> R) -> R {
+  let previous = ENTITY_MAP.with(|current| current.replace(Some(map)));
+  let result = f();
+  ENTITY_MAP.with(|current| current.replace(previous));
+  result
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct EntityMeta {
+  pub generation: u32,
+  pub alive: bool,
+}
@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+
+use super::components::Transform;
+use super::entity::Entity;
+use super::world::World;
+
+// The entity this one hangs off. Its `Transform` is then relative to the parent's; see
+// `World::world_transform`. Set it with `World::set_parent` so the parent's `Children` stays in step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Parent(pub Entity);
+
+impl Default for Parent {
+  fn default() -> Self {
+    Parent(Entity::PLACEHOLDER)
+  }
+}
+
+// The entities whose `Parent` is this one, in the order they were added.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Children(pub Vec<Entity>);
+
+impl World {
+  // Hangs `child` off `parent`, taking it away from any parent it had. Returns false, changing
+  // nothing, if `parent` is `child` or below it.
+  pub fn set_parent(&mut self, child: Entity, parent: Entity) -> bool {
+    let mut ancestor = Some(parent);
+    while let Some(entity) = ancestor {
+      if entity == child {
+        return false;
+      }
+      ancestor = self.parent(entity);
+    }
+    self.remove_parent(child);
+    self.insert(child, Parent(parent));
+    match self.get_mut::<Children>(parent) {
+      Some(children) => children.0.push(child),
+      None => {
+        self.insert(parent, Children(vec![child]));
+      }
+    }
+    true
+  }
+
+  // Makes `child` a root again. Its `Transform` isn't changed, so it may jump.
+  pub fn remove_parent(&mut self, child: Entity) -> Option<Entity> {
+    let Parent(parent) = self.remove::<Parent>(child)?;
+    if let Some(children) = self.get_mut::<Children>(parent) {
+      children.0.retain(|entity| *entity != child);
+    }
+    Some(parent)
+  }
+
+  pub fn parent(&self, entity: Entity) -> Option<Entity> {
+    self.get::<Parent>(entity).map(|parent| parent.0)
+  }
+
+  pub fn children(&self, entity: Entity) -> Vec<Entity> {
+    self.get::<Children>(entity).map_or_else(Vec::new, |children| children.0.clone())
+  }
+
+  // Despawns `entity` and everything below it.
+  pub fn despawn_recursive(&mut self, entity: Entity) -> bool {
+    for child in self.children(entity) {
+      self.despawn_recursive(child);
+    }
+    self.remove_parent(entity);
+    self.despawn(entity)
+  }
+
+  // The entity's `Transform` with all of its parents' applied, or `None` without one.
+  pub fn world_transform(&self, entity: Entity) -> Option<Transform> {
+    let mut matrix = self.get::<Transform>(entity)?.matrix();
+    let mut current = entity;
+    while let Some(parent) = self.parent(current) {
+      if let Some(transform) = self.get::<Transform>(parent) {
+        matrix = transform.matrix() * matrix;
+      }
+      current = parent;
+    }
+    let (scale, rotation, translation) = matrix.to_scale_rotation_translation();
+    Some(Transform { translation, rotation, scale })
+  }
+}
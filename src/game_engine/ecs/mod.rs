@@ -0,0 +1,31 @@
+mod change;
+mod commands;
+mod components;
+mod entity;
+mod hierarchy;
+mod name;
+mod query;
+mod reflect;
+mod resource;
+mod schedule;
+mod state;
+mod storage;
+mod world;
+mod worlds;
+
+pub use self::{
+  change::Mut,
+  commands::*,
+  components::*,
+  entity::*,
+  hierarchy::*,
+  name::*,
+  query::*,
+  reflect::*,
+  resource::{Res, ResMut},
+  schedule::*,
+  state::*,
+  storage::Component,
+  world::*,
+  worlds::*
+};
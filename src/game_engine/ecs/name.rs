@@ -0,0 +1,30 @@
+use serde::{Deserialize, Serialize};
+
+// Human-readable name for an entity. The world indexes these so `World::find_by_name` doesn't have
+// to scan; changing one in place works, but costs a rebuild of the index on the next lookup.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Name(String);
+
+impl Name {
+  pub fn new(name: impl Into<String>) -> Self {
+    Name(name.into())
+  }
+
+  pub fn as_str(&self) -> &str {
+    &self.0
+  }
+}
+
+// Groups entities under a label, e.g. every "enemy". Looked up with `World::with_tag`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Tag(String);
+
+impl Tag {
+  pub fn new(tag: impl Into<String>) -> Self {
+    Tag(tag.into())
+  }
+
+  pub fn as_str(&self) -> &str {
+    &self.0
+  }
+}
@@ -0,0 +1,266 @@
+use std::any::TypeId;
+use std::cell::{Ref, RefMut};
+use std::marker::PhantomData;
+
+use super::change::Mut;
+use super::entity::Entity;
+use super::storage::{downcast_mut, downcast_ref, AnyStorage, Component};
+use super::world::World;
+
+// Something that can be fetched per entity by a `Query`: `&T`, `&mut T`, `Option<Q>` or a tuple of
+// those. `Fetch` holds the storage borrows for the duration of the query.
+pub trait WorldQuery {
+  type Fetch<'w>;
+  type Item<'f>;
+
+  // Adds each component type this fetches to `access`, with whether it's fetched mutably.
+  fn access(access: &mut Vec<(TypeId, &'static str, bool)>);
+
+  // Returns `None` when a required component type has never been inserted, so nothing can match.
+  fn init(world: &World) -> Option<Self::Fetch<'_>>;
+  fn fetch<'f>(fetch: &'f mut Self::Fetch<'_>, index: u32, tick: u32) -> Option<Self::Item<'f>>;
+}
+
+impl<T: Component> WorldQuery for &T {
+  type Fetch<'w> = Ref<'w, Box<dyn AnyStorage>>;
+  type Item<'f> = &'f T;
+
+  fn access(access: &mut Vec<(TypeId, &'static str, bool)>) {
+    access.push((TypeId::of::<T>(), std::any::type_name::<T>(), false));
+  }
+
+  fn init(world: &World) -> Option<Self::Fetch<'_>> {
+    world.storage_cell(TypeId::of::<T>()).map(|storage| storage.borrow())
+  }
+
+  fn fetch<'f>(fetch: &'f mut Self::Fetch<'_>, index: u32, _tick: u32) -> Option<&'f T> {
+    downcast_ref::<T>(&***fetch).get(index)
+  }
+}
+
+impl<T: Component> WorldQuery for &mut T {
+  type Fetch<'w> = RefMut<'w, Box<dyn AnyStorage>>;
+  type Item<'f> = Mut<'f, T>;
+
+  fn access(access: &mut Vec<(TypeId, &'static str, bool)>) {
+    access.push((TypeId::of::<T>(), std::any::type_name::<T>(), true));
+  }
+
+  fn init(world: &World) -> Option<Self::Fetch<'_>> {
+    world.mark_mutable::<T>();
+    world.storage_cell(TypeId::of::<T>()).map(|storage| storage.borrow_mut())
+  }
+
+  fn fetch<'f>(fetch: &'f mut Self::Fetch<'_>, index: u32, tick: u32) -> Option<Mut<'f, T>> {
+    let slot = downcast_mut::<T>(&mut ***fetch).slot_mut(index)?;
+    Some(Mut { value: &mut slot.value, changed: &mut slot.changed, tick })
+  }
+}
+
+impl<Q: WorldQuery> WorldQuery for Option<Q> {
+  type Fetch<'w> = Option<Q::Fetch<'w>>;
+  type Item<'f> = Option<Q::Item<'f>>;
+
+  fn access(access: &mut Vec<(TypeId, &'static str, bool)>) {
+    Q::access(access);
+  }
+
+  fn init(world: &World) -> Option<Self::Fetch<'_>> {
+    Some(Q::init(world))
+  }
+
+  fn fetch<'f>(fetch: &'f mut Self::Fetch<'_>, index: u32, tick: u32) -> Option<Self::Item<'f>> {
+    Some(fetch.as_mut().and_then(|fetch| Q::fetch(fetch, index, tick)))
+  }
+}
+
+macro_rules! impl_world_query_tuple {
+  ($($name:ident),*) => {
+    #[allow(non_snake_case)]
+    impl<$($name: WorldQuery),*> WorldQuery for ($($name,)*) {
+      type Fetch<'w> = ($($name::Fetch<'w>,)*);
+      type Item<'f> = ($($name::Item<'f>,)*);
+
+      fn access(access: &mut Vec<(TypeId, &'static str, bool)>) {
+        $($name::access(access);)*
+      }
+
+      fn init(world: &World) -> Option<Self::Fetch<'_>> {
+        Some(($($name::init(world)?,)*))
+      }
+
+      fn fetch<'f>(fetch: &'f mut Self::Fetch<'_>, index: u32, tick: u32) -> Option<Self::Item<'f>> {
+        let ($($name,)*) = fetch;
+        Some(($($name::fetch($name, index, tick)?,)*))
+      }
+    }
+  };
+}
+
+impl_world_query_tuple!(A);
+impl_world_query_tuple!(A, B);
+impl_world_query_tuple!(A, B, C);
+impl_world_query_tuple!(A, B, C, D);
+impl_world_query_tuple!(A, B, C, D, E);
+impl_world_query_tuple!(A, B, C, D, E, F);
+
+// Narrows a query down without fetching anything. Filters are checked before the query borrows its
+// storages, so `Query<&mut T, Changed<T>>` doesn't conflict with itself.
+pub trait QueryFilter {
+  fn matches(world: &World, entity: Entity) -> bool;
+}
+
+impl QueryFilter for () {
+  fn matches(_world: &World, _entity: Entity) -> bool {
+    true
+  }
+}
+
+pub struct With<T>(PhantomData<T>);
+pub struct Without<T>(PhantomData<T>);
+
+// Matches components inserted since the last `World::clear_trackers`.
+pub struct Added<T>(PhantomData<T>);
+
+// Matches components inserted or written to since the last `World::clear_trackers`.
+pub struct Changed<T>(PhantomData<T>);
+
+impl<T: Component> QueryFilter for With<T> {
+  fn matches(world: &World, entity: Entity) -> bool {
+    world.has::<T>(entity)
+  }
+}
+
+impl<T: Component> QueryFilter for Without<T> {
+  fn matches(world: &World, entity: Entity) -> bool {
+    !world.has::<T>(entity)
+  }
+}
+
+impl<T: Component> QueryFilter for Added<T> {
+  fn matches(world: &World, entity: Entity) -> bool {
+    world.is_added::<T>(entity)
+  }
+}
+
+impl<T: Component> QueryFilter for Changed<T> {
+  fn matches(world: &World, entity: Entity) -> bool {
+    world.is_changed::<T>(entity)
+  }
+}
+
+macro_rules! impl_query_filter_tuple {
+  ($($name:ident),*) => {
+    impl<$($name: QueryFilter),*> QueryFilter for ($($name,)*) {
+      fn matches(world: &World, entity: Entity) -> bool {
+        $($name::matches(world, entity))&&*
+      }
+    }
+  };
+}
+
+impl_query_filter_tuple!(A);
+impl_query_filter_tuple!(A, B);
+impl_query_filter_tuple!(A, B, C);
+impl_query_filter_tuple!(A, B, C, D);
+
+// A query can't fetch a component mutably alongside any other fetch of the same component, as in
+// `(&mut A, &A)` or `(&mut A, &mut A)`: building one panics, naming the component. Split it into
+// two queries instead.
+pub struct Query<'w, Q: WorldQuery, F: QueryFilter = ()> {
+  world: &'w World,
+  marker: PhantomData<(Q, F)>,
+}
+
+impl<'w, Q: WorldQuery, F: QueryFilter> Query<'w, Q, F> {
+  pub(crate) fn new(world: &'w World) -> Self {
+    if let Some(component) = conflicting_access::<Q>() {
+      panic!("query `{}` fetches `{}` mutably alongside another fetch of it", std::any::type_name::<Q>(), component);
+    }
+    Query { world, marker: PhantomData }
+  }
+
+  // Entities that pass the filter and have every component the query asks for.
+  pub fn entities(&self) -> Vec<Entity> {
+    let mut entities = Vec::new();
+    self.for_each(|entity, _| entities.push(entity));
+    entities
+  }
+
+  pub fn count(&self) -> usize {
+    let mut count = 0;
+    self.for_each(|_, _| count += 1);
+    count
+  }
+
+  pub fn for_each(&self, mut f: impl FnMut(Entity, Q::Item<'_>)) {
+    let candidates: Vec<Entity> = self.world.entities()
+      .filter(|&entity| F::matches(self.world, entity))
+      .collect();
+
+    let mut fetch = match Q::init(self.world) {
+      Some(fetch) => fetch,
+      None => return,
+    };
+    let tick = self.world.change_tick();
+    for entity in candidates {
+      if let Some(item) = Q::fetch(&mut fetch, entity.index, tick) {
+        f(entity, item);
+      }
+    }
+  }
+
+  // Runs `f` on a single entity's query item, if it matches.
+  pub fn get<R>(&self, entity: Entity, f: impl FnOnce(Q::Item<'_>) -> R) -> Option<R> {
+    if !self.world.is_alive(entity) || !F::matches(self.world, entity) {
+      return None;
+    }
+    let mut fetch = Q::init(self.world)?;
+    Q::fetch(&mut fetch, entity.index, self.world.change_tick()).map(f)
+  }
+}
+
+// The first component `Q` fetches mutably as well as some other way, which the storage's `RefCell`
+// would otherwise only catch as a borrow error partway through.
+fn conflicting_access<Q: WorldQuery>() -> Option<&'static str> {
+  let mut access = Vec::new();
+  Q::access(&mut access);
+  access.iter().enumerate().find_map(|(i, &(type_id, name, mutable))| {
+    access[i + 1..].iter()
+      .any(|&(other, _, other_mutable)| other == type_id && (mutable || other_mutable))
+      .then_some(name)
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  struct A(u32);
+  struct B;
+
+  #[test]
+  fn shared_and_disjoint_access_is_allowed() {
+    assert_eq!(conflicting_access::<(&A, &A)>(), None);
+    assert_eq!(conflicting_access::<(&mut A, &B)>(), None);
+    assert_eq!(conflicting_access::<(&mut A, Option<&mut B>)>(), None);
+  }
+
+  #[test]
+  fn mutable_access_alongside_another_conflicts() {
+    let name = std::any::type_name::<A>();
+    assert_eq!(conflicting_access::<(&mut A, &A)>(), Some(name));
+    assert_eq!(conflicting_access::<(&A, &mut A)>(), Some(name));
+    assert_eq!(conflicting_access::<(&mut A, &mut A)>(), Some(name));
+    assert_eq!(conflicting_access::<(&B, (Option<&mut A>, &A))>(), Some(name));
+  }
+
+  #[test]
+  #[should_panic(expected = "mutably alongside another fetch")]
+  fn conflicting_query_panics_when_built() {
+    let mut world = World::new();
+    let entity = world.spawn();
+    world.insert(entity, A(1));
+    world.query::<(&mut A, &A)>().for_each(|_, (mut a, b)| a.0 += b.0);
+  }
+}
@@ -0,0 +1,226 @@
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use ron::Value;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::components::{Transform, Velocity};
+use super::entity::Entity;
+use super::hierarchy::{Children, Parent};
+use super::name::{Name, Tag};
+use super::storage::Component;
+use super::world::World;
+
+// Components that can be reflected: shown in the inspector, written to scenes and patched by
+// prefab overrides. Blanket-implemented, so registering is all a type needs.
+pub trait Reflect: Component + Serialize + DeserializeOwned + Default + Debug {}
+
+impl<T: Component + Serialize + DeserializeOwned + Default + Debug> Reflect for T {}
+
+// Everything the registry knows about one component type. The function pointers are the type's
+// monomorphized hooks, so callers can work with components they only know by name.
+#[derive(Clone)]
+pub struct ComponentInfo {
+  pub name: &'static str,
+  pub type_name: &'static str,
+  pub type_id: TypeId,
+  default_value: fn() -> Result<Value, ron::Error>,
+  serialize: fn(&World, Entity) -> Option<Result<Value, ron::Error>>,
+  insert: fn(&mut World, Entity, Value) -> Result<(), ron::Error>,
+  deserialize: fn(Value) -> Result<DeserializedComponent, ron::Error>,
+  remove: fn(&mut World, Entity) -> bool,
+  has: fn(&World, Entity) -> bool,
+  debug: fn(&World, Entity) -> Option<String>,
+}
+
+impl ComponentInfo {
+  fn of<T: Reflect>(name: &'static str) -> Self {
+    ComponentInfo {
+      name,
+      type_name: std::any::type_name::<T>(),
+      type_id: TypeId::of::<T>(),
+      default_value: || to_value(&T::default()),
+      serialize: |world, entity| world.get::<T>(entity).map(|component| to_value(&*component)),
+      insert: |world, entity, value| {
+        let component: T = value.into_rust()?;
+        world.insert(entity, component);
+        Ok(())
+      },
+      deserialize: |value| {
+        let component: T = value.into_rust()?;
+        Ok(DeserializedComponent(Box::new(move |world, entity| { world.insert(entity, component); })))
+      },
+      remove: |world, entity| world.remove::<T>(entity).is_some(),
+      has: |world, entity| world.has::<T>(entity),
+      debug: |world, entity| world.get::<T>(entity).map(|component| format!("{:#?}", *component)),
+    }
+  }
+
+  pub fn default_value(&self) -> Result<Value, ron::Error> {
+    (self.default_value)()
+  }
+
+  // `None` when the entity doesn't have this component.
+  pub fn serialize(&self, world: &World, entity: Entity) -> Option<Result<Value, ron::Error>> {
+    (self.serialize)(world, entity)
+  }
+
+  pub fn insert(&self, world: &mut World, entity: Entity, value: Value) -> Result<(), ron::Error> {
+    (self.insert)(world, entity, value)
+  }
+
+  // Reads the component out of `value` without inserting it yet, so a whole batch can be checked
+  // before the world is touched.
+  pub fn deserialize(&self, value: Value) -> Result<DeserializedComponent, ron::Error> {
+    (self.deserialize)(value)
+  }
+
+  // Takes the component off the entity, returning whether it had one.
+  pub fn remove(&self, world: &mut World, entity: Entity) -> bool {
+    (self.remove)(world, entity)
+  }
+
+  pub fn has(&self, world: &World, entity: Entity) -> bool {
+    (self.has)(world, entity)
+  }
+
+  // Pretty-printed component for inspectors and the console.
+  pub fn debug(&self, world: &World, entity: Entity) -> Option<String> {
+    (self.debug)(world, entity)
+  }
+
+  // Applies a partial override on top of the entity's current component (or the default, if it
+  // doesn't have one yet). Only the struct fields present in `patch` are replaced.
+  pub fn patch(&self, world: &mut World, entity: Entity, patch: Value) -> Result<(), ron::Error> {
+    let base = match self.serialize(world, entity) {
+      Some(current) => current?,
+      None => self.default_value()?,
+    };
+    self.insert(world, entity, merge(base, patch))
+  }
+}
+
+// A component from `ComponentInfo::deserialize`, waiting to be put on an entity.
+pub struct DeserializedComponent(InsertComponent);
+
+type InsertComponent = Box<dyn FnOnce(&mut World, Entity)>;
+
+impl DeserializedComponent {
+  pub fn insert(self, world: &mut World, entity: Entity) {
+    (self.0)(world, entity)
+  }
+}
+
+#[derive(Clone, Default)]
+pub struct TypeRegistry {
+  infos: Vec<ComponentInfo>,
+  by_name: HashMap<&'static str, usize>,
+  by_type: HashMap<TypeId, usize>,
+  version: u64, // bumped by every `register`
+}
+
+impl TypeRegistry {
+  // A registry with the engine's own reflectable components already in it.
+  pub fn new() -> Self {
+    let mut registry = TypeRegistry::default();
+    registry.register::<Name>("Name");
+    registry.register::<Parent>("Parent");
+    registry.register::<Children>("Children");
+    registry.register::<Tag>("Tag");
+    registry.register::<Transform>("Transform");
+    registry.register::<Velocity>("Velocity");
+    registry
+  }
+
+  // `name` is the stable identifier used in scene and save files, so it shouldn't change when the
+  // type is moved between modules. Panics if another type is already registered under `name`,
+  // since files written with one type would then load as the other.
+  pub fn register<T: Reflect>(&mut self, name: &'static str) -> &mut Self {
+    let info = ComponentInfo::of::<T>(name);
+    if let Some(&index) = self.by_name.get(name) {
+      let existing = &self.infos[index];
+      assert!(
+        existing.type_id == info.type_id,
+        "component name {name:?} is already registered to {}, can't register {}",
+        existing.type_name,
+        info.type_name,
+      );
+    }
+    self.version += 1;
+    match self.by_type.get(&info.type_id) {
+      Some(&index) => {
+        self.by_name.remove(self.infos[index].name);
+        self.by_name.insert(name, index);
+        self.infos[index] = info;
+      }
+      None => {
+        self.by_name.insert(name, self.infos.len());
+        self.by_type.insert(info.type_id, self.infos.len());
+        self.infos.push(info);
+      }
+    }
+    self
+  }
+
+  // Goes up whenever a type is registered, so a copy of the registry can tell it's out of date.
+  pub fn version(&self) -> u64 {
+    self.version
+  }
+
+  pub fn get(&self, name: &str) -> Option<&ComponentInfo> {
+    self.by_name.get(name).map(|&index| &self.infos[index])
+  }
+
+  pub fn get_by_type<T: Component>(&self) -> Option<&ComponentInfo> {
+    self.by_type.get(&TypeId::of::<T>()).map(|&index| &self.infos[index])
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &ComponentInfo> {
+    self.infos.iter()
+  }
+
+  // Serializes every registered component on `entity`, keyed by registered name.
+  pub fn serialize_entity(&self, world: &World, entity: Entity) -> Result<Vec<(&'static str, Value)>, ron::Error> {
+    let mut components = Vec::new();
+    for info in &self.infos {
+      if let Some(value) = info.serialize(world, entity) {
+        components.push((info.name, value?));
+      }
+    }
+    Ok(components)
+  }
+}
+
+// Registers several components at once, using each type's name as its registered name:
+// `register_components!(registry, Transform, Velocity)`.
+#[macro_export]
+macro_rules! register_components {
+  ($registry:expr, $($component:ty),+ $(,)?) => {
+    $( $registry.register::<$component>(stringify!($component)); )+
+  };
+}
+
+pub(crate) fn to_value<T: Serialize>(value: &T) -> Result<Value, ron::Error> {
+  ron::from_str(&ron::to_string(value)?).map_err(|err| err.code)
+}
+
+// Overlays the keys of `patch` onto `base`, recursing into nested maps. An empty `()` patch changes
+// nothing, since that's how RON writes a struct with no fields given.
+pub(crate) fn merge(base: Value, patch: Value) -> Value {
+  match (base, patch) {
+    (base, Value::Unit) => base,
+    (Value::Map(mut base), Value::Map(patch)) => {
+      for (key, value) in patch {
+        let merged = match base.remove(&key) {
+          Some(existing) => merge(existing, value),
+          None => value,
+        };
+        base.insert(key, merged);
+      }
+      Value::Map(base)
+    }
+    (_, patch) => patch,
+  }
+}
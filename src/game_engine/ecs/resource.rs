@@ -0,0 +1,58 @@
+use std::any::Any;
+use std::cell::{Ref, RefMut};
+use std::ops::{Deref, DerefMut};
+
+pub(crate) struct ResourceSlot {
+  pub value: Box<dyn Any>,
+  pub added: u32,
+  pub changed: u32,
+}
+
+// Shared access to a world resource. Holding one keeps the resource borrowed, so drop it before
+// asking for a `ResMut` of the same type.
+pub struct Res<'w, T: 'static> {
+  pub(crate) value: Ref<'w, T>,
+  pub(crate) added: u32,
+  pub(crate) changed: u32,
+  pub(crate) last_change_tick: u32,
+}
+
+impl<T: 'static> Res<'_, T> {
+  pub fn is_added(&self) -> bool {
+    self.added > self.last_change_tick
+  }
+
+  pub fn is_changed(&self) -> bool {
+    self.changed > self.last_change_tick
+  }
+}
+
+impl<T: 'static> Deref for Res<'_, T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    &self.value
+  }
+}
+
+// Exclusive access to a world resource. Like `Mut`, only writes flag the resource as changed.
+pub struct ResMut<'w, T: 'static> {
+  pub(crate) value: RefMut<'w, T>,
+  pub(crate) changed: RefMut<'w, u32>,
+  pub(crate) tick: u32,
+}
+
+impl<T: 'static> Deref for ResMut<'_, T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    &self.value
+  }
+}
+
+impl<T: 'static> DerefMut for ResMut<'_, T> {
+  fn deref_mut(&mut self) -> &mut T {
+    *self.changed = self.tick;
+    &mut self.value
+  }
+}
@@ -0,0 +1,191 @@
+use std::collections::{BTreeMap, HashMap};
+
+use super::world::World;
+
+// Stages run in declaration order. Commands queued by a stage's systems are applied when the
+// stage finishes, so the next stage sees the spawned/despawned entities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Stage {
+  PreUpdate,
+  Update,
+  PostUpdate,
+  RenderPrep,
+}
+
+pub type RunCondition = Box<dyn FnMut(&World) -> bool>;
+
+pub struct System {
+  name: String,
+  run: Box<dyn FnMut(&mut World)>,
+  stage: Stage,
+  before: Vec<String>,
+  after: Vec<String>,
+  conditions: Vec<RunCondition>,
+  last_run: HashMap<u64, u32>, // the change tick after its last run, by `World::id`
+}
+
+impl System {
+  pub fn new(name: impl Into<String>, run: impl FnMut(&mut World) + 'static) -> Self {
+    System {
+      name: name.into(),
+      run: Box::new(run),
+      stage: Stage::Update,
+      before: Vec::new(),
+      after: Vec::new(),
+      conditions: Vec::new(),
+      last_run: HashMap::new(),
+    }
+  }
+
+  pub fn in_stage(mut self, stage: Stage) -> Self {
+    self.stage = stage;
+    self
+  }
+
+  // Ordering constraints only apply between systems in the same stage.
+  pub fn before(mut self, system: impl Into<String>) -> Self {
+    self.before.push(system.into());
+    self
+  }
+
+  pub fn after(mut self, system: impl Into<String>) -> Self {
+    self.after.push(system.into());
+    self
+  }
+
+  // The system is skipped for the frame unless every condition holds.
+  pub fn run_if(mut self, condition: impl FnMut(&World) -> bool + 'static) -> Self {
+    self.conditions.push(Box::new(condition));
+    self
+  }
+
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn run(&mut self, world: &mut World) {
+    if !self.conditions.iter_mut().all(|condition| condition(world)) {
+      return;
+    }
+
+    // Each system sees `Added`/`Changed` relative to its own previous run rather than the frame,
+    // so it neither misses changes made after it ran last frame nor sees its own writes again.
+    // Ticks are per world, so a schedule run on several worlds remembers each separately.
+    let frame_last_change_tick = world.last_change_tick();
+    if let Some(&last_run) = self.last_run.get(&world.id()) {
+      world.set_last_change_tick(last_run);
+    }
+    (self.run)(world);
+    self.last_run.insert(world.id(), world.change_tick());
+    world.set_last_change_tick(frame_last_change_tick);
+    world.increment_change_tick();
+  }
+}
+
+#[derive(Default)]
+pub struct Schedule {
+  stages: BTreeMap<Stage, Vec<System>>,
+  dirty: bool,
+}
+
+impl Schedule {
+  pub fn new() -> Self {
+    Schedule::default()
+  }
+
+  pub fn add_system(&mut self, system: System) -> &mut Self {
+    self.stages.entry(system.stage).or_default().push(system);
+    self.dirty = true;
+    self
+  }
+
+  pub fn remove_system(&mut self, name: &str) -> bool {
+    let mut removed = false;
+    for systems in self.stages.values_mut() {
+      let len = systems.len();
+      systems.retain(|system| system.name != name);
+      removed |= systems.len() != len;
+    }
+    removed
+  }
+
+  pub fn run(&mut self, world: &mut World) {
+    if self.dirty {
+      for systems in self.stages.values_mut() {
+        sort_systems(systems);
+      }
+      self.dirty = false;
+    }
+
+    for systems in self.stages.values_mut() {
+      for system in systems.iter_mut() {
+        system.run(world);
+      }
+      world.apply_commands();
+    }
+  }
+
+  // System names in the order they will run.
+  pub fn order(&mut self) -> Vec<(Stage, &str)> {
+    if self.dirty {
+      for systems in self.stages.values_mut() {
+        sort_systems(systems);
+      }
+      self.dirty = false;
+    }
+    self.stages.iter()
+      .flat_map(|(stage, systems)| systems.iter().map(move |system| (*stage, system.name())))
+      .collect()
+  }
+}
+
+// Topologically sorts a stage by its before/after constraints, keeping insertion order wherever
+// the constraints leave it open. Panics on cycles, since there's no sensible order to pick.
+fn sort_systems(systems: &mut Vec<System>) {
+  let index_of: HashMap<&str, usize> = systems.iter().enumerate()
+    .map(|(i, system)| (system.name.as_str(), i))
+    .collect();
+
+  let mut edges = vec![Vec::new(); systems.len()];
+  let mut incoming = vec![0; systems.len()];
+  for (i, system) in systems.iter().enumerate() {
+    let befores = system.before.iter().map(|name| (name, true));
+    let afters = system.after.iter().map(|name| (name, false));
+    for (name, is_before) in befores.chain(afters) {
+      let other = match index_of.get(name.as_str()) {
+        Some(&other) => other,
+        None => {
+          log::warn!("system `{}` is ordered against `{}`, which isn't in its stage", system.name, name);
+          continue;
+        }
+      };
+      let (from, to) = if is_before { (i, other) } else { (other, i) };
+      edges[from].push(to);
+      incoming[to] += 1;
+    }
+  }
+
+  let mut order = Vec::with_capacity(systems.len());
+  let mut ready: Vec<usize> = (0..systems.len()).filter(|&i| incoming[i] == 0).collect();
+  while let Some(next) = ready.iter().copied().min() {
+    ready.retain(|&i| i != next);
+    order.push(next);
+    for &to in &edges[next] {
+      incoming[to] -= 1;
+      if incoming[to] == 0 {
+        ready.push(to);
+      }
+    }
+  }
+
+  if order.len() != systems.len() {
+    let cyclic: Vec<&str> = (0..systems.len())
+      .filter(|i| !order.contains(i))
+      .map(|i| systems[i].name.as_str())
+      .collect();
+    panic!("system ordering cycle between {:?}", cyclic);
+  }
+
+  let mut slots: Vec<Option<System>> = systems.drain(..).map(Some).collect();
+  systems.extend(order.into_iter().filter_map(|i| slots[i].take()));
+}
@@ -0,0 +1,30 @@
+use super::world::World;
+
+// A game state machine stored as a world resource, e.g. `State<GameState>` with `Menu`/`Playing`.
+pub struct State<S> {
+  current: S,
+}
+
+impl<S: PartialEq + 'static> State<S> {
+  pub fn new(initial: S) -> Self {
+    State { current: initial }
+  }
+
+  pub fn get(&self) -> &S {
+    &self.current
+  }
+
+  pub fn set(&mut self, state: S) {
+    self.current = state;
+  }
+}
+
+// Run condition: only run while the world's `State<S>` equals `state`.
+pub fn in_state<S: PartialEq + 'static>(state: S) -> impl FnMut(&World) -> bool {
+  move |world| world.get_resource::<State<S>>().is_some_and(|current| *current.get() == state)
+}
+
+// Run condition: only run once the resource has been inserted.
+pub fn resource_exists<T: 'static>() -> impl FnMut(&World) -> bool {
+  |world| world.contains_resource::<T>()
+}
@@ -0,0 +1,118 @@
+use std::any::Any;
+
+// Anything 'static can be a component - there's nothing to derive.
+pub trait Component: Any {}
+
+impl<T: Any> Component for T {}
+
+// Type-erased view of a `Storage<T>` so the world can keep every component type in one map. Public
+// only so it can appear in `WorldQuery::Fetch`; it is not re-exported.
+pub trait AnyStorage: Any {
+  fn contains(&self, index: u32) -> bool;
+  fn remove_index(&mut self, index: u32);
+  fn added_tick(&self, index: u32) -> Option<u32>;
+  fn changed_tick(&self, index: u32) -> Option<u32>;
+  // Used to move components between worlds without knowing their types.
+  fn take_boxed(&mut self, index: u32) -> Option<Box<dyn Any>>;
+  fn insert_boxed(&mut self, index: u32, component: Box<dyn Any>, tick: u32);
+  fn new_empty(&self) -> Box<dyn AnyStorage>;
+  fn as_any(&self) -> &dyn Any;
+  fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+pub(crate) struct Slot<T> {
+  pub value: T,
+  pub added: u32, // world tick the component was inserted on
+  pub changed: u32, // world tick the component was last mutably accessed on
+}
+
+// Sparse storage indexed directly by `Entity::index`.
+pub(crate) struct Storage<T> {
+  slots: Vec<Option<Slot<T>>>,
+}
+
+impl<T: Component> Storage<T> {
+  pub fn new() -> Self {
+    Storage { slots: Vec::new() }
+  }
+
+  pub fn insert(&mut self, index: u32, component: T, tick: u32) -> Option<T> {
+    let index = index as usize;
+    if index >= self.slots.len() {
+      self.slots.resize_with(index + 1, || None);
+    }
+    match &mut self.slots[index] {
+      Some(slot) => {
+        slot.changed = tick;
+        Some(std::mem::replace(&mut slot.value, component))
+      }
+      empty => {
+        *empty = Some(Slot { value: component, added: tick, changed: tick });
+        None
+      }
+    }
+  }
+
+  pub fn remove(&mut self, index: u32) -> Option<T> {
+    self.slots.get_mut(index as usize).and_then(|slot| slot.take()).map(|slot| slot.value)
+  }
+
+  pub fn get(&self, index: u32) -> Option<&T> {
+    self.slot(index).map(|slot| &slot.value)
+  }
+
+  pub fn slot(&self, index: u32) -> Option<&Slot<T>> {
+    self.slots.get(index as usize).and_then(|slot| slot.as_ref())
+  }
+
+  pub fn slot_mut(&mut self, index: u32) -> Option<&mut Slot<T>> {
+    self.slots.get_mut(index as usize).and_then(|slot| slot.as_mut())
+  }
+}
+
+impl<T: Component> AnyStorage for Storage<T> {
+  fn contains(&self, index: u32) -> bool {
+    self.slot(index).is_some()
+  }
+
+  fn remove_index(&mut self, index: u32) {
+    self.remove(index);
+  }
+
+  fn added_tick(&self, index: u32) -> Option<u32> {
+    self.slot(index).map(|slot| slot.added)
+  }
+
+  fn changed_tick(&self, index: u32) -> Option<u32> {
+    self.slot(index).map(|slot| slot.changed)
+  }
+
+  fn take_boxed(&mut self, index: u32) -> Option<Box<dyn Any>> {
+    self.remove(index).map(|component| Box::new(component) as Box<dyn Any>)
+  }
+
+  fn insert_boxed(&mut self, index: u32, component: Box<dyn Any>, tick: u32) {
+    let component = component.downcast::<T>().expect("component storage type mismatch");
+    self.insert(index, *component, tick);
+  }
+
+  fn new_empty(&self) -> Box<dyn AnyStorage> {
+    Box::new(Storage::<T>::new())
+  }
+
+  fn as_any(&self) -> &dyn Any {
+    self
+  }
+
+  fn as_any_mut(&mut self) -> &mut dyn Any {
+    self
+  }
+}
+
+pub(crate) fn downcast_ref<T: Component>(storage: &dyn AnyStorage) -> &Storage<T> {
+  storage.as_any().downcast_ref::<Storage<T>>().expect("component storage type mismatch")
+}
+
+pub(crate) fn downcast_mut<T: Component>(storage: &mut dyn AnyStorage) -> &mut Storage<T> {
+  storage.as_any_mut().downcast_mut::<Storage<T>>().expect("component storage type mismatch")
+}
@@ -0,0 +1,470 @@
+use std::any::{Any, TypeId};
+use std::cell::{Cell, Ref, RefCell, RefMut};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::commands::{Command, Commands};
+use super::entity::{Entity, EntityMeta};
+use super::name::{Name, Tag};
+use super::query::{Query, QueryFilter, WorldQuery};
+use super::resource::{Res, ResMut, ResourceSlot};
+use super::storage::{downcast_mut, downcast_ref, AnyStorage, Component, Storage};
+
+// Hands out `World::id`s.
+static NEXT_WORLD_ID: AtomicU64 = AtomicU64::new(0);
+
+pub struct World {
+  id: u64,
+  entities: Vec<EntityMeta>,
+  free: Vec<u32>,
+  // Entities handed out by `reserve_entity` that haven't been brought to life yet: the last
+  // `reserved_free` entries of `free`, then `reserved_new` indices past the end of `entities`.
+  reserved_free: Cell<usize>,
+  reserved_new: Cell<u32>,
+  command_queue: RefCell<Vec<Command>>,
+  storages: HashMap<TypeId, RefCell<Box<dyn AnyStorage>>>,
+  resources: HashMap<TypeId, RefCell<ResourceSlot>>,
+
+  // Components inserted or written on a tick newer than `last_change_tick` count as added/changed.
+  change_tick: u32,
+  last_change_tick: u32,
+
+  // Entities by `Name` (most recently named last) and by `Tag`. Handing either component out
+  // mutably marks its index stale, since it could change in place; the next lookup rebuilds it.
+  names: RefCell<HashMap<String, Vec<Entity>>>,
+  tags: RefCell<HashMap<String, Vec<Entity>>>,
+  names_stale: Cell<bool>,
+  tags_stale: Cell<bool>,
+}
+
+impl World {
+  pub fn new() -> Self {
+    World {
+      id: NEXT_WORLD_ID.fetch_add(1, Ordering::Relaxed),
+      entities: Vec::new(),
+      free: Vec::new(),
+      reserved_free: Cell::new(0),
+      reserved_new: Cell::new(0),
+      command_queue: RefCell::new(Vec::new()),
+      storages: HashMap::new(),
+      resources: HashMap::new(),
+      change_tick: 1,
+      last_change_tick: 0,
+      names: RefCell::new(HashMap::new()),
+      tags: RefCell::new(HashMap::new()),
+      names_stale: Cell::new(false),
+      tags_stale: Cell::new(false),
+    }
+  }
+
+  pub fn spawn(&mut self) -> Entity {
+    self.flush_reserved();
+    match self.free.pop() {
+      Some(index) => {
+        let meta = &mut self.entities[index as usize];
+        meta.alive = true;
+        Entity { index, generation: meta.generation }
+      }
+      None => {
+        self.entities.push(EntityMeta { generation: 0, alive: true });
+        Entity { index: (self.entities.len() - 1) as u32, generation: 0 }
+      }
+    }
+  }
+
+  pub fn despawn(&mut self, entity: Entity) -> bool {
+    self.flush_reserved();
+    if !self.is_alive(entity) {
+      return false;
+    }
+
+    self.unindex_name(entity);
+    self.unindex_tag(entity);
+    for storage in self.storages.values_mut() {
+      storage.get_mut().remove_index(entity.index);
+    }
+
+    let meta = &mut self.entities[entity.index as usize];
+    meta.alive = false;
+    meta.generation = meta.generation.wrapping_add(1);
+    self.free.push(entity.index);
+    true
+  }
+
+  // Allocates an entity id without needing `&mut World`. The entity becomes alive on the next
+  // spawn, despawn, or `apply_commands`.
+  pub fn reserve_entity(&self) -> Entity {
+    let reserved_free = self.reserved_free.get();
+    if reserved_free < self.free.len() {
+      self.reserved_free.set(reserved_free + 1);
+      let index = self.free[self.free.len() - 1 - reserved_free];
+      Entity { index, generation: self.entities[index as usize].generation }
+    } else {
+      let reserved_new = self.reserved_new.get();
+      self.reserved_new.set(reserved_new + 1);
+      Entity { index: self.entities.len() as u32 + reserved_new, generation: 0 }
+    }
+  }
+
+  pub fn commands(&self) -> Commands<'_> {
+    Commands::new(self)
+  }
+
+  // Sync point: runs every queued command in order, including ones queued by other commands.
+  pub fn apply_commands(&mut self) {
+    self.flush_reserved();
+    loop {
+      let queue = std::mem::take(self.command_queue.get_mut());
+      if queue.is_empty() {
+        break;
+      }
+      for command in queue {
+        command(self);
+      }
+      self.flush_reserved();
+    }
+  }
+
+  pub(crate) fn push_command(&self, command: Command) {
+    self.command_queue.borrow_mut().push(command);
+  }
+
+  pub fn is_alive(&self, entity: Entity) -> bool {
+    match self.entities.get(entity.index as usize) {
+      Some(meta) => meta.alive && meta.generation == entity.generation,
+      None => false,
+    }
+  }
+
+  pub fn entities(&self) -> impl Iterator<Item = Entity> + '_ {
+    self.entities.iter().enumerate()
+      .filter(|(_, meta)| meta.alive)
+      .map(|(index, meta)| Entity { index: index as u32, generation: meta.generation })
+  }
+
+  pub fn len(&self) -> usize {
+    self.entities.len() - self.free.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  // Adds `component` to `entity`, handing back whatever component of the same type it replaced.
+  // Panics if the entity has been despawned.
+  pub fn insert<T: Component>(&mut self, entity: Entity, component: T) -> Option<T> {
+    assert!(self.is_alive(entity), "insert on a despawned entity {:?}", entity);
+
+    let component_any = &component as &dyn Any;
+    if let Some(name) = component_any.downcast_ref::<Name>() {
+      self.unindex_name(entity);
+      self.names.get_mut().entry(name.as_str().to_owned()).or_default().push(entity);
+    }
+    if let Some(tag) = component_any.downcast_ref::<Tag>() {
+      self.unindex_tag(entity);
+      self.tags.get_mut().entry(tag.as_str().to_owned()).or_default().push(entity);
+    }
+
+    let tick = self.change_tick;
+    self.storage_mut::<T>().insert(entity.index, component, tick)
+  }
+
+  pub fn remove<T: Component>(&mut self, entity: Entity) -> Option<T> {
+    if !self.is_alive(entity) {
+      return None;
+    }
+
+    if TypeId::of::<T>() == TypeId::of::<Name>() {
+      self.unindex_name(entity);
+    }
+    if TypeId::of::<T>() == TypeId::of::<Tag>() {
+      self.unindex_tag(entity);
+    }
+
+    match self.storages.get_mut(&TypeId::of::<T>()) {
+      Some(storage) => downcast_mut::<T>(&mut **storage.get_mut()).remove(entity.index),
+      None => None,
+    }
+  }
+
+  // Moves `entity` and all of its components into `other`, returning its id there. The id changes,
+  // so anything holding the old one has to be updated by the caller.
+  pub fn move_entity_to(&mut self, entity: Entity, other: &mut World) -> Option<Entity> {
+    self.flush_reserved();
+    if !self.is_alive(entity) {
+      return None;
+    }
+
+    self.unindex_name(entity);
+    self.unindex_tag(entity);
+    let components: Vec<_> = self.storages.iter_mut()
+      .filter_map(|(&type_id, storage)| {
+        let storage = storage.get_mut();
+        let component = storage.take_boxed(entity.index)?;
+        Some((type_id, component, storage.new_empty()))
+      })
+      .collect();
+    self.despawn(entity);
+
+    let moved = other.spawn();
+    let tick = other.change_tick;
+    for (type_id, component, empty) in components {
+      if let Some(name) = component.downcast_ref::<Name>() {
+        other.names.get_mut().entry(name.as_str().to_owned()).or_default().push(moved);
+      }
+      if let Some(tag) = component.downcast_ref::<Tag>() {
+        other.tags.get_mut().entry(tag.as_str().to_owned()).or_default().push(moved);
+      }
+      other.storages.entry(type_id)
+        .or_insert_with(|| RefCell::new(empty))
+        .get_mut()
+        .insert_boxed(moved.index, component, tick);
+    }
+    Some(moved)
+  }
+
+  pub fn has<T: Component>(&self, entity: Entity) -> bool {
+    self.is_alive(entity) && match self.storages.get(&TypeId::of::<T>()) {
+      Some(storage) => storage.borrow().contains(entity.index),
+      None => false,
+    }
+  }
+
+  pub fn get<T: Component>(&self, entity: Entity) -> Option<Ref<'_, T>> {
+    if !self.is_alive(entity) {
+      return None;
+    }
+    let storage = self.storages.get(&TypeId::of::<T>())?.borrow();
+    Ref::filter_map(storage, |storage| downcast_ref::<T>(&**storage).get(entity.index)).ok()
+  }
+
+  // Mutable access always flags the component as changed; use a `&mut T` query for finer tracking.
+  pub fn get_mut<T: Component>(&mut self, entity: Entity) -> Option<&mut T> {
+    if !self.is_alive(entity) {
+      return None;
+    }
+    self.mark_mutable::<T>();
+    let tick = self.change_tick;
+    let storage = self.storages.get_mut(&TypeId::of::<T>())?.get_mut();
+    let slot = downcast_mut::<T>(&mut **storage).slot_mut(entity.index)?;
+    slot.changed = tick;
+    Some(&mut slot.value)
+  }
+
+  pub fn query<Q: WorldQuery>(&self) -> Query<'_, Q> {
+    Query::new(self)
+  }
+
+  pub fn query_filtered<Q: WorldQuery, F: QueryFilter>(&self) -> Query<'_, Q, F> {
+    Query::new(self)
+  }
+
+  // Resources are typed singletons owned by the world (score, settings, the asset server...), so
+  // anything with access to the world can reach them without globals.
+  pub fn insert_resource<T: 'static>(&mut self, resource: T) -> Option<T> {
+    let tick = self.change_tick;
+    match self.resources.get_mut(&TypeId::of::<T>()) {
+      Some(slot) => {
+        let slot = slot.get_mut();
+        slot.changed = tick;
+        let old = std::mem::replace(&mut slot.value, Box::new(resource));
+        old.downcast::<T>().ok().map(|old| *old)
+      }
+      None => {
+        let slot = ResourceSlot { value: Box::new(resource), added: tick, changed: tick };
+        self.resources.insert(TypeId::of::<T>(), RefCell::new(slot));
+        None
+      }
+    }
+  }
+
+  pub fn remove_resource<T: 'static>(&mut self) -> Option<T> {
+    let slot = self.resources.remove(&TypeId::of::<T>())?.into_inner();
+    slot.value.downcast::<T>().ok().map(|value| *value)
+  }
+
+  pub fn contains_resource<T: 'static>(&self) -> bool {
+    self.resources.contains_key(&TypeId::of::<T>())
+  }
+
+  pub fn get_resource<T: 'static>(&self) -> Option<Res<'_, T>> {
+    let slot = self.resources.get(&TypeId::of::<T>())?.borrow();
+    let (added, changed) = (slot.added, slot.changed);
+    let value = Ref::map(slot, |slot| slot.value.downcast_ref::<T>().expect("resource type mismatch"));
+    Some(Res { value, added, changed, last_change_tick: self.last_change_tick })
+  }
+
+  pub fn get_resource_mut<T: 'static>(&self) -> Option<ResMut<'_, T>> {
+    let slot = self.resources.get(&TypeId::of::<T>())?.borrow_mut();
+    let (value, changed) = RefMut::map_split(slot, |slot| {
+      (slot.value.downcast_mut::<T>().expect("resource type mismatch"), &mut slot.changed)
+    });
+    Some(ResMut { value, changed, tick: self.change_tick })
+  }
+
+  // Panics if the resource was never inserted; use `get_resource` when it's optional.
+  pub fn resource<T: 'static>(&self) -> Res<'_, T> {
+    self.get_resource::<T>()
+      .unwrap_or_else(|| panic!("resource {} does not exist", std::any::type_name::<T>()))
+  }
+
+  pub fn resource_mut<T: 'static>(&self) -> ResMut<'_, T> {
+    self.get_resource_mut::<T>()
+      .unwrap_or_else(|| panic!("resource {} does not exist", std::any::type_name::<T>()))
+  }
+
+  pub fn is_added<T: Component>(&self, entity: Entity) -> bool {
+    self.component_tick::<T>(entity, |storage, index| storage.added_tick(index))
+      .is_some_and(|tick| tick > self.last_change_tick)
+  }
+
+  pub fn is_changed<T: Component>(&self, entity: Entity) -> bool {
+    self.component_tick::<T>(entity, |storage, index| storage.changed_tick(index))
+      .is_some_and(|tick| tick > self.last_change_tick)
+  }
+
+  // When the component was last changed (or added), for code that outlives a frame's change window
+  // and compares ticks itself, like a renderer caching what it built from the component.
+  pub fn changed_tick<T: Component>(&self, entity: Entity) -> Option<u32> {
+    self.component_tick::<T>(entity, |storage, index| storage.changed_tick(index))
+  }
+
+  // Unique among the worlds this process makes, so state kept per world (like a system's last run)
+  // can tell them apart.
+  pub fn id(&self) -> u64 {
+    self.id
+  }
+
+  pub fn change_tick(&self) -> u32 {
+    self.change_tick
+  }
+
+  pub fn last_change_tick(&self) -> u32 {
+    self.last_change_tick
+  }
+
+  pub(crate) fn set_last_change_tick(&mut self, tick: u32) {
+    self.last_change_tick = tick;
+  }
+
+  pub(crate) fn increment_change_tick(&mut self) {
+    self.change_tick += 1;
+  }
+
+  // Ends the current change-detection window: everything added or changed so far stops matching
+  // `Added`/`Changed` filters. The engine calls this once per frame after the user task has run.
+  pub fn clear_trackers(&mut self) {
+    self.last_change_tick = self.change_tick;
+    self.change_tick += 1;
+  }
+
+  // Looks up an entity by its `Name` component. If several entities share a name, the one that was
+  // named most recently wins.
+  pub fn find_by_name(&self, name: &str) -> Option<Entity> {
+    self.refresh_indices();
+    self.names.borrow().get(name)?.last().copied()
+  }
+
+  // Every live entity carrying the given `Tag`.
+  pub fn with_tag(&self, tag: &str) -> Vec<Entity> {
+    self.refresh_indices();
+    self.tags.borrow().get(tag).cloned().unwrap_or_default()
+  }
+
+  // Called whenever components of type `T` are handed out mutably.
+  pub(crate) fn mark_mutable<T: Component>(&self) {
+    if TypeId::of::<T>() == TypeId::of::<Name>() {
+      self.names_stale.set(true);
+    }
+    if TypeId::of::<T>() == TypeId::of::<Tag>() {
+      self.tags_stale.set(true);
+    }
+  }
+
+  // Rebuilds whichever of the `Name` and `Tag` indices could have gone stale.
+  fn refresh_indices(&self) {
+    if self.names_stale.replace(false) {
+      let mut named = Vec::new();
+      self.query::<&Name>().for_each(|entity, name| named.push((entity, name.as_str().to_owned())));
+      // Oldest first, so renamed entities end up last as if they'd been inserted.
+      named.sort_by_key(|(entity, _)| self.changed_tick::<Name>(*entity));
+      let mut names: HashMap<String, Vec<Entity>> = HashMap::new();
+      for (entity, name) in named {
+        names.entry(name).or_default().push(entity);
+      }
+      *self.names.borrow_mut() = names;
+    }
+    if self.tags_stale.replace(false) {
+      let mut tags: HashMap<String, Vec<Entity>> = HashMap::new();
+      self.query::<&Tag>().for_each(|entity, tag| tags.entry(tag.as_str().to_owned()).or_default().push(entity));
+      *self.tags.borrow_mut() = tags;
+    }
+  }
+
+  pub(crate) fn storage_cell(&self, type_id: TypeId) -> Option<&RefCell<Box<dyn AnyStorage>>> {
+    self.storages.get(&type_id)
+  }
+
+  fn flush_reserved(&mut self) {
+    for _ in 0..self.reserved_free.replace(0) {
+      if let Some(index) = self.free.pop() {
+        self.entities[index as usize].alive = true;
+      }
+    }
+    for _ in 0..self.reserved_new.replace(0) {
+      self.entities.push(EntityMeta { generation: 0, alive: true });
+    }
+  }
+
+  fn component_tick<T: Component>(
+    &self,
+    entity: Entity,
+    tick: impl FnOnce(&dyn AnyStorage, u32) -> Option<u32>
+  ) -> Option<u32> {
+    if !self.is_alive(entity) {
+      return None;
+    }
+    let storage = self.storages.get(&TypeId::of::<T>())?.borrow();
+    tick(&**storage, entity.index)
+  }
+
+  fn storage_mut<T: Component>(&mut self) -> &mut Storage<T> {
+    let storage = self.storages
+      .entry(TypeId::of::<T>())
+      .or_insert_with(|| RefCell::new(Box::new(Storage::<T>::new())))
+      .get_mut();
+    downcast_mut::<T>(&mut **storage)
+  }
+
+  // A stale index is rebuilt from the components on the next lookup, so it needn't be kept up here.
+  fn unindex_name(&mut self, entity: Entity) {
+    let old = match self.get::<Name>(entity) {
+      Some(name) => name.as_str().to_owned(),
+      None => return,
+    };
+    unindex(self.names.get_mut(), &old, entity);
+  }
+
+  fn unindex_tag(&mut self, entity: Entity) {
+    let old = match self.get::<Tag>(entity) {
+      Some(tag) => tag.as_str().to_owned(),
+      None => return,
+    };
+    unindex(self.tags.get_mut(), &old, entity);
+  }
+}
+
+fn unindex(index: &mut HashMap<String, Vec<Entity>>, key: &str, entity: Entity) {
+  if let Some(entities) = index.get_mut(key) {
+    entities.retain(|&e| e != entity);
+    if entities.is_empty() {
+      index.remove(key);
+    }
+  }
+}
+
+impl Default for World {
+  fn default() -> Self {
+    World::new()
+  }
+}
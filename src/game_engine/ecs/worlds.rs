@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+
+use super::entity::Entity;
+use super::world::World;
+
+pub const MAIN_WORLD: &str = "main";
+
+// Every world the engine owns, keyed by name. Only the active world is handed to the schedule each
+// frame; the others sit untouched until switched to (e.g. a paused gameplay world behind a menu).
+//
+// Shared resources, like the renderer's texture manager and UI, follow the active world: switching
+// moves them over, replacing the new world's own, so there's only ever one of each.
+pub struct Worlds {
+  worlds: HashMap<String, World>,
+  active: String,
+  shared: Vec<fn(&mut World, &mut World)>, // each moves one shared resource between worlds
+}
+
+impl Worlds {
+  pub fn new() -> Self {
+    let mut worlds = HashMap::new();
+    worlds.insert(MAIN_WORLD.to_owned(), World::new());
+    Worlds { worlds, active: MAIN_WORLD.to_owned(), shared: Vec::new() }
+  }
+
+  // Makes the active world's `T` resource follow it from world to world.
+  pub fn share_resource<T: 'static>(&mut self) {
+    self.shared.push(|from, to| {
+      if let Some(resource) = from.remove_resource::<T>() {
+        to.insert_resource(resource);
+      }
+    });
+  }
+
+  // Creates an empty world, replacing any existing world with the same name. Replacing the active
+  // world (e.g. to reset it) carries the shared resources over into the new one.
+  pub fn create(&mut self, name: impl Into<String>) -> &mut World {
+    let name = name.into();
+    let mut world = World::new();
+    if name == self.active {
+      let old = self.worlds.get_mut(&name).unwrap();
+      for share in &self.shared {
+        share(old, &mut world);
+      }
+    }
+    self.worlds.insert(name.clone(), world);
+    self.worlds.get_mut(&name).unwrap()
+  }
+
+  // The active world can't be removed; switch away from it first.
+  pub fn remove(&mut self, name: &str) -> Option<World> {
+    if name == self.active {
+      return None;
+    }
+    self.worlds.remove(name)
+  }
+
+  pub fn get(&self, name: &str) -> Option<&World> {
+    self.worlds.get(name)
+  }
+
+  pub fn get_mut(&mut self, name: &str) -> Option<&mut World> {
+    self.worlds.get_mut(name)
+  }
+
+  pub fn names(&self) -> impl Iterator<Item = &str> {
+    self.worlds.keys().map(String::as_str)
+  }
+
+  pub fn active(&self) -> &World {
+    &self.worlds[&self.active]
+  }
+
+  pub fn active_mut(&mut self) -> &mut World {
+    self.worlds.get_mut(&self.active).unwrap()
+  }
+
+  pub fn active_name(&self) -> &str {
+    &self.active
+  }
+
+  // Switches to `name`, taking the shared resources along.
+  pub fn set_active(&mut self, name: &str) -> bool {
+    if name == self.active {
+      return true;
+    }
+    let Some(mut target) = self.worlds.remove(name) else { return false };
+    let current = self.worlds.get_mut(&self.active).unwrap();
+    for share in &self.shared {
+      share(current, &mut target);
+    }
+    self.worlds.insert(name.to_owned(), target);
+    self.active = name.to_owned();
+    true
+  }
+
+  // Moves an entity with all its components between two worlds, returning its id in `to`.
+  pub fn move_entity(&mut self, entity: Entity, from: &str, to: &str) -> Option<Entity> {
+    if from == to {
+      return None;
+    }
+    let mut target = self.worlds.remove(to)?;
+    let moved = match self.worlds.get_mut(from) {
+      Some(source) => source.move_entity_to(entity, &mut target),
+      None => None,
+    };
+    self.worlds.insert(to.to_owned(), target);
+    moved
+  }
+}
+
+impl Default for Worlds {
+  fn default() -> Self {
+    Worlds::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  struct Shared(u32);
+  struct Own;
+
+  #[test]
+  fn shared_resources_follow_the_active_world() {
+    let mut worlds = Worlds::new();
+    worlds.share_resource::<Shared>();
+    worlds.active_mut().insert_resource(Shared(7));
+    worlds.active_mut().insert_resource(Own);
+    worlds.create("menu").insert_resource(Shared(1));
+
+    assert!(worlds.set_active("menu"));
+    assert_eq!(worlds.active().get_resource::<Shared>().map(|shared| shared.0), Some(7));
+    assert!(!worlds.active().contains_resource::<Own>());
+    assert!(!worlds.get(MAIN_WORLD).unwrap().contains_resource::<Shared>());
+    assert!(worlds.get(MAIN_WORLD).unwrap().contains_resource::<Own>());
+
+    assert!(worlds.set_active(MAIN_WORLD));
+    assert_eq!(worlds.active().get_resource::<Shared>().map(|shared| shared.0), Some(7));
+    assert!(!worlds.set_active("missing"));
+    assert_eq!(worlds.active_name(), MAIN_WORLD);
+  }
+
+  #[test]
+  fn recreating_the_active_world_keeps_shared_resources() {
+    let mut worlds = Worlds::new();
+    worlds.share_resource::<Shared>();
+    worlds.active_mut().insert_resource(Shared(7));
+    worlds.active_mut().insert_resource(Own);
+
+    worlds.create(MAIN_WORLD);
+    assert_eq!(worlds.active_name(), MAIN_WORLD);
+    assert_eq!(worlds.active().get_resource::<Shared>().map(|shared| shared.0), Some(7));
+    assert!(!worlds.active().contains_resource::<Own>());
+  }
+}
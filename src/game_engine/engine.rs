@@ -1,63 +1,428 @@
-#[cfg(target_arch="wasm32")]
-use wasm_bindgen::prelude::*;
-
-use std::borrow::Borrow;
-use std::task::Poll;
-use std::thread;
-use std::time::{Duration, SystemTime};
-use wgpu::SurfaceError;
-use winit::dpi::PhysicalSize;
-use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use glam::{UVec2, Vec2};
+use winit::event::{DeviceEvent, ElementState, Event, Ime, KeyboardInput, VirtualKeyCode, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
-use winit::window::WindowBuilder;
-
+use winit::window::Window;
+
+use super::accessibility::AccessibilitySettings;
+use super::animation::update_animation_players;
+use super::assets::Assets;
+use super::audio::{Audio, AudioOutput, NullOutput};
+#[cfg(not(target_arch = "wasm32"))]
+use super::audio::open_audio_output;
+use super::backend::{Backend, FrameError, VsyncMode};
+use super::camera::Camera;
+use super::camera_2d::update_camera_2d;
+use super::compute::Compute;
+use super::console::{update_console, Console};
+use super::culling::{cull_entities, CullingSettings};
+use super::cvars::{run_cvar_handlers, CVars};
+use super::debug_draw::DebugDraw;
+use super::error::EngineError;
+use super::ecs::{apply_velocities, Entity, Schedule, TypeRegistry, World, Worlds};
+use super::gamepad::{GamepadEvent, Gamepads};
+#[cfg(not(target_arch = "wasm32"))]
+use super::gamepad_backend::GamepadBackend;
+use super::graphics_state::GraphicsState;
+use super::input::{Composition, Input};
+use super::instancing::{draw_instanced_entities, InstanceBatch};
+use super::jobs::Jobs;
+#[cfg(not(target_arch = "wasm32"))]
+use super::net::{update_network, Replicated};
+use super::lighting::{DirectionalLight, PointLight, SpotLight};
+use super::lines::update_trails;
+use super::lockstep::{state_hash, Lockstep};
+use super::logging::{self, LogConfig};
+use super::particles::ParticleEmitter;
+use super::physics::{draw_physics_debug, simulate_cloth, step_physics, update_character_controllers, Collision, PhysicsDebugSettings, PhysicsWorld};
+use super::post_process::PostProcessStack;
+#[cfg(not(target_arch = "wasm32"))]
+use super::plugin::Plugins;
+use super::prefab::{PrefabInstance, Prefabs};
+use super::profiler;
+use super::random::Rng;
+use super::scene::Scene;
+#[cfg(not(target_arch = "wasm32"))]
+use super::scripting::{ScriptEvent, Scripts};
+#[cfg(not(target_arch = "wasm32"))]
+use super::recorder::update_recorder;
+use super::render_target::RenderTargets;
+use super::replay::{InputEvent, Replay, ReplayMode};
+use super::render_thread::{Renderer, ThreadableBackend};
+use super::screenshot::Screenshots;
+use super::skybox::Environment;
+use super::sprite_animation::{update_animated_sprites, AnimationFinished};
+use super::sprite_batch::{draw_sprite_entities, SpriteBatch};
+use super::text::TextRenderer;
+use super::texture::TextureManager;
 use super::task::GameEvent;
-use super::taskqueue::taskqueue::GameEventQueue;
-// use crate::game_engine::taskqueue::;
+use super::taskqueue::taskqueue::EventQueue;
+use super::terrain::update_voxel_terrain;
+use super::stats::FrameStats;
+use super::time::{record_previous_transforms, Instant, Time};
+use super::viewport::{RenderResolution, ViewportScaling};
+use super::window::{GameWindow, WindowConfig};
+use super::ui::{update_subtitles, update_ui_focus, FontId, Fonts, NavAction, Subtitles, UiDraw, UiFocus};
+
+const ANIMATION_FRAMES: bool = cfg!(target_arch = "wasm32");
+
+pub type MainLoopFn = fn(engine: &mut Engine) -> Result<(), EngineError>;
+pub type WindowResizedFn = fn(engine: &mut Engine, event: WindowResized);
+pub type CollisionFn = fn(engine: &mut Engine, collision: Collision);
+pub type ScaleFactorChangedFn = fn(engine: &mut Engine, event: ScaleFactorChanged);
+pub type AnimationFinishedFn = fn(engine: &mut Engine, event: AnimationFinished);
+
+// The window's drawable area changed, in physical pixels. The renderer has already been resized by
+// the time subscribers hear about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowResized {
+  pub width: u32,
+  pub height: u32,
+}
 
-const FRAME_DURATION: Duration = Duration::from_nanos(33_333_333);
+// The window moved to a monitor with a different scale factor, or the monitor's changed. `width`
+// and `height` are the new drawable size in physical pixels, which the renderer's been resized to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScaleFactorChanged {
+  pub scale_factor: f32,
+  pub width: u32,
+  pub height: u32,
+}
 
-pub type MainLoopFn = fn(engine: &mut Engine) -> Result<(), String>;
+// How to start the engine, for `Engine::run_with_config`. Pacing comes from both settings: no
+// `target_fps` with vsync off runs uncapped, `target_fps` caps the rate by sleeping, and vsync on
+// its own lets presentation set the pace. `match_refresh_rate` replaces `target_fps` with the
+// monitor's refresh rate, following the window between monitors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EngineConfig {
+  pub window: WindowConfig,
+  pub backend: Backend,
+  pub target_fps: Option<u32>,
+  pub match_refresh_rate: bool,
+  pub vsync: VsyncMode,
+  pub render_thread: bool, // draw on a thread of its own, a frame behind the game; ignored on the web
+  pub cvars_file: Option<PathBuf>, // where `Engine::cvars` are loaded from at start and saved to at exit
+  pub log: LogConfig,
+}
 
-pub struct Engine {
-  pub event_queue: Vec<GameEvent>,
-  task: MainLoopFn,
+impl EngineConfig {
+  pub fn frame_limit(&self) -> Option<Duration> {
+    self.target_fps.filter(|fps| *fps > 0).map(|fps| Duration::from_secs_f64(1.0 / fps as f64))
+  }
+}
 
+impl Default for EngineConfig {
+  fn default() -> Self {
+    EngineConfig { window: WindowConfig::default(), backend: Backend::default(), target_fps: Some(30), match_refresh_rate: false, vsync: VsyncMode::On, render_thread: true, cvars_file: None, log: LogConfig::default() }
+  }
+}
 
+pub struct Engine {
+  pub event_queue: EventQueue,
+  pub worlds: Worlds,
+  pub schedule: Schedule, // runs once a frame
+  pub fixed_schedule: Schedule, // runs at `time`'s fixed rate, before `schedule`
+  pub time: Time,
+  pub input: Input,
+  pub gamepads: Gamepads,
+  #[cfg(not(target_arch = "wasm32"))]
+  gamepad_backend: Option<GamepadBackend>, // polled into `gamepads` at the start of each frame
+  pub assets: Assets,
+  pub jobs: Jobs, // the worker pool; `spawn_frame` jobs are joined before each frame renders
+  pub audio_output: Box<dyn AudioOutput>, // plays the world's `Audio` mix
+  pub main_camera: Camera, // copied into the active world as a resource before rendering
+  pub window: GameWindow, // title, fullscreen and cursor, applied after every frame
+  pub exit_key: Option<VirtualKeyCode>, // closes the game when pressed
+  pub frame_limit: Option<Duration>, // sleep out the rest of each frame to at most this rate; `None` is uncapped
+  pub match_refresh_rate: bool, // set `frame_limit` from the monitor's refresh rate whenever it changes
+  pub stats_overlay: Option<FontId>, // draws `stats` in the corner with this font
+  pub console: Console,
+  pub cvars: CVars,
+  cvars_file: Option<PathBuf>,
+  stats: FrameStats,
+  window_size: UVec2,
+  scale_factor: f32,
+  resized: Option<WindowResized>, // delivered at the start of the next frame
+  rescaled: Option<ScaleFactorChanged>, // likewise
+  exiting: bool,
+  resize_handlers: Vec<WindowResizedFn>,
+  rescale_handlers: Vec<ScaleFactorChangedFn>,
+  collision_handlers: Vec<CollisionFn>,
+  animation_finished_handlers: Vec<AnimationFinishedFn>,
+  pub registry: TypeRegistry,
+  #[cfg(not(target_arch = "wasm32"))]
+  pub scripts: Scripts, // run every frame after `schedule`, with the active world
+  #[cfg(not(target_arch = "wasm32"))]
+  pub plugins: Plugins, // run every frame after `scripts`
+  replay: Option<ReplayMode>,
+  task: MainLoopFn,
 }
 
 impl Engine {
-  pub fn run(task: MainLoopFn) {
+  // Only returns if setup fails: once the window is up, the event loop exits the process itself.
+  pub fn run(task: MainLoopFn) -> Result<(), EngineError> {
+    Engine::run_with(Backend::default(), task)
+  }
+
+  pub fn run_with(backend: Backend, task: MainLoopFn) -> Result<(), EngineError> {
+    Engine::run_with_config(EngineConfig { backend, ..EngineConfig::default() }, task)
+  }
+
+  pub fn run_with_config(config: EngineConfig, task: MainLoopFn) -> Result<(), EngineError> {
+    Engine::start(config, task, None)
+  }
+
+  // Runs the game as usual while recording a `Replay` of it to `path`, written when the game exits
+  // or `stop_recording` is called. The world's `Rng` is reseeded every frame, from a seed that's
+  // recorded along with the frame's time and input.
+  pub fn run_recording(path: impl Into<PathBuf>, task: MainLoopFn) -> Result<(), EngineError> {
+    Engine::run_recording_with_config(EngineConfig::default(), path, task)
+  }
+
+  pub fn run_recording_with_config(config: EngineConfig, path: impl Into<PathBuf>, task: MainLoopFn) -> Result<(), EngineError> {
+    Engine::start(config, task, Some(ReplayMode::record(path)))
+  }
+
+  // Runs the game on the frames recorded in the replay at `path` instead of the window's input and
+  // the clock, then carries on live once it's played out.
+  pub fn run_replay(path: impl AsRef<Path>, task: MainLoopFn) -> Result<(), EngineError> {
+    Engine::run_replay_with_config(EngineConfig::default(), path, task)
+  }
+
+  // Pass the config the replay was recorded with, so things like the fixed rate match.
+  pub fn run_replay_with_config(config: EngineConfig, path: impl AsRef<Path>, task: MainLoopFn) -> Result<(), EngineError> {
+    let replay = Replay::load(path)?;
+    Engine::start(config, task, Some(ReplayMode::play(replay)))
+  }
+
+  // Runs the game loop without a window or GPU, for dedicated servers and tests: the fixed steps,
+  // schedules, physics and event queue all run as usual, but nothing is drawn or played and there's
+  // no input.
+  // Returns once the game calls `exit`. `config.backend` and `vsync` are ignored, and with no
+  // `target_fps` frames run back to back.
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn run_headless(config: EngineConfig, task: MainLoopFn) -> Result<(), EngineError> {
+    logging::init(&config.log);
+    profiler::init();
+    let mut engine = Engine::new(&config, task, None);
+    while !engine.exiting {
+      let start = Instant::now();
+      engine.main_loop(start);
+      engine.jobs.join_frame();
+      engine.stats.record(start, start.elapsed(), Duration::ZERO, None, 0);
+      if let Some(frame_limit) = engine.frame_limit {
+        Engine::end(start, frame_limit);
+      }
+      profiler::frame_mark();
+    }
+    if let Err(err) = engine.save_cvars() {
+      log::error!("{}", err);
+    }
+    engine.stop_recording()
+  }
+
+  fn start(config: EngineConfig, task: MainLoopFn, replay: Option<ReplayMode>) -> Result<(), EngineError> {
+    logging::init(&config.log);
+    profiler::init();
+    let engine = Engine::new(&config, task, replay);
+    // Only a windowed game plays sound and reads gamepads; the web has no backend for either yet.
+    #[cfg(not(target_arch = "wasm32"))]
+    let engine = Engine {
+      audio_output: open_audio_output(),
+      gamepad_backend: GamepadBackend::new().map_err(|err| log::warn!("no gamepads: {}", err)).ok(),
+      ..engine
+    };
+    match config.backend {
+      // The browser can't block on a future, so the web build hands it to the page's event loop
+      // and can only log a setup failure.
+      #[cfg(not(target_arch = "wasm32"))]
+      Backend::Wgpu => pollster::block_on(engine.init::<GraphicsState>(config.window.size, config.vsync, config.render_thread)),
+      #[cfg(target_arch = "wasm32")]
+      Backend::Wgpu => {
+        wasm_bindgen_futures::spawn_local(async move {
+          if let Err(err) = engine.init::<GraphicsState>(config.window.size, config.vsync, config.render_thread).await {
+            log::error!("{}", err);
+          }
+        });
+        Ok(())
+      }
+    }
+  }
+
+  fn new(config: &EngineConfig, task: MainLoopFn, replay: Option<ReplayMode>) -> Engine {
+    let jobs = Jobs::default();
     let mut engine = Engine {
-      event_queue: Vec::new(),
+      event_queue: EventQueue::new(),
+      worlds: Worlds::new(),
+      schedule: Schedule::new(),
+      fixed_schedule: Schedule::new(),
+      time: Time::default(),
+      input: Input::new(),
+      gamepads: Gamepads::new(),
+      #[cfg(not(target_arch = "wasm32"))]
+      gamepad_backend: None,
+      assets: Assets::with_jobs(jobs.clone()),
+      jobs,
+      audio_output: Box::new(NullOutput::default()),
+      main_camera: Camera::default(),
+      window: GameWindow::new(&config.window),
+      exit_key: Some(VirtualKeyCode::Escape),
+      frame_limit: config.frame_limit(),
+      match_refresh_rate: config.match_refresh_rate,
+      stats_overlay: None,
+      console: Console::new(),
+      cvars: CVars::new(),
+      cvars_file: config.cvars_file.clone(),
+      stats: FrameStats::new(),
+      window_size: UVec2::ZERO,
+      scale_factor: 1.0,
+      resized: None,
+      rescaled: None,
+      exiting: false,
+      resize_handlers: Vec::new(),
+      rescale_handlers: Vec::new(),
+      collision_handlers: Vec::new(),
+      animation_finished_handlers: Vec::new(),
+      registry: TypeRegistry::new(),
+      #[cfg(not(target_arch = "wasm32"))]
+      scripts: Scripts::new(),
+      #[cfg(not(target_arch = "wasm32"))]
+      plugins: Plugins::new(),
+      replay,
       task,
     };
 
-    pollster::block_on(Engine::init());
+    engine.registry.register::<PointLight>("PointLight").register::<SpotLight>("SpotLight").register::<ParticleEmitter>("ParticleEmitter");
+    engine.registry.register::<PrefabInstance>("PrefabInstance");
+    #[cfg(not(target_arch = "wasm32"))]
+    engine.registry.register::<Replicated>("Replicated");
+    engine.world_mut().insert_resource(DebugDraw::new());
+    engine.world_mut().insert_resource(TextureManager::new());
+    engine.world_mut().insert_resource(RenderTargets::new());
+    engine.world_mut().insert_resource(Compute::new());
+    engine.world_mut().insert_resource(SpriteBatch::new());
+    engine.world_mut().insert_resource(InstanceBatch::new());
+    engine.world_mut().insert_resource(Environment::new());
+    engine.world_mut().insert_resource(DirectionalLight::default());
+    engine.world_mut().insert_resource(TextRenderer::new());
+    engine.world_mut().insert_resource(AccessibilitySettings::default());
+    engine.world_mut().insert_resource(PostProcessStack::new());
+    engine.world_mut().insert_resource(UiDraw::new());
+    engine.world_mut().insert_resource(Fonts::new());
+    engine.world_mut().insert_resource(Subtitles::new());
+    engine.world_mut().insert_resource(UiFocus::new());
+    engine.world_mut().insert_resource(Rng::from_time());
+    engine.world_mut().insert_resource(Audio::default());
+    engine.world_mut().insert_resource(PhysicsWorld::new());
+    engine.world_mut().insert_resource(Prefabs::new());
+    engine.world_mut().insert_resource(Screenshots::new());
+    engine.share_engine_resources();
+    engine.register_cvars(config);
+    engine
+  }
 
-    // engine.init()
-    // engine.main_loop();
+  // What the renderer, audio and UI read from the active world, which has to keep working after a
+  // switch to another one. One `TextureManager` also keeps texture handles from meaning different
+  // textures in different worlds.
+  fn share_engine_resources(&mut self) {
+    let worlds = &mut self.worlds;
+    worlds.share_resource::<DebugDraw>();
+    worlds.share_resource::<TextureManager>();
+    worlds.share_resource::<RenderTargets>();
+    worlds.share_resource::<Compute>();
+    worlds.share_resource::<SpriteBatch>();
+    worlds.share_resource::<InstanceBatch>();
+    worlds.share_resource::<TextRenderer>();
+    worlds.share_resource::<AccessibilitySettings>();
+    worlds.share_resource::<PostProcessStack>();
+    worlds.share_resource::<UiDraw>();
+    worlds.share_resource::<Fonts>();
+    worlds.share_resource::<Subtitles>();
+    worlds.share_resource::<Audio>();
+    worlds.share_resource::<Screenshots>();
   }
 
-  async fn init() {
-    cfg_if::cfg_if! {
-      if #[cfg(target_arch = "wasm32")] {
-        std::panic::set_hook(Box::new(console_error_panic_hook::hook));
-        console_log::init_with_level(log::Level::Warn).expect("Couldn't initialize logger");
-      } else {
-        env_logger::init();
+  // The engine's own variables, then any saved ones and `+name=value` command-line overrides. Their
+  // handlers run at the start of the first frame.
+  fn register_cvars(&mut self, config: &EngineConfig) {
+    let cvars = &mut self.cvars;
+    // Rounded, as the rate comes back from the step's length.
+    cvars.register("fixed_hz", (self.time.fixed_hz() * 1000.0).round() / 1000.0, "fixed updates a second, for physics and `fixed_schedule`");
+    cvars.on_change("fixed_hz", |engine, value| {
+      // Past these the step is too long to be useful or too short to keep up with.
+      const RANGE: std::ops::RangeInclusive<f64> = 1.0..=1000.0;
+      // Out of range, the variable's put back in it, and this runs again with that.
+      let corrected = match value.as_float().filter(|hz| hz.is_finite()) {
+        Some(hz) if RANGE.contains(&hz) => return engine.time.set_fixed_hz(hz),
+        Some(hz) => hz.clamp(*RANGE.start(), *RANGE.end()),
+        None => (engine.time.fixed_hz() * 1000.0).round() / 1000.0,
+      };
+      // Debug-formatted, so tiny values come out as `1e-300` rather than three hundred zeroes.
+      engine.console.error(format!("fixed_hz has to be between {} and {}, not {:?}", RANGE.start(), RANGE.end(), value.as_float().unwrap_or(f64::NAN)));
+      let _ = engine.cvars.set("fixed_hz", corrected);
+    });
+    cvars.register("fps_max", config.target_fps.unwrap_or(0) as i64, "frame rate cap; 0 for none");
+    cvars.on_change("fps_max", |engine, value| {
+      engine.frame_limit = value.as_int().filter(|fps| *fps > 0).map(|fps| Duration::from_secs_f64(1.0 / fps as f64));
+    });
+    cvars.register("render_scale", 1.0, "the scene's resolution as a fraction of the window's");
+    cvars.on_change("render_scale", |engine, value| {
+      let scale = value.as_float().unwrap_or(1.0).clamp(0.1, 4.0) as f32;
+      let current = engine.world().get_resource::<ViewportScaling>().map(|scaling| scaling.resolution);
+      // A fixed resolution is the game's choice, and stays.
+      match current {
+        Some(RenderResolution::Fixed(_)) => log::warn!("render_scale does nothing with a fixed ViewportScaling resolution"),
+        _ if scale == 1.0 => drop(engine.world_mut().remove_resource::<ViewportScaling>()),
+        _ => drop(engine.world_mut().insert_resource(ViewportScaling::render_scale(scale))),
+      }
+    });
+    cvars.register("log_level", config.log.level.to_string().to_lowercase(), "off, error, warn, info, debug or trace, for modules without their own filter");
+    cvars.on_change("log_level", |engine, value| match value.as_str().and_then(|level| level.parse().ok()) {
+      Some(level) => logging::set_level(level),
+      None => engine.console.error(format!("{} isn't a log level", value)),
+    });
+    cvars.register("debug_draw", true, "draws `DebugDraw` lines");
+    cvars.on_change("debug_draw", |engine, value| {
+      if let Some(mut debug_draw) = engine.world().get_resource_mut::<DebugDraw>() {
+        debug_draw.enabled = value.as_bool().unwrap_or(true);
+      }
+    });
+    cvars.register("physics_debug", false, "draws colliders, contacts, joints and velocities");
+    cvars.on_change("physics_debug", |engine, value| {
+      let enabled = value.as_bool().unwrap_or(false);
+      if !engine.world().contains_resource::<PhysicsDebugSettings>() {
+        engine.world_mut().insert_resource(PhysicsDebugSettings::default());
+      }
+      engine.world().resource_mut::<PhysicsDebugSettings>().enabled = enabled;
+    });
+
+    if let Some(path) = &self.cvars_file {
+      if let Err(err) = self.cvars.load(path) {
+        log::warn!("{}", err);
       }
     }
+    self.cvars.apply_args(std::env::args().skip(1));
+  }
+
+  // Writes `cvars` to `EngineConfig::cvars_file`, if there is one. Called when the game exits.
+  pub fn save_cvars(&self) -> Result<(), EngineError> {
+    match &self.cvars_file {
+      Some(path) => self.cvars.save(path),
+      None => Ok(()),
+    }
+  }
+
+  async fn init<R: ThreadableBackend>(mut self, size: UVec2, vsync: VsyncMode, render_thread: bool) -> Result<(), EngineError> {
+    #[cfg(target_arch = "wasm32")]
+    std::panic::set_hook(Box::new(console_error_panic_hook::hook));
     let event_loop = EventLoop::new();
-    let window = WindowBuilder::new().build(&event_loop).unwrap();
+    // Winit prevents sizing the canvas with CSS, so on the web too the size comes from here.
+    let builder = self.window.builder(size, event_loop.available_monitors().collect(), event_loop.primary_monitor());
+    let window = builder.build(&event_loop).map_err(|err| EngineError::Window(err.to_string()))?;
 
     #[cfg(target_arch = "wasm32")]
     {
-      // Winit prevents sizing with CSS, so we have to set
-      // the size manually when on web.
-      use winit::dpi::PhysicalSize;
-      window.set_inner_size(PhysicalSize::new(450, 400));
-
       use winit::platform::web::WindowExtWebSys;
       web_sys::window()
           .and_then(|win| win.document())
@@ -67,14 +432,23 @@ impl Engine {
             dst.append_child(&canvas).ok()?;
             Some(())
           })
-          .expect("Couldn't append canvas to document body.");
+          .ok_or_else(|| EngineError::Window("couldn't append the canvas to the document".to_string()))?;
     }
 
-    let mut gfx_state =
-        super::graphics::graphics_state::GraphicsState::new(&window).await;
+    let mut renderer = Renderer::new(R::init(&window, vsync).await?, render_thread);
+    let size = window.inner_size();
+    self.window_size = UVec2::new(size.width, size.height);
+    self.scale_factor = window.scale_factor() as f32;
+    self.monitors_changed(&window);
 
     event_loop.run(move |event, _, control_flow| {
-      control_flow.set_poll();
+      // In the browser frames are driven by `requestAnimationFrame` (winit's redraw requests) rather
+      // than a busy loop, which the page would never get a chance to paint during.
+      if ANIMATION_FRAMES {
+        control_flow.set_wait();
+      } else {
+        control_flow.set_poll();
+      }
 
       match event {
         Event::NewEvents(_) => {}
@@ -84,84 +458,521 @@ impl Engine {
           ref event
         } if window_id == window.id() => match event {
 
-          WindowEvent::CloseRequested |
-          WindowEvent::KeyboardInput {
-            input: KeyboardInput {
-              state: ElementState::Pressed,
-              virtual_keycode: Some(VirtualKeyCode::Escape), ..
-            }, ..
-          } => control_flow.set_exit(),
+          WindowEvent::CloseRequested => control_flow.set_exit(),
 
           WindowEvent::KeyboardInput {
-            input: KeyboardInput {
-              state: ElementState::Pressed,
-              virtual_keycode: Some(VirtualKeyCode::A), ..
-            }, ..
-          } => println!("Hello there"),
+            input: KeyboardInput { state, virtual_keycode: Some(key), .. }, ..
+          } => {
+            self.input_event(InputEvent::Key(*key, *state));
+            // Escape closes the console rather than the game.
+            if *state == ElementState::Pressed && Some(*key) == self.exit_key && !self.console.is_open() {
+              control_flow.set_exit();
+            }
+          }
+
+          WindowEvent::MouseInput { state, button, .. } => self.input_event(InputEvent::MouseButton(*button, *state)),
+
+          WindowEvent::CursorMoved { position, .. } =>
+            self.input_event(InputEvent::CursorMoved(Vec2::new(position.x as f32, position.y as f32))),
+
+          WindowEvent::MouseWheel { delta, .. } => self.input_event(InputEvent::MouseWheel(*delta)),
+
+          // Input methods send their text as `Ime` events, so this is only plain typing.
+          WindowEvent::ReceivedCharacter(c) if self.input.is_text_input() && self.input.composition().is_none() =>
+            self.input_event(InputEvent::Text(c.to_string())),
+
+          WindowEvent::Ime(ime) if self.input.is_text_input() => match ime {
+            Ime::Preedit(text, cursor) => self.input_event(InputEvent::Composition(Some(Composition { text: text.clone(), cursor: *cursor }))),
+            Ime::Commit(text) => self.input_event(InputEvent::Text(text.clone())),
+            Ime::Disabled => self.input_event(InputEvent::Composition(None)),
+            Ime::Enabled => {}
+          }
+
+          WindowEvent::Focused(focused) => {
+            self.window.set_focused(*focused);
+            if *focused {
+              // Display settings are usually changed from another window.
+              self.monitors_changed(&window);
+            } else {
+              self.input_event(InputEvent::ReleaseAll);
+            }
+          }
+
+          WindowEvent::Moved(_) if self.window.changed_monitor(&window) => self.monitors_changed(&window),
 
           WindowEvent::Resized(physical_size) =>
-            gfx_state.resize(physical_size.width, physical_size.height),
+            self.window_resized(&mut renderer, physical_size.width, physical_size.height),
 
-          WindowEvent::ScaleFactorChanged {new_inner_size, ..} =>
-            gfx_state.resize(new_inner_size.width, new_inner_size.height),
+          WindowEvent::ScaleFactorChanged {scale_factor, new_inner_size} => {
+            self.scale_factor = *scale_factor as f32;
+            self.window_resized(&mut renderer, new_inner_size.width, new_inner_size.height);
+            self.rescaled = Some(ScaleFactorChanged { scale_factor: self.scale_factor, width: new_inner_size.width, height: new_inner_size.height });
+            self.monitors_changed(&window);
+          }
 
           _ => {},
         }
 
         Event::WindowEvent { .. } => {}
+        Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta }, .. } =>
+          self.input_event(InputEvent::MouseMotion(Vec2::new(delta.0 as f32, delta.1 as f32))),
         Event::DeviceEvent { .. } => {}
         Event::UserEvent(_) => {}
 
         Event::Suspended => {}
         Event::Resumed => {}
 
-        Event::MainEventsCleared => {
-          match gfx_state.render() {
-            Ok(_) => {},
-            Err(SurfaceError::Lost) => gfx_state.resize(
-              gfx_state.config.width,
-              gfx_state.config.height),
-            Err(SurfaceError::OutOfMemory) => control_flow.set_exit(),
-            Err(e) => println!("{:?}", e),
+        Event::MainEventsCleared if ANIMATION_FRAMES => window.request_redraw(),
+        Event::MainEventsCleared => self.frame(&mut renderer, &window, control_flow),
+        Event::RedrawRequested(_) if ANIMATION_FRAMES => self.frame(&mut renderer, &window, control_flow),
+        // Event::RedrawEventsCleared => {}
+        Event::LoopDestroyed => {
+          for result in [self.save_cvars(), self.stop_recording()] {
+            if let Err(err) = result {
+              log::error!("{}", err);
+            }
           }
         }
-        // Event::RedrawRequested(_) => {}
-        // Event::RedrawEventsCleared => {}
-        Event::LoopDestroyed => {}
         _ => {}
       };
 
     });
   }
 
-  fn main_loop(&mut self) {
-    // loop {
-      let start = SystemTime::now();
+  // The currently active world. Switch worlds with `engine.worlds.set_active`.
+  pub fn world(&self) -> &World {
+    self.worlds.active()
+  }
+
+  pub fn world_mut(&mut self) -> &mut World {
+    self.worlds.active_mut()
+  }
+
+  // The window's drawable size in physical pixels. Also in the world as a `WindowResized` resource.
+  pub fn window_size(&self) -> UVec2 {
+    self.window_size
+  }
+
+  // Spawns the entities in a `.scene.ron` file into the active world, using `registry` for their
+  // components. Returns them in the order they're written.
+  pub fn load_scene(&mut self, path: impl AsRef<Path>) -> Result<Vec<Entity>, EngineError> {
+    let path = path.as_ref();
+    let scene = Scene::load(path)?;
+    scene.spawn(self.worlds.active_mut(), &self.registry).map_err(|message| EngineError::Asset { path: path.to_path_buf(), message })
+  }
 
-    println!("hello!!!!");
+  // Loads a prefab into the active world's `Prefabs` as `name`, for `World::spawn_prefab`.
+  pub fn load_prefab(&mut self, name: &str, path: impl AsRef<Path>) -> Result<(), EngineError> {
+    if !self.world().contains_resource::<Prefabs>() {
+      self.world_mut().insert_resource(Prefabs::new());
+    }
+    let mut prefabs = self.worlds.active().resource_mut::<Prefabs>();
+    prefabs.sync_registry(&self.registry);
+    prefabs.load(name, path)
+  }
 
-      self.run_task();
+  // Physical pixels per logical pixel on the window's monitor.
+  pub fn scale_factor(&self) -> f32 {
+    self.scale_factor
+  }
 
-      self.event_queue.run_all();
-      self.event_queue.prune();
+  // Calls `handler` whenever the window changes size, once per frame at most with the latest size.
+  pub fn on_window_resized(&mut self, handler: WindowResizedFn) {
+    self.resize_handlers.push(handler);
+  }
+
+  // Calls `handler` whenever the window's scale factor changes, once per frame at most, after any
+  // `on_window_resized` handlers.
+  pub fn on_scale_factor_changed(&mut self, handler: ScaleFactorChangedFn) {
+    self.rescale_handlers.push(handler);
+  }
+
+  // Calls `handler` for every collision between entities' `Collider`s, through the event queue in the
+  // frame the physics step found it.
+  pub fn on_collision(&mut self, handler: CollisionFn) {
+    self.collision_handlers.push(handler);
+  }
 
-      Engine::end(start);
-    // }
+  // Calls `handler` whenever an `AnimatedSprite` plays out an animation that doesn't loop forever,
+  // through the event queue in the frame it finished.
+  pub fn on_animation_finished(&mut self, handler: AnimationFinishedFn) {
+    self.animation_finished_handlers.push(handler);
+  }
+
+  // Switches the active world's fixed schedule to lockstep, reseeding its `Rng` from the
+  // lockstep's seed so every player draws the same numbers.
+  pub fn start_lockstep(&mut self, lockstep: Lockstep) {
+    self.world_mut().insert_resource(Rng::new(lockstep.seed));
+    self.world_mut().insert_resource(lockstep);
+  }
+
+  // Runs the lockstep ticks that are due and have every player's input, hashing the world after
+  // each. The physics and cloth steps are skipped; they aren't deterministic.
+  fn run_lockstep(&mut self, fixed_steps: u32) {
+    let Some(mut lockstep) = self.world_mut().remove_resource::<Lockstep>() else { return };
+    let ticks = lockstep.due(fixed_steps, self.time.max_fixed_steps);
+    for _ in 0..ticks {
+      let Some(inputs) = lockstep.take_ready() else { break };
+      let tick = inputs.tick;
+      self.world_mut().insert_resource(inputs);
+      record_previous_transforms(self.worlds.active());
+      self.fixed_schedule.run(self.worlds.active_mut());
+      apply_velocities(self.worlds.active(), self.time.fixed_delta_seconds());
+      lockstep.record_hash(tick, state_hash(self.worlds.active(), &self.registry));
+    }
+    self.world_mut().insert_resource(lockstep);
+  }
+
+  // Closes the game once this frame is done.
+  pub fn exit(&mut self) {
+    self.exiting = true;
+  }
+
+  // Feeds `Gamepads` an event from a controller backend, so it's recorded with the rest of the input.
+  // A windowed game's gamepads come through here on their own; this is for other backends.
+  pub fn gamepad_event(&mut self, event: GamepadEvent) {
+    self.input_event(InputEvent::Gamepad(event));
+  }
+
+  // Whether the game is running on a replay's input rather than the player's.
+  pub fn is_replaying(&self) -> bool {
+    self.replay.as_ref().is_some_and(ReplayMode::is_playing)
+  }
+
+  // Writes out the replay being recorded, if there is one, and stops recording.
+  pub fn stop_recording(&mut self) -> Result<(), EngineError> {
+    match self.replay.take() {
+      Some(replay) if !replay.is_playing() => replay.finish(),
+      replay => {
+        self.replay = replay;
+        Ok(())
+      }
+    }
+  }
+
+  fn input_event(&mut self, event: InputEvent) {
+    if self.replay.as_mut().is_none_or(|replay| replay.input(&event)) {
+      self.apply_input(&event);
+    }
+  }
+
+  fn apply_input(&mut self, event: &InputEvent) {
+    event.apply(&mut self.input, &mut self.gamepads);
+    if let InputEvent::Key(key, ElementState::Pressed) = event {
+      if let (Some(action), Some(mut focus)) = (NavAction::from_key(*key), self.world().get_resource_mut::<UiFocus>()) {
+        focus.navigate(action);
+      }
+    }
+  }
+
+  // Moves the clock on for a new frame, from a replay while one's playing, and returns how many fixed
+  // steps are due.
+  fn update_time(&mut self, start: Instant) -> u32 {
+    let Some(mut replay) = self.replay.take() else { return self.time.update(start) };
+    let (fixed_steps, frame) = if replay.is_playing() {
+      match replay.frame(Duration::ZERO) {
+        Some(frame) => {
+          frame.events.iter().for_each(|event| self.apply_input(event));
+          (self.time.update_with_delta(frame.delta()), Some(frame))
+        }
+        None => {
+          log::info!("the replay has played out; carrying on live");
+          return self.time.update(start);
+        }
+      }
+    } else {
+      let fixed_steps = self.time.update(start);
+      (fixed_steps, replay.frame(self.time.delta()))
+    };
+    if let Some(frame) = frame {
+      self.world_mut().insert_resource(Rng::new(frame.seed));
+    }
+    self.replay = Some(replay);
+    fixed_steps
+  }
+
+  fn window_resized<R: ThreadableBackend>(&mut self, renderer: &mut Renderer<R>, width: u32, height: u32) {
+    renderer.backend_mut().resize(width, height);
+    // Minimising reports a zero size; keep the last real one.
+    if width > 0 && height > 0 && UVec2::new(width, height) != self.window_size {
+      self.window_size = UVec2::new(width, height);
+      self.resized = Some(WindowResized { width, height });
+    }
+  }
+
+  fn monitors_changed(&mut self, window: &Window) {
+    if !self.window.update_monitors(window) || !self.match_refresh_rate {
+      return;
+    }
+    if let Some(refresh_rate) = self.window.refresh_rate().filter(|rate| *rate > 0.0) {
+      self.frame_limit = Some(Duration::from_secs_f64(1.0 / refresh_rate as f64));
+    }
+  }
+
+  // How the last couple of seconds of frames went.
+  pub fn stats(&self) -> &FrameStats {
+    &self.stats
+  }
+
+  fn frame<R: ThreadableBackend>(&mut self, renderer: &mut Renderer<R>, window: &Window, control_flow: &mut ControlFlow) {
+    let start = Instant::now();
+    let frame = profiler::ProfileScope::new("frame", module_path!(), file!(), line!());
+    self.main_loop(start);
+    self.jobs.join_frame();
+    if self.exiting {
+      control_flow.set_exit();
+    }
+    if let (Some(font), Some(mut ui)) = (self.stats_overlay, self.world().get_resource_mut::<UiDraw>()) {
+      self.stats.draw_overlay(&mut ui, font, Vec2::splat(12.0));
+    }
+    if let (true, Some(mut ui), Some(fonts)) = (self.console.is_open(), self.world().get_resource_mut::<UiDraw>(), self.world().get_resource::<Fonts>()) {
+      self.console.draw(&mut ui, &fonts, self.time.elapsed_seconds());
+    }
+    self.prepare_draws();
+    self.window.apply(window);
+    self.window.apply_text_input(window, &self.input);
+    let game_window = self.window.clone();
+    self.world_mut().insert_resource(game_window);
+    let updated = Instant::now();
+
+    let render = profiler::ProfileScope::new("render", module_path!(), file!(), line!());
+    match renderer.render(self.worlds.active()) {
+      Ok(_) => {},
+      // Reconfigure from the window itself, which may have changed size since the last resize event.
+      Err(FrameError::Lost) => {
+        let size = window.inner_size();
+        renderer.backend_mut().resize(size.width, size.height)
+      }
+      Err(FrameError::OutOfMemory) => control_flow.set_exit(),
+      Err(e) => log::error!("couldn't render the frame: {:?}", e),
+    }
+    drop(render);
+    let render = renderer.stats();
+    self.stats.record(start, updated - start, updated.elapsed(), render.gpu_time, render.draw_calls);
+    drop(frame);
+
+    // The browser paces animation frames itself, and can't sleep.
+    if let (Some(frame_limit), false) = (self.frame_limit, ANIMATION_FRAMES) {
+      Engine::end(start, frame_limit);
+    }
+    profiler::frame_mark();
+  }
+
+  // Gathers what the renderer draws from the world's entities, after the frame's updates.
+  fn prepare_draws(&mut self) {
+    crate::profile_scope!("extract");
+    // The same size the renderer works out the camera's aspect from.
+    let target = match self.world().get_resource::<ViewportScaling>() {
+      Some(scaling) => scaling.render_size(self.window_size),
+      None => self.window_size,
+    };
+    if let (Some(mut culling), Some(camera)) = (self.world().get_resource_mut::<CullingSettings>(), self.world().get_resource::<Camera>()) {
+      culling.view_projection = camera.view_projection(target.x as f32 / target.y.max(1) as f32);
+    }
+    cull_entities(self.world_mut());
+    draw_instanced_entities(self.world());
+    draw_sprite_entities(self.world());
+  }
+
+  // Brings the engine's copies of time, input and the window into the world and runs what reacts
+  // to them, returning how many fixed steps are due.
+  fn begin_frame(&mut self, start: Instant) -> u32 {
+    crate::profile_scope!("input");
+    self.assets.update();
+    let fixed_steps = self.update_time(start);
+    let time = self.time;
+    self.world_mut().insert_resource(time);
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(events) = self.gamepad_backend.as_mut().map(GamepadBackend::poll) {
+      events.into_iter().for_each(|event| self.gamepad_event(event));
+    }
+    self.input.gamepad_buttons(&self.gamepads);
+    update_console(self);
+    run_cvar_handlers(self);
+    let scaling = self.world().get_resource::<ViewportScaling>().map(|scaling| *scaling);
+    self.input.set_virtual_view(scaling.map(|scaling| scaling.view(self.window_size)));
+    let input = self.input.clone();
+    self.world_mut().insert_resource(input);
+    let gamepads = self.gamepads.clone();
+    self.world_mut().insert_resource(gamepads);
+    let jobs = self.jobs.clone();
+    self.world_mut().insert_resource(jobs);
+    let size = WindowResized { width: self.window_size.x, height: self.window_size.y };
+    self.world_mut().insert_resource(size);
+    self.window.set_size(self.window_size, self.scale_factor);
+    // The renderer keeps this up to date too, but only after the first frame's been drawn.
+    let ui_scale = self.world().get_resource::<AccessibilitySettings>().map_or(1.0, |settings| settings.scaled(1.0));
+    if let (Some(mut ui), false) = (self.world().get_resource_mut::<UiDraw>(), self.window_size == UVec2::ZERO) {
+      ui.fit(self.window_size.as_vec2(), ui_scale);
+    }
+    if let Some(mut prefabs) = self.worlds.active().get_resource_mut::<Prefabs>() {
+      prefabs.sync_registry(&self.registry);
+      prefabs.reload_changed();
+    }
+    if let Some(resized) = self.resized.take() {
+      for handler in self.resize_handlers.clone() {
+        handler(self, resized);
+      }
+    }
+    if let Some(rescaled) = self.rescaled.take() {
+      for handler in self.rescale_handlers.clone() {
+        handler(self, rescaled);
+      }
+    }
+    if let Some(mut screenshots) = self.world().get_resource_mut::<Screenshots>() {
+      if screenshots.key.is_some_and(|key| self.input.just_pressed(key)) {
+        screenshots.save_to_directory();
+      }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    update_recorder(self.world());
+    if let Some(mut focus) = self.world().get_resource_mut::<UiFocus>() {
+      for (_, pad) in self.gamepads.iter() {
+        pad.just_pressed_buttons().filter_map(NavAction::from_gamepad_button).for_each(|action| focus.navigate(action));
+      }
+    }
+    update_ui_focus(self.world());
+    fixed_steps
+  }
+
+  fn main_loop(&mut self, start: Instant) {
+    let fixed_steps = self.begin_frame(start);
+    let time = self.time;
+    crate::profile_scope!("update");
+    self.run_task();
+    self.worlds.active_mut().apply_commands();
+    if self.world().contains_resource::<Lockstep>() {
+      self.run_lockstep(fixed_steps);
+    } else {
+      for _ in 0..fixed_steps {
+        crate::profile_scope!("fixed_step");
+        record_previous_transforms(self.worlds.active());
+        self.fixed_schedule.run(self.worlds.active_mut());
+        apply_velocities(self.worlds.active(), time.fixed_delta_seconds());
+        update_character_controllers(self.worlds.active(), time.fixed_delta_seconds());
+        step_physics(self.worlds.active_mut(), time.fixed_delta_seconds());
+        simulate_cloth(self.worlds.active(), time.fixed_delta_seconds());
+      }
+    }
+    // Once a frame rather than a step, as the debug lines only last the frame.
+    draw_physics_debug(self.world());
+    let collisions = self.world().get_resource_mut::<PhysicsWorld>().map(|mut physics| physics.take_collisions()).unwrap_or_default();
+    for collision in collisions {
+      for handler in self.collision_handlers.clone() {
+        self.event_queue.push(GameEvent::new("collision", 1, move |engine| handler(engine, collision)));
+      }
+    }
+    self.schedule.run(self.worlds.active_mut());
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+      crate::profile_scope!("scripts");
+      let events = self.scripts.update(self.worlds.active_mut(), &self.registry, time.delta_seconds());
+      self.push_script_events(events);
+      let events = self.plugins.update(self.worlds.active_mut(), &self.registry, time.delta_seconds());
+      self.push_script_events(events);
+      update_network(self.worlds.active_mut(), &self.registry, time.delta_seconds());
+    }
+    for finished in update_animated_sprites(self.worlds.active(), time.delta_seconds()) {
+      for handler in self.animation_finished_handlers.clone() {
+        let finished = finished.clone();
+        self.event_queue.push(GameEvent::new("animation-finished", 1, move |engine| handler(engine, finished.clone())));
+      }
+    }
+    update_animation_players(self.worlds.active(), time.delta_seconds());
+    update_trails(self.worlds.active(), time.delta_seconds());
+    update_voxel_terrain(self.worlds.active());
+    // Systems ask for rumble on the world's copy of the pads.
+    let rumble = self.world().get_resource_mut::<Gamepads>().map(|mut pads| pads.take_rumble()).unwrap_or_default();
+    for rumble in rumble {
+      self.gamepads.rumble(rumble.gamepad, rumble.strong, rumble.weak, rumble.duration);
+    }
+    // Taken even with no backend, or it'd be copied back into the world and asked for again.
+    let rumble = self.gamepads.take_rumble();
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(backend) = &mut self.gamepad_backend {
+      rumble.iter().for_each(|rumble| backend.rumble(rumble));
+    }
+    #[cfg(target_arch = "wasm32")]
+    let _ = rumble;
+    if let Some(mut audio) = self.worlds.active().get_resource_mut::<Audio>() {
+      self.audio_output.update(&mut audio, self.time.delta());
+      let finished = audio.take_finished();
+      if let Some(mut subtitles) = self.world().get_resource_mut::<Subtitles>() {
+        finished.into_iter().for_each(|id| subtitles.audio_finished(id));
+      }
+    }
+    update_subtitles(self.world(), time.delta_seconds());
+    // Under a `ViewportScaling` the 2D camera works in the scene's pixels rather than the window's.
+    let (viewport, scale_factor) = match self.world().get_resource::<ViewportScaling>() {
+      Some(scaling) => (scaling.render_size(self.window_size).as_vec2(), 1.0),
+      None => (self.window_size.as_vec2(), self.scale_factor),
+    };
+    update_camera_2d(self.worlds.active(), viewport, scale_factor, time.delta_seconds());
+    let camera = self.main_camera;
+    self.world_mut().insert_resource(camera);
+
+    // Take the queue out while it runs so events can reach the engine, including its queue.
+    let mut events = std::mem::take(&mut self.event_queue);
+    events.run_all(self);
+    events.prune();
+    events.append(&mut self.event_queue);
+    self.event_queue = events;
+    self.worlds.active_mut().clear_trackers();
+    self.input.end_frame();
+    self.gamepads.end_frame();
+  }
+
+  // Puts events pushed by scripts and plugins on the queue, each handing its data to both of their
+  // `on_event`s when it runs.
+  #[cfg(not(target_arch = "wasm32"))]
+  fn push_script_events(&mut self, events: Vec<ScriptEvent>) {
+    for ScriptEvent { name, data } in events {
+      self.event_queue.push(GameEvent::new(name.clone(), 1, move |engine| {
+        let mut events = engine.scripts.dispatch(&name, &data, engine.worlds.active_mut(), &engine.registry);
+        events.extend(engine.plugins.dispatch(&name, &data, engine.worlds.active_mut(), &engine.registry));
+        engine.push_script_events(events);
+      }));
+    }
   }
 
   fn run_task(&mut self) {
     match (self.task)(self) {
       Ok(_) => {}
-      Err(msg) => println!("{}", msg)
+      Err(err) => log::error!("{}", err)
     }
   }
 
-  fn end(start: SystemTime) {
-    let max_frame_time = start + FRAME_DURATION;
-
-    match max_frame_time.duration_since(SystemTime::now()) {
-      Ok(duration) => thread::sleep(duration),
-      Err(_err) => ()
+  fn end(start: Instant, frame_limit: Duration) {
+    crate::profile_scope!("sleep");
+    if let Some(remaining) = (start + frame_limit).checked_duration_since(Instant::now()) {
+      std::thread::sleep(remaining);
     }
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::game_engine::ecs::MAIN_WORLD;
+
+  #[test]
+  fn render_resources_follow_a_world_switch() {
+    let config = EngineConfig { target_fps: None, ..EngineConfig::default() };
+    Engine::run_headless(config, |engine| {
+      engine.worlds.create("menu");
+      assert!(engine.worlds.set_active("menu"));
+      let world = engine.world();
+      assert!(world.contains_resource::<TextureManager>());
+      assert!(world.contains_resource::<SpriteBatch>());
+      assert!(world.contains_resource::<TextRenderer>());
+      assert!(world.contains_resource::<Fonts>());
+      assert!(world.contains_resource::<UiDraw>());
+      assert!(world.contains_resource::<InstanceBatch>());
+      assert!(world.contains_resource::<RenderTargets>());
+      assert!(world.contains_resource::<DebugDraw>());
+      assert!(!engine.worlds.get(MAIN_WORLD).unwrap().contains_resource::<TextureManager>());
+      engine.exit();
+      Ok(())
+    }).unwrap();
+  }
+}
@@ -0,0 +1,47 @@
+use std::fmt;
+use std::path::PathBuf;
+
+// What can stop the engine from starting, or a game from carrying on. Game code can return its own
+// errors as strings with `?` or `.into()`; they become `Task`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EngineError {
+  Window(String), // the window couldn't be opened
+  NoAdapter, // no GPU, or no driver that can draw to the window
+  Device(String), // the GPU was found but wouldn't give us a device
+  Surface(String), // the window can't be drawn to with this GPU
+  Asset { path: PathBuf, message: String },
+  Save { path: PathBuf, message: String }, // a save file couldn't be written or read back
+  Shader(String),
+  Network(String), // a socket couldn't be opened
+  Task(String),
+}
+
+impl fmt::Display for EngineError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      EngineError::Window(message) => write!(f, "couldn't open a window: {}", message),
+      EngineError::NoAdapter => write!(f, "no graphics adapter found; check that a Vulkan, Metal, DX12 or GL driver is installed"),
+      EngineError::Device(message) => write!(f, "couldn't create the graphics device: {}", message),
+      EngineError::Surface(message) => write!(f, "can't draw to the window: {}", message),
+      EngineError::Asset { path, message } => write!(f, "couldn't load {}: {}", path.display(), message),
+      EngineError::Save { path, message } => write!(f, "couldn't save to or load {}: {}", path.display(), message),
+      EngineError::Shader(message) => write!(f, "{}", message),
+      EngineError::Network(message) => write!(f, "network error: {}", message),
+      EngineError::Task(message) => write!(f, "{}", message),
+    }
+  }
+}
+
+impl std::error::Error for EngineError {}
+
+impl From<String> for EngineError {
+  fn from(message: String) -> Self {
+    EngineError::Task(message)
+  }
+}
+
+impl From<&str> for EngineError {
+  fn from(message: &str) -> Self {
+    EngineError::Task(message.to_string())
+  }
+}
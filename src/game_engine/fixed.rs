@@ -0,0 +1,275 @@
+use std::fmt;
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+use serde::{Deserialize, Serialize};
+
+const FRACTION_BITS: u32 = 16;
+const ONE: i64 = 1 << FRACTION_BITS;
+
+// A fixed-point number with 16 fractional bits, for game logic that has to come out exactly the
+// same on every machine, like a lockstep simulation. Floats can round differently between
+// platforms and compilers; this is plain integer arithmetic. Overflow wraps.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Fixed(pub i64); // the raw value, in 65536ths
+
+impl Fixed {
+  pub const ZERO: Fixed = Fixed(0);
+  pub const ONE: Fixed = Fixed(ONE);
+  pub const HALF: Fixed = Fixed(ONE / 2);
+  pub const PI: Fixed = Fixed(205887); // 3.14159...
+
+  pub const fn from_int(value: i32) -> Self {
+    Fixed((value as i64) << FRACTION_BITS)
+  }
+
+  // `numerator / denominator`, for writing constants without going through a float. Rounds towards
+  // zero, and a zero denominator saturates like dividing by zero does.
+  pub const fn from_ratio(numerator: i32, denominator: i32) -> Self {
+    if denominator == 0 {
+      return Fixed(if numerator < 0 { i64::MIN } else { i64::MAX });
+    }
+    Fixed(((numerator as i64) << FRACTION_BITS) / denominator as i64)
+  }
+
+  // Rounds to the nearest 65536th. Fine for constants and settings, but keep floats out of the
+  // simulation itself.
+  pub fn from_f32(value: f32) -> Self {
+    Fixed((value as f64 * ONE as f64).round() as i64)
+  }
+
+  // For drawing and other things that don't feed back into the simulation.
+  pub fn to_f32(self) -> f32 {
+    (self.0 as f64 / ONE as f64) as f32
+  }
+
+  // Rounded towards negative infinity.
+  pub fn to_int(self) -> i64 {
+    self.0 >> FRACTION_BITS
+  }
+
+  pub fn floor(self) -> Self {
+    Fixed(self.0 & !(ONE - 1))
+  }
+
+  pub fn fract(self) -> Self {
+    Fixed(self.0 & (ONE - 1))
+  }
+
+  pub fn abs(self) -> Self {
+    Fixed(self.0.wrapping_abs())
+  }
+
+  pub fn min(self, other: Fixed) -> Self {
+    Ord::min(self, other)
+  }
+
+  pub fn max(self, other: Fixed) -> Self {
+    Ord::max(self, other)
+  }
+
+  pub fn clamp(self, low: Fixed, high: Fixed) -> Self {
+    Ord::clamp(self, low, high)
+  }
+
+  pub fn lerp(self, other: Fixed, t: Fixed) -> Self {
+    self + (other - self) * t
+  }
+
+  // Rounded down, and zero for negative numbers.
+  pub fn sqrt(self) -> Self {
+    if self.0 <= 0 {
+      return Fixed::ZERO;
+    }
+    Fixed(((self.0 as u128) << FRACTION_BITS).isqrt() as i64)
+  }
+}
+
+impl fmt::Display for Fixed {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.0 as f64 / ONE as f64)
+  }
+}
+
+impl From<i32> for Fixed {
+  fn from(value: i32) -> Self {
+    Fixed::from_int(value)
+  }
+}
+
+impl Add for Fixed {
+  type Output = Fixed;
+  fn add(self, other: Fixed) -> Fixed {
+    Fixed(self.0.wrapping_add(other.0))
+  }
+}
+
+impl Sub for Fixed {
+  type Output = Fixed;
+  fn sub(self, other: Fixed) -> Fixed {
+    Fixed(self.0.wrapping_sub(other.0))
+  }
+}
+
+// Rounded towards negative infinity.
+impl Mul for Fixed {
+  type Output = Fixed;
+  fn mul(self, other: Fixed) -> Fixed {
+    Fixed(((self.0 as i128 * other.0 as i128) >> FRACTION_BITS) as i64)
+  }
+}
+
+// Rounded towards zero. Dividing by zero gives the largest value with the dividend's sign rather
+// than panicking.
+impl Div for Fixed {
+  type Output = Fixed;
+  fn div(self, other: Fixed) -> Fixed {
+    if other.0 == 0 {
+      return Fixed(if self.0 < 0 { i64::MIN } else { i64::MAX });
+    }
+    Fixed((((self.0 as i128) << FRACTION_BITS) / other.0 as i128) as i64)
+  }
+}
+
+impl Neg for Fixed {
+  type Output = Fixed;
+  fn neg(self) -> Fixed {
+    Fixed(self.0.wrapping_neg())
+  }
+}
+
+impl AddAssign for Fixed {
+  fn add_assign(&mut self, other: Fixed) {
+    *self = *self + other;
+  }
+}
+
+impl SubAssign for Fixed {
+  fn sub_assign(&mut self, other: Fixed) {
+    *self = *self - other;
+  }
+}
+
+// A 2D vector of `Fixed`s, for positions and velocities in a deterministic simulation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FixedVec2 {
+  pub x: Fixed,
+  pub y: Fixed,
+}
+
+impl FixedVec2 {
+  pub const ZERO: FixedVec2 = FixedVec2 { x: Fixed::ZERO, y: Fixed::ZERO };
+
+  pub fn new(x: Fixed, y: Fixed) -> Self {
+    FixedVec2 { x, y }
+  }
+
+  pub fn dot(self, other: FixedVec2) -> Fixed {
+    self.x * other.x + self.y * other.y
+  }
+
+  pub fn length_squared(self) -> Fixed {
+    self.dot(self)
+  }
+
+  pub fn length(self) -> Fixed {
+    self.length_squared().sqrt()
+  }
+
+  // Zero stays zero.
+  pub fn normalize_or_zero(self) -> Self {
+    let length = self.length();
+    if length == Fixed::ZERO { FixedVec2::ZERO } else { FixedVec2::new(self.x / length, self.y / length) }
+  }
+
+  pub fn lerp(self, other: FixedVec2, t: Fixed) -> Self {
+    FixedVec2::new(self.x.lerp(other.x, t), self.y.lerp(other.y, t))
+  }
+
+  // For drawing.
+  pub fn to_vec2(self) -> glam::Vec2 {
+    glam::Vec2::new(self.x.to_f32(), self.y.to_f32())
+  }
+}
+
+impl Add for FixedVec2 {
+  type Output = FixedVec2;
+  fn add(self, other: FixedVec2) -> FixedVec2 {
+    FixedVec2::new(self.x + other.x, self.y + other.y)
+  }
+}
+
+impl Sub for FixedVec2 {
+  type Output = FixedVec2;
+  fn sub(self, other: FixedVec2) -> FixedVec2 {
+    FixedVec2::new(self.x - other.x, self.y - other.y)
+  }
+}
+
+impl Mul<Fixed> for FixedVec2 {
+  type Output = FixedVec2;
+  fn mul(self, scale: Fixed) -> FixedVec2 {
+    FixedVec2::new(self.x * scale, self.y * scale)
+  }
+}
+
+impl Neg for FixedVec2 {
+  type Output = FixedVec2;
+  fn neg(self) -> FixedVec2 {
+    FixedVec2::new(-self.x, -self.y)
+  }
+}
+
+impl AddAssign for FixedVec2 {
+  fn add_assign(&mut self, other: FixedVec2) {
+    *self = *self + other;
+  }
+}
+
+impl SubAssign for FixedVec2 {
+  fn sub_assign(&mut self, other: FixedVec2) {
+    *self = *self - other;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn mul_rounds_towards_negative_infinity() {
+    assert_eq!(Fixed::from_int(3) * Fixed::HALF, Fixed::from_ratio(3, 2));
+    assert_eq!(Fixed(1) * Fixed::HALF, Fixed(0));
+    assert_eq!(Fixed(-1) * Fixed::HALF, Fixed(-1));
+  }
+
+  #[test]
+  fn div_rounds_towards_zero() {
+    assert_eq!(Fixed::ONE / Fixed::from_int(3), Fixed(21845));
+    assert_eq!(-Fixed::ONE / Fixed::from_int(3), Fixed(-21845));
+    assert_eq!(Fixed::from_int(7) / Fixed::HALF, Fixed::from_int(14));
+  }
+
+  #[test]
+  fn dividing_by_zero_saturates() {
+    assert_eq!(Fixed::ONE / Fixed::ZERO, Fixed(i64::MAX));
+    assert_eq!(Fixed::ZERO / Fixed::ZERO, Fixed(i64::MAX));
+    assert_eq!(-Fixed::ONE / Fixed::ZERO, Fixed(i64::MIN));
+    assert_eq!(Fixed::from_ratio(1, 0), Fixed(i64::MAX));
+    assert_eq!(Fixed::from_ratio(-1, 0), Fixed(i64::MIN));
+  }
+
+  #[test]
+  fn from_ratio_rounds_towards_zero() {
+    assert_eq!(Fixed::from_ratio(1, 3), Fixed(21845));
+    assert_eq!(Fixed::from_ratio(-1, 3), Fixed(-21845));
+    assert_eq!(Fixed::from_ratio(-6, 4), -Fixed::from_ratio(3, 2));
+  }
+
+  #[test]
+  fn sqrt_rounds_down() {
+    assert_eq!(Fixed::from_int(4).sqrt(), Fixed::from_int(2));
+    assert_eq!(Fixed::from_int(2).sqrt(), Fixed(92681)); // 1.41421...
+    assert_eq!(Fixed(1).sqrt(), Fixed(256));
+    assert_eq!(Fixed::from_int(-4).sqrt(), Fixed::ZERO);
+  }
+}
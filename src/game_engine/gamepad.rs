@@ -0,0 +1,229 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct GamepadId(pub usize);
+
+// Buttons by position, so South is A on an Xbox pad and Cross on a PlayStation one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GamepadButton {
+  South,
+  East,
+  North,
+  West,
+  LeftBumper,
+  RightBumper,
+  Select,
+  Start,
+  Mode,
+  LeftStick,
+  RightStick,
+  DPadUp,
+  DPadDown,
+  DPadLeft,
+  DPadRight,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GamepadAxis {
+  LeftStickX,
+  LeftStickY, // up is positive
+  RightStickX,
+  RightStickY,
+  LeftTrigger, // 0..1
+  RightTrigger,
+}
+
+// What a controller backend reports; `Gamepads::handle` turns these into state. Backends should go
+// through `Engine::gamepad_event`, so replays pick them up, as the built-in `GamepadBackend` does.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum GamepadEvent {
+  Connected { id: GamepadId, name: String },
+  Disconnected(GamepadId),
+  Button { id: GamepadId, button: GamepadButton, pressed: bool },
+  Axis { id: GamepadId, axis: GamepadAxis, value: f32 },
+}
+
+// A request to vibrate a pad, for the backend to carry out. Motor strengths are 0..1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rumble {
+  pub gamepad: GamepadId,
+  pub strong: f32,
+  pub weak: f32,
+  pub duration: Duration,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Gamepad {
+  pub name: String,
+  buttons: HashSet<GamepadButton>,
+  pressed: HashSet<GamepadButton>,
+  released: HashSet<GamepadButton>,
+  axes: HashMap<GamepadAxis, f32>,
+  deadzone: f32,
+}
+
+impl Gamepad {
+  pub fn is_pressed(&self, button: GamepadButton) -> bool {
+    self.buttons.contains(&button)
+  }
+
+  pub fn just_pressed(&self, button: GamepadButton) -> bool {
+    self.pressed.contains(&button)
+  }
+
+  pub fn just_released(&self, button: GamepadButton) -> bool {
+    self.released.contains(&button)
+  }
+
+  pub fn just_pressed_buttons(&self) -> impl Iterator<Item = GamepadButton> + '_ {
+    self.pressed.iter().copied()
+  }
+
+  pub fn just_released_buttons(&self) -> impl Iterator<Item = GamepadButton> + '_ {
+    self.released.iter().copied()
+  }
+
+  pub fn pressed_buttons(&self) -> impl Iterator<Item = GamepadButton> + '_ {
+    self.buttons.iter().copied()
+  }
+
+  // The axis with the deadzone taken out and the rest rescaled, so it still reaches 1.
+  pub fn axis(&self, axis: GamepadAxis) -> f32 {
+    let value = self.axes.get(&axis).copied().unwrap_or(0.0);
+    if value.abs() <= self.deadzone {
+      return 0.0;
+    }
+    value.signum() * (value.abs() - self.deadzone) / (1.0 - self.deadzone)
+  }
+
+  // The axis exactly as the backend reported it.
+  pub fn raw_axis(&self, axis: GamepadAxis) -> f32 {
+    self.axes.get(&axis).copied().unwrap_or(0.0)
+  }
+
+  // Sticks use a radial deadzone so diagonals aren't snapped to the axes.
+  pub fn left_stick(&self) -> Vec2 {
+    self.stick(GamepadAxis::LeftStickX, GamepadAxis::LeftStickY)
+  }
+
+  pub fn right_stick(&self) -> Vec2 {
+    self.stick(GamepadAxis::RightStickX, GamepadAxis::RightStickY)
+  }
+
+  fn stick(&self, x: GamepadAxis, y: GamepadAxis) -> Vec2 {
+    let raw = Vec2::new(self.raw_axis(x), self.raw_axis(y));
+    let length = raw.length();
+    if length <= self.deadzone {
+      return Vec2::ZERO;
+    }
+    raw / length * ((length.min(1.0) - self.deadzone) / (1.0 - self.deadzone))
+  }
+}
+
+// Every connected controller, on `Engine` and copied into the active world as a resource each
+// frame. A platform backend, `GamepadBackend` unless the game brings its own, feeds it
+// `GamepadEvent`s and carries out the rumble it asks for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gamepads {
+  pub deadzone: f32,
+  pads: HashMap<GamepadId, Gamepad>,
+  connected: Vec<GamepadId>,
+  disconnected: Vec<GamepadId>,
+  rumble: Vec<Rumble>,
+}
+
+impl Gamepads {
+  pub fn new() -> Self {
+    Gamepads { deadzone: 0.15, pads: HashMap::new(), connected: Vec::new(), disconnected: Vec::new(), rumble: Vec::new() }
+  }
+
+  pub fn handle(&mut self, event: GamepadEvent) {
+    match event {
+      GamepadEvent::Connected { id, name } => {
+        self.pads.insert(id, Gamepad { name, deadzone: self.deadzone, ..Gamepad::default() });
+        self.connected.push(id);
+      }
+      GamepadEvent::Disconnected(id) => {
+        if self.pads.remove(&id).is_some() {
+          self.disconnected.push(id);
+        }
+      }
+      GamepadEvent::Button { id, button, pressed } => {
+        if let Some(pad) = self.pads.get_mut(&id) {
+          if pressed && pad.buttons.insert(button) {
+            pad.pressed.insert(button);
+          } else if !pressed && pad.buttons.remove(&button) {
+            pad.released.insert(button);
+          }
+        }
+      }
+      GamepadEvent::Axis { id, axis, value } => {
+        if let Some(pad) = self.pads.get_mut(&id) {
+          pad.axes.insert(axis, value.clamp(-1.0, 1.0));
+        }
+      }
+    }
+  }
+
+  pub fn get(&self, id: GamepadId) -> Option<&Gamepad> {
+    self.pads.get(&id)
+  }
+
+  // The connected pad with the lowest id, for single-player games.
+  pub fn first(&self) -> Option<(GamepadId, &Gamepad)> {
+    self.pads.iter().min_by_key(|(id, _)| **id).map(|(id, pad)| (*id, pad))
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (GamepadId, &Gamepad)> {
+    self.pads.iter().map(|(id, pad)| (*id, pad))
+  }
+
+  pub fn len(&self) -> usize {
+    self.pads.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.pads.is_empty()
+  }
+
+  // Pads plugged in this frame.
+  pub fn just_connected(&self) -> &[GamepadId] {
+    &self.connected
+  }
+
+  // Pads unplugged this frame.
+  pub fn just_disconnected(&self) -> &[GamepadId] {
+    &self.disconnected
+  }
+
+  pub fn rumble(&mut self, gamepad: GamepadId, strong: f32, weak: f32, duration: Duration) {
+    if self.pads.contains_key(&gamepad) {
+      self.rumble.push(Rumble { gamepad, strong: strong.clamp(0.0, 1.0), weak: weak.clamp(0.0, 1.0), duration });
+    }
+  }
+
+  // The rumble asked for since the last call, for the backend.
+  pub fn take_rumble(&mut self) -> Vec<Rumble> {
+    std::mem::take(&mut self.rumble)
+  }
+
+  // Forgets this frame's presses, releases and hot-plugs.
+  pub fn end_frame(&mut self) {
+    for pad in self.pads.values_mut() {
+      pad.pressed.clear();
+      pad.released.clear();
+      pad.deadzone = self.deadzone;
+    }
+    self.connected.clear();
+    self.disconnected.clear();
+  }
+}
+
+impl Default for Gamepads {
+  fn default() -> Self {
+    Gamepads::new()
+  }
+}
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use gilrs::ff::{BaseEffect, BaseEffectType, Effect, EffectBuilder, Repeat, Replay, Ticks};
+use gilrs::{Axis, Button, EventType, Gilrs};
+
+use super::gamepad::{GamepadAxis, GamepadButton, GamepadEvent, GamepadId, Rumble};
+
+// Reads controllers through gilrs for the engine, which opens one for a windowed game and feeds
+// what it reads to `Engine::gamepad_event` each frame.
+pub struct GamepadBackend {
+  gilrs: Gilrs,
+  pending: Vec<GamepadEvent>, // pads already plugged in at start-up, which gilrs doesn't announce
+  rumble: HashMap<GamepadId, Effect>, // playing until it's done or replaced; dropping one stops it
+}
+
+impl GamepadBackend {
+  pub fn new() -> Result<Self, String> {
+    let gilrs = Gilrs::new().map_err(|err| err.to_string())?;
+    let pending = gilrs.gamepads()
+      .map(|(id, pad)| GamepadEvent::Connected { id: GamepadId(id.into()), name: pad.name().to_string() })
+      .collect();
+    Ok(GamepadBackend { gilrs, pending, rumble: HashMap::new() })
+  }
+
+  // Everything that happened since the last poll.
+  pub fn poll(&mut self) -> Vec<GamepadEvent> {
+    let mut events = std::mem::take(&mut self.pending);
+    while let Some(event) = self.gilrs.next_event() {
+      let id = GamepadId(event.id.into());
+      events.extend(match event.event {
+        EventType::Connected => Some(GamepadEvent::Connected { id, name: self.gilrs.gamepad(event.id).name().to_string() }),
+        EventType::Disconnected => {
+          self.rumble.remove(&id);
+          Some(GamepadEvent::Disconnected(id))
+        }
+        // The analog triggers are axes here, and their digital presses are left out.
+        EventType::ButtonChanged(Button::LeftTrigger2, value, _) => Some(GamepadEvent::Axis { id, axis: GamepadAxis::LeftTrigger, value }),
+        EventType::ButtonChanged(Button::RightTrigger2, value, _) => Some(GamepadEvent::Axis { id, axis: GamepadAxis::RightTrigger, value }),
+        EventType::ButtonPressed(button, _) => button_of(button).map(|button| GamepadEvent::Button { id, button, pressed: true }),
+        EventType::ButtonReleased(button, _) => button_of(button).map(|button| GamepadEvent::Button { id, button, pressed: false }),
+        EventType::AxisChanged(axis, value, _) => axis_of(axis).map(|axis| GamepadEvent::Axis { id, axis, value }),
+        _ => None,
+      });
+    }
+    events
+  }
+
+  // Starts `rumble` on its pad, replacing any that's still going. Pads without force feedback
+  // ignore it.
+  pub fn rumble(&mut self, rumble: &Rumble) {
+    let Some((id, _)) = self.gilrs.gamepads().find(|(id, _)| usize::from(*id) == rumble.gamepad.0) else { return };
+    let duration = Ticks::from_ms(rumble.duration.as_millis().min(u32::MAX as u128) as u32);
+    let motor = |kind| BaseEffect { kind, scheduling: Replay { play_for: duration, ..Default::default() }, envelope: Default::default() };
+    let effect = EffectBuilder::new()
+      .add_effect(motor(BaseEffectType::Strong { magnitude: (rumble.strong * u16::MAX as f32) as u16 }))
+      .add_effect(motor(BaseEffectType::Weak { magnitude: (rumble.weak * u16::MAX as f32) as u16 }))
+      .repeat(Repeat::For(duration))
+      .gamepads(&[id])
+      .finish(&mut self.gilrs);
+    match effect.and_then(|effect| effect.play().map(|_| effect)) {
+      Ok(effect) => {
+        self.rumble.insert(rumble.gamepad, effect);
+      }
+      Err(err) => log::debug!("no rumble on gamepad {}: {}", rumble.gamepad.0, err),
+    }
+  }
+}
+
+fn button_of(button: Button) -> Option<GamepadButton> {
+  Some(match button {
+    Button::South => GamepadButton::South,
+    Button::East => GamepadButton::East,
+    Button::North => GamepadButton::North,
+    Button::West => GamepadButton::West,
+    Button::LeftTrigger => GamepadButton::LeftBumper,
+    Button::RightTrigger => GamepadButton::RightBumper,
+    Button::Select => GamepadButton::Select,
+    Button::Start => GamepadButton::Start,
+    Button::Mode => GamepadButton::Mode,
+    Button::LeftThumb => GamepadButton::LeftStick,
+    Button::RightThumb => GamepadButton::RightStick,
+    Button::DPadUp => GamepadButton::DPadUp,
+    Button::DPadDown => GamepadButton::DPadDown,
+    Button::DPadLeft => GamepadButton::DPadLeft,
+    Button::DPadRight => GamepadButton::DPadRight,
+    _ => return None,
+  })
+}
+
+fn axis_of(axis: Axis) -> Option<GamepadAxis> {
+  Some(match axis {
+    Axis::LeftStickX => GamepadAxis::LeftStickX,
+    Axis::LeftStickY => GamepadAxis::LeftStickY,
+    Axis::RightStickX => GamepadAxis::RightStickX,
+    Axis::RightStickY => GamepadAxis::RightStickY,
+    _ => return None,
+  })
+}
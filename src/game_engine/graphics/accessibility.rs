@@ -0,0 +1,277 @@
+use std::borrow::Cow;
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat3, UVec2, Vec3};
+use serde::{Deserialize, Serialize};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferBindingType, BufferUsages, Color, ColorTargetState, ColorWrites, CommandEncoder, Device, Extent3d, FilterMode, FragmentState, LoadOp, MultisampleState, Operations, PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension, VertexState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ColorBlindMode {
+  #[default]
+  None,
+  Protanopia, // no red cones
+  Deuteranopia, // no green cones
+  Tritanopia, // no blue cones
+  Achromatopsia, // no colour at all
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ColorFilter {
+  // Shows how the game looks with the deficiency, for developers checking their palettes.
+  Simulate,
+  // Shifts the colours the deficiency can't tell apart into ones it can, for players.
+  #[default]
+  Daltonize,
+}
+
+// Engine-wide accessibility options, kept as a resource so games can expose them in their
+// settings menus.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessibilitySettings {
+  pub color_blind_mode: ColorBlindMode,
+  pub color_filter: ColorFilter,
+  pub filter_strength: f32, // 0 leaves the image alone, 1 applies the filter fully
+  pub ui_scale: f32, // multiplies every UI and text size: window pixels per `UiDraw` unit
+}
+
+impl Default for AccessibilitySettings {
+  fn default() -> Self {
+    AccessibilitySettings {
+      color_blind_mode: ColorBlindMode::None,
+      color_filter: ColorFilter::Daltonize,
+      filter_strength: 1.0,
+      ui_scale: 1.0,
+    }
+  }
+}
+
+impl AccessibilitySettings {
+  // A UI size in logical pixels scaled by `ui_scale`.
+  pub fn scaled(&self, size: f32) -> f32 {
+    size * self.ui_scale.max(0.1)
+  }
+
+  // The linear-RGB colour matrix the post filter applies, or the identity when it's off.
+  pub fn color_matrix(&self) -> Mat3 {
+    let simulation = match simulation_matrix(self.color_blind_mode) {
+      Some(simulation) => simulation,
+      None => return Mat3::IDENTITY,
+    };
+    let filter = match self.color_filter {
+      ColorFilter::Simulate => simulation,
+      // Fidaner et al.: take what the viewer loses (original minus simulated) and redistribute it
+      // into the channels they can still see.
+      ColorFilter::Daltonize => {
+        let shift = Mat3::from_cols(Vec3::new(0.0, 0.7, 0.7), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        Mat3::IDENTITY + shift * (Mat3::IDENTITY - simulation)
+      }
+    };
+    let strength = self.filter_strength.clamp(0.0, 1.0);
+    Mat3::IDENTITY * (1.0 - strength) + filter * strength
+  }
+}
+
+// Machado, Oliveira and Fernandes (2009) at full severity, in linear RGB.
+fn simulation_matrix(mode: ColorBlindMode) -> Option<Mat3> {
+  let rows = match mode {
+    ColorBlindMode::None => return None,
+    ColorBlindMode::Protanopia => [[0.152286, 1.052583, -0.204868], [0.114503, 0.786281, 0.099216], [-0.003882, -0.048116, 1.051998]],
+    ColorBlindMode::Deuteranopia => [[0.367322, 0.860646, -0.227968], [0.280085, 0.672501, 0.047413], [-0.011820, 0.042940, 0.968881]],
+    ColorBlindMode::Tritanopia => [[1.255528, -0.076749, -0.178779], [-0.078411, 0.930809, 0.147602], [0.004733, 0.691367, 0.303900]],
+    ColorBlindMode::Achromatopsia => [[0.2126, 0.7152, 0.0722]; 3],
+  };
+  Some(Mat3::from_cols_array_2d(&rows).transpose())
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct FilterUniforms {
+  columns: [[f32; 4]; 3], // mat3x3 columns padded to vec4 for WGSL
+}
+
+// The last step of the post chain: the frame is drawn into `view()` and `apply` writes it to the
+// surface through the colour matrix.
+pub struct AccessibilityFilter {
+  pipeline: RenderPipeline,
+  bind_group_layout: BindGroupLayout,
+  sampler: Sampler,
+  uniform_buffer: Buffer,
+  format: TextureFormat,
+  target: Option<(UVec2, TextureView, BindGroup)>,
+}
+
+impl AccessibilityFilter {
+  pub fn new(device: &Device, format: TextureFormat) -> Self {
+    let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+      label: Some("accessibility-filter-shader"),
+      source: ShaderSource::Wgsl(Cow::Borrowed(
+"
+struct Uniforms {
+    color_matrix: mat3x3<f32>,
+};
+
+@group(0) @binding(0) var source: texture_2d<f32>;
+@group(0) @binding(1) var source_sampler: sampler;
+@group(0) @binding(2) var<uniform> uniforms: Uniforms;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv);
+    return vec4<f32>(clamp(uniforms.color_matrix * color.rgb, vec3<f32>(0.0), vec3<f32>(1.0)), color.a);
+}
+"
+      ))
+    });
+
+    let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("accessibility-filter-bind-group-layout"),
+      entries: &[
+        BindGroupLayoutEntry {
+          binding: 0,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: true },
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false
+          },
+          count: None
+        },
+        BindGroupLayoutEntry {
+          binding: 1,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Sampler(SamplerBindingType::Filtering),
+          count: None
+        },
+        BindGroupLayoutEntry {
+          binding: 2,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None
+          },
+          count: None
+        }
+      ]
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+      label: Some("accessibility-filter-pipeline-layout"),
+      bind_group_layouts: &[&bind_group_layout],
+      push_constant_ranges: &[]
+    });
+
+    let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+      label: Some("accessibility-filter-pipeline"),
+      layout: Some(&pipeline_layout),
+      vertex: VertexState {
+        module: &shader_module,
+        entry_point: "vs_main",
+        buffers: &[]
+      },
+      fragment: Some(FragmentState {
+        module: &shader_module,
+        entry_point: "fs_main",
+        targets: &[Some(ColorTargetState {
+          format,
+          blend: None,
+          write_mask: ColorWrites::ALL
+        })]
+      }),
+      primitive: PrimitiveState::default(),
+      depth_stencil: None,
+      multisample: MultisampleState::default(),
+      multiview: None
+    });
+
+    let sampler = device.create_sampler(&SamplerDescriptor {
+      label: Some("accessibility-filter-sampler"),
+      address_mode_u: AddressMode::ClampToEdge,
+      address_mode_v: AddressMode::ClampToEdge,
+      mag_filter: FilterMode::Nearest,
+      min_filter: FilterMode::Nearest,
+      ..SamplerDescriptor::default()
+    });
+
+    let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("accessibility-filter-uniforms"),
+      usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+      contents: bytemuck::bytes_of(&uniforms(Mat3::IDENTITY))
+    });
+
+    AccessibilityFilter { pipeline, bind_group_layout, sampler, uniform_buffer, format, target: None }
+  }
+
+  // Uploads the colour matrix and makes sure the intermediate target matches the window size.
+  pub fn prepare(&mut self, device: &Device, queue: &Queue, color_matrix: Mat3, window: UVec2) {
+    queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms(color_matrix)));
+    if self.target.as_ref().is_some_and(|(size, _, _)| *size == window) {
+      return;
+    }
+
+    let texture = device.create_texture(&TextureDescriptor {
+      label: Some("accessibility-filter-target"),
+      size: Extent3d { width: window.x.max(1), height: window.y.max(1), depth_or_array_layers: 1 },
+      mip_level_count: 1,
+      sample_count: 1,
+      dimension: TextureDimension::D2,
+      format: self.format,
+      usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING
+    });
+    let view = texture.create_view(&TextureViewDescriptor::default());
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+      label: Some("accessibility-filter-bind-group"),
+      layout: &self.bind_group_layout,
+      entries: &[
+        BindGroupEntry { binding: 0, resource: BindingResource::TextureView(&view) },
+        BindGroupEntry { binding: 1, resource: BindingResource::Sampler(&self.sampler) },
+        BindGroupEntry { binding: 2, resource: self.uniform_buffer.as_entire_binding() }
+      ]
+    });
+    self.target = Some((window, view, bind_group));
+  }
+
+  // Where the frame should be drawn instead of the surface. Only valid after `prepare`.
+  pub fn view(&self) -> Option<&TextureView> {
+    self.target.as_ref().map(|(_, view, _)| view)
+  }
+
+  pub fn apply(&self, encoder: &mut CommandEncoder, target: &TextureView) {
+    let bind_group = match &self.target {
+      Some((_, _, bind_group)) => bind_group,
+      None => return,
+    };
+    let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+      label: Some("accessibility-filter"),
+      color_attachments: &[Some(RenderPassColorAttachment {
+        view: target,
+        ops: Operations { load: LoadOp::Clear(Color::BLACK), store: true },
+        resolve_target: None
+      })],
+      depth_stencil_attachment: None
+    });
+    render_pass.set_pipeline(&self.pipeline);
+    render_pass.set_bind_group(0, bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
+  }
+}
+
+fn uniforms(color_matrix: Mat3) -> FilterUniforms {
+  let column = |index: usize| color_matrix.col(index).extend(0.0).to_array();
+  FilterUniforms { columns: [column(0), column(1), column(2)] }
+}
@@ -0,0 +1,218 @@
+use std::sync::Arc;
+
+use crate::game_engine::ecs::{Transform, World};
+use super::gltf::GltfAnimation;
+use super::skinning::SkinnedMesh;
+
+// A clip playing in an `AnimationPlayer`, and how much of the pose it makes up.
+#[derive(Debug, Clone)]
+struct Layer {
+  clip: usize,
+  time: f32,
+  weight: f32,
+  target: f32, // the weight it's fading towards
+  fade_rate: f32, // weight per second
+}
+
+// Plays clips on an entity's `SkinnedMesh`, blending any number of them by weight. Clips' channels
+// index the skeleton's joints, like the ones `GltfScene::skin_animation` makes.
+#[derive(Debug, Clone)]
+pub struct AnimationPlayer {
+  pub speed: f32, // 2 plays twice as fast
+  pub looping: bool, // otherwise clips hold their last frame
+  clips: Vec<(String, Arc<GltfAnimation>)>,
+  layers: Vec<Layer>,
+  paused: bool,
+}
+
+impl AnimationPlayer {
+  pub fn new() -> Self {
+    AnimationPlayer { speed: 1.0, looping: true, clips: Vec::new(), layers: Vec::new(), paused: false }
+  }
+
+  pub fn with_clip(mut self, name: impl Into<String>, clip: Arc<GltfAnimation>) -> Self {
+    self.add_clip(name, clip);
+    self
+  }
+
+  // Adds a clip to play by `name`, replacing any clip already called that.
+  pub fn add_clip(&mut self, name: impl Into<String>, clip: Arc<GltfAnimation>) {
+    let name = name.into();
+    match self.clips.iter_mut().find(|(other, _)| *other == name) {
+      Some(existing) => existing.1 = clip,
+      None => self.clips.push((name, clip)),
+    }
+  }
+
+  pub fn clip_names(&self) -> impl Iterator<Item = &str> {
+    self.clips.iter().map(|(name, _)| name.as_str())
+  }
+
+  fn clip_index(&self, name: &str) -> Option<usize> {
+    self.clips.iter().position(|(other, _)| other == name)
+  }
+
+  fn layer(&mut self, clip: usize) -> &mut Layer {
+    let index = match self.layers.iter().position(|layer| layer.clip == clip) {
+      Some(index) => index,
+      None => {
+        self.layers.push(Layer { clip, time: 0.0, weight: 0.0, target: 0.0, fade_rate: 0.0 });
+        self.layers.len() - 1
+      }
+    };
+    &mut self.layers[index]
+  }
+
+  // Plays `name` from the start on its own, cutting off whatever was playing. False if there's no
+  // such clip.
+  pub fn play(&mut self, name: &str) -> bool {
+    let clip = match self.clip_index(name) {
+      Some(clip) => clip,
+      None => return false,
+    };
+    self.layers.clear();
+    *self.layer(clip) = Layer { clip, time: 0.0, weight: 1.0, target: 1.0, fade_rate: 0.0 };
+    self.paused = false;
+    true
+  }
+
+  // Fades `name` in over `duration` seconds while everything else fades out. A clip already playing
+  // carries on from where it is; a new one starts from the beginning.
+  pub fn crossfade(&mut self, name: &str, duration: f32) -> bool {
+    let clip = match self.clip_index(name) {
+      Some(clip) => clip,
+      None => return false,
+    };
+    if duration <= 0.0 {
+      return self.play(name);
+    }
+    for layer in &mut self.layers {
+      layer.target = 0.0;
+      layer.fade_rate = layer.weight / duration;
+    }
+    let layer = self.layer(clip);
+    layer.target = 1.0;
+    layer.fade_rate = (1.0 - layer.weight).abs() / duration;
+    self.paused = false;
+    true
+  }
+
+  // Mixes `name` in at `weight` alongside whatever else is playing, e.g. a wave over a walk. Weights
+  // are relative to each other; below a total of 1 the rest pose makes up the difference. A weight of
+  // 0 stops the clip.
+  pub fn blend(&mut self, name: &str, weight: f32) -> bool {
+    let clip = match self.clip_index(name) {
+      Some(clip) => clip,
+      None => return false,
+    };
+    if weight <= 0.0 {
+      self.layers.retain(|layer| layer.clip != clip);
+      return true;
+    }
+    let layer = self.layer(clip);
+    layer.weight = weight;
+    layer.target = weight;
+    layer.fade_rate = 0.0;
+    true
+  }
+
+  pub fn stop(&mut self) {
+    self.layers.clear();
+  }
+
+  pub fn pause(&mut self) {
+    self.paused = true;
+  }
+
+  pub fn resume(&mut self) {
+    self.paused = false;
+  }
+
+  pub fn is_paused(&self) -> bool {
+    self.paused
+  }
+
+  pub fn is_playing(&self, name: &str) -> bool {
+    self.clip_index(name).is_some_and(|clip| self.layers.iter().any(|layer| layer.clip == clip))
+  }
+
+  // How far into `name` playback is, in seconds, if it's playing.
+  pub fn time(&self, name: &str) -> Option<f32> {
+    let clip = self.clip_index(name)?;
+    self.layers.iter().find(|layer| layer.clip == clip).map(|layer| layer.time)
+  }
+
+  pub fn seek(&mut self, name: &str, time: f32) -> bool {
+    let clip = match self.clip_index(name) {
+      Some(clip) => clip,
+      None => return false,
+    };
+    match self.layers.iter_mut().find(|layer| layer.clip == clip) {
+      Some(layer) => {
+        layer.time = time;
+        true
+      }
+      None => false,
+    }
+  }
+
+  // Moves playback and fades on by `dt` seconds, dropping clips that have faded out.
+  pub fn update(&mut self, dt: f32) {
+    if self.paused {
+      return;
+    }
+    for layer in &mut self.layers {
+      let duration = self.clips[layer.clip].1.duration;
+      layer.time += dt * self.speed;
+      layer.time = match self.looping && duration > 0.0 {
+        true => layer.time.rem_euclid(duration),
+        false => layer.time.clamp(0.0, duration),
+      };
+      let step = layer.fade_rate * dt;
+      layer.weight = match layer.weight < layer.target {
+        true => (layer.weight + step).min(layer.target),
+        false => (layer.weight - step).max(layer.target),
+      };
+    }
+    self.layers.retain(|layer| layer.weight > 0.0 || layer.target > 0.0);
+  }
+
+  // Writes the blended pose of every playing clip into `pose`, starting from `rest`.
+  pub fn apply(&self, rest: &[Transform], pose: &mut [Transform]) {
+    let count = rest.len().min(pose.len());
+    pose[..count].copy_from_slice(&rest[..count]);
+    let mut total = (1.0 - self.layers.iter().map(|layer| layer.weight).sum::<f32>()).max(0.0);
+    let mut sampled = rest.to_vec();
+    for layer in &self.layers {
+      if layer.weight <= 0.0 {
+        continue;
+      }
+      sampled.copy_from_slice(rest);
+      self.clips[layer.clip].1.sample(layer.time, &mut sampled);
+      // Each layer moves the blend towards itself by its share of the weight so far.
+      total += layer.weight;
+      let t = layer.weight / total;
+      for (blended, sampled) in pose.iter_mut().zip(&sampled) {
+        blended.translation = blended.translation.lerp(sampled.translation, t);
+        blended.rotation = blended.rotation.slerp(sampled.rotation, t);
+        blended.scale = blended.scale.lerp(sampled.scale, t);
+      }
+    }
+  }
+}
+
+impl Default for AnimationPlayer {
+  fn default() -> Self {
+    AnimationPlayer::new()
+  }
+}
+
+// Advances every `AnimationPlayer` by `dt` seconds and poses the `SkinnedMesh` on its entity. The
+// engine calls it every frame, alongside the sprite animations.
+pub fn update_animation_players(world: &World, dt: f32) {
+  world.query::<(&mut AnimationPlayer, &mut SkinnedMesh)>().for_each(|_, (mut player, mut skinned)| {
+    player.update(dt);
+    let rest = skinned.skeleton.rest_pose();
+    player.apply(&rest, &mut skinned.pose);
+  });
+}
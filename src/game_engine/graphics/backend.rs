@@ -0,0 +1,130 @@
+use std::future::Future;
+use std::time::Duration;
+use winit::window::Window;
+
+use crate::game_engine::ecs::World;
+use crate::game_engine::EngineError;
+use super::graphics_state::{FramePacket, GraphicsState, SurfaceFrame};
+
+// The renderers the engine can drive; pick one with `Engine::run_with`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+  #[default]
+  Wgpu,
+}
+
+// Why a frame couldn't be drawn.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameError {
+  Lost, // the presentation surface must be set up again; the engine resizes to the window
+  OutOfMemory, // unrecoverable, the engine exits
+  Skipped(String), // try again next frame
+}
+
+// How presenting waits for the display. With vsync the GPU paces the loop; `EngineConfig::target_fps`
+// is a separate, CPU-side cap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VsyncMode {
+  #[default]
+  On,
+  Off, // present as soon as a frame is ready, tearing if the platform has to
+  Adaptive, // vsync, but present late frames straight away instead of waiting a whole refresh
+}
+
+impl VsyncMode {
+  // The mode to ask for, and what to fall back to if the surface doesn't offer it.
+  pub fn present_modes(&self) -> (wgpu::PresentMode, wgpu::PresentMode) {
+    match self {
+      VsyncMode::On => (wgpu::PresentMode::Fifo, wgpu::PresentMode::Fifo),
+      VsyncMode::Off => (wgpu::PresentMode::AutoNoVsync, wgpu::PresentMode::Fifo),
+      VsyncMode::Adaptive => (wgpu::PresentMode::FifoRelaxed, wgpu::PresentMode::Fifo),
+    }
+  }
+}
+
+// What the last frame cost the renderer, for `FrameStats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RenderStats {
+  pub draw_calls: u32,
+  pub gpu_time: Option<Duration>, // submit to completion, for the most recent frame that has finished
+}
+
+// What the engine needs from a renderer. A frame goes `prepare` (upload the world's draw data),
+// `acquire` (wait for an image to draw into), `submit` (record and submit the GPU work) then
+// `present`. Only `prepare` sees the world, so the rest can run on a render thread.
+pub trait RenderBackend: Sized {
+  type Packet: Send; // what `prepare` hands on to the rest of the frame
+  type Frame;
+
+  fn init(window: &Window, vsync: VsyncMode) -> impl Future<Output = Result<Self, EngineError>>;
+  fn resize(&mut self, width: u32, height: u32);
+  fn prepare(&mut self, world: &World) -> Self::Packet;
+  fn acquire(&mut self, packet: Self::Packet) -> Result<Self::Frame, FrameError>;
+  fn submit(&mut self, frame: &mut Self::Frame);
+  fn present(&mut self, frame: Self::Frame);
+
+  fn stats(&self) -> RenderStats {
+    RenderStats::default()
+  }
+
+  fn begin_frame(&mut self, world: &World) -> Result<Self::Frame, FrameError> {
+    let packet = self.prepare(world);
+    self.acquire(packet)
+  }
+
+  // Everything after `prepare`.
+  fn draw(&mut self, packet: Self::Packet) -> Result<(), FrameError> {
+    let mut frame = self.acquire(packet)?;
+    self.submit(&mut frame);
+    self.present(frame);
+    Ok(())
+  }
+
+  fn render(&mut self, world: &World) -> Result<(), FrameError> {
+    let packet = self.prepare(world);
+    self.draw(packet)
+  }
+}
+
+impl From<wgpu::SurfaceError> for FrameError {
+  fn from(error: wgpu::SurfaceError) -> Self {
+    match error {
+      wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated => FrameError::Lost,
+      wgpu::SurfaceError::OutOfMemory => FrameError::OutOfMemory,
+      wgpu::SurfaceError::Timeout => FrameError::Skipped(format!("{:?}", error)),
+    }
+  }
+}
+
+impl RenderBackend for GraphicsState {
+  type Packet = FramePacket;
+  type Frame = SurfaceFrame;
+
+  fn init(window: &Window, vsync: VsyncMode) -> impl Future<Output = Result<Self, EngineError>> {
+    GraphicsState::new(window, vsync)
+  }
+
+  fn resize(&mut self, width: u32, height: u32) {
+    GraphicsState::resize(self, width, height)
+  }
+
+  fn prepare(&mut self, world: &World) -> FramePacket {
+    GraphicsState::prepare(self, world)
+  }
+
+  fn acquire(&mut self, packet: FramePacket) -> Result<SurfaceFrame, FrameError> {
+    Ok(GraphicsState::acquire(self, packet)?)
+  }
+
+  fn submit(&mut self, frame: &mut SurfaceFrame) {
+    GraphicsState::submit(self, frame)
+  }
+
+  fn present(&mut self, frame: SurfaceFrame) {
+    GraphicsState::present(self, frame)
+  }
+
+  fn stats(&self) -> RenderStats {
+    RenderStats { draw_calls: self.draw_calls, gpu_time: *self.gpu_time.lock().unwrap() }
+  }
+}
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindingResource, Buffer, BufferAddress, BufferBinding, BufferSize, Device, Sampler, TextureView};
+
+// wgpu resources can't be compared or hashed, so anything that goes into a cached bind group is
+// given an id when it's created. Recreating a resource (e.g. on resize) means a new id, or
+// `BindGroupCache::invalidate` on the old one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ResourceId(u64);
+
+impl ResourceId {
+  pub fn new() -> Self {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    ResourceId(NEXT.fetch_add(1, Ordering::Relaxed))
+  }
+}
+
+impl Default for ResourceId {
+  fn default() -> Self {
+    ResourceId::new()
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum CachedBinding<'a> {
+  Buffer { id: ResourceId, buffer: &'a Buffer, offset: BufferAddress, size: Option<BufferSize> },
+  Texture { id: ResourceId, view: &'a TextureView },
+  Sampler { id: ResourceId, sampler: &'a Sampler },
+}
+
+impl<'a> CachedBinding<'a> {
+  pub fn buffer(id: ResourceId, buffer: &'a Buffer) -> Self {
+    CachedBinding::Buffer { id, buffer, offset: 0, size: None }
+  }
+
+  pub fn texture(id: ResourceId, view: &'a TextureView) -> Self {
+    CachedBinding::Texture { id, view }
+  }
+
+  pub fn sampler(id: ResourceId, sampler: &'a Sampler) -> Self {
+    CachedBinding::Sampler { id, sampler }
+  }
+
+  fn key(&self) -> (ResourceId, BufferAddress, u64) {
+    match *self {
+      CachedBinding::Buffer { id, offset, size, .. } => (id, offset, size.map_or(0, BufferSize::get)),
+      CachedBinding::Texture { id, .. } | CachedBinding::Sampler { id, .. } => (id, 0, 0),
+    }
+  }
+
+  fn resource(&self) -> BindingResource<'a> {
+    match *self {
+      CachedBinding::Buffer { buffer, offset, size, .. } => BindingResource::Buffer(BufferBinding { buffer, offset, size }),
+      CachedBinding::Texture { view, .. } => BindingResource::TextureView(view),
+      CachedBinding::Sampler { sampler, .. } => BindingResource::Sampler(sampler),
+    }
+  }
+}
+
+// The layout plus every resource in binding order.
+type BindGroupKey = (ResourceId, Vec<(ResourceId, BufferAddress, u64)>);
+
+// Hands out a cached bind group for a layout and set of resources, creating it only the first
+// time that combination is asked for. Look groups up with `get` while preparing the frame, then
+// borrow them with `bind_group` inside the render pass.
+pub struct BindGroupCache {
+  ids: HashMap<BindGroupKey, ResourceId>,
+  entries: HashMap<ResourceId, CachedBindGroup>,
+  frame: u64,
+  pub max_unused_frames: u64, // groups not asked for in this many frames are dropped
+}
+
+struct CachedBindGroup {
+  key: BindGroupKey,
+  bind_group: BindGroup,
+  last_used: u64,
+}
+
+impl BindGroupCache {
+  pub fn new() -> Self {
+    BindGroupCache { ids: HashMap::new(), entries: HashMap::new(), frame: 0, max_unused_frames: 60 }
+  }
+
+  // The id of the bind group for `layout` with `bindings` (numbered from 0), creating it if needed.
+  pub fn get(&mut self, device: &Device, layout: (ResourceId, &BindGroupLayout), bindings: &[CachedBinding]) -> ResourceId {
+    let key: BindGroupKey = (layout.0, bindings.iter().map(CachedBinding::key).collect());
+    if let Some(id) = self.ids.get(&key) {
+      let entry = self.entries.get_mut(id).unwrap();
+      entry.last_used = self.frame;
+      return *id;
+    }
+
+    let entries: Vec<BindGroupEntry> = bindings.iter().enumerate()
+      .map(|(binding, cached)| BindGroupEntry { binding: binding as u32, resource: cached.resource() })
+      .collect();
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+      label: Some("cached-bind-group"),
+      layout: layout.1,
+      entries: &entries
+    });
+
+    let id = ResourceId::new();
+    self.ids.insert(key.clone(), id);
+    self.entries.insert(id, CachedBindGroup { key, bind_group, last_used: self.frame });
+    id
+  }
+
+  pub fn bind_group(&self, id: ResourceId) -> Option<&BindGroup> {
+    self.entries.get(&id).map(|entry| &entry.bind_group)
+  }
+
+  // Drops every group that uses `resource` (or is built on it, for a layout), e.g. after the
+  // resource is destroyed or recreated.
+  pub fn invalidate(&mut self, resource: ResourceId) {
+    self.retain(|key| key.0 != resource && key.1.iter().all(|(id, _, _)| *id != resource));
+  }
+
+  // Advances the frame counter and evicts groups that have gone unused for too long.
+  pub fn end_frame(&mut self) {
+    let (frame, max_unused_frames) = (self.frame, self.max_unused_frames);
+    let stale: Vec<ResourceId> = self.entries.iter()
+      .filter(|(_, entry)| frame - entry.last_used > max_unused_frames)
+      .map(|(id, _)| *id)
+      .collect();
+    for id in stale {
+      if let Some(entry) = self.entries.remove(&id) {
+        self.ids.remove(&entry.key);
+      }
+    }
+    self.frame += 1;
+  }
+
+  fn retain(&mut self, keep: impl Fn(&BindGroupKey) -> bool) {
+    self.entries.retain(|_, entry| keep(&entry.key));
+    self.ids.retain(|key, _| keep(key));
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  pub fn clear(&mut self) {
+    self.entries.clear();
+    self.ids.clear();
+  }
+}
+
+impl Default for BindGroupCache {
+  fn default() -> Self {
+    BindGroupCache::new()
+  }
+}
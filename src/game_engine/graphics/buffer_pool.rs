@@ -0,0 +1,184 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use wgpu::{Buffer, BufferAddress, BufferDescriptor, BufferSize, BufferSlice, BufferUsages, Device, Queue};
+
+use super::bind_group_cache::{CachedBinding, ResourceId};
+
+// How big the pool's shared buffers are. Anything bigger gets a buffer of its own.
+pub const BLOCK_SIZE: BufferAddress = 4 << 20;
+
+// A range of one of `BufferPool`'s buffers, until it's given back with `BufferPool::free`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct BufferAllocation {
+  block: usize,
+  pub offset: BufferAddress,
+  pub size: BufferAddress,
+}
+
+struct Block {
+  buffer: Buffer,
+  id: ResourceId,
+  size: BufferAddress,
+  free: Vec<(BufferAddress, BufferAddress)>, // offset and size of each gap, in order
+}
+
+impl Block {
+  fn take(&mut self, size: BufferAddress, alignment: BufferAddress) -> Option<BufferAddress> {
+    let (index, offset) = self.free.iter().enumerate().find_map(|(index, (start, length))| {
+      let offset = align(*start, alignment);
+      (offset + size <= start + length).then_some((index, offset))
+    })?;
+    let (start, length) = self.free.remove(index);
+    // What's left either side of the allocation stays free.
+    if offset + size < start + length {
+      self.free.insert(index, (offset + size, start + length - offset - size));
+    }
+    if offset > start {
+      self.free.insert(index, (start, offset - start));
+    }
+    Some(offset)
+  }
+
+  fn give_back(&mut self, offset: BufferAddress, size: BufferAddress) {
+    let index = self.free.partition_point(|(start, _)| *start < offset);
+    self.free.insert(index, (offset, size));
+    // Merge with the gaps after and before it.
+    if index + 1 < self.free.len() && offset + size == self.free[index + 1].0 {
+      self.free[index].1 += self.free.remove(index + 1).1;
+    }
+    if index > 0 && self.free[index - 1].0 + self.free[index - 1].1 == offset {
+      self.free[index - 1].1 += self.free.remove(index).1;
+    }
+  }
+
+  fn is_empty(&self) -> bool {
+    self.free == [(0, self.size)]
+  }
+}
+
+// Long-lived GPU data (meshes, instance data, uniforms that rarely change) sub-allocated from a few
+// big buffers instead of a buffer each, which fragments memory and makes creating them slow.
+// Freed ranges go back to the pool once the GPU has finished every frame that might still use them.
+//
+// Call `begin_frame` before allocating and `end_frame` right after submitting the frame's work,
+// like `FrameAllocator`.
+pub struct BufferPool {
+  blocks: Vec<Option<Block>>, // a freed block's slot stays, so allocations keep their index
+  alignment: BufferAddress, // enough for any use, uniform bindings included
+  frame: u64,
+  freed: Vec<(u64, BufferAllocation)>, // with the frame they were freed in
+  completed: Arc<AtomicU64>, // the newest frame the GPU has finished
+}
+
+impl BufferPool {
+  pub fn new(device: &Device) -> Self {
+    let limits = device.limits();
+    BufferPool {
+      blocks: Vec::new(),
+      alignment: (limits.min_uniform_buffer_offset_alignment.max(limits.min_storage_buffer_offset_alignment) as BufferAddress).max(wgpu::COPY_BUFFER_ALIGNMENT),
+      frame: 0,
+      freed: Vec::new(),
+      completed: Arc::new(AtomicU64::new(0)),
+    }
+  }
+
+  // Gives back what was freed in frames the GPU has since finished.
+  pub fn begin_frame(&mut self) {
+    self.frame += 1;
+    let completed = self.completed.load(Ordering::Acquire);
+    let (done, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.freed).into_iter().partition(|(frame, _)| *frame <= completed);
+    self.freed = waiting;
+    for (_, allocation) in done {
+      let slot = &mut self.blocks[allocation.block];
+      if let Some(block) = slot {
+        block.give_back(allocation.offset, allocation.size);
+        // Oversized blocks only ever hold one allocation.
+        if block.size > BLOCK_SIZE && block.is_empty() {
+          *slot = None;
+        }
+      }
+    }
+  }
+
+  // Marks this frame's frees as safe once the work submitted so far completes.
+  pub fn end_frame(&mut self, queue: &Queue) {
+    let (frame, completed) = (self.frame, self.completed.clone());
+    queue.on_submitted_work_done(move || {
+      completed.fetch_max(frame, Ordering::AcqRel);
+    });
+  }
+
+  // `size` bytes for vertex, index, uniform or storage data. Fill it with `UploadQueue::write_buffer`
+  // or `Queue::write_buffer` at `offset` in `buffer`.
+  pub fn alloc(&mut self, device: &Device, size: BufferAddress) -> BufferAllocation {
+    let size = align(size.max(1), wgpu::COPY_BUFFER_ALIGNMENT);
+    let alignment = self.alignment;
+    for (index, slot) in self.blocks.iter_mut().enumerate() {
+      if let Some(offset) = slot.as_mut().and_then(|block| block.take(size, alignment)) {
+        return BufferAllocation { block: index, offset, size };
+      }
+    }
+
+    let block_size = BLOCK_SIZE.max(align(size, alignment));
+    let mut block = Block {
+      buffer: device.create_buffer(&BufferDescriptor {
+        label: Some("buffer-pool-block"),
+        size: block_size,
+        usage: BufferUsages::VERTEX | BufferUsages::INDEX | BufferUsages::UNIFORM | BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+        mapped_at_creation: false
+      }),
+      id: ResourceId::new(),
+      size: block_size,
+      free: vec![(0, block_size)],
+    };
+    let offset = block.take(size, alignment).unwrap();
+    let index = match self.blocks.iter().position(Option::is_none) {
+      Some(index) => {
+        self.blocks[index] = Some(block);
+        index
+      }
+      None => {
+        self.blocks.push(Some(block));
+        self.blocks.len() - 1
+      }
+    };
+    BufferAllocation { block: index, offset, size }
+  }
+
+  // Gives `allocation` back once the GPU's done with the frames that might still read it.
+  pub fn free(&mut self, allocation: BufferAllocation) {
+    self.freed.push((self.frame, allocation));
+  }
+
+  pub fn buffer(&self, allocation: &BufferAllocation) -> &Buffer {
+    &self.block(allocation).buffer
+  }
+
+  fn block(&self, allocation: &BufferAllocation) -> &Block {
+    self.blocks[allocation.block].as_ref().expect("buffer allocation used after being freed")
+  }
+
+  // The allocation as a binding for `BindGroupCache`.
+  pub fn binding(&self, allocation: &BufferAllocation) -> CachedBinding<'_> {
+    let block = self.block(allocation);
+    CachedBinding::Buffer { id: block.id, buffer: &block.buffer, offset: allocation.offset, size: BufferSize::new(allocation.size) }
+  }
+
+  pub fn slice(&self, allocation: &BufferAllocation) -> BufferSlice<'_> {
+    self.buffer(allocation).slice(allocation.offset..allocation.offset + allocation.size)
+  }
+
+  // Bytes in the pool's buffers, used or not.
+  pub fn capacity(&self) -> BufferAddress {
+    self.blocks.iter().flatten().map(|block| block.size).sum()
+  }
+
+  // Bytes handed out and not yet given back.
+  pub fn allocated(&self) -> BufferAddress {
+    self.blocks.iter().flatten().map(|block| block.size - block.free.iter().map(|(_, size)| size).sum::<BufferAddress>()).sum()
+  }
+}
+
+fn align(value: BufferAddress, alignment: BufferAddress) -> BufferAddress {
+  value.div_ceil(alignment) * alignment
+}
@@ -0,0 +1,158 @@
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Quat, Vec2, Vec3};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType, BufferUsages, Device, Queue, ShaderStages};
+
+use crate::game_engine::ecs::Transform;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+  Perspective { fov_y: f32, near: f32, far: f32 }, // fov_y in radians
+  Orthographic { height: f32, near: f32, far: f32 }, // world units visible top to bottom
+}
+
+// Where the scene is seen from. `Engine::main_camera` is copied into the active world as a resource
+// each frame, and the renderer uploads its view-projection before drawing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+  pub transform: Transform, // looks down its -z, like `Transform::forward`
+  pub projection: Projection,
+  pub zoom: f32, // 2 shows half as much
+}
+
+impl Camera {
+  pub fn perspective(fov_y: f32, near: f32, far: f32) -> Self {
+    Camera { transform: Transform::IDENTITY, projection: Projection::Perspective { fov_y, near, far }, zoom: 1.0 }
+  }
+
+  pub fn orthographic(height: f32, near: f32, far: f32) -> Self {
+    Camera { transform: Transform::IDENTITY, projection: Projection::Orthographic { height, near, far }, zoom: 1.0 }
+  }
+
+  pub fn position(&self) -> Vec3 {
+    self.transform.translation
+  }
+
+  pub fn set_position(&mut self, position: Vec3) -> &mut Self {
+    self.transform.translation = position;
+    self
+  }
+
+  // Moves relative to where the camera faces: x right, y up, z backwards.
+  pub fn translate_local(&mut self, offset: Vec3) -> &mut Self {
+    self.transform.translation += self.transform.rotation * offset;
+    self
+  }
+
+  pub fn look_at(&mut self, target: Vec3, up: Vec3) -> &mut Self {
+    let view = Mat4::look_at_rh(self.transform.translation, target, up);
+    self.transform.rotation = Quat::from_mat4(&view.inverse());
+    self
+  }
+
+  // Multiplies the zoom, so `zoom_by(1.1)` each frame zooms in steadily.
+  pub fn zoom_by(&mut self, factor: f32) -> &mut Self {
+    self.zoom = (self.zoom * factor).max(f32::EPSILON);
+    self
+  }
+
+  // The near and far plane distances, whichever the projection.
+  pub fn clip_planes(&self) -> (f32, f32) {
+    match self.projection {
+      Projection::Perspective { near, far, .. } | Projection::Orthographic { near, far, .. } => (near, far),
+    }
+  }
+
+  pub fn view(&self) -> Mat4 {
+    Mat4::from_rotation_translation(self.transform.rotation, self.transform.translation).inverse()
+  }
+
+  // `aspect` is the render target's width over its height.
+  pub fn projection(&self, aspect: f32) -> Mat4 {
+    match self.projection {
+      Projection::Perspective { fov_y, near, far } => {
+        // Zooming narrows the field of view rather than moving the camera.
+        let fov_y = 2.0 * ((fov_y * 0.5).tan() / self.zoom).atan();
+        Mat4::perspective_rh(fov_y, aspect, near, far)
+      }
+      Projection::Orthographic { height, near, far } => {
+        let half = Vec2::new(height * aspect, height) * 0.5 / self.zoom;
+        Mat4::orthographic_rh(-half.x, half.x, -half.y, half.y, near, far)
+      }
+    }
+  }
+
+  pub fn view_projection(&self, aspect: f32) -> Mat4 {
+    self.projection(aspect) * self.view()
+  }
+
+  // The world-space ray through a point in the viewport (pixels, origin top-left), as an origin and
+  // a unit direction. Useful for picking with the mouse.
+  pub fn viewport_ray(&self, point: Vec2, viewport: Vec2) -> (Vec3, Vec3) {
+    let ndc = Vec2::new(point.x / viewport.x * 2.0 - 1.0, 1.0 - point.y / viewport.y * 2.0);
+    let inverse = self.view_projection(viewport.x / viewport.y).inverse();
+    let near = inverse.project_point3(ndc.extend(0.0));
+    let far = inverse.project_point3(ndc.extend(1.0));
+    (near, (far - near).normalize())
+  }
+}
+
+impl Default for Camera {
+  // Perspective, from 5 units back along +z looking at the origin.
+  fn default() -> Self {
+    let mut camera = Camera::perspective(60f32.to_radians(), 0.1, 1000.0);
+    camera.transform.translation = Vec3::new(0.0, 0.0, 5.0);
+    camera
+  }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct CameraUniforms {
+  pub view_proj: [f32; 16],
+  pub position: [f32; 4],
+}
+
+// The camera's uniform buffer at group 0 binding 0, for pipelines that draw in world space.
+pub struct CameraBuffer {
+  pub layout: BindGroupLayout,
+  pub bind_group: BindGroup,
+  buffer: Buffer,
+}
+
+impl CameraBuffer {
+  pub fn new(device: &Device) -> Self {
+    let buffer = device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("camera-uniforms"),
+      contents: bytemuck::bytes_of(&CameraUniforms { view_proj: Mat4::IDENTITY.to_cols_array(), position: [0.0, 0.0, 0.0, 1.0] }),
+      usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
+    });
+
+    let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("camera-bind-group-layout"),
+      entries: &[BindGroupLayoutEntry {
+        binding: 0,
+        visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+        ty: BindingType::Buffer {
+          ty: BufferBindingType::Uniform,
+          has_dynamic_offset: false,
+          min_binding_size: None
+        },
+        count: None
+      }]
+    });
+
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+      label: Some("camera-bind-group"),
+      layout: &layout,
+      entries: &[BindGroupEntry { binding: 0, resource: buffer.as_entire_binding() }]
+    });
+
+    CameraBuffer { layout, bind_group, buffer }
+  }
+
+  pub fn write(&self, queue: &Queue, view_projection: Mat4, position: Vec3) {
+    let uniforms = CameraUniforms { view_proj: view_projection.to_cols_array(), position: position.extend(1.0).to_array() };
+    queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&uniforms));
+  }
+}
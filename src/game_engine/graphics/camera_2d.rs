@@ -0,0 +1,145 @@
+use glam::{Mat4, Vec2};
+
+use crate::game_engine::ecs::{Entity, Transform, World};
+use super::pixel_perfect::snap_to_pixel;
+use super::viewport::ViewportScaling;
+
+// Keeps a `Camera2D` on an entity's `Transform`, easing towards it rather than snapping.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraFollow {
+  pub target: Entity,
+  pub offset: Vec2, // added to the target's position, to look ahead of it
+  pub smoothing: f32, // how quickly the camera catches up, per second; infinite snaps straight to it
+}
+
+impl CameraFollow {
+  pub fn new(target: Entity, smoothing: f32) -> Self {
+    CameraFollow { target, offset: Vec2::ZERO, smoothing }
+  }
+}
+
+// An orthographic camera for 2D games, as a world resource. World units are logical pixels at zoom
+// 1 with y pointing down, like `SpriteBatch`'s, and `position` is the point shown at the middle of
+// the window. While it's in the world, sprites are drawn through it unless the batch has its own
+// `view_projection`.
+//
+// The engine keeps `viewport` and `scale_factor` up to date with the window and moves the camera
+// along after any `follow` target each frame, after the schedule has run. Under a `ViewportScaling`
+// they're the scene's resolution and 1 instead, and screen positions are in the scene's pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera2D {
+  pub position: Vec2,
+  pub zoom: f32, // 2 shows half as much
+  pub rotation: f32, // radians, clockwise on screen
+  pub follow: Option<CameraFollow>,
+  pub viewport: Vec2, // the window's size in physical pixels, or the scene's under a `ViewportScaling`
+  pub scale_factor: f32, // physical pixels per logical pixel
+  pub pixel_snap: bool, // views from whole pixels; the engine sets it under a pixel-art `ViewportScaling`
+}
+
+impl Camera2D {
+  pub fn new(position: Vec2) -> Self {
+    Camera2D { position, zoom: 1.0, rotation: 0.0, follow: None, viewport: Vec2::ONE, scale_factor: 1.0, pixel_snap: false }
+  }
+
+  pub fn translate(&mut self, offset: Vec2) -> &mut Self {
+    self.position += offset;
+    self
+  }
+
+  // Multiplies the zoom, so `zoom_by(1.1)` each frame zooms in steadily.
+  pub fn zoom_by(&mut self, factor: f32) -> &mut Self {
+    self.zoom = (self.zoom * factor).max(f32::EPSILON);
+    self
+  }
+
+  // Zooms while keeping the world point under `screen` where it is, for zooming towards the mouse.
+  pub fn zoom_at(&mut self, factor: f32, screen: Vec2) -> &mut Self {
+    let before = self.screen_to_world(screen);
+    self.zoom_by(factor);
+    self.position += before - self.screen_to_world(screen);
+    self
+  }
+
+  pub fn follow(&mut self, follow: CameraFollow) -> &mut Self {
+    self.follow = Some(follow);
+    self
+  }
+
+  // Pixels of the viewport per world unit.
+  pub fn pixels_per_unit(&self) -> f32 {
+    self.scale_factor * self.zoom
+  }
+
+  // How much of the world is visible, in world units.
+  pub fn visible_size(&self) -> Vec2 {
+    self.viewport / self.pixels_per_unit()
+  }
+
+  // The visible area's corners as a min and max, enclosing it when the camera is rotated.
+  pub fn visible_bounds(&self) -> (Vec2, Vec2) {
+    let half = self.visible_size() * 0.5;
+    let rotation = Vec2::from_angle(self.rotation);
+    let extent = rotation.rotate(half).abs().max(rotation.rotate(Vec2::new(half.x, -half.y)).abs());
+    (self.position - extent, self.position + extent)
+  }
+
+  // Only the view snaps, so following and zooming still move `position` smoothly underneath.
+  pub fn view(&self) -> Mat4 {
+    let position = if self.pixel_snap { snap_to_pixel(self.position, self.pixels_per_unit()) } else { self.position };
+    Mat4::from_rotation_z(-self.rotation) * Mat4::from_translation(-position.extend(0.0))
+  }
+
+  pub fn projection(&self) -> Mat4 {
+    let half = self.visible_size() * 0.5;
+    Mat4::orthographic_rh(-half.x, half.x, half.y, -half.y, -1.0, 1.0)
+  }
+
+  pub fn view_projection(&self) -> Mat4 {
+    self.projection() * self.view()
+  }
+
+  // The world point under a point in the window, in physical pixels with the origin top-left like
+  // `Input::mouse_position` (or `Input::virtual_mouse_position` under a `ViewportScaling`).
+  pub fn screen_to_world(&self, screen: Vec2) -> Vec2 {
+    let offset = (screen - self.viewport * 0.5) / (self.scale_factor * self.zoom);
+    self.position + Vec2::from_angle(self.rotation).rotate(offset)
+  }
+
+  // Where a world point appears in the window, in physical pixels with the origin top-left.
+  pub fn world_to_screen(&self, world: Vec2) -> Vec2 {
+    let offset = Vec2::from_angle(-self.rotation).rotate(world - self.position);
+    self.viewport * 0.5 + offset * self.scale_factor * self.zoom
+  }
+
+  // Eases towards the follow target, if it's still alive and has a `Transform`.
+  pub fn update_follow(&mut self, world: &World, dt: f32) {
+    let follow = match self.follow {
+      Some(follow) => follow,
+      None => return,
+    };
+    let target = match world.get::<Transform>(follow.target) {
+      Some(transform) => transform.translation.truncate() + follow.offset,
+      None => return,
+    };
+    // Frame-rate independent: the same fraction of the gap closes every second.
+    let t = 1.0 - (-follow.smoothing * dt).exp();
+    self.position = self.position.lerp(target, if t.is_nan() { 1.0 } else { t });
+  }
+}
+
+impl Default for Camera2D {
+  fn default() -> Self {
+    Camera2D::new(Vec2::ZERO)
+  }
+}
+
+// Keeps the world's `Camera2D`, if it has one, in step with the window and its follow target.
+pub fn update_camera_2d(world: &World, viewport: Vec2, scale_factor: f32, dt: f32) {
+  if let Some(mut camera) = world.get_resource_mut::<Camera2D>() {
+    camera.viewport = viewport.max(Vec2::ONE);
+    camera.scale_factor = scale_factor;
+    camera.pixel_snap = world.get_resource::<ViewportScaling>().is_some_and(|scaling| scaling.snaps_to_pixels());
+    camera.update_follow(world, dt);
+  }
+}
@@ -0,0 +1,89 @@
+use bytemuck::{Pod, Zeroable};
+use serde::{Deserialize, Serialize};
+
+// Linear RGBA color with components in 0..1.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Pod, Zeroable, Serialize, Deserialize)]
+pub struct Color {
+  pub r: f32,
+  pub g: f32,
+  pub b: f32,
+  pub a: f32,
+}
+
+impl Color {
+  pub const WHITE: Color = Color::rgb(1.0, 1.0, 1.0);
+  pub const BLACK: Color = Color::rgb(0.0, 0.0, 0.0);
+  pub const RED: Color = Color::rgb(1.0, 0.0, 0.0);
+  pub const GREEN: Color = Color::rgb(0.0, 1.0, 0.0);
+  pub const BLUE: Color = Color::rgb(0.0, 0.0, 1.0);
+  pub const YELLOW: Color = Color::rgb(1.0, 1.0, 0.0);
+  pub const CYAN: Color = Color::rgb(0.0, 1.0, 1.0);
+  pub const MAGENTA: Color = Color::rgb(1.0, 0.0, 1.0);
+  pub const TRANSPARENT: Color = Color::rgba(0.0, 0.0, 0.0, 0.0);
+
+  pub const fn rgb(r: f32, g: f32, b: f32) -> Self {
+    Color { r, g, b, a: 1.0 }
+  }
+
+  pub const fn rgba(r: f32, g: f32, b: f32, a: f32) -> Self {
+    Color { r, g, b, a }
+  }
+
+  // Hue in degrees, saturation/lightness/alpha in 0..1.
+  pub fn hsla(hue: f32, saturation: f32, lightness: f32, alpha: f32) -> Self {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let hue = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+      0 => (chroma, x, 0.0),
+      1 => (x, chroma, 0.0),
+      2 => (0.0, chroma, x),
+      3 => (0.0, x, chroma),
+      4 => (x, 0.0, chroma),
+      _ => (chroma, 0.0, x),
+    };
+    let m = lightness - chroma / 2.0;
+    Color::rgba(r + m, g + m, b + m, alpha)
+  }
+
+  // `#rrggbb` or `#rrggbbaa`, taken as already linear.
+  pub fn from_hex(hex: &str) -> Option<Self> {
+    let hex = hex.strip_prefix('#')?;
+    if !matches!(hex.len(), 6 | 8) || !hex.is_ascii() {
+      return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok().map(|value| value as f32 / 255.0);
+    let alpha = if hex.len() == 8 { channel(3)? } else { 1.0 };
+    Some(Color::rgba(channel(0)?, channel(1)?, channel(2)?, alpha))
+  }
+
+  pub fn with_alpha(self, a: f32) -> Self {
+    Color { a, ..self }
+  }
+
+  pub fn lerp(self, other: Color, t: f32) -> Self {
+    Color::rgba(
+      self.r + (other.r - self.r) * t,
+      self.g + (other.g - self.g) * t,
+      self.b + (other.b - self.b) * t,
+      self.a + (other.a - self.a) * t,
+    )
+  }
+
+  pub fn to_array(self) -> [f32; 4] {
+    [self.r, self.g, self.b, self.a]
+  }
+}
+
+impl Default for Color {
+  fn default() -> Self {
+    Color::WHITE
+  }
+}
+
+impl From<Color> for wgpu::Color {
+  fn from(color: Color) -> Self {
+    wgpu::Color { r: color.r as f64, g: color.g as f64, b: color.b as f64, a: color.a as f64 }
+  }
+}
@@ -0,0 +1,379 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use bytemuck::Pod;
+use glam::UVec3;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindingResource, Buffer, BufferAddress, BufferDescriptor, BufferUsages, CommandEncoder, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor, Device, FilterMode, Maintain, MapMode, Sampler, SamplerDescriptor};
+
+use super::bind_group_cache::ResourceId;
+use super::debug_markers::DebugScope;
+use super::shaders::build_checked;
+use super::texture::{GpuTextures, TextureHandle};
+use super::upload::UploadQueue;
+
+// A compute shader made with `Compute::create_compute`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ComputeTask(u32);
+
+// A GPU buffer made with `Compute::create_buffer`, usable as a storage or uniform buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ComputeBuffer(u32);
+
+// What a task's shader sees at one of its bindings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ComputeBinding {
+  Buffer(ComputeBuffer),
+  Texture(TextureHandle), // one of the world's `TextureManager` textures, to sample or load from
+  Sampler, // linear filtering, clamped to the edge
+}
+
+pub(crate) enum ComputeCommand {
+  CreateTask { task: ComputeTask, label: String, source: String },
+  RemoveTask(ComputeTask),
+  CreateBuffer { buffer: ComputeBuffer, size: BufferAddress, contents: Option<Vec<u8>> },
+  WriteBuffer { buffer: ComputeBuffer, offset: BufferAddress, bytes: Vec<u8> },
+  RemoveBuffer(ComputeBuffer),
+  Bind { task: ComputeTask, group: u32, binding: u32, resource: ComputeBinding },
+  Dispatch { task: ComputeTask, workgroups: UVec3 },
+  Read(ComputeBuffer),
+}
+
+// Compute shaders for game code, as a world resource: GPU simulations like particles, erosion or
+// boids. Everything here is a request the renderer carries out in order at the start of the next
+// frame, before anything's drawn, so a frame's draws see that frame's dispatches.
+//
+// Shaders are WGSL with their entry point at `main`; their bind group layouts come from the shader,
+// so any `@group`/`@binding` works as long as something's bound there before the first dispatch.
+// Compute needs storage buffers, which WebGL doesn't have: there every task fails.
+#[derive(Default)]
+pub struct Compute {
+  next: u32,
+  commands: Vec<ComputeCommand>,
+  sizes: HashMap<ComputeBuffer, BufferAddress>,
+  reads: HashMap<ComputeBuffer, Vec<u8>>, // finished reads, not yet taken
+  errors: HashMap<ComputeTask, String>,
+}
+
+impl Compute {
+  pub fn new() -> Self {
+    Compute::default()
+  }
+
+  fn next_id(&mut self) -> u32 {
+    self.next += 1;
+    self.next
+  }
+
+  // Compiles `source` on the GPU. A shader that doesn't compile is reported through `error`.
+  pub fn create_compute(&mut self, label: &str, source: &str) -> ComputeTask {
+    let task = ComputeTask(self.next_id());
+    self.commands.push(ComputeCommand::CreateTask { task, label: label.to_string(), source: source.to_string() });
+    task
+  }
+
+  pub fn remove_task(&mut self, task: ComputeTask) {
+    self.errors.remove(&task);
+    self.commands.push(ComputeCommand::RemoveTask(task));
+  }
+
+  // A buffer holding `data`.
+  pub fn create_buffer<T: Pod>(&mut self, data: &[T]) -> ComputeBuffer {
+    let bytes = bytemuck::cast_slice(data).to_vec();
+    self.add_buffer(bytes.len() as BufferAddress, Some(bytes))
+  }
+
+  // A buffer of `size` zeroed bytes.
+  pub fn create_buffer_zeroed(&mut self, size: BufferAddress) -> ComputeBuffer {
+    self.add_buffer(size, None)
+  }
+
+  fn add_buffer(&mut self, size: BufferAddress, contents: Option<Vec<u8>>) -> ComputeBuffer {
+    // Copies and mapping need sizes in 4-byte steps, and bindings can't be empty.
+    let size = size.max(4).next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT);
+    let contents = contents.map(|mut bytes| {
+      bytes.resize(size as usize, 0);
+      bytes
+    });
+    let buffer = ComputeBuffer(self.next_id());
+    self.sizes.insert(buffer, size);
+    self.commands.push(ComputeCommand::CreateBuffer { buffer, size, contents });
+    buffer
+  }
+
+  pub fn buffer_size(&self, buffer: ComputeBuffer) -> Option<BufferAddress> {
+    self.sizes.get(&buffer).copied()
+  }
+
+  // Overwrites part of `buffer` from `offset` bytes in, between the dispatches before and after.
+  pub fn write_buffer<T: Pod>(&mut self, buffer: ComputeBuffer, offset: BufferAddress, data: &[T]) {
+    self.commands.push(ComputeCommand::WriteBuffer { buffer, offset, bytes: bytemuck::cast_slice(data).to_vec() });
+  }
+
+  pub fn remove_buffer(&mut self, buffer: ComputeBuffer) {
+    self.sizes.remove(&buffer);
+    self.reads.remove(&buffer);
+    self.commands.push(ComputeCommand::RemoveBuffer(buffer));
+  }
+
+  // Binds `resource` at `@group(group) @binding(binding)` in `task`'s shader, for its later dispatches.
+  pub fn bind(&mut self, task: ComputeTask, group: u32, binding: u32, resource: ComputeBinding) {
+    self.commands.push(ComputeCommand::Bind { task, group, binding, resource });
+  }
+
+  // Runs `task` over `workgroups` groups of its `@workgroup_size`.
+  pub fn dispatch(&mut self, task: ComputeTask, workgroups: UVec3) {
+    self.commands.push(ComputeCommand::Dispatch { task, workgroups });
+  }
+
+  // Copies `buffer` back once the dispatches before this are done. It's usually ready for
+  // `take_read` a frame or two later.
+  pub fn read_buffer(&mut self, buffer: ComputeBuffer) {
+    self.commands.push(ComputeCommand::Read(buffer));
+  }
+
+  // The latest finished `read_buffer` of `buffer`, if there's one not yet taken.
+  pub fn take_read<T: Pod>(&mut self, buffer: ComputeBuffer) -> Option<Vec<T>> {
+    self.reads.remove(&buffer).map(|bytes| bytemuck::pod_collect_to_vec(&bytes))
+  }
+
+  // Why `task` can't run, if it can't.
+  pub fn error(&self, task: ComputeTask) -> Option<&str> {
+    self.errors.get(&task).map(|err| err.as_str())
+  }
+
+  pub(crate) fn take_commands(&mut self) -> Vec<ComputeCommand> {
+    std::mem::take(&mut self.commands)
+  }
+}
+
+struct GpuTask {
+  label: String,
+  pipeline: Option<ComputePipeline>, // `None` if the shader didn't build
+  bindings: HashMap<(u32, u32), ComputeBinding>,
+  bind_groups: Option<(Vec<ResourceId>, Vec<BindGroup>)>, // built for the texture ids bound, until a binding changes
+}
+
+// A buffer copy on its way back from the GPU.
+struct PendingRead {
+  buffer: ComputeBuffer,
+  staging: Buffer,
+  mapped: Arc<Mutex<Option<bool>>>, // whether mapping succeeded, once it's done
+}
+
+// The GPU side of the world's `Compute`. `prepare` takes its requests; `dispatch` records the frame's
+// share of them ahead of the frame's draws.
+pub struct ComputeRenderer {
+  supported: bool,
+  tasks: HashMap<ComputeTask, GpuTask>,
+  buffers: HashMap<ComputeBuffer, Buffer>,
+  frame: Vec<ComputeCommand>, // dispatches, writes and reads to record this frame, in order
+  copying: Vec<(ComputeBuffer, Buffer)>, // read copies recorded this frame, to map once submitted
+  pending: Vec<PendingRead>,
+  sampler: Sampler,
+  dispatches: u32, // in the last recorded frame
+}
+
+impl ComputeRenderer {
+  pub fn new(device: &Device) -> Self {
+    let sampler = device.create_sampler(&SamplerDescriptor {
+      label: Some("compute-sampler"),
+      mag_filter: FilterMode::Linear,
+      min_filter: FilterMode::Linear,
+      ..SamplerDescriptor::default()
+    });
+    ComputeRenderer {
+      supported: device.limits().max_storage_buffers_per_shader_stage > 0,
+      tasks: HashMap::new(),
+      buffers: HashMap::new(),
+      frame: Vec::new(),
+      copying: Vec::new(),
+      pending: Vec::new(),
+      sampler,
+      dispatches: 0,
+    }
+  }
+
+  // Carries out `compute`'s new requests, leaving dispatches, writes and reads for `dispatch`, and
+  // hands back reads that have finished.
+  pub fn prepare(&mut self, device: &Device, compute: &mut Compute) {
+    device.poll(Maintain::Poll);
+    let (finished, pending): (Vec<PendingRead>, Vec<PendingRead>) = std::mem::take(&mut self.pending).into_iter()
+      .partition(|read| read.mapped.lock().unwrap().is_some());
+    self.pending = pending;
+    for read in finished {
+      if *read.mapped.lock().unwrap() == Some(true) {
+        let bytes = read.staging.slice(..).get_mapped_range().to_vec();
+        read.staging.unmap();
+        if compute.sizes.contains_key(&read.buffer) {
+          compute.reads.insert(read.buffer, bytes);
+        }
+      }
+    }
+
+    for command in compute.take_commands() {
+      match command {
+        ComputeCommand::CreateTask { task, label, source } => {
+          let pipeline = match self.supported {
+            true => build_checked(device, &label, Cow::Owned(source), |module| device.create_compute_pipeline(&ComputePipelineDescriptor {
+              label: Some(&label),
+              layout: None,
+              module,
+              entry_point: "main"
+            })),
+            false => Err("compute shaders aren't supported here".to_string()),
+          };
+          let pipeline = match pipeline {
+            Ok(pipeline) => Some(pipeline),
+            Err(err) => {
+              log::error!("{}", err);
+              compute.errors.insert(task, err);
+              None
+            }
+          };
+          self.tasks.insert(task, GpuTask { label, pipeline, bindings: HashMap::new(), bind_groups: None });
+        }
+        ComputeCommand::RemoveTask(task) => {
+          self.tasks.remove(&task);
+        }
+        ComputeCommand::CreateBuffer { buffer, size, contents } => {
+          let usage = BufferUsages::STORAGE | BufferUsages::UNIFORM | BufferUsages::VERTEX | BufferUsages::COPY_SRC | BufferUsages::COPY_DST;
+          let created = match contents {
+            Some(contents) => device.create_buffer_init(&BufferInitDescriptor { label: Some("compute-buffer"), contents: &contents, usage }),
+            None => device.create_buffer(&BufferDescriptor { label: Some("compute-buffer"), size, usage, mapped_at_creation: false }),
+          };
+          self.buffers.insert(buffer, created);
+        }
+        ComputeCommand::RemoveBuffer(buffer) => {
+          self.buffers.remove(&buffer);
+          for task in self.tasks.values_mut() {
+            task.bindings.retain(|_, bound| *bound != ComputeBinding::Buffer(buffer));
+            task.bind_groups = None;
+          }
+        }
+        ComputeCommand::Bind { task, group, binding, resource } => {
+          if let Some(task) = self.tasks.get_mut(&task) {
+            task.bindings.insert((group, binding), resource);
+            task.bind_groups = None;
+          }
+        }
+        command => self.frame.push(command),
+      }
+    }
+  }
+
+  // Builds `task`'s bind groups if they're missing or a bound texture has been recreated. False if
+  // something it needs isn't there (yet).
+  fn bind(&mut self, device: &Device, textures: &GpuTextures, task: ComputeTask) -> bool {
+    let Some(gpu_task) = self.tasks.get_mut(&task) else { return false };
+    let Some(pipeline) = &gpu_task.pipeline else { return false };
+    let texture_ids: Vec<ResourceId> = gpu_task.bindings.values()
+      .filter_map(|resource| match resource {
+        ComputeBinding::Texture(handle) => Some(textures.get(*handle).map(|texture| texture.id)),
+        _ => None,
+      })
+      .collect::<Option<_>>()
+      .unwrap_or_default();
+    if gpu_task.bind_groups.as_ref().is_some_and(|(ids, _)| *ids == texture_ids) {
+      return true;
+    }
+
+    let groups = gpu_task.bindings.keys().map(|(group, _)| group + 1).max().unwrap_or(0);
+    let mut bind_groups = Vec::new();
+    for group in 0..groups {
+      let mut entries = Vec::new();
+      let mut bound: Vec<(&u32, &ComputeBinding)> = gpu_task.bindings.iter()
+        .filter(|((bound_group, _), _)| *bound_group == group)
+        .map(|((_, binding), resource)| (binding, resource))
+        .collect();
+      bound.sort_by_key(|(binding, _)| **binding);
+      for (binding, resource) in bound {
+        let resource = match resource {
+          ComputeBinding::Buffer(buffer) => match self.buffers.get(buffer) {
+            Some(buffer) => buffer.as_entire_binding(),
+            None => return false,
+          },
+          ComputeBinding::Texture(handle) => match textures.get(*handle) {
+            Some(texture) => BindingResource::TextureView(&texture.view),
+            None => return false,
+          },
+          ComputeBinding::Sampler => BindingResource::Sampler(&self.sampler),
+        };
+        entries.push(BindGroupEntry { binding: *binding, resource });
+      }
+      bind_groups.push(device.create_bind_group(&BindGroupDescriptor {
+        label: Some(&gpu_task.label),
+        layout: &pipeline.get_bind_group_layout(group),
+        entries: &entries
+      }));
+    }
+    gpu_task.bind_groups = Some((texture_ids, bind_groups));
+    true
+  }
+
+  // Records this frame's dispatches, buffer writes and read copies, in the order they were asked for.
+  // A task whose bindings aren't all there yet is skipped with a warning.
+  pub fn dispatch(&mut self, device: &Device, encoder: &mut CommandEncoder, uploads: &mut UploadQueue, textures: &GpuTextures) {
+    self.dispatches = 0;
+    for command in std::mem::take(&mut self.frame) {
+      match command {
+        ComputeCommand::Dispatch { task, workgroups } => {
+          if !self.bind(device, textures, task) {
+            log::warn!("skipped a dispatch of compute task {:?}: it didn't build or isn't fully bound", task);
+            continue;
+          }
+          let gpu_task = &self.tasks[&task];
+          let (Some(pipeline), Some((_, bind_groups))) = (&gpu_task.pipeline, &gpu_task.bind_groups) else { continue };
+          let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor { label: Some("compute-pass") });
+          pass.scope(&gpu_task.label, |pass| {
+            pass.set_pipeline(pipeline);
+            for (index, bind_group) in bind_groups.iter().enumerate() {
+              pass.set_bind_group(index as u32, bind_group, &[]);
+            }
+            pass.dispatch_workgroups(workgroups.x, workgroups.y, workgroups.z);
+          });
+          self.dispatches += 1;
+        }
+        // Copied in this encoder so it lands between the dispatches around it, which
+        // `queue.write_buffer` wouldn't.
+        ComputeCommand::WriteBuffer { buffer, offset, bytes } => {
+          if let Some(target) = self.buffers.get(&buffer) {
+            uploads.write_buffer_in(device, encoder, target, offset, &bytes);
+          }
+        }
+        ComputeCommand::Read(buffer) => {
+          if let Some(source) = self.buffers.get(&buffer) {
+            let staging = device.create_buffer(&BufferDescriptor {
+              label: Some("compute-read"),
+              size: source.size(),
+              usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+              mapped_at_creation: false
+            });
+            encoder.copy_buffer_to_buffer(source, 0, &staging, 0, source.size());
+            self.copying.push((buffer, staging));
+          }
+        }
+        _ => {}
+      }
+    }
+  }
+
+  // Starts mapping this frame's read copies. Call once the frame's been submitted.
+  pub fn after_submit(&mut self) {
+    for (buffer, staging) in self.copying.drain(..) {
+      let mapped = Arc::new(Mutex::new(None));
+      let done = mapped.clone();
+      staging.slice(..).map_async(MapMode::Read, move |result| *done.lock().unwrap() = Some(result.is_ok()));
+      self.pending.push(PendingRead { buffer, staging, mapped });
+    }
+  }
+
+  // The underlying buffer, e.g. to draw from in a custom pass.
+  pub fn buffer(&self, buffer: ComputeBuffer) -> Option<&Buffer> {
+    self.buffers.get(&buffer)
+  }
+
+  pub fn draw_calls(&self) -> u32 {
+    self.dispatches
+  }
+}
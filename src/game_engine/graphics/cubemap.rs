@@ -0,0 +1,199 @@
+use std::f32::consts::PI;
+use std::path::Path;
+use glam::{Vec2, Vec3, Vec4};
+use image::DynamicImage;
+
+use crate::game_engine::assets::Asset;
+
+// The faces of a cube texture in the order the GPU layers them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CubeFace {
+  PositiveX,
+  NegativeX,
+  PositiveY,
+  NegativeY,
+  PositiveZ,
+  NegativeZ,
+}
+
+impl CubeFace {
+  pub const ALL: [CubeFace; 6] = [CubeFace::PositiveX, CubeFace::NegativeX, CubeFace::PositiveY, CubeFace::NegativeY, CubeFace::PositiveZ, CubeFace::NegativeZ];
+
+  // The direction through a point on the face, with `uv` from -1 to 1 left to right and top to
+  // bottom as the face's image is stored.
+  pub fn direction(self, uv: Vec2) -> Vec3 {
+    let (u, v) = (uv.x, uv.y);
+    match self {
+      CubeFace::PositiveX => Vec3::new(1.0, -v, -u),
+      CubeFace::NegativeX => Vec3::new(-1.0, -v, u),
+      CubeFace::PositiveY => Vec3::new(u, 1.0, v),
+      CubeFace::NegativeY => Vec3::new(u, -1.0, -v),
+      CubeFace::PositiveZ => Vec3::new(u, -v, 1.0),
+      CubeFace::NegativeZ => Vec3::new(-u, -v, -1.0),
+    }
+  }
+
+  // Which face `direction` goes through and where, the inverse of `direction`.
+  pub fn of(direction: Vec3) -> (CubeFace, Vec2) {
+    let abs = direction.abs();
+    let (face, major) = if abs.x >= abs.y && abs.x >= abs.z {
+      (if direction.x > 0.0 { CubeFace::PositiveX } else { CubeFace::NegativeX }, abs.x)
+    } else if abs.y >= abs.z {
+      (if direction.y > 0.0 { CubeFace::PositiveY } else { CubeFace::NegativeY }, abs.y)
+    } else {
+      (if direction.z > 0.0 { CubeFace::PositiveZ } else { CubeFace::NegativeZ }, abs.z)
+    };
+    let d = direction / major.max(f32::EPSILON);
+    let uv = match face {
+      CubeFace::PositiveX => Vec2::new(-d.z, -d.y),
+      CubeFace::NegativeX => Vec2::new(d.z, -d.y),
+      CubeFace::PositiveY => Vec2::new(d.x, d.z),
+      CubeFace::NegativeY => Vec2::new(d.x, -d.z),
+      CubeFace::PositiveZ => Vec2::new(d.x, -d.y),
+      CubeFace::NegativeZ => Vec2::new(-d.x, -d.y),
+    };
+    (face, uv)
+  }
+}
+
+// A cube of six square images seen from the inside, for skies and environment lighting. Texels are
+// linear RGBA floats, so HDR environments keep light brighter than white.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cubemap {
+  pub size: u32, // of each face, in texels
+  pub faces: [Vec<Vec4>; 6], // in `CubeFace::ALL` order, rows top to bottom
+}
+
+impl Cubemap {
+  // One colour all round, e.g. a plain backdrop or a stand-in while the real sky loads.
+  pub fn solid(color: Vec4) -> Self {
+    Cubemap { size: 1, faces: std::array::from_fn(|_| vec![color]) }
+  }
+
+  // Six encoded square images (PNG, JPEG or Radiance HDR), in `CubeFace::ALL` order.
+  pub fn from_faces(faces: [&[u8]; 6]) -> Result<Self, String> {
+    let mut size = None;
+    let mut decoded = Vec::with_capacity(6);
+    for (bytes, face) in faces.iter().zip(CubeFace::ALL) {
+      let image = image::load_from_memory(bytes).map_err(|err| format!("couldn't decode {:?} face: {}", face, err))?;
+      if image.width() != image.height() || size.is_some_and(|size| size != image.width()) {
+        return Err(format!("{:?} face is {}x{}; every face must be the same square size", face, image.width(), image.height()));
+      }
+      size = Some(image.width());
+      decoded.push(linear_pixels(image));
+    }
+    let mut faces = decoded.into_iter();
+    Ok(Cubemap { size: size.unwrap_or(1), faces: std::array::from_fn(|_| faces.next().unwrap()) })
+  }
+
+  pub fn load_faces<P: AsRef<Path>>(paths: [P; 6]) -> Result<Self, String> {
+    let bytes: Vec<Vec<u8>> = paths.iter()
+      .map(|path| std::fs::read(path).map_err(|err| format!("couldn't read {}: {}", path.as_ref().display(), err)))
+      .collect::<Result<_, _>>()?;
+    Cubemap::from_faces(std::array::from_fn(|face| bytes[face].as_slice()))
+  }
+
+  // Resamples an equirectangular (latitude-longitude) panorama into faces of `size` texels, the
+  // usual layout for HDR skies. The middle of the image ends up straight ahead, down -z.
+  pub fn from_equirectangular(bytes: &[u8], size: u32) -> Result<Self, String> {
+    let image = image::load_from_memory(bytes).map_err(|err| format!("couldn't decode panorama: {}", err))?;
+    Ok(Cubemap::from_panorama(image, size))
+  }
+
+  fn from_panorama(image: DynamicImage, size: u32) -> Self {
+    let (width, height) = (image.width(), image.height());
+    let pixels = linear_pixels(image);
+    let texel = |x: i64, y: i64| pixels[(y.clamp(0, height as i64 - 1) as u32 * width + x.rem_euclid(width as i64) as u32) as usize];
+    let size = size.max(1);
+    let faces = CubeFace::ALL.map(|face| {
+      let mut texels = Vec::with_capacity((size * size) as usize);
+      for y in 0..size {
+        for x in 0..size {
+          let uv = (Vec2::new(x as f32, y as f32) + 0.5) / size as f32 * 2.0 - 1.0;
+          let direction = face.direction(uv).normalize();
+          let longitude = direction.x.atan2(-direction.z);
+          let latitude = direction.y.clamp(-1.0, 1.0).acos();
+          let source = Vec2::new((longitude / (2.0 * PI) + 0.5) * width as f32, latitude / PI * height as f32) - 0.5;
+          // Bilinear, wrapping round horizontally.
+          let base = source.floor();
+          let t = source - base;
+          let (x0, y0) = (base.x as i64, base.y as i64);
+          let top = texel(x0, y0).lerp(texel(x0 + 1, y0), t.x);
+          let bottom = texel(x0, y0 + 1).lerp(texel(x0 + 1, y0 + 1), t.x);
+          texels.push(top.lerp(bottom, t.y));
+        }
+      }
+      texels
+    });
+    Cubemap { size, faces }
+  }
+
+  pub fn load_equirectangular(path: impl AsRef<Path>, size: u32) -> Result<Self, String> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path).map_err(|err| format!("couldn't read {}: {}", path.display(), err))?;
+    Cubemap::from_equirectangular(&bytes, size).map_err(|err| format!("{}: {}", path.display(), err))
+  }
+
+  // The texel `direction` points at, without filtering. Enough for working out ambient light on the
+  // CPU.
+  pub fn sample(&self, direction: Vec3) -> Vec4 {
+    let (face, uv) = CubeFace::of(direction);
+    let texel = ((uv * 0.5 + 0.5) * self.size as f32).floor().as_uvec2().min(glam::UVec2::splat(self.size - 1));
+    self.faces[face as usize][(texel.y * self.size + texel.x) as usize]
+  }
+
+  // The faces as `Rgba16Float` texels, one after another, for uploading.
+  pub(crate) fn to_half_floats(&self) -> Vec<u16> {
+    self.faces.iter()
+      .flat_map(|face| face.iter().flat_map(|texel| texel.to_array()))
+      .map(f16_bits)
+      .collect()
+  }
+}
+
+// An equirectangular panorama, with faces half its height across.
+impl Asset for Cubemap {
+  fn from_bytes(bytes: &[u8], _path: &Path) -> Result<Self, String> {
+    let image = image::load_from_memory(bytes).map_err(|err| format!("couldn't decode panorama: {}", err))?;
+    let size = (image.height() / 2).max(1);
+    Ok(Cubemap::from_panorama(image, size))
+  }
+}
+
+// HDR formats decode to linear floats already; everything else is sRGB.
+fn linear_pixels(image: DynamicImage) -> Vec<Vec4> {
+  let linear = matches!(image, DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_));
+  image.into_rgba32f().pixels()
+    .map(|pixel| {
+      let [r, g, b, a] = pixel.0;
+      if linear {
+        Vec4::new(r, g, b, a)
+      } else {
+        Vec4::new(srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a)
+      }
+    })
+    .collect()
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+  if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+}
+
+// Rounds towards zero and clamps to the largest finite half, which is plenty for a sky.
+fn f16_bits(value: f32) -> u16 {
+  if value.is_nan() {
+    return 0x7e00;
+  }
+  let bits = value.clamp(-65504.0, 65504.0).to_bits();
+  let sign = ((bits >> 16) & 0x8000) as u16;
+  let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+  let mantissa = bits & 0x7f_ffff;
+  if exponent <= 0 {
+    // Too small for a normal half: subnormal, or zero.
+    if exponent < -10 {
+      return sign;
+    }
+    return sign | ((mantissa | 0x80_0000) >> (14 - exponent)) as u16;
+  }
+  sign | ((exponent as u16) << 10) | (mantissa >> 13) as u16
+}
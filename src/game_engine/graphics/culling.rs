@@ -0,0 +1,375 @@
+use std::collections::HashSet;
+use glam::{Mat4, UVec2, Vec2, Vec3, Vec3Swizzles, Vec4, Vec4Swizzles};
+
+use crate::game_engine::ecs::{Entity, Transform, World};
+use crate::game_engine::jobs::Jobs;
+use crate::game_engine::time::Interpolation;
+use super::color::Color;
+use super::debug_draw::DebugDraw;
+use super::mesh::Mesh;
+
+// An entity's bounding box in its local space, for culling.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+  pub min: Vec3,
+  pub max: Vec3,
+}
+
+impl Bounds {
+  pub fn new(min: Vec3, max: Vec3) -> Self {
+    Bounds { min, max }
+  }
+
+  pub fn from_mesh(mesh: &Mesh) -> Option<Self> {
+    mesh.bounds().map(|(min, max)| Bounds { min, max })
+  }
+
+  fn corners(&self) -> [Vec3; 8] {
+    let (min, max) = (self.min, self.max);
+    [
+      Vec3::new(min.x, min.y, min.z), Vec3::new(max.x, min.y, min.z),
+      Vec3::new(min.x, max.y, min.z), Vec3::new(max.x, max.y, min.z),
+      Vec3::new(min.x, min.y, max.z), Vec3::new(max.x, min.y, max.z),
+      Vec3::new(min.x, max.y, max.z), Vec3::new(max.x, max.y, max.z),
+    ]
+  }
+}
+
+// Marks a large solid entity (wall, building, terrain hill) that hides what's behind it. The box
+// is in local space and must lie entirely inside the visible geometry, or things peeking around
+// the real shape will be culled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Occluder {
+  pub min: Vec3,
+  pub max: Vec3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+  planes: [Vec4; 6], // (normal, distance), normals pointing inwards
+}
+
+impl Frustum {
+  // Extracts the planes of a wgpu-style (0..1 depth) view-projection matrix.
+  pub fn from_view_projection(view_projection: Mat4) -> Self {
+    let (x, y, z, w) = (view_projection.row(0), view_projection.row(1), view_projection.row(2), view_projection.row(3));
+    let planes = [w + x, w - x, w + y, w - y, z, w - z]
+      .map(|plane| plane / plane.xyz().length().max(f32::EPSILON));
+    Frustum { planes }
+  }
+
+  // Whether any of the world-space box might be inside.
+  pub fn intersects_aabb(&self, min: Vec3, max: Vec3) -> bool {
+    self.planes.iter().all(|plane| {
+      // The corner furthest along the plane's normal.
+      let corner = Vec3::select(plane.xyz().cmpge(Vec3::ZERO), max, min);
+      plane.xyz().dot(corner) + plane.w >= 0.0
+    })
+  }
+}
+
+// A small CPU depth buffer of the occluders' far depths, with a max-reduced mip chain, so a box
+// can be tested against a few texels whatever its size on screen.
+pub struct HiZBuffer {
+  size: UVec2,
+  levels: Vec<(UVec2, Vec<f32>)>, // level 0 is full size
+}
+
+impl HiZBuffer {
+  pub fn new(size: UVec2) -> Self {
+    let mut buffer = HiZBuffer { size: size.max(UVec2::ONE), levels: Vec::new() };
+    buffer.clear();
+    buffer
+  }
+
+  pub fn clear(&mut self) {
+    self.levels.clear();
+    self.levels.push((self.size, vec![1.0; (self.size.x * self.size.y) as usize]));
+  }
+
+  // Projects a clip-space point to (pixel x, pixel y, depth), or `None` if it's behind the eye.
+  fn project(&self, clip: Vec4) -> Option<Vec3> {
+    if clip.w <= 1e-4 {
+      return None;
+    }
+    let ndc = clip.xyz() / clip.w;
+    let size = self.size.as_vec2();
+    Some(Vec3::new((ndc.x * 0.5 + 0.5) * size.x, (0.5 - ndc.y * 0.5) * size.y, ndc.z))
+  }
+
+  // Draws a box's faces into level 0. Each triangle writes its farthest depth, which keeps the
+  // buffer conservative. Boxes crossing the near plane are skipped.
+  pub fn rasterize_box(&mut self, view_projection: Mat4, model: Mat4, min: Vec3, max: Vec3) {
+    let matrix = view_projection * model;
+    let corners = Bounds::new(min, max).corners().map(|corner| self.project(matrix * corner.extend(1.0)));
+    let corners = match corners.iter().copied().collect::<Option<Vec<Vec3>>>() {
+      Some(corners) => corners,
+      None => return,
+    };
+    const FACES: [[usize; 4]; 6] = [[0, 1, 3, 2], [4, 5, 7, 6], [0, 1, 5, 4], [2, 3, 7, 6], [0, 2, 6, 4], [1, 3, 7, 5]];
+    for face in FACES {
+      self.rasterize_triangle(corners[face[0]], corners[face[1]], corners[face[2]]);
+      self.rasterize_triangle(corners[face[0]], corners[face[2]], corners[face[3]]);
+    }
+  }
+
+  fn rasterize_triangle(&mut self, a: Vec3, b: Vec3, c: Vec3) {
+    let area = (b.xy() - a.xy()).perp_dot(c.xy() - a.xy());
+    if area.abs() <= f32::EPSILON {
+      return;
+    }
+    let depth = a.z.max(b.z).max(c.z).clamp(0.0, 1.0);
+    let size = self.size;
+    let min = a.xy().min(b.xy()).min(c.xy()).floor().max(Vec2::ZERO).as_uvec2();
+    let max = a.xy().max(b.xy()).max(c.xy()).ceil().min(size.as_vec2()).as_uvec2();
+    let depths = &mut self.levels[0].1;
+    for y in min.y..max.y {
+      for x in min.x..max.x {
+        let point = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+        let weights = Vec3::new(
+          (c.xy() - b.xy()).perp_dot(point - b.xy()),
+          (a.xy() - c.xy()).perp_dot(point - c.xy()),
+          (b.xy() - a.xy()).perp_dot(point - a.xy()),
+        ) * area.signum();
+        if weights.min_element() >= 0.0 {
+          let texel = &mut depths[(y * size.x + x) as usize];
+          *texel = texel.min(depth);
+        }
+      }
+    }
+  }
+
+  // Rebuilds the mip chain from level 0; call after rasterizing the frame's occluders.
+  pub fn build_mips(&mut self) {
+    self.levels.truncate(1);
+    loop {
+      let (size, depths) = self.levels.last().unwrap();
+      if size.x == 1 && size.y == 1 {
+        break;
+      }
+      let next = UVec2::new(size.x.div_ceil(2), size.y.div_ceil(2));
+      let mut reduced = vec![0.0f32; (next.x * next.y) as usize];
+      for y in 0..size.y {
+        for x in 0..size.x {
+          let texel = &mut reduced[((y / 2) * next.x + x / 2) as usize];
+          *texel = texel.max(depths[(y * size.x + x) as usize]);
+        }
+      }
+      self.levels.push((next, reduced));
+    }
+  }
+
+  // Whether a box might be visible past the occluders. Boxes crossing the near plane always are.
+  pub fn is_visible(&self, view_projection: Mat4, model: Mat4, min: Vec3, max: Vec3) -> bool {
+    let matrix = view_projection * model;
+    let mut rect_min = Vec2::splat(f32::MAX);
+    let mut rect_max = Vec2::splat(f32::MIN);
+    let mut nearest = f32::MAX;
+    for corner in Bounds::new(min, max).corners() {
+      match self.project(matrix * corner.extend(1.0)) {
+        Some(point) => {
+          rect_min = rect_min.min(point.xy());
+          rect_max = rect_max.max(point.xy());
+          nearest = nearest.min(point.z);
+        }
+        None => return true,
+      }
+    }
+
+    let size = self.size.as_vec2();
+    let rect_min = rect_min.max(Vec2::ZERO);
+    let rect_max = rect_max.min(size - Vec2::ONE);
+    if rect_min.x > rect_max.x || rect_min.y > rect_max.y {
+      return true; // off screen; the frustum test decides those
+    }
+
+    // The level where the rectangle covers at most 2x2 texels.
+    let extent = (rect_max - rect_min).max_element().max(1.0);
+    let level = (extent.log2().ceil() as usize).min(self.levels.len() - 1);
+    let (level_size, depths) = &self.levels[level];
+    let scale = (1u32 << level) as f32;
+    let (first, last) = ((rect_min / scale).as_uvec2(), (rect_max / scale).as_uvec2().min(*level_size - UVec2::ONE));
+    (first.y..=last.y).any(|y| (first.x..=last.x).any(|x| nearest <= depths[(y * level_size.x + x) as usize]))
+  }
+}
+
+// What the culling pass does each frame. Insert it as a resource to turn culling on; the engine
+// keeps `view_projection` at the main camera's.
+#[derive(Debug, Clone)]
+pub struct CullingSettings {
+  pub view_projection: Mat4,
+  pub occlusion: bool,
+  pub resolution: UVec2, // of the hierarchical depth buffer; small is fine
+  pub debug: bool, // draw visible (green) and occluded (red) boxes with `DebugDraw`
+}
+
+impl Default for CullingSettings {
+  fn default() -> Self {
+    CullingSettings { view_projection: Mat4::IDENTITY, occlusion: true, resolution: UVec2::new(256, 128), debug: false }
+  }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CullStats {
+  pub total: usize,
+  pub frustum_culled: usize,
+  pub occlusion_culled: usize,
+  pub visible: usize,
+}
+
+// The entities that survived culling this frame, for the renderer.
+#[derive(Debug, Clone, Default)]
+pub struct VisibleEntities(pub Vec<Entity>);
+
+// Culls every entity with `Bounds` and a `Transform`: first against the frustum, then (if enabled)
+// against the occluders' depth. Writes the `VisibleEntities` and `CullStats` resources. The engine
+// runs it every frame before drawing.
+pub fn cull_entities(world: &mut World) {
+  let settings = world.get_resource::<CullingSettings>().map(|settings| settings.clone());
+  let settings = match settings {
+    Some(settings) => settings,
+    None => {
+      // Last frame's would hide things now that culling's off.
+      world.remove_resource::<VisibleEntities>();
+      return;
+    }
+  };
+  let (visible, stats) = cull(world, &settings);
+  world.insert_resource(visible);
+  world.insert_resource(stats);
+}
+
+// Which entities culling left out this frame, for the draws that read them straight from the
+// world. Entities without `Bounds`, and everything while culling's off, are drawn.
+pub(crate) struct CullFilter {
+  visible: Option<HashSet<Entity>>,
+}
+
+impl CullFilter {
+  pub(crate) fn new(world: &World) -> Self {
+    CullFilter { visible: world.get_resource::<VisibleEntities>().map(|visible| visible.0.iter().copied().collect()) }
+  }
+
+  pub(crate) fn is_culled(&self, world: &World, entity: Entity) -> bool {
+    self.visible.as_ref().is_some_and(|visible| !visible.contains(&entity) && world.has::<Bounds>(entity))
+  }
+}
+
+fn cull(world: &World, settings: &CullingSettings) -> (VisibleEntities, CullStats) {
+  let frustum = Frustum::from_view_projection(settings.view_projection);
+  // The tests themselves are spread over the pool when there is one; the world has to be read here.
+  let jobs = world.get_resource::<Jobs>().map(|jobs| jobs.clone());
+  let test = |candidates: &[(Entity, Mat4, Bounds)], visible: &(dyn Fn(&(Entity, Mat4, Bounds)) -> bool + Sync)| match &jobs {
+    Some(jobs) => jobs.map(candidates, visible),
+    None => candidates.iter().map(visible).collect::<Vec<bool>>(),
+  };
+
+  let mut stats = CullStats::default();
+  let mut candidates = Vec::new();
+  let interpolation = Interpolation::new(world);
+  world.query::<(&Bounds, &Transform)>().for_each(|entity, (bounds, transform)| {
+    candidates.push((entity, interpolation.transform(world, entity, transform).matrix(), *bounds));
+  });
+  stats.total = candidates.len();
+  let in_view = test(&candidates, &|(_, model, bounds)| {
+    let corners = bounds.corners().map(|corner| model.transform_point3(corner));
+    let min = corners.iter().fold(Vec3::splat(f32::MAX), |min, corner| min.min(*corner));
+    let max = corners.iter().fold(Vec3::splat(f32::MIN), |max, corner| max.max(*corner));
+    frustum.intersects_aabb(min, max)
+  });
+  let in_frustum: Vec<_> = candidates.into_iter().zip(in_view).filter_map(|(candidate, in_view)| in_view.then_some(candidate)).collect();
+  stats.frustum_culled = stats.total - in_frustum.len();
+
+  let mut visible = Vec::with_capacity(in_frustum.len());
+  let mut occluded = Vec::new();
+  if settings.occlusion {
+    let mut hi_z = HiZBuffer::new(settings.resolution);
+    world.query::<(&Occluder, &Transform)>().for_each(|_, (occluder, transform)| {
+      hi_z.rasterize_box(settings.view_projection, transform.matrix(), occluder.min, occluder.max);
+    });
+    hi_z.build_mips();
+    // An occluder's own box is inside its bounds, so it never hides itself.
+    let unoccluded = test(&in_frustum, &|(_, model, bounds)| hi_z.is_visible(settings.view_projection, *model, bounds.min, bounds.max));
+    for (candidate, unoccluded) in in_frustum.into_iter().zip(unoccluded) {
+      match unoccluded {
+        true => visible.push(candidate),
+        false => occluded.push(candidate),
+      }
+    }
+  } else {
+    visible = in_frustum;
+  }
+  stats.occlusion_culled = occluded.len();
+  stats.visible = visible.len();
+
+  if settings.debug {
+    if let Some(mut debug_draw) = world.get_resource_mut::<DebugDraw>() {
+      for (color, entries) in [(Color::GREEN, &visible), (Color::RED, &occluded)] {
+        for (_, model, bounds) in entries {
+          let corners = bounds.corners().map(|corner| model.transform_point3(corner));
+          for (a, b) in [(0, 1), (1, 3), (3, 2), (2, 0), (4, 5), (5, 7), (7, 6), (6, 4), (0, 4), (1, 5), (2, 6), (3, 7)] {
+            debug_draw.line(corners[a], corners[b], color);
+          }
+        }
+      }
+    }
+  }
+
+  (VisibleEntities(visible.into_iter().map(|(entity, _, _)| entity).collect()), stats)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+  use glam::Vec3;
+
+  use super::*;
+  use crate::game_engine::graphics::instancing::{draw_instanced_entities, InstanceBatch, InstancedMesh};
+  use crate::game_engine::graphics::mesh::MeshBuilder;
+
+  // A camera at the origin looking down -Z.
+  fn settings() -> CullingSettings {
+    let view_projection = Mat4::perspective_rh(1.0, 1.0, 0.1, 100.0) * Mat4::look_at_rh(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y);
+    CullingSettings { view_projection, occlusion: false, ..CullingSettings::default() }
+  }
+
+  fn spawn_box(world: &mut World, mesh: &Arc<Mesh>, z: f32) -> Entity {
+    let entity = world.spawn();
+    world.insert(entity, Transform::from_xyz(0.0, 0.0, z));
+    world.insert(entity, Bounds::new(Vec3::splat(-0.5), Vec3::splat(0.5)));
+    world.insert(entity, InstancedMesh::new(mesh.clone()));
+    entity
+  }
+
+  #[test]
+  fn entities_outside_the_frustum_are_not_drawn() {
+    let mut world = World::new();
+    let mesh = Arc::new(MeshBuilder::cuboid(Vec3::ONE).build());
+    let in_front = spawn_box(&mut world, &mesh, -5.0);
+    spawn_box(&mut world, &mesh, 5.0);
+    world.insert_resource(settings());
+    world.insert_resource(InstanceBatch::new());
+
+    cull_entities(&mut world);
+    assert_eq!(world.resource::<VisibleEntities>().0, [in_front]);
+    assert_eq!(*world.resource::<CullStats>(), CullStats { total: 2, frustum_culled: 1, occlusion_culled: 0, visible: 1 });
+    draw_instanced_entities(&world);
+    assert_eq!(world.resource::<InstanceBatch>().instance_count(), 1);
+  }
+
+  #[test]
+  fn everything_is_drawn_once_culling_is_off() {
+    let mut world = World::new();
+    let mesh = Arc::new(MeshBuilder::cuboid(Vec3::ONE).build());
+    spawn_box(&mut world, &mesh, -5.0);
+    spawn_box(&mut world, &mesh, 5.0);
+    world.insert_resource(settings());
+    world.insert_resource(InstanceBatch::new());
+    cull_entities(&mut world);
+
+    world.remove_resource::<CullingSettings>();
+    cull_entities(&mut world);
+    draw_instanced_entities(&world);
+    assert_eq!(world.resource::<InstanceBatch>().instance_count(), 2);
+  }
+}
@@ -0,0 +1,69 @@
+use glam::{Mat4, UVec2, Vec2};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{BindGroup, Buffer, BufferUsages, Device, Queue, RenderPass, TextureFormat};
+
+use crate::game_engine::window::CursorImage;
+use super::bind_group_cache::ResourceId;
+use super::sprite_batch::{SpriteInstance, SpritePipeline};
+use super::texture::{GpuTextures, TextureHandle};
+
+// Draws `GameWindow::cursor_image` at the mouse, over the UI, since windows can only show the
+// system's own cursor shapes.
+pub struct CursorRenderer {
+  pipeline: SpritePipeline,
+  uniform_buffer: Buffer,
+  uniform_bind_group: BindGroup,
+  texture: Option<(TextureHandle, ResourceId, BindGroup)>,
+  instance_buffer: Buffer,
+  visible: bool,
+}
+
+impl CursorRenderer {
+  pub fn new(device: &Device, format: TextureFormat) -> Self {
+    let pipeline = SpritePipeline::instanced(device, format);
+    let (uniform_buffer, uniform_bind_group) = pipeline.create_uniforms(device);
+    let instance_buffer = device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("cursor-instance"),
+      usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+      contents: bytemuck::bytes_of(&SpriteInstance { position: [0.0; 2], half_size: [0.0; 2], rotation: [0.0, 1.0], uv_min: [0.0; 2], uv_max: [1.0; 2], color: [1.0; 4] })
+    });
+    CursorRenderer { pipeline, uniform_buffer, uniform_bind_group, texture: None, instance_buffer, visible: false }
+  }
+
+  // `mouse` is the cursor's position in window pixels.
+  pub fn prepare(&mut self, device: &Device, queue: &Queue, cursor: Option<CursorImage>, mouse: Vec2, textures: &GpuTextures, window: UVec2) {
+    let Some((cursor, texture)) = cursor.and_then(|cursor| Some((cursor, textures.get(cursor.texture)?))) else {
+      self.visible = false;
+      return;
+    };
+    if !self.texture.as_ref().is_some_and(|(handle, id, _)| *handle == cursor.texture && *id == texture.id) {
+      self.texture = Some((cursor.texture, texture.id, self.pipeline.create_texture_bind_group(device, texture)));
+    }
+    let size = texture.size.as_vec2() * cursor.scale;
+    let instance = SpriteInstance {
+      position: (mouse - cursor.hotspot * cursor.scale + size / 2.0).to_array(),
+      half_size: (size / 2.0).to_array(),
+      rotation: [0.0, 1.0],
+      uv_min: [0.0; 2],
+      uv_max: [1.0; 2],
+      color: [1.0; 4],
+    };
+    let view_projection = Mat4::orthographic_rh(0.0, window.x as f32, window.y as f32, 0.0, -1.0, 1.0);
+    queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&view_projection.to_cols_array()));
+    queue.write_buffer(&self.instance_buffer, 0, bytemuck::bytes_of(&instance));
+    self.visible = true;
+  }
+
+  pub fn draw_calls(&self) -> u32 {
+    self.visible as u32
+  }
+
+  pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+    let Some((_, _, bind_group)) = self.texture.as_ref().filter(|_| self.visible) else { return };
+    render_pass.set_pipeline(&self.pipeline.pipeline);
+    render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+    render_pass.set_bind_group(1, bind_group, &[]);
+    render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+    render_pass.draw(0..6, 0..1);
+  }
+}
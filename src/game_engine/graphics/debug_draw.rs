@@ -0,0 +1,246 @@
+use std::borrow::Cow;
+use std::mem::size_of;
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Quat, Vec3};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BlendState, Buffer, BufferAddress, BufferBindingType, BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites, Device, FragmentState, MultisampleState, PipelineLayoutDescriptor, PrimitiveState, PrimitiveTopology, Queue, RenderPass, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureFormat, VertexAttribute, VertexBufferLayout, VertexFormat, VertexState, VertexStepMode};
+
+use super::color::Color;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct DebugVertex {
+  pub position: [f32; 3],
+  pub color: [f32; 4],
+}
+
+// Immediate-mode debug lines in world space. Lives in the world as a resource: anything can add
+// lines during the frame, and the renderer draws and clears them once per frame.
+pub struct DebugDraw {
+  pub enabled: bool,
+  vertices: Vec<DebugVertex>,
+}
+
+impl DebugDraw {
+  pub fn new() -> Self {
+    DebugDraw { enabled: true, vertices: Vec::new() }
+  }
+
+  pub fn line(&mut self, start: Vec3, end: Vec3, color: Color) {
+    if !self.enabled {
+      return;
+    }
+    self.vertices.push(DebugVertex { position: start.to_array(), color: color.to_array() });
+    self.vertices.push(DebugVertex { position: end.to_array(), color: color.to_array() });
+  }
+
+  pub fn ray(&mut self, origin: Vec3, direction: Vec3, color: Color) {
+    self.line(origin, origin + direction, color);
+  }
+
+  // Three axis-aligned lines crossing at `point`, for marking positions like contact points.
+  pub fn cross(&mut self, point: Vec3, size: f32, color: Color) {
+    let half = size / 2.0;
+    self.line(point - Vec3::X * half, point + Vec3::X * half, color);
+    self.line(point - Vec3::Y * half, point + Vec3::Y * half, color);
+    self.line(point - Vec3::Z * half, point + Vec3::Z * half, color);
+  }
+
+  pub fn aabb(&mut self, min: Vec3, max: Vec3, color: Color) {
+    let corner = |i: usize| Vec3::new(
+      if i & 1 == 0 { min.x } else { max.x },
+      if i & 2 == 0 { min.y } else { max.y },
+      if i & 4 == 0 { min.z } else { max.z },
+    );
+    for (a, b) in [(0, 1), (2, 3), (4, 5), (6, 7), (0, 2), (1, 3), (4, 6), (5, 7), (0, 4), (1, 5), (2, 6), (3, 7)] {
+      self.line(corner(a), corner(b), color);
+    }
+  }
+
+  pub fn circle(&mut self, center: Vec3, normal: Vec3, radius: f32, color: Color) {
+    const SEGMENTS: usize = 24;
+    let rotation = Quat::from_rotation_arc(Vec3::Z, normal.normalize_or_zero());
+    let point = |i: usize| {
+      let angle = i as f32 / SEGMENTS as f32 * std::f32::consts::TAU;
+      center + rotation * Vec3::new(angle.cos() * radius, angle.sin() * radius, 0.0)
+    };
+    for i in 0..SEGMENTS {
+      self.line(point(i), point(i + 1), color);
+    }
+  }
+
+  pub fn sphere(&mut self, center: Vec3, radius: f32, color: Color) {
+    self.circle(center, Vec3::X, radius, color);
+    self.circle(center, Vec3::Y, radius, color);
+    self.circle(center, Vec3::Z, radius, color);
+  }
+
+  pub fn vertices(&self) -> &[DebugVertex] {
+    &self.vertices
+  }
+
+  pub fn clear(&mut self) {
+    self.vertices.clear();
+  }
+}
+
+impl Default for DebugDraw {
+  fn default() -> Self {
+    DebugDraw::new()
+  }
+}
+
+// Draws `DebugDraw` lines as a line list on top of the scene.
+pub struct DebugLineRenderer {
+  pipeline: RenderPipeline,
+  uniform_buffer: Buffer,
+  bind_group: BindGroup,
+  vertex_buffer: Option<Buffer>,
+  vertex_count: u32,
+}
+
+impl DebugLineRenderer {
+  pub fn new(device: &Device, format: TextureFormat) -> Self {
+    let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+      label: Some("debug-line-shader"),
+      source: ShaderSource::Wgsl(Cow::Borrowed(
+"
+struct Uniforms {
+    view_proj: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> uniforms: Uniforms;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>, @location(1) color: vec4<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = uniforms.view_proj * vec4<f32>(position, 1.0);
+    out.color = color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
+"
+      ))
+    });
+
+    let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("debug-line-uniforms"),
+      usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+      contents: bytemuck::cast_slice(&Mat4::IDENTITY.to_cols_array())
+    });
+
+    let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("debug-line-bind-group-layout"),
+      entries: &[BindGroupLayoutEntry {
+        binding: 0,
+        visibility: ShaderStages::VERTEX,
+        ty: BindingType::Buffer {
+          ty: BufferBindingType::Uniform,
+          has_dynamic_offset: false,
+          min_binding_size: None
+        },
+        count: None
+      }]
+    });
+
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+      label: Some("debug-line-bind-group"),
+      layout: &bind_group_layout,
+      entries: &[BindGroupEntry { binding: 0, resource: uniform_buffer.as_entire_binding() }]
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+      label: Some("debug-line-pipeline-layout"),
+      bind_group_layouts: &[&bind_group_layout],
+      push_constant_ranges: &[]
+    });
+
+    let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+      label: Some("debug-line-pipeline"),
+      layout: Some(&pipeline_layout),
+      vertex: VertexState {
+        module: &shader_module,
+        entry_point: "vs_main",
+        buffers: &[VertexBufferLayout {
+          array_stride: size_of::<DebugVertex>() as BufferAddress,
+          step_mode: VertexStepMode::Vertex,
+          attributes: &[
+            VertexAttribute { format: VertexFormat::Float32x3, shader_location: 0, offset: 0 },
+            VertexAttribute {
+              format: VertexFormat::Float32x4,
+              shader_location: 1,
+              offset: size_of::<[f32; 3]>() as BufferAddress
+            }
+          ]
+        }]
+      },
+      fragment: Some(FragmentState {
+        module: &shader_module,
+        entry_point: "fs_main",
+        targets: &[Some(ColorTargetState {
+          format,
+          blend: Some(BlendState::ALPHA_BLENDING),
+          write_mask: ColorWrites::ALL
+        })]
+      }),
+      primitive: PrimitiveState {
+        topology: PrimitiveTopology::LineList,
+        ..PrimitiveState::default()
+      },
+      depth_stencil: None,
+      multisample: MultisampleState::default(),
+      multiview: None
+    });
+
+    DebugLineRenderer { pipeline, uniform_buffer, bind_group, vertex_buffer: None, vertex_count: 0 }
+  }
+
+  pub fn set_view_projection(&self, queue: &Queue, view_projection: Mat4) {
+    queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&view_projection.to_cols_array()));
+  }
+
+  // Uploads this frame's lines, growing the vertex buffer if they don't fit.
+  pub fn prepare(&mut self, device: &Device, queue: &Queue, vertices: &[DebugVertex]) {
+    self.vertex_count = vertices.len() as u32;
+    if vertices.is_empty() {
+      return;
+    }
+
+    let size = std::mem::size_of_val(vertices) as BufferAddress;
+    let too_small = self.vertex_buffer.as_ref().is_none_or(|buffer| buffer.size() < size);
+    if too_small {
+      self.vertex_buffer = Some(device.create_buffer(&BufferDescriptor {
+        label: Some("debug-line-vertices"),
+        size: size.next_power_of_two(),
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false
+      }));
+    }
+    queue.write_buffer(self.vertex_buffer.as_ref().unwrap(), 0, bytemuck::cast_slice(vertices));
+  }
+
+  pub fn draw_calls(&self) -> u32 {
+    (self.vertex_buffer.is_some() && self.vertex_count > 0) as u32
+  }
+
+  pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+    let buffer = match &self.vertex_buffer {
+      Some(buffer) if self.vertex_count > 0 => buffer,
+      _ => return,
+    };
+    render_pass.set_pipeline(&self.pipeline);
+    render_pass.set_bind_group(0, &self.bind_group, &[]);
+    render_pass.set_vertex_buffer(0, buffer.slice(..));
+    render_pass.draw(0..self.vertex_count, 0..1);
+  }
+}
@@ -0,0 +1,59 @@
+use wgpu::{CommandEncoder, ComputePass, RenderPass};
+
+// Named regions around GPU work, so frame captures in RenderDoc, Nsight or Xcode group commands
+// under readable headings instead of one flat list. Backends without debug utils ignore them.
+pub trait DebugScope {
+  fn push_group(&mut self, label: &str);
+  fn pop_group(&mut self);
+  fn marker(&mut self, label: &str);
+
+  // Runs `f` inside a group named `label`, keeping pushes and pops balanced.
+  fn scope<R>(&mut self, label: &str, f: impl FnOnce(&mut Self) -> R) -> R where Self: Sized {
+    self.push_group(label);
+    let result = f(self);
+    self.pop_group();
+    result
+  }
+}
+
+impl DebugScope for CommandEncoder {
+  fn push_group(&mut self, label: &str) {
+    self.push_debug_group(label);
+  }
+
+  fn pop_group(&mut self) {
+    self.pop_debug_group();
+  }
+
+  fn marker(&mut self, label: &str) {
+    self.insert_debug_marker(label);
+  }
+}
+
+impl<'a> DebugScope for RenderPass<'a> {
+  fn push_group(&mut self, label: &str) {
+    self.push_debug_group(label);
+  }
+
+  fn pop_group(&mut self) {
+    self.pop_debug_group();
+  }
+
+  fn marker(&mut self, label: &str) {
+    self.insert_debug_marker(label);
+  }
+}
+
+impl<'a> DebugScope for ComputePass<'a> {
+  fn push_group(&mut self, label: &str) {
+    self.push_debug_group(label);
+  }
+
+  fn pop_group(&mut self) {
+    self.pop_debug_group();
+  }
+
+  fn marker(&mut self, label: &str) {
+    self.insert_debug_marker(label);
+  }
+}
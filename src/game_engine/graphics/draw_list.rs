@@ -0,0 +1,155 @@
+use glam::{Mat4, Vec3};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RenderPhase {
+  Opaque, // sorted by state, then front to back so early depth testing rejects hidden pixels
+  Transparent, // sorted back to front so blending composites correctly
+  Overlay, // drawn last in submission order, e.g. UI and debug lines
+}
+
+// Packs a draw's phase, pipeline, material and depth into one integer, so a plain sort groups
+// draws into the cheapest submission order.
+//
+// Opaque:      | phase:2 | pipeline:12 | material:20 | depth:30 |
+// Transparent: | phase:2 | inverted depth:30 | pipeline:12 | material:20 |
+// Overlay:     | phase:2 | 0 | (submission order comes from the stable sort)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SortKey(pub u64);
+
+const PIPELINE_BITS: u64 = 12;
+const MATERIAL_BITS: u64 = 20;
+const DEPTH_BITS: u64 = 30;
+
+impl SortKey {
+  pub fn new(phase: RenderPhase, pipeline: u32, material: u32, depth: f32) -> Self {
+    let pipeline = pipeline as u64 & ((1 << PIPELINE_BITS) - 1);
+    let material = material as u64 & ((1 << MATERIAL_BITS) - 1);
+    let depth = quantize_depth(depth);
+    let phase_bits = (phase as u64) << (PIPELINE_BITS + MATERIAL_BITS + DEPTH_BITS);
+    let body = match phase {
+      RenderPhase::Opaque => (pipeline << (MATERIAL_BITS + DEPTH_BITS)) | (material << DEPTH_BITS) | depth,
+      RenderPhase::Transparent => {
+        let inverted = (1 << DEPTH_BITS) - 1 - depth;
+        (inverted << (PIPELINE_BITS + MATERIAL_BITS)) | (pipeline << MATERIAL_BITS) | material
+      }
+      RenderPhase::Overlay => 0,
+    };
+    SortKey(phase_bits | body)
+  }
+}
+
+// Positive floats order the same as their bit patterns, so the top bits of the float make an
+// order-preserving fixed-width depth without needing the camera's near and far planes.
+fn quantize_depth(depth: f32) -> u64 {
+  (depth.max(0.0).to_bits() >> (32 - DEPTH_BITS)) as u64
+}
+
+// Distance in front of the camera along its view direction, for `DrawList::push`.
+pub fn view_depth(view: Mat4, position: Vec3) -> f32 {
+  -view.transform_point3(position).z
+}
+
+#[derive(Debug, Clone)]
+struct DrawCommand<T> {
+  key: SortKey,
+  pipeline: u32,
+  material: u32,
+  item: T,
+}
+
+// One draw from a sorted list. `bind_pipeline` and `bind_material` are only set when the state
+// differs from the previous draw, so redundant `set_pipeline`/`set_bind_group` calls can be skipped.
+#[derive(Debug)]
+pub struct Draw<'a, T> {
+  pub item: &'a T,
+  pub pipeline: u32,
+  pub material: u32,
+  pub bind_pipeline: bool,
+  pub bind_material: bool,
+}
+
+// A frame's draws, collected in any order and sorted before submission. `T` is whatever the
+// renderer needs to issue the draw (a mesh handle, an entity, an index...).
+#[derive(Debug, Clone)]
+pub struct DrawList<T> {
+  commands: Vec<DrawCommand<T>>,
+  sorted: bool,
+}
+
+impl<T> DrawList<T> {
+  pub fn new() -> Self {
+    DrawList { commands: Vec::new(), sorted: true }
+  }
+
+  // `pipeline` and `material` are ids the caller picks; only their low 12 and 20 bits affect the
+  // order, but changes are detected on the full values.
+  pub fn push(&mut self, phase: RenderPhase, pipeline: u32, material: u32, depth: f32, item: T) {
+    let key = SortKey::new(phase, pipeline, material, depth);
+    self.commands.push(DrawCommand { key, pipeline, material, item });
+    self.sorted = false;
+  }
+
+  // Stable, so equal keys (and all overlay draws) keep the order they were pushed in.
+  pub fn sort(&mut self) {
+    if !self.sorted {
+      self.commands.sort_by_key(|command| command.key);
+      self.sorted = true;
+    }
+  }
+
+  // The draws in sorted order (sorting first if needed).
+  pub fn draws(&mut self) -> impl Iterator<Item = Draw<'_, T>> {
+    self.sort();
+    self.iter()
+  }
+
+  // The draws in the order they're in, for a list already sorted with `sort`.
+  pub fn iter(&self) -> impl Iterator<Item = Draw<'_, T>> {
+    debug_assert!(self.sorted, "draw list used without sorting it");
+    let mut previous: Option<(u32, u32)> = None;
+    self.commands.iter().map(move |command| {
+      let bind_pipeline = previous.is_none_or(|(pipeline, _)| pipeline != command.pipeline);
+      let bind_material = bind_pipeline || previous.is_none_or(|(_, material)| material != command.material);
+      previous = Some((command.pipeline, command.material));
+      Draw { item: &command.item, pipeline: command.pipeline, material: command.material, bind_pipeline, bind_material }
+    })
+  }
+
+  // How many pipeline and material binds submitting the list in its current order would take.
+  pub fn state_changes(&self) -> (usize, usize) {
+    let mut changes = (0, 0);
+    let mut previous: Option<(u32, u32)> = None;
+    for command in &self.commands {
+      match previous {
+        Some((pipeline, _)) if pipeline == command.pipeline => {
+          if previous.is_some_and(|(_, material)| material != command.material) {
+            changes.1 += 1;
+          }
+        }
+        _ => changes = (changes.0 + 1, changes.1 + 1),
+      }
+      previous = Some((command.pipeline, command.material));
+    }
+    changes
+  }
+
+  pub fn len(&self) -> usize {
+    self.commands.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.commands.is_empty()
+  }
+
+  // Empties the list but keeps its allocation for the next frame.
+  pub fn clear(&mut self) {
+    self.commands.clear();
+    self.sorted = true;
+  }
+}
+
+impl<T> Default for DrawList<T> {
+  fn default() -> Self {
+    DrawList::new()
+  }
+}
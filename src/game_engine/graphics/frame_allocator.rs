@@ -0,0 +1,171 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{Buffer, BufferAddress, BufferDescriptor, BufferSize, BufferSlice, BufferUsages, Device, Maintain, Queue};
+
+use super::bind_group_cache::{CachedBinding, ResourceId};
+
+pub const FRAMES_IN_FLIGHT: u64 = 3;
+
+// Where an allocation landed. Bind it with `FrameAllocator::buffer` and `offset` (e.g. as a
+// dynamic offset) or draw from it with `FrameAllocator::slice`. Only valid for the frame it was
+// allocated in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameSlice {
+  pub offset: BufferAddress,
+  pub size: BufferAddress,
+  overflow: Option<usize>, // index into the frame's overflow buffers, if the ring was full
+}
+
+// A ring buffer for transient per-frame GPU data (uniforms, vertices, instances). The buffer is
+// split into one region per frame in flight, and each frame bump-allocates from its own region,
+// so nothing is created per draw. A region is reused only once the GPU has finished the frame that
+// last wrote it.
+//
+// Call `begin_frame` before allocating and `end_frame` right after submitting the frame's work.
+pub struct FrameAllocator {
+  buffer: Buffer,
+  id: ResourceId, // changes when the ring grows
+  region_size: BufferAddress,
+  uniform_alignment: BufferAddress,
+  frame: u64,
+  cursor: BufferAddress,
+  overflow: Vec<(ResourceId, Buffer)>, // one-off buffers for this frame, used when its region runs out
+  high_water: BufferAddress, // most bytes any frame asked for, to size the next region
+  completed: Arc<AtomicU64>, // the newest frame the GPU has finished
+}
+
+impl FrameAllocator {
+  pub fn new(device: &Device, region_size: BufferAddress) -> Self {
+    let region_size = align(region_size.max(256), wgpu::COPY_BUFFER_ALIGNMENT);
+    FrameAllocator {
+      buffer: FrameAllocator::create_buffer(device, region_size),
+      id: ResourceId::new(),
+      region_size,
+      uniform_alignment: device.limits().min_uniform_buffer_offset_alignment as BufferAddress,
+      frame: 0,
+      cursor: 0,
+      overflow: Vec::new(),
+      high_water: 0,
+      completed: Arc::new(AtomicU64::new(0)),
+    }
+  }
+
+  fn create_buffer(device: &Device, region_size: BufferAddress) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+      label: Some("frame-ring-buffer"),
+      size: region_size * FRAMES_IN_FLIGHT,
+      usage: BufferUsages::UNIFORM | BufferUsages::VERTEX | BufferUsages::INDEX | BufferUsages::STORAGE | BufferUsages::COPY_DST,
+      mapped_at_creation: false
+    })
+  }
+
+  // Moves to the next region, waiting for the GPU if it's still reading the frame that last used
+  // it. The ring grows here if the last frame overflowed.
+  pub fn begin_frame(&mut self, device: &Device) {
+    self.frame += 1;
+    self.wait_for(device, self.frame.saturating_sub(FRAMES_IN_FLIGHT));
+
+    if self.high_water > self.region_size {
+      // Every earlier frame must be done before the old buffer can go.
+      self.wait_for(device, self.frame - 1);
+      self.region_size = align(self.high_water.next_power_of_two(), wgpu::COPY_BUFFER_ALIGNMENT);
+      self.buffer = FrameAllocator::create_buffer(device, self.region_size);
+      self.id = ResourceId::new();
+      log::debug!("frame ring buffer grown to {} bytes per frame", self.region_size);
+    }
+
+    self.cursor = 0;
+    self.overflow.clear();
+  }
+
+  // Blocks until the GPU has finished `frame`. The browser can't be blocked on, but there
+  // `write_buffer` is ordered with earlier submissions anyway.
+  fn wait_for(&self, device: &Device, frame: u64) {
+    if cfg!(target_arch = "wasm32") {
+      return;
+    }
+    while self.completed.load(Ordering::Acquire) < frame {
+      device.poll(Maintain::Wait);
+    }
+  }
+
+  // Marks the current frame's allocations as in use until the work submitted so far completes.
+  pub fn end_frame(&mut self, queue: &Queue) {
+    let (frame, completed) = (self.frame, self.completed.clone());
+    queue.on_submitted_work_done(move || {
+      completed.fetch_max(frame, Ordering::AcqRel);
+    });
+  }
+
+  // Copies `data` into this frame's region, aligned for use as a uniform buffer binding.
+  pub fn uniform(&mut self, device: &Device, queue: &Queue, data: &[u8]) -> FrameSlice {
+    self.allocate(device, queue, data, self.uniform_alignment)
+  }
+
+  // Copies `data` into this frame's region, for vertex, index, instance or storage data.
+  pub fn vertices(&mut self, device: &Device, queue: &Queue, data: &[u8]) -> FrameSlice {
+    self.allocate(device, queue, data, wgpu::COPY_BUFFER_ALIGNMENT)
+  }
+
+  fn allocate(&mut self, device: &Device, queue: &Queue, data: &[u8], alignment: BufferAddress) -> FrameSlice {
+    let size = data.len() as BufferAddress;
+    let padded = align(size, wgpu::COPY_BUFFER_ALIGNMENT);
+    let offset = align(self.cursor, alignment);
+    self.cursor = offset + padded;
+    self.high_water = self.high_water.max(self.cursor);
+
+    if self.cursor > self.region_size {
+      self.overflow.push((ResourceId::new(), device.create_buffer_init(&BufferInitDescriptor {
+        label: Some("frame-overflow"),
+        usage: BufferUsages::UNIFORM | BufferUsages::VERTEX | BufferUsages::INDEX | BufferUsages::STORAGE,
+        contents: &padded_bytes(data, padded)
+      })));
+      return FrameSlice { offset: 0, size, overflow: Some(self.overflow.len() - 1) };
+    }
+
+    let start = self.region_start() + offset;
+    if padded == size {
+      queue.write_buffer(&self.buffer, start, data);
+    } else {
+      queue.write_buffer(&self.buffer, start, &padded_bytes(data, padded));
+    }
+    FrameSlice { offset: start, size, overflow: None }
+  }
+
+  fn region_start(&self) -> BufferAddress {
+    (self.frame % FRAMES_IN_FLIGHT) * self.region_size
+  }
+
+  pub fn buffer(&self, slice: &FrameSlice) -> &Buffer {
+    match slice.overflow {
+      Some(index) => &self.overflow[index].1,
+      None => &self.buffer,
+    }
+  }
+
+  // The allocation as a binding for `BindGroupCache`. It covers the allocation's size from the
+  // start of its buffer, so pass `slice.offset` as the dynamic offset; the cached group then
+  // stays valid across frames for the same size.
+  pub fn binding(&self, slice: &FrameSlice) -> CachedBinding<'_> {
+    let id = match slice.overflow {
+      Some(index) => self.overflow[index].0,
+      None => self.id,
+    };
+    CachedBinding::Buffer { id, buffer: self.buffer(slice), offset: 0, size: BufferSize::new(slice.size) }
+  }
+
+  pub fn slice(&self, slice: &FrameSlice) -> BufferSlice<'_> {
+    self.buffer(slice).slice(slice.offset..slice.offset + slice.size)
+  }
+}
+
+fn align(value: BufferAddress, alignment: BufferAddress) -> BufferAddress {
+  value.div_ceil(alignment) * alignment
+}
+
+fn padded_bytes(data: &[u8], size: BufferAddress) -> Vec<u8> {
+  let mut bytes = data.to_vec();
+  bytes.resize(size as usize, 0);
+  bytes
+}
@@ -0,0 +1,912 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use glam::{Mat4, Quat, UVec2, Vec2, Vec3};
+use serde::Deserialize;
+
+use crate::game_engine::assets::Asset;
+use crate::game_engine::ecs::{Entity, Name, Transform, World};
+use super::animation::AnimationPlayer;
+use super::camera::Camera;
+use super::color::Color;
+use super::instancing::InstancedMesh;
+use super::material::Material;
+use super::mesh::{Mesh, MeshBuilder};
+use super::skinning::{Joint, SkinVertex, Skeleton, SkinnedMesh, SkinnedPart};
+use super::texture::Texture;
+
+const GLB_MAGIC: u32 = 0x4654_6C67; // "glTF"
+const CHUNK_JSON: u32 = 0x4E4F_534A;
+const CHUNK_BIN: u32 = 0x004E_4942;
+const MODE_TRIANGLES: u32 = 4;
+
+// A glTF 2.0 file (`.gltf` with its buffers and images, or a self-contained `.glb`) converted to
+// engine types. Indices between the parts are the file's own: a node's `mesh` indexes `meshes`, a
+// channel's `node` indexes `nodes`, and so on.
+#[derive(Debug, Clone)]
+pub struct GltfScene {
+  pub meshes: Vec<GltfMesh>,
+  pub materials: Vec<GltfMaterial>,
+  pub images: Vec<Option<Texture>>, // `None` where the image couldn't be decoded
+  pub nodes: Vec<GltfNode>,
+  pub roots: Vec<usize>, // the top-level nodes of the default scene
+  pub cameras: Vec<Camera>, // placed by the nodes that use them
+  pub skins: Vec<GltfSkin>,
+  pub animations: Vec<GltfAnimation>, // by node; `skin_animation` turns them into skeleton clips
+}
+
+#[derive(Debug, Clone)]
+pub struct GltfMesh {
+  pub name: String,
+  pub primitives: Vec<GltfPrimitive>,
+}
+
+// One part of a mesh drawn with a single material. Only triangle lists are imported.
+#[derive(Debug, Clone)]
+pub struct GltfPrimitive {
+  pub mesh: Arc<Mesh>, // shared, so spawning the scene twice instances it
+  pub material: Option<usize>,
+  pub skin: Option<Arc<Vec<SkinVertex>>>, // joint weights, indexing the joints of the node's skin
+}
+
+// A material and the images its maps use, for the ones `Material`'s paths can't point at because
+// they're inside the file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GltfMaterial {
+  pub material: Material,
+  pub base_color_image: Option<usize>,
+  pub metallic_roughness_image: Option<usize>,
+  pub normal_image: Option<usize>,
+  pub emissive_image: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GltfNode {
+  pub name: String,
+  pub transform: Transform, // relative to `parent`
+  pub parent: Option<usize>,
+  pub children: Vec<usize>,
+  pub mesh: Option<usize>,
+  pub camera: Option<usize>,
+  pub skin: Option<usize>, // bends `mesh`, whose placement then comes from the joints alone
+}
+
+// The joints a skinned mesh is bound to, as nodes, with the matrices that bound it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GltfSkin {
+  pub name: String,
+  pub joints: Vec<usize>,
+  pub inverse_bind_matrices: Vec<Mat4>, // one per joint
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+  Step,
+  Linear,
+  CubicSpline, // values are stored as in-tangent, value, out-tangent triples
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChannelValues {
+  Translation(Vec<Vec3>),
+  Rotation(Vec<Quat>),
+  Scale(Vec<Vec3>),
+  Weights(Vec<f32>), // morph target weights, kept but not applied
+}
+
+// Keyframes for one property of one node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GltfChannel {
+  pub node: usize,
+  pub interpolation: Interpolation,
+  pub times: Vec<f32>, // seconds, ascending
+  pub values: ChannelValues,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GltfAnimation {
+  pub name: String,
+  pub channels: Vec<GltfChannel>,
+  pub duration: f32, // the last keyframe's time, in seconds
+}
+
+impl GltfAnimation {
+  // Poses `transforms` (one per node, e.g. from `GltfScene::local_transforms`) at `time` seconds,
+  // holding the first and last keyframes outside the animation's range.
+  pub fn sample(&self, time: f32, transforms: &mut [Transform]) {
+    for channel in &self.channels {
+      let transform = match transforms.get_mut(channel.node) {
+        Some(transform) => transform,
+        None => continue,
+      };
+      match &channel.values {
+        ChannelValues::Translation(values) => transform.translation = sample(channel, values, time, |a, b, t| a.lerp(b, t)),
+        ChannelValues::Scale(values) => transform.scale = sample(channel, values, time, |a, b, t| a.lerp(b, t)),
+        ChannelValues::Rotation(values) => transform.rotation = sample(channel, values, time, |a, b, t| a.slerp(b, t)).normalize(),
+        ChannelValues::Weights(_) => {}
+      }
+    }
+  }
+}
+
+// The value of `channel` at `time`. `values` has one entry per keyframe, or three for cubic splines.
+fn sample<T>(channel: &GltfChannel, values: &[T], time: f32, lerp: impl Fn(T, T, f32) -> T) -> T
+where
+  T: Copy + std::ops::Add<Output = T> + std::ops::Mul<f32, Output = T>,
+{
+  let times = &channel.times;
+  let cubic = channel.interpolation == Interpolation::CubicSpline;
+  let value = |key: usize| if cubic { values[key * 3 + 1] } else { values[key] };
+  let next = times.partition_point(|key| *key <= time);
+  if next == 0 {
+    return value(0);
+  }
+  if next == times.len() {
+    return value(times.len() - 1);
+  }
+  let previous = next - 1;
+  let span = times[next] - times[previous];
+  let t = if span > 0.0 { (time - times[previous]) / span } else { 0.0 };
+  match channel.interpolation {
+    Interpolation::Step => value(previous),
+    Interpolation::Linear => lerp(value(previous), value(next), t),
+    Interpolation::CubicSpline => {
+      // Hermite, with the tangents scaled by the keyframe gap as the spec says.
+      let (t2, t3) = (t * t, t * t * t);
+      let out_tangent = values[previous * 3 + 2] * span;
+      let in_tangent = values[next * 3] * span;
+      value(previous) * (2.0 * t3 - 3.0 * t2 + 1.0) + out_tangent * (t3 - 2.0 * t2 + t)
+        + value(next) * (-2.0 * t3 + 3.0 * t2) + in_tangent * (t3 - t2)
+    }
+  }
+}
+
+impl GltfScene {
+  // Reads a `.gltf` document or `.glb` container. External buffers and images are looked up
+  // relative to `directory`.
+  pub fn from_bytes(bytes: &[u8], directory: &Path) -> Result<Self, String> {
+    let (json, binary) = match bytes.get(0..4) {
+      Some(magic) if u32::from_le_bytes(magic.try_into().unwrap()) == GLB_MAGIC => split_glb(bytes)?,
+      _ => (bytes, None),
+    };
+    let document: Document = serde_json::from_slice(json).map_err(|err| format!("couldn't parse glTF: {}", err))?;
+    let buffers = document.buffers.iter().enumerate()
+      .map(|(index, buffer)| match &buffer.uri {
+        Some(uri) => read_uri(uri, directory).map_err(|err| format!("buffer {}: {}", index, err)),
+        None => binary.map(<[u8]>::to_vec).ok_or_else(|| format!("buffer {} has no uri and there's no GLB binary chunk", index)),
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+    let reader = Reader { document: &document, buffers };
+
+    let images = (0..document.images.len()).map(|index| reader.image(index, directory)).collect();
+    let materials = document.materials.iter()
+      .map(|material| GltfMaterial {
+        material: gltf_material(&document, material, directory),
+        base_color_image: texture_image(&document, &material.pbr_metallic_roughness.base_color_texture),
+        metallic_roughness_image: texture_image(&document, &material.pbr_metallic_roughness.metallic_roughness_texture),
+        normal_image: texture_image(&document, &material.normal_texture),
+        emissive_image: texture_image(&document, &material.emissive_texture),
+      })
+      .collect();
+    let meshes = document.meshes.iter().enumerate()
+      .map(|(index, mesh)| reader.mesh(mesh).map_err(|err| format!("mesh {}: {}", index, err)))
+      .collect::<Result<Vec<_>, _>>()?;
+
+    let mut nodes: Vec<GltfNode> = document.nodes.iter()
+      .map(|node| GltfNode {
+        name: node.name.clone().unwrap_or_default(),
+        transform: node.transform(),
+        parent: None,
+        children: node.children.clone(),
+        mesh: node.mesh,
+        camera: node.camera,
+        skin: node.skin,
+      })
+      .collect();
+    for index in 0..nodes.len() {
+      for child in nodes[index].children.clone() {
+        match nodes.get_mut(child) {
+          Some(node) => node.parent = Some(index),
+          None => return Err(format!("node {} has a child {} that doesn't exist", index, child)),
+        }
+      }
+    }
+    // Without a scene, every node nothing else parents is a root.
+    let roots = match document.scene.or(if document.scenes.is_empty() { None } else { Some(0) }) {
+      Some(scene) => document.scenes.get(scene).ok_or_else(|| format!("scene {} doesn't exist", scene))?.nodes.clone(),
+      None => (0..nodes.len()).filter(|index| nodes[*index].parent.is_none()).collect(),
+    };
+
+    let cameras = document.cameras.iter().map(CameraDef::camera).collect();
+    let skins = document.skins.iter().enumerate()
+      .map(|(index, skin)| reader.skin(skin).map_err(|err| format!("skin {}: {}", index, err)))
+      .collect::<Result<Vec<_>, _>>()?;
+    let animations = document.animations.iter().enumerate()
+      .map(|(index, animation)| reader.animation(animation).map_err(|err| format!("animation {}: {}", index, err)))
+      .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(GltfScene { meshes, materials, images, nodes, roots, cameras, skins, animations })
+  }
+
+  pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path).map_err(|err| format!("couldn't read {}: {}", path.display(), err))?;
+    GltfScene::from_bytes(&bytes, path.parent().unwrap_or(Path::new(""))).map_err(|err| format!("{}: {}", path.display(), err))
+  }
+
+  // Every node's own transform, in the rest pose, for posing with `GltfAnimation::sample`.
+  pub fn local_transforms(&self) -> Vec<Transform> {
+    self.nodes.iter().map(|node| node.transform).collect()
+  }
+
+  // Each node's transform in the scene, from `local` transforms like `local_transforms`'.
+  pub fn world_matrices(&self, local: &[Transform]) -> Vec<Mat4> {
+    let mut world = vec![None; self.nodes.len()];
+    fn resolve(scene: &GltfScene, local: &[Transform], world: &mut [Option<Mat4>], index: usize, depth: usize) -> Mat4 {
+      if let Some(matrix) = world[index] {
+        return matrix;
+      }
+      let own = local.get(index).map_or(Mat4::IDENTITY, Transform::matrix);
+      // A parent loop would be a broken file; stop climbing rather than overflow.
+      let matrix = match scene.nodes[index].parent {
+        Some(parent) if depth < scene.nodes.len() => resolve(scene, local, world, parent, depth + 1) * own,
+        _ => own,
+      };
+      world[index] = Some(matrix);
+      matrix
+    }
+    (0..self.nodes.len()).map(|index| resolve(self, local, &mut world, index, 0)).collect()
+  }
+
+  // The camera `node` carries, placed where the node is in the rest pose.
+  pub fn camera(&self, node: usize) -> Option<Camera> {
+    let mut camera = *self.cameras.get(self.nodes.get(node)?.camera?)?;
+    camera.transform = transform_from_matrix(self.world_matrices(&self.local_transforms())[node]);
+    Some(camera)
+  }
+
+  // `skin` as a `Skeleton`, its joints in the same order, parented to each other the way their
+  // nodes are and placed in the scene's space.
+  pub fn skeleton(&self, skin: usize) -> Option<Skeleton> {
+    let skin = self.skins.get(skin)?;
+    let rest = self.world_matrices(&self.local_transforms());
+    let joints = skin.joints.iter().enumerate()
+      .map(|(index, node)| {
+        // Nodes between two joints, or above the top ones, fold into the joint's offset.
+        let above = self.nodes[*node].parent;
+        let mut ancestor = above;
+        let mut parent = None;
+        for _ in 0..self.nodes.len() {
+          let node = match ancestor {
+            Some(node) => node,
+            None => break,
+          };
+          parent = skin.joints.iter().position(|joint| *joint == node);
+          if parent.is_some() {
+            break;
+          }
+          ancestor = self.nodes[node].parent;
+        }
+        let above = above.map_or(Mat4::IDENTITY, |node| rest[node]);
+        let offset = match parent {
+          Some(parent) => rest[skin.joints[parent]].inverse() * above,
+          None => above,
+        };
+        Joint {
+          name: self.nodes[*node].name.clone(),
+          parent,
+          rest: self.nodes[*node].transform,
+          offset,
+          inverse_bind: skin.inverse_bind_matrices.get(index).copied().unwrap_or(Mat4::IDENTITY),
+        }
+      })
+      .collect();
+    Some(Skeleton::new(joints))
+  }
+
+  // `animation`'s channels that move `skin`'s joints, renumbered to index the skeleton, for an
+  // `AnimationPlayer`.
+  pub fn skin_animation(&self, skin: usize, animation: &GltfAnimation) -> Option<GltfAnimation> {
+    let skin = self.skins.get(skin)?;
+    let channels = animation.channels.iter()
+      .filter_map(|channel| {
+        let joint = skin.joints.iter().position(|joint| *joint == channel.node)?;
+        Some(GltfChannel { node: joint, ..channel.clone() })
+      })
+      .collect();
+    Some(GltfAnimation { name: animation.name.clone(), channels, duration: animation.duration })
+  }
+
+  // Spawns the default scene into `world`: an entity per node with its `Name` and scene-space
+  // `Transform`, and one per mesh primitive drawn as an `InstancedMesh` in its material's base
+  // colour. A skinned node's primitives go on one entity instead, as a `SkinnedMesh` with an
+  // `AnimationPlayer` holding every animation by name, stopped. Returns the node entities, indexed
+  // like `nodes`, `None` for nodes outside the scene.
+  pub fn spawn(&self, world: &mut World) -> Vec<Option<Entity>> {
+    let matrices = self.world_matrices(&self.local_transforms());
+    let mut skeletons: Vec<Option<Arc<Skeleton>>> = vec![None; self.skins.len()];
+    let mut entities = vec![None; self.nodes.len()];
+    let mut stack = self.roots.clone();
+    while let Some(index) = stack.pop() {
+      let node = match self.nodes.get(index) {
+        Some(node) if entities[index].is_none() => node,
+        _ => continue,
+      };
+      let transform = transform_from_matrix(matrices[index]);
+      let entity = world.spawn();
+      world.insert(entity, Name::new(node.name.clone()));
+      world.insert(entity, transform);
+      entities[index] = Some(entity);
+      stack.extend(node.children.iter().copied());
+
+      let primitives = node.mesh.and_then(|mesh| self.meshes.get(mesh)).map_or(&[][..], |mesh| &mesh.primitives);
+      let color = |primitive: &GltfPrimitive| primitive.material.and_then(|material| self.materials.get(material)).map_or(Color::WHITE, |material| material.material.base_color);
+      let mut skinned = node.skin.filter(|skin| *skin < self.skins.len()).map(|skin| {
+        let skeleton = skeletons[skin].get_or_insert_with(|| Arc::new(self.skeleton(skin).unwrap()));
+        (skin, SkinnedMesh::new(skeleton.clone()))
+      });
+      for primitive in primitives {
+        match (&mut skinned, &primitive.skin) {
+          (Some((_, skinned)), Some(skin)) => skinned.parts.push(SkinnedPart { mesh: primitive.mesh.clone(), skin: skin.clone(), color: color(primitive) }),
+          _ => {
+            let part = world.spawn();
+            world.insert(part, transform);
+            world.insert(part, InstancedMesh { mesh: primitive.mesh.clone(), color: color(primitive) });
+          }
+        }
+      }
+      if let Some((skin, skinned)) = skinned.filter(|(_, skinned)| !skinned.parts.is_empty()) {
+        let mut player = AnimationPlayer::new();
+        for (number, animation) in self.animations.iter().enumerate() {
+          let name = if animation.name.is_empty() { format!("animation {}", number) } else { animation.name.clone() };
+          player.add_clip(name, Arc::new(self.skin_animation(skin, animation).unwrap()));
+        }
+        let part = world.spawn();
+        world.insert(part, Transform::IDENTITY);
+        world.insert(part, skinned);
+        world.insert(part, player);
+      }
+    }
+    entities
+  }
+}
+
+impl Asset for GltfScene {
+  fn from_bytes(bytes: &[u8], path: &Path) -> Result<Self, String> {
+    GltfScene::from_bytes(bytes, path.parent().unwrap_or(Path::new("")))
+  }
+}
+
+// The JSON and binary chunks of a `.glb`.
+fn split_glb(bytes: &[u8]) -> Result<(&[u8], Option<&[u8]>), String> {
+  let word = |offset: usize| bytes.get(offset..offset + 4).map(|word| u32::from_le_bytes(word.try_into().unwrap()));
+  if word(4) != Some(2) {
+    return Err(format!("unsupported GLB version {:?}", word(4)));
+  }
+  let length = (word(8).unwrap_or(0) as usize).min(bytes.len());
+  let (mut json, mut binary) = (None, None);
+  let mut offset = 12;
+  while offset + 8 <= length {
+    let (chunk_length, kind) = (word(offset).unwrap() as usize, word(offset + 4).unwrap());
+    let chunk = bytes.get(offset + 8..offset + 8 + chunk_length).ok_or("GLB chunk runs past the end of the file")?;
+    match kind {
+      CHUNK_JSON => json = Some(chunk),
+      CHUNK_BIN if binary.is_none() => binary = Some(chunk),
+      _ => {}
+    }
+    // Chunks are padded to four bytes.
+    offset += 8 + chunk_length.next_multiple_of(4);
+  }
+  Ok((json.ok_or("GLB has no JSON chunk")?, binary))
+}
+
+// A buffer or image's contents: a base64 `data:` URI, or a file relative to `directory`.
+fn read_uri(uri: &str, directory: &Path) -> Result<Vec<u8>, String> {
+  if let Some(data) = uri.strip_prefix("data:") {
+    let (_, encoded) = data.split_once(";base64,").ok_or("only base64 data URIs are supported")?;
+    return decode_base64(encoded);
+  }
+  let path = uri_path(uri, directory);
+  std::fs::read(&path).map_err(|err| format!("couldn't read {}: {}", path.display(), err))
+}
+
+pub(super) fn uri_path(uri: &str, directory: &Path) -> PathBuf {
+  directory.join(uri.replace("%20", " "))
+}
+
+fn decode_base64(encoded: &str) -> Result<Vec<u8>, String> {
+  let mut bytes = Vec::with_capacity(encoded.len() * 3 / 4);
+  let (mut bits, mut count) = (0u32, 0);
+  for byte in encoded.bytes() {
+    let value = match byte {
+      b'A'..=b'Z' => byte - b'A',
+      b'a'..=b'z' => byte - b'a' + 26,
+      b'0'..=b'9' => byte - b'0' + 52,
+      b'+' | b'-' => 62,
+      b'/' | b'_' => 63,
+      b'=' | b'\n' | b'\r' | b' ' => continue,
+      _ => return Err(format!("bad base64 character {:?}", byte as char)),
+    };
+    bits = bits << 6 | value as u32;
+    count += 6;
+    if count >= 8 {
+      count -= 8;
+      bytes.push((bits >> count) as u8);
+    }
+  }
+  Ok(bytes)
+}
+
+pub(super) fn transform_from_matrix(matrix: Mat4) -> Transform {
+  let (scale, rotation, translation) = matrix.to_scale_rotation_translation();
+  Transform { translation, rotation, scale }
+}
+
+// The image a texture reference ends up at.
+fn texture_image(document: &Document, info: &Option<TextureInfoDef>) -> Option<usize> {
+  document.textures.get(info.as_ref()?.index)?.source
+}
+
+// `material` as a `Material`, with maps that are files relative to `directory`. Images inside the
+// file are left off, since a `Material` can only point at files.
+pub(super) fn gltf_material(document: &Document, material: &MaterialDef, directory: &Path) -> Material {
+  let map = |info: &Option<TextureInfoDef>| -> Option<PathBuf> {
+    let image = document.images.get(texture_image(document, info)?)?;
+    match image.uri.as_deref() {
+      Some(uri) if !uri.starts_with("data:") => Some(uri_path(uri, directory)),
+      _ => None,
+    }
+  };
+  let pbr = &material.pbr_metallic_roughness;
+  let [r, g, b, a] = pbr.base_color_factor;
+  Material {
+    name: material.name.clone().unwrap_or_default(),
+    base_color: Color::rgba(r, g, b, a),
+    metallic: pbr.metallic_factor,
+    roughness: pbr.roughness_factor,
+    emissive: Vec3::from(material.emissive_factor),
+    normal_scale: material.normal_texture.as_ref().map_or(1.0, |info| info.scale),
+    base_color_map: map(&pbr.base_color_texture),
+    metallic_roughness_map: map(&pbr.metallic_roughness_texture),
+    normal_map: map(&material.normal_texture),
+    emissive_map: map(&material.emissive_texture),
+  }
+}
+
+// Reads accessors out of the loaded buffers.
+struct Reader<'a> {
+  document: &'a Document,
+  buffers: Vec<Vec<u8>>,
+}
+
+impl Reader<'_> {
+  fn view(&self, index: usize) -> Result<(&[u8], Option<usize>), String> {
+    let view = self.document.buffer_views.get(index).ok_or_else(|| format!("buffer view {} doesn't exist", index))?;
+    let buffer = self.buffers.get(view.buffer).ok_or_else(|| format!("buffer {} doesn't exist", view.buffer))?;
+    let bytes = buffer.get(view.byte_offset..view.byte_offset + view.byte_length).ok_or_else(|| format!("buffer view {} runs past its buffer", index))?;
+    Ok((bytes, view.byte_stride))
+  }
+
+  // An accessor's elements, flattened to floats; normalized integers come out in 0..1 or -1..1.
+  fn floats(&self, index: usize) -> Result<(Vec<f32>, usize), String> {
+    let accessor = self.document.accessors.get(index).ok_or_else(|| format!("accessor {} doesn't exist", index))?;
+    if accessor.sparse.is_some() {
+      return Err(format!("accessor {} is sparse, which isn't supported", index));
+    }
+    let components = match accessor.kind.as_str() {
+      "SCALAR" => 1,
+      "VEC2" => 2,
+      "VEC3" => 3,
+      "VEC4" | "MAT2" => 4,
+      "MAT3" => 9,
+      "MAT4" => 16,
+      kind => return Err(format!("accessor {} has unknown type {}", index, kind)),
+    };
+    let size = match accessor.component_type {
+      5120 | 5121 => 1,
+      5122 | 5123 => 2,
+      5125 | 5126 => 4,
+      kind => return Err(format!("accessor {} has unknown component type {}", index, kind)),
+    };
+    let view = match accessor.buffer_view {
+      Some(view) => view,
+      None => return Ok((vec![0.0; accessor.count * components], components)),
+    };
+    let (bytes, stride) = self.view(view)?;
+    let stride = stride.unwrap_or(size * components);
+    let mut values = Vec::with_capacity(accessor.count * components);
+    for element in 0..accessor.count {
+      for component in 0..components {
+        let offset = accessor.byte_offset + element * stride + component * size;
+        let raw = bytes.get(offset..offset + size).ok_or_else(|| format!("accessor {} runs past its buffer view", index))?;
+        let normalized = accessor.normalized;
+        values.push(match accessor.component_type {
+          5120 if normalized => (raw[0] as i8 as f32 / 127.0).max(-1.0),
+          5120 => raw[0] as i8 as f32,
+          5121 if normalized => raw[0] as f32 / 255.0,
+          5121 => raw[0] as f32,
+          5122 if normalized => (i16::from_le_bytes([raw[0], raw[1]]) as f32 / 32767.0).max(-1.0),
+          5122 => i16::from_le_bytes([raw[0], raw[1]]) as f32,
+          5123 if normalized => u16::from_le_bytes([raw[0], raw[1]]) as f32 / 65535.0,
+          5123 => u16::from_le_bytes([raw[0], raw[1]]) as f32,
+          5125 => u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f32,
+          _ => f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]),
+        });
+      }
+    }
+    Ok((values, components))
+  }
+
+  fn vectors<const N: usize>(&self, index: usize) -> Result<Vec<[f32; N]>, String> {
+    let (values, components) = self.floats(index)?;
+    if components != N {
+      return Err(format!("accessor {} has {} components where {} were expected", index, components, N));
+    }
+    Ok(values.chunks_exact(N).map(|chunk| chunk.try_into().unwrap()).collect())
+  }
+
+  fn indices(&self, index: usize) -> Result<Vec<u32>, String> {
+    // Indices are at most 32-bit integers, which floats can't all hold, so they're read directly.
+    let accessor = self.document.accessors.get(index).ok_or_else(|| format!("accessor {} doesn't exist", index))?;
+    if accessor.component_type != 5125 {
+      return Ok(self.floats(index)?.0.into_iter().map(|value| value as u32).collect());
+    }
+    let (bytes, stride) = self.view(accessor.buffer_view.ok_or("index accessor has no buffer view")?)?;
+    let stride = stride.unwrap_or(4);
+    (0..accessor.count)
+      .map(|element| {
+        let offset = accessor.byte_offset + element * stride;
+        bytes.get(offset..offset + 4).map(|raw| u32::from_le_bytes(raw.try_into().unwrap())).ok_or_else(|| format!("accessor {} runs past its buffer view", index))
+      })
+      .collect()
+  }
+
+  fn mesh(&self, mesh: &MeshDef) -> Result<GltfMesh, String> {
+    let mut primitives = Vec::new();
+    for primitive in &mesh.primitives {
+      if primitive.mode.unwrap_or(MODE_TRIANGLES) != MODE_TRIANGLES {
+        log::warn!("skipping a primitive of mesh {:?} that isn't a triangle list", mesh.name);
+        continue;
+      }
+      let positions = self.vectors::<3>(*primitive.attributes.get("POSITION").ok_or("primitive has no POSITION")?)?;
+      let normals = primitive.attributes.get("NORMAL").map(|index| self.vectors::<3>(*index)).transpose()?;
+      let uvs = primitive.attributes.get("TEXCOORD_0").map(|index| self.vectors::<2>(*index)).transpose()?;
+      let skin = match (primitive.attributes.get("JOINTS_0"), primitive.attributes.get("WEIGHTS_0")) {
+        (Some(joints), Some(weights)) => Some(self.skin_vertices(*joints, *weights, positions.len())?),
+        _ => None,
+      };
+      let indices = match primitive.indices {
+        Some(index) => self.indices(index)?,
+        None => (0..positions.len() as u32).collect(),
+      };
+
+      let mut builder = MeshBuilder::with_capacity(positions.len(), indices.len());
+      for (vertex, position) in positions.iter().enumerate() {
+        let normal = normals.as_ref().and_then(|normals| normals.get(vertex)).map_or(Vec3::ZERO, |normal| Vec3::from(*normal));
+        let uv = uvs.as_ref().and_then(|uvs| uvs.get(vertex)).map_or(Vec2::ZERO, |uv| Vec2::from(*uv));
+        builder.vertex(Vec3::from(*position), normal, uv);
+      }
+      for triangle in indices.chunks_exact(3) {
+        if triangle.iter().any(|index| *index as usize >= positions.len()) {
+          return Err("an index is past the last vertex".to_owned());
+        }
+        builder.triangle(triangle[0], triangle[1], triangle[2]);
+      }
+      if normals.is_none() {
+        builder.compute_normals();
+      }
+      primitives.push(GltfPrimitive { mesh: Arc::new(builder.build()), material: primitive.material, skin: skin.map(Arc::new) });
+    }
+    Ok(GltfMesh { name: mesh.name.clone().unwrap_or_default(), primitives })
+  }
+
+  // Joints and weights for `count` vertices, with weights scaled to add up to 1.
+  fn skin_vertices(&self, joints: usize, weights: usize, count: usize) -> Result<Vec<SkinVertex>, String> {
+    let joints = self.vectors::<4>(joints)?;
+    let weights = self.vectors::<4>(weights)?;
+    if joints.len() != count || weights.len() != count {
+      return Err(format!("{} vertices have {} joints and {} weights", count, joints.len(), weights.len()));
+    }
+    Ok(joints.iter().zip(&weights)
+      .map(|(joints, weights)| {
+        let total: f32 = weights.iter().sum();
+        // Unweighted vertices follow the first joint rather than collapsing to the origin.
+        let weights = if total > 0.0 { weights.map(|weight| weight / total) } else { [1.0, 0.0, 0.0, 0.0] };
+        SkinVertex { joints: joints.map(|joint| joint as u16), weights }
+      })
+      .collect())
+  }
+
+  fn skin(&self, skin: &SkinDef) -> Result<GltfSkin, String> {
+    if let Some(joint) = skin.joints.iter().find(|joint| **joint >= self.document.nodes.len()) {
+      return Err(format!("joint {} doesn't exist", joint));
+    }
+    let inverse_bind_matrices = match skin.inverse_bind_matrices {
+      Some(accessor) => self.vectors::<16>(accessor)?.iter().map(Mat4::from_cols_array).collect(),
+      None => vec![Mat4::IDENTITY; skin.joints.len()],
+    };
+    Ok(GltfSkin { name: skin.name.clone().unwrap_or_default(), joints: skin.joints.clone(), inverse_bind_matrices })
+  }
+
+  fn image(&self, index: usize, directory: &Path) -> Option<Texture> {
+    let image = &self.document.images[index];
+    let bytes = match (&image.uri, image.buffer_view) {
+      (Some(uri), _) => read_uri(uri, directory),
+      (None, Some(view)) => self.view(view).map(|(bytes, _)| bytes.to_vec()),
+      (None, None) => Err("no uri or buffer view".to_owned()),
+    };
+    let decoded = bytes.and_then(|bytes| image::load_from_memory(&bytes).map_err(|err| err.to_string()));
+    match decoded {
+      Ok(decoded) => {
+        let rgba = decoded.to_rgba8();
+        Some(Texture { size: UVec2::new(rgba.width(), rgba.height()), pixels: rgba.into_raw() })
+      }
+      Err(err) => {
+        log::warn!("couldn't load glTF image {}: {}", index, err);
+        None
+      }
+    }
+  }
+
+  fn animation(&self, animation: &AnimationDef) -> Result<GltfAnimation, String> {
+    let mut channels = Vec::new();
+    for channel in &animation.channels {
+      let node = match channel.target.node {
+        Some(node) => node,
+        None => continue,
+      };
+      let sampler = animation.samplers.get(channel.sampler).ok_or_else(|| format!("sampler {} doesn't exist", channel.sampler))?;
+      let interpolation = match sampler.interpolation.as_str() {
+        "STEP" => Interpolation::Step,
+        "CUBICSPLINE" => Interpolation::CubicSpline,
+        _ => Interpolation::Linear,
+      };
+      let times = self.floats(sampler.input)?.0;
+      let values = match channel.target.path.as_str() {
+        "translation" => ChannelValues::Translation(self.vectors::<3>(sampler.output)?.into_iter().map(Vec3::from).collect()),
+        "scale" => ChannelValues::Scale(self.vectors::<3>(sampler.output)?.into_iter().map(Vec3::from).collect()),
+        "rotation" => ChannelValues::Rotation(self.vectors::<4>(sampler.output)?.into_iter().map(Quat::from_array).collect()),
+        "weights" => ChannelValues::Weights(self.floats(sampler.output)?.0),
+        path => return Err(format!("unknown animation path {}", path)),
+      };
+      let per_key = if interpolation == Interpolation::CubicSpline { 3 } else { 1 };
+      let count = match &values {
+        ChannelValues::Translation(values) | ChannelValues::Scale(values) => values.len(),
+        ChannelValues::Rotation(values) => values.len(),
+        // Weights hold every morph target's value per keyframe.
+        ChannelValues::Weights(_) => times.len() * per_key,
+      };
+      if times.is_empty() || count != times.len() * per_key {
+        return Err(format!("channel for node {} has {} values for {} keyframes", node, count, times.len()));
+      }
+      channels.push(GltfChannel { node, interpolation, times, values });
+    }
+    let duration = channels.iter().filter_map(|channel| channel.times.last().copied()).fold(0.0, f32::max);
+    Ok(GltfAnimation { name: animation.name.clone().unwrap_or_default(), channels, duration })
+  }
+}
+
+// The glTF JSON schema, as much of it as is imported, with the spec's defaults.
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub(super) struct Document {
+  scene: Option<usize>,
+  scenes: Vec<SceneDef>,
+  nodes: Vec<NodeDef>,
+  meshes: Vec<MeshDef>,
+  accessors: Vec<AccessorDef>,
+  buffer_views: Vec<BufferViewDef>,
+  buffers: Vec<BufferDef>,
+  pub(super) materials: Vec<MaterialDef>,
+  textures: Vec<TextureDef>,
+  images: Vec<ImageDef>,
+  cameras: Vec<CameraDef>,
+  skins: Vec<SkinDef>,
+  animations: Vec<AnimationDef>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SceneDef {
+  nodes: Vec<usize>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct NodeDef {
+  name: Option<String>,
+  children: Vec<usize>,
+  mesh: Option<usize>,
+  camera: Option<usize>,
+  skin: Option<usize>,
+  matrix: Option<[f32; 16]>,
+  translation: Option<[f32; 3]>,
+  rotation: Option<[f32; 4]>,
+  scale: Option<[f32; 3]>,
+}
+
+impl NodeDef {
+  fn transform(&self) -> Transform {
+    match self.matrix {
+      Some(matrix) => transform_from_matrix(Mat4::from_cols_array(&matrix)),
+      None => Transform {
+        translation: self.translation.map_or(Vec3::ZERO, Vec3::from),
+        rotation: self.rotation.map_or(Quat::IDENTITY, Quat::from_array),
+        scale: self.scale.map_or(Vec3::ONE, Vec3::from),
+      },
+    }
+  }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct MeshDef {
+  name: Option<String>,
+  primitives: Vec<PrimitiveDef>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct PrimitiveDef {
+  attributes: HashMap<String, usize>,
+  indices: Option<usize>,
+  material: Option<usize>,
+  mode: Option<u32>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct AccessorDef {
+  buffer_view: Option<usize>,
+  byte_offset: usize,
+  component_type: u32,
+  normalized: bool,
+  count: usize,
+  #[serde(rename = "type")]
+  kind: String,
+  sparse: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct BufferViewDef {
+  buffer: usize,
+  byte_offset: usize,
+  byte_length: usize,
+  byte_stride: Option<usize>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct BufferDef {
+  uri: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub(super) struct MaterialDef {
+  name: Option<String>,
+  pbr_metallic_roughness: PbrDef,
+  normal_texture: Option<TextureInfoDef>,
+  emissive_texture: Option<TextureInfoDef>,
+  emissive_factor: [f32; 3],
+}
+
+#[derive(Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct PbrDef {
+  base_color_factor: [f32; 4],
+  base_color_texture: Option<TextureInfoDef>,
+  metallic_factor: f32,
+  roughness_factor: f32,
+  metallic_roughness_texture: Option<TextureInfoDef>,
+}
+
+impl Default for PbrDef {
+  fn default() -> Self {
+    PbrDef { base_color_factor: [1.0; 4], base_color_texture: None, metallic_factor: 1.0, roughness_factor: 1.0, metallic_roughness_texture: None }
+  }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct TextureInfoDef {
+  index: usize,
+  scale: f32, // only on normal textures
+}
+
+impl Default for TextureInfoDef {
+  fn default() -> Self {
+    TextureInfoDef { index: 0, scale: 1.0 }
+  }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct TextureDef {
+  source: Option<usize>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct ImageDef {
+  uri: Option<String>,
+  buffer_view: Option<usize>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct CameraDef {
+  perspective: Option<PerspectiveDef>,
+  orthographic: Option<OrthographicDef>,
+}
+
+impl CameraDef {
+  fn camera(&self) -> Camera {
+    match (&self.perspective, &self.orthographic) {
+      (Some(perspective), _) => Camera::perspective(perspective.yfov, perspective.znear, perspective.zfar.unwrap_or(1000.0)),
+      (None, Some(orthographic)) => Camera::orthographic(orthographic.ymag * 2.0, orthographic.znear, orthographic.zfar),
+      (None, None) => Camera::default(),
+    }
+  }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct PerspectiveDef {
+  yfov: f32,
+  znear: f32,
+  zfar: Option<f32>, // infinite when missing
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct OrthographicDef {
+  ymag: f32, // half the height
+  znear: f32,
+  zfar: f32,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct SkinDef {
+  name: Option<String>,
+  joints: Vec<usize>,
+  inverse_bind_matrices: Option<usize>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct AnimationDef {
+  name: Option<String>,
+  channels: Vec<ChannelDef>,
+  samplers: Vec<SamplerDef>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ChannelDef {
+  sampler: usize,
+  target: TargetDef,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct TargetDef {
+  node: Option<usize>,
+  path: String,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct SamplerDef {
+  input: usize,
+  output: usize,
+  interpolation: String,
+}
+
+impl Default for SamplerDef {
+  fn default() -> Self {
+    SamplerDef { input: 0, output: 0, interpolation: "LINEAR".to_owned() }
+  }
+}
@@ -1,23 +1,119 @@
-use std::borrow::Cow;
-use std::mem::size_of;
-use tobj::{LoadOptions, Material, Model};
-use wgpu::{Backends, DeviceDescriptor, Instance, PowerPreference, RequestAdapterOptions, Features, Limits, SurfaceConfiguration, TextureUsages, PresentMode, CompositeAlphaMode, TextureViewDescriptor, BufferDescriptor, BufferAddress, BufferUsages, CommandEncoderDescriptor, Label, RenderPassDescriptor, RenderPassColorAttachment, Operations, LoadOp, Color, RenderPipelineDescriptor, PipelineLayout, MultisampleState, VertexState, ShaderModule, ShaderModuleDescriptor, ShaderSource, PrimitiveState, VertexBufferLayout, VertexAttribute, VertexFormat, VertexStepMode};
-use wgpu::util::{BufferInitDescriptor, DeviceExt};
-use winit::window::CursorIcon::Default;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use wgpu::{Backends, DeviceDescriptor, Instance, PowerPreference, RequestAdapterOptions, Features, Limits, SurfaceConfiguration, TextureUsages, PresentMode, CompositeAlphaMode, TextureViewDescriptor, CommandBuffer, CommandEncoderDescriptor, RenderPassDescriptor, RenderPassColorAttachment, Operations, LoadOp, Color, RenderPipelineDescriptor, SurfaceTexture, MultisampleState, VertexState, ShaderModule, PrimitiveState, RenderPipeline, TextureFormat, FragmentState, ColorTargetState, BlendState, ColorWrites, BindGroupLayout, PipelineLayoutDescriptor, DepthStencilState, CompareFunction, RenderPassDepthStencilAttachment};
+use glam::{Mat3, Mat4, UVec2, Vec2};
 use winit::window::Window;
 
+use crate::game_engine::ecs::World;
+use crate::game_engine::time::{Instant, Time};
+use crate::game_engine::EngineError;
+use crate::game_engine::input::Input;
+use crate::game_engine::ui::{Fonts, UiDraw, UiRenderer};
+use crate::game_engine::window::GameWindow;
+use super::accessibility::{AccessibilityFilter, AccessibilitySettings};
+use super::bind_group_cache::BindGroupCache;
+use super::backend::VsyncMode;
+use super::camera::{Camera, CameraBuffer};
+use super::camera_2d::Camera2D;
+use super::compute::{Compute, ComputeRenderer};
+use super::cursor::CursorRenderer;
+use super::debug_draw::{DebugDraw, DebugLineRenderer};
+use super::debug_markers::DebugScope;
+use super::draw_list::DrawList;
+use super::buffer_pool::{BufferAllocation, BufferPool};
+use super::frame_allocator::{FrameAllocator, FrameSlice};
+use super::instancing::{InstanceBatch, InstanceRenderer};
+use super::lighting::{collect_local_lights, DirectionalLight, LightRenderer};
+use super::lightmap::LightmapRenderer;
+use super::lines::{collect_lines, LineRenderer};
+use super::mesh::Vertex;
+use super::model::{ModelRenderer, SceneDraw, DEPTH_FORMAT};
+use super::particles::ParticleSystem;
+use super::pixel_perfect::PixelPerfectTarget;
+use super::post_process::{PostProcessRenderer, PostProcessStack, HDR_FORMAT};
+use super::render_graph::{CustomPass, FrameTargets, PassStage, PrepareContext, RenderGraph, RenderPasses, TransientDesc, TransientPool};
+use super::shaders::ShaderManager;
+use super::skinning::SkinnedMeshRenderer;
+use super::skybox::{Environment, SkyboxRenderer};
+use super::sprite_batch::{SpriteBatch, SpriteRenderer};
+use super::text::TextRenderer;
+use super::render_target::{RenderTargetRenderer, RenderTargets};
+use super::screenshot::{ScreenshotRenderer, Screenshots};
+use super::texture::{GpuTextures, TextureManager};
+use super::upload::UploadQueue;
+use super::tilemap_renderer::TilemapRenderer;
+use super::viewport::ViewportScaling;
+
 pub struct GraphicsState {
   pub surface: wgpu::Surface, // The surface for the window we're rendering onto
   pub config: SurfaceConfiguration, // The surface's config (size, vsync, format)
+  present_modes: Vec<PresentMode>, // what the surface supports, for `set_vsync`
   pub device: wgpu::Device, // The gpu
   pub queue: wgpu::Queue, // Where commands are submitted to
 
-  pub models: Vec<Model>,
-  pub materials: Vec<Material>,
+  pub model_renderer: ModelRenderer, // the world's `StaticMesh` batches, with their materials
+  scene_draws: DrawList<SceneDraw>, // the model pass's meshes and static batches, sorted by material then depth
+  pub instances: InstanceRenderer, // the world's `InstanceBatch`, drawn in the model pass
+  pub skinned: SkinnedMeshRenderer, // the world's `SkinnedMesh`es, drawn in the model pass
+  pub lightmapped: LightmapRenderer, // the world's `Lightmapped` static meshes, drawn in the model pass
+  pub skybox: SkyboxRenderer, // the world's `Environment`, drawn behind the models
+  pub particles: ParticleSystem, // the world's `ParticleEmitter`s, simulated before the model pass and drawn last in it
+  pub compute: ComputeRenderer, // the world's `Compute` dispatches, run first each frame
+  // Built once by `setup` rather than every frame; `invalidate` drops it when the surface format changes.
+  model_pipeline: Option<RenderPipeline>,
+  pub shaders: ShaderManager, // `assets/shaders`, watched for edits
+
+  pub camera: CameraBuffer, // the world's `Camera`, uploaded each frame
+  pub lighting: LightRenderer, // the world's `DirectionalLight` and its shadow maps
+  pub frame_allocator: FrameAllocator, // transient per-frame uniform/vertex/instance data
+  pub buffer_pool: BufferPool, // long-lived vertex/index/uniform/storage data, sub-allocated
+  pub bind_groups: BindGroupCache,
+  pub lines: LineRenderer,
+  pub debug_lines: DebugLineRenderer,
+  pub textures: GpuTextures, // uploaded from the world's `TextureManager` each frame
+  pub uploads: UploadQueue, // staged copies, submitted ahead of each frame
+  pub sprites: SpriteRenderer,
+  pub tilemaps: TilemapRenderer,
+  pub render_targets: RenderTargetRenderer, // the world's `RenderTargets`, drawn before the window
+  pub ui: UiRenderer,
+  pub cursor: CursorRenderer, // the world's `GameWindow::cursor_image`, over the UI
+  // Added through the world's `RenderPasses`, and run at their stage of the frame's graph.
+  pub custom_passes: Vec<Box<dyn CustomPass>>,
+  pub transients: TransientPool, // textures the frame's graph allocates, like the depth buffer
+
+  // When set, the scene is drawn at this target's resolution and scaled up to the window.
+  pub pixel_perfect: Option<PixelPerfectTarget>,
+  // The world's `PostProcessStack`, run between the scene and the UI while it's active.
+  pub post_process: PostProcessRenderer,
+  hdr: bool, // whether the scene is drawn in `HDR_FORMAT` for `post_process`
+  // Colour-blindness filter, run last when the world's `AccessibilitySettings` ask for one.
+  pub accessibility: AccessibilityFilter,
+  pub screenshots: ScreenshotRenderer, // the world's `Screenshots`, copied from the finished frame
+
+  pub draw_calls: u32, // in the last submitted frame
+  // Filled in by the queue when the last submitted frame finishes on the GPU.
+  pub gpu_time: Arc<Mutex<Option<Duration>>>,
+}
+
+// What `prepare` took from the world that the rest of the frame still needs. Everything else has
+// already gone to the GPU, so it's all the render thread is sent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FramePacket {
+  color_matrix: Option<Mat3>,
+  post_process: bool,
+}
+
+// A frame between `begin_frame` and `present`: the acquired surface texture and what the post chain
+// needs to know about it.
+pub struct SurfaceFrame {
+  output: SurfaceTexture,
+  color_matrix: Option<Mat3>,
+  post_process: bool,
 }
 
 impl GraphicsState {
-  pub async fn new(window: &Window) -> Self {
+  pub async fn new(window: &Window, vsync: VsyncMode) -> Result<Self, EngineError> {
     let size = window.inner_size();
 
     let instance = Instance::new(Backends::all());
@@ -28,7 +124,7 @@ impl GraphicsState {
         compatible_surface: Some(&surface),
         force_fallback_adapter: false
       }
-    ).await.unwrap();
+    ).await.ok_or(EngineError::NoAdapter)?;
 
     let (device, queue) = adapter.request_device(
       &DeviceDescriptor {
@@ -38,51 +134,222 @@ impl GraphicsState {
         } else {
           Limits::default()
         },
-        label: None
+        label: Some("engine-device")
       },
       None
-    ).await.unwrap();
+    ).await.map_err(|err| EngineError::Device(err.to_string()))?;
 
+    let present_modes = surface.get_supported_present_modes(&adapter);
     let config = SurfaceConfiguration {
       usage: TextureUsages::RENDER_ATTACHMENT,
-      format: surface.get_supported_formats(&adapter)[0],
+      format: *surface.get_supported_formats(&adapter).first()
+        .ok_or_else(|| EngineError::Surface(format!("{} has no formats for this window", adapter.get_info().name)))?,
       width: size.width,
       height: size.height,
-      present_mode: PresentMode::Fifo,
+      present_mode: choose_present_mode(&present_modes, vsync),
       alpha_mode: CompositeAlphaMode::Auto
     };
     surface.configure(&device, &config);
 
-    let obj = tobj::load_obj(
-      "assets/teslacyberv3.0.obj",
-      &LoadOptions {
-        single_index: true,
-        triangulate: true,
-        ..LoadOptions::default()
-      }
-    ).unwrap();
+    // Games bring their own meshes, through `Assets::load::<Mesh>`.
+    let model_renderer = ModelRenderer::new(&device, &queue, &[], &[], Path::new(""));
 
-    let models = obj.0;
-    let materials = obj.1.unwrap();
+    let mut shaders = ShaderManager::new("assets/shaders");
+    shaders.register("model", include_str!("../../../assets/shaders/model.wgsl"));
+    let camera = CameraBuffer::new(&device);
+    let lighting = LightRenderer::new(&device, &camera.layout);
+    let instances = InstanceRenderer::new(&device, config.format, &camera.layout, &lighting.layout);
+    let skinned = SkinnedMeshRenderer::new(&device, config.format, &camera.layout, &lighting.layout);
+    let lightmapped = LightmapRenderer::new(&device, config.format, &camera.layout);
+    let skybox = SkyboxRenderer::new(&device, config.format);
+    let particles = ParticleSystem::new(&device, config.format, &camera.layout);
+    let compute = ComputeRenderer::new(&device);
+    let frame_allocator = FrameAllocator::new(&device, 1 << 20);
+    let buffer_pool = BufferPool::new(&device);
+    let lines = LineRenderer::new(&device, config.format, config.width, config.height);
+    let debug_lines = DebugLineRenderer::new(&device, config.format);
+    let sprites = SpriteRenderer::new(&device, config.format);
+    let tilemaps = TilemapRenderer::new(&device, config.format);
+    let ui = UiRenderer::new(&device, config.format);
+    let cursor = CursorRenderer::new(&device, config.format);
+    let post_process = PostProcessRenderer::new(&device, config.format);
+    let accessibility = AccessibilityFilter::new(&device, config.format);
+    let screenshots = ScreenshotRenderer::new(&device, config.format);
 
-    GraphicsState {
+    let mut state = GraphicsState {
       surface,
       device,
       queue,
       config,
-      models,
-      materials
-    }
+      present_modes,
+      model_renderer,
+      scene_draws: DrawList::new(),
+      instances,
+      skinned,
+      lightmapped,
+      skybox,
+      particles,
+      compute,
+      model_pipeline: None,
+      shaders,
+      camera,
+      lighting,
+      frame_allocator,
+      buffer_pool,
+      bind_groups: BindGroupCache::new(),
+      lines,
+      debug_lines,
+      textures: GpuTextures::new(),
+      uploads: UploadQueue::default(),
+      sprites,
+      tilemaps,
+      render_targets: RenderTargetRenderer::new(),
+      ui,
+      cursor,
+      custom_passes: Vec::new(),
+      transients: TransientPool::new(),
+      pixel_perfect: None,
+      post_process,
+      hdr: false,
+      accessibility,
+      screenshots,
+      draw_calls: 0,
+      gpu_time: Arc::new(Mutex::new(None))
+    };
+    state.setup()?;
+    Ok(state)
   }
 
   pub fn resize(&mut self, new_width: u32, new_height: u32) {
     if new_width > 0 && new_height > 0 {
       self.config.width = new_width;
       self.config.height = new_height;
-      self.surface.configure(&self.device, &self.config)
+      self.surface.configure(&self.device, &self.config);
+      if self.pixel_perfect.is_none() {
+        self.lines.set_viewport(&self.queue, new_width, new_height);
+      }
     }
   }
 
+  pub fn set_vsync(&mut self, vsync: VsyncMode) {
+    self.config.present_mode = choose_present_mode(&self.present_modes, vsync);
+    self.surface.configure(&self.device, &self.config);
+  }
+
+  // Builds the GPU state that only depends on the surface format. Cheap when nothing was invalidated.
+  pub fn setup(&mut self) -> Result<(), EngineError> {
+    if self.model_pipeline.is_none() {
+      let pipeline = match self.shaders.build(&self.device, "model", |module| self.create_model_pipeline(module)) {
+        Ok(pipeline) => pipeline,
+        Err(err) => {
+          log::error!("{}", err);
+          self.shaders.build_embedded(&self.device, "model", |module| self.create_model_pipeline(module)).map_err(EngineError::Shader)?
+        }
+      };
+      self.model_pipeline = Some(pipeline);
+    }
+    Ok(())
+  }
+
+  // Rebuilds the pipelines whose shader files were edited. A shader that doesn't compile is
+  // reported and the old pipeline kept.
+  pub fn reload_shaders(&mut self) {
+    for name in self.shaders.poll() {
+      if name == "model" {
+        match self.shaders.build(&self.device, "model", |module| self.create_model_pipeline(module)) {
+          Ok(pipeline) => {
+            log::info!("reloaded shader {}", name);
+            self.model_pipeline = Some(pipeline);
+          }
+          Err(err) => log::error!("{}", err),
+        }
+      }
+    }
+  }
+
+  fn create_model_pipeline(&self, module: &ShaderModule) -> RenderPipeline {
+    create_model_pipeline(&self.device, self.scene_format(), module, &self.camera.layout, &self.lighting.layout, &self.model_renderer.materials.layout)
+  }
+
+  // Drops what `setup` built so it's rebuilt for the current surface on the next frame.
+  pub fn invalidate(&mut self) {
+    self.model_pipeline = None;
+  }
+
+  // What the scene's pipelines draw into: the surface's format, or `HDR_FORMAT` while
+  // post-processing.
+  pub fn scene_format(&self) -> TextureFormat {
+    if self.hdr { HDR_FORMAT } else { self.config.format }
+  }
+
+  fn prepare_context(&mut self) -> PrepareContext<'_> {
+    PrepareContext { device: &self.device, queue: &self.queue, frame: &mut self.frame_allocator, buffers: &mut self.buffer_pool, uploads: &mut self.uploads }
+  }
+
+  // `data` in this frame's ring buffer, for vertex, index, instance or storage data. Valid until
+  // the frame's submitted.
+  pub fn alloc_vertex(&mut self, data: &[u8]) -> FrameSlice {
+    self.prepare_context().alloc_vertex(data)
+  }
+
+  // Like `alloc_vertex`, aligned for binding as a uniform buffer.
+  pub fn alloc_uniform(&mut self, data: &[u8]) -> FrameSlice {
+    self.prepare_context().alloc_uniform(data)
+  }
+
+  // `data` in `buffer_pool`, uploaded ahead of the next frame, until `free_buffer`.
+  pub fn alloc_buffer(&mut self, data: &[u8]) -> BufferAllocation {
+    self.prepare_context().alloc_buffer(data)
+  }
+
+  // Gives `allocation` back to the pool once the GPU's done with the frames that might use it.
+  pub fn free_buffer(&mut self, allocation: BufferAllocation) {
+    self.buffer_pool.free(allocation);
+  }
+
+  // Switches the scene to drawing in `HDR_FORMAT` or back to the surface's format, remaking what
+  // draws it.
+  fn set_hdr(&mut self, hdr: bool) {
+    self.hdr = hdr;
+    let format = self.scene_format();
+    let resolution = self.pixel_perfect.as_ref().map_or(UVec2::new(self.config.width, self.config.height), |target| target.resolution);
+    self.lines = LineRenderer::new(&self.device, format, resolution.x, resolution.y);
+    self.debug_lines = DebugLineRenderer::new(&self.device, format);
+    self.sprites = SpriteRenderer::new(&self.device, format);
+    self.tilemaps = TilemapRenderer::new(&self.device, format);
+    self.instances = InstanceRenderer::new(&self.device, format, &self.camera.layout, &self.lighting.layout);
+    self.skinned = SkinnedMeshRenderer::new(&self.device, format, &self.camera.layout, &self.lighting.layout);
+    self.lightmapped = LightmapRenderer::new(&self.device, format, &self.camera.layout);
+    self.skybox = SkyboxRenderer::new(&self.device, format);
+    self.particles.set_format(&self.device, format, &self.camera.layout);
+    if let Some(target) = &self.pixel_perfect {
+      self.pixel_perfect = Some(PixelPerfectTarget::with_scaling(&self.device, format, resolution, target.scaling, target.smooth));
+    }
+    self.invalidate();
+  }
+
+  // Switches to pixel-perfect rendering at `resolution`, or back to full resolution with `None`.
+  pub fn set_pixel_perfect(&mut self, resolution: Option<UVec2>) {
+    self.set_viewport_scaling(resolution.map(ViewportScaling::pixel_perfect).as_ref());
+  }
+
+  // Draws the scene at the resolution `scaling` asks for and fits it to the window, or at the
+  // window's resolution with `None`. Only remakes the target when something changed.
+  pub fn set_viewport_scaling(&mut self, scaling: Option<&ViewportScaling>) {
+    let window = UVec2::new(self.config.width, self.config.height);
+    let wanted = scaling.map(|scaling| (scaling.render_size(window), scaling.scaling, scaling.smooth));
+    let current = self.pixel_perfect.as_ref().map(|target| (target.resolution, target.scaling, target.smooth));
+    if wanted == current {
+      return;
+    }
+    self.pixel_perfect = wanted.map(|(resolution, scaling, smooth)| PixelPerfectTarget::with_scaling(&self.device, self.scene_format(), resolution, scaling, smooth));
+    let (width, height) = match &self.pixel_perfect {
+      Some(target) => (target.resolution.x, target.resolution.y),
+      None => (self.config.width, self.config.height),
+    };
+    self.lines.set_viewport(&self.queue, width, height);
+  }
+
   // pub fn input(&mut self, event: &WindowEvent) -> bool {
   //   todo!()
   // }
@@ -91,77 +358,250 @@ impl GraphicsState {
   //   todo!()
   // }
 
-  pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-    let output = self.surface.get_current_texture()?;
+  pub fn render(&mut self, world: &World) -> Result<(), wgpu::SurfaceError> {
+    let frame = self.begin_frame(world)?;
+    self.submit(&frame);
+    self.present(frame);
+    Ok(())
+  }
 
-    let view = output.texture.create_view(&TextureViewDescriptor::default());
+  // Uploads the world's camera, lines, debug draws and UI for this frame and acquires the surface texture.
+  pub fn begin_frame(&mut self, world: &World) -> Result<SurfaceFrame, wgpu::SurfaceError> {
+    let packet = self.prepare(world);
+    self.acquire(packet)
+  }
 
-    let mut encoder = self.device.create_command_encoder(&CommandEncoderDescriptor {
-      label: Some("my-command-encoder")
-    });
+  // Uploads the world's draw data for this frame. Doesn't wait on the GPU.
+  pub fn prepare(&mut self, world: &World) -> FramePacket {
+    crate::profile_scope!("prepare");
+    // First, so anything prepared below can allocate.
+    self.frame_allocator.begin_frame(&self.device);
+    self.buffer_pool.begin_frame();
+    self.reload_shaders();
+    if let Some(mut passes) = world.get_resource_mut::<RenderPasses>() {
+      passes.apply(&mut self.custom_passes);
+    }
+    let mut context = PrepareContext { device: &self.device, queue: &self.queue, frame: &mut self.frame_allocator, buffers: &mut self.buffer_pool, uploads: &mut self.uploads };
+    for pass in &mut self.custom_passes {
+      pass.prepare(&mut context, world);
+    }
+    // First, since switching formats remakes the renderers prepared below.
+    let post_stack = world.get_resource::<PostProcessStack>().filter(|stack| stack.is_active());
+    if post_stack.is_some() != self.hdr {
+      self.set_hdr(post_stack.is_some());
+    }
+    if let Some(stack) = &post_stack {
+      let time = world.get_resource::<Time>().map_or(0.0, |time| time.elapsed_seconds());
+      self.post_process.prepare(&self.device, &self.queue, stack, UVec2::new(self.config.width, self.config.height), time);
+    }
+    self.set_viewport_scaling(world.get_resource::<ViewportScaling>().as_deref());
+    let mut camera_view_projection = None;
+    let target = self.pixel_perfect.as_ref().map_or(UVec2::new(self.config.width, self.config.height), |target| target.resolution);
+    let aspect = target.x as f32 / target.y as f32;
+    let camera = world.get_resource::<Camera>();
+    if let Some(camera) = &camera {
+      let view_projection = camera.view_projection(aspect);
+      self.camera.write(&self.queue, view_projection, camera.position());
+      self.lines.set_camera(&self.queue, view_projection, camera.position());
+      self.debug_lines.set_view_projection(&self.queue, view_projection);
+      camera_view_projection = Some(view_projection);
+      if let Some(environment) = world.get_resource::<Environment>() {
+        self.skybox.prepare(&self.device, &self.queue, &environment, camera, aspect);
+      }
+    }
+    self.model_renderer.sync_statics(world, &self.device, &self.queue);
+    self.scene_draws.clear();
+    self.model_renderer.queue(&mut self.scene_draws, camera.as_deref().map_or(Mat4::IDENTITY, Camera::view));
+    self.scene_draws.sort();
+    let light = world.get_resource::<DirectionalLight>().map_or_else(DirectionalLight::default, |light| light.clone());
+    self.lighting.prepare(&self.device, &self.queue, &light, collect_local_lights(world), camera.as_deref().map(|camera| (camera, aspect)));
+    self.lines.prepare(&self.device, &self.queue, &collect_lines(world));
+    if let Some(mut debug_draw) = world.get_resource_mut::<DebugDraw>() {
+      self.debug_lines.prepare(&self.device, &self.queue, debug_draw.vertices());
+      debug_draw.clear();
+    }
+    if let (Some(mut text), Some(fonts), Some(mut texture_manager), Some(mut sprite_batch)) = (
+      world.get_resource_mut::<TextRenderer>(),
+      world.get_resource::<Fonts>(),
+      world.get_resource_mut::<TextureManager>(),
+      world.get_resource_mut::<SpriteBatch>(),
+    ) {
+      text.prepare(&fonts, &mut texture_manager, &mut sprite_batch);
+    }
+    if let Some(mut texture_manager) = world.get_resource_mut::<TextureManager>() {
+      texture_manager.reload_changed();
+      self.textures.sync(&self.device, &mut self.uploads, &mut texture_manager);
+    }
+    // After the textures, so dispatches can bind ones added this frame.
+    if let Some(mut compute) = world.get_resource_mut::<Compute>() {
+      self.compute.prepare(&self.device, &mut compute);
+    }
+    if let Some(mut batch) = world.get_resource_mut::<InstanceBatch>() {
+      self.instances.prepare(&self.device, &self.queue, &mut self.uploads, &batch);
+      batch.clear();
+    }
+    self.skinned.prepare(&self.device, &self.queue, &mut self.uploads, world);
+    self.lightmapped.prepare(&self.device, &self.queue, world);
+    let dt = world.get_resource::<Time>().map_or(0.0, |time| time.delta_seconds());
+    self.particles.prepare(&self.device, &self.queue, world, camera.as_deref(), dt);
+    if let Some(targets) = world.get_resource::<RenderTargets>() {
+      let format = self.scene_format();
+      self.render_targets.prepare(&self.device, &self.queue, &targets, &mut self.textures, &self.camera.layout, format);
+    }
+    if let Some(view_projection) = camera_view_projection {
+      let time = world.get_resource::<Time>().map_or(0.0, |time| time.elapsed_seconds());
+      self.tilemaps.prepare(&self.device, &self.queue, world, &self.textures, view_projection, time);
+    }
+    if let Some(mut sprite_batch) = world.get_resource_mut::<SpriteBatch>() {
+      // The 2D camera only stands in for a view-projection the batch doesn't have.
+      let own = sprite_batch.view_projection;
+      let camera_2d = world.get_resource::<Camera2D>().map(|camera| *camera);
+      if own.is_none() {
+        sprite_batch.view_projection = camera_2d.map(|camera| camera.view_projection());
+      }
+      // Only the camera's units or the target's pixels are known; a batch's own view-projection isn't snapped.
+      let snap = match (world.get_resource::<ViewportScaling>().is_some_and(|scaling| scaling.snaps_to_pixels()), own) {
+        (true, None) => Some(camera_2d.map_or(1.0, |camera| camera.pixels_per_unit())),
+        _ => None,
+      };
+      let target = self.pixel_perfect.as_ref().map_or(UVec2::new(self.config.width, self.config.height), |target| target.resolution);
+      self.sprites.prepare(&self.device, &self.queue, &mut sprite_batch, &self.textures, target, snap);
+      sprite_batch.view_projection = own;
+      sprite_batch.clear();
+    }
+    let window_size = Vec2::new(self.config.width as f32, self.config.height as f32);
+    if let Some(mut ui_draw) = world.get_resource_mut::<UiDraw>() {
+      let fonts = world.get_resource::<Fonts>();
+      let ui_scale = world.get_resource::<AccessibilitySettings>().map_or(1.0, |settings| settings.scaled(1.0));
+      self.ui.prepare(&self.device, &self.queue, fonts.as_deref(), ui_draw.commands(), window_size, ui_draw.scale);
+      ui_draw.clear();
+      ui_draw.fit(window_size, ui_scale);
+    }
+    let cursor_image = world.get_resource::<GameWindow>().and_then(|window| window.visible_cursor_image());
+    let mouse = world.get_resource::<Input>().map_or(Vec2::ZERO, |input| input.mouse_position());
+    self.cursor.prepare(&self.device, &self.queue, cursor_image, mouse, &self.textures, UVec2::new(self.config.width, self.config.height));
 
+    let window = UVec2::new(self.config.width, self.config.height);
+    let color_matrix: Option<Mat3> = world.get_resource::<AccessibilitySettings>()
+      .map(|settings| settings.color_matrix())
+      .filter(|matrix| *matrix != Mat3::IDENTITY);
+    if let Some(color_matrix) = color_matrix {
+      self.accessibility.prepare(&self.device, &self.queue, color_matrix, window);
+    }
 
+    if let Some(mut screenshots) = world.get_resource_mut::<Screenshots>() {
+      self.screenshots.prepare(&self.device, &mut screenshots, window);
+    }
 
-    let buffer = self.device.create_buffer_init(&BufferInitDescriptor {
-      label: Some("my-buffer"),
-      usage: BufferUsages::VERTEX,
-      contents: bytemuck::cast_slice(&self.models[0].mesh.positions[..])
-    });
+    FramePacket { color_matrix, post_process: post_stack.is_some() }
+  }
 
-    let buffer_layout = VertexBufferLayout {
-      array_stride: size_of::<[f32; 3]>() as BufferAddress,
-      step_mode: VertexStepMode::Vertex,
-      attributes: &[
-        VertexAttribute {
-          format: VertexFormat::Float32x3, // represents a vec3 in the shader code
-          shader_location: 0, // maps to the shader's @location
-          offset: 0 // Offset from the previous VertexAttribute - but we only have one, so it's zero.
-        }
-      ]
+  // Waits for the next surface texture to draw `packet`'s frame into.
+  pub fn acquire(&mut self, packet: FramePacket) -> Result<SurfaceFrame, wgpu::SurfaceError> {
+    crate::profile_scope!("acquire");
+    let output = match self.surface.get_current_texture() {
+      Ok(output) => output,
+      Err(err) => {
+        // The frame won't be submitted, but what `prepare` allocated is free to reuse once the
+        // work before it is done.
+        self.frame_allocator.end_frame(&self.queue);
+        self.buffer_pool.end_frame(&self.queue);
+        return Err(err);
+      }
     };
+    Ok(SurfaceFrame { output, color_matrix: packet.color_matrix, post_process: packet.post_process })
+  }
 
-    let shader_module = self.device.create_shader_module(ShaderModuleDescriptor {
-      label: Some("my-shader"),
-      source: ShaderSource::Wgsl(Cow::Borrowed(
-"
-struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
-};
-
-@vertex
-fn vs_main(
-    @builtin(vertex_index) in_vertex_index: u32,
-) -> VertexOutput {
-    var out: VertexOutput;
-    let x = f32(1 - i32(in_vertex_index)) * 0.5;
-    let y = f32(i32(in_vertex_index & 1u) * 2 - 1) * 0.5;
-    out.clip_position = vec4<f32>(x, y, 0.0, 1.0);
-    return out;
-}
-"
-      ))
-    });
+  // Records the frame's passes and submits them to the queue.
+  pub fn submit(&mut self, frame: &SurfaceFrame) {
+    let commands = self.record(frame);
+    crate::profile_scope!("submit");
+    // Uploads go first in the same submission, so the frame sees them finished.
+    self.queue.submit(self.uploads.finish().into_iter().chain(std::iter::once(commands)));
+    let submitted = Instant::now();
+    let gpu_time = self.gpu_time.clone();
+    self.queue.on_submitted_work_done(move || *gpu_time.lock().unwrap() = Some(submitted.elapsed()));
+    self.uploads.recall();
+    self.compute.after_submit();
+    self.screenshots.after_submit();
+    self.frame_allocator.end_frame(&self.queue);
+    self.buffer_pool.end_frame(&self.queue);
+    self.bind_groups.end_frame();
+  }
 
-    let render_pipeline = self.device.create_render_pipeline(&RenderPipelineDescriptor {
-      label: Some("my-render-pipeline"),
-      depth_stencil: None,
-      layout: None,
-      fragment: None,
-      multisample: MultisampleState::default(),
-      multiview: None,
-      vertex: VertexState {
-        buffers: &[buffer_layout],
-        module: &shader_module,
-        entry_point: "vertex-entry"
-      },
-      primitive: PrimitiveState::default()
+  // Records the frame's passes into a command buffer.
+  fn record(&mut self, frame: &SurfaceFrame) -> CommandBuffer {
+    crate::profile_scope!("record");
+    // Only fails if the built-in shader is broken; the frame goes ahead without models.
+    if let Err(err) = self.setup() {
+      log::error!("{}", err);
+    }
+    let window = UVec2::new(self.config.width, self.config.height);
+    let color_matrix = frame.color_matrix;
+    let view = frame.output.texture.create_view(&TextureViewDescriptor {
+      label: Some("surface-view"),
+      ..TextureViewDescriptor::default()
     });
 
-    { // we have this new scope so that `encoder` can be given back (it is borrowed here)
+    // The post chain runs backwards from the surface: scene -> pixel-perfect target -> post-process
+    // input -> filter target. Each stage that's off leaves the one before drawing into the next.
+    let mut graph = RenderGraph::new();
+    // A frame being captured is drawn into the screenshot texture, which is copied onto the surface last.
+    let window_surface = graph.import("surface", &view);
+    let surface = match self.screenshots.view() {
+      Some(capture_view) => graph.import("screenshot-target", capture_view),
+      None => window_surface,
+    };
+    let filtered = match (color_matrix, self.accessibility.view()) {
+      (Some(_), Some(filter_view)) => graph.import("accessibility-input", filter_view),
+      _ => surface,
+    };
+    let post = match (frame.post_process, self.post_process.view()) {
+      (true, Some(post_view)) => graph.import("post-process-input", post_view),
+      _ => filtered,
+    };
+    let scene = match &self.pixel_perfect {
+      Some(target) => graph.import("pixel-perfect-target", target.view()),
+      None => post,
+    };
+    let scene_size = self.pixel_perfect.as_ref().map_or(window, |target| target.resolution);
+    let depth = graph.create("depth", TransientDesc { size: scene_size, format: DEPTH_FORMAT });
+    let shadow_maps = graph.import_external("shadow-maps");
+    let render_targets = graph.import_external("render-targets");
+    let particles = graph.import_external("particles");
+    let compute = graph.import_external("compute");
+    let targets = FrameTargets {
+      scene,
+      depth,
+      window: filtered,
+      compute,
+      scene_size,
+      window_size: window,
+      scene_format: self.scene_format(),
+      window_format: self.config.format,
+      frame: &self.frame_allocator,
+      buffers: &self.buffer_pool
+    };
+
+    // Game compute first, so anything drawn this frame sees it. Shadows next, for everything lit to
+    // sample, then offscreen targets so the window's passes can sample them.
+    graph.add_pass("compute").write(compute).run(|encoder, _| self.compute.dispatch(&self.device, encoder, &mut self.uploads, &self.textures));
+    graph.add_pass("shadows").write(shadow_maps).run(|encoder, _| self.lighting.draw_shadows(encoder, &self.model_renderer, &self.instances));
+    if let Some(pipeline) = &self.model_pipeline {
+      graph.add_pass("render-targets").read(shadow_maps).write(render_targets)
+        .run(|encoder, _| self.render_targets.draw(encoder, &self.textures, &self.model_renderer, pipeline, &self.instances, &self.lighting));
+    }
+    graph.add_pass("particles-simulate").write(particles).run(|encoder, _| self.particles.simulate(encoder));
+    add_custom_passes(&self.custom_passes, &mut graph, PassStage::BeforeScene, &targets);
+
+    // Models get a pass of their own since they're the only thing drawn with depth. The depth
+    // buffer's only kept for custom passes that draw after it.
+    let keep_depth = self.custom_passes.iter().any(|pass| pass.stage() == PassStage::AfterOpaque);
+    graph.add_pass("model-pass").read(shadow_maps).read(render_targets).read(particles).write(scene).write(depth).run(|encoder, resources| {
       let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
-        label: Some("my-render-pass"),
+        label: Some("model-pass"),
         color_attachments: &[Some(RenderPassColorAttachment {
-          view: &view,
+          view: resources.view(scene),
           ops: Operations {
             load: LoadOp::Clear(Color {
               r: 0.1,
@@ -173,20 +613,153 @@ fn vs_main(
           },
           resolve_target: None
         })],
+        depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+          view: resources.view(depth),
+          depth_ops: Some(Operations { load: LoadOp::Clear(1.0), store: keep_depth }),
+          stencil_ops: None
+        })
+      });
+      if let Some(pipeline) = &self.model_pipeline {
+        render_pass.scope("models", |render_pass| self.model_renderer.draw_list(render_pass, pipeline, &self.camera.bind_group, &self.lighting.bind_group, &self.scene_draws));
+      }
+      render_pass.scope("instances", |render_pass| self.instances.draw(render_pass, &self.camera.bind_group, &self.lighting.bind_group));
+      render_pass.scope("skinned", |render_pass| self.skinned.draw(render_pass, &self.camera.bind_group, &self.lighting.bind_group));
+      render_pass.scope("lightmapped", |render_pass| self.lightmapped.draw(render_pass, &self.camera.bind_group));
+      // Last, so the depth test skips every pixel something already covers.
+      render_pass.scope("skybox", |render_pass| self.skybox.draw(render_pass));
+      // Blended over everything opaque, sky included.
+      render_pass.scope("particles", |render_pass| self.particles.draw(render_pass, &self.camera.bind_group));
+    });
+    add_custom_passes(&self.custom_passes, &mut graph, PassStage::AfterOpaque, &targets);
+
+    graph.add_pass("scene-pass").read(render_targets).write(scene).run(|encoder, resources| {
+      let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+        label: Some("scene-pass"),
+        color_attachments: &[Some(RenderPassColorAttachment {
+          view: resources.view(scene),
+          ops: Operations { load: LoadOp::Load, store: true },
+          resolve_target: None
+        })],
+        depth_stencil_attachment: None
+      });
+
+      render_pass.scope("tilemaps", |render_pass| self.tilemaps.draw(render_pass));
+      render_pass.scope("sprites", |render_pass| self.sprites.draw(render_pass));
+      render_pass.scope("lines", |render_pass| self.lines.draw(render_pass));
+      render_pass.scope("debug-lines", |render_pass| self.debug_lines.draw(render_pass));
+    });
+    add_custom_passes(&self.custom_passes, &mut graph, PassStage::AfterScene, &targets);
+
+    if let Some(target) = &self.pixel_perfect {
+      graph.add_pass("pixel-perfect").read(scene).write(post).run(|encoder, resources| target.blit(encoder, resources.view(post), window));
+    }
+    if frame.post_process {
+      graph.add_pass("post-process").read(post).write(filtered).run(|encoder, resources| self.post_process.draw(encoder, resources.view(filtered)));
+    }
+    add_custom_passes(&self.custom_passes, &mut graph, PassStage::AfterPostProcess, &targets);
+
+    // UI goes on at window resolution, after any pixel-perfect scaling.
+    graph.add_pass("ui-pass").write(filtered).run(|encoder, resources| {
+      let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+        label: Some("ui-pass"),
+        color_attachments: &[Some(RenderPassColorAttachment {
+          view: resources.view(filtered),
+          ops: Operations { load: LoadOp::Load, store: true },
+          resolve_target: None
+        })],
         depth_stencil_attachment: None
       });
+      render_pass.scope("ui", |render_pass| self.ui.draw(render_pass));
+      render_pass.scope("cursor", |render_pass| self.cursor.draw(render_pass));
+    });
+    add_custom_passes(&self.custom_passes, &mut graph, PassStage::AfterUi, &targets);
 
-      render_pass.set_vertex_buffer(0, buffer.slice(..));
-      render_pass.set_pipeline(&render_pipeline);
-      render_pass.draw(0..((self.models[0].mesh.positions.len() / 3) as u32), 0..1);
+    if color_matrix.is_some() {
+      graph.add_pass("accessibility-filter").read(filtered).write(surface).run(|encoder, resources| self.accessibility.apply(encoder, resources.view(surface)));
     }
 
-    // here's where we move `encoder` - which is why we have the scope above.
-    self.queue.submit(std::iter::once(encoder.finish()));
-    output.present();
+    if surface != window_surface {
+      graph.add_pass("screenshot").read(surface).write(window_surface).run(|encoder, resources| self.screenshots.draw(encoder, resources.view(window_surface)));
+    }
 
-    Ok(())
+    let mut encoder = self.device.create_command_encoder(&CommandEncoderDescriptor {
+      label: Some("frame-encoder")
+    });
+    graph.execute(&self.device, &mut encoder, &mut self.transients);
+    self.screenshots.copy(&self.device, &mut encoder);
+
+    self.draw_calls = self.model_renderer.draw_calls() + self.instances.draw_calls() + self.skinned.draw_calls() + self.lightmapped.draw_calls() + self.skybox.draw_calls() + self.particles.draw_calls() + self.compute.draw_calls() + self.lighting.draw_calls(&self.model_renderer, &self.instances) + self.render_targets.draw_calls(&self.model_renderer, &self.instances) + self.tilemaps.draw_calls() + self.sprites.draw_calls() + self.lines.draw_calls()
+      + self.debug_lines.draw_calls() + self.ui.draw_calls() + self.cursor.draw_calls()
+      + self.pixel_perfect.is_some() as u32 + color_matrix.is_some() as u32 + self.screenshots.draw_calls()
+      + if frame.post_process { self.post_process.draw_calls() } else { 0 };
+    encoder.finish()
+  }
+
+  pub fn present(&mut self, frame: SurfaceFrame) {
+    crate::profile_scope!("present");
+    // The surface still works but no longer matches the window exactly; set it up again.
+    let suboptimal = frame.output.suboptimal;
+    frame.output.present();
+    if suboptimal {
+      self.surface.configure(&self.device, &self.config);
+    }
   }
 }
 
 // fn convert_to_2d_array
+
+fn add_custom_passes<'a>(passes: &'a [Box<dyn CustomPass>], graph: &mut RenderGraph<'a>, stage: PassStage, targets: &FrameTargets<'a>) {
+  for pass in passes.iter().filter(|pass| pass.stage() == stage) {
+    pass.add_to(graph, targets);
+  }
+}
+
+fn choose_present_mode(supported: &[PresentMode], vsync: VsyncMode) -> PresentMode {
+  let (wanted, fallback) = vsync.present_modes();
+  // The `Auto` modes pick among what's supported themselves.
+  if matches!(wanted, PresentMode::AutoVsync | PresentMode::AutoNoVsync) || supported.contains(&wanted) {
+    wanted
+  } else {
+    fallback
+  }
+}
+
+// The pipeline for `models`, drawing into surfaces of `format` as seen by the camera and lit by the
+// directional light.
+fn create_model_pipeline(device: &wgpu::Device, format: TextureFormat, shader_module: &ShaderModule, camera_layout: &BindGroupLayout, light_layout: &BindGroupLayout, material_layout: &BindGroupLayout) -> RenderPipeline {
+
+  let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+    label: Some("model-pipeline-layout"),
+    bind_group_layouts: &[camera_layout, light_layout, material_layout],
+    push_constant_ranges: &[]
+  });
+
+  device.create_render_pipeline(&RenderPipelineDescriptor {
+    label: Some("model-pipeline"),
+    depth_stencil: Some(DepthStencilState {
+      format: DEPTH_FORMAT,
+      depth_write_enabled: true,
+      depth_compare: CompareFunction::Less,
+      stencil: Default::default(),
+      bias: Default::default()
+    }),
+    layout: Some(&pipeline_layout),
+    fragment: Some(FragmentState {
+      module: shader_module,
+      entry_point: "fs_main",
+      targets: &[Some(ColorTargetState {
+        format,
+        blend: Some(BlendState::REPLACE),
+        write_mask: ColorWrites::ALL
+      })]
+    }),
+    multisample: MultisampleState::default(),
+    multiview: None,
+    vertex: VertexState {
+      buffers: &[Vertex::layout()],
+      module: shader_module,
+      entry_point: "vs_main"
+    },
+    primitive: PrimitiveState::default()
+  })
+}
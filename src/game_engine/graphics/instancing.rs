@@ -0,0 +1,343 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::ops::Range;
+use std::sync::Arc;
+use bytemuck::{Pod, Zeroable};
+use glam::Mat4;
+use wgpu::{BindGroup, BindGroupLayout, BlendState, Buffer, BufferAddress, BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites, CompareFunction, DepthStencilState, Device, FragmentState, MultisampleState, PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPass, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, TextureFormat, VertexAttribute, VertexBufferLayout, VertexFormat, VertexState, VertexStepMode};
+
+use crate::game_engine::ecs::{Transform, World};
+use crate::game_engine::time::Interpolation;
+use super::color::Color;
+use super::culling::CullFilter;
+use super::lighting::LIGHTING_WGSL;
+use super::mesh::{GpuMesh, Mesh, Vertex};
+use super::model::DEPTH_FORMAT;
+use super::static_batch::StaticMesh;
+use super::upload::UploadQueue;
+
+// One copy of an instanced mesh: where it goes and what colour it's drawn in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Instance {
+  pub transform: Mat4,
+  pub color: Color,
+}
+
+impl Instance {
+  pub fn new(transform: Mat4) -> Self {
+    Instance { transform, color: Color::WHITE }
+  }
+
+  pub fn with_color(mut self, color: Color) -> Self {
+    self.color = color;
+    self
+  }
+
+  pub fn raw(&self) -> InstanceRaw {
+    InstanceRaw { model: self.transform.to_cols_array_2d(), color: self.color.to_array() }
+  }
+}
+
+impl From<&Transform> for Instance {
+  fn from(transform: &Transform) -> Self {
+    Instance::new(transform.matrix())
+  }
+}
+
+// An `Instance` as the GPU reads it, one per instance from vertex buffer slot 1.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct InstanceRaw {
+  pub model: [[f32; 4]; 4],
+  pub color: [f32; 4],
+}
+
+impl InstanceRaw {
+  const ATTRIBUTES: [VertexAttribute; 5] = [
+    VertexAttribute { format: VertexFormat::Float32x4, shader_location: 3, offset: 0 },
+    VertexAttribute { format: VertexFormat::Float32x4, shader_location: 4, offset: 16 },
+    VertexAttribute { format: VertexFormat::Float32x4, shader_location: 5, offset: 32 },
+    VertexAttribute { format: VertexFormat::Float32x4, shader_location: 6, offset: 48 },
+    VertexAttribute { format: VertexFormat::Float32x4, shader_location: 7, offset: 64 },
+  ];
+
+  // The model matrix's columns at shader locations 3 to 6 and the colour at 7, following
+  // `Vertex::layout`'s 0 to 2.
+  pub fn layout() -> VertexBufferLayout<'static> {
+    VertexBufferLayout {
+      array_stride: size_of::<InstanceRaw>() as BufferAddress,
+      step_mode: VertexStepMode::Instance,
+      attributes: &InstanceRaw::ATTRIBUTES
+    }
+  }
+}
+
+// Draws an entity's `mesh` at its `Transform` through the world's `InstanceBatch`, so every entity
+// sharing the mesh goes in one draw call.
+#[derive(Debug, Clone)]
+pub struct InstancedMesh {
+  pub mesh: Arc<Mesh>, // shared with every other entity drawn with it
+  pub color: Color,
+}
+
+impl InstancedMesh {
+  pub fn new(mesh: Arc<Mesh>) -> Self {
+    InstancedMesh { mesh, color: Color::WHITE }
+  }
+}
+
+// Immediate-mode instanced meshes, as a resource. Queue instances during the frame and the renderer
+// draws each mesh's in a single call in the model pass, lit like the models, then clears the list.
+// Meshes are told apart by their `Arc`, so share one rather than cloning the `Mesh`.
+pub struct InstanceBatch {
+  pub enabled: bool,
+  meshes: Vec<(Arc<Mesh>, Vec<InstanceRaw>)>,
+}
+
+impl InstanceBatch {
+  pub fn new() -> Self {
+    InstanceBatch { enabled: true, meshes: Vec::new() }
+  }
+
+  pub fn draw_instanced(&mut self, mesh: &Arc<Mesh>, instances: &[Instance]) {
+    if self.enabled {
+      self.instances(mesh).extend(instances.iter().map(Instance::raw));
+    }
+  }
+
+  pub fn draw(&mut self, mesh: &Arc<Mesh>, instance: Instance) {
+    if self.enabled {
+      self.instances(mesh).push(instance.raw());
+    }
+  }
+
+  fn instances(&mut self, mesh: &Arc<Mesh>) -> &mut Vec<InstanceRaw> {
+    let index = match self.meshes.iter().position(|(other, _)| Arc::ptr_eq(other, mesh)) {
+      Some(index) => index,
+      None => {
+        self.meshes.push((mesh.clone(), Vec::new()));
+        self.meshes.len() - 1
+      }
+    };
+    &mut self.meshes[index].1
+  }
+
+  pub fn instance_count(&self) -> usize {
+    self.meshes.iter().map(|(_, instances)| instances.len()).sum()
+  }
+
+  pub fn clear(&mut self) {
+    self.meshes.clear();
+  }
+}
+
+impl Default for InstanceBatch {
+  fn default() -> Self {
+    InstanceBatch::new()
+  }
+}
+
+// Queues every entity with a `Transform` and an `InstancedMesh` into the world's `InstanceBatch`,
+// leaving out static ones and those culling hid. The engine calls it every frame before drawing.
+pub fn draw_instanced_entities(world: &World) {
+  let mut batch = match world.get_resource_mut::<InstanceBatch>() {
+    Some(batch) => batch,
+    None => return,
+  };
+  let culled = CullFilter::new(world);
+  let interpolation = Interpolation::new(world);
+  world.query::<(&Transform, &InstancedMesh)>().for_each(|entity, (transform, instanced)| {
+    // Static ones are merged into the model pass's batches instead.
+    if world.has::<StaticMesh>(entity) || culled.is_culled(world, entity) {
+      return;
+    }
+    let transform = &interpolation.transform(world, entity, transform);
+    batch.draw(&instanced.mesh, Instance::from(transform).with_color(instanced.color));
+  });
+}
+
+// A mesh drawn through the batch, uploaded once and kept while the game still holds it.
+struct CachedMesh {
+  mesh: Arc<Mesh>,
+  gpu_mesh: GpuMesh,
+}
+
+// Draws an `InstanceBatch`: the instances of every mesh go in one buffer, and each mesh is one
+// indexed draw over its range of it. Uses the camera's and light's bind group layouts, so it can
+// draw in the model pass or a render target's.
+pub struct InstanceRenderer {
+  pipeline: RenderPipeline,
+  meshes: HashMap<usize, CachedMesh>, // by the `Arc`'s address
+  instances: Vec<InstanceRaw>,
+  instance_buffer: Option<Buffer>,
+  draws: Vec<(usize, Range<u32>)>,
+}
+
+impl InstanceRenderer {
+  pub fn new(device: &Device, format: TextureFormat, camera_layout: &BindGroupLayout, light_layout: &BindGroupLayout) -> Self {
+    let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+      label: Some("instanced-mesh-shader"),
+      source: ShaderSource::Wgsl(Cow::Owned(LIGHTING_WGSL.to_owned() +
+"
+struct Camera {
+    view_proj: mat4x4<f32>,
+    position: vec4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+};
+
+struct InstanceInput {
+    @location(3) model_0: vec4<f32>,
+    @location(4) model_1: vec4<f32>,
+    @location(5) model_2: vec4<f32>,
+    @location(6) model_3: vec4<f32>,
+    @location(7) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) normal: vec3<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) world_position: vec3<f32>,
+};
+
+@vertex
+fn vs_main(in: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let model = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+    var out: VertexOutput;
+    let world_position = model * vec4<f32>(in.position, 1.0);
+    out.clip_position = camera.view_proj * world_position;
+    out.world_position = world_position.xyz;
+    // Fine for uniform scales, which is what instanced props mostly have.
+    out.normal = (model * vec4<f32>(in.normal, 0.0)).xyz;
+    out.color = instance.color;
+    return out;
+}
+
+// Non-metal and fairly rough, like the default model material.
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    var surface: Surface;
+    surface.albedo = in.color.rgb;
+    surface.metallic = 0.0;
+    surface.roughness = 0.7;
+    surface.position = in.world_position;
+    surface.normal = in.normal;
+    return vec4<f32>(shade(surface), in.color.a);
+}
+"
+      ))
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+      label: Some("instanced-mesh-pipeline-layout"),
+      bind_group_layouts: &[camera_layout, light_layout],
+      push_constant_ranges: &[]
+    });
+
+    let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+      label: Some("instanced-mesh-pipeline"),
+      layout: Some(&pipeline_layout),
+      vertex: VertexState {
+        module: &shader_module,
+        entry_point: "vs_main",
+        buffers: &[Vertex::layout(), InstanceRaw::layout()]
+      },
+      fragment: Some(FragmentState {
+        module: &shader_module,
+        entry_point: "fs_main",
+        targets: &[Some(ColorTargetState {
+          format,
+          blend: Some(BlendState::REPLACE),
+          write_mask: ColorWrites::ALL
+        })]
+      }),
+      primitive: PrimitiveState::default(),
+      depth_stencil: Some(DepthStencilState {
+        format: DEPTH_FORMAT,
+        depth_write_enabled: true,
+        depth_compare: CompareFunction::Less,
+        stencil: Default::default(),
+        bias: Default::default()
+      }),
+      multisample: MultisampleState::default(),
+      multiview: None
+    });
+
+    InstanceRenderer { pipeline, meshes: HashMap::new(), instances: Vec::new(), instance_buffer: None, draws: Vec::new() }
+  }
+
+  // Uploads meshes drawn for the first time (through `uploads`) and this frame's instances, and
+  // forgets meshes nothing else holds any more.
+  pub fn prepare(&mut self, device: &Device, queue: &Queue, uploads: &mut UploadQueue, batch: &InstanceBatch) {
+    self.meshes.retain(|_, cached| Arc::strong_count(&cached.mesh) > 1);
+    self.instances.clear();
+    self.draws.clear();
+    for (mesh, instances) in &batch.meshes {
+      if instances.is_empty() {
+        continue;
+      }
+      let key = Arc::as_ptr(mesh) as usize;
+      self.meshes.entry(key).or_insert_with(|| CachedMesh {
+        mesh: mesh.clone(),
+        gpu_mesh: GpuMesh::staged(device, uploads, &mut Mesh::clone(mesh)),
+      });
+      let start = self.instances.len() as u32;
+      self.instances.extend_from_slice(instances);
+      self.draws.push((key, start..self.instances.len() as u32));
+    }
+
+    if self.instances.is_empty() {
+      return;
+    }
+    let size = std::mem::size_of_val(self.instances.as_slice()) as BufferAddress;
+    if self.instance_buffer.as_ref().is_none_or(|buffer| buffer.size() < size) {
+      self.instance_buffer = Some(device.create_buffer(&BufferDescriptor {
+        label: Some("mesh-instances"),
+        size: size.next_power_of_two(),
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false
+      }));
+    }
+    queue.write_buffer(self.instance_buffer.as_ref().unwrap(), 0, bytemuck::cast_slice(&self.instances));
+  }
+
+  // One per mesh with instances this frame.
+  pub fn draw_calls(&self) -> u32 {
+    self.draws.len() as u32
+  }
+
+  pub fn instance_count(&self) -> u32 {
+    self.instances.len() as u32
+  }
+
+  // Draws into a pass with the model depth buffer, seen through `camera` and lit by `light`.
+  pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>, camera: &'a BindGroup, light: &'a BindGroup) {
+    if self.instance_buffer.is_none() || self.draws.is_empty() {
+      return;
+    }
+    render_pass.set_pipeline(&self.pipeline);
+    render_pass.set_bind_group(0, camera, &[]);
+    render_pass.set_bind_group(1, light, &[]);
+    self.draw_depth(render_pass);
+  }
+
+  // Just the instances, with whatever pipeline the caller set, for depth-only passes like shadows.
+  pub fn draw_depth<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+    let buffer = match &self.instance_buffer {
+      Some(buffer) if !self.draws.is_empty() => buffer,
+      _ => return,
+    };
+    render_pass.set_vertex_buffer(1, buffer.slice(..));
+    for (key, instances) in &self.draws {
+      self.meshes[key].gpu_mesh.draw_instanced(render_pass, instances.clone());
+    }
+  }
+}
@@ -0,0 +1,683 @@
+use std::borrow::Cow;
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec3};
+use serde::{Deserialize, Serialize};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferBindingType, BufferUsages, CommandEncoder, CompareFunction, DepthBiasState, DepthStencilState, Device, Extent3d, FilterMode, LoadOp, MultisampleState, Operations, PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPassDepthStencilAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor, ShaderModule, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureDescriptor, TextureDimension, TextureSampleType, TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension, VertexBufferLayout, VertexState};
+
+use crate::game_engine::ecs::{Transform, World};
+use crate::game_engine::time::Interpolation;
+use super::camera::{Camera, CameraUniforms};
+use super::color::Color;
+use super::instancing::{InstanceRaw, InstanceRenderer};
+use super::mesh::Vertex;
+use super::model::{ModelRenderer, DEPTH_FORMAT};
+
+pub const MAX_CASCADES: u32 = 4;
+// Point and spot lights drawn at once. Past this the ones furthest from the camera are left out.
+pub const MAX_LIGHTS: usize = 16;
+
+// The lights' bindings at group 1 and `shade`, which lights a surface with them, for the lit
+// shaders. `assets/shaders/model.wgsl` carries its own copy, since shader files can't include.
+pub const LIGHTING_WGSL: &str = "
+struct Light {
+    to_light: vec4<f32>,
+    color: vec4<f32>, // rgb times intensity, with the ambient share in a
+    camera_position: vec4<f32>,
+    camera_forward: vec4<f32>,
+    splits: vec4<f32>, // how far from the camera each cascade reaches
+    shadow: vec4<f32>, // cascade count, normal offset, texel size
+    cascades: array<mat4x4<f32>, 4>,
+};
+@group(1) @binding(0)
+var<uniform> light: Light;
+@group(1) @binding(1)
+var shadow_map: texture_depth_2d_array;
+@group(1) @binding(2)
+var shadow_sampler: sampler_comparison;
+
+struct LocalLight {
+    position: vec4<f32>, // with the range in w
+    color: vec4<f32>, // rgb times intensity
+    direction: vec4<f32>, // a spot's axis, with the cosine of its outer angle in w
+    cone: vec4<f32>, // the cosine of a spot's inner angle in x
+};
+struct LocalLights {
+    count: vec4<u32>,
+    lights: array<LocalLight, 16>,
+};
+@group(1) @binding(3)
+var<uniform> local_lights: LocalLights;
+
+// 1 where the light reaches `position`, 0 in full shadow, with a 3x3 PCF edge.
+fn shadow(position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    let count = i32(light.shadow.x);
+    let depth = dot(position - light.camera_position.xyz, light.camera_forward.xyz);
+    var cascade = 0;
+    loop {
+        if cascade >= count || depth <= light.splits[cascade] {
+            break;
+        }
+        cascade += 1;
+    }
+    if cascade >= count {
+        return 1.0;
+    }
+    let clip = light.cascades[cascade] * vec4<f32>(position + normal * light.shadow.y, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    if any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0 {
+        return 1.0;
+    }
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y += 1) {
+        for (var x = -1; x <= 1; x += 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * light.shadow.z;
+            lit += textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, cascade, ndc.z);
+        }
+    }
+    return lit / 9.0;
+}
+
+// What `shade` needs to know about a point on a surface.
+struct Surface {
+    albedo: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    position: vec3<f32>,
+    normal: vec3<f32>,
+};
+
+// Cook-Torrance with a GGX distribution, Smith geometry and Schlick's Fresnel, for light of
+// `radiance` arriving from `to_light`. Scaled by pi so a white light head-on lights a white matte
+// surface white, as the old lighting did.
+fn brdf(surface: Surface, normal: vec3<f32>, to_view: vec3<f32>, to_light: vec3<f32>, radiance: vec3<f32>) -> vec3<f32> {
+    let n_dot_l = max(dot(normal, to_light), 0.0);
+    let n_dot_v = max(dot(normal, to_view), 0.0001);
+    let half_vector = normalize(to_light + to_view);
+    let n_dot_h = max(dot(normal, half_vector), 0.0);
+    let roughness = clamp(surface.roughness, 0.04, 1.0);
+
+    let alpha = roughness * roughness;
+    let alpha2 = alpha * alpha;
+    let denominator = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    let distribution = alpha2 / (3.14159265 * denominator * denominator);
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    let geometry = n_dot_v / (n_dot_v * (1.0 - k) + k) * n_dot_l / (n_dot_l * (1.0 - k) + k);
+    let f0 = mix(vec3<f32>(0.04), surface.albedo, surface.metallic);
+    let fresnel = f0 + (vec3<f32>(1.0) - f0) * pow(1.0 - max(dot(half_vector, to_view), 0.0), 5.0);
+
+    let specular = distribution * geometry * fresnel / (4.0 * n_dot_v * n_dot_l + 0.0001);
+    let diffuse = (vec3<f32>(1.0) - fresnel) * (1.0 - surface.metallic) * surface.albedo;
+    return (diffuse + specular * 3.14159265) * radiance * n_dot_l;
+}
+
+fn shade(surface: Surface) -> vec3<f32> {
+    let to_view = normalize(light.camera_position.xyz - surface.position);
+    // Two-sided, since OBJ exports don't agree on winding.
+    var normal = normalize(surface.normal);
+    if dot(normal, to_view) < 0.0 {
+        normal = -normal;
+    }
+    let ambient = light.color.a;
+    let sun = light.color.rgb * shadow(surface.position, normal);
+    var color = surface.albedo * ambient + (1.0 - ambient) * brdf(surface, normal, to_view, light.to_light.xyz, sun);
+
+    for (var i = 0u; i < local_lights.count.x; i += 1u) {
+        let local = local_lights.lights[i];
+        let offset = local.position.xyz - surface.position;
+        let distance = length(offset);
+        let to_light = offset / max(distance, 0.0001);
+        // Inverse-square, windowed to reach zero at the range.
+        let window = clamp(1.0 - pow(distance / local.position.w, 4.0), 0.0, 1.0);
+        let attenuation = window * window / (distance * distance + 1.0);
+        let cone = clamp((dot(-to_light, local.direction.xyz) - local.direction.w) / max(local.cone.x - local.direction.w, 0.0001), 0.0, 1.0);
+        color += brdf(surface, normal, to_view, to_light, local.color.rgb * attenuation * cone);
+    }
+    return color;
+}
+";
+
+// How a `DirectionalLight` casts shadows: the camera's view out to `distance` is split into
+// `cascades` slices, near ones drawn with more shadow-map texels per metre than far ones.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShadowSettings {
+  pub enabled: bool,
+  pub resolution: u32, // of each cascade's square map, in texels
+  pub cascades: u32, // 1 to `MAX_CASCADES`
+  pub distance: f32, // from the camera; nothing further away is shadowed
+  pub split_lambda: f32, // 0 splits `distance` evenly, 1 logarithmically
+  pub normal_offset: f32, // world units surfaces are pushed out along their normal against acne
+}
+
+impl Default for ShadowSettings {
+  fn default() -> Self {
+    ShadowSettings { enabled: true, resolution: 2048, cascades: 3, distance: 50.0, split_lambda: 0.6, normal_offset: 0.05 }
+  }
+}
+
+// The sun, as a resource: parallel light from one direction lighting the models and instanced
+// meshes, with cascaded shadows.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DirectionalLight {
+  pub direction: Vec3, // the way the light travels, e.g. `Vec3::NEG_Y` for noon
+  pub color: Color,
+  pub intensity: f32,
+  pub ambient: f32, // the share of light surfaces get facing away from it or in shadow
+  pub shadows: ShadowSettings,
+}
+
+impl Default for DirectionalLight {
+  fn default() -> Self {
+    DirectionalLight {
+      direction: Vec3::new(-0.4, -1.0, -0.6).normalize(),
+      color: Color::WHITE,
+      intensity: 1.0,
+      ambient: 0.25,
+      shadows: ShadowSettings::default(),
+    }
+  }
+}
+
+// A light shining equally every way from its entity's `Transform`, fading out by `range`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PointLight {
+  pub color: Color,
+  pub intensity: f32,
+  pub range: f32, // in world units; nothing further away is lit
+}
+
+impl PointLight {
+  pub fn new(color: Color, intensity: f32, range: f32) -> Self {
+    PointLight { color, intensity, range }
+  }
+}
+
+impl Default for PointLight {
+  fn default() -> Self {
+    PointLight::new(Color::WHITE, 1.0, 10.0)
+  }
+}
+
+// A cone of light down its entity's `Transform::forward`, full strength inside `inner_angle` and
+// fading to nothing at `outer_angle`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpotLight {
+  pub color: Color,
+  pub intensity: f32,
+  pub range: f32,
+  pub inner_angle: f32, // from the axis to the cone's edge, in radians
+  pub outer_angle: f32,
+}
+
+impl SpotLight {
+  pub fn new(color: Color, intensity: f32, range: f32, angle: f32) -> Self {
+    SpotLight { color, intensity, range, inner_angle: angle * 0.75, outer_angle: angle }
+  }
+}
+
+impl Default for SpotLight {
+  fn default() -> Self {
+    SpotLight::new(Color::WHITE, 1.0, 15.0, 30f32.to_radians())
+  }
+}
+
+// A point or spot light where it is in the world, as the renderer takes them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LocalLight {
+  pub position: Vec3,
+  pub direction: Vec3,
+  pub color: Color,
+  pub intensity: f32,
+  pub range: f32,
+  pub cone: Option<(f32, f32)>, // a spot's inner and outer angles
+}
+
+impl LocalLight {
+  fn raw(&self) -> LocalLightRaw {
+    // A point light's cone lets everything through.
+    let (inner, outer) = self.cone.map_or((-1.0, -2.0), |(inner, outer)| (inner.min(outer).cos(), outer.cos()));
+    let radiance = Vec3::new(self.color.r, self.color.g, self.color.b) * self.intensity;
+    LocalLightRaw {
+      position: self.position.extend(self.range.max(f32::EPSILON)).to_array(),
+      color: radiance.extend(1.0).to_array(),
+      direction: self.direction.normalize_or_zero().extend(outer).to_array(),
+      cone: [inner, 0.0, 0.0, 0.0],
+    }
+  }
+}
+
+// Every entity with a `Transform` and a `PointLight` or `SpotLight`.
+pub fn collect_local_lights(world: &World) -> Vec<LocalLight> {
+  let mut lights = Vec::new();
+  let interpolation = Interpolation::new(world);
+  world.query::<(&Transform, &PointLight)>().for_each(|entity, (transform, light)| {
+    let transform = interpolation.transform(world, entity, transform);
+    lights.push(LocalLight { position: transform.translation, direction: Vec3::ZERO, color: light.color, intensity: light.intensity, range: light.range, cone: None });
+  });
+  world.query::<(&Transform, &SpotLight)>().for_each(|entity, (transform, light)| {
+    let transform = interpolation.transform(world, entity, transform);
+    lights.push(LocalLight {
+      position: transform.translation,
+      direction: transform.forward(),
+      color: light.color,
+      intensity: light.intensity,
+      range: light.range,
+      cone: Some((light.inner_angle, light.outer_angle)),
+    });
+  });
+  lights
+}
+
+// One slice of the view's shadows: the light's view-projection over it and how far from the camera
+// it reaches.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cascade {
+  pub view_projection: Mat4,
+  pub far: f32,
+}
+
+impl DirectionalLight {
+  pub fn new(direction: Vec3) -> Self {
+    DirectionalLight { direction, ..DirectionalLight::default() }
+  }
+
+  pub fn with_shadows(mut self, shadows: ShadowSettings) -> Self {
+    self.shadows = shadows;
+    self
+  }
+
+  fn unit_direction(&self) -> Vec3 {
+    self.direction.try_normalize().unwrap_or(Vec3::NEG_Y)
+  }
+
+  // The cascades covering what `camera` sees of a target with `aspect`, nearest first.
+  pub fn cascades(&self, camera: &Camera, aspect: f32) -> Vec<Cascade> {
+    let settings = &self.shadows;
+    let (near, far) = camera.clip_planes();
+    let reach = far.min(near + settings.distance.max(0.0));
+    let count = settings.cascades.clamp(1, MAX_CASCADES);
+    let direction = self.unit_direction();
+    let up = if direction.y.abs() > 0.99 { Vec3::Z } else { Vec3::Y };
+
+    // The frustum's four edges, from the near plane to the far one. View depth is linear along them.
+    let inverse = camera.view_projection(aspect).inverse();
+    let edges = [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)]
+      .map(|(x, y)| (inverse.project_point3(Vec3::new(x, y, 0.0)), inverse.project_point3(Vec3::new(x, y, 1.0))));
+    let at_depth = |depth: f32| edges.map(|(near_point, far_point)| near_point.lerp(far_point, (depth - near) / (far - near).max(f32::EPSILON)));
+
+    let log_near = near.max(0.01);
+    let mut start = near;
+    (0..count)
+      .map(|index| {
+        let t = (index + 1) as f32 / count as f32;
+        let even = near + (reach - near) * t;
+        let log = log_near * (reach.max(log_near) / log_near).powf(t);
+        let end = even + (log - even) * settings.split_lambda.clamp(0.0, 1.0);
+        let corners: Vec<Vec3> = at_depth(start).into_iter().chain(at_depth(end)).collect();
+        start = end;
+
+        // A sphere round the slice keeps the map's size fixed as the camera turns, and snapping its
+        // centre to whole texels stops shadow edges crawling as it moves.
+        let center = corners.iter().sum::<Vec3>() / corners.len() as f32;
+        let radius = (corners.iter().map(|corner| corner.distance(center)).fold(0.0, f32::max) * 16.0).ceil() / 16.0;
+        let texel = 2.0 * radius.max(f32::EPSILON) / settings.resolution.max(1) as f32;
+        let light_view = Mat4::look_to_rh(Vec3::ZERO, direction, up);
+        let snapped = light_view.transform_point3(center);
+        let snapped = Vec3::new((snapped.x / texel).floor() * texel, (snapped.y / texel).floor() * texel, snapped.z);
+        let center = light_view.inverse().transform_point3(snapped);
+
+        // Back far enough to catch casters out of view between the slice and the light.
+        let back = radius + settings.distance.max(radius);
+        let view = Mat4::look_to_rh(center - direction * back, direction, up);
+        let projection = Mat4::orthographic_rh(-radius, radius, -radius, radius, 0.0, back + radius);
+        Cascade { view_projection: projection * view, far: end }
+      })
+      .collect()
+  }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct LightUniforms {
+  to_light: [f32; 4],
+  color: [f32; 4],
+  camera_position: [f32; 4],
+  camera_forward: [f32; 4],
+  splits: [f32; 4],
+  shadow: [f32; 4],
+  cascades: [[f32; 16]; MAX_CASCADES as usize],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct LocalLightRaw {
+  position: [f32; 4],
+  color: [f32; 4],
+  direction: [f32; 4],
+  cone: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct LocalLightsUniforms {
+  count: [u32; 4],
+  lights: [LocalLightRaw; MAX_LIGHTS],
+}
+
+// The shadow map and a depth attachment for each of its layers.
+struct ShadowMap {
+  resolution: u32,
+  layers: u32,
+  view: TextureView,
+  cascade_views: Vec<TextureView>,
+}
+
+// The world's lights on the GPU: the `DirectionalLight`'s uniforms and shadow map and the point and
+// spot lights in `bind_group`, laid out by `layout` for the lit pipelines' group 1, and the
+// depth-only passes that fill the map.
+pub struct LightRenderer {
+  pub layout: BindGroupLayout,
+  pub bind_group: BindGroup,
+  uniform_buffer: Buffer,
+  local_buffer: Buffer,
+  reported_overflow: usize, // the light count last reported as over `MAX_LIGHTS`
+  sampler: Sampler,
+  shadow_map: ShadowMap,
+  cascade_cameras: Vec<(Buffer, BindGroup)>, // each cascade's view-projection, for its pass
+  pipeline: RenderPipeline,
+  instanced_pipeline: RenderPipeline,
+  active_cascades: u32, // drawn this frame; 0 when shadows are off
+}
+
+impl LightRenderer {
+  pub fn new(device: &Device, camera_layout: &BindGroupLayout) -> Self {
+    let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("light-bind-group-layout"),
+      entries: &[
+        BindGroupLayoutEntry {
+          binding: 0,
+          visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+          ty: BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None
+          },
+          count: None
+        },
+        BindGroupLayoutEntry {
+          binding: 1,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Texture {
+            sample_type: TextureSampleType::Depth,
+            view_dimension: TextureViewDimension::D2Array,
+            multisampled: false
+          },
+          count: None
+        },
+        BindGroupLayoutEntry {
+          binding: 2,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Sampler(SamplerBindingType::Comparison),
+          count: None
+        },
+        BindGroupLayoutEntry {
+          binding: 3,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None
+          },
+          count: None
+        }
+      ]
+    });
+
+    let sampler = device.create_sampler(&SamplerDescriptor {
+      label: Some("shadow-sampler"),
+      address_mode_u: AddressMode::ClampToEdge,
+      address_mode_v: AddressMode::ClampToEdge,
+      mag_filter: FilterMode::Linear,
+      min_filter: FilterMode::Linear,
+      compare: Some(CompareFunction::LessEqual),
+      ..SamplerDescriptor::default()
+    });
+
+    let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("light-uniforms"),
+      contents: bytemuck::bytes_of(&LightUniforms::zeroed()),
+      usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
+    });
+    let local_buffer = device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("local-light-uniforms"),
+      contents: bytemuck::bytes_of(&LocalLightsUniforms::zeroed()),
+      usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
+    });
+
+    let cascade_cameras = (0..MAX_CASCADES)
+      .map(|_| {
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+          label: Some("shadow-cascade-uniforms"),
+          contents: bytemuck::bytes_of(&CameraUniforms { view_proj: Mat4::IDENTITY.to_cols_array(), position: [0.0; 4] }),
+          usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
+        });
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+          label: Some("shadow-cascade-bind-group"),
+          layout: camera_layout,
+          entries: &[BindGroupEntry { binding: 0, resource: buffer.as_entire_binding() }]
+        });
+        (buffer, bind_group)
+      })
+      .collect();
+
+    let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+      label: Some("shadow-shader"),
+      source: ShaderSource::Wgsl(Cow::Borrowed(
+"
+struct Camera {
+    view_proj: mat4x4<f32>,
+    position: vec4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>) -> @builtin(position) vec4<f32> {
+    return camera.view_proj * vec4<f32>(position, 1.0);
+}
+
+@vertex
+fn vs_instanced(
+    @location(0) position: vec3<f32>,
+    @location(3) model_0: vec4<f32>,
+    @location(4) model_1: vec4<f32>,
+    @location(5) model_2: vec4<f32>,
+    @location(6) model_3: vec4<f32>,
+) -> @builtin(position) vec4<f32> {
+    let model = mat4x4<f32>(model_0, model_1, model_2, model_3);
+    return camera.view_proj * model * vec4<f32>(position, 1.0);
+}
+"
+      ))
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+      label: Some("shadow-pipeline-layout"),
+      bind_group_layouts: &[camera_layout],
+      push_constant_ranges: &[]
+    });
+    let pipeline = create_shadow_pipeline(device, &pipeline_layout, &shader_module, "vs_main", &[Vertex::layout()]);
+    let instanced_pipeline = create_shadow_pipeline(device, &pipeline_layout, &shader_module, "vs_instanced", &[Vertex::layout(), InstanceRaw::layout()]);
+
+    let shadow_map = create_shadow_map(device, 1, 1);
+    let bind_group = create_bind_group(device, &layout, &uniform_buffer, &local_buffer, &shadow_map.view, &sampler);
+    LightRenderer {
+      layout,
+      bind_group,
+      uniform_buffer,
+      local_buffer,
+      reported_overflow: 0,
+      sampler,
+      shadow_map,
+      cascade_cameras,
+      pipeline,
+      instanced_pipeline,
+      active_cascades: 0
+    }
+  }
+
+  // Uploads `light` and, when it casts shadows and there's a camera, fits the cascades to what the
+  // camera sees of a target with the given aspect. Remakes the shadow map if its settings changed.
+  // Of `local_lights`, the `MAX_LIGHTS` nearest the camera are drawn.
+  pub fn prepare(&mut self, device: &Device, queue: &Queue, light: &DirectionalLight, mut local_lights: Vec<LocalLight>, camera: Option<(&Camera, f32)>) {
+    let settings = &light.shadows;
+    let cascades = match camera {
+      Some((camera, aspect)) if settings.enabled => light.cascades(camera, aspect),
+      _ => Vec::new(),
+    };
+    let (resolution, layers) = match cascades.len() {
+      0 => (1, 1),
+      count => (settings.resolution.clamp(1, device.limits().max_texture_dimension_2d), count as u32),
+    };
+    if self.shadow_map.resolution != resolution || self.shadow_map.layers != layers {
+      self.shadow_map = create_shadow_map(device, resolution, layers);
+      self.bind_group = create_bind_group(device, &self.layout, &self.uniform_buffer, &self.local_buffer, &self.shadow_map.view, &self.sampler);
+    }
+
+    let mut uniforms = LightUniforms {
+      to_light: (-light.unit_direction()).extend(0.0).to_array(),
+      color: [light.color.r * light.intensity, light.color.g * light.intensity, light.color.b * light.intensity, light.ambient.clamp(0.0, 1.0)],
+      shadow: [cascades.len() as f32, settings.normal_offset, 1.0 / resolution as f32, 0.0],
+      ..LightUniforms::zeroed()
+    };
+    if let Some((camera, _)) = camera {
+      uniforms.camera_position = camera.position().extend(1.0).to_array();
+      uniforms.camera_forward = camera.transform.forward().extend(0.0).to_array();
+    }
+    for (index, cascade) in cascades.iter().enumerate() {
+      uniforms.splits[index] = cascade.far;
+      uniforms.cascades[index] = cascade.view_projection.to_cols_array();
+      let (buffer, _) = &self.cascade_cameras[index];
+      let camera = CameraUniforms { view_proj: cascade.view_projection.to_cols_array(), position: [0.0; 4] };
+      queue.write_buffer(buffer, 0, bytemuck::bytes_of(&camera));
+    }
+    queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+    self.active_cascades = cascades.len() as u32;
+
+    if local_lights.len() > MAX_LIGHTS {
+      if self.reported_overflow != local_lights.len() {
+        log::error!(
+          "{} point and spot lights but only {} can be drawn at once; the {} furthest from the camera are left out",
+          local_lights.len(), MAX_LIGHTS, local_lights.len() - MAX_LIGHTS
+        );
+        self.reported_overflow = local_lights.len();
+      }
+      let eye = camera.map_or(Vec3::ZERO, |(camera, _)| camera.position());
+      local_lights.sort_by(|a, b| a.position.distance_squared(eye).total_cmp(&b.position.distance_squared(eye)));
+      local_lights.truncate(MAX_LIGHTS);
+    } else {
+      self.reported_overflow = 0;
+    }
+    let mut local = LocalLightsUniforms { count: [local_lights.len() as u32, 0, 0, 0], ..LocalLightsUniforms::zeroed() };
+    for (raw, light) in local.lights.iter_mut().zip(&local_lights) {
+      *raw = light.raw();
+    }
+    queue.write_buffer(&self.local_buffer, 0, bytemuck::bytes_of(&local));
+  }
+
+  // One depth-only pass per cascade, drawing every model and instanced mesh as a caster. Run before
+  // anything samples the map.
+  pub fn draw_shadows(&self, encoder: &mut CommandEncoder, models: &ModelRenderer, instances: &InstanceRenderer) {
+    for index in 0..self.active_cascades as usize {
+      let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+        label: Some("shadow-pass"),
+        color_attachments: &[],
+        depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+          view: &self.shadow_map.cascade_views[index],
+          depth_ops: Some(Operations { load: LoadOp::Clear(1.0), store: true }),
+          stencil_ops: None
+        })
+      });
+      let camera = &self.cascade_cameras[index].1;
+      render_pass.set_pipeline(&self.pipeline);
+      render_pass.set_bind_group(0, camera, &[]);
+      models.draw_depth(&mut render_pass);
+      render_pass.set_pipeline(&self.instanced_pipeline);
+      render_pass.set_bind_group(0, camera, &[]);
+      instances.draw_depth(&mut render_pass);
+    }
+  }
+
+  pub fn draw_calls(&self, models: &ModelRenderer, instances: &InstanceRenderer) -> u32 {
+    self.active_cascades * (models.draw_calls() + instances.draw_calls())
+  }
+}
+
+fn create_shadow_pipeline(device: &Device, layout: &wgpu::PipelineLayout, shader_module: &ShaderModule, entry_point: &str, buffers: &[VertexBufferLayout]) -> RenderPipeline {
+  device.create_render_pipeline(&RenderPipelineDescriptor {
+    label: Some("shadow-pipeline"),
+    layout: Some(layout),
+    vertex: VertexState {
+      module: shader_module,
+      entry_point,
+      buffers
+    },
+    fragment: None,
+    primitive: PrimitiveState::default(),
+    depth_stencil: Some(DepthStencilState {
+      format: DEPTH_FORMAT,
+      depth_write_enabled: true,
+      depth_compare: CompareFunction::Less,
+      stencil: Default::default(),
+      // Slope-scaled, so surfaces at a grazing angle to the light don't shadow themselves.
+      bias: DepthBiasState { constant: 2, slope_scale: 2.0, clamp: 0.0 }
+    }),
+    multisample: MultisampleState::default(),
+    multiview: None
+  })
+}
+
+fn create_shadow_map(device: &Device, resolution: u32, layers: u32) -> ShadowMap {
+  let texture = device.create_texture(&TextureDescriptor {
+    label: Some("shadow-map"),
+    size: Extent3d { width: resolution, height: resolution, depth_or_array_layers: layers },
+    mip_level_count: 1,
+    sample_count: 1,
+    dimension: TextureDimension::D2,
+    format: DEPTH_FORMAT,
+    usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING
+  });
+  let view = texture.create_view(&TextureViewDescriptor {
+    label: Some("shadow-map-view"),
+    dimension: Some(TextureViewDimension::D2Array),
+    ..TextureViewDescriptor::default()
+  });
+  let cascade_views = (0..layers)
+    .map(|layer| texture.create_view(&TextureViewDescriptor {
+      label: Some("shadow-cascade-view"),
+      dimension: Some(TextureViewDimension::D2),
+      base_array_layer: layer,
+      array_layer_count: std::num::NonZeroU32::new(1),
+      ..TextureViewDescriptor::default()
+    }))
+    .collect();
+  ShadowMap { resolution, layers, view, cascade_views }
+}
+
+fn create_bind_group(device: &Device, layout: &BindGroupLayout, uniforms: &Buffer, local_lights: &Buffer, shadow_map: &TextureView, sampler: &Sampler) -> BindGroup {
+  device.create_bind_group(&BindGroupDescriptor {
+    label: Some("light-bind-group"),
+    layout,
+    entries: &[
+      BindGroupEntry { binding: 0, resource: uniforms.as_entire_binding() },
+      BindGroupEntry { binding: 1, resource: BindingResource::TextureView(shadow_map) },
+      BindGroupEntry { binding: 2, resource: BindingResource::Sampler(sampler) },
+      BindGroupEntry { binding: 3, resource: local_lights.as_entire_binding() }
+    ]
+  })
+}
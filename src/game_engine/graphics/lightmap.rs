@@ -0,0 +1,735 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::sync::Arc;
+use std::thread;
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec2, Vec3};
+use rapier3d::parry::query::{Ray, RayCast};
+use rapier3d::parry::shape::{FeatureId, TriMesh};
+use rapier3d::prelude::Point;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferAddress, BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites, CompareFunction, DepthStencilState, Device, Extent3d, FilterMode, FragmentState, ImageCopyTexture, ImageDataLayout, IndexFormat, MultisampleState, Origin3d, PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPass, RenderPipeline, RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureViewDescriptor, TextureViewDimension, VertexAttribute, VertexBufferLayout, VertexFormat, VertexState, VertexStepMode};
+
+use crate::game_engine::ecs::World;
+use crate::game_engine::error::EngineError;
+use crate::game_engine::random::Rng;
+use super::color::Color;
+use super::culling::CullFilter;
+use super::mesh::Mesh;
+use super::model::DEPTH_FORMAT;
+
+// A piece of static geometry to bake, with its placement in the world.
+pub struct BakeMesh<'a> {
+  pub mesh: &'a Mesh,
+  pub transform: Mat4,
+  pub albedo: Color, // how much bounced light the surface reflects
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum BakeLight {
+  Directional { direction: Vec3, color: Color, intensity: f32 },
+  Point { position: Vec3, color: Color, intensity: f32, range: f32 },
+}
+
+#[derive(Debug, Clone)]
+pub struct LightmapSettings {
+  pub resolution: u32, // the lightmap is square
+  pub texels_per_unit: f32, // lowered automatically if the geometry doesn't fit
+  pub samples: u32, // paths traced per texel
+  pub bounces: u32,
+  pub sky: Color, // light arriving from rays that escape the scene
+  pub padding: u32, // texels around each triangle, to stop bilinear filtering bleeding
+  pub seed: u64,
+}
+
+impl Default for LightmapSettings {
+  fn default() -> Self {
+    LightmapSettings {
+      resolution: 512,
+      texels_per_unit: 8.0,
+      samples: 64,
+      bounces: 2,
+      sky: Color::rgb(0.2, 0.25, 0.3),
+      padding: 2,
+      seed: 0,
+    }
+  }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Pod, Zeroable)]
+pub struct LightmapVertex {
+  pub position: [f32; 3],
+  pub normal: [f32; 3],
+  pub uv: [f32; 2],
+  pub lightmap_uv: [f32; 2],
+}
+
+// A baked mesh in world space. Every triangle has its own vertices, since each occupies its own
+// island in the lightmap.
+#[derive(Debug, Clone, Default)]
+pub struct LightmappedMesh {
+  pub vertices: Vec<LightmapVertex>,
+  pub indices: Vec<u32>,
+}
+
+// Linear RGB light per texel, as it would light a white surface.
+#[derive(Debug, Clone)]
+pub struct Lightmap {
+  pub size: u32,
+  pub texels: Vec<Vec3>,
+}
+
+impl Lightmap {
+  // Tone maps and gamma encodes the lightmap into RGBA8 for upload.
+  pub fn to_rgba8(&self, exposure: f32) -> Vec<u8> {
+    self.texels.iter().flat_map(|texel| {
+      let mapped = Vec3::ONE - (-*texel * exposure).exp();
+      let encoded = mapped.powf(1.0 / 2.2) * 255.0;
+      [encoded.x as u8, encoded.y as u8, encoded.z as u8, 255]
+    }).collect()
+  }
+}
+
+pub struct BakeResult {
+  pub lightmap: Lightmap,
+  pub meshes: Vec<LightmappedMesh>, // one per `BakeMesh`, in the same order
+}
+
+struct Triangle {
+  positions: [Vec3; 3],
+  normal: Vec3,
+  uvs: [Vec2; 3],
+  normals: [Vec3; 3],
+  albedo: Vec3,
+  flat: [Vec2; 3], // the triangle unfolded onto its own plane, in texels, origin at its corner
+  size: Vec2, // `flat`'s bounding box in texels
+  origin: Vec2, // where the packer put it in the lightmap, in texels
+}
+
+// Bakes direct and bounced light for `meshes` into one lightmap by path tracing on the CPU, and
+// returns the meshes with lightmap UVs. Work is split across all cores. Fails if the triangles
+// can't all fit in a lightmap of `settings.resolution`.
+pub fn bake_lightmap(meshes: &[BakeMesh], lights: &[BakeLight], settings: &LightmapSettings) -> Result<BakeResult, EngineError> {
+  let mut triangles = Vec::new();
+  let mut owners = Vec::new();
+  for (owner, bake_mesh) in meshes.iter().enumerate() {
+    let vertices = bake_mesh.mesh.vertices();
+    let normal_matrix = bake_mesh.transform.inverse().transpose();
+    for indices in bake_mesh.mesh.indices().chunks_exact(3) {
+      let vertex = |i: usize| &vertices[indices[i] as usize];
+      let positions = [0, 1, 2].map(|i| bake_mesh.transform.transform_point3(Vec3::from(vertex(i).position)));
+      let normal = (positions[1] - positions[0]).cross(positions[2] - positions[0]);
+      if normal.length_squared() <= f32::EPSILON {
+        continue;
+      }
+      triangles.push(Triangle {
+        positions,
+        normal: normal.normalize(),
+        uvs: [0, 1, 2].map(|i| Vec2::from(vertex(i).uv)),
+        normals: [0, 1, 2].map(|i| normal_matrix.transform_vector3(Vec3::from(vertex(i).normal)).normalize_or_zero()),
+        albedo: Vec3::new(bake_mesh.albedo.r, bake_mesh.albedo.g, bake_mesh.albedo.b),
+        flat: [Vec2::ZERO; 3],
+        size: Vec2::ZERO,
+        origin: Vec2::ZERO,
+      });
+      owners.push(owner);
+    }
+  }
+
+  // Nothing to trace against; parry won't build an empty scene.
+  let size = settings.resolution;
+  if triangles.is_empty() {
+    let texels = vec![Vec3::ZERO; (size * size) as usize];
+    return Ok(BakeResult { lightmap: Lightmap { size, texels }, meshes: meshes.iter().map(|_| LightmappedMesh::default()).collect() });
+  }
+
+  pack(&mut triangles, settings)?;
+
+  let scene = TriMesh::new(
+    triangles.iter().flat_map(|triangle| triangle.positions.map(|position| Point::from(position.to_array()))).collect(),
+    (0..triangles.len() as u32).map(|i| [i * 3, i * 3 + 1, i * 3 + 2]).collect(),
+  );
+  let tracer = Tracer { scene: &scene, triangles: &triangles, lights, settings };
+
+  // Each worker bakes a share of the triangles; texels hit by several triangles keep whichever
+  // sample lay inside its triangle rather than clamped onto an edge.
+  let mut texels = vec![Vec3::ZERO; (size * size) as usize];
+  let mut coverage = vec![0u8; texels.len()];
+  let threads = thread::available_parallelism().map_or(1, |count| count.get()).min(triangles.len().max(1));
+  let per_thread = triangles.len().div_ceil(threads).max(1);
+  let mut seeds = Rng::new(settings.seed);
+  let workers: Vec<(usize, Rng)> = (0..threads).map(|worker| (worker * per_thread, seeds.fork())).collect();
+  let results: Vec<Vec<(usize, Vec3, u8)>> = thread::scope(|scope| {
+    let handles: Vec<_> = workers.into_iter()
+      .map(|(start, mut rng)| {
+        let tracer = &tracer;
+        scope.spawn(move || {
+          let end = (start + per_thread).min(tracer.triangles.len());
+          (start..end).flat_map(|index| tracer.bake_triangle(index, &mut rng)).collect()
+        })
+      })
+      .collect();
+    handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+  });
+  for (index, value, weight) in results.into_iter().flatten() {
+    if weight > coverage[index] {
+      texels[index] = value;
+      coverage[index] = weight;
+    }
+  }
+  dilate(&mut texels, &mut coverage, size, settings.padding);
+
+  let mut baked: Vec<LightmappedMesh> = (0..meshes.len()).map(|_| LightmappedMesh::default()).collect();
+  for (triangle, &owner) in triangles.iter().zip(&owners) {
+    let mesh = &mut baked[owner];
+    for i in 0..3 {
+      let lightmap_uv = (triangle.origin + triangle.flat[i]) / size as f32;
+      mesh.indices.push(mesh.vertices.len() as u32);
+      mesh.vertices.push(LightmapVertex {
+        position: triangle.positions[i].to_array(),
+        normal: triangle.normals[i].to_array(),
+        uv: triangle.uvs[i].to_array(),
+        lightmap_uv: lightmap_uv.to_array(),
+      });
+    }
+  }
+
+  Ok(BakeResult { lightmap: Lightmap { size, texels }, meshes: baked })
+}
+
+// Unfolds each triangle onto its plane and shelf-packs the islands into the lightmap, shrinking
+// the texel density until everything fits. Past a point, shrinking can't help, since every island
+// keeps its padding; islands would overlap, so that's an error instead.
+fn pack(triangles: &mut [Triangle], settings: &LightmapSettings) -> Result<(), EngineError> {
+  let size = settings.resolution as f32;
+  let padding = settings.padding as f32;
+  let mut density = settings.texels_per_unit;
+
+  loop {
+    for triangle in triangles.iter_mut() {
+      let [a, b, c] = triangle.positions;
+      let u = (b - a).normalize();
+      let v = triangle.normal.cross(u);
+      let flat = [a, b, c].map(|point| Vec2::new((point - a).dot(u), (point - a).dot(v)) * density);
+      let min = flat.iter().fold(Vec2::splat(f32::MAX), |min, point| min.min(*point));
+      let max = flat.iter().fold(Vec2::splat(f32::MIN), |max, point| max.max(*point));
+      triangle.flat = flat.map(|point| point - min + Vec2::splat(padding));
+      triangle.size = (max - min + Vec2::splat(padding * 2.0)).ceil();
+    }
+
+    let mut order: Vec<usize> = (0..triangles.len()).collect();
+    order.sort_by(|&a, &b| triangles[b].size.y.total_cmp(&triangles[a].size.y));
+    let (mut cursor, mut shelf_height, mut fits) = (Vec2::ZERO, 0.0f32, true);
+    for index in order {
+      let triangle = &mut triangles[index];
+      if cursor.x + triangle.size.x > size {
+        cursor = Vec2::new(0.0, cursor.y + shelf_height);
+        shelf_height = 0.0;
+      }
+      if cursor.x + triangle.size.x > size || cursor.y + triangle.size.y > size {
+        fits = false;
+        break;
+      }
+      triangle.origin = cursor;
+      cursor.x += triangle.size.x;
+      shelf_height = shelf_height.max(triangle.size.y);
+    }
+
+    if fits {
+      return Ok(());
+    }
+    if density < 1e-3 {
+      return Err(EngineError::Task(format!(
+        "{} triangles don't fit in a {}x{} lightmap; raise its resolution or lower its padding",
+        triangles.len(), settings.resolution, settings.resolution,
+      )));
+    }
+    density *= 0.8;
+  }
+}
+
+struct Tracer<'a> {
+  scene: &'a TriMesh,
+  triangles: &'a [Triangle],
+  lights: &'a [BakeLight],
+  settings: &'a LightmapSettings,
+}
+
+impl Tracer<'_> {
+  const EPSILON: f32 = 1e-3;
+
+  // Lights every texel of the triangle's island: (texel index, light, 2 if inside the triangle
+  // else 1).
+  fn bake_triangle(&self, index: usize, rng: &mut Rng) -> Vec<(usize, Vec3, u8)> {
+    let triangle = &self.triangles[index];
+    let size = self.settings.resolution;
+    let mut texels = Vec::new();
+    for y in 0..triangle.size.y as u32 {
+      for x in 0..triangle.size.x as u32 {
+        let local = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+        let (weights, inside) = barycentric(local, triangle.flat);
+        let position = triangle.positions[0] * weights.x + triangle.positions[1] * weights.y + triangle.positions[2] * weights.z;
+        let light = self.irradiance(position, triangle.normal, rng);
+        let texel = (triangle.origin + local).as_uvec2();
+        texels.push(((texel.y * size + texel.x) as usize, light, if inside { 2 } else { 1 }));
+      }
+    }
+    texels
+  }
+
+  fn irradiance(&self, position: Vec3, normal: Vec3, rng: &mut Rng) -> Vec3 {
+    let samples = self.settings.samples.max(1);
+    let sky = Vec3::new(self.settings.sky.r, self.settings.sky.g, self.settings.sky.b);
+    let mut total = Vec3::ZERO;
+    for _ in 0..samples {
+      let (mut point, mut surface_normal, mut throughput) = (position, normal, Vec3::ONE);
+      let mut light = self.direct(point, surface_normal);
+      for _ in 0..self.settings.bounces {
+        let direction = cosine_sample(surface_normal, rng);
+        match self.trace(point + surface_normal * Tracer::EPSILON, direction, f32::MAX) {
+          Some((distance, hit)) => {
+            let triangle = &self.triangles[hit];
+            point = point + surface_normal * Tracer::EPSILON + direction * distance;
+            surface_normal = if triangle.normal.dot(direction) > 0.0 { -triangle.normal } else { triangle.normal };
+            throughput *= triangle.albedo;
+            light += throughput * self.direct(point, surface_normal);
+          }
+          None => {
+            light += throughput * sky;
+            break;
+          }
+        }
+      }
+      total += light;
+    }
+    total / samples as f32
+  }
+
+  fn direct(&self, position: Vec3, normal: Vec3) -> Vec3 {
+    let origin = position + normal * Tracer::EPSILON;
+    self.lights.iter().map(|light| {
+      let (to_light, distance, color, intensity) = match *light {
+        BakeLight::Directional { direction, color, intensity } => (-direction.normalize(), f32::MAX, color, intensity),
+        BakeLight::Point { position: light_position, color, intensity, range } => {
+          let offset = light_position - origin;
+          let distance = offset.length();
+          if distance > range {
+            return Vec3::ZERO;
+          }
+          let falloff = (1.0 - distance / range).powi(2) / (1.0 + distance * distance);
+          (offset / distance, distance, color, intensity * falloff)
+        }
+      };
+      let cosine = normal.dot(to_light);
+      if cosine <= 0.0 || self.trace(origin, to_light, distance).is_some() {
+        return Vec3::ZERO;
+      }
+      Vec3::new(color.r, color.g, color.b) * intensity * cosine
+    }).sum()
+  }
+
+  // Distance to and index of the nearest triangle along the ray.
+  fn trace(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<(f32, usize)> {
+    let ray = Ray::new(Point::from(origin.to_array()), direction.to_array().into());
+    let hit = self.scene.cast_local_ray_and_get_normal(&ray, max_distance, false)?;
+    let triangle = match hit.feature {
+      FeatureId::Face(face) => face as usize % self.triangles.len(),
+      _ => return None,
+    };
+    Some((hit.toi, triangle))
+  }
+}
+
+// Barycentric weights of `point` in a 2D triangle, clamped onto the triangle when it lies outside
+// (so padding texels take the nearest edge's light), and whether it was inside.
+fn barycentric(point: Vec2, [a, b, c]: [Vec2; 3]) -> (Vec3, bool) {
+  let (v0, v1, v2) = (b - a, c - a, point - a);
+  let denominator = v0.perp_dot(v1);
+  let v = v2.perp_dot(v1) / denominator;
+  let w = v0.perp_dot(v2) / denominator;
+  let u = 1.0 - v - w;
+  let inside = u >= 0.0 && v >= 0.0 && w >= 0.0;
+  let clamped = Vec3::new(u, v, w).max(Vec3::ZERO);
+  (clamped / (clamped.x + clamped.y + clamped.z).max(f32::EPSILON), inside)
+}
+
+fn cosine_sample(normal: Vec3, rng: &mut Rng) -> Vec3 {
+  let (r1, r2) = (rng.next_f32(), rng.next_f32());
+  let phi = std::f32::consts::TAU * r1;
+  let radius = r2.sqrt();
+  let (tangent, bitangent) = normal.any_orthonormal_pair();
+  (tangent * phi.cos() * radius + bitangent * phi.sin() * radius + normal * (1.0 - r2).sqrt()).normalize()
+}
+
+// Grows baked texels outwards into empty ones, so bilinear sampling near island edges never
+// blends in unlit black.
+fn dilate(texels: &mut [Vec3], coverage: &mut [u8], size: u32, passes: u32) {
+  let size = size as i32;
+  for _ in 0..passes.max(1) {
+    let mut filled = Vec::new();
+    for y in 0..size {
+      for x in 0..size {
+        if coverage[(y * size + x) as usize] > 0 {
+          continue;
+        }
+        let (mut sum, mut count) = (Vec3::ZERO, 0);
+        for (dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+          let (nx, ny) = (x + dx, y + dy);
+          if nx >= 0 && ny >= 0 && nx < size && ny < size && coverage[(ny * size + nx) as usize] > 0 {
+            sum += texels[(ny * size + nx) as usize];
+            count += 1;
+          }
+        }
+        if count > 0 {
+          filled.push(((y * size + x) as usize, sum / count as f32));
+        }
+      }
+    }
+    for (index, value) in filled {
+      texels[index] = value;
+      coverage[index] = 1;
+    }
+  }
+}
+
+
+// Draws a baked `LightmappedMesh` in the model pass, lit only by its lightmap: albedo times the
+// lightmap texel. The mesh is already in world space, so the entity needs no `Transform`.
+#[derive(Debug, Clone)]
+pub struct Lightmapped {
+  pub mesh: Arc<LightmappedMesh>,
+  pub lightmap: Arc<Lightmap>,
+  pub albedo: Color,
+  pub exposure: f32, // for `Lightmap::to_rgba8`; changing it uploads the lightmap again
+}
+
+impl Lightmapped {
+  pub fn new(mesh: Arc<LightmappedMesh>, lightmap: Arc<Lightmap>) -> Self {
+    Lightmapped { mesh, lightmap, albedo: Color::WHITE, exposure: 1.0 }
+  }
+
+  pub fn with_albedo(mut self, albedo: Color) -> Self {
+    self.albedo = albedo;
+    self
+  }
+
+  pub fn with_exposure(mut self, exposure: f32) -> Self {
+    self.exposure = exposure;
+    self
+  }
+}
+
+// A `LightmappedMesh` uploaded for drawing.
+pub struct GpuLightmappedMesh {
+  vertex_buffer: Buffer,
+  index_buffer: Buffer,
+  index_count: u32,
+}
+
+impl GpuLightmappedMesh {
+  pub fn new(device: &Device, mesh: &LightmappedMesh) -> Self {
+    GpuLightmappedMesh {
+      vertex_buffer: device.create_buffer_init(&BufferInitDescriptor {
+        label: Some("lightmapped-vertices"),
+        usage: BufferUsages::VERTEX,
+        contents: bytemuck::cast_slice(&mesh.vertices)
+      }),
+      index_buffer: device.create_buffer_init(&BufferInitDescriptor {
+        label: Some("lightmapped-indices"),
+        usage: BufferUsages::INDEX,
+        contents: bytemuck::cast_slice(&mesh.indices)
+      }),
+      index_count: mesh.indices.len() as u32,
+    }
+  }
+}
+
+// A mesh or lightmap uploaded once and kept while the game still holds it.
+struct CachedMesh {
+  mesh: Arc<LightmappedMesh>,
+  gpu_mesh: GpuLightmappedMesh,
+}
+
+struct CachedLightmap {
+  lightmap: Arc<Lightmap>,
+  bind_group: BindGroup,
+}
+
+// Draws every `Lightmapped` entity in the world. Each draw's albedo is an instance attribute, so
+// entities sharing a mesh and lightmap only differ in their instance.
+pub struct LightmapRenderer {
+  pipeline: RenderPipeline,
+  lightmap_layout: BindGroupLayout,
+  sampler: Sampler,
+  meshes: HashMap<usize, CachedMesh>, // by the mesh's `Arc` address
+  lightmaps: HashMap<(usize, u32), CachedLightmap>, // by the lightmap's `Arc` address and exposure bits
+  instances: Vec<[f32; 4]>,
+  instance_buffer: Option<Buffer>,
+  draws: Vec<(usize, (usize, u32), u32)>, // the mesh, the lightmap, and the instance
+}
+
+impl LightmapRenderer {
+  pub fn new(device: &Device, format: TextureFormat, camera_layout: &BindGroupLayout) -> Self {
+    let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+      label: Some("lightmap-shader"),
+      source: ShaderSource::Wgsl(Cow::Borrowed(
+"
+struct Camera {
+    view_proj: mat4x4<f32>,
+    position: vec4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+@group(1) @binding(0) var lightmap: texture_2d<f32>;
+@group(1) @binding(1) var lightmap_sampler: sampler;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(3) lightmap_uv: vec2<f32>,
+    @location(4) albedo: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) lightmap_uv: vec2<f32>,
+    @location(1) albedo: vec4<f32>,
+};
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(in.position, 1.0);
+    out.lightmap_uv = in.lightmap_uv;
+    out.albedo = in.albedo;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let light = textureSample(lightmap, lightmap_sampler, in.lightmap_uv).rgb;
+    return vec4<f32>(in.albedo.rgb * light, in.albedo.a);
+}
+"
+      ))
+    });
+
+    let lightmap_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("lightmap-bind-group-layout"),
+      entries: &[
+        BindGroupLayoutEntry {
+          binding: 0,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: true },
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false
+          },
+          count: None
+        },
+        BindGroupLayoutEntry {
+          binding: 1,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Sampler(SamplerBindingType::Filtering),
+          count: None
+        }
+      ]
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+      label: Some("lightmap-pipeline-layout"),
+      bind_group_layouts: &[camera_layout, &lightmap_layout],
+      push_constant_ranges: &[]
+    });
+
+    let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+      label: Some("lightmap-pipeline"),
+      layout: Some(&pipeline_layout),
+      vertex: VertexState {
+        module: &shader_module,
+        entry_point: "vs_main",
+        buffers: &[
+          VertexBufferLayout {
+            array_stride: size_of::<LightmapVertex>() as BufferAddress,
+            step_mode: VertexStepMode::Vertex,
+            attributes: &[
+              VertexAttribute { format: VertexFormat::Float32x3, shader_location: 0, offset: 0 },
+              VertexAttribute { format: VertexFormat::Float32x3, shader_location: 1, offset: 12 },
+              VertexAttribute { format: VertexFormat::Float32x2, shader_location: 2, offset: 24 },
+              VertexAttribute { format: VertexFormat::Float32x2, shader_location: 3, offset: 32 }
+            ]
+          },
+          VertexBufferLayout {
+            array_stride: size_of::<[f32; 4]>() as BufferAddress,
+            step_mode: VertexStepMode::Instance,
+            attributes: &[VertexAttribute { format: VertexFormat::Float32x4, shader_location: 4, offset: 0 }]
+          }
+        ]
+      },
+      fragment: Some(FragmentState {
+        module: &shader_module,
+        entry_point: "fs_main",
+        targets: &[Some(ColorTargetState {
+          format,
+          blend: None,
+          write_mask: ColorWrites::ALL
+        })]
+      }),
+      primitive: PrimitiveState::default(),
+      depth_stencil: Some(DepthStencilState {
+        format: DEPTH_FORMAT,
+        depth_write_enabled: true,
+        depth_compare: CompareFunction::Less,
+        stencil: Default::default(),
+        bias: Default::default()
+      }),
+      multisample: MultisampleState::default(),
+      multiview: None
+    });
+
+    let sampler = device.create_sampler(&SamplerDescriptor {
+      label: Some("lightmap-sampler"),
+      address_mode_u: AddressMode::ClampToEdge,
+      address_mode_v: AddressMode::ClampToEdge,
+      mag_filter: FilterMode::Linear,
+      min_filter: FilterMode::Linear,
+      ..SamplerDescriptor::default()
+    });
+
+    LightmapRenderer {
+      pipeline,
+      lightmap_layout,
+      sampler,
+      meshes: HashMap::new(),
+      lightmaps: HashMap::new(),
+      instances: Vec::new(),
+      instance_buffer: None,
+      draws: Vec::new(),
+    }
+  }
+
+  // Uploads meshes and lightmaps drawn for the first time and every entity's albedo, and forgets
+  // meshes and lightmaps nothing else holds any more.
+  pub fn prepare(&mut self, device: &Device, queue: &Queue, world: &World) {
+    self.meshes.retain(|_, cached| Arc::strong_count(&cached.mesh) > 1);
+    self.lightmaps.retain(|_, cached| Arc::strong_count(&cached.lightmap) > 1);
+    self.instances.clear();
+    self.draws.clear();
+    let culled = CullFilter::new(world);
+    world.query::<&Lightmapped>().for_each(|entity, lightmapped| {
+      if lightmapped.mesh.indices.is_empty() || lightmapped.lightmap.size == 0 || culled.is_culled(world, entity) {
+        return;
+      }
+      let mesh_key = Arc::as_ptr(&lightmapped.mesh) as usize;
+      self.meshes.entry(mesh_key).or_insert_with(|| CachedMesh {
+        mesh: lightmapped.mesh.clone(),
+        gpu_mesh: GpuLightmappedMesh::new(device, &lightmapped.mesh),
+      });
+      let lightmap_key = (Arc::as_ptr(&lightmapped.lightmap) as usize, lightmapped.exposure.to_bits());
+      if !self.lightmaps.contains_key(&lightmap_key) {
+        let bind_group = self.upload_lightmap(device, queue, &lightmapped.lightmap, lightmapped.exposure);
+        self.lightmaps.insert(lightmap_key, CachedLightmap { lightmap: lightmapped.lightmap.clone(), bind_group });
+      }
+      self.draws.push((mesh_key, lightmap_key, self.instances.len() as u32));
+      self.instances.push(lightmapped.albedo.to_array());
+    });
+
+    if self.instances.is_empty() {
+      return;
+    }
+    let size = std::mem::size_of_val(self.instances.as_slice()) as BufferAddress;
+    if self.instance_buffer.as_ref().is_none_or(|buffer| buffer.size() < size) {
+      self.instance_buffer = Some(device.create_buffer(&BufferDescriptor {
+        label: Some("lightmap-instances"),
+        size: size.next_power_of_two(),
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false
+      }));
+    }
+    queue.write_buffer(self.instance_buffer.as_ref().unwrap(), 0, bytemuck::cast_slice(&self.instances));
+  }
+
+  fn upload_lightmap(&self, device: &Device, queue: &Queue, lightmap: &Lightmap, exposure: f32) -> BindGroup {
+    let size = Extent3d { width: lightmap.size, height: lightmap.size, depth_or_array_layers: 1 };
+    let texture = device.create_texture(&TextureDescriptor {
+      label: Some("lightmap"),
+      size,
+      mip_level_count: 1,
+      sample_count: 1,
+      dimension: TextureDimension::D2,
+      format: TextureFormat::Rgba8UnormSrgb,
+      usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST
+    });
+    queue.write_texture(
+      ImageCopyTexture { texture: &texture, mip_level: 0, origin: Origin3d::ZERO, aspect: TextureAspect::All },
+      &lightmap.to_rgba8(exposure),
+      ImageDataLayout { offset: 0, bytes_per_row: std::num::NonZeroU32::new(lightmap.size * 4), rows_per_image: None },
+      size
+    );
+    let view = texture.create_view(&TextureViewDescriptor::default());
+    device.create_bind_group(&BindGroupDescriptor {
+      label: Some("lightmap-bind-group"),
+      layout: &self.lightmap_layout,
+      entries: &[
+        BindGroupEntry { binding: 0, resource: BindingResource::TextureView(&view) },
+        BindGroupEntry { binding: 1, resource: BindingResource::Sampler(&self.sampler) }
+      ]
+    })
+  }
+
+  // One per lightmapped entity this frame.
+  pub fn draw_calls(&self) -> u32 {
+    self.draws.len() as u32
+  }
+
+  // Draws into a pass with the model depth buffer, seen through `camera`.
+  pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>, camera: &'a BindGroup) {
+    let instances = match &self.instance_buffer {
+      Some(instances) if !self.draws.is_empty() => instances,
+      _ => return,
+    };
+    render_pass.set_pipeline(&self.pipeline);
+    render_pass.set_bind_group(0, camera, &[]);
+    render_pass.set_vertex_buffer(1, instances.slice(..));
+    for (mesh_key, lightmap_key, instance) in &self.draws {
+      let mesh = &self.meshes[mesh_key].gpu_mesh;
+      render_pass.set_bind_group(1, &self.lightmaps[lightmap_key].bind_group, &[]);
+      render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+      render_pass.set_index_buffer(mesh.index_buffer.slice(..), IndexFormat::Uint32);
+      render_pass.draw_indexed(0..mesh.index_count, 0, *instance..*instance + 1);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::game_engine::graphics::mesh::MeshBuilder;
+
+  #[test]
+  fn baking_nothing_gives_an_empty_lightmap() {
+    let settings = LightmapSettings { resolution: 8, ..LightmapSettings::default() };
+    let baked = bake_lightmap(&[], &[], &settings).unwrap();
+    assert_eq!(baked.lightmap.size, 8);
+    assert!(baked.lightmap.texels.iter().all(|texel| *texel == Vec3::ZERO));
+    assert!(baked.meshes.is_empty());
+  }
+
+  #[test]
+  fn baking_only_degenerate_triangles_keeps_a_mesh_per_input() {
+    let mut builder = MeshBuilder::new();
+    builder.vertex(Vec3::ZERO, Vec3::Y, Vec2::ZERO);
+    builder.vertex(Vec3::X, Vec3::Y, Vec2::ZERO);
+    builder.vertex(Vec3::X * 2.0, Vec3::Y, Vec2::ZERO);
+    builder.triangle(0, 1, 2);
+    let mesh = builder.build();
+    let settings = LightmapSettings { resolution: 8, ..LightmapSettings::default() };
+    let baked = bake_lightmap(&[BakeMesh { mesh: &mesh, transform: Mat4::IDENTITY, albedo: Color::WHITE }], &[], &settings).unwrap();
+    assert_eq!(baked.meshes.len(), 1);
+    assert!(baked.meshes[0].indices.is_empty());
+  }
+}
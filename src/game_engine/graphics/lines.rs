@@ -0,0 +1,403 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::mem::size_of;
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec3};
+use serde::{Deserialize, Serialize};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BlendState, Buffer, BufferAddress, BufferBindingType, BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites, Device, FragmentState, MultisampleState, PipelineLayoutDescriptor, PrimitiveState, PrimitiveTopology, Queue, RenderPass, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureFormat, VertexAttribute, VertexBufferLayout, VertexFormat, VertexState, VertexStepMode};
+
+use crate::game_engine::ecs::{Transform, World};
+use super::color::Color;
+
+// Whether a line's width is in world units (and shrinks with distance) or in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum LineSpace {
+  #[default]
+  World,
+  Screen,
+}
+
+// A thick line through `points`, which are relative to the entity's `Transform` if it has one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Polyline {
+  pub points: Vec<Vec3>,
+  pub thickness: f32,
+  pub color: Color,
+  pub space: LineSpace,
+  pub closed: bool,
+}
+
+impl Polyline {
+  pub fn new(points: Vec<Vec3>, thickness: f32, color: Color) -> Self {
+    Polyline { points, thickness, color, ..Polyline::default() }
+  }
+}
+
+impl Default for Polyline {
+  fn default() -> Self {
+    Polyline {
+      points: Vec::new(),
+      thickness: 0.05,
+      color: Color::WHITE,
+      space: LineSpace::World,
+      closed: false,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TrailPoint {
+  position: Vec3,
+  age: f32,
+}
+
+// A ribbon left behind an entity as its `Transform` moves. Width and color are interpolated from
+// start to end over each point's lifetime.
+#[derive(Debug, Clone)]
+pub struct Trail {
+  pub lifetime: f32, // seconds
+  pub min_distance: f32, // how far the entity moves before a new point is recorded
+  pub start_width: f32,
+  pub end_width: f32,
+  pub start_color: Color,
+  pub end_color: Color,
+  pub space: LineSpace,
+  pub emitting: bool,
+
+  points: VecDeque<TrailPoint>,
+  head: Option<Vec3>,
+}
+
+impl Trail {
+  pub fn new(lifetime: f32, width: f32, color: Color) -> Self {
+    Trail {
+      lifetime,
+      min_distance: 0.1,
+      start_width: width,
+      end_width: 0.0,
+      start_color: color,
+      end_color: color.with_alpha(0.0),
+      space: LineSpace::World,
+      emitting: true,
+      points: VecDeque::new(),
+      head: None,
+    }
+  }
+
+  pub fn clear(&mut self) {
+    self.points.clear();
+  }
+
+  fn update(&mut self, position: Vec3, dt: f32) {
+    for point in &mut self.points {
+      point.age += dt;
+    }
+    while self.points.back().is_some_and(|point| point.age >= self.lifetime) {
+      self.points.pop_back();
+    }
+
+    if !self.emitting {
+      self.head = None;
+      return;
+    }
+    self.head = Some(position);
+    let far_enough = self.points.front().is_none_or(|point| point.position.distance(position) >= self.min_distance);
+    if far_enough {
+      self.points.push_front(TrailPoint { position, age: 0.0 });
+    }
+  }
+
+  fn at(&self, age: f32) -> (f32, Color) {
+    let t = if self.lifetime > 0.0 { (age / self.lifetime).clamp(0.0, 1.0) } else { 1.0 };
+    (self.start_width + (self.end_width - self.start_width) * t, self.start_color.lerp(self.end_color, t))
+  }
+}
+
+// Records trail points for every entity with a `Transform` and a `Trail`. The engine calls it every
+// frame after the game's update.
+pub fn update_trails(world: &World, dt: f32) {
+  world.query::<(&Transform, &mut Trail)>().for_each(|_, (transform, mut trail)| {
+    trail.bypass_change_detection().update(transform.translation, dt);
+  });
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct LineVertex {
+  pub position: [f32; 3],
+  pub tangent: [f32; 3],
+  pub color: [f32; 4],
+  pub width: f32,
+  pub side: f32, // -1 or 1, which edge of the ribbon this vertex is on
+  pub screen_space: f32, // 1 for `LineSpace::Screen`
+}
+
+// Appends a ribbon through `points` as a triangle list. The vertex shader pushes each vertex out
+// sideways, so the CPU only supplies the centre line and per-point tangents.
+fn push_ribbon(vertices: &mut Vec<LineVertex>, points: &[(Vec3, f32, Color)], space: LineSpace) {
+  if points.len() < 2 {
+    return;
+  }
+  let screen_space = match space {
+    LineSpace::World => 0.0,
+    LineSpace::Screen => 1.0,
+  };
+  let vertex = |i: usize, side: f32| {
+    let (position, width, color) = points[i];
+    let before = points[i.saturating_sub(1)].0;
+    let after = points[(i + 1).min(points.len() - 1)].0;
+    LineVertex {
+      position: position.to_array(),
+      tangent: (after - before).normalize_or_zero().to_array(),
+      color: color.to_array(),
+      width,
+      side,
+      screen_space,
+    }
+  };
+  for i in 0..points.len() - 1 {
+    if points[i].0 == points[i + 1].0 {
+      continue;
+    }
+    let (a, b) = (vertex(i, -1.0), vertex(i, 1.0));
+    let (c, d) = (vertex(i + 1, -1.0), vertex(i + 1, 1.0));
+    vertices.extend_from_slice(&[a, b, c, b, d, c]);
+  }
+}
+
+// Builds the vertices for every `Polyline` and `Trail` in the world.
+pub fn collect_lines(world: &World) -> Vec<LineVertex> {
+  let mut vertices = Vec::new();
+
+  world.query::<(&Polyline, Option<&Transform>)>().for_each(|_, (line, transform)| {
+    let matrix = transform.map_or(Mat4::IDENTITY, |transform| transform.matrix());
+    let mut points: Vec<_> = line.points.iter()
+      .map(|point| (matrix.transform_point3(*point), line.thickness, line.color))
+      .collect();
+    if line.closed && points.len() > 2 {
+      points.push(points[0]);
+    }
+    push_ribbon(&mut vertices, &points, line.space);
+  });
+
+  world.query::<&Trail>().for_each(|_, trail| {
+    let mut points = Vec::with_capacity(trail.points.len() + 1);
+    if let Some(head) = trail.head {
+      let (width, color) = trail.at(0.0);
+      points.push((head, width, color));
+    }
+    for point in &trail.points {
+      let (width, color) = trail.at(point.age);
+      points.push((point.position, width, color));
+    }
+    push_ribbon(&mut vertices, &points, trail.space);
+  });
+
+  vertices
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct LineUniforms {
+  view_proj: [f32; 16],
+  camera_position: [f32; 4],
+  viewport: [f32; 4],
+}
+
+// Draws `Polyline`s and `Trail`s, re-uploading their vertices every frame like the debug lines.
+pub struct LineRenderer {
+  pipeline: RenderPipeline,
+  uniforms: LineUniforms,
+  uniform_buffer: Buffer,
+  bind_group: BindGroup,
+  vertex_buffer: Option<Buffer>,
+  vertex_count: u32,
+}
+
+impl LineRenderer {
+  pub fn new(device: &Device, format: TextureFormat, width: u32, height: u32) -> Self {
+    let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+      label: Some("line-shader"),
+      source: ShaderSource::Wgsl(Cow::Borrowed(
+"
+struct Uniforms {
+    view_proj: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    viewport: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> uniforms: Uniforms;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tangent: vec3<f32>,
+    @location(2) color: vec4<f32>,
+    @location(3) width: f32,
+    @location(4) side: f32,
+    @location(5) screen_space: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.color = in.color;
+    let half_width = in.width * 0.5 * in.side;
+
+    if (in.screen_space > 0.5) {
+        // Offset perpendicular to the line's direction on screen, in pixels.
+        let clip = uniforms.view_proj * vec4<f32>(in.position, 1.0);
+        let ahead = uniforms.view_proj * vec4<f32>(in.position + in.tangent, 1.0);
+        let half_viewport = uniforms.viewport.xy * 0.5;
+        let direction = normalize((ahead.xy / ahead.w - clip.xy / clip.w) * half_viewport);
+        let offset = vec2<f32>(-direction.y, direction.x) * half_width / half_viewport;
+        out.clip_position = vec4<f32>(clip.xy + offset * clip.w, clip.zw);
+    } else {
+        // Offset in world space, turned to face the camera.
+        let to_camera = normalize(uniforms.camera_position.xyz - in.position);
+        let normal = normalize(cross(in.tangent, to_camera));
+        out.clip_position = uniforms.view_proj * vec4<f32>(in.position + normal * half_width, 1.0);
+    }
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
+"
+      ))
+    });
+
+    let uniforms = LineUniforms {
+      view_proj: Mat4::IDENTITY.to_cols_array(),
+      camera_position: [0.0, 0.0, -1.0, 1.0],
+      viewport: [width as f32, height as f32, 0.0, 0.0],
+    };
+    let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("line-uniforms"),
+      usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+      contents: bytemuck::bytes_of(&uniforms)
+    });
+
+    let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("line-bind-group-layout"),
+      entries: &[BindGroupLayoutEntry {
+        binding: 0,
+        visibility: ShaderStages::VERTEX,
+        ty: BindingType::Buffer {
+          ty: BufferBindingType::Uniform,
+          has_dynamic_offset: false,
+          min_binding_size: None
+        },
+        count: None
+      }]
+    });
+
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+      label: Some("line-bind-group"),
+      layout: &bind_group_layout,
+      entries: &[BindGroupEntry { binding: 0, resource: uniform_buffer.as_entire_binding() }]
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+      label: Some("line-pipeline-layout"),
+      bind_group_layouts: &[&bind_group_layout],
+      push_constant_ranges: &[]
+    });
+
+    let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+      label: Some("line-pipeline"),
+      layout: Some(&pipeline_layout),
+      vertex: VertexState {
+        module: &shader_module,
+        entry_point: "vs_main",
+        buffers: &[VertexBufferLayout {
+          array_stride: size_of::<LineVertex>() as BufferAddress,
+          step_mode: VertexStepMode::Vertex,
+          attributes: &[
+            VertexAttribute { format: VertexFormat::Float32x3, shader_location: 0, offset: 0 },
+            VertexAttribute { format: VertexFormat::Float32x3, shader_location: 1, offset: 12 },
+            VertexAttribute { format: VertexFormat::Float32x4, shader_location: 2, offset: 24 },
+            VertexAttribute { format: VertexFormat::Float32, shader_location: 3, offset: 40 },
+            VertexAttribute { format: VertexFormat::Float32, shader_location: 4, offset: 44 },
+            VertexAttribute { format: VertexFormat::Float32, shader_location: 5, offset: 48 }
+          ]
+        }]
+      },
+      fragment: Some(FragmentState {
+        module: &shader_module,
+        entry_point: "fs_main",
+        targets: &[Some(ColorTargetState {
+          format,
+          blend: Some(BlendState::ALPHA_BLENDING),
+          write_mask: ColorWrites::ALL
+        })]
+      }),
+      primitive: PrimitiveState {
+        topology: PrimitiveTopology::TriangleList,
+        cull_mode: None,
+        ..PrimitiveState::default()
+      },
+      depth_stencil: None,
+      multisample: MultisampleState::default(),
+      multiview: None
+    });
+
+    LineRenderer { pipeline, uniforms, uniform_buffer, bind_group, vertex_buffer: None, vertex_count: 0 }
+  }
+
+  pub fn set_camera(&mut self, queue: &Queue, view_projection: Mat4, camera_position: Vec3) {
+    self.uniforms.view_proj = view_projection.to_cols_array();
+    self.uniforms.camera_position = camera_position.extend(1.0).to_array();
+    queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&self.uniforms));
+  }
+
+  // Screen-space widths are in pixels, so this needs updating when the surface is resized.
+  pub fn set_viewport(&mut self, queue: &Queue, width: u32, height: u32) {
+    self.uniforms.viewport = [width as f32, height as f32, 0.0, 0.0];
+    queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&self.uniforms));
+  }
+
+  // Uploads this frame's ribbons, growing the vertex buffer if they don't fit.
+  pub fn prepare(&mut self, device: &Device, queue: &Queue, vertices: &[LineVertex]) {
+    self.vertex_count = vertices.len() as u32;
+    if vertices.is_empty() {
+      return;
+    }
+
+    let size = std::mem::size_of_val(vertices) as BufferAddress;
+    let too_small = self.vertex_buffer.as_ref().is_none_or(|buffer| buffer.size() < size);
+    if too_small {
+      self.vertex_buffer = Some(device.create_buffer(&BufferDescriptor {
+        label: Some("line-vertices"),
+        size: size.next_power_of_two(),
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false
+      }));
+    }
+    queue.write_buffer(self.vertex_buffer.as_ref().unwrap(), 0, bytemuck::cast_slice(vertices));
+  }
+
+  pub fn draw_calls(&self) -> u32 {
+    (self.vertex_buffer.is_some() && self.vertex_count > 0) as u32
+  }
+
+  pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+    let buffer = match &self.vertex_buffer {
+      Some(buffer) if self.vertex_count > 0 => buffer,
+      _ => return,
+    };
+    render_pass.set_pipeline(&self.pipeline);
+    render_pass.set_bind_group(0, &self.bind_group, &[]);
+    render_pass.set_vertex_buffer(0, buffer.slice(..));
+    render_pass.draw(0..self.vertex_count, 0..1);
+  }
+}
@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use bytemuck::{Pod, Zeroable};
+use glam::{UVec2, Vec3};
+use serde::{Deserialize, Serialize};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferBindingType, BufferUsages, Device, FilterMode, Queue, Sampler, SamplerBindingType, SamplerDescriptor, ShaderStages, TextureSampleType, TextureViewDimension};
+
+use super::color::Color;
+use super::gltf::{gltf_material, Document};
+use super::texture::GpuTexture;
+
+// How a surface reflects light, in the metallic-roughness model glTF uses. Each factor is
+// multiplied by its map where there is one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Material {
+  pub name: String,
+  pub base_color: Color, // linear, with alpha
+  pub metallic: f32, // 0 for dielectrics, 1 for bare metal
+  pub roughness: f32, // 0 is a mirror, 1 fully matte
+  pub emissive: Vec3, // linear light given off, above 1 for HDR glow
+  pub normal_scale: f32, // how strongly `normal_map` bends the surface
+  pub base_color_map: Option<PathBuf>, // sRGB
+  pub metallic_roughness_map: Option<PathBuf>, // roughness in green, metallic in blue, as glTF packs them
+  pub normal_map: Option<PathBuf>, // tangent space, +y up
+  pub emissive_map: Option<PathBuf>, // sRGB
+}
+
+impl Default for Material {
+  fn default() -> Self {
+    Material {
+      name: String::new(),
+      base_color: Color::WHITE,
+      metallic: 0.0,
+      roughness: 0.5,
+      emissive: Vec3::ZERO,
+      normal_scale: 1.0,
+      base_color_map: None,
+      metallic_roughness_map: None,
+      normal_map: None,
+      emissive_map: None,
+    }
+  }
+}
+
+impl Material {
+  // From a `.mtl` material, with maps relative to `directory`. The PBR extension's `Pr`, `Pm` and
+  // `Ke` are used when present; otherwise roughness comes from the Phong shininess `Ns`.
+  pub fn from_mtl(material: &tobj::Material, directory: &Path) -> Self {
+    let param = |name: &str| material.unknown_param.get(name).map(|value| value.trim());
+    let number = |name: &str| param(name).and_then(|value| value.parse::<f32>().ok());
+    let map = |file: &str| match file.trim() {
+      "" => None,
+      file => Some(directory.join(file)),
+    };
+    let emissive = param("Ke")
+      .map(|value| value.split_whitespace().filter_map(|part| part.parse::<f32>().ok()).collect::<Vec<_>>())
+      .filter(|parts| parts.len() == 3)
+      .map_or(Vec3::ZERO, |parts| Vec3::new(parts[0], parts[1], parts[2]));
+    Material {
+      name: material.name.clone(),
+      base_color: Color::rgba(material.diffuse[0], material.diffuse[1], material.diffuse[2], material.dissolve),
+      metallic: number("Pm").unwrap_or(0.0).clamp(0.0, 1.0),
+      roughness: number("Pr").unwrap_or_else(|| (2.0 / (material.shininess.max(0.0) + 2.0)).sqrt()).clamp(0.0, 1.0),
+      emissive,
+      normal_scale: 1.0,
+      base_color_map: map(&material.diffuse_texture),
+      metallic_roughness_map: None,
+      normal_map: map(&material.normal_texture),
+      emissive_map: param("map_Ke").and_then(map),
+    }
+  }
+
+  // Every material in a glTF 2.0 document's JSON, in order, so a primitive's `material` index picks
+  // one out. Images are resolved relative to `directory`; ones embedded in buffers or data URIs
+  // aren't supported and are left off.
+  pub fn from_gltf(json: &str, directory: &Path) -> Result<Vec<Material>, String> {
+    let document: Document = serde_json::from_str(json).map_err(|err| format!("couldn't parse glTF: {}", err))?;
+    Ok(document.materials.iter().map(|material| gltf_material(&document, material, directory)).collect())
+  }
+
+  pub fn load_gltf(path: impl AsRef<Path>) -> Result<Vec<Material>, String> {
+    let path = path.as_ref();
+    let json = std::fs::read_to_string(path).map_err(|err| format!("couldn't read {}: {}", path.display(), err))?;
+    Material::from_gltf(&json, path.parent().unwrap_or(Path::new(""))).map_err(|err| format!("{}: {}", path.display(), err))
+  }
+
+  fn key(&self) -> MaterialKey {
+    let factors = [
+      self.base_color.r, self.base_color.g, self.base_color.b, self.base_color.a,
+      self.metallic, self.roughness, self.emissive.x, self.emissive.y, self.emissive.z, self.normal_scale,
+    ];
+    MaterialKey {
+      factors: factors.map(f32::to_bits),
+      maps: [&self.base_color_map, &self.metallic_roughness_map, &self.normal_map, &self.emissive_map].map(Clone::clone),
+    }
+  }
+
+  fn uniforms(&self) -> MaterialUniforms {
+    MaterialUniforms {
+      base_color: self.base_color.to_array(),
+      emissive: self.emissive.extend(0.0).to_array(),
+      factors: [self.metallic.clamp(0.0, 1.0), self.roughness.clamp(0.0, 1.0), self.normal_scale, 0.0],
+    }
+  }
+}
+
+// Whether each of a key's maps holds data rather than colour: base colour and emission are
+// colours; metallic-roughness and normals are data.
+const LINEAR_MAPS: [bool; 4] = [false, true, true, false];
+
+// A `Material` as the cache tells them apart: its factors bit for bit, and its maps.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct MaterialKey {
+  factors: [u32; 10],
+  maps: [Option<PathBuf>; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct MaterialUniforms {
+  base_color: [f32; 4],
+  emissive: [f32; 4],
+  factors: [f32; 4], // metallic, roughness, normal scale
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MaterialHandle(usize);
+
+impl MaterialHandle {
+  // A number for the material in sort keys like `DrawList`'s.
+  pub fn index(self) -> u32 {
+    self.0 as u32
+  }
+}
+
+struct GpuMaterial {
+  bind_group: BindGroup,
+  uniforms: Buffer,
+}
+
+// Materials on the GPU, for the model pipeline's group 2. Asking for the same `Material` twice
+// gives back the same bind group, and maps are loaded once however many materials share them.
+pub struct MaterialCache {
+  pub layout: BindGroupLayout,
+  sampler: Sampler,
+  materials: Vec<GpuMaterial>,
+  by_key: HashMap<MaterialKey, MaterialHandle>,
+  maps: HashMap<(PathBuf, bool), Option<GpuTexture>>, // by path and whether it's linear; `None` failed to load
+  defaults: [GpuTexture; 4], // in place of each missing map
+}
+
+impl MaterialCache {
+  pub fn new(device: &Device, queue: &Queue) -> Self {
+    let texture_entry = |binding| BindGroupLayoutEntry {
+      binding,
+      visibility: ShaderStages::FRAGMENT,
+      ty: BindingType::Texture {
+        sample_type: TextureSampleType::Float { filterable: true },
+        view_dimension: TextureViewDimension::D2,
+        multisampled: false
+      },
+      count: None
+    };
+    let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("material-bind-group-layout"),
+      entries: &[
+        BindGroupLayoutEntry {
+          binding: 0,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None
+          },
+          count: None
+        },
+        BindGroupLayoutEntry {
+          binding: 1,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Sampler(SamplerBindingType::Filtering),
+          count: None
+        },
+        texture_entry(2),
+        texture_entry(3),
+        texture_entry(4),
+        texture_entry(5)
+      ]
+    });
+
+    let sampler = device.create_sampler(&SamplerDescriptor {
+      label: Some("material-sampler"),
+      address_mode_u: AddressMode::Repeat,
+      address_mode_v: AddressMode::Repeat,
+      mag_filter: FilterMode::Linear,
+      min_filter: FilterMode::Linear,
+      mipmap_filter: FilterMode::Linear,
+      ..SamplerDescriptor::default()
+    });
+
+    // White leaves each factor as it is, and the flat normal leaves the surface alone.
+    let defaults = [
+      GpuTexture::from_rgba(device, queue, UVec2::ONE, vec![255; 4]),
+      GpuTexture::from_rgba_linear(device, queue, UVec2::ONE, vec![255; 4]),
+      GpuTexture::from_rgba_linear(device, queue, UVec2::ONE, vec![128, 128, 255, 255]),
+      GpuTexture::from_rgba(device, queue, UVec2::ONE, vec![255; 4]),
+    ];
+
+    MaterialCache { layout, sampler, materials: Vec::new(), by_key: HashMap::new(), maps: HashMap::new(), defaults }
+  }
+
+  // The bind group for `material`, made the first time it's asked for. Maps that don't load are
+  // reported and left out.
+  pub fn get_or_create(&mut self, device: &Device, queue: &Queue, material: &Material) -> MaterialHandle {
+    let key = material.key();
+    if let Some(handle) = self.by_key.get(&key) {
+      return *handle;
+    }
+
+    for (path, linear) in key.maps.iter().zip(LINEAR_MAPS) {
+      if let Some(path) = path {
+        self.maps.entry((path.clone(), linear)).or_insert_with(|| load_map(device, queue, path, linear));
+      }
+    }
+    let uniforms = device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("material-uniforms"),
+      contents: bytemuck::bytes_of(&material.uniforms()),
+      usage: BufferUsages::UNIFORM
+    });
+    let bind_group = self.create_bind_group(device, &key, &uniforms);
+
+    let handle = MaterialHandle(self.materials.len());
+    self.materials.push(GpuMaterial { bind_group, uniforms });
+    self.by_key.insert(key, handle);
+    handle
+  }
+
+  fn create_bind_group(&self, device: &Device, key: &MaterialKey, uniforms: &Buffer) -> BindGroup {
+    let views: Vec<&wgpu::TextureView> = key.maps.iter().zip(LINEAR_MAPS).zip(&self.defaults)
+      .map(|((path, linear), default)| {
+        let loaded = path.as_ref().and_then(|path| self.maps[&(path.clone(), linear)].as_ref());
+        &loaded.unwrap_or(default).view
+      })
+      .collect();
+
+    device.create_bind_group(&BindGroupDescriptor {
+      label: Some("material-bind-group"),
+      layout: &self.layout,
+      entries: &[
+        BindGroupEntry { binding: 0, resource: uniforms.as_entire_binding() },
+        BindGroupEntry { binding: 1, resource: BindingResource::Sampler(&self.sampler) },
+        BindGroupEntry { binding: 2, resource: BindingResource::TextureView(views[0]) },
+        BindGroupEntry { binding: 3, resource: BindingResource::TextureView(views[1]) },
+        BindGroupEntry { binding: 4, resource: BindingResource::TextureView(views[2]) },
+        BindGroupEntry { binding: 5, resource: BindingResource::TextureView(views[3]) }
+      ]
+    })
+  }
+
+  // Loads `path` again for every map that uses it and rebuilds the bind groups of the materials
+  // that do. False if no material uses it.
+  pub fn reload_map(&mut self, device: &Device, queue: &Queue, path: &Path) -> bool {
+    let mut reloaded = false;
+    for ((map, linear), texture) in self.maps.iter_mut() {
+      if map == path {
+        *texture = load_map(device, queue, map, *linear);
+        reloaded = true;
+      }
+    }
+    if reloaded {
+      let keys: Vec<(MaterialKey, MaterialHandle)> = self.by_key.iter()
+        .filter(|(key, _)| key.maps.iter().flatten().any(|map| map == path))
+        .map(|(key, handle)| (key.clone(), *handle))
+        .collect();
+      for (key, handle) in keys {
+        let bind_group = self.create_bind_group(device, &key, &self.materials[handle.0].uniforms);
+        self.materials[handle.0].bind_group = bind_group;
+      }
+    }
+    reloaded
+  }
+
+  // Every map file a material uses, for watching.
+  pub fn map_paths(&self) -> impl Iterator<Item = &Path> {
+    self.maps.keys().map(|(path, _)| path.as_path())
+  }
+
+  pub fn bind_group(&self, handle: MaterialHandle) -> &BindGroup {
+    &self.materials[handle.0].bind_group
+  }
+
+  pub fn len(&self) -> usize {
+    self.materials.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.materials.is_empty()
+  }
+}
+
+fn load_map(device: &Device, queue: &Queue, path: &Path, linear: bool) -> Option<GpuTexture> {
+  let image = match image::open(path) {
+    Ok(image) => image.to_rgba8(),
+    Err(err) => {
+      log::warn!("couldn't load {}: {}", path.display(), err);
+      return None;
+    }
+  };
+  let size = UVec2::new(image.width(), image.height());
+  Some(match linear {
+    true => GpuTexture::from_rgba_linear(device, queue, size, image.into_raw()),
+    false => GpuTexture::from_rgba(device, queue, size, image.into_raw()),
+  })
+}
@@ -0,0 +1,359 @@
+use std::mem::size_of;
+use std::ops::Range;
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat3, Vec2, Vec3};
+use wgpu::{Buffer, BufferAddress, BufferDescriptor, BufferUsages, Device, IndexFormat, Queue, RenderPass, VertexAttribute, VertexBufferLayout, VertexFormat, VertexStepMode};
+
+use crate::game_engine::assets::Asset;
+use crate::game_engine::ecs::Transform;
+use super::upload::UploadQueue;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Pod, Zeroable)]
+pub struct Vertex {
+  pub position: [f32; 3],
+  pub normal: [f32; 3],
+  pub uv: [f32; 2],
+}
+
+impl Vertex {
+  const ATTRIBUTES: [VertexAttribute; 3] = [
+    VertexAttribute { format: VertexFormat::Float32x3, shader_location: 0, offset: 0 },
+    VertexAttribute { format: VertexFormat::Float32x3, shader_location: 1, offset: 12 },
+    VertexAttribute { format: VertexFormat::Float32x2, shader_location: 2, offset: 24 },
+  ];
+
+  pub fn new(position: Vec3, normal: Vec3, uv: Vec2) -> Self {
+    Vertex { position: position.to_array(), normal: normal.to_array(), uv: uv.to_array() }
+  }
+
+  // Positions, normals and UVs at shader locations 0, 1 and 2.
+  pub fn layout() -> VertexBufferLayout<'static> {
+    VertexBufferLayout {
+      array_stride: size_of::<Vertex>() as BufferAddress,
+      step_mode: VertexStepMode::Vertex,
+      attributes: &Vertex::ATTRIBUTES
+    }
+  }
+}
+
+// Accumulates geometry for a `Mesh`. Triangles are counter-clockwise when seen from the front.
+#[derive(Debug, Clone, Default)]
+pub struct MeshBuilder {
+  vertices: Vec<Vertex>,
+  indices: Vec<u32>,
+}
+
+impl MeshBuilder {
+  pub fn new() -> Self {
+    MeshBuilder::default()
+  }
+
+  pub fn with_capacity(vertices: usize, indices: usize) -> Self {
+    MeshBuilder { vertices: Vec::with_capacity(vertices), indices: Vec::with_capacity(indices) }
+  }
+
+  // Returns the new vertex's index, for use in `triangle`.
+  pub fn vertex(&mut self, position: Vec3, normal: Vec3, uv: Vec2) -> u32 {
+    self.vertices.push(Vertex::new(position, normal, uv));
+    self.vertices.len() as u32 - 1
+  }
+
+  pub fn triangle(&mut self, a: u32, b: u32, c: u32) -> &mut Self {
+    self.indices.extend_from_slice(&[a, b, c]);
+    self
+  }
+
+  // Two triangles over `a b c d`, given counter-clockwise.
+  pub fn quad(&mut self, a: u32, b: u32, c: u32, d: u32) -> &mut Self {
+    self.indices.extend_from_slice(&[a, b, c, a, c, d]);
+    self
+  }
+
+  // Appends another mesh's geometry, offsetting its indices.
+  pub fn append(&mut self, other: &MeshBuilder) -> &mut Self {
+    let offset = self.vertices.len() as u32;
+    self.vertices.extend_from_slice(&other.vertices);
+    self.indices.extend(other.indices.iter().map(|index| index + offset));
+    self
+  }
+
+  // Appends `mesh` moved into place by `transform`, so it can be drawn without a model matrix.
+  pub fn append_transformed(&mut self, mesh: &Mesh, transform: &Transform) -> &mut Self {
+    let matrix = transform.matrix();
+    let normal_matrix = Mat3::from_mat4(matrix).inverse().transpose();
+    let offset = self.vertices.len() as u32;
+    self.vertices.extend(mesh.vertices.iter().map(|vertex| Vertex::new(
+      matrix.transform_point3(Vec3::from(vertex.position)),
+      (normal_matrix * Vec3::from(vertex.normal)).normalize_or_zero(),
+      Vec2::from(vertex.uv),
+    )));
+    self.indices.extend(mesh.indices.iter().map(|index| index + offset));
+    self
+  }
+
+  // Replaces every normal with the area-weighted average of its adjacent faces.
+  pub fn compute_normals(&mut self) -> &mut Self {
+    let mut normals = vec![Vec3::ZERO; self.vertices.len()];
+    for triangle in self.indices.chunks_exact(3) {
+      let [a, b, c] = [triangle[0] as usize, triangle[1] as usize, triangle[2] as usize];
+      let position = |i: usize| Vec3::from(self.vertices[i].position);
+      let normal = (position(b) - position(a)).cross(position(c) - position(a));
+      normals[a] += normal;
+      normals[b] += normal;
+      normals[c] += normal;
+    }
+    for (vertex, normal) in self.vertices.iter_mut().zip(normals) {
+      vertex.normal = normal.normalize_or_zero().to_array();
+    }
+    self
+  }
+
+  pub fn vertex_count(&self) -> usize {
+    self.vertices.len()
+  }
+
+  pub fn index_count(&self) -> usize {
+    self.indices.len()
+  }
+
+  pub fn build(self) -> Mesh {
+    let dirty_vertices = Some(0..self.vertices.len());
+    let dirty_indices = Some(0..self.indices.len());
+    Mesh { vertices: self.vertices, indices: self.indices, dirty_vertices, dirty_indices }
+  }
+
+  // A flat `size` x `size` square in the XZ plane facing +Y, split into `subdivisions` cells per side.
+  pub fn plane(size: f32, subdivisions: u32) -> Self {
+    let cells = subdivisions.max(1);
+    let mut builder = MeshBuilder::new();
+    for z in 0..=cells {
+      for x in 0..=cells {
+        let uv = Vec2::new(x as f32, z as f32) / cells as f32;
+        builder.vertex(Vec3::new(uv.x - 0.5, 0.0, uv.y - 0.5) * size, Vec3::Y, uv);
+      }
+    }
+    let row = cells + 1;
+    for z in 0..cells {
+      for x in 0..cells {
+        let a = z * row + x;
+        builder.quad(a, a + row, a + row + 1, a + 1);
+      }
+    }
+    builder
+  }
+
+  // A box centred on the origin with separate vertices per face, so edges stay sharp.
+  pub fn cuboid(size: Vec3) -> Self {
+    let half = size / 2.0;
+    let mut builder = MeshBuilder::with_capacity(24, 36);
+    for normal in [Vec3::X, -Vec3::X, Vec3::Y, -Vec3::Y, Vec3::Z, -Vec3::Z] {
+      let tangent = if normal.y.abs() > 0.5 { Vec3::X } else { Vec3::Y.cross(normal) };
+      let bitangent = normal.cross(tangent);
+      let corner = |u: f32, v: f32| (normal + tangent * u + bitangent * v) * half;
+      let a = builder.vertex(corner(-1.0, -1.0), normal, Vec2::new(0.0, 1.0));
+      let b = builder.vertex(corner(1.0, -1.0), normal, Vec2::new(1.0, 1.0));
+      let c = builder.vertex(corner(1.0, 1.0), normal, Vec2::new(1.0, 0.0));
+      let d = builder.vertex(corner(-1.0, 1.0), normal, Vec2::new(0.0, 0.0));
+      builder.quad(a, b, c, d);
+    }
+    builder
+  }
+}
+
+// Indexed triangle geometry kept on the CPU. Edits record which vertices and indices changed, so
+// `GpuMesh::update` only re-uploads those ranges.
+#[derive(Debug, Clone)]
+pub struct Mesh {
+  vertices: Vec<Vertex>,
+  indices: Vec<u32>,
+  dirty_vertices: Option<Range<usize>>,
+  dirty_indices: Option<Range<usize>>,
+}
+
+impl Mesh {
+  pub fn vertices(&self) -> &[Vertex] {
+    &self.vertices
+  }
+
+  pub fn indices(&self) -> &[u32] {
+    &self.indices
+  }
+
+  // Mutable access to some vertices, marking them for upload.
+  pub fn vertices_mut(&mut self, range: Range<usize>) -> &mut [Vertex] {
+    self.dirty_vertices = Some(union(self.dirty_vertices.take(), range.clone()));
+    &mut self.vertices[range]
+  }
+
+  pub fn indices_mut(&mut self, range: Range<usize>) -> &mut [u32] {
+    self.dirty_indices = Some(union(self.dirty_indices.take(), range.clone()));
+    &mut self.indices[range]
+  }
+
+  pub fn set_position(&mut self, index: usize, position: Vec3) {
+    self.vertices_mut(index..index + 1)[0].position = position.to_array();
+  }
+
+  // Swaps in completely new geometry, e.g. after remeshing a terrain chunk.
+  pub fn replace(&mut self, builder: MeshBuilder) {
+    *self = builder.build();
+  }
+
+  // The bounding box of all vertices, or `None` for an empty mesh.
+  pub fn bounds(&self) -> Option<(Vec3, Vec3)> {
+    let mut positions = self.vertices.iter().map(|vertex| Vec3::from(vertex.position));
+    let first = positions.next()?;
+    Some(positions.fold((first, first), |(min, max), position| (min.min(position), max.max(position))))
+  }
+}
+
+impl Mesh {
+  // One object from a loaded OBJ. Normals are computed if the file has none.
+  pub fn from_obj(mesh: &tobj::Mesh) -> Mesh {
+    let mut builder = MeshBuilder::with_capacity(mesh.positions.len() / 3, mesh.indices.len());
+    if !append_obj(&mut builder, mesh) {
+      builder.compute_normals();
+    }
+    builder.build()
+  }
+}
+
+// Wavefront OBJ, with every object merged into one mesh. Materials are ignored.
+impl Asset for Mesh {
+  fn from_bytes(bytes: &[u8], _path: &std::path::Path) -> Result<Self, String> {
+    let options = tobj::LoadOptions { single_index: true, triangulate: true, ..tobj::LoadOptions::default() };
+    let (models, _) = tobj::load_obj_buf(&mut std::io::Cursor::new(bytes), &options, |_| Err(tobj::LoadError::OpenFileFailed))
+      .map_err(|err| format!("bad obj: {}", err))?;
+
+    let mut builder = MeshBuilder::new();
+    let mut has_normals = true;
+    for model in &models {
+      has_normals &= append_obj(&mut builder, &model.mesh);
+    }
+    if !has_normals {
+      builder.compute_normals();
+    }
+    Ok(builder.build())
+  }
+}
+
+// Adds an OBJ object's triangles to `builder`, returning whether it came with normals.
+fn append_obj(builder: &mut MeshBuilder, mesh: &tobj::Mesh) -> bool {
+  let base = builder.vertex_count() as u32;
+  for i in 0..mesh.positions.len() / 3 {
+    let position = Vec3::new(mesh.positions[i * 3], mesh.positions[i * 3 + 1], mesh.positions[i * 3 + 2]);
+    let normal = mesh.normals.get(i * 3..i * 3 + 3).map_or(Vec3::ZERO, |normal| Vec3::new(normal[0], normal[1], normal[2]));
+    // OBJ puts v = 0 at the bottom of the image.
+    let uv = mesh.texcoords.get(i * 2..i * 2 + 2).map_or(Vec2::ZERO, |uv| Vec2::new(uv[0], 1.0 - uv[1]));
+    builder.vertex(position, normal, uv);
+  }
+  for triangle in mesh.indices.chunks_exact(3) {
+    builder.triangle(base + triangle[0], base + triangle[1], base + triangle[2]);
+  }
+  !mesh.normals.is_empty()
+}
+
+fn union(existing: Option<Range<usize>>, range: Range<usize>) -> Range<usize> {
+  match existing {
+    Some(existing) => existing.start.min(range.start)..existing.end.max(range.end),
+    None => range,
+  }
+}
+
+// A `Mesh`'s vertex and index buffers on the GPU. Buffers are allocated with spare room so meshes
+// that grow a little don't need a new buffer every edit.
+pub struct GpuMesh {
+  vertex_buffer: Buffer,
+  index_buffer: Buffer,
+  vertex_capacity: usize,
+  index_capacity: usize,
+  index_count: u32,
+}
+
+impl GpuMesh {
+  pub fn new(device: &Device, queue: &Queue, mesh: &mut Mesh) -> Self {
+    let mut gpu_mesh = GpuMesh::allocate(device, mesh);
+    gpu_mesh.update(device, queue, mesh);
+    gpu_mesh
+  }
+
+  // Like `new`, but copies the mesh in through `uploads` rather than the queue's own staging memory,
+  // for big meshes.
+  pub fn staged(device: &Device, uploads: &mut UploadQueue, mesh: &mut Mesh) -> Self {
+    let mut gpu_mesh = GpuMesh::allocate(device, mesh);
+    gpu_mesh.stage(device, uploads, mesh);
+    gpu_mesh
+  }
+
+  fn allocate(device: &Device, mesh: &mut Mesh) -> Self {
+    let vertex_capacity = mesh.vertices.len().max(1).next_power_of_two();
+    let index_capacity = mesh.indices.len().max(1).next_power_of_two();
+    mesh.dirty_vertices = Some(0..mesh.vertices.len());
+    mesh.dirty_indices = Some(0..mesh.indices.len());
+    GpuMesh {
+      vertex_buffer: create_buffer(device, "mesh-vertices", BufferUsages::VERTEX, vertex_capacity * size_of::<Vertex>()),
+      index_buffer: create_buffer(device, "mesh-indices", BufferUsages::INDEX, index_capacity * size_of::<u32>()),
+      vertex_capacity,
+      index_capacity,
+      index_count: 0,
+    }
+  }
+
+  // Uploads whatever changed in `mesh` since the last update.
+  pub fn update(&mut self, device: &Device, queue: &Queue, mesh: &mut Mesh) {
+    self.write_changes(device, mesh, |buffer, offset, data| queue.write_buffer(buffer, offset, data));
+  }
+
+  // `update` through `uploads`.
+  pub fn stage(&mut self, device: &Device, uploads: &mut UploadQueue, mesh: &mut Mesh) {
+    self.write_changes(device, mesh, |buffer, offset, data| uploads.write_buffer(device, buffer, offset, data));
+  }
+
+  fn write_changes(&mut self, device: &Device, mesh: &mut Mesh, mut write: impl FnMut(&Buffer, BufferAddress, &[u8])) {
+    if mesh.vertices.len() > self.vertex_capacity {
+      self.vertex_capacity = mesh.vertices.len().next_power_of_two();
+      self.vertex_buffer = create_buffer(device, "mesh-vertices", BufferUsages::VERTEX, self.vertex_capacity * size_of::<Vertex>());
+      mesh.dirty_vertices = Some(0..mesh.vertices.len());
+    }
+    if mesh.indices.len() > self.index_capacity {
+      self.index_capacity = mesh.indices.len().next_power_of_two();
+      self.index_buffer = create_buffer(device, "mesh-indices", BufferUsages::INDEX, self.index_capacity * size_of::<u32>());
+      mesh.dirty_indices = Some(0..mesh.indices.len());
+    }
+
+    if let Some(range) = mesh.dirty_vertices.take().filter(|range| !range.is_empty()) {
+      let offset = (range.start * size_of::<Vertex>()) as BufferAddress;
+      write(&self.vertex_buffer, offset, bytemuck::cast_slice(&mesh.vertices[range]));
+    }
+    if let Some(range) = mesh.dirty_indices.take().filter(|range| !range.is_empty()) {
+      let offset = (range.start * size_of::<u32>()) as BufferAddress;
+      write(&self.index_buffer, offset, bytemuck::cast_slice(&mesh.indices[range]));
+    }
+    self.index_count = mesh.indices.len() as u32;
+  }
+
+  pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+    self.draw_instanced(render_pass, 0..1);
+  }
+
+  // Draws `instances` copies in one call. The pipeline reads them from whatever is bound at vertex
+  // buffer slot 1, e.g. `InstanceRaw`s.
+  pub fn draw_instanced<'a>(&'a self, render_pass: &mut RenderPass<'a>, instances: Range<u32>) {
+    if self.index_count == 0 || instances.is_empty() {
+      return;
+    }
+    render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+    render_pass.set_index_buffer(self.index_buffer.slice(..), IndexFormat::Uint32);
+    render_pass.draw_indexed(0..self.index_count, 0, instances);
+  }
+}
+
+fn create_buffer(device: &Device, label: &str, usage: BufferUsages, size: usize) -> Buffer {
+  device.create_buffer(&BufferDescriptor {
+    label: Some(label),
+    size: size as BufferAddress,
+    usage: usage | BufferUsages::COPY_DST,
+    mapped_at_creation: false
+  })
+}
@@ -1 +1,46 @@
-pub mod graphics_state;
\ No newline at end of file
+pub mod accessibility;
+pub mod animation;
+pub mod backend;
+pub mod bind_group_cache;
+pub mod buffer_pool;
+pub mod camera;
+pub mod camera_2d;
+pub mod color;
+pub mod compute;
+pub mod cubemap;
+pub mod culling;
+pub mod cursor;
+pub mod debug_draw;
+pub mod debug_markers;
+pub mod draw_list;
+pub mod frame_allocator;
+pub mod gltf;
+pub mod graphics_state;
+pub mod instancing;
+pub mod lighting;
+pub mod lightmap;
+pub mod lines;
+pub mod material;
+pub mod mesh;
+pub mod model;
+pub mod particles;
+pub mod pixel_perfect;
+pub mod post_process;
+pub mod procedural_texture;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod recorder;
+pub mod render_graph;
+pub mod render_target;
+pub mod render_thread;
+pub mod screenshot;
+pub mod shaders;
+pub mod skinning;
+pub mod skybox;
+pub mod sprite_animation;
+pub mod sprite_batch;
+pub mod static_batch;
+pub mod text;
+pub mod texture;
+pub mod tilemap_renderer;
+pub mod upload;
+pub mod viewport;
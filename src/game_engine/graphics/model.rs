@@ -0,0 +1,146 @@
+use std::path::Path;
+use glam::{Mat4, UVec2, Vec3};
+use tobj::{Material as MtlMaterial, Model};
+use wgpu::{BindGroup, Device, Extent3d, Queue, RenderPass, RenderPipeline, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureView, TextureViewDescriptor};
+
+use crate::game_engine::ecs::World;
+use super::color::Color;
+use super::draw_list::{view_depth, DrawList, RenderPhase};
+use super::material::{Material, MaterialCache, MaterialHandle};
+use super::mesh::{GpuMesh, Mesh};
+use super::static_batch::StaticBatches;
+
+pub const DEPTH_FORMAT: TextureFormat = TextureFormat::Depth32Float;
+
+// The loaded OBJ models on the GPU, one mesh per object, each drawn with its own material.
+pub struct ModelRenderer {
+  pub materials: MaterialCache,
+  meshes: Vec<(GpuMesh, MaterialHandle)>,
+  centers: Vec<Vec3>, // of each mesh's bounds, for sorting by depth
+  statics: StaticBatches, // the world's `StaticMesh` entities
+  static_materials: Vec<MaterialHandle>, // each static batch's
+}
+
+// Something the model pass draws with the model pipeline, from a `DrawList` sorted each frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SceneDraw {
+  Mesh(usize), // the `ModelRenderer`'s
+  Static(usize), // a static batch
+}
+
+impl ModelRenderer {
+  // Texture paths in `materials` are relative to `directory`, normally the OBJ's own.
+  pub fn new(device: &Device, queue: &Queue, models: &[Model], materials: &[MtlMaterial], directory: &Path) -> Self {
+    let mut cache = MaterialCache::new(device, queue);
+    let handles: Vec<MaterialHandle> = materials.iter()
+      .map(|material| cache.get_or_create(device, queue, &Material::from_mtl(material, directory)))
+      .collect();
+    // For objects without a material.
+    let default_material = cache.get_or_create(device, queue, &Material { base_color: Color::rgb(0.8, 0.8, 0.8), roughness: 0.7, ..Material::default() });
+
+    let (meshes, centers) = models.iter()
+      .map(|model| {
+        let material = model.mesh.material_id.and_then(|id| handles.get(id).copied()).unwrap_or(default_material);
+        let mut mesh = Mesh::from_obj(&model.mesh);
+        let center = mesh.bounds().map_or(Vec3::ZERO, |(min, max)| (min + max) / 2.0);
+        ((GpuMesh::new(device, queue, &mut mesh), material), center)
+      })
+      .unzip();
+
+    ModelRenderer { materials: cache, meshes, centers, statics: StaticBatches::new(), static_materials: Vec::new() }
+  }
+
+  // Rebuilds the static batches if the world's `StaticMesh` entities changed.
+  pub fn sync_statics(&mut self, world: &World, device: &Device, queue: &Queue) {
+    if self.statics.sync(world, device, queue) {
+      self.static_materials = self.statics.batches().iter().map(|batch| self.materials.get_or_create(device, queue, &batch.material)).collect();
+    }
+  }
+
+  pub fn statics(&self) -> &StaticBatches {
+    &self.statics
+  }
+
+  // Draws the `object`th model with `material` from now on, e.g. one from `Material::from_gltf`.
+  // False if there's no such object.
+  pub fn set_material(&mut self, device: &Device, queue: &Queue, object: usize, material: &Material) -> bool {
+    let handle = self.materials.get_or_create(device, queue, material);
+    match self.meshes.get_mut(object) {
+      Some((_, current)) => {
+        *current = handle;
+        true
+      }
+      None => false,
+    }
+  }
+
+  pub fn draw_calls(&self) -> u32 {
+    (self.meshes.len() + self.statics.batches().len()) as u32
+  }
+
+  pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>, pipeline: &'a RenderPipeline, camera: &'a BindGroup, light: &'a BindGroup) {
+    render_pass.set_pipeline(pipeline);
+    render_pass.set_bind_group(0, camera, &[]);
+    render_pass.set_bind_group(1, light, &[]);
+    for (mesh, material) in &self.meshes {
+      render_pass.set_bind_group(2, self.materials.bind_group(*material), &[]);
+      mesh.draw(render_pass);
+    }
+    for (batch, material) in self.statics.batches().iter().zip(&self.static_materials) {
+      render_pass.set_bind_group(2, self.materials.bind_group(*material), &[]);
+      batch.draw(render_pass);
+    }
+  }
+
+  // Adds every mesh and static batch to `draws`, keyed by its material and then its distance in
+  // front of `view`.
+  pub fn queue(&self, draws: &mut DrawList<SceneDraw>, view: Mat4) {
+    for (index, ((_, material), center)) in self.meshes.iter().zip(&self.centers).enumerate() {
+      draws.push(RenderPhase::Opaque, 0, material.index(), view_depth(view, *center), SceneDraw::Mesh(index));
+    }
+    for (index, (batch, material)) in self.statics.batches().iter().zip(&self.static_materials).enumerate() {
+      draws.push(RenderPhase::Opaque, 0, material.index(), view_depth(view, batch.center()), SceneDraw::Static(index));
+    }
+  }
+
+  // Draws a sorted `draws`, binding each material only where it changes.
+  pub fn draw_list<'a>(&'a self, render_pass: &mut RenderPass<'a>, pipeline: &'a RenderPipeline, camera: &'a BindGroup, light: &'a BindGroup, draws: &'a DrawList<SceneDraw>) {
+    render_pass.set_pipeline(pipeline);
+    render_pass.set_bind_group(0, camera, &[]);
+    render_pass.set_bind_group(1, light, &[]);
+    // The list is queued from this renderer every frame, so its indices are always in range.
+    for draw in draws.iter() {
+      let material = match *draw.item {
+        SceneDraw::Mesh(index) => self.meshes[index].1,
+        SceneDraw::Static(index) => self.static_materials[index],
+      };
+      if draw.bind_material {
+        render_pass.set_bind_group(2, self.materials.bind_group(material), &[]);
+      }
+      match *draw.item {
+        SceneDraw::Mesh(index) => self.meshes[index].0.draw(render_pass),
+        SceneDraw::Static(index) => self.statics.batches()[index].draw(render_pass),
+      }
+    }
+  }
+
+  // Just the geometry, with whatever pipeline the caller set, for depth-only passes like shadows.
+  pub fn draw_depth<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+    for (mesh, _) in &self.meshes {
+      mesh.draw(render_pass);
+    }
+    self.statics.draw(render_pass);
+  }
+}
+
+pub(crate) fn create_depth(device: &Device, target: UVec2) -> TextureView {
+  device.create_texture(&TextureDescriptor {
+    label: Some("model-depth"),
+    size: Extent3d { width: target.x.max(1), height: target.y.max(1), depth_or_array_layers: 1 },
+    mip_level_count: 1,
+    sample_count: 1,
+    dimension: TextureDimension::D2,
+    format: DEPTH_FORMAT,
+    usage: TextureUsages::RENDER_ATTACHMENT
+  }).create_view(&TextureViewDescriptor::default())
+}
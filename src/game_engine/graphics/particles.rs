@@ -0,0 +1,489 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use bytemuck::{Pod, Zeroable};
+use glam::{Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BlendState, Buffer, BufferAddress, BufferBindingType, BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites, CommandEncoder, CompareFunction, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor, DepthStencilState, Device, FragmentState, MultisampleState, PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPass, RenderPipeline, RenderPipelineDescriptor, ShaderModule, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureFormat, VertexState};
+
+use crate::game_engine::ecs::{Entity, Transform, World};
+use super::camera::Camera;
+use super::color::Color;
+use super::debug_markers::DebugScope;
+use super::model::DEPTH_FORMAT;
+
+const WORKGROUP_SIZE: u32 = 64;
+
+const PARTICLE_WGSL: &str = "
+struct Emitter {
+    origin: vec4<f32>, // w is the spread
+    direction: vec4<f32>, // w is the time step
+    gravity: vec4<f32>,
+    ranges: vec4<f32>, // lifetime min and max, speed min and max
+    start_color: vec4<f32>,
+    end_color: vec4<f32>,
+    size: vec4<f32>, // at birth and death
+    camera_right: vec4<f32>,
+    camera_up: vec4<f32>,
+    spawn: vec4<u32>, // first slot, count, capacity, seed
+};
+
+struct Particle {
+    position: vec3<f32>,
+    age: f32,
+    velocity: vec3<f32>,
+    lifetime: f32,
+};
+
+@group(1) @binding(0) var<uniform> emitter: Emitter;
+";
+
+const SIMULATE_WGSL: &str = "
+@group(1) @binding(1) var<storage, read_write> particles: array<Particle>;
+
+fn pcg(input: u32) -> u32 {
+    let state = input * 747796405u + 2891336463u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn unit(hash: u32) -> f32 {
+    return f32(hash) / 4294967295.0;
+}
+
+// Slots from `spawn.x` on, wrapping round, are reborn this step; the rest age and fall.
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    let capacity = emitter.spawn.z;
+    if (index >= capacity) {
+        return;
+    }
+    var particle = particles[index];
+    let dt = emitter.direction.w;
+    if ((index + capacity - emitter.spawn.x) % capacity < emitter.spawn.y) {
+        let h1 = pcg(index ^ pcg(emitter.spawn.w));
+        let h2 = pcg(h1);
+        let h3 = pcg(h2);
+        let h4 = pcg(h3);
+        // A uniformly random direction within `spread` of the emitter's.
+        let axis = emitter.direction.xyz;
+        let helper = select(vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(1.0, 0.0, 0.0), abs(axis.y) > 0.99);
+        let tangent = normalize(cross(helper, axis));
+        let bitangent = cross(axis, tangent);
+        let cos_theta = mix(1.0, cos(emitter.origin.w), unit(h1));
+        let sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+        let phi = 6.2831853 * unit(h2);
+        let direction = (tangent * cos(phi) + bitangent * sin(phi)) * sin_theta + axis * cos_theta;
+        particle.position = emitter.origin.xyz;
+        particle.velocity = direction * mix(emitter.ranges.z, emitter.ranges.w, unit(h3));
+        particle.age = 0.0;
+        particle.lifetime = mix(emitter.ranges.x, emitter.ranges.y, unit(h4));
+    } else if (particle.age < particle.lifetime) {
+        particle.velocity = particle.velocity + emitter.gravity.xyz * dt;
+        particle.position = particle.position + particle.velocity * dt;
+        particle.age = particle.age + dt;
+    }
+    particles[index] = particle;
+}
+";
+
+const RENDER_WGSL: &str = "
+struct Camera {
+    view_proj: mat4x4<f32>,
+    position: vec4<f32>,
+};
+@group(0) @binding(0) var<uniform> camera: Camera;
+
+@group(1) @binding(1) var<storage, read> particles: array<Particle>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    @location(1) corner: vec2<f32>,
+};
+
+// One camera-facing quad per particle slot; dead ones collapse to a point outside the view.
+@vertex
+fn vs_main(@builtin(vertex_index) vertex: u32, @builtin(instance_index) instance: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let particle = particles[instance];
+    if (particle.age >= particle.lifetime) {
+        out.clip_position = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+        return out;
+    }
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, -1.0), vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, 1.0), vec2<f32>(-1.0, 1.0)
+    );
+    let corner = corners[vertex];
+    let life = clamp(particle.age / particle.lifetime, 0.0, 1.0);
+    let half_size = mix(emitter.size.x, emitter.size.y, life) * 0.5;
+    let position = particle.position + (emitter.camera_right.xyz * corner.x + emitter.camera_up.xyz * corner.y) * half_size;
+    out.clip_position = camera.view_proj * vec4<f32>(position, 1.0);
+    out.color = mix(emitter.start_color, emitter.end_color, life);
+    out.corner = corner;
+    return out;
+}
+
+// Soft round dots.
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let fade = 1.0 - smoothstep(0.5, 1.0, length(in.corner));
+    return vec4<f32>(in.color.rgb, in.color.a * fade);
+}
+";
+
+fn shader(device: &Device, label: &str, body: &str) -> ShaderModule {
+  device.create_shader_module(ShaderModuleDescriptor {
+    label: Some(label),
+    source: ShaderSource::Wgsl(Cow::Owned(PARTICLE_WGSL.to_owned() + body))
+  })
+}
+
+// Sprays particles from its entity's `Transform`. Particles live in world space, so moving the
+// emitter leaves a trail, and are simulated and drawn on the GPU by the `ParticleSystem`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ParticleEmitter {
+  pub emitting: bool, // particles already out carry on either way
+  pub rate: f32, // particles per second
+  pub max_particles: u32, // alive at once; past this the oldest are reborn first
+  pub lifetime: Vec2, // seconds, picked between min and max per particle
+  pub speed: Vec2, // world units per second, min and max
+  pub direction: Vec3, // in the entity's space
+  pub spread: f32, // radians either side of `direction`; PI sprays every way
+  pub gravity: Vec3, // world units per second squared
+  pub start_color: Color,
+  pub end_color: Color, // blended to over each particle's life
+  pub start_size: f32, // world units across
+  pub end_size: f32,
+  #[serde(skip)]
+  burst: u32, // extra particles to emit next frame
+}
+
+impl ParticleEmitter {
+  pub fn new(rate: f32) -> Self {
+    ParticleEmitter { rate, ..ParticleEmitter::default() }
+  }
+
+  pub fn with_lifetime(mut self, min: f32, max: f32) -> Self {
+    self.lifetime = Vec2::new(min, max);
+    self
+  }
+
+  pub fn with_speed(mut self, min: f32, max: f32) -> Self {
+    self.speed = Vec2::new(min, max);
+    self
+  }
+
+  pub fn with_direction(mut self, direction: Vec3, spread: f32) -> Self {
+    self.direction = direction;
+    self.spread = spread;
+    self
+  }
+
+  pub fn with_gravity(mut self, gravity: Vec3) -> Self {
+    self.gravity = gravity;
+    self
+  }
+
+  pub fn with_colors(mut self, start: Color, end: Color) -> Self {
+    self.start_color = start;
+    self.end_color = end;
+    self
+  }
+
+  pub fn with_sizes(mut self, start: f32, end: f32) -> Self {
+    self.start_size = start;
+    self.end_size = end;
+    self
+  }
+
+  pub fn with_max_particles(mut self, max_particles: u32) -> Self {
+    self.max_particles = max_particles;
+    self
+  }
+
+  // Emits `count` particles at once next frame, on top of the steady rate, e.g. for explosions.
+  pub fn burst(&mut self, count: u32) {
+    self.burst = self.burst.saturating_add(count);
+  }
+}
+
+impl Default for ParticleEmitter {
+  fn default() -> Self {
+    ParticleEmitter {
+      emitting: true,
+      rate: 50.0,
+      max_particles: 1000,
+      lifetime: Vec2::new(1.0, 2.0),
+      speed: Vec2::new(1.0, 2.0),
+      direction: Vec3::Y,
+      spread: 0.3,
+      gravity: Vec3::ZERO,
+      start_color: Color::WHITE,
+      end_color: Color::rgba(1.0, 1.0, 1.0, 0.0),
+      start_size: 0.1,
+      end_size: 0.1,
+      burst: 0,
+    }
+  }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct EmitterUniforms {
+  origin: [f32; 4],
+  direction: [f32; 4],
+  gravity: [f32; 4],
+  ranges: [f32; 4],
+  start_color: [f32; 4],
+  end_color: [f32; 4],
+  size: [f32; 4],
+  camera_right: [f32; 4],
+  camera_up: [f32; 4],
+  spawn: [u32; 4],
+}
+
+// Position and age, velocity and lifetime, as the shaders read them.
+const PARTICLE_SIZE: u64 = 32;
+
+// An emitter's particles on the GPU.
+struct GpuEmitter {
+  capacity: u32,
+  cursor: u32, // the next slot to spawn into
+  pending: f32, // fractional particles carried over to the next frame
+  uniform_buffer: Buffer,
+  simulate_bind_group: BindGroup,
+  render_bind_group: BindGroup,
+}
+
+// Runs every `ParticleEmitter` in the world: a compute pass spawns, moves and ages each emitter's
+// particles in a storage buffer, then they're drawn as instanced camera-facing quads in the model
+// pass. Storage buffers aren't available on WebGL, so there it does nothing.
+pub struct ParticleSystem {
+  pipelines: Option<ParticlePipelines>, // `None` without storage buffers
+  emitters: HashMap<Entity, GpuEmitter>,
+  frame: u32, // seeds each frame's spawns differently
+}
+
+struct ParticlePipelines {
+  simulate: ComputePipeline,
+  render: RenderPipeline,
+  simulate_layout: BindGroupLayout,
+  render_layout: BindGroupLayout,
+}
+
+impl ParticleSystem {
+  pub fn new(device: &Device, format: TextureFormat, camera_layout: &BindGroupLayout) -> Self {
+    // Checked before making anything with a storage buffer in it, which wouldn't even validate.
+    if device.limits().max_storage_buffers_per_shader_stage == 0 {
+      log::warn!("storage buffers aren't supported here; particles won't be simulated or drawn");
+      return ParticleSystem { pipelines: None, emitters: HashMap::new(), frame: 0 };
+    }
+
+    let layout = |label, read_only, visibility| device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some(label),
+      entries: &[
+        BindGroupLayoutEntry {
+          binding: 0,
+          visibility: ShaderStages::COMPUTE | ShaderStages::VERTEX,
+          ty: BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None
+          },
+          count: None
+        },
+        BindGroupLayoutEntry {
+          binding: 1,
+          visibility,
+          ty: BindingType::Buffer {
+            ty: BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None
+          },
+          count: None
+        }
+      ]
+    });
+    let simulate_layout = layout("particle-simulate-layout", false, ShaderStages::COMPUTE);
+    let render_layout = layout("particle-render-layout", true, ShaderStages::VERTEX);
+
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+      label: Some("particle-simulate-pipeline-layout"),
+      bind_group_layouts: &[&device.create_bind_group_layout(&BindGroupLayoutDescriptor { label: Some("particle-empty-layout"), entries: &[] }), &simulate_layout],
+      push_constant_ranges: &[]
+    });
+    let simulate = device.create_compute_pipeline(&ComputePipelineDescriptor {
+      label: Some("particle-simulate-pipeline"),
+      layout: Some(&pipeline_layout),
+      module: &shader(device, "particle-simulate-shader", SIMULATE_WGSL),
+      entry_point: "cs_main"
+    });
+    let render = ParticleSystem::create_render_pipeline(device, format, &[camera_layout, &render_layout]);
+
+    let pipelines = ParticlePipelines { simulate, render, simulate_layout, render_layout };
+    ParticleSystem { pipelines: Some(pipelines), emitters: HashMap::new(), frame: 0 }
+  }
+
+  // Redraws into `format`, keeping the particles already out.
+  pub fn set_format(&mut self, device: &Device, format: TextureFormat, camera_layout: &BindGroupLayout) {
+    if let Some(pipelines) = &mut self.pipelines {
+      pipelines.render = ParticleSystem::create_render_pipeline(device, format, &[camera_layout, &pipelines.render_layout]);
+    }
+  }
+
+  fn create_render_pipeline(device: &Device, format: TextureFormat, layouts: &[&BindGroupLayout]) -> RenderPipeline {
+    let module = &shader(device, "particle-render-shader", RENDER_WGSL);
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+      label: Some("particle-render-pipeline-layout"),
+      bind_group_layouts: layouts,
+      push_constant_ranges: &[]
+    });
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+      label: Some("particle-render-pipeline"),
+      layout: Some(&pipeline_layout),
+      vertex: VertexState {
+        module,
+        entry_point: "vs_main",
+        buffers: &[]
+      },
+      fragment: Some(FragmentState {
+        module,
+        entry_point: "fs_main",
+        targets: &[Some(ColorTargetState {
+          format,
+          blend: Some(BlendState::ALPHA_BLENDING),
+          write_mask: ColorWrites::ALL
+        })]
+      }),
+      primitive: PrimitiveState::default(),
+      // Hidden behind models, but not hiding each other, since they aren't sorted.
+      depth_stencil: Some(DepthStencilState {
+        format: DEPTH_FORMAT,
+        depth_write_enabled: false,
+        depth_compare: CompareFunction::Less,
+        stencil: Default::default(),
+        bias: Default::default()
+      }),
+      multisample: MultisampleState::default(),
+      multiview: None
+    })
+  }
+
+  // Works out this frame's spawns for every emitter and uploads its parameters, `dt` seconds on from
+  // the last frame. Particles face `camera`.
+  pub fn prepare(&mut self, device: &Device, queue: &Queue, world: &World, camera: Option<&Camera>, dt: f32) {
+    let Some(pipelines) = &self.pipelines else { return };
+    self.frame = self.frame.wrapping_add(1);
+    let (right, up) = camera.map_or((Vec3::X, Vec3::Y), |camera| (camera.transform.rotation * Vec3::X, camera.transform.rotation * Vec3::Y));
+    let mut seen = HashSet::new();
+    world.query::<(&Transform, &mut ParticleEmitter)>().for_each(|entity, (transform, mut emitter)| {
+      seen.insert(entity);
+      let capacity = emitter.max_particles.max(1);
+      if self.emitters.get(&entity).is_none_or(|gpu| gpu.capacity != capacity) {
+        self.emitters.insert(entity, ParticleSystem::create_emitter(device, pipelines, capacity));
+      }
+      let burst = match emitter.burst {
+        0 => 0,
+        _ => std::mem::take(&mut emitter.burst),
+      };
+      let gpu = self.emitters.get_mut(&entity).unwrap();
+      if emitter.emitting {
+        gpu.pending += emitter.rate.max(0.0) * dt;
+      }
+      let count = (gpu.pending.floor() as u32).saturating_add(burst).min(capacity);
+      gpu.pending = gpu.pending.fract();
+      let start = gpu.cursor;
+      gpu.cursor = (gpu.cursor + count) % capacity;
+
+      let direction = (transform.rotation * emitter.direction).normalize_or_zero();
+      let uniforms = EmitterUniforms {
+        origin: transform.translation.extend(emitter.spread.clamp(0.0, std::f32::consts::PI)).to_array(),
+        direction: (if direction == Vec3::ZERO { Vec3::Y } else { direction }).extend(dt).to_array(),
+        gravity: emitter.gravity.extend(0.0).to_array(),
+        ranges: [emitter.lifetime.x, emitter.lifetime.y, emitter.speed.x, emitter.speed.y],
+        start_color: emitter.start_color.to_array(),
+        end_color: emitter.end_color.to_array(),
+        size: [emitter.start_size, emitter.end_size, 0.0, 0.0],
+        camera_right: right.extend(0.0).to_array(),
+        camera_up: up.extend(0.0).to_array(),
+        spawn: [start, count, capacity, self.frame],
+      };
+      queue.write_buffer(&gpu.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+    });
+    self.emitters.retain(|entity, _| seen.contains(entity));
+  }
+
+  fn create_emitter(device: &Device, pipelines: &ParticlePipelines, capacity: u32) -> GpuEmitter {
+    let uniform_buffer = device.create_buffer(&BufferDescriptor {
+      label: Some("particle-emitter-uniforms"),
+      size: std::mem::size_of::<EmitterUniforms>() as BufferAddress,
+      usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+      mapped_at_creation: false
+    });
+    // Zeroed, so every slot starts dead: an age of 0 isn't under a lifetime of 0.
+    let particles = device.create_buffer(&BufferDescriptor {
+      label: Some("particles"),
+      size: capacity as u64 * PARTICLE_SIZE,
+      usage: BufferUsages::STORAGE,
+      mapped_at_creation: false
+    });
+    let bind_group = |label, layout| device.create_bind_group(&BindGroupDescriptor {
+      label: Some(label),
+      layout,
+      entries: &[
+        BindGroupEntry { binding: 0, resource: uniform_buffer.as_entire_binding() },
+        BindGroupEntry { binding: 1, resource: particles.as_entire_binding() }
+      ]
+    });
+    GpuEmitter {
+      capacity,
+      cursor: 0,
+      pending: 0.0,
+      simulate_bind_group: bind_group("particle-simulate-bind-group", &pipelines.simulate_layout),
+      render_bind_group: bind_group("particle-render-bind-group", &pipelines.render_layout),
+      uniform_buffer,
+    }
+  }
+
+  // Steps every emitter's particles, before they're drawn.
+  pub fn simulate(&self, encoder: &mut CommandEncoder) {
+    let pipeline = match &self.pipelines {
+      Some(pipelines) if !self.emitters.is_empty() => &pipelines.simulate,
+      _ => return,
+    };
+    let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor { label: Some("particle-simulate-pass") });
+    pass.scope("particles", |pass| {
+      pass.set_pipeline(pipeline);
+      for emitter in self.emitters.values() {
+        pass.set_bind_group(1, &emitter.simulate_bind_group, &[]);
+        pass.dispatch_workgroups(emitter.capacity.div_ceil(WORKGROUP_SIZE), 1, 1);
+      }
+    });
+  }
+
+  // One per emitter.
+  pub fn draw_calls(&self) -> u32 {
+    if self.pipelines.is_some() { self.emitters.len() as u32 } else { 0 }
+  }
+
+  pub fn emitter_count(&self) -> usize {
+    self.emitters.len()
+  }
+
+  // Draws into a pass with the model depth buffer, after everything opaque.
+  pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>, camera: &'a BindGroup) {
+    let pipeline = match &self.pipelines {
+      Some(pipelines) if !self.emitters.is_empty() => &pipelines.render,
+      _ => return,
+    };
+    render_pass.set_pipeline(pipeline);
+    render_pass.set_bind_group(0, camera, &[]);
+    for emitter in self.emitters.values() {
+      render_pass.set_bind_group(1, &emitter.render_bind_group, &[]);
+      render_pass.draw(0..6, 0..emitter.capacity);
+    }
+  }
+}
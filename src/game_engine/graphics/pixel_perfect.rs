@@ -0,0 +1,187 @@
+use std::borrow::Cow;
+use glam::{UVec2, Vec2};
+use wgpu::{AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Color, ColorTargetState, ColorWrites, CommandEncoder, Device, Extent3d, FilterMode, FragmentState, LoadOp, MultisampleState, Operations, PipelineLayoutDescriptor, PrimitiveState, RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, SamplerBindingType, SamplerDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension, VertexState};
+
+use super::viewport::{Scaling, VirtualView};
+
+// Rounds a camera (or sprite) position to whole pixels of the internal resolution, so the
+// low-resolution image doesn't shimmer as things move by fractions of a pixel. The renderer does
+// this for `Camera2D` and sprites under a `ViewportScaling` with sharp pixels.
+pub fn snap_to_pixel(position: Vec2, pixels_per_unit: f32) -> Vec2 {
+  (position * pixels_per_unit).round() / pixels_per_unit
+}
+
+// The largest whole-number scale at which `resolution` fits in `window`, at least 1.
+pub fn integer_scale(resolution: UVec2, window: UVec2) -> u32 {
+  (window.x / resolution.x.max(1)).min(window.y / resolution.y.max(1)).max(1)
+}
+
+// Retro-style rendering: the scene is drawn into a small texture, which is then scaled up by a
+// whole number with nearest filtering and centred in the window, with black bars around it. Other
+// `Scaling`s and linear filtering cover `ViewportScaling`'s other modes.
+pub struct PixelPerfectTarget {
+  pub resolution: UVec2,
+  pub scaling: Scaling,
+  pub smooth: bool,
+  view: TextureView,
+  bind_group: BindGroup,
+  pipeline: RenderPipeline,
+}
+
+impl PixelPerfectTarget {
+  // `format` should match the surface so the scene's pipelines can draw into either.
+  pub fn new(device: &Device, format: TextureFormat, resolution: UVec2) -> Self {
+    PixelPerfectTarget::with_scaling(device, format, resolution, Scaling::Integer, false)
+  }
+
+  pub fn with_scaling(device: &Device, format: TextureFormat, resolution: UVec2, scaling: Scaling, smooth: bool) -> Self {
+    let filter = if smooth { FilterMode::Linear } else { FilterMode::Nearest };
+    let texture = device.create_texture(&TextureDescriptor {
+      label: Some("pixel-perfect-target"),
+      size: Extent3d { width: resolution.x.max(1), height: resolution.y.max(1), depth_or_array_layers: 1 },
+      mip_level_count: 1,
+      sample_count: 1,
+      dimension: TextureDimension::D2,
+      format,
+      usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING
+    });
+    let view = texture.create_view(&TextureViewDescriptor::default());
+    let sampler = device.create_sampler(&SamplerDescriptor {
+      label: Some("pixel-perfect-sampler"),
+      address_mode_u: AddressMode::ClampToEdge,
+      address_mode_v: AddressMode::ClampToEdge,
+      mag_filter: filter,
+      min_filter: filter,
+      ..SamplerDescriptor::default()
+    });
+
+    let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+      label: Some("pixel-perfect-shader"),
+      source: ShaderSource::Wgsl(Cow::Borrowed(
+"
+@group(0) @binding(0) var source: texture_2d<f32>;
+@group(0) @binding(1) var source_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// One triangle covering the viewport.
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(source, source_sampler, in.uv);
+}
+"
+      ))
+    });
+
+    let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("pixel-perfect-bind-group-layout"),
+      entries: &[
+        BindGroupLayoutEntry {
+          binding: 0,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: true },
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false
+          },
+          count: None
+        },
+        BindGroupLayoutEntry {
+          binding: 1,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Sampler(SamplerBindingType::Filtering),
+          count: None
+        }
+      ]
+    });
+
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+      label: Some("pixel-perfect-bind-group"),
+      layout: &bind_group_layout,
+      entries: &[
+        BindGroupEntry { binding: 0, resource: BindingResource::TextureView(&view) },
+        BindGroupEntry { binding: 1, resource: BindingResource::Sampler(&sampler) }
+      ]
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+      label: Some("pixel-perfect-pipeline-layout"),
+      bind_group_layouts: &[&bind_group_layout],
+      push_constant_ranges: &[]
+    });
+
+    let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+      label: Some("pixel-perfect-pipeline"),
+      layout: Some(&pipeline_layout),
+      vertex: VertexState {
+        module: &shader_module,
+        entry_point: "vs_main",
+        buffers: &[]
+      },
+      fragment: Some(FragmentState {
+        module: &shader_module,
+        entry_point: "fs_main",
+        targets: &[Some(ColorTargetState {
+          format,
+          blend: None,
+          write_mask: ColorWrites::ALL
+        })]
+      }),
+      primitive: PrimitiveState::default(),
+      depth_stencil: None,
+      multisample: MultisampleState::default(),
+      multiview: None
+    });
+
+    PixelPerfectTarget { resolution, scaling, smooth, view, bind_group, pipeline }
+  }
+
+  // Where the scene should be rendered instead of the surface.
+  pub fn view(&self) -> &TextureView {
+    &self.view
+  }
+
+  // The scaled image's rectangle in the window: (x, y, width, height) in pixels. A window smaller
+  // than the internal resolution squashes the image rather than cropping it.
+  pub fn viewport(&self, window: UVec2) -> (u32, u32, u32, u32) {
+    let view = VirtualView::new(self.resolution, window, self.scaling);
+    (view.offset.x, view.offset.y, view.size.x, view.size.y)
+  }
+
+  // Converts a window position (e.g. the cursor) to a pixel of the internal resolution, or `None`
+  // over the black bars.
+  pub fn window_to_pixel(&self, position: Vec2, window: UVec2) -> Option<UVec2> {
+    VirtualView::new(self.resolution, window, self.scaling).window_to_virtual(position).map(|pixel| pixel.as_uvec2())
+  }
+
+  // Scales the internal image onto `target` (the surface), clearing the bars to black.
+  pub fn blit(&self, encoder: &mut CommandEncoder, target: &TextureView, window: UVec2) {
+    let (x, y, width, height) = self.viewport(window);
+    let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+      label: Some("pixel-perfect-blit"),
+      color_attachments: &[Some(RenderPassColorAttachment {
+        view: target,
+        ops: Operations { load: LoadOp::Clear(Color::BLACK), store: true },
+        resolve_target: None
+      })],
+      depth_stencil_attachment: None
+    });
+    render_pass.set_viewport(x as f32, y as f32, width as f32, height as f32, 0.0, 1.0);
+    render_pass.set_scissor_rect(x, y, width, height);
+    render_pass.set_pipeline(&self.pipeline);
+    render_pass.set_bind_group(0, &self.bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
+  }
+}
@@ -0,0 +1,590 @@
+use std::borrow::Cow;
+use bytemuck::{Pod, Zeroable};
+use glam::{UVec2, Vec2};
+use serde::{Deserialize, Serialize};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferBindingType, BufferUsages, Color, ColorTargetState, ColorWrites, CommandEncoder, Device, Extent3d, FilterMode, FragmentState, LoadOp, MultisampleState, Operations, PipelineLayout, PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor, ShaderStages, TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension, VertexState};
+
+use super::shaders::build_checked;
+
+// What the scene is drawn in while post-processing is on, so bloom and tonemapping have the light
+// above 1.0 to work with.
+pub const HDR_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+// One fullscreen effect in a `PostProcessStack`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PostEffect {
+  // Light brighter than `threshold` is blurred (by `radius` texels a tap, at half resolution) and
+  // added back `intensity` times.
+  Bloom { threshold: f32, intensity: f32, radius: f32 },
+  // The ACES filmic curve, bringing HDR colour into 0..1 after multiplying by `exposure`.
+  Tonemap { exposure: f32 },
+  // Fast approximate anti-aliasing. Works on 0..1 colour, so it goes after `Tonemap`.
+  Fxaa,
+  // A WGSL fragment shader defining `fs_main(in: VertexOutput) -> @location(0) vec4<f32>`. It's
+  // compiled after the prelude in `CUSTOM_PRELUDE`, which declares the input image (`source`,
+  // `source_sampler`) and `uniforms`, whose `params` are these `params`.
+  Custom { name: String, source: String, params: [f32; 4] },
+}
+
+impl PostEffect {
+  pub fn bloom() -> Self {
+    PostEffect::Bloom { threshold: 1.0, intensity: 0.6, radius: 1.0 }
+  }
+
+  pub fn tonemap() -> Self {
+    PostEffect::Tonemap { exposure: 1.0 }
+  }
+
+  pub fn custom(name: impl Into<String>, source: impl Into<String>) -> Self {
+    PostEffect::Custom { name: name.into(), source: source.into(), params: [0.0; 4] }
+  }
+
+  pub fn name(&self) -> &str {
+    match self {
+      PostEffect::Bloom { .. } => "bloom",
+      PostEffect::Tonemap { .. } => "tonemap",
+      PostEffect::Fxaa => "fxaa",
+      PostEffect::Custom { name, .. } => name,
+    }
+  }
+
+  // What the passes' `params` uniform is set to each frame.
+  fn params(&self) -> [f32; 4] {
+    match self {
+      PostEffect::Bloom { threshold, intensity, radius } => [*threshold, *intensity, *radius, 0.0],
+      PostEffect::Tonemap { exposure } => [*exposure, 0.0, 0.0, 0.0],
+      PostEffect::Fxaa => [0.0; 4],
+      PostEffect::Custom { params, .. } => *params,
+    }
+  }
+
+  // What the GPU side has to rebuild for; parameters alone are just uploaded.
+  fn key(&self) -> EffectKey {
+    match self {
+      PostEffect::Bloom { .. } => EffectKey::Bloom,
+      PostEffect::Tonemap { .. } => EffectKey::Tonemap,
+      PostEffect::Fxaa => EffectKey::Fxaa,
+      PostEffect::Custom { name, source, .. } => EffectKey::Custom(name.clone(), source.clone()),
+    }
+  }
+}
+
+// The fullscreen passes the frame goes through, in order, between the scene and the UI. Kept as a
+// resource; while it has effects (and is enabled) the scene is drawn in `HDR_FORMAT` and the last
+// pass writes the window's image.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PostProcessStack {
+  pub enabled: bool,
+  pub effects: Vec<PostEffect>,
+}
+
+impl Default for PostProcessStack {
+  fn default() -> Self {
+    PostProcessStack { enabled: true, effects: Vec::new() }
+  }
+}
+
+impl PostProcessStack {
+  pub fn new() -> Self {
+    PostProcessStack::default()
+  }
+
+  // Bloom, then tonemapping, then FXAA: the usual chain for an HDR scene.
+  pub fn standard() -> Self {
+    PostProcessStack::new().with(PostEffect::bloom()).with(PostEffect::tonemap()).with(PostEffect::Fxaa)
+  }
+
+  pub fn with(mut self, effect: PostEffect) -> Self {
+    self.effects.push(effect);
+    self
+  }
+
+  pub fn push(&mut self, effect: PostEffect) -> &mut Self {
+    self.effects.push(effect);
+    self
+  }
+
+  pub fn insert(&mut self, index: usize, effect: PostEffect) -> &mut Self {
+    self.effects.insert(index.min(self.effects.len()), effect);
+    self
+  }
+
+  // Removes the first effect called `name`.
+  pub fn remove(&mut self, name: &str) -> Option<PostEffect> {
+    let index = self.effects.iter().position(|effect| effect.name() == name)?;
+    Some(self.effects.remove(index))
+  }
+
+  pub fn get_mut(&mut self, name: &str) -> Option<&mut PostEffect> {
+    self.effects.iter_mut().find(|effect| effect.name() == name)
+  }
+
+  pub fn is_active(&self) -> bool {
+    self.enabled && !self.effects.is_empty()
+  }
+}
+
+// Declarations shared by every pass, built-in or custom, ahead of its fragment shader.
+pub const CUSTOM_PRELUDE: &str = "
+struct Uniforms {
+    resolution: vec2<f32>, // of the image being written, in pixels
+    texel_size: vec2<f32>, // of `source`: one over its size
+    params: vec4<f32>,
+    direction: vec2<f32>, // blur passes only
+    time: f32, // seconds since the engine started
+};
+
+@group(0) @binding(0) var source: texture_2d<f32>;
+@group(0) @binding(1) var source_sampler: sampler;
+@group(0) @binding(2) var<uniform> uniforms: Uniforms;
+@group(0) @binding(3) var extra: texture_2d<f32>; // the bloom composite's blurred light
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+";
+
+const THRESHOLD_SHADER: &str = "
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv).rgb;
+    let brightness = max(color.r, max(color.g, color.b));
+    let contribution = max(brightness - uniforms.params.x, 0.0) / max(brightness, 0.0001);
+    return vec4<f32>(color * contribution, 1.0);
+}
+";
+
+const BLUR_SHADER: &str = "
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    var weights = array<f32, 5>(0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+    let step = uniforms.direction * uniforms.texel_size * uniforms.params.z;
+    var color = textureSample(source, source_sampler, in.uv).rgb * weights[0];
+    for (var i = 1; i < 5; i = i + 1) {
+        let offset = step * f32(i);
+        color = color + textureSample(source, source_sampler, in.uv + offset).rgb * weights[i];
+        color = color + textureSample(source, source_sampler, in.uv - offset).rgb * weights[i];
+    }
+    return vec4<f32>(color, 1.0);
+}
+";
+
+const COMPOSITE_SHADER: &str = "
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv);
+    let bloom = textureSample(extra, source_sampler, in.uv).rgb;
+    return vec4<f32>(color.rgb + bloom * uniforms.params.y, color.a);
+}
+";
+
+// Narkowicz's fit of the ACES filmic curve.
+const TONEMAP_SHADER: &str = "
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv);
+    return vec4<f32>(aces(color.rgb * uniforms.params.x), color.a);
+}
+";
+
+// The low-quality preset of FXAA: blends along the edge direction found from the corners' luma,
+// unless that strays outside the neighbourhood's range.
+const FXAA_SHADER: &str = "
+fn luma(color: vec3<f32>) -> f32 {
+    // Edges are judged perceptually, so take the colour out of linear first.
+    return dot(sqrt(max(color, vec3<f32>(0.0))), vec3<f32>(0.299, 0.587, 0.114));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = uniforms.texel_size;
+    let center = textureSample(source, source_sampler, in.uv);
+    let luma_nw = luma(textureSample(source, source_sampler, in.uv + vec2<f32>(-1.0, -1.0) * texel).rgb);
+    let luma_ne = luma(textureSample(source, source_sampler, in.uv + vec2<f32>(1.0, -1.0) * texel).rgb);
+    let luma_sw = luma(textureSample(source, source_sampler, in.uv + vec2<f32>(-1.0, 1.0) * texel).rgb);
+    let luma_se = luma(textureSample(source, source_sampler, in.uv + vec2<f32>(1.0, 1.0) * texel).rgb);
+    let luma_m = luma(center.rgb);
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    var direction = vec2<f32>(-((luma_nw + luma_ne) - (luma_sw + luma_se)), (luma_nw + luma_sw) - (luma_ne + luma_se));
+    let reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * (1.0 / 8.0), 1.0 / 128.0);
+    let scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+    direction = clamp(direction * scale, vec2<f32>(-8.0), vec2<f32>(8.0)) * texel;
+
+    let a = 0.5 * (textureSample(source, source_sampler, in.uv + direction * (1.0 / 3.0 - 0.5)).rgb
+        + textureSample(source, source_sampler, in.uv + direction * (2.0 / 3.0 - 0.5)).rgb);
+    let b = a * 0.5 + 0.25 * (textureSample(source, source_sampler, in.uv - direction * 0.5).rgb
+        + textureSample(source, source_sampler, in.uv + direction * 0.5).rgb);
+    let luma_b = luma(b);
+    return vec4<f32>(select(b, a, luma_b < luma_min || luma_b > luma_max), center.a);
+}
+";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum EffectKey {
+  Bloom,
+  Tonemap,
+  Fxaa,
+  Custom(String, String),
+}
+
+// The images passes read and write. `Full` and `Half` are ping-pong pairs at the window's size and
+// half of it; `Output` is wherever the chain ends, given to `draw`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Image {
+  Input,
+  Full(usize),
+  Half(usize),
+  Output,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct PassUniforms {
+  resolution: [f32; 2],
+  texel_size: [f32; 2],
+  params: [f32; 4],
+  direction: [f32; 2],
+  time: f32,
+  _padding: f32,
+}
+
+struct Pass {
+  label: String,
+  effect: usize, // index in the stack, for its params
+  pipeline: RenderPipeline,
+  source: Image,
+  extra: Option<Image>,
+  target: Image,
+  direction: Vec2,
+  uniforms: Buffer,
+  bind_group: Option<BindGroup>, // made once the images exist, dropped when they're remade
+}
+
+struct Images {
+  size: UVec2,
+  input: TextureView,
+  full: [TextureView; 2],
+  half: [TextureView; 2],
+}
+
+impl Images {
+  fn view(&self, image: Image) -> Option<&TextureView> {
+    match image {
+      Image::Input => Some(&self.input),
+      Image::Full(index) => Some(&self.full[index]),
+      Image::Half(index) => Some(&self.half[index]),
+      Image::Output => None,
+    }
+  }
+
+  fn size(&self, image: Image) -> UVec2 {
+    match image {
+      Image::Half(_) => half_size(self.size),
+      _ => self.size,
+    }
+  }
+}
+
+// Runs the world's `PostProcessStack`. The scene is drawn into `view()`, then `draw` takes it
+// through the stack's passes into the given target, in the surface's format.
+pub struct PostProcessRenderer {
+  bind_group_layout: BindGroupLayout,
+  pipeline_layout: PipelineLayout,
+  sampler: Sampler,
+  format: TextureFormat, // of the final pass's target
+  keys: Vec<EffectKey>, // what `passes` were built for
+  passes: Vec<Pass>,
+  images: Option<Images>,
+}
+
+impl PostProcessRenderer {
+  pub fn new(device: &Device, format: TextureFormat) -> Self {
+    let texture_entry = |binding| BindGroupLayoutEntry {
+      binding,
+      visibility: ShaderStages::FRAGMENT,
+      ty: BindingType::Texture {
+        sample_type: TextureSampleType::Float { filterable: true },
+        view_dimension: TextureViewDimension::D2,
+        multisampled: false
+      },
+      count: None
+    };
+    let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("post-process-bind-group-layout"),
+      entries: &[
+        texture_entry(0),
+        BindGroupLayoutEntry {
+          binding: 1,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Sampler(SamplerBindingType::Filtering),
+          count: None
+        },
+        BindGroupLayoutEntry {
+          binding: 2,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None
+          },
+          count: None
+        },
+        texture_entry(3)
+      ]
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+      label: Some("post-process-pipeline-layout"),
+      bind_group_layouts: &[&bind_group_layout],
+      push_constant_ranges: &[]
+    });
+
+    // Linear, so the half-resolution passes average what they shrink and the blur can take
+    // fractional steps.
+    let sampler = device.create_sampler(&SamplerDescriptor {
+      label: Some("post-process-sampler"),
+      address_mode_u: AddressMode::ClampToEdge,
+      address_mode_v: AddressMode::ClampToEdge,
+      mag_filter: FilterMode::Linear,
+      min_filter: FilterMode::Linear,
+      ..SamplerDescriptor::default()
+    });
+
+    PostProcessRenderer { bind_group_layout, pipeline_layout, sampler, format, keys: Vec::new(), passes: Vec::new(), images: None }
+  }
+
+  // Rebuilds the passes if the stack's effects changed, remakes the images if the window did, and
+  // uploads every pass's parameters. A custom shader that doesn't compile is reported once and its
+  // effect skipped.
+  pub fn prepare(&mut self, device: &Device, queue: &Queue, stack: &PostProcessStack, window: UVec2, time: f32) {
+    let keys: Vec<EffectKey> = stack.effects.iter().map(PostEffect::key).collect();
+    if keys != self.keys {
+      self.build(device, stack);
+      self.keys = keys;
+    }
+    let window = window.max(UVec2::ONE);
+    if self.images.as_ref().is_none_or(|images| images.size != window) {
+      self.images = Some(create_images(device, window));
+      for pass in &mut self.passes {
+        pass.bind_group = None;
+      }
+    }
+
+    let images = self.images.as_ref().unwrap();
+    for pass in &mut self.passes {
+      if pass.bind_group.is_none() {
+        let source = images.view(pass.source).unwrap();
+        let extra = pass.extra.and_then(|extra| images.view(extra)).unwrap_or(source);
+        pass.bind_group = Some(device.create_bind_group(&BindGroupDescriptor {
+          label: Some("post-process-bind-group"),
+          layout: &self.bind_group_layout,
+          entries: &[
+            BindGroupEntry { binding: 0, resource: BindingResource::TextureView(source) },
+            BindGroupEntry { binding: 1, resource: BindingResource::Sampler(&self.sampler) },
+            BindGroupEntry { binding: 2, resource: pass.uniforms.as_entire_binding() },
+            BindGroupEntry { binding: 3, resource: BindingResource::TextureView(extra) }
+          ]
+        }));
+      }
+      let params = stack.effects.get(pass.effect).map_or([0.0; 4], PostEffect::params);
+      let uniforms = PassUniforms {
+        resolution: images.size(pass.target).as_vec2().to_array(),
+        texel_size: (1.0 / images.size(pass.source).as_vec2()).to_array(),
+        params,
+        direction: pass.direction.to_array(),
+        time,
+        _padding: 0.0,
+      };
+      queue.write_buffer(&pass.uniforms, 0, bytemuck::bytes_of(&uniforms));
+    }
+  }
+
+  // Where the scene should be drawn. Only valid after `prepare`.
+  pub fn view(&self) -> Option<&TextureView> {
+    self.images.as_ref().map(|images| &images.input)
+  }
+
+  pub fn draw(&self, encoder: &mut CommandEncoder, output: &TextureView) {
+    let images = match &self.images {
+      Some(images) => images,
+      None => return,
+    };
+    for pass in &self.passes {
+      let bind_group = match &pass.bind_group {
+        Some(bind_group) => bind_group,
+        None => continue,
+      };
+      let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+        label: Some(&pass.label),
+        color_attachments: &[Some(RenderPassColorAttachment {
+          view: images.view(pass.target).unwrap_or(output),
+          ops: Operations { load: LoadOp::Clear(Color::BLACK), store: true },
+          resolve_target: None
+        })],
+        depth_stencil_attachment: None
+      });
+      render_pass.set_pipeline(&pass.pipeline);
+      render_pass.set_bind_group(0, bind_group, &[]);
+      render_pass.draw(0..3, 0..1);
+    }
+  }
+
+  pub fn draw_calls(&self) -> u32 {
+    self.passes.len() as u32
+  }
+
+  // Lays the stack out as passes: each effect reads what the one before wrote, alternating between
+  // the two full-size images, and the last writes the output. Custom effects that don't compile are
+  // left out and the rest laid out again without them.
+  fn build(&mut self, device: &Device, stack: &PostProcessStack) {
+    let mut skipped = Vec::new();
+    self.passes = loop {
+      match plan(stack, &skipped).into_iter().map(|plan| self.create_pass(device, plan)).collect() {
+        Ok(passes) => break passes,
+        Err((effect, err)) => {
+          log::error!("post-process effect {} skipped: {}", stack.effects[effect].name(), err);
+          skipped.push(effect);
+        }
+      }
+    };
+  }
+
+  fn create_pass(&self, device: &Device, plan: Planned) -> Result<Pass, (usize, String)> {
+    let format = if plan.target == Image::Output { self.format } else { HDR_FORMAT };
+    let source = format!("{}{}", CUSTOM_PRELUDE, plan.shader);
+    let pipeline = build_checked(device, &plan.label, Cow::Owned(source), |module| {
+      device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(&plan.label),
+        layout: Some(&self.pipeline_layout),
+        vertex: VertexState {
+          module,
+          entry_point: "vs_main",
+          buffers: &[]
+        },
+        fragment: Some(FragmentState {
+          module,
+          entry_point: "fs_main",
+          targets: &[Some(ColorTargetState {
+            format,
+            blend: None,
+            write_mask: ColorWrites::ALL
+          })]
+        }),
+        primitive: PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        multiview: None
+      })
+    }).map_err(|err| (plan.effect, err))?;
+    let uniforms = device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("post-process-uniforms"),
+      usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+      contents: bytemuck::bytes_of(&PassUniforms::zeroed())
+    });
+    Ok(Pass {
+      label: plan.label,
+      effect: plan.effect,
+      pipeline,
+      source: plan.source,
+      extra: plan.extra,
+      target: plan.target,
+      direction: plan.direction,
+      uniforms,
+      bind_group: None,
+    })
+  }
+}
+
+// A pass before it has a pipeline.
+struct Planned<'a> {
+  label: String,
+  effect: usize,
+  shader: &'a str, // goes after `CUSTOM_PRELUDE`
+  source: Image,
+  extra: Option<Image>,
+  target: Image,
+  direction: Vec2,
+}
+
+fn plan<'a>(stack: &'a PostProcessStack, skipped: &[usize]) -> Vec<Planned<'a>> {
+  let mut planned = Vec::new();
+  let mut current = Image::Input;
+  let mut next_full = 0;
+  for (effect, settings) in stack.effects.iter().enumerate().filter(|(effect, _)| !skipped.contains(effect)) {
+    let target = Image::Full(next_full);
+    let mut pass = |label: &str, shader, source, extra, target, direction| planned.push(Planned {
+      label: format!("post-process-{}", label),
+      effect,
+      shader,
+      source,
+      extra,
+      target,
+      direction,
+    });
+    match settings {
+      PostEffect::Bloom { .. } => {
+        pass("bloom-threshold", THRESHOLD_SHADER, current, None, Image::Half(0), Vec2::ZERO);
+        pass("bloom-blur-horizontal", BLUR_SHADER, Image::Half(0), None, Image::Half(1), Vec2::X);
+        pass("bloom-blur-vertical", BLUR_SHADER, Image::Half(1), None, Image::Half(0), Vec2::Y);
+        pass("bloom-composite", COMPOSITE_SHADER, current, Some(Image::Half(0)), target, Vec2::ZERO);
+      }
+      PostEffect::Tonemap { .. } => pass("tonemap", TONEMAP_SHADER, current, None, target, Vec2::ZERO),
+      PostEffect::Fxaa => pass("fxaa", FXAA_SHADER, current, None, target, Vec2::ZERO),
+      PostEffect::Custom { name, source, .. } => pass(name, source, current, None, target, Vec2::ZERO),
+    }
+    current = target;
+    next_full = 1 - next_full;
+  }
+  if let Some(last) = planned.last_mut() {
+    last.target = Image::Output;
+  }
+  planned
+}
+
+fn half_size(size: UVec2) -> UVec2 {
+  (size / 2).max(UVec2::ONE)
+}
+
+fn create_images(device: &Device, size: UVec2) -> Images {
+  let create = |label, size: UVec2| {
+    let texture = device.create_texture(&TextureDescriptor {
+      label: Some(label),
+      size: Extent3d { width: size.x, height: size.y, depth_or_array_layers: 1 },
+      mip_level_count: 1,
+      sample_count: 1,
+      dimension: TextureDimension::D2,
+      format: HDR_FORMAT,
+      usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING
+    });
+    texture.create_view(&TextureViewDescriptor::default())
+  };
+  Images {
+    size,
+    input: create("post-process-input", size),
+    full: [create("post-process-full-0", size), create("post-process-full-1", size)],
+    half: [create("post-process-half-0", half_size(size)), create("post-process-half-1", half_size(size))],
+  }
+}
@@ -0,0 +1,183 @@
+use std::borrow::Cow;
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{AddressMode, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, BufferBindingType, BufferUsages, CommandEncoderDescriptor, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor, Device, Extent3d, FilterMode, PipelineLayoutDescriptor, Queue, Sampler, SamplerDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, StorageTextureAccess, Texture, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension};
+
+use super::debug_markers::DebugScope;
+
+const WORKGROUP_SIZE: u32 = 8;
+
+// The Mandelbrot set from the old vulkano compute example, as a `TextureGenerator` shader.
+pub const MANDELBROT_SHADER: &str = "
+struct Params {
+    center: vec2<f32>,
+    scale: f32,
+    iterations: u32,
+};
+
+@group(0) @binding(0) var output: texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(1) var<uniform> params: Params;
+
+@compute @workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = vec2<u32>(textureDimensions(output));
+    if (id.x >= size.x || id.y >= size.y) { return; }
+
+    let uv = (vec2<f32>(id.xy) + vec2<f32>(0.5)) / vec2<f32>(size);
+    let c = (uv - vec2<f32>(0.5)) * params.scale + params.center;
+
+    var z = vec2<f32>(0.0, 0.0);
+    var i = 0u;
+    for (; i < params.iterations; i++) {
+        z = vec2<f32>(z.x * z.x - z.y * z.y + c.x, 2.0 * z.x * z.y + c.y);
+        if (length(z) > 4.0) { break; }
+    }
+
+    let shade = f32(i) / f32(max(params.iterations, 1u));
+    textureStore(output, vec2<i32>(id.xy), vec4<f32>(vec3<f32>(shade), 1.0));
+}
+";
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct MandelbrotParams {
+  pub center: [f32; 2],
+  pub scale: f32,
+  pub iterations: u32,
+}
+
+impl Default for MandelbrotParams {
+  fn default() -> Self {
+    MandelbrotParams { center: [-0.5, 0.0], scale: 3.0, iterations: 200 }
+  }
+}
+
+// An RGBA8 texture filled by a `TextureGenerator`, ready to be sampled as a material input.
+pub struct GeneratedTexture {
+  pub texture: Texture,
+  pub view: TextureView,
+  pub sampler: Sampler,
+  pub width: u32,
+  pub height: u32,
+}
+
+// Runs a user compute shader over every texel of a texture. The shader's entry point is `main`
+// with `@workgroup_size(8, 8)`; it writes to `@group(0) @binding(0)`, a
+// `texture_storage_2d<rgba8unorm, write>`, and can read its parameters from `@binding(1)`, a uniform.
+pub struct TextureGenerator {
+  label: String, // names the generator's passes in GPU captures
+  pipeline: ComputePipeline,
+  bind_group_layout: BindGroupLayout,
+}
+
+impl TextureGenerator {
+  pub fn new(device: &Device, label: &str, wgsl: &str) -> Self {
+    let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+      label: Some(label),
+      source: ShaderSource::Wgsl(Cow::Owned(wgsl.to_string()))
+    });
+
+    let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("procedural-texture-bind-group-layout"),
+      entries: &[
+        BindGroupLayoutEntry {
+          binding: 0,
+          visibility: ShaderStages::COMPUTE,
+          ty: BindingType::StorageTexture {
+            access: StorageTextureAccess::WriteOnly,
+            format: TextureFormat::Rgba8Unorm,
+            view_dimension: TextureViewDimension::D2
+          },
+          count: None
+        },
+        BindGroupLayoutEntry {
+          binding: 1,
+          visibility: ShaderStages::COMPUTE,
+          ty: BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None
+          },
+          count: None
+        }
+      ]
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+      label: Some("procedural-texture-pipeline-layout"),
+      bind_group_layouts: &[&bind_group_layout],
+      push_constant_ranges: &[]
+    });
+
+    let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+      label: Some(label),
+      layout: Some(&pipeline_layout),
+      module: &shader_module,
+      entry_point: "main"
+    });
+
+    TextureGenerator { label: label.to_string(), pipeline, bind_group_layout }
+  }
+
+  pub fn mandelbrot(device: &Device) -> Self {
+    TextureGenerator::new(device, "mandelbrot-shader", MANDELBROT_SHADER)
+  }
+
+  // Creates a `width` x `height` texture and fills it with the shader.
+  pub fn generate<P: Pod>(&self, device: &Device, queue: &Queue, width: u32, height: u32, params: &P) -> GeneratedTexture {
+    let texture = device.create_texture(&TextureDescriptor {
+      label: Some("procedural-texture"),
+      size: Extent3d { width, height, depth_or_array_layers: 1 },
+      mip_level_count: 1,
+      sample_count: 1,
+      dimension: TextureDimension::D2,
+      format: TextureFormat::Rgba8Unorm,
+      usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_SRC
+    });
+    let view = texture.create_view(&TextureViewDescriptor::default());
+    let sampler = device.create_sampler(&SamplerDescriptor {
+      label: Some("procedural-texture-sampler"),
+      address_mode_u: AddressMode::Repeat,
+      address_mode_v: AddressMode::Repeat,
+      mag_filter: FilterMode::Linear,
+      min_filter: FilterMode::Linear,
+      ..SamplerDescriptor::default()
+    });
+
+    let generated = GeneratedTexture { texture, view, sampler, width, height };
+    self.regenerate(device, queue, &generated, params);
+    generated
+  }
+
+  // Runs the shader again into an existing texture, e.g. with animated parameters.
+  pub fn regenerate<P: Pod>(&self, device: &Device, queue: &Queue, target: &GeneratedTexture, params: &P) {
+    // Uniform buffers can't be empty and are sized in 16-byte steps.
+    let mut contents = bytemuck::bytes_of(params).to_vec();
+    contents.resize(contents.len().max(1).next_multiple_of(16), 0);
+    let params_buffer = device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("procedural-texture-params"),
+      usage: BufferUsages::UNIFORM,
+      contents: &contents
+    });
+
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+      label: Some("procedural-texture-bind-group"),
+      layout: &self.bind_group_layout,
+      entries: &[
+        BindGroupEntry { binding: 0, resource: BindingResource::TextureView(&target.view) },
+        BindGroupEntry { binding: 1, resource: params_buffer.as_entire_binding() }
+      ]
+    });
+
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: Some("procedural-texture-encoder") });
+    {
+      let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor { label: Some("procedural-texture-pass") });
+      pass.scope(&self.label, |pass| {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.dispatch_workgroups(target.width.div_ceil(WORKGROUP_SIZE), target.height.div_ceil(WORKGROUP_SIZE), 1);
+      });
+    }
+    queue.submit(std::iter::once(encoder.finish()));
+  }
+}
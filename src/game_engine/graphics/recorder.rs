@@ -0,0 +1,179 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
+use std::thread::JoinHandle;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::imageops::FilterType;
+use image::{Delay, Frame, RgbaImage};
+
+use crate::game_engine::ecs::World;
+use crate::game_engine::time::Time;
+use super::screenshot::{FrameCapture, Screenshots};
+
+enum Job {
+  Frame { image: RgbaImage, time: f32, max_width: u32 },
+  Clear,
+  SaveGif(PathBuf),
+  SaveFrames(PathBuf),
+}
+
+// Keeps the last few seconds of gameplay, as a world resource, to save as an animated GIF or a
+// folder of PNGs: "save the last 10 seconds" clips and bug reports. Frames are taken through
+// `Screenshots` `fps` times a second, then scaled down to `max_width` and kept on a thread of the
+// recorder's own, which does the saving too, so the game only pays for the captures.
+pub struct Recorder {
+  pub fps: f32,
+  pub max_width: u32, // frames wider than this are scaled down, to keep memory and files small
+  recording: bool,
+  next_frame: Option<f32>, // game time the next capture is due at
+  capturing: Vec<(FrameCapture, f32)>,
+  jobs: Sender<Job>,
+  worker: Option<JoinHandle<()>>,
+}
+
+impl Recorder {
+  // Keeps the last `seconds` of frames, recording straight away.
+  pub fn new(seconds: f32, fps: f32) -> Self {
+    let (jobs, queue) = channel();
+    let capacity = (seconds * fps).ceil().max(1.0) as usize;
+    let worker = std::thread::Builder::new().name("recorder".to_string()).spawn(move || {
+      let mut frames: VecDeque<(RgbaImage, f32)> = VecDeque::with_capacity(capacity);
+      for job in queue {
+        match job {
+          Job::Frame { image, time, max_width } => {
+            if frames.len() == capacity {
+              frames.pop_front();
+            }
+            frames.push_back((scale_down(image, max_width), time));
+          }
+          Job::Clear => frames.clear(),
+          Job::SaveGif(path) => report(&path, save_gif(&path, &frames, fps)),
+          Job::SaveFrames(directory) => report(&directory, save_frames(&directory, &frames)),
+        }
+      }
+    }).ok();
+    if worker.is_none() {
+      log::warn!("couldn't start the recorder's thread; nothing will be recorded");
+    }
+    Recorder { fps, max_width: 640, recording: true, next_frame: None, capturing: Vec::new(), jobs, worker }
+  }
+
+  pub fn is_recording(&self) -> bool {
+    self.recording
+  }
+
+  // Stops taking frames, keeping the ones already taken.
+  pub fn pause(&mut self) {
+    self.recording = false;
+  }
+
+  pub fn resume(&mut self) {
+    self.recording = true;
+    self.next_frame = None;
+  }
+
+  // Forgets every frame taken so far.
+  pub fn clear(&mut self) {
+    self.capturing.clear();
+    let _ = self.jobs.send(Job::Clear);
+  }
+
+  // Writes the frames kept so far to an animated GIF at `path`, in the background. Frames still
+  // being read back from the GPU miss out.
+  pub fn save_gif(&self, path: impl Into<PathBuf>) {
+    let _ = self.jobs.send(Job::SaveGif(path.into()));
+  }
+
+  // Writes the frames kept so far into `directory` as numbered PNGs, in the background.
+  pub fn save_frames(&self, directory: impl Into<PathBuf>) {
+    let _ = self.jobs.send(Job::SaveFrames(directory.into()));
+  }
+
+  // Asks for a frame if one's due and passes on the ones that have been read back.
+  pub fn update(&mut self, screenshots: &mut Screenshots, time: f32) {
+    let max_width = self.max_width.max(1);
+    self.capturing.retain(|(capture, taken)| match screenshots.take(*capture) {
+      Some(image) => {
+        let _ = self.jobs.send(Job::Frame { image, time: *taken, max_width });
+        false
+      }
+      None => true,
+    });
+
+    if !self.recording {
+      return;
+    }
+    let interval = 1.0 / self.fps.max(0.1);
+    let due = self.next_frame.is_none_or(|next| time >= next);
+    if due {
+      self.capturing.push((screenshots.capture_frame(), time));
+      // Skips ahead after a stall rather than capturing several frames at once.
+      let next = self.next_frame.unwrap_or(time) + interval;
+      self.next_frame = Some(if next <= time { time + interval } else { next });
+    }
+  }
+}
+
+impl Drop for Recorder {
+  fn drop(&mut self) {
+    // Closing the channel ends the thread once it's done with anything being saved.
+    let (jobs, _) = channel();
+    drop(std::mem::replace(&mut self.jobs, jobs));
+    if let Some(worker) = self.worker.take() {
+      let _ = worker.join();
+    }
+  }
+}
+
+// Runs the world's `Recorder`, if it has one. Called by the engine every frame.
+pub fn update_recorder(world: &World) {
+  if let (Some(mut recorder), Some(mut screenshots), Some(time)) = (world.get_resource_mut::<Recorder>(), world.get_resource_mut::<Screenshots>(), world.get_resource::<Time>()) {
+    recorder.update(&mut screenshots, time.elapsed_seconds());
+  }
+}
+
+fn scale_down(image: RgbaImage, max_width: u32) -> RgbaImage {
+  if image.width() <= max_width {
+    return image;
+  }
+  let height = (image.height() as u64 * max_width as u64 / image.width() as u64).max(1) as u32;
+  image::imageops::resize(&image, max_width, height, FilterType::Triangle)
+}
+
+fn report(path: &Path, result: Result<usize, String>) {
+  match result {
+    Ok(frames) => log::info!("saved {} recorded frames to {}", frames, path.display()),
+    Err(err) => log::warn!("couldn't save the recording to {}: {}", path.display(), err),
+  }
+}
+
+fn save_gif(path: &Path, frames: &VecDeque<(RgbaImage, f32)>, fps: f32) -> Result<usize, String> {
+  if let Some(directory) = path.parent().filter(|directory| !directory.as_os_str().is_empty()) {
+    std::fs::create_dir_all(directory).map_err(|err| err.to_string())?;
+  }
+  let file = File::create(path).map_err(|err| err.to_string())?;
+  // Speed 10 of 30 trades a little palette quality for much faster encoding.
+  let mut encoder = GifEncoder::new_with_speed(BufWriter::new(file), 10);
+  encoder.set_repeat(Repeat::Infinite).map_err(|err| err.to_string())?;
+  // A GIF is one size throughout, so frames from before a window resize are left out.
+  let size = frames.back().map(|(image, _)| image.dimensions());
+  let kept: Vec<&(RgbaImage, f32)> = frames.iter().filter(|(image, _)| Some(image.dimensions()) == size).collect();
+  for (index, (image, time)) in kept.iter().enumerate() {
+    // Each frame shows until the next was taken; the last for one interval.
+    let seconds = kept.get(index + 1).map_or(1.0 / fps.max(0.1), |(_, next)| next - time);
+    let delay = Delay::from_numer_denom_ms((seconds * 1000.0).round().max(10.0) as u32, 1);
+    encoder.encode_frame(Frame::from_parts(image.clone(), 0, 0, delay)).map_err(|err| err.to_string())?;
+  }
+  Ok(kept.len())
+}
+
+fn save_frames(directory: &Path, frames: &VecDeque<(RgbaImage, f32)>) -> Result<usize, String> {
+  std::fs::create_dir_all(directory).map_err(|err| err.to_string())?;
+  for (index, (image, _)) in frames.iter().enumerate() {
+    let path = directory.join(format!("frame-{:05}.png", index));
+    image.save_with_format(&path, image::ImageFormat::Png).map_err(|err| format!("{}: {}", path.display(), err))?;
+  }
+  Ok(frames.len())
+}
@@ -0,0 +1,435 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use glam::UVec2;
+use wgpu::{CommandEncoder, Device, Extent3d, Queue, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureView, TextureViewDescriptor};
+
+use crate::game_engine::ecs::World;
+use super::buffer_pool::{BufferAllocation, BufferPool};
+use super::debug_markers::DebugScope;
+use super::frame_allocator::{FrameAllocator, FrameSlice};
+use super::upload::UploadQueue;
+
+// How many frames a pooled transient texture survives without being used before it's freed.
+const UNUSED_FRAMES: u64 = 60;
+
+// A resource in a `RenderGraph`, as passes refer to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GraphResource(usize);
+
+// A texture the graph allocates for the frame. Its usages come from what passes do with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TransientDesc {
+  pub size: UVec2,
+  pub format: TextureFormat,
+}
+
+enum ResourceKind<'a> {
+  Imported(Option<&'a TextureView>), // `None` for things only ordered around, like buffers
+  Transient(TransientDesc),
+}
+
+struct Resource<'a> {
+  name: String,
+  kind: ResourceKind<'a>,
+  version: usize, // bumped by every pass that writes it
+}
+
+type PassFn<'a> = Box<dyn FnOnce(&mut CommandEncoder, &PassResources<'_>) + 'a>;
+
+struct Pass<'a> {
+  name: String,
+  reads: Vec<(usize, usize)>, // resource and the version read
+  writes: Vec<(usize, usize)>, // resource and the version written
+  keep: bool,
+  run: PassFn<'a>,
+}
+
+// One frame's GPU work as passes that say which resources they read and write. From that the graph
+// works out which passes matter (those that end up in an imported resource, or are kept), the order
+// to run them in, how each transient texture is used, and how long it lives, so transients whose
+// lifetimes don't overlap share a texture. wgpu inserts the barriers between passes itself.
+//
+// Reads see the latest write declared before them, so passes added in order draw in order.
+// Transients start each frame undefined: the first pass writing one should clear it.
+pub struct RenderGraph<'a> {
+  resources: Vec<Resource<'a>>,
+  passes: Vec<Pass<'a>>,
+}
+
+impl<'a> RenderGraph<'a> {
+  pub fn new() -> Self {
+    RenderGraph { resources: Vec::new(), passes: Vec::new() }
+  }
+
+  // A texture that outlives the frame, like the surface or a renderer's own target.
+  pub fn import(&mut self, name: impl Into<String>, view: &'a TextureView) -> GraphResource {
+    self.add_resource(name, ResourceKind::Imported(Some(view)))
+  }
+
+  // Something outside the graph that passes still need ordering around, like a buffer one pass
+  // fills for another. Writing it keeps a pass.
+  pub fn import_external(&mut self, name: impl Into<String>) -> GraphResource {
+    self.add_resource(name, ResourceKind::Imported(None))
+  }
+
+  // A texture for this frame only, allocated if a pass that runs uses it.
+  pub fn create(&mut self, name: impl Into<String>, desc: TransientDesc) -> GraphResource {
+    self.add_resource(name, ResourceKind::Transient(desc))
+  }
+
+  fn add_resource(&mut self, name: impl Into<String>, kind: ResourceKind<'a>) -> GraphResource {
+    self.resources.push(Resource { name: name.into(), kind, version: 0 });
+    GraphResource(self.resources.len() - 1)
+  }
+
+  pub fn name(&self, resource: GraphResource) -> &str {
+    &self.resources[resource.0].name
+  }
+
+  pub fn add_pass(&mut self, name: impl Into<String>) -> PassBuilder<'_, 'a> {
+    PassBuilder { graph: self, name: name.into(), reads: Vec::new(), writes: Vec::new(), keep: false }
+  }
+
+  pub fn pass_count(&self) -> usize {
+    self.passes.len()
+  }
+
+  // The passes that would run, in order.
+  pub fn order(&self) -> Vec<&str> {
+    self.compile().iter().map(|index| self.passes[*index].name.as_str()).collect()
+  }
+
+  fn compile(&self) -> Vec<usize> {
+    let mut writers = HashMap::new();
+    let mut readers: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+    for (index, pass) in self.passes.iter().enumerate() {
+      for write in &pass.writes {
+        writers.insert(*write, index);
+      }
+      for read in &pass.reads {
+        readers.entry(*read).or_default().push(index);
+      }
+    }
+
+    // `needs` carries what a pass's output is built from (the writers of what it reads, and of what
+    // it draws over); `after` adds passes that must read a resource before this one overwrites it.
+    let mut needs = vec![Vec::new(); self.passes.len()];
+    let mut after = vec![Vec::new(); self.passes.len()];
+    for (index, pass) in self.passes.iter().enumerate() {
+      for read in &pass.reads {
+        needs[index].extend(writers.get(read).filter(|writer| **writer != index));
+      }
+      for (resource, version) in &pass.writes {
+        let previous = (*resource, version - 1);
+        needs[index].extend(writers.get(&previous).filter(|writer| **writer != index));
+        after[index].extend(readers.get(&previous).into_iter().flatten().filter(|reader| **reader != index));
+      }
+    }
+
+    let mut live = vec![false; self.passes.len()];
+    let mut stack: Vec<usize> = self.passes.iter().enumerate()
+      .filter(|(_, pass)| pass.keep || pass.writes.iter().any(|(resource, _)| matches!(self.resources[*resource].kind, ResourceKind::Imported(_))))
+      .map(|(index, _)| index)
+      .collect();
+    while let Some(index) = stack.pop() {
+      if !live[index] {
+        live[index] = true;
+        stack.extend(&needs[index]);
+      }
+    }
+
+    // Kahn's algorithm over the live passes, earliest added first when there's a choice.
+    let mut waiting = vec![0; self.passes.len()];
+    let mut dependents = vec![Vec::new(); self.passes.len()];
+    for index in (0..self.passes.len()).filter(|index| live[*index]) {
+      let mut dependencies: Vec<usize> = needs[index].iter().chain(&after[index]).copied().filter(|dependency| live[*dependency]).collect();
+      dependencies.sort_unstable();
+      dependencies.dedup();
+      waiting[index] = dependencies.len();
+      for dependency in dependencies {
+        dependents[dependency].push(index);
+      }
+    }
+    let mut ready: BinaryHeap<Reverse<usize>> = (0..self.passes.len()).filter(|index| live[*index] && waiting[*index] == 0).map(Reverse).collect();
+    let mut order = Vec::new();
+    while let Some(Reverse(index)) = ready.pop() {
+      order.push(index);
+      for dependent in &dependents[index] {
+        waiting[*dependent] -= 1;
+        if waiting[*dependent] == 0 {
+          ready.push(Reverse(*dependent));
+        }
+      }
+    }
+    order
+  }
+
+  // Records the passes that matter into `encoder`, each in a debug group of its name, with
+  // transients from `pool`.
+  pub fn execute(mut self, device: &Device, encoder: &mut CommandEncoder, pool: &mut TransientPool) {
+    let order = self.compile();
+
+    // Each transient's usages and the span of `order` it's used in.
+    let mut transients: HashMap<usize, (TextureUsages, usize, usize)> = HashMap::new();
+    for (position, index) in order.iter().enumerate() {
+      let pass = &self.passes[*index];
+      let accesses = pass.reads.iter().map(|(resource, _)| (*resource, TextureUsages::TEXTURE_BINDING))
+        .chain(pass.writes.iter().map(|(resource, _)| (*resource, TextureUsages::RENDER_ATTACHMENT)));
+      for (resource, usage) in accesses {
+        if let ResourceKind::Transient(_) = self.resources[resource].kind {
+          let (usages, _, last) = transients.entry(resource).or_insert((TextureUsages::empty(), position, position));
+          *usages |= usage;
+          *last = position;
+        }
+      }
+    }
+    let mut requests: Vec<(usize, TransientDesc, TextureUsages, usize, usize)> = transients.into_iter()
+      .filter_map(|(resource, (usage, first, last))| match self.resources[resource].kind {
+        ResourceKind::Transient(desc) => Some((resource, desc, usage, first, last)),
+        ResourceKind::Imported(_) => None,
+      })
+      .collect();
+    requests.sort_by_key(|(resource, _, _, first, _)| (*first, *resource));
+    let allocated = pool.allocate(device, &requests);
+
+    let views = self.resources.iter().enumerate()
+      .map(|(index, resource)| match resource.kind {
+        ResourceKind::Imported(view) => view,
+        ResourceKind::Transient(_) => allocated.get(&index).map(|slot| &pool.textures[*slot].view),
+      })
+      .collect();
+    let resources = PassResources { views };
+
+    let mut passes: Vec<Option<Pass<'a>>> = self.passes.drain(..).map(Some).collect();
+    for index in order {
+      let pass = passes[index].take().unwrap();
+      encoder.scope(&pass.name, |encoder| (pass.run)(encoder, &resources));
+    }
+  }
+}
+
+impl<'a> Default for RenderGraph<'a> {
+  fn default() -> Self {
+    RenderGraph::new()
+  }
+}
+
+// Declares what a pass reads and writes; `run` adds it to the graph.
+pub struct PassBuilder<'g, 'a> {
+  graph: &'g mut RenderGraph<'a>,
+  name: String,
+  reads: Vec<(usize, usize)>,
+  writes: Vec<(usize, usize)>,
+  keep: bool,
+}
+
+impl<'g, 'a> PassBuilder<'g, 'a> {
+  // Sampled, or otherwise used as it was left by an earlier pass.
+  pub fn read(mut self, resource: GraphResource) -> Self {
+    self.reads.push((resource.0, self.graph.resources[resource.0].version));
+    self
+  }
+
+  // Drawn into. Passes added later that use it run after this one.
+  pub fn write(mut self, resource: GraphResource) -> Self {
+    let written = &mut self.graph.resources[resource.0];
+    written.version += 1;
+    self.writes.push((resource.0, written.version));
+    self
+  }
+
+  // Runs even if nothing reads what it writes, e.g. for its side effects.
+  pub fn keep(mut self) -> Self {
+    self.keep = true;
+    self
+  }
+
+  pub fn run(self, run: impl FnOnce(&mut CommandEncoder, &PassResources<'_>) + 'a) {
+    self.graph.passes.push(Pass { name: self.name, reads: self.reads, writes: self.writes, keep: self.keep, run: Box::new(run) });
+  }
+}
+
+// The textures behind a frame's graph resources, for passes to draw with.
+pub struct PassResources<'r> {
+  views: Vec<Option<&'r TextureView>>,
+}
+
+impl<'r> PassResources<'r> {
+  // Panics for an external resource, which has no texture, or a transient the pass didn't declare.
+  pub fn view(&self, resource: GraphResource) -> &'r TextureView {
+    self.views[resource.0].expect("render graph resource has no texture for this pass")
+  }
+}
+
+struct PooledTexture {
+  desc: TransientDesc,
+  usage: TextureUsages,
+  view: TextureView,
+  last_frame: u64,
+}
+
+// Transient textures kept between frames, so a graph only allocates when it needs something new.
+#[derive(Default)]
+pub struct TransientPool {
+  textures: Vec<PooledTexture>,
+  frame: u64,
+}
+
+impl TransientPool {
+  pub fn new() -> Self {
+    TransientPool::default()
+  }
+
+  pub fn len(&self) -> usize {
+    self.textures.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.textures.is_empty()
+  }
+
+  // A pooled texture for each request, given in order of first use. Requests whose spans don't
+  // overlap can share one.
+  fn allocate(&mut self, device: &Device, requests: &[(usize, TransientDesc, TextureUsages, usize, usize)]) -> HashMap<usize, usize> {
+    self.frame += 1;
+    let frame = self.frame;
+    self.textures.retain(|texture| frame - texture.last_frame <= UNUSED_FRAMES);
+
+    let mut busy_until: Vec<Option<usize>> = vec![None; self.textures.len()];
+    let mut allocated = HashMap::new();
+    for (resource, desc, usage, first, last) in requests {
+      let free = (0..self.textures.len()).find(|slot| {
+        let texture = &self.textures[*slot];
+        texture.desc == *desc && texture.usage == *usage && busy_until[*slot].is_none_or(|until| until < *first)
+      });
+      let slot = free.unwrap_or_else(|| {
+        self.textures.push(PooledTexture { desc: *desc, usage: *usage, view: create_transient(device, desc, *usage), last_frame: frame });
+        busy_until.push(None);
+        self.textures.len() - 1
+      });
+      self.textures[slot].last_frame = frame;
+      busy_until[slot] = Some(*last);
+      allocated.insert(*resource, slot);
+    }
+    allocated
+  }
+}
+
+fn create_transient(device: &Device, desc: &TransientDesc, usage: TextureUsages) -> TextureView {
+  device.create_texture(&TextureDescriptor {
+    label: Some("render-graph-transient"),
+    size: Extent3d { width: desc.size.x.max(1), height: desc.size.y.max(1), depth_or_array_layers: 1 },
+    mip_level_count: 1,
+    sample_count: 1,
+    dimension: TextureDimension::D2,
+    format: desc.format,
+    usage
+  }).create_view(&TextureViewDescriptor::default())
+}
+
+// Where in the frame a `CustomPass` goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PassStage {
+  BeforeScene, // after shadows and render targets, before anything's drawn to the scene
+  AfterOpaque, // after models, instances, skinned meshes, the sky and particles, with depth still there
+  AfterScene, // after sprites, tilemaps and lines, before pixel-perfect scaling and post-processing
+  AfterPostProcess, // at window resolution, before the UI
+  AfterUi, // last, before the accessibility filter
+}
+
+// The engine's own resources in the frame's graph, for custom passes to read and write, and the
+// buffers their data was allocated from.
+#[derive(Clone, Copy)]
+pub struct FrameTargets<'a> {
+  pub scene: GraphResource, // what the scene's drawn into, at `scene_size`
+  pub depth: GraphResource, // the models' depth buffer, at `scene_size`; only until `AfterOpaque`
+  pub window: GraphResource, // what the UI's drawn into, at `window_size`
+  pub compute: GraphResource, // the world's `Compute` buffers, written before anything else
+  pub scene_size: UVec2,
+  pub window_size: UVec2,
+  pub scene_format: TextureFormat,
+  pub window_format: TextureFormat,
+  pub frame: &'a FrameAllocator, // for `FrameSlice`s from `alloc_vertex` and `alloc_uniform`
+  pub buffers: &'a BufferPool, // for `BufferAllocation`s from `alloc_buffer`
+}
+
+// The renderer's GPU memory, for getting a custom pass's data to the GPU in `CustomPass::prepare`.
+pub struct PrepareContext<'r> {
+  pub device: &'r Device,
+  pub queue: &'r Queue,
+  pub frame: &'r mut FrameAllocator,
+  pub buffers: &'r mut BufferPool,
+  pub uploads: &'r mut UploadQueue,
+}
+
+impl<'r> PrepareContext<'r> {
+  // `data` in this frame's ring, for vertex, index, instance or storage data.
+  pub fn alloc_vertex(&mut self, data: &[u8]) -> FrameSlice {
+    self.frame.vertices(self.device, self.queue, data)
+  }
+
+  // `data` in this frame's ring, aligned for binding as a uniform buffer.
+  pub fn alloc_uniform(&mut self, data: &[u8]) -> FrameSlice {
+    self.frame.uniform(self.device, self.queue, data)
+  }
+
+  // `data` in the long-lived pool, uploaded ahead of this frame. Keep it until `free_buffer`.
+  pub fn alloc_buffer(&mut self, data: &[u8]) -> BufferAllocation {
+    let allocation = self.buffers.alloc(self.device, data.len() as u64);
+    self.uploads.write_buffer(self.device, self.buffers.buffer(&allocation), allocation.offset, data);
+    allocation
+  }
+
+  pub fn free_buffer(&mut self, allocation: BufferAllocation) {
+    self.buffers.free(allocation);
+  }
+}
+
+// A pass of a game's own in the engine's frame, added through `RenderPasses`.
+pub trait CustomPass: Send {
+  fn name(&self) -> &str;
+  fn stage(&self) -> PassStage;
+
+  // Each frame before it's recorded, with the world, e.g. to upload uniforms.
+  fn prepare(&mut self, _context: &mut PrepareContext<'_>, _world: &World) {}
+
+  // Adds the pass, or several, to the frame's graph.
+  fn add_to<'a>(&'a self, graph: &mut RenderGraph<'a>, targets: &FrameTargets<'a>);
+}
+
+// Adds and removes the renderer's custom passes, as a world resource. The renderer takes the
+// changes each frame.
+#[derive(Default)]
+pub struct RenderPasses {
+  added: Vec<Box<dyn CustomPass>>,
+  removed: Vec<String>,
+}
+
+impl RenderPasses {
+  pub fn new() -> Self {
+    RenderPasses::default()
+  }
+
+  pub fn with(mut self, pass: impl CustomPass + 'static) -> Self {
+    self.add(pass);
+    self
+  }
+
+  pub fn add(&mut self, pass: impl CustomPass + 'static) {
+    self.added.push(Box::new(pass));
+  }
+
+  // Removes every pass called `name`, including one added since the last frame.
+  pub fn remove(&mut self, name: &str) {
+    self.added.retain(|pass| pass.name() != name);
+    self.removed.push(name.to_string());
+  }
+
+  // Applies the changes since the last call to `passes`.
+  pub fn apply(&mut self, passes: &mut Vec<Box<dyn CustomPass>>) {
+    for name in self.removed.drain(..) {
+      passes.retain(|pass| pass.name() != name);
+    }
+    passes.append(&mut self.added);
+  }
+}
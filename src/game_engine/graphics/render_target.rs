@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use glam::UVec2;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, Buffer, BufferUsages, CommandEncoder, Device, Extent3d, LoadOp, Operations, Queue, RenderPassColorAttachment, RenderPassDepthStencilAttachment, RenderPassDescriptor, RenderPipeline, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureView, TextureViewDescriptor};
+
+use super::bind_group_cache::ResourceId;
+use super::camera::{Camera, CameraUniforms};
+use super::color::Color;
+use super::instancing::InstanceRenderer;
+use super::lighting::LightRenderer;
+use super::model::{create_depth, ModelRenderer};
+use super::texture::{GpuTexture, GpuTextures, TextureHandle, TextureManager};
+
+// An offscreen texture the scene is drawn into from a camera of its own, every frame before the
+// window's passes. Its `texture` is an ordinary handle, so sprites and materials can show it in the
+// same frame: minimaps, mirrors, security monitors, portals.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderTarget {
+  pub camera: Camera,
+  pub clear_color: Color,
+  pub active: bool, // inactive targets keep their last image
+  size: UVec2,
+  texture: TextureHandle,
+}
+
+impl RenderTarget {
+  pub fn size(&self) -> UVec2 {
+    self.size
+  }
+
+  pub fn texture(&self) -> TextureHandle {
+    self.texture
+  }
+}
+
+// The world's render targets, as a resource.
+#[derive(Debug, Default)]
+pub struct RenderTargets {
+  targets: Vec<RenderTarget>,
+}
+
+impl RenderTargets {
+  pub fn new() -> Self {
+    RenderTargets::default()
+  }
+
+  // Makes a target of `size` pixels viewed through `camera`, returning the texture to draw it with.
+  pub fn create(&mut self, textures: &mut TextureManager, size: UVec2, camera: Camera) -> TextureHandle {
+    let size = size.max(UVec2::ONE);
+    let texture = textures.reserve(size);
+    self.targets.push(RenderTarget { camera, clear_color: Color::BLACK, active: true, size, texture });
+    texture
+  }
+
+  pub fn get(&self, texture: TextureHandle) -> Option<&RenderTarget> {
+    self.targets.iter().find(|target| target.texture == texture)
+  }
+
+  pub fn get_mut(&mut self, texture: TextureHandle) -> Option<&mut RenderTarget> {
+    self.targets.iter_mut().find(|target| target.texture == texture)
+  }
+
+  // The texture keeps its handle; its contents start over.
+  pub fn resize(&mut self, textures: &mut TextureManager, texture: TextureHandle, size: UVec2) {
+    if let Some(target) = self.get_mut(texture) {
+      target.size = size.max(UVec2::ONE);
+      textures.resize_reserved(texture, target.size);
+    }
+  }
+
+  pub fn remove(&mut self, textures: &mut TextureManager, texture: TextureHandle) -> bool {
+    let before = self.targets.len();
+    self.targets.retain(|target| target.texture != texture);
+    textures.remove(texture);
+    self.targets.len() != before
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &RenderTarget> {
+    self.targets.iter()
+  }
+
+  pub fn len(&self) -> usize {
+    self.targets.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.targets.is_empty()
+  }
+}
+
+struct GpuRenderTarget {
+  size: UVec2,
+  format: TextureFormat,
+  depth: TextureView,
+  uniforms: Buffer,
+  bind_group: BindGroup, // the camera, laid out like the window's
+  clear_color: wgpu::Color,
+}
+
+// Draws the world's `RenderTargets`. The targets share the surface's format so the scene's
+// pipelines can draw into them; their textures live in `GpuTextures` under the targets' handles.
+#[derive(Default)]
+pub struct RenderTargetRenderer {
+  targets: HashMap<TextureHandle, GpuRenderTarget>,
+  active: Vec<TextureHandle>, // drawn this frame
+}
+
+impl RenderTargetRenderer {
+  pub fn new() -> Self {
+    RenderTargetRenderer::default()
+  }
+
+  // Makes or remakes targets to match the resource and uploads their cameras. Run after
+  // `GpuTextures::sync`, which frees removed targets' textures.
+  pub fn prepare(&mut self, device: &Device, queue: &Queue, targets: &RenderTargets, textures: &mut GpuTextures, camera_layout: &BindGroupLayout, format: TextureFormat) {
+    self.targets.retain(|handle, _| targets.get(*handle).is_some());
+    self.active.clear();
+    for target in targets.iter() {
+      let stale = self.targets.get(&target.texture).is_none_or(|gpu| gpu.size != target.size || gpu.format != format);
+      if stale || !textures.contains(target.texture) {
+        textures.insert(target.texture, create_texture(device, target.size, format));
+        self.targets.insert(target.texture, create_target(device, target.size, format, camera_layout));
+      }
+      if !target.active {
+        continue;
+      }
+      let gpu = self.targets.get_mut(&target.texture).unwrap();
+      let view_projection = target.camera.view_projection(target.size.x as f32 / target.size.y as f32);
+      let uniforms = CameraUniforms { view_proj: view_projection.to_cols_array(), position: target.camera.position().extend(1.0).to_array() };
+      queue.write_buffer(&gpu.uniforms, 0, bytemuck::bytes_of(&uniforms));
+      let color = target.clear_color;
+      gpu.clear_color = wgpu::Color { r: color.r as f64, g: color.g as f64, b: color.b as f64, a: color.a as f64 };
+      self.active.push(target.texture);
+    }
+  }
+
+  // One pass per active target, drawing the models and instanced meshes from its camera. The
+  // shadows are fitted to the main camera's view, so only what it also sees is shadowed.
+  pub fn draw(&self, encoder: &mut CommandEncoder, textures: &GpuTextures, models: &ModelRenderer, pipeline: &RenderPipeline, instances: &InstanceRenderer, light: &LightRenderer) {
+    for handle in &self.active {
+      let (target, texture) = match (self.targets.get(handle), textures.get(*handle)) {
+        (Some(target), Some(texture)) => (target, texture),
+        _ => continue,
+      };
+      let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+        label: Some("render-target-pass"),
+        color_attachments: &[Some(RenderPassColorAttachment {
+          view: &texture.view,
+          ops: Operations { load: LoadOp::Clear(target.clear_color), store: true },
+          resolve_target: None
+        })],
+        depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+          view: &target.depth,
+          depth_ops: Some(Operations { load: LoadOp::Clear(1.0), store: false }),
+          stencil_ops: None
+        })
+      });
+      models.draw(&mut render_pass, pipeline, &target.bind_group, &light.bind_group);
+      instances.draw(&mut render_pass, &target.bind_group, &light.bind_group);
+    }
+  }
+
+  pub fn draw_calls(&self, models: &ModelRenderer, instances: &InstanceRenderer) -> u32 {
+    self.active.len() as u32 * (models.draw_calls() + instances.draw_calls())
+  }
+}
+
+fn create_texture(device: &Device, size: UVec2, format: TextureFormat) -> GpuTexture {
+  let texture = device.create_texture(&TextureDescriptor {
+    label: Some("render-target"),
+    size: Extent3d { width: size.x, height: size.y, depth_or_array_layers: 1 },
+    mip_level_count: 1,
+    sample_count: 1,
+    dimension: TextureDimension::D2,
+    format,
+    usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING
+  });
+  let view = texture.create_view(&TextureViewDescriptor::default());
+  GpuTexture { texture, view, size, mip_levels: 1, id: ResourceId::new() }
+}
+
+fn create_target(device: &Device, size: UVec2, format: TextureFormat, camera_layout: &BindGroupLayout) -> GpuRenderTarget {
+  let uniforms = device.create_buffer_init(&BufferInitDescriptor {
+    label: Some("render-target-camera"),
+    contents: bytemuck::bytes_of(&CameraUniforms { view_proj: glam::Mat4::IDENTITY.to_cols_array(), position: [0.0, 0.0, 0.0, 1.0] }),
+    usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
+  });
+  let bind_group = device.create_bind_group(&BindGroupDescriptor {
+    label: Some("render-target-camera-bind-group"),
+    layout: camera_layout,
+    entries: &[BindGroupEntry { binding: 0, resource: uniforms.as_entire_binding() }]
+  });
+  GpuRenderTarget { size, format, depth: create_depth(device, size), uniforms, bind_group, clear_color: wgpu::Color::BLACK }
+}
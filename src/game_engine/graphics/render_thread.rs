@@ -0,0 +1,118 @@
+use std::sync::mpsc::{Receiver, SyncSender};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::mpsc::sync_channel;
+
+use crate::game_engine::ecs::World;
+use super::backend::{FrameError, RenderBackend, RenderStats};
+
+// Backends `Renderer` can hand to a render thread. On the web there are no threads, so any will do.
+cfg_if::cfg_if! {
+  if #[cfg(target_arch = "wasm32")] {
+    pub trait ThreadableBackend: RenderBackend + 'static {}
+    impl<R: RenderBackend + 'static> ThreadableBackend for R {}
+  } else {
+    pub trait ThreadableBackend: RenderBackend + Send + 'static {}
+    impl<R: RenderBackend + Send + 'static> ThreadableBackend for R {}
+  }
+}
+
+// The render thread's ends of the channels. The backend travels with each frame's packet and
+// comes back once the frame's presented.
+struct RenderThread<R: RenderBackend> {
+  packets: SyncSender<(R, R::Packet)>,
+  finished: Receiver<(R, Result<(), FrameError>)>,
+}
+
+// Drives a backend for the engine, either on the calling thread or on a render thread of its own.
+// Threaded, `render` prepares the frame from the world then sends the backend off to acquire,
+// record, submit and present it, returning straight away so the next frame's input and game logic
+// run while the GPU catches up. One frame is in flight at a time: the next `render`, or anything
+// else that needs the backend, waits for it to come back.
+pub struct Renderer<R: RenderBackend> {
+  backend: Option<R>, // `None` while the render thread has it
+  thread: Option<RenderThread<R>>,
+  result: Option<Result<(), FrameError>>, // of the last threaded frame, not yet reported
+  stats: RenderStats, // from the last frame to finish
+}
+
+impl<R: ThreadableBackend> Renderer<R> {
+  // On the web, or if the thread can't be started, `threaded` is ignored and frames draw inline.
+  pub fn new(backend: R, threaded: bool) -> Self {
+    let thread = match threaded && !cfg!(target_arch = "wasm32") {
+      true => Renderer::spawn(),
+      false => None,
+    };
+    Renderer { backend: Some(backend), thread, result: None, stats: RenderStats::default() }
+  }
+
+  #[cfg(not(target_arch = "wasm32"))]
+  fn spawn() -> Option<RenderThread<R>> {
+    let (packets, incoming) = sync_channel::<(R, R::Packet)>(1);
+    let (outgoing, finished) = sync_channel(1);
+    let spawned = std::thread::Builder::new().name("render".to_string()).spawn(move || {
+      // Ends when the `Renderer` is dropped.
+      for (mut backend, packet) in incoming {
+        let result = backend.draw(packet);
+        if outgoing.send((backend, result)).is_err() {
+          break;
+        }
+      }
+    });
+    match spawned {
+      Ok(_) => Some(RenderThread { packets, finished }),
+      Err(err) => {
+        log::warn!("couldn't start the render thread, drawing on the main thread: {}", err);
+        None
+      }
+    }
+  }
+
+  #[cfg(target_arch = "wasm32")]
+  fn spawn() -> Option<RenderThread<R>> {
+    None
+  }
+
+  pub fn is_threaded(&self) -> bool {
+    self.thread.is_some()
+  }
+
+  // Waits for the frame in flight, if there is one.
+  fn finish(&mut self) {
+    if let (None, Some(thread)) = (&self.backend, &self.thread) {
+      let (backend, result) = thread.finished.recv().expect("the render thread panicked");
+      self.stats = backend.stats();
+      self.backend = Some(backend);
+      self.result = Some(result);
+    }
+  }
+
+  // The backend, once it's back from the render thread.
+  pub fn backend_mut(&mut self) -> &mut R {
+    self.finish();
+    self.backend.as_mut().unwrap()
+  }
+
+  // Draws `world`. Threaded, the result is the previous frame's, since this one's still going.
+  pub fn render(&mut self, world: &World) -> Result<(), FrameError> {
+    self.finish();
+    let mut backend = self.backend.take().unwrap();
+    let packet = backend.prepare(world);
+    match &self.thread {
+      Some(thread) => {
+        thread.packets.send((backend, packet)).expect("the render thread panicked");
+        self.result.take().unwrap_or(Ok(()))
+      }
+      None => {
+        let result = backend.draw(packet);
+        self.stats = backend.stats();
+        self.backend = Some(backend);
+        result
+      }
+    }
+  }
+
+  // From the last frame to finish, so a frame behind when threaded.
+  pub fn stats(&self) -> RenderStats {
+    self.stats
+  }
+}
@@ -0,0 +1,377 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use glam::UVec2;
+use image::RgbaImage;
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferAddress, BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites, CommandEncoder, Device, Extent3d, FragmentState, ImageCopyBuffer, ImageCopyTexture, ImageDataLayout, LoadOp, Maintain, MapMode, MultisampleState, Operations, Origin3d, PipelineLayoutDescriptor, PrimitiveState, RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, Texture, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension, VertexState};
+
+use crate::game_engine::input::KeyCode;
+
+// A frame asked for with `Screenshots::capture_frame`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FrameCapture(u32);
+
+// Copies of finished frames, as a world resource. A capture is of the next frame drawn, exactly as
+// it reaches the window, UI and filters included, and is read back from the GPU a frame or two
+// later: `take` it once it's there, or have `save` write it to a PNG.
+//
+// Pressing `key` saves a screenshot into `directory`.
+pub struct Screenshots {
+  pub key: Option<KeyCode>,
+  pub directory: PathBuf,
+  next: u32,
+  requested: Vec<(FrameCapture, Option<PathBuf>)>, // and where to save them, if anywhere
+  finished: HashMap<FrameCapture, RgbaImage>,
+}
+
+impl Screenshots {
+  pub fn new() -> Self {
+    Screenshots { key: Some(KeyCode::F12), directory: PathBuf::from("screenshots"), next: 0, requested: Vec::new(), finished: HashMap::new() }
+  }
+
+  pub fn capture_frame(&mut self) -> FrameCapture {
+    self.request(None)
+  }
+
+  // The image of `capture`, once it's been read back. Only given out once.
+  pub fn take(&mut self, capture: FrameCapture) -> Option<RgbaImage> {
+    self.finished.remove(&capture)
+  }
+
+  // Captures the next frame and writes it to `path` as a PNG, off the main thread.
+  pub fn save(&mut self, path: impl Into<PathBuf>) -> FrameCapture {
+    self.request(Some(path.into()))
+  }
+
+  // `save` into `directory`, named after the time so they sort in order.
+  pub fn save_to_directory(&mut self) -> PathBuf {
+    let path = self.directory.join(format!("screenshot-{}.png", timestamp(self.next + 1)));
+    self.save(path.clone());
+    path
+  }
+
+  fn request(&mut self, path: Option<PathBuf>) -> FrameCapture {
+    self.next += 1;
+    let capture = FrameCapture(self.next);
+    self.requested.push((capture, path));
+    capture
+  }
+
+  pub(crate) fn take_requests(&mut self) -> Vec<(FrameCapture, Option<PathBuf>)> {
+    std::mem::take(&mut self.requested)
+  }
+
+  pub(crate) fn finish(&mut self, capture: FrameCapture, path: Option<PathBuf>, image: RgbaImage) {
+    match path {
+      Some(path) => save_png(path, image),
+      None => {
+        self.finished.insert(capture, image);
+      }
+    }
+  }
+}
+
+impl Default for Screenshots {
+  fn default() -> Self {
+    Screenshots::new()
+  }
+}
+
+cfg_if::cfg_if! {
+  if #[cfg(target_arch = "wasm32")] {
+    // The browser has no files to save to, so this only logs why.
+    fn save_png(path: PathBuf, image: RgbaImage) {
+      write_png(&path, &image);
+    }
+
+    // No clock to name them by here, so they're numbered.
+    fn timestamp(count: u32) -> String {
+      count.to_string()
+    }
+  } else {
+    // Encoding a PNG takes long enough to hitch a frame.
+    fn save_png(path: PathBuf, image: RgbaImage) {
+      std::thread::spawn(move || write_png(&path, &image));
+    }
+
+    fn timestamp(_count: u32) -> String {
+      let since_epoch = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+      format!("{}-{:03}", since_epoch.as_secs(), since_epoch.subsec_millis())
+    }
+  }
+}
+
+fn write_png(path: &Path, image: &RgbaImage) {
+  if let Some(directory) = path.parent().filter(|directory| !directory.as_os_str().is_empty()) {
+    if let Err(err) = std::fs::create_dir_all(directory) {
+      return log::warn!("couldn't save a screenshot to {}: {}", path.display(), err);
+    }
+  }
+  match image.save_with_format(path, image::ImageFormat::Png) {
+    Ok(_) => log::info!("saved a screenshot to {}", path.display()),
+    Err(err) => log::warn!("couldn't save a screenshot to {}: {}", path.display(), err),
+  }
+}
+
+struct Readback {
+  captures: Vec<(FrameCapture, Option<PathBuf>)>,
+  buffer: Buffer,
+  size: UVec2,
+  padded_row: u32,
+  mapped: Arc<Mutex<Option<bool>>>, // whether mapping worked, once it's done
+}
+
+// Carries out the world's `Screenshots`. On a frame with captures the window's passes draw into a
+// texture of this renderer's instead of the surface, which is then drawn onto the surface and copied
+// into a buffer to read back; the surface itself usually can't be copied from.
+pub struct ScreenshotRenderer {
+  format: TextureFormat,
+  layout: BindGroupLayout,
+  pipeline: RenderPipeline,
+  target: Option<(UVec2, Texture, TextureView, BindGroup)>,
+  captures: Vec<(FrameCapture, Option<PathBuf>)>, // for the frame being drawn
+  copying: Option<Readback>, // recorded this frame, to start mapping once it's submitted
+  pending: Vec<Readback>,
+}
+
+impl ScreenshotRenderer {
+  // `format` is the surface's.
+  pub fn new(device: &Device, format: TextureFormat) -> Self {
+    let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+      label: Some("screenshot-shader"),
+      source: ShaderSource::Wgsl(Cow::Borrowed(
+"
+@group(0) @binding(0) var source: texture_2d<f32>;
+
+// One triangle covering the viewport.
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+}
+
+// Same size as the surface, so a straight pixel-for-pixel copy.
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    return textureLoad(source, vec2<i32>(position.xy), 0);
+}
+"
+      ))
+    });
+
+    let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("screenshot-bind-group-layout"),
+      entries: &[
+        BindGroupLayoutEntry {
+          binding: 0,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: false },
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false
+          },
+          count: None
+        }
+      ]
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+      label: Some("screenshot-pipeline-layout"),
+      bind_group_layouts: &[&layout],
+      push_constant_ranges: &[]
+    });
+
+    let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+      label: Some("screenshot-pipeline"),
+      layout: Some(&pipeline_layout),
+      vertex: VertexState {
+        module: &shader_module,
+        entry_point: "vs_main",
+        buffers: &[]
+      },
+      fragment: Some(FragmentState {
+        module: &shader_module,
+        entry_point: "fs_main",
+        targets: &[Some(ColorTargetState {
+          format,
+          blend: None,
+          write_mask: ColorWrites::ALL
+        })]
+      }),
+      primitive: PrimitiveState::default(),
+      depth_stencil: None,
+      multisample: MultisampleState::default(),
+      multiview: None
+    });
+
+    ScreenshotRenderer { format, layout, pipeline, target: None, captures: Vec::new(), copying: None, pending: Vec::new() }
+  }
+
+  // Hands finished captures to `screenshots` and takes its new requests for this frame.
+  pub fn prepare(&mut self, device: &Device, screenshots: &mut Screenshots, window: UVec2) {
+    if !self.pending.is_empty() {
+      device.poll(Maintain::Poll);
+    }
+    let (finished, pending): (Vec<Readback>, Vec<Readback>) = std::mem::take(&mut self.pending).into_iter()
+      .partition(|readback| readback.mapped.lock().unwrap().is_some());
+    self.pending = pending;
+    for readback in finished {
+      if *readback.mapped.lock().unwrap() != Some(true) {
+        log::warn!("couldn't read a screenshot back from the GPU");
+        continue;
+      }
+      let image = to_image(&readback.buffer.slice(..).get_mapped_range(), readback.size, readback.padded_row, self.format);
+      readback.buffer.unmap();
+      match image {
+        Ok(image) => {
+          for (capture, path) in readback.captures {
+            screenshots.finish(capture, path, image.clone());
+          }
+        }
+        Err(err) => log::warn!("{}", err),
+      }
+    }
+
+    // Added to any left from a frame that couldn't be drawn.
+    self.captures.extend(screenshots.take_requests());
+    if self.captures.is_empty() {
+      // Kept while readbacks are in flight, then let go of until the next capture.
+      if self.pending.is_empty() {
+        self.target = None;
+      }
+      return;
+    }
+    if self.target.as_ref().is_some_and(|(size, ..)| *size == window) {
+      return;
+    }
+    let texture = device.create_texture(&TextureDescriptor {
+      label: Some("screenshot-target"),
+      size: Extent3d { width: window.x.max(1), height: window.y.max(1), depth_or_array_layers: 1 },
+      mip_level_count: 1,
+      sample_count: 1,
+      dimension: TextureDimension::D2,
+      format: self.format,
+      usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_SRC
+    });
+    let view = texture.create_view(&TextureViewDescriptor::default());
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+      label: Some("screenshot-bind-group"),
+      layout: &self.layout,
+      entries: &[BindGroupEntry { binding: 0, resource: BindingResource::TextureView(&view) }]
+    });
+    self.target = Some((window, texture, view, bind_group));
+  }
+
+  // Where this frame should be drawn instead of the surface, if it's being captured.
+  pub fn view(&self) -> Option<&TextureView> {
+    match &self.target {
+      Some((_, _, view, _)) if !self.captures.is_empty() => Some(view),
+      _ => None,
+    }
+  }
+
+  // Draws the captured frame onto `surface`.
+  pub fn draw(&self, encoder: &mut CommandEncoder, surface: &TextureView) {
+    let Some((_, _, _, bind_group)) = &self.target else { return };
+    let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+      label: Some("screenshot-blit"),
+      color_attachments: &[Some(RenderPassColorAttachment {
+        view: surface,
+        ops: Operations { load: LoadOp::Load, store: true },
+        resolve_target: None
+      })],
+      depth_stencil_attachment: None
+    });
+    render_pass.set_pipeline(&self.pipeline);
+    render_pass.set_bind_group(0, bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
+  }
+
+  // Copies the captured frame out to read back, once it's been drawn.
+  pub fn copy(&mut self, device: &Device, encoder: &mut CommandEncoder) {
+    if self.captures.is_empty() {
+      return;
+    }
+    let Some((size, texture, _, _)) = &self.target else { return };
+    let bytes_per_pixel = self.format.describe().block_size as u32;
+    let padded_row = (size.x * bytes_per_pixel).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let buffer = device.create_buffer(&BufferDescriptor {
+      label: Some("screenshot-readback"),
+      size: padded_row as BufferAddress * size.y as BufferAddress,
+      usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+      mapped_at_creation: false
+    });
+    encoder.copy_texture_to_buffer(
+      ImageCopyTexture { texture, mip_level: 0, origin: Origin3d::ZERO, aspect: TextureAspect::All },
+      ImageCopyBuffer {
+        buffer: &buffer,
+        layout: ImageDataLayout { offset: 0, bytes_per_row: NonZeroU32::new(padded_row), rows_per_image: None }
+      },
+      Extent3d { width: size.x, height: size.y, depth_or_array_layers: 1 }
+    );
+    let size = *size;
+    let captures = std::mem::take(&mut self.captures);
+    self.copying = Some(Readback { captures, buffer, size, padded_row, mapped: Arc::new(Mutex::new(None)) });
+  }
+
+  // Starts mapping this frame's copy. Call once the frame's been submitted.
+  pub fn after_submit(&mut self) {
+    if let Some(readback) = self.copying.take() {
+      let done = readback.mapped.clone();
+      readback.buffer.slice(..).map_async(MapMode::Read, move |result| *done.lock().unwrap() = Some(result.is_ok()));
+      self.pending.push(readback);
+    }
+  }
+
+  pub fn draw_calls(&self) -> u32 {
+    self.copying.is_some() as u32
+  }
+}
+
+// Unpads the rows of a frame read back in the surface's `format` and converts it to 8-bit RGBA,
+// encoded as the window showed it. Alpha is made opaque, since the window ignores it.
+fn to_image(bytes: &[u8], size: UVec2, padded_row: u32, format: TextureFormat) -> Result<RgbaImage, String> {
+  let convert: fn(&[u8]) -> [u8; 4] = match format {
+    TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => |pixel| [pixel[0], pixel[1], pixel[2], 255],
+    TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => |pixel| [pixel[2], pixel[1], pixel[0], 255],
+    TextureFormat::Rgb10a2Unorm => |pixel| {
+      let packed = u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+      let channel = |shift: u32| (((packed >> shift) & 0x3ff) * 255 / 0x3ff) as u8;
+      [channel(0), channel(10), channel(20), 255]
+    },
+    // Linear and possibly brighter than white, so it's clamped and encoded to sRGB.
+    TextureFormat::Rgba16Float => |pixel| {
+      let channel = |index: usize| linear_to_srgb(f16_to_f32(u16::from_le_bytes([pixel[index * 2], pixel[index * 2 + 1]])));
+      [channel(0), channel(1), channel(2), 255]
+    },
+    _ => return Err(format!("can't save a screenshot from a {:?} surface", format)),
+  };
+  let bytes_per_pixel = format.describe().block_size as usize;
+  let row = size.x as usize * bytes_per_pixel;
+  let mut pixels = Vec::with_capacity(size.x as usize * size.y as usize * 4);
+  for line in bytes.chunks_exact(padded_row as usize).take(size.y as usize) {
+    for pixel in line[..row].chunks_exact(bytes_per_pixel) {
+      pixels.extend_from_slice(&convert(pixel));
+    }
+  }
+  RgbaImage::from_raw(size.x, size.y, pixels).ok_or_else(|| "a screenshot came back the wrong size".to_string())
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+  let value = value.clamp(0.0, 1.0);
+  let encoded = if value <= 0.0031308 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 };
+  (encoded * 255.0).round() as u8
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+  let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+  let exponent = ((bits >> 10) & 0x1f) as i32;
+  let mantissa = (bits & 0x3ff) as f32;
+  match exponent {
+    0 => sign * mantissa * 2f32.powi(-24),
+    31 => if mantissa == 0.0 { sign * f32::INFINITY } else { f32::NAN },
+    _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+  }
+}
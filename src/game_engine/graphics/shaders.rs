@@ -0,0 +1,100 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use wgpu::{Device, ErrorFilter, ShaderModule, ShaderModuleDescriptor, ShaderSource};
+
+struct Shader {
+  embedded: &'static str,
+  modified: Option<SystemTime>,
+}
+
+// WGSL shaders by name, read from `<directory>/<name>.wgsl` when that file exists and from the
+// source built into the engine otherwise. `poll` notices edited files so the renderer can rebuild
+// whatever pipelines use them without a restart. On the web there's no filesystem, so only the
+// built-in sources are used.
+pub struct ShaderManager {
+  pub directory: PathBuf,
+  pub poll_interval: Duration,
+  shaders: HashMap<String, Shader>,
+  last_poll: Option<Instant>,
+}
+
+impl ShaderManager {
+  pub fn new(directory: impl Into<PathBuf>) -> Self {
+    ShaderManager { directory: directory.into(), poll_interval: Duration::from_millis(500), shaders: HashMap::new(), last_poll: None }
+  }
+
+  // `embedded` is the fallback, usually the same file through `include_str!`.
+  pub fn register(&mut self, name: &str, embedded: &'static str) -> &mut Self {
+    let modified = modified(&self.path(name));
+    self.shaders.insert(name.to_string(), Shader { embedded, modified });
+    self
+  }
+
+  pub fn path(&self, name: &str) -> PathBuf {
+    self.directory.join(format!("{}.wgsl", name))
+  }
+
+  pub fn source(&self, name: &str) -> Option<Cow<'static, str>> {
+    let shader = self.shaders.get(name)?;
+    cfg_if::cfg_if! {
+      if #[cfg(target_arch = "wasm32")] {
+        Some(Cow::Borrowed(shader.embedded))
+      } else {
+        match std::fs::read_to_string(self.path(name)) {
+          Ok(source) => Some(Cow::Owned(source)),
+          Err(_) => Some(Cow::Borrowed(shader.embedded)),
+        }
+      }
+    }
+  }
+
+  // The shaders whose files changed (or appeared, or went away) since the last poll. Checks at
+  // most once per `poll_interval`.
+  pub fn poll(&mut self) -> Vec<String> {
+    if cfg!(target_arch = "wasm32") || self.last_poll.is_some_and(|last| last.elapsed() < self.poll_interval) {
+      return Vec::new();
+    }
+    self.last_poll = Some(Instant::now());
+    let mut changed = Vec::new();
+    for (name, shader) in self.shaders.iter_mut() {
+      let modified = modified(&self.directory.join(format!("{}.wgsl", name)));
+      if modified != shader.modified {
+        shader.modified = modified;
+        changed.push(name.clone());
+      }
+    }
+    changed
+  }
+
+  // Compiles `name` and hands it to `build` to make a pipeline from, catching validation errors in
+  // either so a typo in a shader file is reported instead of crashing the engine.
+  pub fn build<T>(&self, device: &Device, name: &str, build: impl FnOnce(&ShaderModule) -> T) -> Result<T, String> {
+    let source = self.source(name).ok_or_else(|| format!("no shader called {}", name))?;
+    build_checked(device, name, source, build)
+  }
+
+  // Like `build` but always from the built-in source, for when the file on disk is broken.
+  pub fn build_embedded<T>(&self, device: &Device, name: &str, build: impl FnOnce(&ShaderModule) -> T) -> Result<T, String> {
+    let shader = self.shaders.get(name).ok_or_else(|| format!("no shader called {}", name))?;
+    build_checked(device, name, Cow::Borrowed(shader.embedded), build)
+  }
+}
+
+pub(crate) fn build_checked<T>(device: &Device, name: &str, source: Cow<'static, str>, build: impl FnOnce(&ShaderModule) -> T) -> Result<T, String> {
+  device.push_error_scope(ErrorFilter::Validation);
+  let module = device.create_shader_module(ShaderModuleDescriptor {
+    label: Some(name),
+    source: ShaderSource::Wgsl(source)
+  });
+  let built = build(&module);
+  match pollster::block_on(device.pop_error_scope()) {
+    Some(err) => Err(format!("shader {}: {}", name, err)),
+    None => Ok(built),
+  }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+  std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
@@ -0,0 +1,418 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::sync::Arc;
+use bytemuck::{Pod, Zeroable};
+use glam::Mat4;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BlendState, Buffer, BufferAddress, BufferBindingType, BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites, CompareFunction, DepthStencilState, Device, FragmentState, MultisampleState, PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPass, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureFormat, VertexAttribute, VertexBufferLayout, VertexFormat, VertexState, VertexStepMode};
+
+use crate::game_engine::ecs::{Transform, World};
+use crate::game_engine::time::Interpolation;
+use super::color::Color;
+use super::culling::CullFilter;
+use super::lighting::LIGHTING_WGSL;
+use super::mesh::{GpuMesh, Mesh, Vertex};
+use super::model::DEPTH_FORMAT;
+use super::upload::UploadQueue;
+
+// One bone of a `Skeleton`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Joint {
+  pub name: String,
+  pub parent: Option<usize>, // another joint of the same skeleton
+  pub rest: Transform, // relative to the parent joint, or the skeleton for roots
+  pub offset: Mat4, // what sits between the parent joint (or the skeleton) and this one's space
+  pub inverse_bind: Mat4, // from the mesh's space into the joint's, as it was bound
+}
+
+// A joint hierarchy that skinned meshes bend with. Poses are a local `Transform` per joint, in the
+// same order as `joints`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Skeleton {
+  pub joints: Vec<Joint>,
+}
+
+impl Skeleton {
+  pub fn new(joints: Vec<Joint>) -> Self {
+    Skeleton { joints }
+  }
+
+  pub fn rest_pose(&self) -> Vec<Transform> {
+    self.joints.iter().map(|joint| joint.rest).collect()
+  }
+
+  // Where each joint ends up in the skeleton's space for `pose`.
+  pub fn joint_transforms(&self, pose: &[Transform]) -> Vec<Mat4> {
+    let mut resolved = vec![None; self.joints.len()];
+    fn resolve(skeleton: &Skeleton, pose: &[Transform], resolved: &mut [Option<Mat4>], index: usize, depth: usize) -> Mat4 {
+      if let Some(matrix) = resolved[index] {
+        return matrix;
+      }
+      let joint = &skeleton.joints[index];
+      let local = joint.offset * pose.get(index).unwrap_or(&joint.rest).matrix();
+      // A parent loop would be a broken skeleton; stop climbing rather than overflow.
+      let matrix = match joint.parent {
+        Some(parent) if parent < skeleton.joints.len() && depth < skeleton.joints.len() => resolve(skeleton, pose, resolved, parent, depth + 1) * local,
+        _ => local,
+      };
+      resolved[index] = Some(matrix);
+      matrix
+    }
+    (0..self.joints.len()).map(|index| resolve(self, pose, &mut resolved, index, 0)).collect()
+  }
+
+  // The matrices vertices are skinned with: from the mesh as bound to where `pose` moves it.
+  pub fn skinning_matrices(&self, pose: &[Transform]) -> Vec<Mat4> {
+    self.joint_transforms(pose).into_iter().zip(&self.joints).map(|(matrix, joint)| matrix * joint.inverse_bind).collect()
+  }
+}
+
+// Which joints move a vertex and how much, parallel to a mesh's vertices. Weights add up to 1.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Pod, Zeroable)]
+pub struct SkinVertex {
+  pub joints: [u16; 4],
+  pub weights: [f32; 4],
+}
+
+impl SkinVertex {
+  const ATTRIBUTES: [VertexAttribute; 2] = [
+    VertexAttribute { format: VertexFormat::Uint16x4, shader_location: 3, offset: 0 },
+    VertexAttribute { format: VertexFormat::Float32x4, shader_location: 4, offset: 8 },
+  ];
+
+  // Shader locations 3 and 4 from vertex buffer slot 1, following `Vertex::layout`'s 0 to 2.
+  pub fn layout() -> VertexBufferLayout<'static> {
+    VertexBufferLayout {
+      array_stride: size_of::<SkinVertex>() as BufferAddress,
+      step_mode: VertexStepMode::Vertex,
+      attributes: &SkinVertex::ATTRIBUTES
+    }
+  }
+}
+
+// A mesh bound to a skeleton, with its per-vertex weights.
+#[derive(Debug, Clone)]
+pub struct SkinnedPart {
+  pub mesh: Arc<Mesh>,
+  pub skin: Arc<Vec<SkinVertex>>,
+  pub color: Color,
+}
+
+// Draws meshes bent by `skeleton` in `pose`, at the entity's `Transform`. An `AnimationPlayer` on
+// the same entity poses it; otherwise set `pose` directly.
+#[derive(Debug, Clone)]
+pub struct SkinnedMesh {
+  pub parts: Vec<SkinnedPart>,
+  pub skeleton: Arc<Skeleton>,
+  pub pose: Vec<Transform>, // each joint's local transform, starting at the rest pose
+}
+
+impl SkinnedMesh {
+  pub fn new(skeleton: Arc<Skeleton>) -> Self {
+    SkinnedMesh { parts: Vec::new(), pose: skeleton.rest_pose(), skeleton }
+  }
+
+  pub fn with_part(mut self, mesh: Arc<Mesh>, skin: Arc<Vec<SkinVertex>>, color: Color) -> Self {
+    self.parts.push(SkinnedPart { mesh, skin, color });
+    self
+  }
+}
+
+// Per draw: the entity's transform and colour, and where its joints start in the joint buffer.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct SkinnedInstanceRaw {
+  model: [[f32; 4]; 4],
+  color: [f32; 4],
+  joint_offset: [u32; 4],
+}
+
+impl SkinnedInstanceRaw {
+  const ATTRIBUTES: [VertexAttribute; 6] = [
+    VertexAttribute { format: VertexFormat::Float32x4, shader_location: 5, offset: 0 },
+    VertexAttribute { format: VertexFormat::Float32x4, shader_location: 6, offset: 16 },
+    VertexAttribute { format: VertexFormat::Float32x4, shader_location: 7, offset: 32 },
+    VertexAttribute { format: VertexFormat::Float32x4, shader_location: 8, offset: 48 },
+    VertexAttribute { format: VertexFormat::Float32x4, shader_location: 9, offset: 64 },
+    VertexAttribute { format: VertexFormat::Uint32, shader_location: 10, offset: 80 },
+  ];
+
+  fn layout() -> VertexBufferLayout<'static> {
+    VertexBufferLayout {
+      array_stride: size_of::<SkinnedInstanceRaw>() as BufferAddress,
+      step_mode: VertexStepMode::Instance,
+      attributes: &SkinnedInstanceRaw::ATTRIBUTES
+    }
+  }
+}
+
+// A skinned part uploaded once and kept while the game still holds its mesh and weights.
+struct CachedPart {
+  mesh: Arc<Mesh>,
+  skin: Arc<Vec<SkinVertex>>,
+  gpu_mesh: GpuMesh,
+  skin_buffer: Buffer,
+}
+
+// Draws every `SkinnedMesh` in the world, skinning on the GPU: the frame's joint matrices all go in
+// one storage buffer, and each part is one draw that finds its entity's through an instance
+// attribute. Storage buffers aren't available on WebGL, so there it draws nothing.
+pub struct SkinnedMeshRenderer {
+  pipeline: Option<RenderPipeline>,
+  joints_layout: Option<BindGroupLayout>, // `None` with the pipeline, without storage buffers
+  parts: HashMap<(usize, usize), CachedPart>, // by the mesh's and skin's `Arc` addresses
+  joints: Vec<[[f32; 4]; 4]>,
+  instances: Vec<SkinnedInstanceRaw>,
+  joint_buffer: Option<(Buffer, BindGroup)>,
+  instance_buffer: Option<Buffer>,
+  draws: Vec<((usize, usize), u32)>, // the part, and its instance
+}
+
+impl SkinnedMeshRenderer {
+  pub fn new(device: &Device, format: TextureFormat, camera_layout: &BindGroupLayout, light_layout: &BindGroupLayout) -> Self {
+    let renderer = SkinnedMeshRenderer {
+      pipeline: None,
+      joints_layout: None,
+      parts: HashMap::new(),
+      joints: Vec::new(),
+      instances: Vec::new(),
+      joint_buffer: None,
+      instance_buffer: None,
+      draws: Vec::new(),
+    };
+    // Checked before making the storage-buffer layout, which wouldn't even validate.
+    if device.limits().max_storage_buffers_per_shader_stage == 0 {
+      log::warn!("storage buffers aren't supported here; skinned meshes won't be drawn");
+      return renderer;
+    }
+
+    let joints_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("skinned-mesh-joints-layout"),
+      entries: &[
+        BindGroupLayoutEntry {
+          binding: 0,
+          visibility: ShaderStages::VERTEX,
+          ty: BindingType::Buffer {
+            ty: BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None
+          },
+          count: None
+        }
+      ]
+    });
+    let pipeline = SkinnedMeshRenderer::create_pipeline(device, format, &[camera_layout, light_layout, &joints_layout]);
+    SkinnedMeshRenderer { pipeline: Some(pipeline), joints_layout: Some(joints_layout), ..renderer }
+  }
+
+  fn create_pipeline(device: &Device, format: TextureFormat, layouts: &[&BindGroupLayout]) -> RenderPipeline {
+    let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+      label: Some("skinned-mesh-shader"),
+      source: ShaderSource::Wgsl(Cow::Owned(LIGHTING_WGSL.to_owned() +
+"
+struct Camera {
+    view_proj: mat4x4<f32>,
+    position: vec4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct Joints {
+    matrices: array<mat4x4<f32>>,
+};
+@group(2) @binding(0)
+var<storage, read> joints: Joints;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(3) joints: vec4<u32>,
+    @location(4) weights: vec4<f32>,
+};
+
+struct InstanceInput {
+    @location(5) model_0: vec4<f32>,
+    @location(6) model_1: vec4<f32>,
+    @location(7) model_2: vec4<f32>,
+    @location(8) model_3: vec4<f32>,
+    @location(9) color: vec4<f32>,
+    @location(10) joint_offset: u32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) normal: vec3<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) world_position: vec3<f32>,
+};
+
+@vertex
+fn vs_main(in: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let base = instance.joint_offset;
+    let skin = joints.matrices[base + in.joints.x] * in.weights.x
+        + joints.matrices[base + in.joints.y] * in.weights.y
+        + joints.matrices[base + in.joints.z] * in.weights.z
+        + joints.matrices[base + in.joints.w] * in.weights.w;
+    let model = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3) * skin;
+    var out: VertexOutput;
+    let world_position = model * vec4<f32>(in.position, 1.0);
+    out.clip_position = camera.view_proj * world_position;
+    out.world_position = world_position.xyz;
+    out.normal = (model * vec4<f32>(in.normal, 0.0)).xyz;
+    out.color = instance.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    var surface: Surface;
+    surface.albedo = in.color.rgb;
+    surface.metallic = 0.0;
+    surface.roughness = 0.7;
+    surface.position = in.world_position;
+    surface.normal = in.normal;
+    return vec4<f32>(shade(surface), in.color.a);
+}
+"
+      ))
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+      label: Some("skinned-mesh-pipeline-layout"),
+      bind_group_layouts: layouts,
+      push_constant_ranges: &[]
+    });
+
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+      label: Some("skinned-mesh-pipeline"),
+      layout: Some(&pipeline_layout),
+      vertex: VertexState {
+        module: &shader_module,
+        entry_point: "vs_main",
+        buffers: &[Vertex::layout(), SkinVertex::layout(), SkinnedInstanceRaw::layout()]
+      },
+      fragment: Some(FragmentState {
+        module: &shader_module,
+        entry_point: "fs_main",
+        targets: &[Some(ColorTargetState {
+          format,
+          blend: Some(BlendState::REPLACE),
+          write_mask: ColorWrites::ALL
+        })]
+      }),
+      primitive: PrimitiveState::default(),
+      depth_stencil: Some(DepthStencilState {
+        format: DEPTH_FORMAT,
+        depth_write_enabled: true,
+        depth_compare: CompareFunction::Less,
+        stencil: Default::default(),
+        bias: Default::default()
+      }),
+      multisample: MultisampleState::default(),
+      multiview: None
+    })
+  }
+
+  // Uploads parts drawn for the first time (through `uploads`) and every entity's joint matrices,
+  // and forgets parts nothing else holds any more.
+  pub fn prepare(&mut self, device: &Device, queue: &Queue, uploads: &mut UploadQueue, world: &World) {
+    self.parts.retain(|_, cached| Arc::strong_count(&cached.mesh) > 1 && Arc::strong_count(&cached.skin) > 1);
+    self.joints.clear();
+    self.instances.clear();
+    self.draws.clear();
+    if self.pipeline.is_none() {
+      return;
+    }
+    let culled = CullFilter::new(world);
+    let interpolation = Interpolation::new(world);
+    world.query::<(&Transform, &SkinnedMesh)>().for_each(|entity, (transform, skinned)| {
+      if skinned.parts.is_empty() || culled.is_culled(world, entity) {
+        return;
+      }
+      let transform = interpolation.transform(world, entity, transform);
+      let joint_offset = self.joints.len() as u32;
+      self.joints.extend(skinned.skeleton.skinning_matrices(&skinned.pose).iter().map(Mat4::to_cols_array_2d));
+      // Every part's joint indices have to land somewhere, even on an empty skeleton.
+      if skinned.skeleton.joints.is_empty() {
+        self.joints.push(Mat4::IDENTITY.to_cols_array_2d());
+      }
+      for part in &skinned.parts {
+        let key = (Arc::as_ptr(&part.mesh) as usize, Arc::as_ptr(&part.skin) as usize);
+        self.parts.entry(key).or_insert_with(|| CachedPart {
+          mesh: part.mesh.clone(),
+          skin: part.skin.clone(),
+          gpu_mesh: GpuMesh::staged(device, uploads, &mut Mesh::clone(&part.mesh)),
+          skin_buffer: device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("skinned-mesh-weights"),
+            usage: BufferUsages::VERTEX,
+            contents: bytemuck::cast_slice(&part.skin)
+          }),
+        });
+        self.draws.push((key, self.instances.len() as u32));
+        self.instances.push(SkinnedInstanceRaw {
+          model: transform.matrix().to_cols_array_2d(),
+          color: part.color.to_array(),
+          joint_offset: [joint_offset, 0, 0, 0],
+        });
+      }
+    });
+
+    let Some(joints_layout) = &self.joints_layout else { return };
+    if self.instances.is_empty() {
+      return;
+    }
+    let size = std::mem::size_of_val(self.instances.as_slice()) as BufferAddress;
+    if self.instance_buffer.as_ref().is_none_or(|buffer| buffer.size() < size) {
+      self.instance_buffer = Some(device.create_buffer(&BufferDescriptor {
+        label: Some("skinned-mesh-instances"),
+        size: size.next_power_of_two(),
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false
+      }));
+    }
+    queue.write_buffer(self.instance_buffer.as_ref().unwrap(), 0, bytemuck::cast_slice(&self.instances));
+
+    let size = std::mem::size_of_val(self.joints.as_slice()) as BufferAddress;
+    if self.joint_buffer.as_ref().is_none_or(|(buffer, _)| buffer.size() < size) {
+      let buffer = device.create_buffer(&BufferDescriptor {
+        label: Some("skinned-mesh-joints"),
+        size: size.next_power_of_two(),
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        mapped_at_creation: false
+      });
+      let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        label: Some("skinned-mesh-joints-bind-group"),
+        layout: joints_layout,
+        entries: &[BindGroupEntry { binding: 0, resource: buffer.as_entire_binding() }]
+      });
+      self.joint_buffer = Some((buffer, bind_group));
+    }
+    queue.write_buffer(&self.joint_buffer.as_ref().unwrap().0, 0, bytemuck::cast_slice(&self.joints));
+  }
+
+  // One per skinned part this frame.
+  pub fn draw_calls(&self) -> u32 {
+    self.draws.len() as u32
+  }
+
+  // Draws into a pass with the model depth buffer, seen through `camera` and lit by `light`.
+  pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>, camera: &'a BindGroup, light: &'a BindGroup) {
+    let (pipeline, (_, joints), instances) = match (&self.pipeline, &self.joint_buffer, &self.instance_buffer) {
+      (Some(pipeline), Some(joints), Some(instances)) if !self.draws.is_empty() => (pipeline, joints, instances),
+      _ => return,
+    };
+    render_pass.set_pipeline(pipeline);
+    render_pass.set_bind_group(0, camera, &[]);
+    render_pass.set_bind_group(1, light, &[]);
+    render_pass.set_bind_group(2, joints, &[]);
+    render_pass.set_vertex_buffer(2, instances.slice(..));
+    for (key, instance) in &self.draws {
+      let part = &self.parts[key];
+      render_pass.set_vertex_buffer(1, part.skin_buffer.slice(..));
+      part.gpu_mesh.draw_instanced(render_pass, *instance..*instance + 1);
+    }
+  }
+}
@@ -0,0 +1,270 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Quat};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferBindingType, BufferUsages, ColorTargetState, ColorWrites, CompareFunction, DepthStencilState, Device, Extent3d, FilterMode, FragmentState, ImageCopyTexture, ImageDataLayout, MultisampleState, Origin3d, PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPass, RenderPipeline, RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, Texture, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureViewDescriptor, TextureViewDimension, VertexState};
+
+use super::camera::Camera;
+use super::cubemap::Cubemap;
+use super::model::DEPTH_FORMAT;
+
+// The scene's surroundings, as a resource: the sky drawn behind everything and, later, the light it
+// casts on what's in front of it. With no skybox the model pass's clear colour shows instead.
+#[derive(Debug, Clone)]
+pub struct Environment {
+  pub skybox: Option<Arc<Cubemap>>,
+  pub intensity: f32, // multiplies the sky's colour; above 1 only shows with post-processing
+  pub rotation: Quat, // of the sky about the world, e.g. to line the sun up with a light
+}
+
+impl Environment {
+  pub fn new() -> Self {
+    Environment { skybox: None, intensity: 1.0, rotation: Quat::IDENTITY }
+  }
+
+  pub fn with_skybox(mut self, skybox: Cubemap) -> Self {
+    self.skybox = Some(Arc::new(skybox));
+    self
+  }
+
+  // Swaps the sky; the renderer uploads the new one next frame. Pass an `Arc` already uploaded to
+  // switch back to it without another upload.
+  pub fn set_skybox(&mut self, skybox: impl Into<Arc<Cubemap>>) {
+    self.skybox = Some(skybox.into());
+  }
+
+  pub fn clear_skybox(&mut self) {
+    self.skybox = None;
+  }
+}
+
+impl Default for Environment {
+  fn default() -> Self {
+    Environment::new()
+  }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct SkyUniforms {
+  clip_to_sky: [[f32; 4]; 4], // from clip space to a direction in the sky's own space
+  intensity: [f32; 4],
+}
+
+// Draws the world's `Environment` skybox as a fullscreen triangle at the far plane, after the
+// opaque models so it only shades what they left uncovered.
+pub struct SkyboxRenderer {
+  pipeline: RenderPipeline,
+  bind_group_layout: BindGroupLayout,
+  sampler: Sampler,
+  uniform_buffer: Buffer,
+  cubemap: Option<(Arc<Cubemap>, Texture, BindGroup)>, // the sky last uploaded
+  visible: bool, // whether there's a sky to draw this frame
+}
+
+impl SkyboxRenderer {
+  pub fn new(device: &Device, format: TextureFormat) -> Self {
+    let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+      label: Some("skybox-shader"),
+      source: ShaderSource::Wgsl(Cow::Borrowed(
+"
+struct Uniforms {
+    clip_to_sky: mat4x4<f32>,
+    intensity: vec4<f32>,
+};
+
+@group(0) @binding(0) var sky: texture_cube<f32>;
+@group(0) @binding(1) var sky_sampler: sampler;
+@group(0) @binding(2) var<uniform> uniforms: Uniforms;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+};
+
+// A fullscreen triangle with z = w, so it lands on the far plane whatever the projection.
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.ndc = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+    out.clip_position = vec4<f32>(out.ndc, 1.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let near = uniforms.clip_to_sky * vec4<f32>(in.ndc, 0.0, 1.0);
+    let far = uniforms.clip_to_sky * vec4<f32>(in.ndc, 1.0, 1.0);
+    let direction = far.xyz / far.w - near.xyz / near.w;
+    let color = textureSample(sky, sky_sampler, direction);
+    return vec4<f32>(color.rgb * uniforms.intensity.x, 1.0);
+}
+"
+      ))
+    });
+
+    let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("skybox-bind-group-layout"),
+      entries: &[
+        BindGroupLayoutEntry {
+          binding: 0,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: true },
+            view_dimension: TextureViewDimension::Cube,
+            multisampled: false
+          },
+          count: None
+        },
+        BindGroupLayoutEntry {
+          binding: 1,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Sampler(SamplerBindingType::Filtering),
+          count: None
+        },
+        BindGroupLayoutEntry {
+          binding: 2,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None
+          },
+          count: None
+        }
+      ]
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+      label: Some("skybox-pipeline-layout"),
+      bind_group_layouts: &[&bind_group_layout],
+      push_constant_ranges: &[]
+    });
+
+    let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+      label: Some("skybox-pipeline"),
+      layout: Some(&pipeline_layout),
+      vertex: VertexState {
+        module: &shader_module,
+        entry_point: "vs_main",
+        buffers: &[]
+      },
+      fragment: Some(FragmentState {
+        module: &shader_module,
+        entry_point: "fs_main",
+        targets: &[Some(ColorTargetState {
+          format,
+          blend: None,
+          write_mask: ColorWrites::ALL
+        })]
+      }),
+      primitive: PrimitiveState::default(),
+      // Depth is cleared to 1, so the sky passes only where no model was drawn.
+      depth_stencil: Some(DepthStencilState {
+        format: DEPTH_FORMAT,
+        depth_write_enabled: false,
+        depth_compare: CompareFunction::LessEqual,
+        stencil: Default::default(),
+        bias: Default::default()
+      }),
+      multisample: MultisampleState::default(),
+      multiview: None
+    });
+
+    let sampler = device.create_sampler(&SamplerDescriptor {
+      label: Some("skybox-sampler"),
+      address_mode_u: AddressMode::ClampToEdge,
+      address_mode_v: AddressMode::ClampToEdge,
+      address_mode_w: AddressMode::ClampToEdge,
+      mag_filter: FilterMode::Linear,
+      min_filter: FilterMode::Linear,
+      ..SamplerDescriptor::default()
+    });
+
+    let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("skybox-uniforms"),
+      usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+      contents: bytemuck::bytes_of(&SkyUniforms { clip_to_sky: Mat4::IDENTITY.to_cols_array_2d(), intensity: [1.0; 4] })
+    });
+
+    SkyboxRenderer { pipeline, bind_group_layout, sampler, uniform_buffer, cubemap: None, visible: false }
+  }
+
+  // Uploads the environment's sky if it changed, and where to look in it from `camera` with a target
+  // of `aspect`.
+  pub fn prepare(&mut self, device: &Device, queue: &Queue, environment: &Environment, camera: &Camera, aspect: f32) {
+    let skybox = match &environment.skybox {
+      Some(skybox) => skybox,
+      None => {
+        self.visible = false;
+        return;
+      }
+    };
+    if !self.cubemap.as_ref().is_some_and(|(uploaded, _, _)| Arc::ptr_eq(uploaded, skybox)) {
+      let (texture, bind_group) = self.upload(device, queue, skybox);
+      self.cubemap = Some((skybox.clone(), texture, bind_group));
+    }
+
+    // Only the camera's rotation matters for something infinitely far away.
+    let view = Mat4::from_quat(camera.transform.rotation.inverse());
+    let clip_to_sky = Mat4::from_quat(environment.rotation.inverse()) * (camera.projection(aspect) * view).inverse();
+    let uniforms = SkyUniforms { clip_to_sky: clip_to_sky.to_cols_array_2d(), intensity: [environment.intensity.max(0.0); 4] };
+    queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+    self.visible = true;
+  }
+
+  fn upload(&self, device: &Device, queue: &Queue, cubemap: &Cubemap) -> (Texture, BindGroup) {
+    let size = Extent3d { width: cubemap.size, height: cubemap.size, depth_or_array_layers: 6 };
+    let texture = device.create_texture(&TextureDescriptor {
+      label: Some("skybox-cubemap"),
+      size,
+      mip_level_count: 1,
+      sample_count: 1,
+      dimension: TextureDimension::D2,
+      format: TextureFormat::Rgba16Float,
+      usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST
+    });
+    queue.write_texture(
+      ImageCopyTexture { texture: &texture, mip_level: 0, origin: Origin3d::ZERO, aspect: TextureAspect::All },
+      bytemuck::cast_slice(&cubemap.to_half_floats()),
+      ImageDataLayout {
+        offset: 0,
+        bytes_per_row: std::num::NonZeroU32::new(cubemap.size * 8),
+        rows_per_image: std::num::NonZeroU32::new(cubemap.size)
+      },
+      size
+    );
+    let view = texture.create_view(&TextureViewDescriptor {
+      label: Some("skybox-cubemap-view"),
+      dimension: Some(TextureViewDimension::Cube),
+      ..TextureViewDescriptor::default()
+    });
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+      label: Some("skybox-bind-group"),
+      layout: &self.bind_group_layout,
+      entries: &[
+        BindGroupEntry { binding: 0, resource: BindingResource::TextureView(&view) },
+        BindGroupEntry { binding: 1, resource: BindingResource::Sampler(&self.sampler) },
+        BindGroupEntry { binding: 2, resource: self.uniform_buffer.as_entire_binding() }
+      ]
+    });
+    (texture, bind_group)
+  }
+
+  pub fn draw_calls(&self) -> u32 {
+    self.visible as u32
+  }
+
+  // Draws into a pass with the model depth buffer, after whatever should cover the sky.
+  pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+    let bind_group = match &self.cubemap {
+      Some((_, _, bind_group)) if self.visible => bind_group,
+      _ => return,
+    };
+    render_pass.set_pipeline(&self.pipeline);
+    render_pass.set_bind_group(0, bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
+  }
+}
@@ -0,0 +1,185 @@
+use glam::{UVec2, Vec2};
+
+use crate::game_engine::assets::{AnimationDirection, AsepriteFile, SpriteAnimation, TextureAtlas};
+use crate::game_engine::ecs::{Entity, World};
+use super::sprite_batch::Sprite;
+
+// An `AnimatedSprite` reaching the end of an animation that doesn't loop forever.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationFinished {
+  pub entity: Entity,
+  pub animation: String,
+}
+
+// Flips an entity's `Sprite` through frames of a sheet. `frames` are uv rects in the sprite's
+// texture and each animation's `frames` index them, so one sheet can hold every animation.
+#[derive(Debug, Clone)]
+pub struct AnimatedSprite {
+  pub frames: Vec<(Vec2, Vec2)>, // uv min/max, like `Sprite::region`
+  pub animations: Vec<SpriteAnimation>,
+  pub speed: f32, // 2 plays twice as fast
+  pub paused: bool,
+  current: Option<usize>,
+  order: Vec<usize>, // positions in the current animation's `frames`, in play order for one cycle
+  step: usize, // into `order`
+  elapsed: f32, // seconds into the current frame
+  cycles: u32, // finished passes through `order`
+  finished: bool,
+}
+
+impl AnimatedSprite {
+  pub fn new(frames: Vec<(Vec2, Vec2)>) -> Self {
+    AnimatedSprite {
+      frames,
+      animations: Vec::new(),
+      speed: 1.0,
+      paused: false,
+      current: None,
+      order: Vec::new(),
+      step: 0,
+      elapsed: 0.0,
+      cycles: 0,
+      finished: false,
+    }
+  }
+
+  // Every region of `atlas` as a frame, in the atlas's order, with its named animations at `fps`.
+  pub fn from_atlas(atlas: &TextureAtlas, texture_size: UVec2, fps: f32) -> Self {
+    let mut sprite = AnimatedSprite::new(atlas.regions().iter().map(|region| region.uv_rect(texture_size)).collect());
+    for (name, frames) in atlas.animations() {
+      sprite.add_animation(SpriteAnimation::new(name, frames.to_vec(), fps));
+    }
+    sprite
+  }
+
+  // The frames of `file`'s `sprite_sheet` strip, with an animation per tag.
+  pub fn from_aseprite(file: &AsepriteFile) -> Self {
+    let count = file.frames.len().max(1) as f32;
+    let frames = (0..file.frames.len()).map(|frame| (Vec2::new(frame as f32 / count, 0.0), Vec2::new((frame + 1) as f32 / count, 1.0))).collect();
+    let mut sprite = AnimatedSprite::new(frames);
+    file.animations().into_iter().for_each(|animation| sprite.add_animation(animation));
+    sprite
+  }
+
+  pub fn with_animation(mut self, animation: SpriteAnimation) -> Self {
+    self.add_animation(animation);
+    self
+  }
+
+  // Adds an animation, replacing any with the same name.
+  pub fn add_animation(&mut self, animation: SpriteAnimation) {
+    match self.animations.iter_mut().find(|other| other.name == animation.name) {
+      Some(existing) => *existing = animation,
+      None => self.animations.push(animation),
+    }
+  }
+
+  // Plays `name` from its first frame, unless it's already playing, so it's safe to call every
+  // frame. False if there's no such animation.
+  pub fn play(&mut self, name: &str) -> bool {
+    match self.animations.iter().position(|animation| animation.name == name) {
+      Some(index) if self.current == Some(index) && !self.finished => true,
+      Some(index) => {
+        self.start(index);
+        true
+      }
+      None => false,
+    }
+  }
+
+  // Plays the current animation again from its first frame.
+  pub fn restart(&mut self) {
+    if let Some(index) = self.current {
+      self.start(index);
+    }
+  }
+
+  fn start(&mut self, index: usize) {
+    let animation = &self.animations[index];
+    let count = animation.frames.len();
+    // Ping-pong doesn't show the end frames twice in a row.
+    let inner = 1..count.saturating_sub(1);
+    self.order = match animation.direction {
+      AnimationDirection::Forward => (0..count).collect(),
+      AnimationDirection::Reverse => (0..count).rev().collect(),
+      AnimationDirection::PingPong => (0..count).chain(inner.rev()).collect(),
+      AnimationDirection::PingPongReverse => (0..count).rev().chain(inner).collect(),
+    };
+    self.current = Some(index);
+    self.step = 0;
+    self.elapsed = 0.0;
+    self.cycles = 0;
+    self.finished = self.order.is_empty();
+  }
+
+  pub fn stop(&mut self) {
+    self.current = None;
+    self.order.clear();
+  }
+
+  pub fn current_animation(&self) -> Option<&str> {
+    self.current.map(|index| self.animations[index].name.as_str())
+  }
+
+  // Whether the current animation has played all its repeats and is holding its last frame.
+  pub fn is_finished(&self) -> bool {
+    self.finished
+  }
+
+  // The index into `frames` showing now.
+  pub fn frame(&self) -> Option<usize> {
+    let animation = &self.animations[self.current?];
+    animation.frames.get(*self.order.get(self.step)?).copied()
+  }
+
+  pub fn region(&self) -> Option<(Vec2, Vec2)> {
+    self.frames.get(self.frame()?).copied()
+  }
+
+  // Moves on by `dt` seconds, skipping frames if it's fallen behind. Returns whether the animation
+  // finished during this update.
+  pub fn update(&mut self, dt: f32) -> bool {
+    let animation = match self.current {
+      Some(index) if !self.paused && !self.finished => &self.animations[index],
+      _ => return false,
+    };
+    self.elapsed += dt * self.speed.max(0.0);
+    loop {
+      let duration = animation.durations.get(self.order[self.step]).copied().unwrap_or(0.0);
+      // A frame with no duration would spin forever; hold it instead.
+      if duration <= 0.0 || self.elapsed < duration {
+        return false;
+      }
+      if self.step + 1 < self.order.len() {
+        self.elapsed -= duration;
+        self.step += 1;
+        continue;
+      }
+      self.cycles += 1;
+      if animation.repeat != 0 && self.cycles >= animation.repeat as u32 {
+        self.elapsed = 0.0;
+        self.finished = true;
+        return true;
+      }
+      self.elapsed -= duration;
+      self.step = 0;
+    }
+  }
+}
+
+// Advances every `AnimatedSprite` by `dt` seconds and points its entity's `Sprite` at the frame
+// showing. Returns the animations that finished, for the engine to send out as events.
+pub fn update_animated_sprites(world: &World, dt: f32) -> Vec<AnimationFinished> {
+  let mut finished = Vec::new();
+  world.query::<(&mut AnimatedSprite, &mut Sprite)>().for_each(|entity, (mut animated, mut sprite)| {
+    if animated.update(dt) {
+      let animation = animated.current_animation().unwrap_or_default().to_string();
+      finished.push(AnimationFinished { entity, animation });
+    }
+    let region = animated.region();
+    if region.is_some() && sprite.region != region {
+      sprite.region = region;
+    }
+  });
+  finished
+}
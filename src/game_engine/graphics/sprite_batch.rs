@@ -0,0 +1,447 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::mem::size_of;
+use bytemuck::{Pod, Zeroable};
+use glam::{EulerRot, Mat4, UVec2, Vec2};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, BlendState, Buffer, BufferAddress, BufferBindingType, BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites, Device, FilterMode, FragmentState, MultisampleState, PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPass, RenderPipeline, RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureFormat, TextureSampleType, TextureViewDimension, VertexAttribute, VertexBufferLayout, VertexFormat, VertexState, VertexStepMode};
+
+use crate::game_engine::ecs::{Transform, World};
+use crate::game_engine::time::Interpolation;
+use super::bind_group_cache::ResourceId;
+use super::color::Color;
+use super::pixel_perfect::snap_to_pixel;
+use super::texture::{GpuTexture, GpuTextures, TextureHandle};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sprite {
+  pub texture: TextureHandle,
+  pub position: Vec2, // where the sprite's centre goes
+  pub rotation: f32, // radians, clockwise on screen
+  pub scale: Vec2, // of the texture's (or region's) size in pixels
+  pub tint: Color,
+  pub region: Option<(Vec2, Vec2)>, // uv min/max of the part of the texture to draw, for atlases
+  pub layer: i32, // higher layers draw on top; within a layer, sprites are grouped by texture
+}
+
+impl Sprite {
+  pub fn new(texture: TextureHandle, position: Vec2) -> Self {
+    Sprite { texture, position, rotation: 0.0, scale: Vec2::ONE, tint: Color::WHITE, region: None, layer: 0 }
+  }
+}
+
+// Immediate-mode 2D sprites, as a resource. Queue sprites during the frame and the renderer draws
+// them in the scene pass with one draw call per run of sprites sharing a texture, then clears the
+// list. Positions are in render-target pixels (origin top-left) unless a `view_projection` is set.
+pub struct SpriteBatch {
+  pub enabled: bool,
+  pub view_projection: Option<Mat4>,
+  sprites: Vec<Sprite>,
+}
+
+impl SpriteBatch {
+  pub fn new() -> Self {
+    SpriteBatch { enabled: true, view_projection: None, sprites: Vec::new() }
+  }
+
+  pub fn draw_sprite(&mut self, texture: TextureHandle, position: Vec2, rotation: f32, scale: Vec2, tint: Color) {
+    self.draw(Sprite { rotation, scale, tint, ..Sprite::new(texture, position) });
+  }
+
+  pub fn draw(&mut self, sprite: Sprite) {
+    if self.enabled {
+      self.sprites.push(sprite);
+    }
+  }
+
+  pub fn sprites(&self) -> &[Sprite] {
+    &self.sprites
+  }
+
+  pub fn clear(&mut self) {
+    self.sprites.clear();
+  }
+}
+
+// Queues every entity with a `Transform` and a `Sprite` into the world's `SpriteBatch`. The
+// sprite's own position, rotation and scale are taken relative to the transform (x/y and the
+// rotation about z). The engine calls it every frame, after the updates and before drawing.
+pub fn draw_sprite_entities(world: &World) {
+  let mut batch = match world.get_resource_mut::<SpriteBatch>() {
+    Some(batch) => batch,
+    None => return,
+  };
+  let interpolation = Interpolation::new(world);
+  world.query::<(&Transform, &Sprite)>().for_each(|entity, (transform, sprite)| {
+    let transform = interpolation.transform(world, entity, transform);
+    let (_, _, angle) = transform.rotation.to_euler(EulerRot::XYZ);
+    let scale = transform.scale.truncate();
+    let rotation = Mat4::from_rotation_z(angle).transform_vector3((sprite.position * scale).extend(0.0)).truncate();
+    batch.draw(Sprite {
+      position: transform.translation.truncate() + rotation,
+      rotation: sprite.rotation + angle,
+      scale: sprite.scale * scale,
+      ..*sprite
+    });
+  });
+}
+
+impl Default for SpriteBatch {
+  fn default() -> Self {
+    SpriteBatch::new()
+  }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub(crate) struct SpriteVertex {
+  pub position: [f32; 2],
+  pub uv: [f32; 2],
+  pub color: [f32; 4],
+}
+
+// One sprite as the instanced pipeline reads it; the vertex shader makes the quad's corners.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub(crate) struct SpriteInstance {
+  pub position: [f32; 2], // of the centre
+  pub half_size: [f32; 2],
+  pub rotation: [f32; 2], // sine and cosine
+  pub uv_min: [f32; 2],
+  pub uv_max: [f32; 2],
+  pub color: [f32; 4],
+}
+
+// One draw call: a run of sprites with the same texture.
+struct SpriteDraw {
+  texture: TextureHandle,
+  instances: std::ops::Range<u32>,
+}
+
+// Draws a `SpriteBatch` as textured, alpha-blended quads, one instance per sprite.
+pub struct SpriteRenderer {
+  pipeline: SpritePipeline,
+  uniform_buffer: Buffer,
+  uniform_bind_group: BindGroup,
+  bind_groups: HashMap<TextureHandle, (ResourceId, BindGroup)>, // made for that texture
+  instances: Vec<SpriteInstance>,
+  instance_buffer: Option<Buffer>,
+  draws: Vec<SpriteDraw>,
+}
+
+// The sprite shader and its layouts, shared by everything drawing `SpriteVertex` quads (or, with
+// `instanced`, `SpriteInstance`s): group 0 is the view-projection uniform, group 1 a texture and
+// sampler.
+pub(crate) struct SpritePipeline {
+  pub pipeline: RenderPipeline,
+  pub uniform_layout: BindGroupLayout,
+  pub texture_layout: BindGroupLayout,
+  pub sampler: Sampler,
+}
+
+const SPRITE_SHADER: &str = "
+@group(0) @binding(0) var<uniform> view_projection: mat4x4<f32>;
+@group(1) @binding(0) var sprite_texture: texture_2d<f32>;
+@group(1) @binding(1) var sprite_sampler: sampler;
+
+struct VertexInput {
+    @location(0) position: vec2<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = view_projection * vec4<f32>(in.position, 0.0, 1.0);
+    out.uv = in.uv;
+    out.color = in.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(sprite_texture, sprite_sampler, in.uv) * in.color;
+}
+";
+
+const INSTANCED_SPRITE_SHADER: &str = "
+@group(0) @binding(0) var<uniform> view_projection: mat4x4<f32>;
+@group(1) @binding(0) var sprite_texture: texture_2d<f32>;
+@group(1) @binding(1) var sprite_sampler: sampler;
+
+struct InstanceInput {
+    @location(0) position: vec2<f32>,
+    @location(1) half_size: vec2<f32>,
+    @location(2) rotation: vec2<f32>,
+    @location(3) uv_min: vec2<f32>,
+    @location(4) uv_max: vec2<f32>,
+    @location(5) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+// Two triangles, in the same order the vertex path builds them.
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, in: InstanceInput) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, -1.0), vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, 1.0), vec2<f32>(-1.0, 1.0)
+    );
+    let corner = corners[index];
+    let offset = corner * in.half_size;
+    let sin = in.rotation.x;
+    let cos = in.rotation.y;
+    let rotated = vec2<f32>(offset.x * cos - offset.y * sin, offset.x * sin + offset.y * cos);
+    var out: VertexOutput;
+    out.clip_position = view_projection * vec4<f32>(in.position + rotated, 0.0, 1.0);
+    out.uv = mix(in.uv_min, in.uv_max, corner * 0.5 + 0.5);
+    out.color = in.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(sprite_texture, sprite_sampler, in.uv) * in.color;
+}
+";
+
+impl SpritePipeline {
+  pub fn new(device: &Device, format: TextureFormat) -> Self {
+    SpritePipeline::create(device, format, SPRITE_SHADER, VertexBufferLayout {
+      array_stride: size_of::<SpriteVertex>() as BufferAddress,
+      step_mode: VertexStepMode::Vertex,
+      attributes: &[
+        VertexAttribute { format: VertexFormat::Float32x2, shader_location: 0, offset: 0 },
+        VertexAttribute { format: VertexFormat::Float32x2, shader_location: 1, offset: 8 },
+        VertexAttribute { format: VertexFormat::Float32x4, shader_location: 2, offset: 16 }
+      ]
+    })
+  }
+
+  // Draws six vertices per `SpriteInstance` rather than taking vertices.
+  pub fn instanced(device: &Device, format: TextureFormat) -> Self {
+    SpritePipeline::create(device, format, INSTANCED_SPRITE_SHADER, VertexBufferLayout {
+      array_stride: size_of::<SpriteInstance>() as BufferAddress,
+      step_mode: VertexStepMode::Instance,
+      attributes: &[
+        VertexAttribute { format: VertexFormat::Float32x2, shader_location: 0, offset: 0 },
+        VertexAttribute { format: VertexFormat::Float32x2, shader_location: 1, offset: 8 },
+        VertexAttribute { format: VertexFormat::Float32x2, shader_location: 2, offset: 16 },
+        VertexAttribute { format: VertexFormat::Float32x2, shader_location: 3, offset: 24 },
+        VertexAttribute { format: VertexFormat::Float32x2, shader_location: 4, offset: 32 },
+        VertexAttribute { format: VertexFormat::Float32x4, shader_location: 5, offset: 40 }
+      ]
+    })
+  }
+
+  fn create(device: &Device, format: TextureFormat, shader: &'static str, buffer: VertexBufferLayout) -> Self {
+    let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+      label: Some("sprite-shader"),
+      source: ShaderSource::Wgsl(Cow::Borrowed(shader))
+    });
+
+    let uniform_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("sprite-uniform-layout"),
+      entries: &[BindGroupLayoutEntry {
+        binding: 0,
+        visibility: ShaderStages::VERTEX,
+        ty: BindingType::Buffer {
+          ty: BufferBindingType::Uniform,
+          has_dynamic_offset: false,
+          min_binding_size: None
+        },
+        count: None
+      }]
+    });
+
+    let texture_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("sprite-texture-layout"),
+      entries: &[
+        BindGroupLayoutEntry {
+          binding: 0,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: true },
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false
+          },
+          count: None
+        },
+        BindGroupLayoutEntry {
+          binding: 1,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Sampler(SamplerBindingType::Filtering),
+          count: None
+        }
+      ]
+    });
+
+    // Nearest filtering keeps pixel art crisp at whole-number scales.
+    let sampler = device.create_sampler(&SamplerDescriptor {
+      label: Some("sprite-sampler"),
+      address_mode_u: AddressMode::ClampToEdge,
+      address_mode_v: AddressMode::ClampToEdge,
+      mag_filter: FilterMode::Nearest,
+      min_filter: FilterMode::Nearest,
+      ..SamplerDescriptor::default()
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+      label: Some("sprite-pipeline-layout"),
+      bind_group_layouts: &[&uniform_layout, &texture_layout],
+      push_constant_ranges: &[]
+    });
+
+    let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+      label: Some("sprite-pipeline"),
+      layout: Some(&pipeline_layout),
+      vertex: VertexState {
+        module: &shader_module,
+        entry_point: "vs_main",
+        buffers: &[buffer]
+      },
+      fragment: Some(FragmentState {
+        module: &shader_module,
+        entry_point: "fs_main",
+        targets: &[Some(ColorTargetState {
+          format,
+          blend: Some(BlendState::ALPHA_BLENDING),
+          write_mask: ColorWrites::ALL
+        })]
+      }),
+      primitive: PrimitiveState::default(),
+      depth_stencil: None,
+      multisample: MultisampleState::default(),
+      multiview: None
+    });
+
+    SpritePipeline { pipeline, uniform_layout, texture_layout, sampler }
+  }
+
+  pub fn create_uniforms(&self, device: &Device) -> (Buffer, BindGroup) {
+    let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("sprite-uniforms"),
+      usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+      contents: bytemuck::bytes_of(&Mat4::IDENTITY.to_cols_array())
+    });
+
+    let uniform_bind_group = device.create_bind_group(&BindGroupDescriptor {
+      label: Some("sprite-uniform-bind-group"),
+      layout: &self.uniform_layout,
+      entries: &[BindGroupEntry { binding: 0, resource: uniform_buffer.as_entire_binding() }]
+    });
+
+    (uniform_buffer, uniform_bind_group)
+  }
+
+  pub fn create_texture_bind_group(&self, device: &Device, texture: &GpuTexture) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+      label: Some("sprite-texture-bind-group"),
+      layout: &self.texture_layout,
+      entries: &[
+        BindGroupEntry { binding: 0, resource: BindingResource::TextureView(&texture.view) },
+        BindGroupEntry { binding: 1, resource: BindingResource::Sampler(&self.sampler) }
+      ]
+    })
+  }
+}
+
+impl SpriteRenderer {
+  pub fn new(device: &Device, format: TextureFormat) -> Self {
+    let pipeline = SpritePipeline::instanced(device, format);
+    let (uniform_buffer, uniform_bind_group) = pipeline.create_uniforms(device);
+    SpriteRenderer {
+      pipeline,
+      uniform_buffer,
+      uniform_bind_group,
+      bind_groups: HashMap::new(),
+      instances: Vec::new(),
+      instance_buffer: None,
+      draws: Vec::new(),
+    }
+  }
+
+  // Builds this frame's instances. `target` is the size of the render target in pixels, for batches
+  // without a view-projection. With `snap`, positions are rounded to that many pixels per unit.
+  pub fn prepare(&mut self, device: &Device, queue: &Queue, batch: &mut SpriteBatch, textures: &GpuTextures, target: UVec2, snap: Option<f32>) {
+    self.bind_groups.retain(|handle, (id, _)| textures.get(*handle).is_some_and(|texture| texture.id == *id));
+
+    let view_projection = batch.view_projection
+      .unwrap_or_else(|| Mat4::orthographic_rh(0.0, target.x as f32, target.y as f32, 0.0, -1.0, 1.0));
+    queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&view_projection.to_cols_array()));
+
+    // Stable, so sprites on the same layer and texture keep the order they were drawn in.
+    batch.sprites.sort_by_key(|sprite| (sprite.layer, sprite.texture));
+    self.instances.clear();
+    self.draws.clear();
+    for sprite in &batch.sprites {
+      let texture = match textures.get(sprite.texture) {
+        Some(texture) => texture,
+        None => continue,
+      };
+      let size = texture.size.as_vec2();
+      if !self.bind_groups.contains_key(&sprite.texture) {
+        self.bind_groups.insert(sprite.texture, (texture.id, self.pipeline.create_texture_bind_group(device, texture)));
+      }
+      let (uv_min, uv_max) = sprite.region.unwrap_or((Vec2::ZERO, Vec2::ONE));
+      let (sin, cos) = sprite.rotation.sin_cos();
+      let index = self.instances.len() as u32;
+      self.instances.push(SpriteInstance {
+        position: snap.map_or(sprite.position, |pixels_per_unit| snap_to_pixel(sprite.position, pixels_per_unit)).to_array(),
+        half_size: (size * (uv_max - uv_min) * sprite.scale / 2.0).to_array(),
+        rotation: [sin, cos],
+        uv_min: uv_min.to_array(),
+        uv_max: uv_max.to_array(),
+        color: sprite.tint.to_array(),
+      });
+      match self.draws.last_mut() {
+        Some(draw) if draw.texture == sprite.texture => draw.instances.end = index + 1,
+        _ => self.draws.push(SpriteDraw { texture: sprite.texture, instances: index..index + 1 }),
+      }
+    }
+
+    if self.instances.is_empty() {
+      return;
+    }
+    let size = std::mem::size_of_val(self.instances.as_slice()) as BufferAddress;
+    if self.instance_buffer.as_ref().is_none_or(|buffer| buffer.size() < size) {
+      self.instance_buffer = Some(device.create_buffer(&BufferDescriptor {
+        label: Some("sprite-instances"),
+        size: size.next_power_of_two(),
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false
+      }));
+    }
+    queue.write_buffer(self.instance_buffer.as_ref().unwrap(), 0, bytemuck::cast_slice(&self.instances));
+  }
+
+  // One per run of sprites sharing a texture.
+  pub fn draw_calls(&self) -> u32 {
+    if self.instance_buffer.is_some() { self.draws.len() as u32 } else { 0 }
+  }
+
+  pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+    let buffer = match &self.instance_buffer {
+      Some(buffer) if !self.draws.is_empty() => buffer,
+      _ => return,
+    };
+    render_pass.set_pipeline(&self.pipeline.pipeline);
+    render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+    render_pass.set_vertex_buffer(0, buffer.slice(..));
+    for draw in &self.draws {
+      render_pass.set_bind_group(1, &self.bind_groups[&draw.texture].1, &[]);
+      render_pass.draw(0..6, draw.instances.clone());
+    }
+  }
+}
@@ -0,0 +1,141 @@
+use std::ops::Range;
+use std::sync::Arc;
+use glam::Vec3;
+use wgpu::{Device, Queue, RenderPass};
+
+use crate::game_engine::ecs::{Entity, Transform, World};
+use super::material::Material;
+use super::mesh::{GpuMesh, Mesh, MeshBuilder};
+
+// Marks an entity's mesh as static scenery that never moves, so it can be merged with every other
+// static mesh sharing its material. Moving or editing one means rebuilding its batch. The renderer
+// draws the batches in the model pass, and leaves these entities out of the instanced ones.
+#[derive(Debug, Clone)]
+pub struct StaticMesh {
+  pub mesh: Arc<Mesh>, // shared, so many props can use one mesh
+  pub material: Material,
+}
+
+impl StaticMesh {
+  pub fn new(mesh: Arc<Mesh>, material: Material) -> Self {
+    StaticMesh { mesh, material }
+  }
+}
+
+// Which indices each entity contributed to a batch.
+type Contributions = Vec<(Entity, Range<u32>)>;
+
+// One material's static meshes merged into a single world-space mesh, drawn in one call.
+pub struct StaticBatch {
+  pub material: Material,
+  mesh: Mesh,
+  center: Vec3, // of the merged mesh's bounds, for sorting by depth
+  gpu_mesh: Option<GpuMesh>,
+  entities: Contributions,
+}
+
+impl StaticBatch {
+  pub fn mesh(&self) -> &Mesh {
+    &self.mesh
+  }
+
+  pub fn entities(&self) -> &[(Entity, Range<u32>)] {
+    &self.entities
+  }
+
+  pub fn center(&self) -> Vec3 {
+    self.center
+  }
+
+  pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+    if let Some(gpu_mesh) = &self.gpu_mesh {
+      gpu_mesh.draw(render_pass);
+    }
+  }
+}
+
+// Every `StaticMesh` in a world merged into one batch per material. Build once at scene load; `sync`
+// rebuilds only when static entities were added, removed, moved or edited.
+#[derive(Default)]
+pub struct StaticBatches {
+  batches: Vec<StaticBatch>,
+  entities: Vec<Entity>, // what the current batches were built from, in entity order
+  dirty: bool,
+}
+
+impl StaticBatches {
+  pub fn new() -> Self {
+    StaticBatches::default()
+  }
+
+  // Forces a rebuild on the next `sync`, e.g. after changing a shared `Mesh` in place.
+  pub fn mark_dirty(&mut self) {
+    self.dirty = true;
+  }
+
+  // Rebuilds and re-uploads the batches if the static geometry changed. Returns whether it did.
+  pub fn sync(&mut self, world: &World, device: &Device, queue: &Queue) -> bool {
+    let mut entities = Vec::new();
+    let mut changed = false;
+    world.query::<(&StaticMesh, &Transform)>().for_each(|entity, _| {
+      changed |= world.is_changed::<StaticMesh>(entity) || world.is_changed::<Transform>(entity);
+      entities.push(entity);
+    });
+    entities.sort();
+
+    if !self.dirty && !changed && entities == self.entities {
+      return false;
+    }
+    self.rebuild(world);
+    for batch in &mut self.batches {
+      batch.gpu_mesh = Some(GpuMesh::new(device, queue, &mut batch.mesh));
+    }
+    true
+  }
+
+  // Merges the world's static meshes on the CPU without uploading them.
+  pub fn rebuild(&mut self, world: &World) {
+    // Materials hold floats, so they're compared rather than hashed; scenes have few of them.
+    let mut by_material: Vec<(Material, MeshBuilder, Contributions)> = Vec::new();
+    let mut entities = Vec::new();
+    world.query::<(&StaticMesh, &Transform)>().for_each(|entity, (static_mesh, transform)| {
+      let index = match by_material.iter().position(|(material, _, _)| *material == static_mesh.material) {
+        Some(index) => index,
+        None => {
+          by_material.push((static_mesh.material.clone(), MeshBuilder::default(), Vec::new()));
+          by_material.len() - 1
+        }
+      };
+      let (_, builder, contributions) = &mut by_material[index];
+      let start = builder.index_count() as u32;
+      builder.append_transformed(&static_mesh.mesh, transform);
+      contributions.push((entity, start..builder.index_count() as u32));
+      entities.push(entity);
+    });
+    entities.sort();
+
+    self.batches = by_material.into_iter()
+      .map(|(material, builder, entities)| {
+        let mesh = builder.build();
+        let center = mesh.bounds().map_or(Vec3::ZERO, |(min, max)| (min + max) / 2.0);
+        StaticBatch { material, mesh, center, gpu_mesh: None, entities }
+      })
+      .collect();
+    self.entities = entities;
+    self.dirty = false;
+  }
+
+  pub fn batches(&self) -> &[StaticBatch] {
+    &self.batches
+  }
+
+  pub fn batch(&self, material: &Material) -> Option<&StaticBatch> {
+    self.batches.iter().find(|batch| batch.material == *material)
+  }
+
+  pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+    for batch in &self.batches {
+      batch.draw(render_pass);
+    }
+  }
+}
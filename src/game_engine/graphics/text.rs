@@ -0,0 +1,134 @@
+use glam::{UVec2, Vec2};
+
+use crate::game_engine::ui::{FontId, Fonts, GlyphAtlas, RichText, RunContent, RunStyle, TextAlign, TextLayout};
+use super::color::Color;
+use super::sprite_batch::{Sprite, SpriteBatch};
+use super::texture::{TextureHandle, TextureManager};
+
+const ATLAS_SIZE: u32 = 1024;
+
+// A block of text to draw with the sprites. `position` is its top-left, in the sprite batch's
+// space (render-target pixels unless the batch has a view-projection).
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextSprite {
+  pub text: String,
+  pub font: Option<FontId>, // `TextRenderer::font` when unset
+  pub position: Vec2,
+  pub size: f32, // in pixels
+  pub color: Color,
+  pub max_width: Option<f32>, // wrap at word boundaries past this width
+  pub align: TextAlign, // of each line within the widest
+  pub layer: i32, // the sprite layer the glyphs go on
+}
+
+impl TextSprite {
+  pub fn new(text: impl Into<String>, position: Vec2, size: f32, color: Color) -> Self {
+    TextSprite { text: text.into(), font: None, position, size, color, max_width: None, align: TextAlign::Left, layer: 0 }
+  }
+
+  pub fn with_font(mut self, font: FontId) -> Self {
+    self.font = Some(font);
+    self
+  }
+
+  pub fn with_max_width(mut self, max_width: f32) -> Self {
+    self.max_width = Some(max_width);
+    self
+  }
+
+  pub fn with_align(mut self, align: TextAlign) -> Self {
+    self.align = align;
+    self
+  }
+
+  pub fn with_layer(mut self, layer: i32) -> Self {
+    self.layer = layer;
+    self
+  }
+}
+
+// Text in the scene rather than the UI overlay, as a resource. Glyphs of the world's `Fonts` (load
+// TTF/OTF files with `Fonts::load`) are rasterized into an atlas texture in the `TextureManager`,
+// and every queued block becomes one sprite per glyph in the `SpriteBatch`, so text sorts and
+// batches with the other sprites: text on one layer is a single draw call.
+pub struct TextRenderer {
+  pub font: Option<FontId>, // for text that doesn't pick one; the first font loaded otherwise
+  queue: Vec<TextSprite>,
+  atlas: GlyphAtlas,
+  texture: Option<TextureHandle>,
+}
+
+impl TextRenderer {
+  pub fn new() -> Self {
+    TextRenderer { font: None, queue: Vec::new(), atlas: GlyphAtlas::new(ATLAS_SIZE), texture: None }
+  }
+
+  pub fn draw_text(&mut self, text: &str, position: Vec2, size: f32, color: Color) {
+    self.draw(TextSprite::new(text, position, size, color));
+  }
+
+  pub fn draw(&mut self, text: TextSprite) {
+    self.queue.push(text);
+  }
+
+  // The size the text will take up once wrapped.
+  pub fn measure(&self, fonts: &Fonts, text: &TextSprite) -> Vec2 {
+    self.layout(fonts, text).map_or(Vec2::ZERO, |layout| layout.size)
+  }
+
+  pub fn clear(&mut self) {
+    self.queue.clear();
+  }
+
+  // Turns the queued text into sprites, uploading any new glyphs. The renderer calls this each
+  // frame before drawing sprites.
+  pub fn prepare(&mut self, fonts: &Fonts, textures: &mut TextureManager, batch: &mut SpriteBatch) {
+    let queue = std::mem::take(&mut self.queue);
+    let mut glyphs = Vec::new();
+    for text in &queue {
+      let layout = match self.layout(fonts, text) {
+        Some(layout) => layout,
+        None => continue,
+      };
+      for laid_out in layout.glyphs {
+        if let Some(glyph) = self.atlas.glyph(fonts, laid_out.font, laid_out.character, laid_out.size) {
+          if glyph.size.x > 0.0 && glyph.size.y > 0.0 {
+            let min = text.position + laid_out.pen + glyph.offset;
+            glyphs.push((min + glyph.size / 2.0, glyph.uv_min, glyph.uv_max, laid_out.color, text.layer));
+          }
+        }
+      }
+    }
+
+    if self.atlas.take_dirty() || self.texture.is_some_and(|texture| textures.get(texture).is_none()) {
+      // White, with the glyphs' coverage as alpha, so the sprite tint colours them.
+      let pixels: Vec<u8> = self.atlas.pixels().iter().flat_map(|coverage| [255, 255, 255, *coverage]).collect();
+      self.texture = match self.texture.filter(|texture| textures.get(*texture).is_some()) {
+        Some(texture) => textures.update(texture, pixels).ok().map(|_| texture),
+        None => textures.from_rgba(UVec2::splat(self.atlas.size), pixels, false).ok(),
+      };
+    }
+    let texture = match self.texture {
+      Some(texture) => texture,
+      None => return,
+    };
+    for (position, uv_min, uv_max, tint, layer) in glyphs {
+      batch.draw(Sprite { tint, region: Some((uv_min, uv_max)), layer, ..Sprite::new(texture, position) });
+    }
+  }
+
+  fn layout(&self, fonts: &Fonts, text: &TextSprite) -> Option<TextLayout> {
+    let font = text.font.or(self.font).unwrap_or(FontId(0));
+    fonts.get(font)?;
+    let mut rich = RichText::new(font, text.size, text.color).with_align(text.align);
+    rich.max_width = text.max_width;
+    rich.push(RunContent::Text(text.text.clone()), RunStyle::default());
+    Some(rich.layout(fonts))
+  }
+}
+
+impl Default for TextRenderer {
+  fn default() -> Self {
+    TextRenderer::new()
+  }
+}
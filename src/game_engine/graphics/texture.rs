@@ -0,0 +1,404 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use glam::UVec2;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize};
+use crate::game_engine::assets::{Asset, FileWatcher};
+use super::bind_group_cache::ResourceId;
+use super::upload::UploadQueue;
+use wgpu::{Device, Extent3d, ImageCopyTexture, ImageDataLayout, Origin3d, Queue, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureView, TextureViewDescriptor};
+
+thread_local! {
+  // Set while a save or scene is being loaded, so handles stored inside components point at the
+  // textures loaded for them.
+  static HANDLE_MAP: RefCell<Option<HandleMap>> = const { RefCell::new(None) };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(transparent)]
+pub struct TextureHandle(pub u32);
+
+// Scenes can refer to a texture by a name they give it instead of a handle.
+impl<'de> Deserialize<'de> for TextureHandle {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Saved {
+      Handle(u32),
+      Name(String),
+    }
+    HANDLE_MAP.with(|map| {
+      let map = map.borrow();
+      match Saved::deserialize(deserializer)? {
+        Saved::Handle(handle) => {
+          let handle = TextureHandle(handle);
+          Ok(map.as_ref().and_then(|map| map.handles.get(&handle).copied()).unwrap_or(handle))
+        }
+        Saved::Name(name) => map.as_ref().and_then(|map| map.names.get(&name).copied())
+          .ok_or_else(|| D::Error::custom(format!("no texture named {}", name))),
+      }
+    })
+  }
+}
+
+// What saved texture handles and names turn into while loading.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HandleMap {
+  pub handles: HashMap<TextureHandle, TextureHandle>, // handles missing from here are left as they are
+  pub names: HashMap<String, TextureHandle>,
+}
+
+// Runs `f` with texture handles deserialized inside it translated through `map`.
+pub(crate) fn with_handle_map<R>(map: HandleMap, f: impl FnOnce() -> R) -> R {
+  let previous = HANDLE_MAP.with(|current| current.replace(Some(map)));
+  let result = f();
+  HANDLE_MAP.with(|current| current.replace(previous));
+  result
+}
+
+// A decoded RGBA8 (sRGB) image on the CPU, as loaded through `Assets`. Hand it to
+// `TextureManager::add` to draw with it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Texture {
+  pub size: UVec2,
+  pub pixels: Vec<u8>,
+}
+
+impl Asset for Texture {
+  fn from_bytes(bytes: &[u8], _path: &Path) -> Result<Self, String> {
+    let image = image::load_from_memory(bytes).map_err(|err| format!("couldn't decode image: {}", err))?.to_rgba8();
+    Ok(Texture { size: UVec2::new(image.width(), image.height()), pixels: image.into_raw() })
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextureInfo {
+  pub size: UVec2,
+  pub mip_levels: u32,
+  pub path: Option<PathBuf>,
+}
+
+// Pixels waiting to go to the GPU: RGBA8 sRGB, one entry per mip level, largest first.
+#[derive(Debug, Clone)]
+pub struct TextureUpload {
+  pub handle: TextureHandle,
+  pub size: UVec2,
+  pub levels: Vec<Vec<u8>>,
+}
+
+// Every texture the game has loaded, as a resource. Images are decoded and mipmapped on the CPU
+// when loaded, then uploaded by the renderer before the next frame; draw calls and materials
+// refer to them by `TextureHandle`.
+#[derive(Default)]
+pub struct TextureManager {
+  pub watcher: FileWatcher, // textures from `load` are loaded again when their files change
+  textures: HashMap<TextureHandle, TextureInfo>,
+  by_path: HashMap<PathBuf, TextureHandle>,
+  uploads: Vec<TextureUpload>,
+  removed: Vec<TextureHandle>,
+  next: u32,
+}
+
+impl TextureManager {
+  pub fn new() -> Self {
+    TextureManager::default()
+  }
+
+  // Loads a PNG or JPEG with mipmaps. Loading the same path again returns the same handle.
+  pub fn load(&mut self, path: impl AsRef<Path>) -> Result<TextureHandle, String> {
+    let path = path.as_ref();
+    if let Some(handle) = self.by_path.get(path) {
+      return Ok(*handle);
+    }
+    let bytes = std::fs::read(path).map_err(|err| format!("couldn't read {}: {}", path.display(), err))?;
+    let handle = self.from_image_bytes(&bytes).map_err(|err| format!("{}: {}", path.display(), err))?;
+    self.textures.get_mut(&handle).unwrap().path = Some(path.to_path_buf());
+    self.by_path.insert(path.to_path_buf(), handle);
+    self.watcher.watch(path);
+    Ok(handle)
+  }
+
+  // Loads textures from `load` again if their files have changed, keeping their handles; the size
+  // may change. The renderer calls this before each frame's uploads.
+  pub fn reload_changed(&mut self) {
+    for path in self.watcher.poll() {
+      let handle = match self.by_path.get(&path) {
+        Some(handle) => *handle,
+        None => continue,
+      };
+      let texture = std::fs::read(&path).map_err(|err| err.to_string()).and_then(|bytes| Texture::from_bytes(&bytes, &path));
+      match texture {
+        Ok(texture) => {
+          let info = self.textures.get_mut(&handle).unwrap();
+          let levels = if info.mip_levels > 1 { mip_chain(texture.size, texture.pixels, true) } else { vec![texture.pixels] };
+          info.size = texture.size;
+          info.mip_levels = levels.len() as u32;
+          self.uploads.retain(|upload| upload.handle != handle);
+          self.uploads.push(TextureUpload { handle, size: texture.size, levels });
+          log::info!("reloaded {}", path.display());
+        }
+        Err(err) => log::warn!("couldn't reload {}: {}", path.display(), err),
+      }
+    }
+  }
+
+  // Decodes an encoded PNG or JPEG, e.g. one built in with `include_bytes!`.
+  pub fn from_image_bytes(&mut self, bytes: &[u8]) -> Result<TextureHandle, String> {
+    let texture = Texture::from_bytes(bytes, Path::new(""))?;
+    self.from_rgba(texture.size, texture.pixels, true)
+  }
+
+  // Uploads a texture loaded through `Assets`, with mipmaps.
+  pub fn add(&mut self, texture: &Texture) -> Result<TextureHandle, String> {
+    self.from_rgba(texture.size, texture.pixels.clone(), true)
+  }
+
+  pub fn from_rgba(&mut self, size: UVec2, pixels: Vec<u8>, mipmaps: bool) -> Result<TextureHandle, String> {
+    if size.x == 0 || size.y == 0 || pixels.len() != (size.x * size.y * 4) as usize {
+      return Err(format!("texture is {} bytes, expected {} for {}x{}", pixels.len(), size.x * size.y * 4, size.x, size.y));
+    }
+    let levels = if mipmaps { mip_chain(size, pixels, true) } else { vec![pixels] };
+    let handle = TextureHandle(self.next);
+    self.next += 1;
+    self.textures.insert(handle, TextureInfo { size, mip_levels: levels.len() as u32, path: None });
+    self.uploads.push(TextureUpload { handle, size, levels });
+    Ok(handle)
+  }
+
+  // A handle for a texture the renderer fills in itself, such as a `RenderTarget`'s. There are no
+  // pixels to upload, and `update` shouldn't be used on it.
+  pub fn reserve(&mut self, size: UVec2) -> TextureHandle {
+    let handle = TextureHandle(self.next);
+    self.next += 1;
+    self.textures.insert(handle, TextureInfo { size, mip_levels: 1, path: None });
+    handle
+  }
+
+  pub(crate) fn resize_reserved(&mut self, handle: TextureHandle, size: UVec2) {
+    if let Some(info) = self.textures.get_mut(&handle) {
+      info.size = size;
+    }
+  }
+
+  // Replaces a texture's pixels, keeping its handle. The size can't change; mipmaps are rebuilt if it
+  // has them.
+  pub fn update(&mut self, handle: TextureHandle, pixels: Vec<u8>) -> Result<(), String> {
+    let info = self.textures.get(&handle).ok_or_else(|| format!("no texture {:?}", handle))?;
+    let size = info.size;
+    if pixels.len() != (size.x * size.y * 4) as usize {
+      return Err(format!("texture is {} bytes, expected {} for {}x{}", pixels.len(), size.x * size.y * 4, size.x, size.y));
+    }
+    let levels = if info.mip_levels > 1 { mip_chain(size, pixels, true) } else { vec![pixels] };
+    self.uploads.retain(|upload| upload.handle != handle);
+    self.uploads.push(TextureUpload { handle, size, levels });
+    Ok(())
+  }
+
+  pub fn get(&self, handle: TextureHandle) -> Option<&TextureInfo> {
+    self.textures.get(&handle)
+  }
+
+  pub fn size(&self, handle: TextureHandle) -> Option<UVec2> {
+    self.textures.get(&handle).map(|info| info.size)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (TextureHandle, &TextureInfo)> {
+    self.textures.iter().map(|(handle, info)| (*handle, info))
+  }
+
+  // Frees the texture here and, from the next frame, on the GPU.
+  pub fn remove(&mut self, handle: TextureHandle) -> bool {
+    let info = match self.textures.remove(&handle) {
+      Some(info) => info,
+      None => return false,
+    };
+    if let Some(path) = info.path {
+      self.watcher.unwatch(&path);
+      self.by_path.remove(&path);
+    }
+    self.uploads.retain(|upload| upload.handle != handle);
+    self.removed.push(handle);
+    true
+  }
+
+  pub fn len(&self) -> usize {
+    self.textures.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.textures.is_empty()
+  }
+
+  pub fn take_uploads(&mut self) -> Vec<TextureUpload> {
+    std::mem::take(&mut self.uploads)
+  }
+
+  pub fn take_removed(&mut self) -> Vec<TextureHandle> {
+    std::mem::take(&mut self.removed)
+  }
+}
+
+// Box-filtered mip levels down to 1x1. sRGB colours are averaged in linear light so they don't
+// darken; other data (normals, roughness) is averaged as it is.
+fn mip_chain(size: UVec2, pixels: Vec<u8>, srgb: bool) -> Vec<Vec<u8>> {
+  let to_linear: Vec<f32> = (0..256).map(|value| if srgb { srgb_to_linear(value as f32 / 255.0) } else { value as f32 / 255.0 }).collect();
+  let mut levels = vec![pixels];
+  let mut size = size;
+  while size.x > 1 || size.y > 1 {
+    let next = (size / 2).max(UVec2::ONE);
+    let source = levels.last().unwrap();
+    let mut level = vec![0; (next.x * next.y * 4) as usize];
+    for y in 0..next.y {
+      for x in 0..next.x {
+        let mut sum = [0.0; 4];
+        for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+          let sx = (x * 2 + dx).min(size.x - 1);
+          let sy = (y * 2 + dy).min(size.y - 1);
+          let texel = &source[((sy * size.x + sx) * 4) as usize..][..4];
+          for channel in 0..3 {
+            sum[channel] += to_linear[texel[channel] as usize];
+          }
+          sum[3] += texel[3] as f32 / 255.0;
+        }
+        let out = &mut level[((y * next.x + x) * 4) as usize..][..4];
+        for channel in 0..3 {
+          let average = sum[channel] / 4.0;
+          out[channel] = (if srgb { linear_to_srgb(average) } else { average } * 255.0).round() as u8;
+        }
+        out[3] = (sum[3] / 4.0 * 255.0).round() as u8;
+      }
+    }
+    levels.push(level);
+    size = next;
+  }
+  levels
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+  if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+  if value <= 0.0031308 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 }
+}
+
+pub struct GpuTexture {
+  pub texture: wgpu::Texture,
+  pub view: TextureView,
+  pub size: UVec2,
+  pub mip_levels: u32,
+  pub id: ResourceId, // new whenever a handle's texture is recreated, so bind groups of the old one can be dropped
+}
+
+impl GpuTexture {
+  // Uploads RGBA8 sRGB pixels with mipmaps straight away, for textures the renderer owns itself
+  // rather than the world's `TextureManager`.
+  pub fn from_rgba(device: &Device, queue: &Queue, size: UVec2, pixels: Vec<u8>) -> GpuTexture {
+    upload_texture(device, queue, &TextureUpload { handle: TextureHandle(u32::MAX), size, levels: mip_chain(size, pixels, true) }, TextureFormat::Rgba8UnormSrgb)
+  }
+
+  // Like `from_rgba`, for data that isn't colour, such as normal or roughness maps: stored and
+  // sampled as it is, without the sRGB curve.
+  pub fn from_rgba_linear(device: &Device, queue: &Queue, size: UVec2, pixels: Vec<u8>) -> GpuTexture {
+    upload_texture(device, queue, &TextureUpload { handle: TextureHandle(u32::MAX), size, levels: mip_chain(size, pixels, false) }, TextureFormat::Rgba8Unorm)
+  }
+}
+
+// The GPU side of a `TextureManager`, kept in step by `sync` at the start of each frame.
+#[derive(Default)]
+pub struct GpuTextures {
+  textures: HashMap<TextureHandle, GpuTexture>,
+  pending: VecDeque<TextureUpload>, // waiting for room in a frame's upload budget
+}
+
+impl GpuTextures {
+  pub fn new() -> Self {
+    GpuTextures::default()
+  }
+
+  // Records as many of the manager's uploads as fit in this frame's budget; the rest wait for later
+  // frames, in order. A new texture only shows up in `get` once its pixels are on their way.
+  pub fn sync(&mut self, device: &Device, uploads: &mut UploadQueue, manager: &mut TextureManager) {
+    for handle in manager.take_removed() {
+      self.textures.remove(&handle);
+      self.pending.retain(|upload| upload.handle != handle);
+    }
+    self.pending.extend(manager.take_uploads());
+    while uploads.has_room() {
+      let upload = match self.pending.pop_front() {
+        Some(upload) => upload,
+        None => break,
+      };
+      // Updates are written into the existing texture so views and bind groups of it stay valid.
+      match self.textures.get(&upload.handle) {
+        Some(existing) if existing.size == upload.size && existing.mip_levels == upload.levels.len() as u32 =>
+          stage_levels(device, uploads, &existing.texture, &upload),
+        _ => {
+          let texture = create_texture(device, &upload, TextureFormat::Rgba8UnormSrgb);
+          stage_levels(device, uploads, &texture.texture, &upload);
+          self.textures.insert(upload.handle, texture);
+        }
+      }
+    }
+  }
+
+  // How many uploads are still waiting for a frame with room.
+  pub fn pending(&self) -> usize {
+    self.pending.len()
+  }
+
+  pub fn get(&self, handle: TextureHandle) -> Option<&GpuTexture> {
+    self.textures.get(&handle)
+  }
+
+  // Puts a texture the renderer made itself under `handle`, e.g. one from `TextureManager::reserve`.
+  pub fn insert(&mut self, handle: TextureHandle, texture: GpuTexture) {
+    self.textures.insert(handle, texture);
+  }
+
+  pub fn contains(&self, handle: TextureHandle) -> bool {
+    self.textures.contains_key(&handle)
+  }
+}
+
+fn upload_texture(device: &Device, queue: &Queue, upload: &TextureUpload, format: TextureFormat) -> GpuTexture {
+  let texture = create_texture(device, upload, format);
+  write_levels(queue, &texture.texture, upload);
+  texture
+}
+
+fn create_texture(device: &Device, upload: &TextureUpload, format: TextureFormat) -> GpuTexture {
+  let texture = device.create_texture(&TextureDescriptor {
+    label: Some("texture"),
+    size: Extent3d { width: upload.size.x, height: upload.size.y, depth_or_array_layers: 1 },
+    mip_level_count: upload.levels.len() as u32,
+    sample_count: 1,
+    dimension: TextureDimension::D2,
+    format,
+    usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST
+  });
+  let view = texture.create_view(&TextureViewDescriptor::default());
+  GpuTexture { texture, view, size: upload.size, mip_levels: upload.levels.len() as u32, id: ResourceId::new() }
+}
+
+fn write_levels(queue: &Queue, texture: &wgpu::Texture, upload: &TextureUpload) {
+  let mut size = upload.size;
+  for (level, pixels) in upload.levels.iter().enumerate() {
+    queue.write_texture(
+      ImageCopyTexture { texture, mip_level: level as u32, origin: Origin3d::ZERO, aspect: TextureAspect::All },
+      pixels,
+      ImageDataLayout { offset: 0, bytes_per_row: std::num::NonZeroU32::new(size.x * 4), rows_per_image: None },
+      Extent3d { width: size.x, height: size.y, depth_or_array_layers: 1 }
+    );
+    size = (size / 2).max(UVec2::ONE);
+  }
+}
+
+fn stage_levels(device: &Device, uploads: &mut UploadQueue, texture: &wgpu::Texture, upload: &TextureUpload) {
+  let mut size = upload.size;
+  for (level, pixels) in upload.levels.iter().enumerate() {
+    uploads.write_texture(device, texture, level as u32, size, pixels);
+    size = (size / 2).max(UVec2::ONE);
+  }
+}
@@ -0,0 +1,303 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::sync::Arc;
+use glam::{IVec2, Mat4, UVec2, Vec2};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{BindGroup, Buffer, BufferAddress, BufferDescriptor, BufferUsages, Device, Queue, RenderPass, TextureFormat};
+
+use crate::game_engine::ecs::{Entity, Transform, World};
+use crate::game_engine::tilemap::{TileProjection, TiledMap, Tilemap, Tileset};
+use super::bind_group_cache::ResourceId;
+use super::color::Color;
+use super::culling::Frustum;
+use super::sprite_batch::{SpritePipeline, SpriteVertex};
+use super::texture::{GpuTextures, TextureHandle, TextureManager};
+
+// Tiles per side of a chunk: the unit tilemaps are rebuilt and culled in.
+const CHUNK_SIZE: i32 = 16;
+
+// A tileset and the texture its image was loaded into.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TilesetTexture {
+  pub tileset: Tileset,
+  pub texture: TextureHandle,
+}
+
+// Draws an entity's `Tilemap` with the `TilemapRenderer`. Tile ids are looked up in `tilesets`;
+// `grid_size` is the map's cell size in tileset pixels, so tiles bigger than a cell (trees, tall
+// walls) stand up out of it instead of being squashed. Tilemaps are drawn in the XY plane of their
+// `Transform` under the world's `Camera`, before sprites, lowest `layer` first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TilemapVisual {
+  pub tilesets: Arc<[TilesetTexture]>,
+  pub grid_size: UVec2,
+  pub tint: Color,
+  pub layer: i32,
+}
+
+impl TilemapVisual {
+  pub fn new(tilesets: Arc<[TilesetTexture]>) -> Self {
+    let grid_size = tilesets.first().map_or(UVec2::ONE, |tileset| tileset.tileset.tile_size);
+    TilemapVisual { tilesets, grid_size, tint: Color::WHITE, layer: 0 }
+  }
+}
+
+impl TiledMap {
+  // Loads the tilesets' images and spawns an entity per visible tile layer, with a `Transform`,
+  // `Tilemap` and `TilemapVisual`, one world unit to a pixel and the first layer at the bottom.
+  pub fn spawn(&self, world: &mut World, textures: &mut TextureManager) -> Result<Vec<Entity>, String> {
+    let tilesets: Arc<[TilesetTexture]> = self.tilesets.iter()
+      .map(|tileset| Ok(TilesetTexture { tileset: tileset.clone(), texture: textures.load(&tileset.image)? }))
+      .collect::<Result<Vec<_>, String>>()?
+      .into();
+    let mut entities = Vec::new();
+    for (index, layer) in self.layers.iter().enumerate().filter(|(_, layer)| layer.visible) {
+      let entity = world.spawn();
+      world.insert(entity, Transform::from_xyz(layer.offset.x, -layer.offset.y, 0.0));
+      world.insert(entity, layer.tiles.clone());
+      world.insert(entity, TilemapVisual {
+        grid_size: self.tile_size,
+        tint: Color::WHITE.with_alpha(layer.opacity),
+        layer: index as i32,
+        ..TilemapVisual::new(tilesets.clone())
+      });
+      entities.push(entity);
+    }
+    Ok(entities)
+  }
+}
+
+struct ChunkDraw {
+  texture: TextureHandle,
+  vertices: Range<u32>,
+}
+
+// An animated tile's quad, given new uvs every frame.
+struct AnimatedTile {
+  corners: [Vec2; 4],
+  id: u32,
+  tileset: usize,
+}
+
+struct Chunk {
+  min: Vec2, // world-space bounds, for culling
+  max: Vec2,
+  draws: Vec<ChunkDraw>, // into the layer's vertex buffer
+  animated: Vec<AnimatedTile>,
+}
+
+// What was built for one tilemap entity, kept until its components change.
+struct CachedLayer {
+  ticks: [Option<u32>; 3], // when the `Transform`, `Tilemap` and `TilemapVisual` it was built from changed
+  layer: i32,
+  tilesets: Arc<[TilesetTexture]>,
+  color: [f32; 4],
+  buffer: Option<Buffer>,
+  chunks: Vec<Chunk>,
+}
+
+struct TileDraw {
+  layer: Option<Entity>, // whose buffer, or `None` for this frame's animated tiles
+  texture: TextureHandle,
+  vertices: Range<u32>,
+}
+
+// Draws every entity with a `Transform`, `Tilemap` and `TilemapVisual`. Each tilemap is split into
+// chunks whose vertices stay on the GPU until the entity's components change; chunks outside the
+// camera's view are skipped, and animated tiles are rebuilt each frame for the visible chunks only.
+pub struct TilemapRenderer {
+  pipeline: SpritePipeline,
+  uniform_buffer: Buffer,
+  uniform_bind_group: BindGroup,
+  bind_groups: HashMap<TextureHandle, (ResourceId, BindGroup)>, // made for that texture
+  layers: HashMap<Entity, CachedLayer>,
+  animated: Vec<SpriteVertex>,
+  animated_buffer: Option<Buffer>,
+  draws: Vec<TileDraw>,
+}
+
+impl TilemapRenderer {
+  pub fn new(device: &Device, format: TextureFormat) -> Self {
+    let pipeline = SpritePipeline::new(device, format);
+    let (uniform_buffer, uniform_bind_group) = pipeline.create_uniforms(device);
+    TilemapRenderer {
+      pipeline,
+      uniform_buffer,
+      uniform_bind_group,
+      bind_groups: HashMap::new(),
+      layers: HashMap::new(),
+      animated: Vec::new(),
+      animated_buffer: None,
+      draws: Vec::new(),
+    }
+  }
+
+  // Rebuilds the tilemaps that changed and picks this frame's visible chunks. `time` in seconds
+  // drives tile animations.
+  pub fn prepare(&mut self, device: &Device, queue: &Queue, world: &World, textures: &GpuTextures, view_projection: Mat4, time: f32) {
+    self.bind_groups.retain(|handle, (id, _)| textures.get(*handle).is_some_and(|texture| texture.id == *id));
+    queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&view_projection.to_cols_array()));
+
+    let mut alive = HashSet::new();
+    world.query::<(&Transform, &Tilemap, &TilemapVisual)>().for_each(|entity, (transform, tilemap, visual)| {
+      alive.insert(entity);
+      let ticks = [world.changed_tick::<Transform>(entity), world.changed_tick::<Tilemap>(entity), world.changed_tick::<TilemapVisual>(entity)];
+      if self.layers.get(&entity).is_none_or(|layer| layer.ticks != ticks) {
+        self.layers.insert(entity, build_layer(device, ticks, transform, tilemap, visual));
+      }
+    });
+    self.layers.retain(|entity, _| alive.contains(entity));
+
+    let mut order: Vec<(&Entity, &CachedLayer)> = self.layers.iter().collect();
+    order.sort_by_key(|(entity, layer)| (layer.layer, **entity));
+    let frustum = Frustum::from_view_projection(view_projection);
+    self.animated.clear();
+    self.draws.clear();
+    for (entity, layer) in order {
+      let mut animated: Vec<(TextureHandle, [SpriteVertex; 6])> = Vec::new();
+      for chunk in layer.chunks.iter().filter(|chunk| frustum.intersects_aabb(chunk.min.extend(0.0), chunk.max.extend(0.0))) {
+        for draw in &chunk.draws {
+          match self.draws.last_mut() {
+            // Neighbouring chunks are usually next to each other in the buffer too.
+            Some(last) if last.layer == Some(*entity) && last.texture == draw.texture && last.vertices.end == draw.vertices.start =>
+              last.vertices.end = draw.vertices.end,
+            _ => self.draws.push(TileDraw { layer: Some(*entity), texture: draw.texture, vertices: draw.vertices.clone() }),
+          }
+        }
+        for tile in &chunk.animated {
+          let tileset = &layer.tilesets[tile.tileset];
+          let uv = tileset.tileset.uv_rect(tileset.tileset.animated_tile(tile.id, time));
+          animated.push((tileset.texture, quad(tile.corners, uv, layer.color)));
+        }
+      }
+      animated.sort_by_key(|(texture, _)| *texture);
+      for (texture, vertices) in animated {
+        let start = self.animated.len() as u32;
+        self.animated.extend_from_slice(&vertices);
+        match self.draws.last_mut() {
+          Some(last) if last.layer.is_none() && last.texture == texture => last.vertices.end = start + 6,
+          _ => self.draws.push(TileDraw { layer: None, texture, vertices: start..start + 6 }),
+        }
+      }
+    }
+
+    self.draws.retain(|draw| textures.contains(draw.texture));
+    for draw in &self.draws {
+      if !self.bind_groups.contains_key(&draw.texture) {
+        if let Some(texture) = textures.get(draw.texture) {
+          self.bind_groups.insert(draw.texture, (texture.id, self.pipeline.create_texture_bind_group(device, texture)));
+        }
+      }
+    }
+
+    if self.animated.is_empty() {
+      return;
+    }
+    let size = std::mem::size_of_val(self.animated.as_slice()) as BufferAddress;
+    if self.animated_buffer.as_ref().is_none_or(|buffer| buffer.size() < size) {
+      self.animated_buffer = Some(device.create_buffer(&BufferDescriptor {
+        label: Some("tilemap-animated-vertices"),
+        size: size.next_power_of_two(),
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false
+      }));
+    }
+    queue.write_buffer(self.animated_buffer.as_ref().unwrap(), 0, bytemuck::cast_slice(&self.animated));
+  }
+
+  pub fn draw_calls(&self) -> u32 {
+    self.draws.len() as u32
+  }
+
+  pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+    if self.draws.is_empty() {
+      return;
+    }
+    render_pass.set_pipeline(&self.pipeline.pipeline);
+    render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+    let mut bound = None;
+    for draw in &self.draws {
+      let buffer = match draw.layer {
+        Some(entity) => self.layers[&entity].buffer.as_ref(),
+        None => self.animated_buffer.as_ref(),
+      };
+      let buffer = match buffer {
+        Some(buffer) => buffer,
+        None => continue,
+      };
+      if bound != Some(draw.layer) {
+        render_pass.set_vertex_buffer(0, buffer.slice(..));
+        bound = Some(draw.layer);
+      }
+      render_pass.set_bind_group(1, &self.bind_groups[&draw.texture].1, &[]);
+      render_pass.draw(draw.vertices.clone(), 0..1);
+    }
+  }
+}
+
+fn build_layer(device: &Device, ticks: [Option<u32>; 3], transform: &Transform, tilemap: &Tilemap, visual: &TilemapVisual) -> CachedLayer {
+  let matrix = transform.matrix();
+  let color = visual.tint.to_array();
+  let cell = tilemap.tile_size;
+  let mut chunks: HashMap<IVec2, Vec<(IVec2, u32, usize)>> = HashMap::new();
+  for (tile, id) in tilemap.draw_order() {
+    if let Some(tileset) = visual.tilesets.iter().position(|tileset| tileset.tileset.contains(id)) {
+      chunks.entry(tile.div_euclid(IVec2::splat(CHUNK_SIZE))).or_default().push((tile, id, tileset));
+    }
+  }
+  let mut keys: Vec<IVec2> = chunks.keys().copied().collect();
+  keys.sort_by_key(|key| tilemap.projection.draw_key(*key));
+
+  let mut vertices: Vec<SpriteVertex> = Vec::new();
+  let mut built = Vec::new();
+  for key in keys {
+    let mut tiles = chunks.remove(&key).unwrap_or_default();
+    // Stable, so tiles from one tileset keep their back-to-front order.
+    tiles.sort_by_key(|(_, _, tileset)| *tileset);
+    let mut chunk = Chunk { min: Vec2::splat(f32::MAX), max: Vec2::splat(f32::MIN), draws: Vec::new(), animated: Vec::new() };
+    for (tile, id, index) in tiles {
+      let tileset = &visual.tilesets[index];
+      let size = cell * tileset.tileset.tile_size.as_vec2() / visual.grid_size.max(UVec2::ONE).as_vec2();
+      let centre = tilemap.tile_to_local(tile);
+      // Tiles stand on the bottom of their cell, left-aligned on square grids as in Tiled.
+      let min = match tilemap.projection {
+        TileProjection::Orthogonal => centre - cell / 2.0,
+        _ => Vec2::new(centre.x - size.x / 2.0, centre.y - cell.y / 2.0),
+      };
+      let corners = [min, Vec2::new(min.x + size.x, min.y), min + size, Vec2::new(min.x, min.y + size.y)]
+        .map(|corner| matrix.transform_point3(corner.extend(0.0)).truncate());
+      for corner in corners {
+        chunk.min = chunk.min.min(corner);
+        chunk.max = chunk.max.max(corner);
+      }
+      if tileset.tileset.is_animated(id) {
+        chunk.animated.push(AnimatedTile { corners, id, tileset: index });
+        continue;
+      }
+      let start = vertices.len() as u32;
+      vertices.extend_from_slice(&quad(corners, tileset.tileset.uv_rect(id), color));
+      match chunk.draws.last_mut() {
+        Some(draw) if draw.texture == tileset.texture => draw.vertices.end = start + 6,
+        _ => chunk.draws.push(ChunkDraw { texture: tileset.texture, vertices: start..start + 6 }),
+      }
+    }
+    built.push(chunk);
+  }
+
+  let buffer = (!vertices.is_empty()).then(|| device.create_buffer_init(&BufferInitDescriptor {
+    label: Some("tilemap-vertices"),
+    contents: bytemuck::cast_slice(&vertices),
+    usage: BufferUsages::VERTEX
+  }));
+  CachedLayer { ticks, layer: visual.layer, tilesets: visual.tilesets.clone(), color, buffer, chunks: built }
+}
+
+// Two triangles from bottom-left, bottom-right, top-right, top-left corners. Texture v runs down.
+fn quad(corners: [Vec2; 4], (uv_min, uv_max): (Vec2, Vec2), color: [f32; 4]) -> [SpriteVertex; 6] {
+  let vertex = |corner: Vec2, u: f32, v: f32| SpriteVertex { position: corner.to_array(), uv: [u, v], color };
+  let a = vertex(corners[0], uv_min.x, uv_max.y);
+  let b = vertex(corners[1], uv_max.x, uv_max.y);
+  let c = vertex(corners[2], uv_max.x, uv_min.y);
+  let d = vertex(corners[3], uv_min.x, uv_min.y);
+  [a, b, c, a, c, d]
+}
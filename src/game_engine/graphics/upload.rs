@@ -0,0 +1,188 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use glam::UVec2;
+use wgpu::{Buffer, BufferAddress, BufferDescriptor, BufferUsages, CommandBuffer, CommandEncoder, CommandEncoderDescriptor, Device, Extent3d, ImageCopyBuffer, ImageCopyTexture, ImageDataLayout, MapMode, Origin3d, Texture, TextureAspect};
+
+// Enough for a 2048x2048 texture with its mipmaps in one frame.
+pub const DEFAULT_UPLOAD_BUDGET: u64 = 24 << 20;
+
+// How big the staging buffers uploads are packed into are. A bigger upload gets one of its own,
+// which isn't kept for reuse.
+pub const STAGING_CHUNK_SIZE: BufferAddress = 1 << 20;
+
+// Where texture copies start in a staging buffer; D3D12 wants 512.
+const TEXTURE_COPY_ALIGNMENT: BufferAddress = 512;
+
+// A mapped staging buffer being filled from the front.
+struct StagingChunk {
+  buffer: Buffer,
+  size: BufferAddress,
+  cursor: BufferAddress,
+}
+
+// Copies into GPU buffers and textures through staging buffers, recorded on a command buffer of
+// their own that the renderer submits just ahead of the frame's. wgpu hands us a single queue, so
+// that submission order is what makes the frame wait for its uploads; there's no separate transfer
+// queue or semaphore to manage. Unlike `Queue::write_*`, nothing is copied into the queue's own
+// staging memory when the write is made, and callers can spread big uploads over several frames
+// by checking `has_room` against the per-frame `budget`.
+//
+// The staging buffers are a ring: `finish` closes the frame's, `recall` starts mapping them again
+// once submitted, and they're reused as soon as the GPU's done copying out of them.
+pub struct UploadQueue {
+  pub budget: u64, // bytes per frame; a single upload bigger than this still goes through on its own
+  encoder: Option<CommandEncoder>,
+  recorded: u64, // bytes recorded since the last `finish`
+  open: Vec<StagingChunk>, // mapped, being filled this frame
+  closed: Vec<StagingChunk>, // unmapped and submitted, or about to be
+  returning: Vec<(StagingChunk, Arc<AtomicBool>)>, // being mapped again, set once they are
+  free: Vec<StagingChunk>, // mapped and empty
+}
+
+impl UploadQueue {
+  pub fn new(budget: u64) -> Self {
+    UploadQueue { budget, encoder: None, recorded: 0, open: Vec::new(), closed: Vec::new(), returning: Vec::new(), free: Vec::new() }
+  }
+
+  // Whether this frame can take another upload.
+  pub fn has_room(&self) -> bool {
+    self.recorded < self.budget
+  }
+
+  pub fn recorded_bytes(&self) -> u64 {
+    self.recorded
+  }
+
+  pub fn write_buffer(&mut self, device: &Device, buffer: &Buffer, offset: BufferAddress, data: &[u8]) {
+    if data.is_empty() {
+      return;
+    }
+    // Copies have to be whole words; the padding lands in the target's spare room.
+    let size = align(data.len() as BufferAddress, wgpu::COPY_BUFFER_ALIGNMENT);
+    let (chunk, start) = self.stage(device, size, wgpu::COPY_BUFFER_ALIGNMENT, |mapped| mapped[..data.len()].copy_from_slice(data));
+    let encoder = self.encoder.get_or_insert_with(|| create_encoder(device));
+    encoder.copy_buffer_to_buffer(&self.open[chunk].buffer, start, buffer, offset, size);
+    self.recorded += size;
+  }
+
+  // Like `write_buffer`, but the copy's recorded into `encoder`, e.g. to land between two compute
+  // dispatches. Still staged here, so `finish` has to come before `encoder`'s submitted.
+  pub fn write_buffer_in(&mut self, device: &Device, encoder: &mut CommandEncoder, buffer: &Buffer, offset: BufferAddress, data: &[u8]) {
+    if data.is_empty() {
+      return;
+    }
+    let size = align(data.len() as BufferAddress, wgpu::COPY_BUFFER_ALIGNMENT);
+    let (chunk, start) = self.stage(device, size, wgpu::COPY_BUFFER_ALIGNMENT, |mapped| mapped[..data.len()].copy_from_slice(data));
+    encoder.copy_buffer_to_buffer(&self.open[chunk].buffer, start, buffer, offset, size);
+  }
+
+  // Writes tightly packed RGBA8 `pixels` into one mip level of `texture`.
+  pub fn write_texture(&mut self, device: &Device, texture: &Texture, mip_level: u32, size: UVec2, pixels: &[u8]) {
+    let row = size.x as BufferAddress * 4;
+    let padded_row = align(row, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as BufferAddress);
+    let (chunk, start) = self.stage(device, padded_row * size.y as BufferAddress, TEXTURE_COPY_ALIGNMENT, |mapped| {
+      for (source, target) in pixels.chunks_exact(row as usize).zip(mapped.chunks_exact_mut(padded_row as usize)) {
+        target[..row as usize].copy_from_slice(source);
+      }
+    });
+    let encoder = self.encoder.get_or_insert_with(|| create_encoder(device));
+    encoder.copy_buffer_to_texture(
+      ImageCopyBuffer {
+        buffer: &self.open[chunk].buffer,
+        layout: ImageDataLayout { offset: start, bytes_per_row: std::num::NonZeroU32::new(padded_row as u32), rows_per_image: None }
+      },
+      ImageCopyTexture { texture, mip_level, origin: Origin3d::ZERO, aspect: TextureAspect::All },
+      Extent3d { width: size.x, height: size.y, depth_or_array_layers: 1 }
+    );
+    self.recorded += padded_row * size.y as BufferAddress;
+  }
+
+  // Room for `size` bytes in an open staging buffer, filled by `fill`: which open chunk, and where.
+  fn stage(&mut self, device: &Device, size: BufferAddress, alignment: BufferAddress, fill: impl FnOnce(&mut [u8])) -> (usize, BufferAddress) {
+    let open = self.open.iter().position(|chunk| align(chunk.cursor, alignment) + size <= chunk.size);
+    let index = match open {
+      Some(index) => index,
+      None => {
+        self.reclaim();
+        let chunk = match self.free.iter().position(|chunk| chunk.size >= size) {
+          Some(index) => self.free.swap_remove(index),
+          None => {
+            let chunk_size = STAGING_CHUNK_SIZE.max(size);
+            StagingChunk {
+              buffer: device.create_buffer(&BufferDescriptor {
+                label: Some("upload-staging"),
+                size: chunk_size,
+                usage: BufferUsages::MAP_WRITE | BufferUsages::COPY_SRC,
+                mapped_at_creation: true
+              }),
+              size: chunk_size,
+              cursor: 0,
+            }
+          }
+        };
+        self.open.push(chunk);
+        self.open.len() - 1
+      }
+    };
+    let chunk = &mut self.open[index];
+    let start = align(chunk.cursor, alignment);
+    chunk.cursor = start + size;
+    fill(&mut chunk.buffer.slice(start..start + size).get_mapped_range_mut());
+    (index, start)
+  }
+
+  // Takes back the staging buffers the GPU has finished copying out of.
+  fn reclaim(&mut self) {
+    let (mapped, waiting): (Vec<_>, Vec<_>) = self.returning.drain(..).partition(|(_, mapped)| mapped.load(Ordering::Acquire));
+    self.returning = waiting;
+    for (mut chunk, _) in mapped {
+      chunk.cursor = 0;
+      self.free.push(chunk);
+    }
+  }
+
+  // The copies recorded since the last call, if any. Submit them before anything that reads them.
+  pub fn finish(&mut self) -> Option<CommandBuffer> {
+    self.recorded = 0;
+    for chunk in self.open.drain(..) {
+      chunk.buffer.unmap();
+      self.closed.push(chunk);
+    }
+    self.encoder.take().map(|encoder| encoder.finish())
+  }
+
+  // Starts getting the staging buffers `finish` closed back for reuse. Call once what `finish`
+  // returned has been submitted.
+  pub fn recall(&mut self) {
+    for chunk in self.closed.drain(..) {
+      if chunk.size > STAGING_CHUNK_SIZE {
+        continue;
+      }
+      let mapped = Arc::new(AtomicBool::new(false));
+      let done = mapped.clone();
+      chunk.buffer.slice(..).map_async(MapMode::Write, move |result| done.store(result.is_ok(), Ordering::Release));
+      self.returning.push((chunk, mapped));
+    }
+  }
+
+  // Bytes of staging memory held, in use or not.
+  pub fn staging_bytes(&self) -> BufferAddress {
+    self.open.iter().chain(&self.closed).chain(self.returning.iter().map(|(chunk, _)| chunk)).chain(&self.free).map(|chunk| chunk.size).sum()
+  }
+}
+
+fn create_encoder(device: &Device) -> CommandEncoder {
+  device.create_command_encoder(&CommandEncoderDescriptor {
+    label: Some("upload-encoder")
+  })
+}
+
+impl Default for UploadQueue {
+  fn default() -> Self {
+    UploadQueue::new(DEFAULT_UPLOAD_BUDGET)
+  }
+}
+
+fn align(value: BufferAddress, alignment: BufferAddress) -> BufferAddress {
+  value.div_ceil(alignment) * alignment
+}
@@ -0,0 +1,107 @@
+use glam::{UVec2, Vec2};
+
+use super::pixel_perfect::integer_scale;
+
+// How the rendered image is fitted to the window when the two differ in size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scaling {
+  Stretch, // fills the window, distorting the image if the aspect ratios differ
+  Letterbox, // as big as fits with the aspect ratio kept, with black bars either side
+  Integer, // the largest whole-number scale that fits, with black bars, for pixel art
+}
+
+// What resolution the scene is drawn at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RenderResolution {
+  Fixed(UVec2), // a virtual resolution, whatever the window's size
+  Scale(f32), // a fraction of the window's size, e.g. 0.5 to draw a quarter of the pixels
+}
+
+// Draws the scene at a resolution other than the window's and scales it up to fit, as a world
+// resource. Without one the scene is drawn at the window's size. UI is still drawn over the top at
+// the window's resolution.
+//
+// Cursor positions map back into the scene's pixels with `Input::virtual_mouse_position`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ViewportScaling {
+  pub resolution: RenderResolution,
+  pub scaling: Scaling,
+  pub smooth: bool, // filters linearly when scaling up; off keeps pixels sharp
+}
+
+impl ViewportScaling {
+  // Whole-number scaling with sharp pixels, for pixel art.
+  pub fn pixel_perfect(resolution: UVec2) -> Self {
+    ViewportScaling { resolution: RenderResolution::Fixed(resolution), scaling: Scaling::Integer, smooth: false }
+  }
+
+  pub fn letterbox(resolution: UVec2) -> Self {
+    ViewportScaling { resolution: RenderResolution::Fixed(resolution), scaling: Scaling::Letterbox, smooth: true }
+  }
+
+  pub fn stretch(resolution: UVec2) -> Self {
+    ViewportScaling { resolution: RenderResolution::Fixed(resolution), scaling: Scaling::Stretch, smooth: true }
+  }
+
+  // Fills the window but draws the scene at `scale` times its size, to trade sharpness for speed.
+  pub fn render_scale(scale: f32) -> Self {
+    ViewportScaling { resolution: RenderResolution::Scale(scale), scaling: Scaling::Stretch, smooth: true }
+  }
+
+  // Sharp pixels mean pixel art, so the 2D camera and sprites are snapped to whole pixels to stop
+  // them shimmering as they move.
+  pub fn snaps_to_pixels(&self) -> bool {
+    !self.smooth
+  }
+
+  // The size the scene is drawn at, for a window of `window` pixels.
+  pub fn render_size(&self, window: UVec2) -> UVec2 {
+    match self.resolution {
+      RenderResolution::Fixed(resolution) => resolution.max(UVec2::ONE),
+      RenderResolution::Scale(scale) => (window.as_vec2() * scale).round().as_uvec2().max(UVec2::ONE),
+    }
+  }
+
+  // Where the scaled scene goes in a window of `window` pixels.
+  pub fn view(&self, window: UVec2) -> VirtualView {
+    VirtualView::new(self.render_size(window), window, self.scaling)
+  }
+}
+
+// Where the scene's pixels land in the window, for converting positions between the two.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VirtualView {
+  pub resolution: UVec2, // of the scene
+  pub offset: UVec2, // of the scaled scene's top-left corner in the window, in pixels
+  pub size: UVec2, // of the scaled scene in the window, in pixels
+}
+
+impl VirtualView {
+  pub fn new(resolution: UVec2, window: UVec2, scaling: Scaling) -> Self {
+    let window = window.max(UVec2::ONE);
+    // A window smaller than the resolution squashes the image rather than cropping it.
+    let size = match scaling {
+      Scaling::Stretch => window,
+      Scaling::Letterbox => {
+        let scale = (window.as_vec2() / resolution.as_vec2()).min_element();
+        (resolution.as_vec2() * scale).round().as_uvec2().clamp(UVec2::ONE, window)
+      }
+      Scaling::Integer => (resolution * integer_scale(resolution, window)).min(window),
+    };
+    VirtualView { resolution, offset: (window - size) / 2, size }
+  }
+
+  // A window position (e.g. the cursor) in the scene's pixels, or `None` over the bars.
+  pub fn window_to_virtual(&self, position: Vec2) -> Option<Vec2> {
+    let local = position - self.offset.as_vec2();
+    let size = self.size.as_vec2();
+    if local.x < 0.0 || local.y < 0.0 || local.x >= size.x || local.y >= size.y {
+      return None;
+    }
+    Some(local / size * self.resolution.as_vec2())
+  }
+
+  pub fn virtual_to_window(&self, position: Vec2) -> Vec2 {
+    self.offset.as_vec2() + position / self.resolution.as_vec2() * self.size.as_vec2()
+  }
+}
@@ -0,0 +1,265 @@
+use std::collections::HashSet;
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+use winit::event::{ElementState, MouseScrollDelta};
+
+use super::actions::{ActionMap, Binding};
+use super::gamepad::{GamepadButton, Gamepads};
+use super::graphics::viewport::VirtualView;
+
+pub use winit::event::{MouseButton, VirtualKeyCode as KeyCode};
+
+// Lines are turned into pixels at this rate for scroll deltas.
+const PIXELS_PER_LINE: f32 = 20.0;
+
+// What an input method is in the middle of composing, like the kana of a word before it's turned
+// into kanji. Games draw it at the text cursor until it arrives in `Input::text`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Composition {
+  pub text: String,
+  pub cursor: Option<(usize, usize)>, // byte range of `text` the input method has selected; equal ends for a caret
+}
+
+// Keyboard and mouse state, kept up to date by the engine from window events. Poll it from the
+// main loop (`engine.input`) or from systems (it's copied into the active world as a resource).
+// The `just_` queries are true for the one frame the change happened in. `actions` maps named
+// actions to keys, mouse buttons and gamepad buttons for the `action_` queries. Between
+// `start_text_input` and `stop_text_input`, typed text arrives in `text` as the keyboard layout and
+// input method made it, for chat and text fields.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Input {
+  pub actions: ActionMap,
+  keys: HashSet<KeyCode>,
+  keys_pressed: HashSet<KeyCode>,
+  keys_released: HashSet<KeyCode>,
+  buttons: HashSet<MouseButton>,
+  buttons_pressed: HashSet<MouseButton>,
+  buttons_released: HashSet<MouseButton>,
+  mouse_position: Vec2,
+  virtual_view: Option<VirtualView>, // from the world's `ViewportScaling`, if it has one
+  mouse_delta: Vec2,
+  scroll: Vec2,
+  pad_buttons: HashSet<GamepadButton>, // held, pressed and released on any pad, for actions
+  pad_pressed: HashSet<GamepadButton>,
+  pad_released: HashSet<GamepadButton>,
+  text_input: bool,
+  text_input_position: Option<Vec2>,
+  text: String, // typed this frame
+  composition: Option<Composition>,
+}
+
+impl Input {
+  pub fn new() -> Self {
+    Input::default()
+  }
+
+  pub fn is_pressed(&self, key: KeyCode) -> bool {
+    self.keys.contains(&key)
+  }
+
+  pub fn just_pressed(&self, key: KeyCode) -> bool {
+    self.keys_pressed.contains(&key)
+  }
+
+  pub fn just_released(&self, key: KeyCode) -> bool {
+    self.keys_released.contains(&key)
+  }
+
+  pub fn pressed_keys(&self) -> impl Iterator<Item = KeyCode> + '_ {
+    self.keys.iter().copied()
+  }
+
+  pub fn is_mouse_pressed(&self, button: MouseButton) -> bool {
+    self.buttons.contains(&button)
+  }
+
+  pub fn mouse_just_pressed(&self, button: MouseButton) -> bool {
+    self.buttons_pressed.contains(&button)
+  }
+
+  pub fn mouse_just_released(&self, button: MouseButton) -> bool {
+    self.buttons_released.contains(&button)
+  }
+
+  // The cursor in window pixels, origin top-left.
+  pub fn mouse_position(&self) -> Vec2 {
+    self.mouse_position
+  }
+
+  // The cursor in the scene's pixels when a `ViewportScaling` draws it at another resolution, or
+  // `None` over the bars around it. Without one it's the same as `mouse_position`.
+  pub fn virtual_mouse_position(&self) -> Option<Vec2> {
+    match &self.virtual_view {
+      Some(view) => view.window_to_virtual(self.mouse_position),
+      None => Some(self.mouse_position),
+    }
+  }
+
+  // Raw mouse movement this frame, which keeps coming when the cursor is at the window's edge.
+  pub fn mouse_delta(&self) -> Vec2 {
+    self.mouse_delta
+  }
+
+  // Wheel movement this frame in pixels; positive y scrolls up.
+  pub fn scroll(&self) -> Vec2 {
+    self.scroll
+  }
+
+  // Whether anything bound to `action` is held.
+  pub fn action_pressed(&self, action: &str) -> bool {
+    self.actions.bindings(action).iter().any(|binding| self.binding_held(*binding))
+  }
+
+  // The frame `action` went from nothing held to something held. Pressing a second binding while
+  // the first is still down doesn't count again.
+  pub fn action_just_pressed(&self, action: &str) -> bool {
+    let bindings = self.actions.bindings(action);
+    bindings.iter().any(|binding| self.binding_pressed(*binding))
+      && bindings.iter().all(|binding| self.binding_pressed(*binding) || !self.binding_held(*binding))
+  }
+
+  // The frame the last held binding of `action` was let go.
+  pub fn action_just_released(&self, action: &str) -> bool {
+    self.actions.bindings(action).iter().any(|binding| self.binding_released(*binding)) && !self.action_pressed(action)
+  }
+
+  fn binding_held(&self, binding: Binding) -> bool {
+    match binding {
+      Binding::Key(key) => self.keys.contains(&key),
+      Binding::Mouse(button) => self.buttons.contains(&button),
+      Binding::Gamepad(button) => self.pad_buttons.contains(&button),
+    }
+  }
+
+  fn binding_pressed(&self, binding: Binding) -> bool {
+    match binding {
+      Binding::Key(key) => self.keys_pressed.contains(&key),
+      Binding::Mouse(button) => self.buttons_pressed.contains(&button),
+      Binding::Gamepad(button) => self.pad_pressed.contains(&button),
+    }
+  }
+
+  fn binding_released(&self, binding: Binding) -> bool {
+    match binding {
+      Binding::Key(key) => self.keys_released.contains(&key),
+      Binding::Mouse(button) => self.buttons_released.contains(&button),
+      Binding::Gamepad(button) => self.pad_released.contains(&button),
+    }
+  }
+
+  pub fn key_event(&mut self, key: KeyCode, state: ElementState) {
+    match state {
+      // Key repeat sends more presses for a held key; only the first counts.
+      ElementState::Pressed => if self.keys.insert(key) {
+        self.keys_pressed.insert(key);
+      },
+      ElementState::Released => if self.keys.remove(&key) {
+        self.keys_released.insert(key);
+      },
+    }
+  }
+
+  pub fn mouse_button_event(&mut self, button: MouseButton, state: ElementState) {
+    match state {
+      ElementState::Pressed => if self.buttons.insert(button) {
+        self.buttons_pressed.insert(button);
+      },
+      ElementState::Released => if self.buttons.remove(&button) {
+        self.buttons_released.insert(button);
+      },
+    }
+  }
+
+  pub fn cursor_moved(&mut self, position: Vec2) {
+    self.mouse_position = position;
+  }
+
+  pub fn set_virtual_view(&mut self, view: Option<VirtualView>) {
+    self.virtual_view = view;
+  }
+
+  pub fn mouse_motion(&mut self, delta: Vec2) {
+    self.mouse_delta += delta;
+  }
+
+  pub fn mouse_wheel(&mut self, delta: MouseScrollDelta) {
+    self.scroll += match delta {
+      MouseScrollDelta::LineDelta(x, y) => Vec2::new(x, y) * PIXELS_PER_LINE,
+      MouseScrollDelta::PixelDelta(position) => Vec2::new(position.x as f32, position.y as f32),
+    };
+  }
+
+  // Turns on text input, and the platform's input method for languages that need one.
+  pub fn start_text_input(&mut self) {
+    self.text_input = true;
+  }
+
+  pub fn stop_text_input(&mut self) {
+    self.text_input = false;
+    self.composition = None;
+  }
+
+  pub fn is_text_input(&self) -> bool {
+    self.text_input
+  }
+
+  // Where the text cursor is, in window pixels, so the input method can put its list of
+  // candidates beside it.
+  pub fn set_text_input_position(&mut self, position: Vec2) {
+    self.text_input_position = Some(position);
+  }
+
+  pub fn text_input_position(&self) -> Option<Vec2> {
+    self.text_input_position
+  }
+
+  // Text typed this frame, without control characters like backspace, which come as key presses.
+  pub fn text(&self) -> &str {
+    &self.text
+  }
+
+  pub fn composition(&self) -> Option<&Composition> {
+    self.composition.as_ref()
+  }
+
+  pub fn text_event(&mut self, text: &str) {
+    if self.text_input {
+      self.text.extend(text.chars().filter(|c| !c.is_control()));
+    }
+  }
+
+  pub fn composition_event(&mut self, composition: Option<Composition>) {
+    if self.text_input {
+      self.composition = composition.filter(|composition| !composition.text.is_empty());
+    }
+  }
+
+  // Takes the buttons from every connected pad, merged, so actions work on whichever pad is used.
+  pub fn gamepad_buttons(&mut self, gamepads: &Gamepads) {
+    self.pad_buttons.clear();
+    self.pad_pressed.clear();
+    self.pad_released.clear();
+    for (_, pad) in gamepads.iter() {
+      self.pad_pressed.extend(pad.just_pressed_buttons());
+      self.pad_released.extend(pad.just_released_buttons());
+      self.pad_buttons.extend(pad.pressed_buttons());
+    }
+  }
+
+  // Lets go of everything, e.g. when the window loses focus and won't hear the releases.
+  pub fn release_all(&mut self) {
+    self.keys_released.extend(self.keys.drain());
+    self.buttons_released.extend(self.buttons.drain());
+  }
+
+  // Forgets this frame's presses, releases and movement.
+  pub fn end_frame(&mut self) {
+    self.keys_pressed.clear();
+    self.keys_released.clear();
+    self.buttons_pressed.clear();
+    self.buttons_released.clear();
+    self.mouse_delta = Vec2::ZERO;
+    self.scroll = Vec2::ZERO;
+    self.text.clear();
+  }
+}
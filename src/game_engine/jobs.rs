@@ -0,0 +1,311 @@
+use std::any::Any;
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+type Job = Box<dyn FnOnce() + Send>;
+type Panic = Box<dyn Any + Send>;
+
+thread_local! {
+  // Which pool and worker this thread is, so jobs spawned from a job stay on their worker's queue.
+  static WORKER: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+
+struct Shared {
+  injector: Mutex<VecDeque<Job>>, // jobs spawned from outside the pool
+  locals: Vec<Mutex<VecDeque<Job>>>, // one per worker; the owner pops the back, thieves the front
+  queued: AtomicUsize,
+  sleep: (Mutex<()>, Condvar), // workers wait here while there's nothing queued
+  finished: (Mutex<()>, Condvar), // signalled whenever a job finishes, for anyone waiting on one
+  shutdown: AtomicBool,
+}
+
+impl Shared {
+  fn id(&self) -> usize {
+    self as *const Shared as usize
+  }
+
+  fn worker(&self) -> Option<usize> {
+    WORKER.with(|worker| worker.get()).filter(|(pool, _)| *pool == self.id()).map(|(_, index)| index)
+  }
+
+  fn push(&self, job: Job) {
+    match self.worker() {
+      Some(index) => self.locals[index].lock().unwrap().push_back(job),
+      None => self.injector.lock().unwrap().push_back(job),
+    }
+    self.queued.fetch_add(1, Ordering::SeqCst);
+    // Taking the lock means a worker between checking `queued` and waiting can't miss this.
+    let _lock = self.sleep.0.lock().unwrap();
+    self.sleep.1.notify_one();
+  }
+
+  // The newest job on this thread's own queue, else the oldest from outside, else one stolen from
+  // another worker.
+  fn pop(&self) -> Option<Job> {
+    let own = self.worker();
+    let job = own.and_then(|index| self.locals[index].lock().unwrap().pop_back())
+      .or_else(|| self.injector.lock().unwrap().pop_front())
+      .or_else(|| {
+        let start = own.unwrap_or(0);
+        (1..=self.locals.len())
+          .map(|offset| (start + offset) % self.locals.len())
+          .find_map(|index| self.locals[index].lock().unwrap().pop_front())
+      });
+    if job.is_some() {
+      self.queued.fetch_sub(1, Ordering::SeqCst);
+    }
+    job
+  }
+
+  fn run(&self, job: Job) {
+    job();
+    let _lock = self.finished.0.lock().unwrap();
+    self.finished.1.notify_all();
+  }
+
+  // Runs queued jobs on this thread until `done`, so waiting on a job never blocks the pool it's
+  // waiting on.
+  fn help_until(&self, done: impl Fn() -> bool) {
+    loop {
+      if done() {
+        return;
+      }
+      if let Some(job) = self.pop() {
+        self.run(job);
+        continue;
+      }
+      let lock = self.finished.0.lock().unwrap();
+      if done() {
+        return;
+      }
+      // The timeout catches new jobs to help with as well as finishes.
+      let _ = self.finished.1.wait_timeout(lock, Duration::from_millis(1)).unwrap();
+    }
+  }
+
+  fn work(&self, index: usize) {
+    WORKER.with(|worker| worker.set(Some((self.id(), index))));
+    while !self.shutdown.load(Ordering::SeqCst) {
+      match self.pop() {
+        Some(job) => self.run(job),
+        None => {
+          let lock = self.sleep.0.lock().unwrap();
+          if self.queued.load(Ordering::SeqCst) == 0 && !self.shutdown.load(Ordering::SeqCst) {
+            drop(self.sleep.1.wait(lock).unwrap());
+          }
+        }
+      }
+    }
+  }
+}
+
+// Stops the workers once the last `Jobs` is gone. They're not joined, since that could be from one
+// of their own jobs.
+struct Pool {
+  shared: Arc<Shared>,
+}
+
+impl Drop for Pool {
+  fn drop(&mut self) {
+    self.shared.shutdown.store(true, Ordering::SeqCst);
+    let _lock = self.shared.sleep.0.lock().unwrap();
+    self.shared.sleep.1.notify_all();
+  }
+}
+
+// Counts outstanding jobs in a group, e.g. a `Scope` or the frame's jobs.
+#[derive(Default)]
+struct Group {
+  pending: AtomicUsize,
+  panic: Mutex<Option<Panic>>, // the first job to panic
+}
+
+impl Group {
+  fn wrap<'a>(group: &Arc<Group>, f: impl FnOnce() + Send + 'a) -> Box<dyn FnOnce() + Send + 'a> {
+    group.pending.fetch_add(1, Ordering::SeqCst);
+    let group = group.clone();
+    Box::new(move || {
+      if let Err(panic) = catch_unwind(AssertUnwindSafe(f)) {
+        group.panic.lock().unwrap().get_or_insert(panic);
+      }
+      group.pending.fetch_sub(1, Ordering::SeqCst);
+    })
+  }
+
+  fn is_done(&self) -> bool {
+    self.pending.load(Ordering::SeqCst) == 0
+  }
+
+  fn rethrow(&self) {
+    if let Some(panic) = self.panic.lock().unwrap().take() {
+      resume_unwind(panic);
+    }
+  }
+}
+
+// A thread pool that spreads engine and game work across cores. Each worker keeps its own queue
+// and steals from the others when it runs dry. On `Engine` as `jobs`, and in the world as a
+// resource for systems. Clones share the same workers.
+//
+// With no workers, as on the web, every job runs straight away on the thread that spawns it.
+#[derive(Clone)]
+pub struct Jobs {
+  pool: Arc<Pool>,
+  frame: Arc<Group>, // `spawn_frame` jobs not yet joined
+}
+
+impl Jobs {
+  pub fn new(threads: usize) -> Self {
+    let shared = Arc::new(Shared {
+      injector: Mutex::new(VecDeque::new()),
+      locals: (0..threads).map(|_| Mutex::new(VecDeque::new())).collect(),
+      queued: AtomicUsize::new(0),
+      sleep: (Mutex::new(()), Condvar::new()),
+      finished: (Mutex::new(()), Condvar::new()),
+      shutdown: AtomicBool::new(false),
+    });
+    for index in 0..threads {
+      let shared = shared.clone();
+      let spawned = std::thread::Builder::new()
+        .name(format!("job-worker-{}", index))
+        .spawn(move || shared.work(index));
+      if let Err(err) = spawned {
+        log::warn!("couldn't start job worker {}: {}", index, err);
+      }
+    }
+    Jobs { pool: Arc::new(Pool { shared }), frame: Arc::new(Group::default()) }
+  }
+
+  pub fn threads(&self) -> usize {
+    self.pool.shared.locals.len()
+  }
+
+  fn submit(&self, job: Job) {
+    match self.threads() {
+      0 => job(),
+      _ => self.pool.shared.push(job),
+    }
+  }
+
+  // Runs `f` on the pool. The handle gets its result.
+  pub fn spawn<T: Send + 'static>(&self, f: impl FnOnce() -> T + Send + 'static) -> JobHandle<T> {
+    let (handle, job) = self.job(f);
+    self.submit(Box::new(job));
+    handle
+  }
+
+  // Like `spawn`, but the engine waits for it before rendering the frame, so its result is ready by
+  // the next frame's update.
+  pub fn spawn_frame<T: Send + 'static>(&self, f: impl FnOnce() -> T + Send + 'static) -> JobHandle<T> {
+    let (handle, job) = self.job(f);
+    self.submit(Group::wrap(&self.frame, job));
+    handle
+  }
+
+  fn job<T: Send + 'static>(&self, f: impl FnOnce() -> T + Send + 'static) -> (JobHandle<T>, impl FnOnce() + Send + 'static) {
+    let handle = JobHandle { slot: Arc::new(Mutex::new(None)), shared: self.pool.shared.clone() };
+    let slot = handle.slot.clone();
+    (handle, move || *slot.lock().unwrap() = Some(catch_unwind(AssertUnwindSafe(f))))
+  }
+
+  // Waits for every `spawn_frame` job so far, helping to run them.
+  pub fn join_frame(&self) {
+    self.pool.shared.help_until(|| self.frame.is_done());
+  }
+
+  // Runs `f`, which can spawn jobs that borrow from outside it, and waits for all of them before
+  // returning. A panic in any of them carries on once they've all finished.
+  pub fn scope<'scope, R>(&'scope self, f: impl FnOnce(&Scope<'scope>) -> R) -> R {
+    let scope = Scope { jobs: self, group: Arc::new(Group::default()), marker: PhantomData };
+    let result = catch_unwind(AssertUnwindSafe(|| f(&scope)));
+    self.pool.shared.help_until(|| scope.group.is_done());
+    let result = result.unwrap_or_else(|panic| resume_unwind(panic));
+    scope.group.rethrow();
+    result
+  }
+
+  // Calls `f` on every item, split in chunks across the pool.
+  pub fn for_each_mut<T: Send>(&self, items: &mut [T], f: impl Fn(&mut T) + Sync) {
+    let chunk = self.chunk_size(items.len());
+    let f = &f;
+    self.scope(|scope| {
+      for chunk in items.chunks_mut(chunk) {
+        scope.spawn(move || chunk.iter_mut().for_each(f));
+      }
+    });
+  }
+
+  // `f` of every item, in order, worked out across the pool.
+  pub fn map<T: Sync, R: Send>(&self, items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let chunk = self.chunk_size(items.len());
+    let mut results: Vec<Vec<R>> = items.chunks(chunk).map(|_| Vec::new()).collect();
+    let f = &f;
+    self.scope(|scope| {
+      for (items, results) in items.chunks(chunk).zip(results.iter_mut()) {
+        scope.spawn(move || *results = items.iter().map(f).collect());
+      }
+    });
+    results.into_iter().flatten().collect()
+  }
+
+  // A few chunks per thread, so a slow one doesn't hold the rest up.
+  fn chunk_size(&self, len: usize) -> usize {
+    len.div_ceil((self.threads() + 1) * 4).max(1)
+  }
+}
+
+impl Default for Jobs {
+  // A worker per core, leaving one for the main thread. None on the web, which has no threads.
+  fn default() -> Self {
+    let threads = match cfg!(target_arch = "wasm32") {
+      true => 0,
+      false => std::thread::available_parallelism().map_or(1, |cores| cores.get().saturating_sub(1).max(1)),
+    };
+    Jobs::new(threads)
+  }
+}
+
+// Jobs spawned here may borrow anything that outlives the `Jobs::scope` call.
+pub struct Scope<'scope> {
+  jobs: &'scope Jobs,
+  group: Arc<Group>,
+  marker: PhantomData<&'scope mut &'scope ()>, // invariant, so the borrows can't be shortened
+}
+
+impl<'scope> Scope<'scope> {
+  pub fn spawn(&self, f: impl FnOnce() + Send + 'scope) {
+    let job = Group::wrap(&self.group, f);
+    // SAFETY: `Jobs::scope` doesn't return, even by panicking, until every job spawned through this
+    // scope has run, so nothing the job borrows is gone before it's used.
+    let job = unsafe { std::mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Job>(job) };
+    self.jobs.submit(job);
+  }
+}
+
+// A job's result, once it's finished.
+pub struct JobHandle<T> {
+  slot: Arc<Mutex<Option<std::thread::Result<T>>>>,
+  shared: Arc<Shared>,
+}
+
+impl<T> JobHandle<T> {
+  pub fn is_finished(&self) -> bool {
+    self.slot.lock().unwrap().is_some()
+  }
+
+  // The result if the job's finished, leaving the handle empty. Carries on the job's panic if it had one.
+  pub fn try_take(&self) -> Option<T> {
+    self.slot.lock().unwrap().take().map(|result| result.unwrap_or_else(|panic| resume_unwind(panic)))
+  }
+
+  // Waits for the job, helping the pool along meanwhile. Panics if the result's already been taken.
+  pub fn join(self) -> T {
+    self.shared.help_until(|| self.is_finished());
+    self.try_take().expect("job result already taken")
+  }
+}
@@ -0,0 +1,247 @@
+use std::collections::{BTreeMap, VecDeque};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::ecs::{TypeRegistry, World};
+
+// How many ticks of state hashes are kept for comparing with other players'.
+const HASH_HISTORY: usize = 256;
+
+// Every player's input for one lockstep tick, as a resource while that tick's fixed schedule runs.
+// Systems should act on these rather than on `Input`, which is only this machine's.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TickInputs {
+  pub tick: u64,
+  pub inputs: BTreeMap<u32, Vec<u8>>, // by player; empty when a player did nothing
+}
+
+impl TickInputs {
+  pub fn get(&self, player: u32) -> Option<&[u8]> {
+    self.inputs.get(&player).map(Vec::as_slice)
+  }
+
+  // A player's input as a value sent with `Lockstep::submit_value`.
+  pub fn decode<T: DeserializeOwned>(&self, player: u32) -> Option<T> {
+    let bytes = self.get(player).filter(|bytes| !bytes.is_empty())?;
+    ron::de::from_bytes(bytes).ok()
+  }
+}
+
+// Runs the fixed schedule in lockstep, as a world resource: a tick only runs once every player's
+// input for it has been submitted, so every machine runs the same ticks with the same inputs. Start
+// it with `Engine::start_lockstep`.
+//
+// Inputs are scheduled `input_delay` ticks ahead, to give them time to reach the other players;
+// send each one to them with the tick `submit_local` returns and `submit` theirs as they arrive.
+// After each tick the world's registered components are hashed, and `hash` gives the hash for a
+// tick to compare with the other players' and catch a desync.
+//
+// For the simulation to stay in step it has to be deterministic: use `Fixed` rather than floats in
+// game logic, the world's `Rng` (seeded from `seed`) for randomness, and nothing that depends on
+// frame time. The physics world isn't stepped in lockstep, since it uses floats.
+#[derive(Debug, Clone)]
+pub struct Lockstep {
+  pub players: u32,
+  pub input_delay: u64,
+  pub seed: u64,
+  tick: u64, // the next tick to run
+  pending: BTreeMap<u64, BTreeMap<u32, Vec<u8>>>,
+  hashes: VecDeque<(u64, u64)>,
+  owed: u32, // fixed steps that were due but had to wait for inputs
+}
+
+impl Lockstep {
+  pub fn new(players: u32, seed: u64) -> Self {
+    Lockstep::with_input_delay(players, seed, 3)
+  }
+
+  pub fn with_input_delay(players: u32, seed: u64, input_delay: u64) -> Self {
+    // Nobody can have input for the first few ticks, so they run with none.
+    let pending = (0..input_delay).map(|tick| (tick, (0..players).map(|player| (player, Vec::new())).collect())).collect();
+    Lockstep { players, input_delay, seed, tick: 0, pending, hashes: VecDeque::new(), owed: 0 }
+  }
+
+  // The next tick to run.
+  pub fn tick(&self) -> u64 {
+    self.tick
+  }
+
+  // Adds a player's input for `tick`. Returns false if that tick has already run, or the player
+  // already has input for it.
+  pub fn submit(&mut self, tick: u64, player: u32, input: Vec<u8>) -> bool {
+    if tick < self.tick || player >= self.players {
+      return false;
+    }
+    let inputs = self.pending.entry(tick).or_default();
+    if inputs.contains_key(&player) {
+      return false;
+    }
+    inputs.insert(player, input);
+    true
+  }
+
+  // Schedules this machine's input `input_delay` ticks from now, returning the tick to send it to
+  // the other players with.
+  pub fn submit_local(&mut self, player: u32, input: Vec<u8>) -> u64 {
+    let tick = self.tick + self.input_delay;
+    self.submit(tick, player, input);
+    tick
+  }
+
+  // `submit_local` for a serializable value, read back with `TickInputs::decode`.
+  pub fn submit_value<T: Serialize>(&mut self, player: u32, input: &T) -> Result<u64, String> {
+    let bytes = ron::to_string(input).map_err(|err| err.to_string())?.into_bytes();
+    Ok(self.submit_local(player, bytes))
+  }
+
+  // Whether every player's input for the next tick is in.
+  pub fn is_ready(&self) -> bool {
+    self.pending.get(&self.tick).is_some_and(|inputs| inputs.len() == self.players as usize)
+  }
+
+  // Whether ticks were due but couldn't run for want of input. Worth showing as "waiting for
+  // players" if it lasts.
+  pub fn is_stalled(&self) -> bool {
+    self.owed > 0 && !self.is_ready()
+  }
+
+  // The state hash after `tick`, if it's recent enough to still be kept.
+  pub fn hash(&self, tick: u64) -> Option<u64> {
+    self.hashes.iter().find(|(hashed, _)| *hashed == tick).map(|(_, hash)| *hash)
+  }
+
+  pub fn latest_hash(&self) -> Option<(u64, u64)> {
+    self.hashes.back().copied()
+  }
+
+  // Notes that `steps` fixed steps are due this frame and says how many ticks to run: as many of
+  // those, plus any owed from earlier, as have their inputs, up to `max`.
+  pub(crate) fn due(&mut self, steps: u32, max: u32) -> u32 {
+    self.owed = (self.owed + steps).min(max * 4);
+    let mut ready = 0;
+    let mut tick = self.tick;
+    while ready < self.owed.min(max) && self.pending.get(&tick).is_some_and(|inputs| inputs.len() == self.players as usize) {
+      ready += 1;
+      tick += 1;
+    }
+    ready
+  }
+
+  pub(crate) fn take_ready(&mut self) -> Option<TickInputs> {
+    if !self.is_ready() {
+      return None;
+    }
+    let inputs = self.pending.remove(&self.tick)?;
+    let tick = self.tick;
+    self.tick += 1;
+    self.owed = self.owed.saturating_sub(1);
+    Some(TickInputs { tick, inputs })
+  }
+
+  pub(crate) fn record_hash(&mut self, tick: u64, hash: u64) {
+    if self.hashes.len() == HASH_HISTORY {
+      self.hashes.pop_front();
+    }
+    self.hashes.push_back((tick, hash));
+  }
+}
+
+// A hash of every registered component in the world, in entity order, that comes out the same on
+// any machine with the same state. Components are hashed as their RON text, so a float has to
+// match to the bit.
+pub fn state_hash(world: &World, registry: &TypeRegistry) -> u64 {
+  let mut hasher = Fnv1a::new();
+  for entity in world.entities() {
+    hasher.write(&entity.to_bits().to_le_bytes());
+    for info in registry.iter() {
+      if let Some(Ok(value)) = info.serialize(world, entity) {
+        hasher.write(info.name.as_bytes());
+        if let Ok(text) = ron::to_string(&value) {
+          hasher.write(text.as_bytes());
+        }
+      }
+    }
+  }
+  hasher.finish()
+}
+
+// 64-bit FNV-1a. `std`'s hasher isn't promised to be the same between builds.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+  fn new() -> Self {
+    Fnv1a(0xcbf29ce484222325)
+  }
+
+  fn write(&mut self, bytes: &[u8]) {
+    for byte in bytes {
+      self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+    }
+  }
+
+  fn finish(&self) -> u64 {
+    self.0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde::Deserialize;
+  use crate::game_engine::fixed::{Fixed, FixedVec2};
+
+  #[derive(Debug, Default, Serialize, Deserialize)]
+  struct Mover {
+    position: FixedVec2,
+    velocity: FixedVec2,
+  }
+
+  const TICKS: u64 = 20;
+
+  // Runs two players through `TICKS` lockstep ticks, each pushing their mover in the direction
+  // `input` gives for the tick the input is sent on, and returns the state hash after every tick.
+  fn run(input: impl Fn(u64, u32) -> (i32, i32)) -> Vec<u64> {
+    let mut registry = TypeRegistry::new();
+    registry.register::<Mover>("Mover");
+    let mut world = World::new();
+    let movers = [world.spawn(), world.spawn()];
+    for &mover in &movers {
+      world.insert(mover, Mover::default());
+    }
+    let mut lockstep = Lockstep::new(2, 0);
+    for sent in 0..TICKS {
+      for player in 0..2 {
+        lockstep.submit_value(player, &input(sent, player)).unwrap();
+      }
+      let inputs = lockstep.take_ready().unwrap();
+      for (player, &mover) in movers.iter().enumerate() {
+        let (x, y) = inputs.decode::<(i32, i32)>(player as u32).unwrap_or_default();
+        let mover = world.get_mut::<Mover>(mover).unwrap();
+        let push = FixedVec2::new(Fixed::from_int(x), Fixed::from_int(y)) * Fixed::from_ratio(1, 3);
+        mover.velocity = (mover.velocity + push) * Fixed::from_ratio(9, 10);
+        let velocity = mover.velocity;
+        mover.position += velocity;
+      }
+      lockstep.record_hash(inputs.tick, state_hash(&world, &registry));
+    }
+    (0..TICKS).map(|tick| lockstep.hash(tick).unwrap()).collect()
+  }
+
+  fn steer(tick: u64, player: u32) -> (i32, i32) {
+    ((tick % 3) as i32 - 1, player as i32)
+  }
+
+  #[test]
+  fn the_same_inputs_give_the_same_hashes() {
+    assert_eq!(run(steer), run(steer));
+  }
+
+  #[test]
+  fn different_inputs_give_different_hashes() {
+    let hashes = run(steer);
+    let diverged = run(|tick, player| if tick == 5 && player == 1 { (-1, -1) } else { steer(tick, player) });
+    // The changed input is sent on tick 5, so runs on tick 8 after the input delay.
+    assert_eq!(hashes[..8], diverged[..8]);
+    assert!(hashes[8..].iter().zip(&diverged[8..]).all(|(a, b)| a != b));
+  }
+}
@@ -0,0 +1,248 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use super::time::Instant;
+
+// How the engine logs, for `EngineConfig::log`. `filters` set the level for targets starting with
+// a module path, like `("wgpu_core", LevelFilter::Warn)`, with the longest match winning over
+// `level`. A `RUST_LOG` environment variable in the usual `warn,my_game=debug` form replaces both.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogConfig {
+  pub level: LevelFilter,
+  pub filters: Vec<(String, LevelFilter)>,
+  pub buffer_lines: usize, // kept in memory for `recent`, and the console's `log` command
+  // Written to as well as the terminal, and moved aside to `name.1.log` and so on once it's
+  // `max_file_size` bytes, keeping `max_files` old ones. Ignored on the web.
+  pub file: Option<PathBuf>,
+  pub max_file_size: u64,
+  pub max_files: usize,
+}
+
+impl LogConfig {
+  // Reads filters in `RUST_LOG`'s form: a comma-separated list of levels, each either on its own,
+  // for everything, or after a module path and `=`.
+  pub fn parse_filters(&mut self, filters: &str) {
+    for filter in filters.split(',').map(str::trim).filter(|filter| !filter.is_empty()) {
+      let (module, level) = match filter.split_once('=') {
+        Some((module, level)) => (Some(module.trim()), level.trim()),
+        None => (None, filter),
+      };
+      let Ok(level) = level.parse::<LevelFilter>() else {
+        // A bare module name turns on everything from it, as `RUST_LOG` does.
+        match module {
+          None => self.filters.push((filter.to_string(), LevelFilter::Trace)),
+          Some(_) => eprintln!("ignoring the log filter '{}'", filter),
+        }
+        continue;
+      };
+      match module {
+        Some(module) => self.filters.push((module.to_string(), level)),
+        None => self.level = level,
+      }
+    }
+  }
+
+  fn level_for(&self, target: &str) -> LevelFilter {
+    self.filters.iter()
+      .filter(|(module, _)| target == module || target.strip_prefix(module.as_str()).is_some_and(|rest| rest.starts_with("::")))
+      .max_by_key(|(module, _)| module.len())
+      .map_or(self.level, |(_, level)| *level)
+  }
+
+  fn max_level(&self) -> LevelFilter {
+    self.filters.iter().map(|(_, level)| *level).fold(self.level, Ord::max)
+  }
+}
+
+impl Default for LogConfig {
+  fn default() -> Self {
+    LogConfig {
+      level: LevelFilter::Warn,
+      // The engine's own messages are worth seeing, but wgpu's info is every buffer it makes.
+      filters: vec![("basic_game_engine".to_string(), LevelFilter::Info)],
+      buffer_lines: 1000,
+      file: None,
+      max_file_size: 4 << 20,
+      max_files: 3,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogRecord {
+  pub level: Level,
+  pub target: String,
+  pub message: String,
+  pub time: f32, // seconds since logging started
+}
+
+impl LogRecord {
+  pub fn format(&self) -> String {
+    format!("[{:>8.3} {:<5} {}] {}", self.time, self.level, self.target, self.message)
+  }
+}
+
+struct Logger {
+  start: Instant,
+  state: Mutex<LoggerState>,
+}
+
+struct LoggerState {
+  config: LogConfig,
+  buffer: VecDeque<LogRecord>,
+  #[cfg(not(target_arch = "wasm32"))]
+  file: Option<(std::fs::File, u64)>, // and how much has been written to it
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+static INSTALLED: OnceLock<bool> = OnceLock::new(); // whether `LOGGER` is the `log` crate's logger
+
+// Starts logging through the `log` crate's macros with `config`, or changes the config if logging's
+// already started. Called by the engine at start-up. Another logger set beforehand is left alone.
+pub fn init(config: &LogConfig) {
+  let mut config = config.clone();
+  if let Ok(filters) = std::env::var("RUST_LOG") {
+    // With nothing else said, only errors, like `env_logger`.
+    config.level = LevelFilter::Error;
+    config.filters.clear();
+    config.parse_filters(&filters);
+  }
+  let logger = LOGGER.get_or_init(|| Logger {
+    start: Instant::now(),
+    state: Mutex::new(LoggerState {
+      config: LogConfig { level: LevelFilter::Off, filters: Vec::new(), ..LogConfig::default() },
+      buffer: VecDeque::new(),
+      #[cfg(not(target_arch = "wasm32"))]
+      file: None,
+    }),
+  });
+  let max_level = config.max_level();
+  let mut state = logger.state.lock().unwrap();
+  #[cfg(not(target_arch = "wasm32"))]
+  if state.config.file != config.file {
+    state.file = config.file.as_deref().and_then(|path| open_log_file(path).map_err(|err| eprintln!("couldn't open the log file {}: {}", path.display(), err)).ok());
+  }
+  state.config = config;
+  drop(state);
+  if *INSTALLED.get_or_init(|| log::set_logger(logger).is_ok()) {
+    log::set_max_level(max_level);
+  }
+}
+
+// Changes the level for everything without its own filter.
+pub fn set_level(level: LevelFilter) {
+  if let Some(logger) = LOGGER.get() {
+    let mut state = logger.state.lock().unwrap();
+    state.config.level = level;
+    log::set_max_level(state.config.max_level());
+  }
+}
+
+// Up to `count` of the latest records at `level` or more severe, oldest first.
+pub fn recent(count: usize, level: LevelFilter) -> Vec<LogRecord> {
+  let Some(logger) = LOGGER.get() else { return Vec::new() };
+  let state = logger.state.lock().unwrap();
+  let mut records: Vec<LogRecord> = state.buffer.iter().rev().filter(|record| record.level <= level).take(count).cloned().collect();
+  records.reverse();
+  records
+}
+
+impl Log for Logger {
+  fn enabled(&self, metadata: &Metadata) -> bool {
+    metadata.level() <= self.state.lock().unwrap().config.level_for(metadata.target())
+  }
+
+  fn log(&self, record: &Record) {
+    let mut state = self.state.lock().unwrap();
+    if record.level() > state.config.level_for(record.target()) {
+      return;
+    }
+    let record = LogRecord {
+      level: record.level(),
+      target: record.target().to_string(),
+      message: record.args().to_string(),
+      time: self.start.elapsed().as_secs_f32(),
+    };
+    let line = record.format();
+    #[cfg(target_arch = "wasm32")]
+    {
+      let line = wasm_bindgen::JsValue::from_str(&line);
+      match record.level {
+        Level::Error => web_sys::console::error_1(&line),
+        Level::Warn => web_sys::console::warn_1(&line),
+        _ => web_sys::console::log_1(&line),
+      }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+      eprintln!("{}", line);
+      state.write_file(&line);
+    }
+    if state.config.buffer_lines > 0 {
+      while state.buffer.len() >= state.config.buffer_lines {
+        state.buffer.pop_front();
+      }
+      state.buffer.push_back(record);
+    }
+  }
+
+  fn flush(&self) {
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some((file, _)) = self.state.lock().unwrap().file.as_mut() {
+      use std::io::Write;
+      let _ = file.flush();
+    }
+  }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl LoggerState {
+  fn write_file(&mut self, line: &str) {
+    use std::io::Write;
+    let Some((file, written)) = self.file.as_mut() else { return };
+    if writeln!(file, "{}", line).is_ok() {
+      *written += line.len() as u64 + 1;
+    }
+    if *written < self.config.max_file_size {
+      return;
+    }
+    let Some(path) = self.config.file.clone() else { return };
+    self.file = None;
+    rotate(&path, self.config.max_files);
+    self.file = open_log_file(&path).map_err(|err| eprintln!("couldn't open the log file {}: {}", path.display(), err)).ok();
+  }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn open_log_file(path: &std::path::Path) -> std::io::Result<(std::fs::File, u64)> {
+  if let Some(directory) = path.parent().filter(|directory| !directory.as_os_str().is_empty()) {
+    std::fs::create_dir_all(directory)?;
+  }
+  let file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+  let written = file.metadata()?.len();
+  Ok((file, written))
+}
+
+// Moves `game.log` to `game.1.log`, `game.1.log` to `game.2.log` and so on, dropping the oldest.
+#[cfg(not(target_arch = "wasm32"))]
+fn rotate(path: &std::path::Path, max_files: usize) {
+  let numbered = |number: usize| {
+    let stem = path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+    let name = match path.extension() {
+      Some(extension) => format!("{}.{}.{}", stem, number, extension.to_string_lossy()),
+      None => format!("{}.{}", stem, number),
+    };
+    path.with_file_name(name)
+  };
+  if max_files == 0 {
+    let _ = std::fs::remove_file(path);
+    return;
+  }
+  let _ = std::fs::remove_file(numbered(max_files));
+  for number in (1..max_files).rev() {
+    let _ = std::fs::rename(numbered(number), numbered(number + 1));
+  }
+  let _ = std::fs::rename(path, numbered(1));
+}
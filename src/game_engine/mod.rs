@@ -1,9 +1,47 @@
-mod taskqueue;
+pub mod actions;
+pub mod assets;
+pub mod audio;
+pub mod collision;
+pub mod console;
+pub mod cvars;
+pub mod ecs;
+pub mod taskqueue;
 mod engine;
-mod graphics;
+mod error;
+pub mod fixed;
+pub mod gamepad;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod gamepad_backend;
+pub mod graphics;
+pub mod input;
+pub mod jobs;
+pub mod lockstep;
+pub mod logging;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod net;
+pub mod noise;
+pub mod physics;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod plugin;
+pub mod prefab;
+pub mod profiler;
+pub mod random;
+pub mod replay;
+pub mod save;
+pub mod scene;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod scripting;
+mod stats;
+pub mod terrain;
+pub mod tilemap;
+pub mod time;
+pub mod ui;
+pub mod window;
 
 pub use self::{
   engine::*,
+  error::*,
+  stats::*,
   taskqueue::*,
   graphics::*
 };
@@ -0,0 +1,275 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::{Duration, Instant};
+use laminar::{Socket, SocketEvent};
+
+use crate::game_engine::ecs::{with_entity_map, Entity, EntityMap, Transform, TypeRegistry, World};
+use crate::game_engine::error::EngineError;
+use super::protocol::{decode, message_packet, socket_config, user_packet, Channel, ClientId, Message, Payload, MAX_MESSAGE_BYTES, PROTOCOL_VERSION};
+use super::replication::{Components, Snapshot};
+
+// How often to say hello while waiting to be let in, and how long to keep trying.
+const HELLO_INTERVAL: Duration = Duration::from_millis(250);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// How many snapshots are kept, to interpolate between and to rebuild deltas from.
+const BUFFERED_SNAPSHOTS: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientEvent {
+  Connected(ClientId),
+  Rejected(String), // by the server, or because it never answered
+  Disconnected,
+  Received(Vec<u8>), // a message sent with `NetServer::send` or `broadcast`
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+  Connecting,
+  Connected(ClientId),
+  Disconnected,
+}
+
+// The other end of a `NetServer`, as a world resource. Replicated entities are spawned into the
+// client's world and kept in step with the server's, drawn `interpolation_delay` seconds behind the
+// newest snapshot so there's always a later one to blend `Transform`s towards.
+pub struct NetClient {
+  pub interpolation_delay: f32,
+  socket: Socket,
+  server: SocketAddr,
+  state: ConnectionState,
+  started: Instant,
+  last_hello: Option<Instant>,
+  tick_rate: f32,
+  snapshots: VecDeque<Snapshot>, // oldest first
+  render_tick: Option<f64>, // where between snapshots the world is being shown
+  entities: HashMap<Entity, Entity>, // server ids to the client's entities
+  applied: Option<Snapshot>, // what's been put in the world, besides interpolation
+  events: Vec<ClientEvent>,
+}
+
+impl NetClient {
+  // Starts connecting to the server at `address`, e.g. "127.0.0.1:7777".
+  pub fn connect(address: impl ToSocketAddrs) -> Result<Self, EngineError> {
+    let server = address.to_socket_addrs().map_err(|err| EngineError::Network(err.to_string()))?
+      .next().ok_or_else(|| EngineError::Network("no address to connect to".to_string()))?;
+    let local = if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    let socket = Socket::bind_with_config(local, socket_config()).map_err(|err| EngineError::Network(err.to_string()))?;
+    Ok(NetClient {
+      interpolation_delay: 0.1,
+      socket,
+      server,
+      state: ConnectionState::Connecting,
+      started: Instant::now(),
+      last_hello: None,
+      tick_rate: 20.0,
+      snapshots: VecDeque::new(),
+      render_tick: None,
+      entities: HashMap::new(),
+      applied: None,
+      events: Vec::new(),
+    })
+  }
+
+  pub fn state(&self) -> ConnectionState {
+    self.state
+  }
+
+  pub fn id(&self) -> Option<ClientId> {
+    match self.state {
+      ConnectionState::Connected(id) => Some(id),
+      _ => None,
+    }
+  }
+
+  // The client's copy of a replicated entity, from its id on the server.
+  pub fn local_entity(&self, server: Entity) -> Option<Entity> {
+    self.entities.get(&server).copied()
+  }
+
+  pub fn send(&mut self, channel: Channel, data: &[u8]) {
+    if self.id().is_some() {
+      self.send_packet(user_packet(self.server, channel, data));
+    }
+  }
+
+  // Tells the server we're leaving. Replicated entities stay in the world.
+  pub fn disconnect(&mut self) {
+    if self.state != ConnectionState::Disconnected {
+      self.send_message(Channel::Reliable, &Message::Goodbye);
+      self.socket.manual_poll(Instant::now());
+      self.state = ConnectionState::Disconnected;
+      self.events.push(ClientEvent::Disconnected);
+    }
+  }
+
+  pub fn take_events(&mut self) -> Vec<ClientEvent> {
+    std::mem::take(&mut self.events)
+  }
+
+  // Handles whatever's arrived, then brings the replicated entities up to date.
+  pub fn update(&mut self, world: &mut World, registry: &TypeRegistry, delta: f32) {
+    if self.state == ConnectionState::Disconnected {
+      return;
+    }
+    let now = Instant::now();
+    if self.state == ConnectionState::Connecting {
+      if now.duration_since(self.started) > CONNECT_TIMEOUT {
+        self.state = ConnectionState::Disconnected;
+        self.events.push(ClientEvent::Rejected("the server didn't answer".to_string()));
+        return;
+      }
+      if self.last_hello.is_none_or(|last| now.duration_since(last) >= HELLO_INTERVAL) {
+        self.last_hello = Some(now);
+        self.send_message(Channel::Unreliable, &Message::Hello { version: PROTOCOL_VERSION });
+      }
+    }
+
+    self.socket.manual_poll(now);
+    while let Some(event) = self.socket.recv() {
+      match event {
+        SocketEvent::Packet(packet) if packet.addr() == self.server => match decode(packet.payload(), MAX_MESSAGE_BYTES) {
+          Ok(payload) => self.receive(payload),
+          Err(err) => log::warn!("bad packet from the server: {}", err),
+        },
+        SocketEvent::Timeout(address) | SocketEvent::Disconnect(address) if address == self.server && self.id().is_some() => {
+          self.state = ConnectionState::Disconnected;
+          self.events.push(ClientEvent::Disconnected);
+        }
+        _ => {}
+      }
+    }
+    self.socket.manual_poll(Instant::now());
+    self.interpolate(world, registry, delta);
+  }
+
+  fn receive(&mut self, payload: Payload) {
+    match payload {
+      Payload::Message(Message::Welcome { client, tick_rate }) if self.state == ConnectionState::Connecting => {
+        self.state = ConnectionState::Connected(client);
+        self.tick_rate = tick_rate;
+        self.events.push(ClientEvent::Connected(client));
+      }
+      Payload::Message(Message::Rejected { reason }) if self.state == ConnectionState::Connecting => {
+        self.state = ConnectionState::Disconnected;
+        self.events.push(ClientEvent::Rejected(reason));
+      }
+      Payload::Message(Message::Goodbye) => {
+        self.state = ConnectionState::Disconnected;
+        self.events.push(ClientEvent::Disconnected);
+      }
+      Payload::Message(Message::Snapshot(delta)) if self.id().is_some() => {
+        if self.snapshots.back().is_some_and(|newest| newest.tick >= delta.tick) {
+          return;
+        }
+        let baseline = delta.baseline.and_then(|tick| self.snapshots.iter().find(|snapshot| snapshot.tick == tick));
+        match Snapshot::apply(baseline, delta) {
+          Ok(snapshot) => {
+            self.send_message(Channel::Unreliable, &Message::Ack { tick: snapshot.tick });
+            if self.snapshots.len() == BUFFERED_SNAPSHOTS {
+              self.snapshots.pop_front();
+            }
+            self.snapshots.push_back(snapshot);
+          }
+          // Waits for the server to notice and send one against an older baseline, or a whole one.
+          Err(err) => log::debug!("dropped a snapshot: {}", err),
+        }
+      }
+      Payload::User(data) if self.id().is_some() => self.events.push(ClientEvent::Received(data)),
+      _ => {}
+    }
+  }
+
+  fn interpolate(&mut self, world: &mut World, registry: &TypeRegistry, delta: f32) {
+    let (Some(oldest), Some(newest)) = (self.snapshots.front(), self.snapshots.back()) else { return };
+    // Runs a clock in ticks that trails the newest snapshot, nudged back on course when packets
+    // bunch up or arrive late, and jumped if it's far off.
+    let delay = (self.interpolation_delay * self.tick_rate) as f64;
+    let target = newest.tick as f64 - delay;
+    let tick = match self.render_tick {
+      Some(tick) => {
+        let tick = tick + (delta * self.tick_rate) as f64;
+        if (tick - target).abs() > delay.max(1.0) * 2.0 { target } else { tick + (target - tick) * 0.05 }
+      }
+      None => target,
+    };
+    let tick = tick.clamp(oldest.tick as f64, newest.tick as f64);
+    self.render_tick = Some(tick);
+
+    let from = self.snapshots.iter().rev().find(|snapshot| snapshot.tick as f64 <= tick).unwrap_or(oldest).clone();
+    let to = self.snapshots.iter().find(|snapshot| snapshot.tick as f64 > tick);
+    let blend = to.map(|to| ((tick - from.tick as f64) / (to.tick - from.tick) as f64) as f32);
+    let transforms: Vec<(Entity, Transform, Transform)> = match (to, blend, registry.get_by_type::<Transform>()) {
+      (Some(to), Some(_), Some(info)) => from.entities.iter().filter_map(|(entity, components)| {
+        let a = components.get(info.name)?.clone().into_rust().ok()?;
+        let b = to.entities.get(entity)?.get(info.name)?.clone().into_rust().ok()?;
+        Some((*entity, a, b))
+      }).collect(),
+      _ => Vec::new(),
+    };
+
+    if self.applied.as_ref().is_none_or(|applied| applied.tick != from.tick) {
+      self.apply(world, registry, from);
+    }
+    if let Some(blend) = blend {
+      for (entity, a, b) in transforms {
+        if let Some(local) = self.entities.get(&entity) {
+          let transform = Transform { translation: a.translation.lerp(b.translation, blend), rotation: a.rotation.slerp(b.rotation, blend), scale: a.scale.lerp(b.scale, blend) };
+          world.insert(*local, transform);
+        }
+      }
+    }
+  }
+
+  // Makes the world's replicated entities match `snapshot`, touching only what changed since the
+  // last one applied.
+  fn apply(&mut self, world: &mut World, registry: &TypeRegistry, snapshot: Snapshot) {
+    let applied = self.applied.take().unwrap_or_default();
+    for entity in applied.entities.keys().filter(|entity| !snapshot.entities.contains_key(*entity)) {
+      if let Some(local) = self.entities.remove(entity) {
+        world.despawn(local);
+      }
+    }
+    for entity in snapshot.entities.keys() {
+      if !self.entities.get(entity).is_some_and(|local| world.is_alive(*local)) {
+        self.entities.insert(*entity, world.spawn());
+      }
+    }
+
+    let empty = Components::new();
+    with_entity_map(EntityMap { ids: self.entities.clone(), ..EntityMap::default() }, || {
+      for (entity, components) in &snapshot.entities {
+        let local = self.entities[entity];
+        let old = applied.entities.get(entity).unwrap_or(&empty);
+        for (name, value) in components.iter().filter(|(name, value)| old.get(*name) != Some(*value)) {
+          match registry.get(name) {
+            Some(info) => {
+              if let Err(err) = info.insert(world, local, value.clone()) {
+                log::warn!("bad replicated {} component: {}", name, err);
+              }
+            }
+            None => log::warn!("skipping replicated component {}, which isn't registered", name),
+          }
+        }
+        for name in old.keys().filter(|name| !components.contains_key(*name)) {
+          if let Some(info) = registry.get(name) {
+            info.remove(world, local);
+          }
+        }
+      }
+    });
+    self.applied = Some(snapshot);
+  }
+
+  fn send_message(&mut self, channel: Channel, message: &Message) {
+    match message_packet(self.server, channel, message) {
+      Ok(packet) => self.send_packet(packet),
+      Err(err) => log::warn!("couldn't encode a message for the server: {}", err),
+    }
+  }
+
+  fn send_packet(&mut self, packet: laminar::Packet) {
+    if let Err(err) = self.socket.send(packet) {
+      log::warn!("couldn't send to the server: {}", err);
+    }
+  }
+}
@@ -0,0 +1,27 @@
+mod client;
+mod protocol;
+mod replication;
+mod server;
+
+pub use self::{
+  client::*,
+  protocol::*,
+  replication::*,
+  server::*
+};
+
+use super::ecs::{TypeRegistry, World};
+
+// Runs the active world's `NetServer` and `NetClient`, if it has them. The engine calls this every
+// frame after the schedule.
+pub fn update_network(world: &mut World, registry: &TypeRegistry, delta: f32) {
+  // Taken out of the world while they run, since they need it too.
+  if let Some(mut server) = world.remove_resource::<NetServer>() {
+    server.update(world, registry, delta);
+    world.insert_resource(server);
+  }
+  if let Some(mut client) = world.remove_resource::<NetClient>() {
+    client.update(world, registry, delta);
+    world.insert_resource(client);
+  }
+}
@@ -0,0 +1,125 @@
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::time::Duration;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use laminar::{Config, Packet};
+use serde::{Deserialize, Serialize};
+
+use super::replication::SnapshotDelta;
+
+// Changes whenever messages change, so old clients are turned away instead of misreading them.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// Unreliable packets bigger than this are sent reliably instead, since they can't be split up.
+const MAX_UNRELIABLE_SIZE: usize = 1200;
+
+// Engine messages that inflate past this are refused, so a small packet can't make the receiver
+// inflate and parse megabytes.
+pub(crate) const MAX_MESSAGE_BYTES: u64 = 1 << 20;
+// Plenty for a `Hello`, the only message read from an address that hasn't sent one yet.
+pub(crate) const MAX_HELLO_BYTES: u64 = 64;
+
+// The first byte of every packet says what follows.
+const USER: u8 = 0;
+const ENGINE: u8 = 1;
+
+// A client as the server numbers them, in the order they connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ClientId(pub u32);
+
+// How a message is delivered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+  Reliable, // resent until it arrives, and in the order it was sent
+  Unreliable, // sent once; may not arrive, and anything older than the last to arrive is dropped
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) enum Message {
+  Hello { version: u32 },
+  Welcome { client: ClientId, tick_rate: f32 },
+  Rejected { reason: String },
+  Snapshot(SnapshotDelta),
+  Ack { tick: u32 },
+  Goodbye,
+}
+
+pub(crate) enum Payload {
+  Message(Message),
+  User(Vec<u8>),
+}
+
+pub(crate) fn socket_config() -> Config {
+  Config { heartbeat_interval: Some(Duration::from_millis(500)), ..Config::default() }
+}
+
+pub(crate) fn user_packet(address: SocketAddr, channel: Channel, data: &[u8]) -> Packet {
+  let mut payload = Vec::with_capacity(data.len() + 1);
+  payload.push(USER);
+  payload.extend_from_slice(data);
+  packet(address, channel, payload)
+}
+
+// Engine messages are compressed RON.
+pub(crate) fn message_packet(address: SocketAddr, channel: Channel, message: &Message) -> Result<Packet, String> {
+  let text = ron::to_string(message).map_err(|err| err.to_string())?;
+  let mut encoder = DeflateEncoder::new(vec![ENGINE], Compression::fast());
+  let payload = encoder.write_all(text.as_bytes()).and_then(|_| encoder.finish()).map_err(|err| err.to_string())?;
+  Ok(packet(address, channel, payload))
+}
+
+// Whether the packet holds an engine message rather than the game's own data.
+pub(crate) fn is_message(payload: &[u8]) -> bool {
+  payload.first() == Some(&ENGINE)
+}
+
+// Engine messages that inflate to more than `limit` bytes are an error.
+pub(crate) fn decode(payload: &[u8], limit: u64) -> Result<Payload, String> {
+  match payload.split_first() {
+    Some((&USER, data)) => Ok(Payload::User(data.to_vec())),
+    Some((&ENGINE, compressed)) => {
+      let mut text = String::new();
+      DeflateDecoder::new(compressed).take(limit + 1).read_to_string(&mut text).map_err(|err| err.to_string())?;
+      if text.len() as u64 > limit {
+        return Err(format!("message is over {} bytes", limit));
+      }
+      ron::from_str(&text).map(Payload::Message).map_err(|err| err.to_string())
+    }
+    _ => Err("unknown kind of packet".to_string()),
+  }
+}
+
+fn packet(address: SocketAddr, channel: Channel, payload: Vec<u8>) -> Packet {
+  match channel {
+    Channel::Reliable => Packet::reliable_ordered(address, payload, None),
+    Channel::Unreliable if payload.len() <= MAX_UNRELIABLE_SIZE => Packet::unreliable_sequenced(address, payload, None),
+    Channel::Unreliable => Packet::reliable_sequenced(address, payload, None),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn payload(message: &Message) -> Vec<u8> {
+    let address = SocketAddr::from(([127, 0, 0, 1], 0));
+    message_packet(address, Channel::Reliable, message).unwrap().payload().to_vec()
+  }
+
+  #[test]
+  fn a_hello_fits_the_hello_limit() {
+    let hello = payload(&Message::Hello { version: u32::MAX });
+    assert!(matches!(decode(&hello, MAX_HELLO_BYTES), Ok(Payload::Message(Message::Hello { version: u32::MAX }))));
+  }
+
+  #[test]
+  fn messages_past_the_limit_are_refused() {
+    // Compresses to a fraction of its size, but inflates past a megabyte.
+    let rejected = payload(&Message::Rejected { reason: "x".repeat(MAX_MESSAGE_BYTES as usize) });
+    assert!(decode(&rejected, MAX_MESSAGE_BYTES).is_err());
+    assert!(decode(&rejected, MAX_HELLO_BYTES).is_err());
+    assert!(decode(&rejected, MAX_MESSAGE_BYTES * 2).is_ok());
+  }
+}
@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+use ron::Value;
+use serde::{Deserialize, Serialize};
+
+use crate::game_engine::ecs::{Entity, TypeRegistry, World};
+
+// Marks an entity for the server to send to clients. Its registered components are replicated, or
+// just the ones in `NetServer::components`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Replicated;
+
+// An entity's components by registered name.
+pub(crate) type Components = BTreeMap<String, Value>;
+
+// The replicated part of the world at one tick, with entities by their id on the server.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Snapshot {
+  pub tick: u32,
+  pub entities: BTreeMap<Entity, Components>,
+}
+
+// A snapshot as the changes since one the receiver already has, or all of it without a baseline.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SnapshotDelta {
+  pub tick: u32,
+  pub baseline: Option<u32>,
+  pub changed: Vec<(u64, Vec<(String, Value)>)>, // new entities, and new or changed components
+  pub removed: Vec<(u64, Vec<String>)>, // components taken off entities that are still there
+  pub despawned: Vec<u64>,
+}
+
+impl Snapshot {
+  pub fn capture(world: &World, registry: &TypeRegistry, tick: u32, only: Option<&[String]>) -> Result<Snapshot, String> {
+    let mut entities = BTreeMap::new();
+    for entity in world.entities().filter(|entity| world.has::<Replicated>(*entity)) {
+      let components = registry.serialize_entity(world, entity).map_err(|err| err.to_string())?
+        .into_iter()
+        .filter(|(name, _)| only.is_none_or(|only| *name == "Replicated" || only.iter().any(|wanted| wanted == name)))
+        .map(|(name, value)| (name.to_string(), value))
+        .collect();
+      entities.insert(entity, components);
+    }
+    Ok(Snapshot { tick, entities })
+  }
+
+  pub fn diff(&self, baseline: Option<&Snapshot>) -> SnapshotDelta {
+    let empty = BTreeMap::new();
+    let mut delta = SnapshotDelta { tick: self.tick, baseline: baseline.map(|baseline| baseline.tick), changed: Vec::new(), removed: Vec::new(), despawned: Vec::new() };
+    for (entity, components) in &self.entities {
+      let old = baseline.and_then(|baseline| baseline.entities.get(entity)).unwrap_or(&empty);
+      let changed: Vec<(String, Value)> = components.iter()
+        .filter(|(name, value)| old.get(*name) != Some(*value))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+      let removed: Vec<String> = old.keys().filter(|name| !components.contains_key(*name)).cloned().collect();
+      // A new entity is sent even with no components, so it's spawned.
+      let is_new = baseline.is_none_or(|baseline| !baseline.entities.contains_key(entity));
+      if !changed.is_empty() || is_new {
+        delta.changed.push((entity.to_bits(), changed));
+      }
+      if !removed.is_empty() {
+        delta.removed.push((entity.to_bits(), removed));
+      }
+    }
+    if let Some(baseline) = baseline {
+      delta.despawned = baseline.entities.keys().filter(|entity| !self.entities.contains_key(*entity)).map(Entity::to_bits).collect();
+    }
+    delta
+  }
+
+  // Rebuilds the snapshot `delta` was made from, given its baseline.
+  pub fn apply(baseline: Option<&Snapshot>, delta: SnapshotDelta) -> Result<Snapshot, String> {
+    let mut entities = match (delta.baseline, baseline) {
+      (None, _) => BTreeMap::new(),
+      (Some(tick), Some(baseline)) if baseline.tick == tick => baseline.entities.clone(),
+      (Some(tick), _) => return Err(format!("missing the snapshot from tick {}", tick)),
+    };
+    for bits in delta.despawned {
+      entities.remove(&Entity::from_bits(bits));
+    }
+    for (bits, names) in delta.removed {
+      if let Some(components) = entities.get_mut(&Entity::from_bits(bits)) {
+        for name in names {
+          components.remove(&name);
+        }
+      }
+    }
+    for (bits, changed) in delta.changed {
+      entities.entry(Entity::from_bits(bits)).or_default().extend(changed);
+    }
+    Ok(Snapshot { tick: delta.tick, entities })
+  }
+}
@@ -0,0 +1,205 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::Instant;
+use laminar::{Socket, SocketEvent};
+
+use crate::game_engine::ecs::{TypeRegistry, World};
+use crate::game_engine::error::EngineError;
+use super::protocol::{decode, is_message, message_packet, socket_config, user_packet, Channel, ClientId, Message, Payload, MAX_HELLO_BYTES, MAX_MESSAGE_BYTES, PROTOCOL_VERSION};
+use super::replication::Snapshot;
+
+// How many past snapshots are kept to diff against. A client that's further behind than this gets
+// a whole snapshot.
+const HISTORY: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerEvent {
+  Connected(ClientId),
+  Disconnected(ClientId), // left, or stopped answering
+  Received(ClientId, Vec<u8>), // a message sent with `NetClient::send`
+}
+
+struct Connection {
+  id: ClientId,
+  acked: Option<u32>, // the newest snapshot the client has said it got
+}
+
+// The authoritative side of a networked game, as a world resource. Clients connect over UDP with a
+// handshake that checks they speak the same `PROTOCOL_VERSION`. Then, `tick_rate` times a second,
+// the server takes a snapshot of every `Replicated` entity and sends each client what changed since
+// the last snapshot it acknowledged.
+//
+// Games send their own messages with `send` and `broadcast`, and read what arrived, along with
+// clients coming and going, from `take_events`.
+pub struct NetServer {
+  pub tick_rate: f32, // snapshots per second
+  pub max_clients: usize,
+  pub components: Option<Vec<String>>, // the registered components to replicate; `None` is all of them
+  socket: Socket,
+  clients: HashMap<SocketAddr, Connection>,
+  next_client: u32,
+  tick: u32,
+  accumulator: f32,
+  history: VecDeque<Snapshot>,
+  events: Vec<ServerEvent>,
+}
+
+impl NetServer {
+  // Listens on `address`, e.g. "0.0.0.0:7777".
+  pub fn bind(address: impl ToSocketAddrs) -> Result<Self, EngineError> {
+    let socket = Socket::bind_with_config(address, socket_config()).map_err(|err| EngineError::Network(err.to_string()))?;
+    Ok(NetServer {
+      tick_rate: 20.0,
+      max_clients: 16,
+      components: None,
+      socket,
+      clients: HashMap::new(),
+      next_client: 0,
+      tick: 0,
+      accumulator: 0.0,
+      history: VecDeque::new(),
+      events: Vec::new(),
+    })
+  }
+
+  pub fn local_addr(&self) -> Option<SocketAddr> {
+    self.socket.local_addr().ok()
+  }
+
+  pub fn tick(&self) -> u32 {
+    self.tick
+  }
+
+  pub fn clients(&self) -> impl Iterator<Item = ClientId> + '_ {
+    self.clients.values().map(|connection| connection.id)
+  }
+
+  pub fn send(&mut self, client: ClientId, channel: Channel, data: &[u8]) {
+    if let Some(address) = self.address(client) {
+      self.send_packet(user_packet(address, channel, data));
+    }
+  }
+
+  pub fn broadcast(&mut self, channel: Channel, data: &[u8]) {
+    for address in self.clients.keys().copied().collect::<Vec<_>>() {
+      self.send_packet(user_packet(address, channel, data));
+    }
+  }
+
+  pub fn disconnect(&mut self, client: ClientId) {
+    if let Some(address) = self.address(client) {
+      self.send_message(address, Channel::Reliable, &Message::Goodbye);
+      self.socket.manual_poll(Instant::now());
+      self.clients.remove(&address);
+      self.events.push(ServerEvent::Disconnected(client));
+    }
+  }
+
+  // What's happened since the last call.
+  pub fn take_events(&mut self) -> Vec<ServerEvent> {
+    std::mem::take(&mut self.events)
+  }
+
+  // Handles whatever's arrived and sends a snapshot if one's due.
+  pub fn update(&mut self, world: &World, registry: &TypeRegistry, delta: f32) {
+    self.socket.manual_poll(Instant::now());
+    while let Some(event) = self.socket.recv() {
+      match event {
+        SocketEvent::Packet(packet) => {
+          // Until an address has said hello, nothing but a small engine message could be one, and
+          // anything else is dropped without a warning, so junk traffic can't flood the log.
+          let known = self.clients.contains_key(&packet.addr());
+          if !known && !is_message(packet.payload()) {
+            continue;
+          }
+          match decode(packet.payload(), if known { MAX_MESSAGE_BYTES } else { MAX_HELLO_BYTES }) {
+            Ok(payload) => self.receive(packet.addr(), payload),
+            Err(err) if known => log::warn!("bad packet from {}: {}", packet.addr(), err),
+            Err(err) => log::debug!("ignoring packet from unknown {}: {}", packet.addr(), err),
+          }
+        }
+        SocketEvent::Timeout(address) | SocketEvent::Disconnect(address) => {
+          if let Some(connection) = self.clients.remove(&address) {
+            self.events.push(ServerEvent::Disconnected(connection.id));
+          }
+        }
+        SocketEvent::Connect(_) => {}
+      }
+    }
+
+    let step = 1.0 / self.tick_rate.max(1.0);
+    self.accumulator += delta;
+    if self.accumulator >= step {
+      // After a long frame, skip the ticks that were missed rather than sending several at once.
+      self.accumulator %= step;
+      self.tick += 1;
+      match Snapshot::capture(world, registry, self.tick, self.components.as_deref()) {
+        Ok(snapshot) => self.send_snapshot(snapshot),
+        Err(err) => log::warn!("couldn't take a snapshot: {}", err),
+      }
+    }
+    self.socket.manual_poll(Instant::now());
+  }
+
+  fn receive(&mut self, address: SocketAddr, payload: Payload) {
+    if let Payload::Message(Message::Hello { version }) = payload {
+      let reply = match self.clients.get(&address) {
+        // The welcome was lost, so send it again.
+        Some(connection) => Message::Welcome { client: connection.id, tick_rate: self.tick_rate },
+        None if version != PROTOCOL_VERSION => Message::Rejected { reason: format!("the server is on protocol version {}, not {}", PROTOCOL_VERSION, version) },
+        None if self.clients.len() >= self.max_clients => Message::Rejected { reason: "the server is full".to_string() },
+        None => {
+          let id = ClientId(self.next_client);
+          self.next_client += 1;
+          self.clients.insert(address, Connection { id, acked: None });
+          self.events.push(ServerEvent::Connected(id));
+          Message::Welcome { client: id, tick_rate: self.tick_rate }
+        }
+      };
+      return self.send_message(address, Channel::Reliable, &reply);
+    }
+    // Anything else is only listened to from clients that have said hello.
+    let Some(connection) = self.clients.get_mut(&address) else { return };
+    match payload {
+      Payload::Message(Message::Ack { tick }) => connection.acked = Some(connection.acked.map_or(tick, |acked| acked.max(tick))),
+      Payload::Message(Message::Goodbye) => {
+        self.events.push(ServerEvent::Disconnected(connection.id));
+        self.clients.remove(&address);
+      }
+      Payload::User(data) => self.events.push(ServerEvent::Received(connection.id, data)),
+      _ => {}
+    }
+  }
+
+  fn send_snapshot(&mut self, snapshot: Snapshot) {
+    let deltas: Vec<(SocketAddr, Message)> = self.clients.iter().map(|(address, connection)| {
+      let baseline = connection.acked.and_then(|tick| self.history.iter().find(|old| old.tick == tick));
+      (*address, Message::Snapshot(snapshot.diff(baseline)))
+    }).collect();
+    for (address, message) in deltas {
+      self.send_message(address, Channel::Unreliable, &message);
+    }
+    if self.history.len() == HISTORY {
+      self.history.pop_front();
+    }
+    self.history.push_back(snapshot);
+  }
+
+  fn address(&self, client: ClientId) -> Option<SocketAddr> {
+    self.clients.iter().find(|(_, connection)| connection.id == client).map(|(address, _)| *address)
+  }
+
+  fn send_message(&mut self, address: SocketAddr, channel: Channel, message: &Message) {
+    match message_packet(address, channel, message) {
+      Ok(packet) => self.send_packet(packet),
+      Err(err) => log::warn!("couldn't encode a message for {}: {}", address, err),
+    }
+  }
+
+  fn send_packet(&mut self, packet: laminar::Packet) {
+    let address = packet.addr();
+    if let Err(err) = self.socket.send(packet) {
+      log::warn!("couldn't send to {}: {}", address, err);
+    }
+  }
+}
@@ -0,0 +1,277 @@
+use glam::{IVec2, IVec3, Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::game_engine::random::Rng;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum NoiseKind {
+  #[default]
+  Perlin,
+  Simplex,
+  Worley,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum FractalKind {
+  #[default]
+  Fbm, // plain sum of octaves, for rolling hills and clouds
+  Ridged, // 1 - |n| per octave, for mountain ridges
+  Turbulence, // |n| per octave, for fire and marble
+}
+
+// Layers several octaves of a noise function, each `lacunarity` times the frequency and `gain`
+// times the amplitude of the last. The result is normalised back to the base noise's range.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Fractal {
+  pub kind: FractalKind,
+  pub octaves: u32,
+  pub lacunarity: f32,
+  pub gain: f32,
+}
+
+impl Fractal {
+  pub fn fbm(octaves: u32) -> Self {
+    Fractal { octaves, ..Fractal::default() }
+  }
+
+  pub fn sample(&self, point: Vec3, noise: impl Fn(Vec3) -> f32) -> f32 {
+    let (mut sum, mut total, mut amplitude, mut frequency) = (0.0, 0.0, 1.0, 1.0);
+    for _ in 0..self.octaves.max(1) {
+      let value = noise(point * frequency);
+      sum += amplitude * match self.kind {
+        FractalKind::Fbm => value,
+        FractalKind::Ridged => 1.0 - value.abs(),
+        FractalKind::Turbulence => value.abs(),
+      };
+      total += amplitude;
+      amplitude *= self.gain;
+      frequency *= self.lacunarity;
+    }
+    sum / total
+  }
+}
+
+impl Default for Fractal {
+  fn default() -> Self {
+    Fractal { kind: FractalKind::Fbm, octaves: 4, lacunarity: 2.0, gain: 0.5 }
+  }
+}
+
+// Gradient and cellular noise over a seeded permutation table. Perlin and Simplex return values
+// in roughly [-1, 1]; Worley returns the distance to the nearest feature point, roughly [0, 1].
+#[derive(Debug, Clone)]
+pub struct Noise {
+  seed: u32,
+  permutation: [u8; 512],
+}
+
+impl Noise {
+  pub fn new(seed: u32) -> Self {
+    let mut table: [u8; 256] = std::array::from_fn(|i| i as u8);
+    Rng::new(seed as u64).shuffle(&mut table);
+    let permutation = std::array::from_fn(|i| table[i & 255]);
+    Noise { seed, permutation }
+  }
+
+  // Seeded from the engine's `Rng` resource, so noise is reproducible along with everything else.
+  pub fn from_rng(rng: &mut Rng) -> Self {
+    Noise::new(rng.next_u32())
+  }
+
+  pub fn seed(&self) -> u32 {
+    self.seed
+  }
+
+  // The 512-entry permutation table, as uploaded for `GpuNoise`.
+  pub fn permutation(&self) -> &[u8; 512] {
+    &self.permutation
+  }
+
+  pub fn sample2(&self, kind: NoiseKind, point: Vec2) -> f32 {
+    match kind {
+      NoiseKind::Perlin => self.perlin2(point),
+      NoiseKind::Simplex => self.simplex2(point),
+      NoiseKind::Worley => self.worley2(point),
+    }
+  }
+
+  pub fn sample3(&self, kind: NoiseKind, point: Vec3) -> f32 {
+    match kind {
+      NoiseKind::Perlin => self.perlin3(point),
+      NoiseKind::Simplex => self.simplex3(point),
+      NoiseKind::Worley => self.worley3(point),
+    }
+  }
+
+  pub fn fractal2(&self, kind: NoiseKind, fractal: &Fractal, point: Vec2) -> f32 {
+    fractal.sample(point.extend(0.0), |point| self.sample2(kind, point.truncate()))
+  }
+
+  pub fn fractal3(&self, kind: NoiseKind, fractal: &Fractal, point: Vec3) -> f32 {
+    fractal.sample(point, |point| self.sample3(kind, point))
+  }
+
+  fn hash(&self, i: i32) -> usize {
+    self.permutation[(i & 255) as usize] as usize
+  }
+
+  pub fn perlin2(&self, point: Vec2) -> f32 {
+    let cell = point.floor();
+    let (x, y) = (cell.x as i32, cell.y as i32);
+    let local = point - cell;
+    let (u, v) = (fade(local.x), fade(local.y));
+
+    let corner = |dx: i32, dy: i32| {
+      let hash = self.hash(x + dx + self.hash(y + dy) as i32);
+      gradient2(hash, local.x - dx as f32, local.y - dy as f32)
+    };
+    lerp(v, lerp(u, corner(0, 0), corner(1, 0)), lerp(u, corner(0, 1), corner(1, 1)))
+  }
+
+  pub fn perlin3(&self, point: Vec3) -> f32 {
+    let cell = point.floor();
+    let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+    let local = point - cell;
+    let (u, v, w) = (fade(local.x), fade(local.y), fade(local.z));
+
+    let corner = |dx: i32, dy: i32, dz: i32| {
+      let hash = self.hash(x + dx + self.hash(y + dy + self.hash(z + dz) as i32) as i32);
+      gradient3(hash, local.x - dx as f32, local.y - dy as f32, local.z - dz as f32)
+    };
+    lerp(w,
+      lerp(v, lerp(u, corner(0, 0, 0), corner(1, 0, 0)), lerp(u, corner(0, 1, 0), corner(1, 1, 0))),
+      lerp(v, lerp(u, corner(0, 0, 1), corner(1, 0, 1)), lerp(u, corner(0, 1, 1), corner(1, 1, 1))))
+  }
+
+  pub fn simplex2(&self, point: Vec2) -> f32 {
+    const SKEW: f32 = 0.366_025_42; // (sqrt(3) - 1) / 2
+    const UNSKEW: f32 = 0.211_324_87; // (3 - sqrt(3)) / 6
+
+    let skewed = (point + Vec2::splat((point.x + point.y) * SKEW)).floor();
+    let origin = skewed - Vec2::splat((skewed.x + skewed.y) * UNSKEW);
+    let d0 = point - origin;
+    let step = if d0.x > d0.y { Vec2::X } else { Vec2::Y };
+    let d1 = d0 - step + Vec2::splat(UNSKEW);
+    let d2 = d0 - Vec2::ONE + Vec2::splat(2.0 * UNSKEW);
+
+    let (i, j) = (skewed.x as i32, skewed.y as i32);
+    let contribution = |offset: Vec2, d: Vec2| {
+      let t = 0.5 - d.length_squared();
+      if t < 0.0 {
+        return 0.0;
+      }
+      let hash = self.hash(i + offset.x as i32 + self.hash(j + offset.y as i32) as i32);
+      t * t * t * t * gradient3(hash % 12, d.x, d.y, 0.0)
+    };
+    70.0 * (contribution(Vec2::ZERO, d0) + contribution(step, d1) + contribution(Vec2::ONE, d2))
+  }
+
+  pub fn simplex3(&self, point: Vec3) -> f32 {
+    const SKEW: f32 = 1.0 / 3.0;
+    const UNSKEW: f32 = 1.0 / 6.0;
+
+    let skewed = (point + Vec3::splat((point.x + point.y + point.z) * SKEW)).floor();
+    let origin = skewed - Vec3::splat((skewed.x + skewed.y + skewed.z) * UNSKEW);
+    let d0 = point - origin;
+
+    // Which of the six tetrahedra in the skewed cube the point is in.
+    let (step1, step2) = match (d0.x >= d0.y, d0.y >= d0.z, d0.x >= d0.z) {
+      (true, true, _) => (Vec3::X, Vec3::X + Vec3::Y),
+      (true, false, true) => (Vec3::X, Vec3::X + Vec3::Z),
+      (true, false, false) => (Vec3::Z, Vec3::X + Vec3::Z),
+      (false, false, _) => (Vec3::Z, Vec3::Y + Vec3::Z),
+      (false, true, false) => (Vec3::Y, Vec3::Y + Vec3::Z),
+      (false, true, true) => (Vec3::Y, Vec3::X + Vec3::Y),
+    };
+    let d1 = d0 - step1 + Vec3::splat(UNSKEW);
+    let d2 = d0 - step2 + Vec3::splat(2.0 * UNSKEW);
+    let d3 = d0 - Vec3::ONE + Vec3::splat(3.0 * UNSKEW);
+
+    let cell = skewed.as_ivec3();
+    let contribution = |offset: Vec3, d: Vec3| {
+      let t = 0.6 - d.length_squared();
+      if t < 0.0 {
+        return 0.0;
+      }
+      let corner = cell + offset.as_ivec3();
+      let hash = self.hash(corner.x + self.hash(corner.y + self.hash(corner.z) as i32) as i32);
+      t * t * t * t * gradient3(hash % 12, d.x, d.y, d.z)
+    };
+    32.0 * (contribution(Vec3::ZERO, d0) + contribution(step1, d1) + contribution(step2, d2) + contribution(Vec3::ONE, d3))
+  }
+
+  pub fn worley2(&self, point: Vec2) -> f32 {
+    let cell = point.floor().as_ivec2();
+    let mut nearest = f32::MAX;
+    for dy in -1..=1 {
+      for dx in -1..=1 {
+        let neighbour = cell + IVec2::new(dx, dy);
+        let hash = cell_hash(self.seed, neighbour.x, neighbour.y, 0);
+        let feature = neighbour.as_vec2() + Vec2::new(unit(hash), unit(hash >> 8));
+        nearest = nearest.min(feature.distance_squared(point));
+      }
+    }
+    nearest.sqrt()
+  }
+
+  pub fn worley3(&self, point: Vec3) -> f32 {
+    let cell = point.floor().as_ivec3();
+    let mut nearest = f32::MAX;
+    for dz in -1..=1 {
+      for dy in -1..=1 {
+        for dx in -1..=1 {
+          let neighbour = cell + IVec3::new(dx, dy, dz);
+          let hash = cell_hash(self.seed, neighbour.x, neighbour.y, neighbour.z);
+          let feature = neighbour.as_vec3() + Vec3::new(unit(hash), unit(hash >> 8), unit(hash >> 16));
+          nearest = nearest.min(feature.distance_squared(point));
+        }
+      }
+    }
+    nearest.sqrt()
+  }
+}
+
+fn fade(t: f32) -> f32 {
+  t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+  a + t * (b - a)
+}
+
+// Dot product with one of eight 2D gradient directions.
+fn gradient2(hash: usize, x: f32, y: f32) -> f32 {
+  match hash & 7 {
+    0 => x + y,
+    1 => -x + y,
+    2 => x - y,
+    3 => -x - y,
+    4 => x,
+    5 => -x,
+    6 => y,
+    _ => -y,
+  }
+}
+
+// Ken Perlin's improved-noise gradients: the twelve cube edge midpoints.
+fn gradient3(hash: usize, x: f32, y: f32, z: f32) -> f32 {
+  let h = hash & 15;
+  let u = if h < 8 { x } else { y };
+  let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+  (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+fn pcg_hash(input: u32) -> u32 {
+  let state = input.wrapping_mul(747796405).wrapping_add(2891336453);
+  let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+  (word >> 22) ^ word
+}
+
+fn cell_hash(seed: u32, x: i32, y: i32, z: i32) -> u32 {
+  pcg_hash(x as u32 ^ pcg_hash(y as u32 ^ pcg_hash(z as u32 ^ seed)))
+}
+
+fn unit(hash: u32) -> f32 {
+  (hash & 255) as f32 / 255.0
+}
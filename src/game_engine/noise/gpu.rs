@@ -0,0 +1,380 @@
+use std::borrow::Cow;
+use bytemuck::{Pod, Zeroable};
+use glam::{UVec2, Vec2, Vec3};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, Buffer, BufferAddress, BufferBindingType, BufferDescriptor, BufferUsages, CommandEncoderDescriptor, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor, Device, PipelineLayoutDescriptor, Queue, ShaderModuleDescriptor, ShaderSource, ShaderStages};
+
+use super::generator::{Fractal, FractalKind, Noise, NoiseKind};
+
+// What to fill a `GpuNoise` grid with. Texel (x, y) samples the noise at
+// `(origin + (x, y, 0) * step) * frequency`.
+#[derive(Debug, Clone, Copy)]
+pub struct NoiseGrid {
+  pub kind: NoiseKind,
+  pub fractal: Option<Fractal>,
+  pub size: UVec2,
+  pub origin: Vec3,
+  pub step: Vec2,
+  pub frequency: f32,
+  pub three_dimensional: bool, // samples `sample3` instead of `sample2`, with z taken from `origin`
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct NoiseParams {
+  origin: [f32; 4], // xyz, frequency
+  step: [f32; 4], // xy, lacunarity, gain
+  size: [u32; 4], // width, height, kind, fractal kind (0 = none)
+  extra: [u32; 4], // octaves, dimensions, seed, unused
+}
+
+const WORKGROUP_SIZE: u32 = 8;
+
+// Evaluates the same noise as `Noise` in a compute shader, writing one `f32` per texel into a
+// storage buffer, for generating terrain heightmaps and textures without a CPU round trip.
+pub struct GpuNoise {
+  pipeline: ComputePipeline,
+  bind_group_layout: BindGroupLayout,
+}
+
+impl GpuNoise {
+  pub fn new(device: &Device) -> Self {
+    let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+      label: Some("noise-shader"),
+      source: ShaderSource::Wgsl(Cow::Borrowed(
+"
+struct Params {
+    origin: vec4<f32>,
+    step: vec4<f32>,
+    size: vec4<u32>,
+    extra: vec4<u32>,
+};
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> permutation: array<u32>;
+@group(0) @binding(2) var<storage, read_write> output: array<f32>;
+
+fn hash(i: i32) -> i32 {
+    return i32(permutation[u32(i & 255)]);
+}
+
+fn fade(t: f32) -> f32 {
+    return t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+}
+
+fn gradient2(h: i32, x: f32, y: f32) -> f32 {
+    switch (h & 7) {
+        case 0: { return x + y; }
+        case 1: { return -x + y; }
+        case 2: { return x - y; }
+        case 3: { return -x - y; }
+        case 4: { return x; }
+        case 5: { return -x; }
+        case 6: { return y; }
+        default: { return -y; }
+    }
+}
+
+fn gradient3(hash: i32, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    var u = y;
+    if (h < 8) { u = x; }
+    var v = z;
+    if (h < 4) { v = y; } else if (h == 12 || h == 14) { v = x; }
+    if ((h & 1) != 0) { u = -u; }
+    if ((h & 2) != 0) { v = -v; }
+    return u + v;
+}
+
+fn perlin2(p: vec2<f32>) -> f32 {
+    let cell = floor(p);
+    let x = i32(cell.x);
+    let y = i32(cell.y);
+    let l = p - cell;
+    let u = fade(l.x);
+    let v = fade(l.y);
+    let c00 = gradient2(hash(x + hash(y)), l.x, l.y);
+    let c10 = gradient2(hash(x + 1 + hash(y)), l.x - 1.0, l.y);
+    let c01 = gradient2(hash(x + hash(y + 1)), l.x, l.y - 1.0);
+    let c11 = gradient2(hash(x + 1 + hash(y + 1)), l.x - 1.0, l.y - 1.0);
+    return mix(mix(c00, c10, u), mix(c01, c11, u), v);
+}
+
+fn perlin3_corner(c: vec3<i32>, d: vec3<i32>, l: vec3<f32>) -> f32 {
+    let h = hash(c.x + d.x + hash(c.y + d.y + hash(c.z + d.z)));
+    let o = l - vec3<f32>(d);
+    return gradient3(h, o.x, o.y, o.z);
+}
+
+fn perlin3(p: vec3<f32>) -> f32 {
+    let cell = floor(p);
+    let c = vec3<i32>(cell);
+    let l = p - cell;
+    let u = fade(l.x);
+    let v = fade(l.y);
+    let w = fade(l.z);
+    let x00 = mix(perlin3_corner(c, vec3<i32>(0, 0, 0), l), perlin3_corner(c, vec3<i32>(1, 0, 0), l), u);
+    let x10 = mix(perlin3_corner(c, vec3<i32>(0, 1, 0), l), perlin3_corner(c, vec3<i32>(1, 1, 0), l), u);
+    let x01 = mix(perlin3_corner(c, vec3<i32>(0, 0, 1), l), perlin3_corner(c, vec3<i32>(1, 0, 1), l), u);
+    let x11 = mix(perlin3_corner(c, vec3<i32>(0, 1, 1), l), perlin3_corner(c, vec3<i32>(1, 1, 1), l), u);
+    return mix(mix(x00, x10, v), mix(x01, x11, v), w);
+}
+
+fn simplex2_corner(cell: vec2<i32>, offset: vec2<i32>, d: vec2<f32>) -> f32 {
+    let t = 0.5 - dot(d, d);
+    if (t < 0.0) { return 0.0; }
+    let h = hash(cell.x + offset.x + hash(cell.y + offset.y));
+    return t * t * t * t * gradient3(h % 12, d.x, d.y, 0.0);
+}
+
+fn simplex2(p: vec2<f32>) -> f32 {
+    let skew = 0.36602542;
+    let unskew = 0.21132487;
+    let skewed = floor(p + vec2<f32>((p.x + p.y) * skew));
+    let origin = skewed - vec2<f32>((skewed.x + skewed.y) * unskew);
+    let d0 = p - origin;
+    var step = vec2<i32>(0, 1);
+    if (d0.x > d0.y) { step = vec2<i32>(1, 0); }
+    let d1 = d0 - vec2<f32>(step) + vec2<f32>(unskew);
+    let d2 = d0 - vec2<f32>(1.0) + vec2<f32>(2.0 * unskew);
+    let cell = vec2<i32>(skewed);
+    return 70.0 * (simplex2_corner(cell, vec2<i32>(0, 0), d0) + simplex2_corner(cell, step, d1) + simplex2_corner(cell, vec2<i32>(1, 1), d2));
+}
+
+fn simplex3_corner(cell: vec3<i32>, offset: vec3<i32>, d: vec3<f32>) -> f32 {
+    let t = 0.6 - dot(d, d);
+    if (t < 0.0) { return 0.0; }
+    let c = cell + offset;
+    let h = hash(c.x + hash(c.y + hash(c.z)));
+    return t * t * t * t * gradient3(h % 12, d.x, d.y, d.z);
+}
+
+fn simplex3(p: vec3<f32>) -> f32 {
+    let skew = 1.0 / 3.0;
+    let unskew = 1.0 / 6.0;
+    let skewed = floor(p + vec3<f32>((p.x + p.y + p.z) * skew));
+    let origin = skewed - vec3<f32>((skewed.x + skewed.y + skewed.z) * unskew);
+    let d0 = p - origin;
+    var step1: vec3<i32>;
+    var step2: vec3<i32>;
+    if (d0.x >= d0.y) {
+        if (d0.y >= d0.z) { step1 = vec3<i32>(1, 0, 0); step2 = vec3<i32>(1, 1, 0); }
+        else if (d0.x >= d0.z) { step1 = vec3<i32>(1, 0, 0); step2 = vec3<i32>(1, 0, 1); }
+        else { step1 = vec3<i32>(0, 0, 1); step2 = vec3<i32>(1, 0, 1); }
+    } else {
+        if (d0.y < d0.z) { step1 = vec3<i32>(0, 0, 1); step2 = vec3<i32>(0, 1, 1); }
+        else if (d0.x < d0.z) { step1 = vec3<i32>(0, 1, 0); step2 = vec3<i32>(0, 1, 1); }
+        else { step1 = vec3<i32>(0, 1, 0); step2 = vec3<i32>(1, 1, 0); }
+    }
+    let d1 = d0 - vec3<f32>(step1) + vec3<f32>(unskew);
+    let d2 = d0 - vec3<f32>(step2) + vec3<f32>(2.0 * unskew);
+    let d3 = d0 - vec3<f32>(1.0) + vec3<f32>(3.0 * unskew);
+    let cell = vec3<i32>(skewed);
+    return 32.0 * (simplex3_corner(cell, vec3<i32>(0, 0, 0), d0) + simplex3_corner(cell, step1, d1)
+        + simplex3_corner(cell, step2, d2) + simplex3_corner(cell, vec3<i32>(1, 1, 1), d3));
+}
+
+fn pcg_hash(input: u32) -> u32 {
+    let state = input * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn cell_hash(x: i32, y: i32, z: i32) -> u32 {
+    return pcg_hash(bitcast<u32>(x) ^ pcg_hash(bitcast<u32>(y) ^ pcg_hash(bitcast<u32>(z) ^ params.extra.z)));
+}
+
+fn unit(h: u32) -> f32 {
+    return f32(h & 255u) / 255.0;
+}
+
+fn worley2(p: vec2<f32>) -> f32 {
+    let cell = vec2<i32>(floor(p));
+    var nearest = 3.4e38;
+    for (var dy = -1; dy <= 1; dy++) {
+        for (var dx = -1; dx <= 1; dx++) {
+            let n = cell + vec2<i32>(dx, dy);
+            let h = cell_hash(n.x, n.y, 0);
+            let feature = vec2<f32>(n) + vec2<f32>(unit(h), unit(h >> 8u));
+            let d = feature - p;
+            nearest = min(nearest, dot(d, d));
+        }
+    }
+    return sqrt(nearest);
+}
+
+fn worley3(p: vec3<f32>) -> f32 {
+    let cell = vec3<i32>(floor(p));
+    var nearest = 3.4e38;
+    for (var dz = -1; dz <= 1; dz++) {
+        for (var dy = -1; dy <= 1; dy++) {
+            for (var dx = -1; dx <= 1; dx++) {
+                let n = cell + vec3<i32>(dx, dy, dz);
+                let h = cell_hash(n.x, n.y, n.z);
+                let feature = vec3<f32>(n) + vec3<f32>(unit(h), unit(h >> 8u), unit(h >> 16u));
+                let d = feature - p;
+                nearest = min(nearest, dot(d, d));
+            }
+        }
+    }
+    return sqrt(nearest);
+}
+
+fn sample(p: vec3<f32>) -> f32 {
+    let three_d = params.extra.y == 3u;
+    switch (params.size.z) {
+        case 1u: {
+            if (three_d) { return simplex3(p); }
+            return simplex2(p.xy);
+        }
+        case 2u: {
+            if (three_d) { return worley3(p); }
+            return worley2(p.xy);
+        }
+        default: {
+            if (three_d) { return perlin3(p); }
+            return perlin2(p.xy);
+        }
+    }
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.size.x || id.y >= params.size.y) { return; }
+    let p = (params.origin.xyz + vec3<f32>(f32(id.x) * params.step.x, f32(id.y) * params.step.y, 0.0)) * params.origin.w;
+
+    var value = 0.0;
+    let fractal = params.size.w;
+    if (fractal == 0u) {
+        value = sample(p);
+    } else {
+        var total = 0.0;
+        var amplitude = 1.0;
+        var frequency = 1.0;
+        for (var octave = 0u; octave < max(params.extra.x, 1u); octave++) {
+            let n = sample(p * frequency);
+            var layered = n;
+            if (fractal == 2u) { layered = 1.0 - abs(n); }
+            if (fractal == 3u) { layered = abs(n); }
+            value += amplitude * layered;
+            total += amplitude;
+            amplitude *= params.step.w;
+            frequency *= params.step.z;
+        }
+        value /= total;
+    }
+    output[id.y * params.size.x + id.x] = value;
+}
+"
+      ))
+    });
+
+    let storage = |binding: u32, read_only: bool| BindGroupLayoutEntry {
+      binding,
+      visibility: ShaderStages::COMPUTE,
+      ty: BindingType::Buffer {
+        ty: BufferBindingType::Storage { read_only },
+        has_dynamic_offset: false,
+        min_binding_size: None
+      },
+      count: None
+    };
+    let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("noise-bind-group-layout"),
+      entries: &[
+        BindGroupLayoutEntry {
+          binding: 0,
+          visibility: ShaderStages::COMPUTE,
+          ty: BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None
+          },
+          count: None
+        },
+        storage(1, true),
+        storage(2, false)
+      ]
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+      label: Some("noise-pipeline-layout"),
+      bind_group_layouts: &[&bind_group_layout],
+      push_constant_ranges: &[]
+    });
+
+    let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+      label: Some("noise-pipeline"),
+      layout: Some(&pipeline_layout),
+      module: &shader_module,
+      entry_point: "cs_main"
+    });
+
+    GpuNoise { pipeline, bind_group_layout }
+  }
+
+  // Dispatches the shader and returns the filled storage buffer (`size.x * size.y` floats, row
+  // major). The buffer can be bound directly by later passes or copied out for the CPU.
+  pub fn generate(&self, device: &Device, queue: &Queue, noise: &Noise, grid: &NoiseGrid) -> Buffer {
+    let fractal = grid.fractal.unwrap_or_default();
+    let params = NoiseParams {
+      origin: grid.origin.extend(grid.frequency).to_array(),
+      step: [grid.step.x, grid.step.y, fractal.lacunarity, fractal.gain],
+      size: [
+        grid.size.x,
+        grid.size.y,
+        match grid.kind {
+          NoiseKind::Perlin => 0,
+          NoiseKind::Simplex => 1,
+          NoiseKind::Worley => 2,
+        },
+        match grid.fractal.map(|fractal| fractal.kind) {
+          None => 0,
+          Some(FractalKind::Fbm) => 1,
+          Some(FractalKind::Ridged) => 2,
+          Some(FractalKind::Turbulence) => 3,
+        },
+      ],
+      extra: [fractal.octaves, if grid.three_dimensional { 3 } else { 2 }, noise.seed(), 0],
+    };
+
+    let params_buffer = device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("noise-params"),
+      usage: BufferUsages::UNIFORM,
+      contents: bytemuck::bytes_of(&params)
+    });
+    let permutation: Vec<u32> = noise.permutation().iter().map(|&value| value as u32).collect();
+    let permutation_buffer = device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("noise-permutation"),
+      usage: BufferUsages::STORAGE,
+      contents: bytemuck::cast_slice(&permutation)
+    });
+    let output = device.create_buffer(&BufferDescriptor {
+      label: Some("noise-output"),
+      size: (grid.size.x.max(1) * grid.size.y.max(1)) as BufferAddress * 4,
+      usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+      mapped_at_creation: false
+    });
+
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+      label: Some("noise-bind-group"),
+      layout: &self.bind_group_layout,
+      entries: &[
+        BindGroupEntry { binding: 0, resource: params_buffer.as_entire_binding() },
+        BindGroupEntry { binding: 1, resource: permutation_buffer.as_entire_binding() },
+        BindGroupEntry { binding: 2, resource: output.as_entire_binding() }
+      ]
+    });
+
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: Some("noise-encoder") });
+    {
+      let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor { label: Some("noise-pass") });
+      pass.set_pipeline(&self.pipeline);
+      pass.set_bind_group(0, &bind_group, &[]);
+      pass.dispatch_workgroups(grid.size.x.div_ceil(WORKGROUP_SIZE), grid.size.y.div_ceil(WORKGROUP_SIZE), 1);
+    }
+    queue.submit(std::iter::once(encoder.finish()));
+
+    output
+  }
+}
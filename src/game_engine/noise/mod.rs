@@ -0,0 +1,7 @@
+mod generator;
+mod gpu;
+
+pub use self::{
+  generator::*,
+  gpu::*
+};
@@ -0,0 +1,315 @@
+use glam::{Quat, Vec3};
+use rapier3d::prelude::{ActiveEvents, ColliderBuilder, RigidBodyBuilder, RigidBodyType, SharedShape};
+use serde::{Deserialize, Serialize};
+
+use crate::game_engine::ecs::{Entity, Transform, World};
+use super::convert::{from_vector, to_isometry, to_vector};
+use super::joints::BodyHandle;
+use super::physics_world::PhysicsWorld;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BodyKind {
+  Dynamic, // moved by the simulation
+  Fixed, // never moves
+  Kinematic, // moved by gameplay through its `Transform`, pushing dynamic bodies out of the way
+}
+
+// Puts the entity in the simulation, starting at its `Transform`. For dynamic bodies the simulation
+// writes the result back into the `Transform` and the velocities here after every step; setting
+// either from gameplay moves the body.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RigidBody {
+  pub kind: BodyKind,
+  pub linear_velocity: Vec3,
+  pub angular_velocity: Vec3, // radians per second about each axis
+  pub gravity_scale: f32,
+  pub linear_damping: f32,
+  pub angular_damping: f32,
+  pub lock_rotations: bool, // e.g. for characters that mustn't tip over
+  pub ccd: bool, // continuous collision detection, for small fast bodies
+}
+
+impl RigidBody {
+  pub fn new(kind: BodyKind) -> Self {
+    RigidBody {
+      kind,
+      linear_velocity: Vec3::ZERO,
+      angular_velocity: Vec3::ZERO,
+      gravity_scale: 1.0,
+      linear_damping: 0.0,
+      angular_damping: 0.0,
+      lock_rotations: false,
+      ccd: false,
+    }
+  }
+
+  pub fn dynamic() -> Self {
+    RigidBody::new(BodyKind::Dynamic)
+  }
+
+  pub fn fixed() -> Self {
+    RigidBody::new(BodyKind::Fixed)
+  }
+
+  pub fn kinematic() -> Self {
+    RigidBody::new(BodyKind::Kinematic)
+  }
+
+  pub fn with_velocity(mut self, linear_velocity: Vec3) -> Self {
+    self.linear_velocity = linear_velocity;
+    self
+  }
+
+  fn body_type(&self) -> RigidBodyType {
+    match self.kind {
+      BodyKind::Dynamic => RigidBodyType::Dynamic,
+      BodyKind::Fixed => RigidBodyType::Fixed,
+      BodyKind::Kinematic => RigidBodyType::KinematicPositionBased,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ColliderShape {
+  Ball { radius: f32 },
+  Cuboid { half_extents: Vec3 },
+  Capsule { half_height: f32, radius: f32 }, // upright, along Y
+  Cylinder { half_height: f32, radius: f32 },
+}
+
+// The entity's shape for collisions. Attached to the entity's `RigidBody` if it has one, otherwise
+// a fixed collider at its `Transform`. Sensors report collisions without pushing anything.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Collider {
+  pub shape: ColliderShape,
+  pub offset: Vec3, // from the entity's origin
+  pub friction: f32,
+  pub restitution: f32,
+  pub density: f32,
+  pub sensor: bool,
+}
+
+impl Collider {
+  pub fn new(shape: ColliderShape) -> Self {
+    Collider { shape, offset: Vec3::ZERO, friction: 0.5, restitution: 0.0, density: 1.0, sensor: false }
+  }
+
+  pub fn ball(radius: f32) -> Self {
+    Collider::new(ColliderShape::Ball { radius })
+  }
+
+  pub fn cuboid(half_extents: Vec3) -> Self {
+    Collider::new(ColliderShape::Cuboid { half_extents })
+  }
+
+  pub fn capsule(half_height: f32, radius: f32) -> Self {
+    Collider::new(ColliderShape::Capsule { half_height, radius })
+  }
+
+  pub fn sensor(mut self) -> Self {
+    self.sensor = true;
+    self
+  }
+
+  pub fn with_offset(mut self, offset: Vec3) -> Self {
+    self.offset = offset;
+    self
+  }
+
+  pub fn with_friction(mut self, friction: f32) -> Self {
+    self.friction = friction;
+    self
+  }
+
+  pub fn with_restitution(mut self, restitution: f32) -> Self {
+    self.restitution = restitution;
+    self
+  }
+
+  pub fn with_density(mut self, density: f32) -> Self {
+    self.density = density;
+    self
+  }
+
+  fn to_rapier(&self) -> rapier3d::prelude::Collider {
+    let shape = match self.shape {
+      ColliderShape::Ball { radius } => SharedShape::ball(radius),
+      ColliderShape::Cuboid { half_extents } => SharedShape::cuboid(half_extents.x, half_extents.y, half_extents.z),
+      ColliderShape::Capsule { half_height, radius } => SharedShape::capsule_y(half_height, radius),
+      ColliderShape::Cylinder { half_height, radius } => SharedShape::cylinder(half_height, radius),
+    };
+    ColliderBuilder::new(shape)
+      .translation(to_vector(self.offset))
+      .friction(self.friction)
+      .restitution(self.restitution)
+      .density(self.density)
+      .sensor(self.sensor)
+      .active_events(ActiveEvents::COLLISION_EVENTS)
+      .build()
+  }
+}
+
+// One fixed step of the world's `PhysicsWorld`, if it has one: mirrors `RigidBody`, `Collider` and
+// `Joint` components into it, steps it by `dt` and writes bodies' new positions and velocities back.
+// The engine runs this after each run of its fixed schedule.
+pub fn step_physics(world: &mut World, dt: f32) {
+  if !world.contains_resource::<PhysicsWorld>() {
+    return;
+  }
+  // Like a system, the sync sees changes since its own last run rather than the frame's, so when a
+  // frame runs several fixed steps an edit is applied on the first and not over what rapier has
+  // integrated since.
+  let frame_last_change_tick = world.last_change_tick();
+  let last_sync = world.resource::<PhysicsWorld>().last_sync;
+  if let Some((_, tick)) = last_sync.filter(|(id, _)| *id == world.id()) {
+    world.set_last_change_tick(tick);
+  }
+  sync_bodies(world);
+  super::joints::sync_joints(world);
+  world.resource_mut::<PhysicsWorld>().last_sync = Some((world.id(), world.change_tick()));
+  world.set_last_change_tick(frame_last_change_tick);
+  world.increment_change_tick();
+
+  let mut physics = world.resource_mut::<PhysicsWorld>();
+  physics.step(dt);
+
+  world.query::<(&mut Transform, &mut RigidBody)>().for_each(|entity, (mut transform, mut body)| {
+    let handle = match physics.body_of(entity) {
+      Some(handle) if body.kind != BodyKind::Fixed => handle,
+      _ => return,
+    };
+    let rapier_body = &physics.bodies[handle];
+    // Written without change detection, so only gameplay edits count as moving the body.
+    let transform = transform.bypass_change_detection();
+    transform.translation = from_vector(rapier_body.translation());
+    let rotation = rapier_body.rotation();
+    transform.rotation = Quat::from_xyzw(rotation.i, rotation.j, rotation.k, rotation.w);
+    let body = body.bypass_change_detection();
+    body.linear_velocity = from_vector(rapier_body.linvel());
+    body.angular_velocity = from_vector(rapier_body.angvel());
+  });
+}
+
+// Creates, updates and removes rapier bodies and colliders to match the components.
+fn sync_bodies(world: &World) {
+  let mut physics = world.resource_mut::<PhysicsWorld>();
+  let physics = &mut *physics;
+
+  let stale: Vec<Entity> = physics.entity_bodies()
+    .filter(|&entity| !world.has::<RigidBody>(entity))
+    .collect();
+  for entity in stale {
+    physics.remove_body(entity);
+  }
+  let stale: Vec<Entity> = physics.entity_colliders()
+    .filter(|&entity| !world.has::<Collider>(entity))
+    .collect();
+  for entity in stale {
+    physics.remove_entity_collider(entity);
+  }
+
+  let transform_of = |entity: Entity| world.get::<Transform>(entity).map_or(Transform::IDENTITY, |transform| *transform);
+  // Where a collider without a body sits in the world: the entity's transform, then its offset.
+  let placement_of = |entity: Entity, collider: &Collider| {
+    to_isometry(&transform_of(entity)) * to_isometry(&Transform::from_translation(collider.offset))
+  };
+
+  world.query::<&RigidBody>().for_each(|entity, body| {
+    let transform = transform_of(entity);
+    match physics.body_of(entity) {
+      None => {
+        let rapier_body = RigidBodyBuilder::new(body.body_type())
+          .position(to_isometry(&transform))
+          .build();
+        let handle = physics.insert_body(entity, rapier_body);
+        apply_body(&mut physics.bodies[handle], body);
+        world.commands().entity(entity).insert(BodyHandle(handle));
+      }
+      Some(handle) => {
+        let rapier_body = &mut physics.bodies[handle];
+        if world.is_changed::<RigidBody>(entity) {
+          rapier_body.set_body_type(body.body_type(), true);
+          apply_body(rapier_body, body);
+        }
+        if world.is_changed::<Transform>(entity) {
+          match body.kind {
+            BodyKind::Kinematic => rapier_body.set_next_kinematic_position(to_isometry(&transform)),
+            _ => rapier_body.set_position(to_isometry(&transform), true),
+          }
+        }
+      }
+    }
+  });
+
+  world.query::<&Collider>().for_each(|entity, collider| {
+    let existing = physics.collider_of(entity);
+    // A body added or removed since the collider was made means it has to move over.
+    let parent = physics.body_of(entity);
+    let reparented = existing.is_some_and(|handle| physics.colliders[handle].parent() != parent);
+    match existing {
+      Some(handle) if !world.is_changed::<Collider>(entity) && !reparented => {
+        if parent.is_none() && world.is_changed::<Transform>(entity) {
+          physics.colliders[handle].set_position(placement_of(entity, collider));
+        }
+      }
+      _ => {
+        physics.remove_entity_collider(entity);
+        let mut rapier_collider = collider.to_rapier();
+        if parent.is_none() {
+          rapier_collider.set_position(placement_of(entity, collider));
+        }
+        physics.insert_collider(entity, rapier_collider, parent);
+      }
+    }
+  });
+}
+
+fn apply_body(rapier_body: &mut rapier3d::prelude::RigidBody, body: &RigidBody) {
+  rapier_body.set_linvel(to_vector(body.linear_velocity), true);
+  rapier_body.set_angvel(to_vector(body.angular_velocity), true);
+  rapier_body.set_gravity_scale(body.gravity_scale, true);
+  rapier_body.set_linear_damping(body.linear_damping);
+  rapier_body.set_angular_damping(body.angular_damping);
+  rapier_body.lock_rotations(body.lock_rotations, true);
+  rapier_body.enable_ccd(body.ccd);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn an_edit_is_synced_on_one_step_only() {
+    let mut world = World::new();
+    world.insert_resource(PhysicsWorld::new());
+    let wall = world.spawn();
+    world.insert(wall, Collider::cuboid(Vec3::ONE));
+    step_physics(&mut world, 1.0 / 60.0);
+    let built = world.resource::<PhysicsWorld>().collider_of(wall);
+
+    // Several fixed steps in one frame: only the first should rebuild the edited collider.
+    world.get_mut::<Collider>(wall).unwrap().friction = 0.1;
+    step_physics(&mut world, 1.0 / 60.0);
+    let rebuilt = world.resource::<PhysicsWorld>().collider_of(wall);
+    step_physics(&mut world, 1.0 / 60.0);
+    assert_ne!(rebuilt, built);
+    assert_eq!(world.resource::<PhysicsWorld>().collider_of(wall), rebuilt);
+  }
+
+  #[test]
+  fn a_moved_collider_keeps_its_offset() {
+    let mut world = World::new();
+    world.insert_resource(PhysicsWorld::new());
+    let wall = world.spawn();
+    world.insert(wall, Transform::IDENTITY);
+    world.insert(wall, Collider::cuboid(Vec3::ONE).with_offset(Vec3::new(0.0, 2.0, 0.0)));
+    step_physics(&mut world, 1.0 / 60.0);
+
+    world.get_mut::<Transform>(wall).unwrap().translation = Vec3::new(5.0, 0.0, 0.0);
+    step_physics(&mut world, 1.0 / 60.0);
+    let physics = world.resource::<PhysicsWorld>();
+    let handle = physics.collider_of(wall).unwrap();
+    let position = physics.colliders[handle].translation();
+    assert_eq!(from_vector(position), Vec3::new(5.0, 2.0, 0.0));
+  }
+}
@@ -0,0 +1,180 @@
+use glam::Vec3;
+use rapier3d::control::{CharacterAutostep, CharacterLength, KinematicCharacterController};
+use rapier3d::prelude::{QueryFilter, SharedShape};
+
+use crate::game_engine::ecs::{Transform, World};
+use super::convert::{from_vector, to_isometry, to_vector};
+use super::physics_world::PhysicsWorld;
+
+// A capsule-shaped character moved by sweeping against the physics world instead of being
+// simulated. Gameplay sets `desired_translation` each fixed step, e.g. from `fixed_schedule`;
+// `update_character_controllers` works out how far the character can actually move and writes the
+// result into its `Transform`.
+#[derive(Debug, Clone)]
+pub struct CharacterController {
+  pub half_height: f32, // half the length of the capsule's cylindrical section
+  pub radius: f32,
+  pub up: Vec3,
+  pub offset: f32, // skin width kept between the capsule and the world
+
+  pub max_slope_climb_angle: f32, // radians
+  pub min_slope_slide_angle: f32, // radians
+  pub step_height: Option<f32>, // `None` disables stepping up ledges
+  pub step_min_width: f32,
+  pub snap_to_ground: Option<f32>, // keeps the character glued to the ground going down slopes/steps
+
+  // How long after walking off a ledge a jump still counts, and how early a jump press is
+  // remembered before landing. Both in seconds.
+  pub coyote_time: f32,
+  pub jump_buffer_time: f32,
+
+  pub desired_translation: Vec3,
+
+  grounded: bool,
+  time_since_grounded: f32,
+  time_since_jump_request: Option<f32>,
+  effective_translation: Vec3,
+}
+
+impl CharacterController {
+  pub fn capsule(half_height: f32, radius: f32) -> Self {
+    CharacterController {
+      half_height,
+      radius,
+      up: Vec3::Y,
+      offset: 0.01,
+      max_slope_climb_angle: 45f32.to_radians(),
+      min_slope_slide_angle: 30f32.to_radians(),
+      step_height: Some(0.3),
+      step_min_width: 0.2,
+      snap_to_ground: Some(0.2),
+      coyote_time: 0.1,
+      jump_buffer_time: 0.1,
+      desired_translation: Vec3::ZERO,
+      grounded: false,
+      time_since_grounded: f32::INFINITY,
+      time_since_jump_request: None,
+      effective_translation: Vec3::ZERO,
+    }
+  }
+
+  pub fn is_grounded(&self) -> bool {
+    self.grounded
+  }
+
+  // Grounded, or only just left the ground.
+  pub fn can_jump(&self) -> bool {
+    self.grounded || self.time_since_grounded <= self.coyote_time
+  }
+
+  // Remembers a jump press for `jump_buffer_time`, so pressing jump slightly before landing works.
+  pub fn request_jump(&mut self) {
+    self.time_since_jump_request = Some(0.0);
+  }
+
+  // Returns true (once) when a buffered jump request can be performed now. The caller applies the
+  // actual vertical velocity.
+  pub fn consume_jump(&mut self) -> bool {
+    match self.time_since_jump_request {
+      Some(elapsed) if elapsed <= self.jump_buffer_time && self.can_jump() => {
+        self.time_since_jump_request = None;
+        // Stop coyote time from granting a second jump mid-air.
+        self.time_since_grounded = f32::INFINITY;
+        true
+      }
+      _ => false,
+    }
+  }
+
+  // How far the character really moved last update, after collisions.
+  pub fn effective_translation(&self) -> Vec3 {
+    self.effective_translation
+  }
+
+  fn rapier_controller(&self) -> KinematicCharacterController {
+    KinematicCharacterController {
+      up: rapier3d::na::Unit::new_normalize(to_vector(self.up)),
+      offset: CharacterLength::Absolute(self.offset),
+      slide: true,
+      autostep: self.step_height.map(|height| CharacterAutostep {
+        max_height: CharacterLength::Absolute(height),
+        min_width: CharacterLength::Absolute(self.step_min_width),
+        include_dynamic_bodies: false,
+      }),
+      max_slope_climb_angle: self.max_slope_climb_angle,
+      min_slope_slide_angle: self.min_slope_slide_angle,
+      snap_to_ground: self.snap_to_ground.map(CharacterLength::Absolute),
+    }
+  }
+}
+
+// Moves every entity with a `Transform` and `CharacterController` through the world's
+// `PhysicsWorld` resource. Does nothing if the world has no physics. The engine calls it each fixed
+// step, before the physics step.
+pub fn update_character_controllers(world: &World, dt: f32) {
+  let physics = match world.get_resource::<PhysicsWorld>() {
+    Some(physics) => physics,
+    None => return,
+  };
+
+  world.query::<(&mut Transform, &mut CharacterController)>().for_each(|entity, (mut transform, mut controller)| {
+    // The character's own collider and body, if it has them, would otherwise stop every move.
+    let mut filter = QueryFilter::default().exclude_sensors();
+    if let Some(collider) = physics.collider_of(entity) {
+      filter = filter.exclude_collider(collider);
+    }
+    if let Some(body) = physics.body_of(entity) {
+      filter = filter.exclude_rigid_body(body);
+    }
+    let shape = SharedShape::capsule_y(controller.half_height, controller.radius);
+    let movement = controller.rapier_controller().move_shape(
+      dt,
+      &physics.bodies,
+      &physics.colliders,
+      &physics.query_pipeline,
+      &*shape,
+      &to_isometry(&transform),
+      to_vector(controller.desired_translation),
+      filter,
+      |_| {},
+    );
+
+    let translation = from_vector(&movement.translation);
+    transform.translation += translation;
+
+    let controller = controller.bypass_change_detection();
+    controller.effective_translation = translation;
+    controller.grounded = movement.grounded;
+    controller.time_since_grounded = if movement.grounded { 0.0 } else { controller.time_since_grounded + dt };
+    controller.time_since_jump_request = controller.time_since_jump_request.map(|elapsed| elapsed + dt);
+    controller.desired_translation = Vec3::ZERO;
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::game_engine::physics::{step_physics, Collider, RigidBody};
+
+  #[test]
+  fn character_isnt_stopped_by_its_own_collider() {
+    let mut world = World::new();
+    world.insert_resource(PhysicsWorld::new());
+    let character = world.spawn();
+    world.insert(character, Transform::from_translation(Vec3::new(0.0, 2.0, 0.0)));
+    world.insert(character, CharacterController::capsule(0.5, 0.3));
+    world.insert(character, RigidBody::kinematic());
+    world.insert(character, Collider::capsule(0.5, 0.3));
+    // Gives the entity its rapier body and collider.
+    step_physics(&mut world, 1.0 / 60.0);
+    world.apply_commands();
+    assert!(world.resource::<PhysicsWorld>().collider_of(character).is_some());
+
+    for _ in 0..10 {
+      world.get_mut::<CharacterController>(character).unwrap().desired_translation = Vec3::new(0.1, 0.0, 0.0);
+      update_character_controllers(&world, 1.0 / 60.0);
+      step_physics(&mut world, 1.0 / 60.0);
+    }
+    assert!((world.get::<Transform>(character).unwrap().translation.x - 1.0).abs() < 1e-3);
+  }
+}
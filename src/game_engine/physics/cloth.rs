@@ -0,0 +1,216 @@
+use glam::{Vec2, Vec3};
+
+use crate::game_engine::ecs::{Transform, World};
+use super::physics_world::PhysicsWorld;
+
+// Distance constraint between two particles.
+#[derive(Debug, Clone, Copy)]
+struct Stick {
+  a: usize,
+  b: usize,
+  rest_length: f32,
+}
+
+// A rectangular cloth simulated with position-based dynamics (Verlet integration plus iterated
+// distance constraints). Particles live in world space; pinned particles instead follow their
+// rest position through the entity's `Transform`, which is what attaches flags to poles and capes
+// to characters. `positions`, `normals`, `uvs` and `indices` describe the mesh to draw.
+#[derive(Debug, Clone)]
+pub struct Cloth {
+  pub columns: usize,
+  pub rows: usize,
+  pub wind: Vec3,
+  pub damping: f32, // fraction of velocity lost per second
+  pub stiffness: f32, // 0..1, how strongly each iteration corrects stretching
+  pub iterations: u32,
+
+  local: Vec<Vec3>, // rest positions in entity space
+  positions: Vec<Vec3>,
+  previous: Vec<Vec3>,
+  pinned: Vec<bool>,
+  normals: Vec<Vec3>,
+  sticks: Vec<Stick>,
+  indices: Vec<u32>,
+  initialized: bool,
+}
+
+impl Cloth {
+  // A `width` x `height` sheet hanging down from the entity's origin in its local XY plane, with
+  // `columns` x `rows` particles. The top row is pinned.
+  pub fn grid(width: f32, height: f32, columns: usize, rows: usize) -> Self {
+    let columns = columns.max(2);
+    let rows = rows.max(2);
+    let mut local = Vec::with_capacity(columns * rows);
+    for row in 0..rows {
+      for column in 0..columns {
+        let u = column as f32 / (columns - 1) as f32;
+        let v = row as f32 / (rows - 1) as f32;
+        local.push(Vec3::new(u * width, -v * height, 0.0));
+      }
+    }
+
+    let index = |column: usize, row: usize| row * columns + column;
+    let mut stick_pairs = Vec::new();
+    for row in 0..rows {
+      for column in 0..columns {
+        // Structural, shear and bend constraints.
+        if column + 1 < columns { stick_pairs.push((index(column, row), index(column + 1, row))); }
+        if row + 1 < rows { stick_pairs.push((index(column, row), index(column, row + 1))); }
+        if column + 1 < columns && row + 1 < rows {
+          stick_pairs.push((index(column, row), index(column + 1, row + 1)));
+          stick_pairs.push((index(column + 1, row), index(column, row + 1)));
+        }
+        if column + 2 < columns { stick_pairs.push((index(column, row), index(column + 2, row))); }
+        if row + 2 < rows { stick_pairs.push((index(column, row), index(column, row + 2))); }
+      }
+    }
+    let sticks = stick_pairs.into_iter()
+      .map(|(a, b)| Stick { a, b, rest_length: local[a].distance(local[b]) })
+      .collect();
+
+    let mut indices = Vec::with_capacity((columns - 1) * (rows - 1) * 6);
+    for row in 0..rows - 1 {
+      for column in 0..columns - 1 {
+        let (a, b) = (index(column, row) as u32, index(column + 1, row) as u32);
+        let (c, d) = (index(column, row + 1) as u32, index(column + 1, row + 1) as u32);
+        indices.extend_from_slice(&[a, c, b, b, c, d]);
+      }
+    }
+
+    let mut pinned = vec![false; columns * rows];
+    pinned[..columns].fill(true);
+
+    Cloth {
+      columns,
+      rows,
+      wind: Vec3::ZERO,
+      damping: 0.1,
+      stiffness: 1.0,
+      iterations: 8,
+      positions: local.clone(),
+      previous: local.clone(),
+      normals: vec![Vec3::Z; local.len()],
+      local,
+      pinned,
+      sticks,
+      indices,
+      initialized: false,
+    }
+  }
+
+  pub fn pin(&mut self, column: usize, row: usize) {
+    let index = row * self.columns + column;
+    self.pinned[index] = true;
+  }
+
+  pub fn unpin(&mut self, column: usize, row: usize) {
+    let index = row * self.columns + column;
+    self.pinned[index] = false;
+  }
+
+  pub fn unpin_all(&mut self) {
+    self.pinned.fill(false);
+  }
+
+  pub fn positions(&self) -> &[Vec3] {
+    &self.positions
+  }
+
+  pub fn normals(&self) -> &[Vec3] {
+    &self.normals
+  }
+
+  pub fn uvs(&self) -> Vec<Vec2> {
+    (0..self.rows)
+      .flat_map(|row| (0..self.columns).map(move |column| (column, row)))
+      .map(|(column, row)| Vec2::new(
+        column as f32 / (self.columns - 1) as f32,
+        row as f32 / (self.rows - 1) as f32,
+      ))
+      .collect()
+  }
+
+  pub fn indices(&self) -> &[u32] {
+    &self.indices
+  }
+
+  // Advances the cloth by `dt` seconds under `gravity`, with pinned particles following `transform`.
+  pub fn step(&mut self, transform: &Transform, gravity: Vec3, dt: f32) {
+    if dt <= 0.0 {
+      return;
+    }
+    let matrix = transform.matrix();
+    if !self.initialized {
+      for (i, local) in self.local.iter().enumerate() {
+        self.positions[i] = matrix.transform_point3(*local);
+        self.previous[i] = self.positions[i];
+      }
+      self.initialized = true;
+    }
+
+    let retain = (1.0 - self.damping).clamp(0.0, 1.0).powf(dt);
+    for i in 0..self.positions.len() {
+      if self.pinned[i] {
+        self.previous[i] = self.positions[i];
+        self.positions[i] = matrix.transform_point3(self.local[i]);
+        continue;
+      }
+      // Wind pushes harder on faces turned towards it.
+      let wind = self.normals[i] * self.normals[i].dot(self.wind);
+      let velocity = (self.positions[i] - self.previous[i]) * retain;
+      self.previous[i] = self.positions[i];
+      self.positions[i] += velocity + (gravity + wind) * dt * dt;
+    }
+
+    for _ in 0..self.iterations {
+      for stick in &self.sticks {
+        let (pinned_a, pinned_b) = (self.pinned[stick.a], self.pinned[stick.b]);
+        if pinned_a && pinned_b {
+          continue;
+        }
+        let delta = self.positions[stick.b] - self.positions[stick.a];
+        let length = delta.length();
+        if length <= f32::EPSILON {
+          continue;
+        }
+        let correction = delta * ((length - stick.rest_length) / length) * self.stiffness;
+        match (pinned_a, pinned_b) {
+          (true, _) => self.positions[stick.b] -= correction,
+          (_, true) => self.positions[stick.a] += correction,
+          _ => {
+            self.positions[stick.a] += correction * 0.5;
+            self.positions[stick.b] -= correction * 0.5;
+          }
+        }
+      }
+    }
+
+    self.update_normals();
+  }
+
+  fn update_normals(&mut self) {
+    self.normals.fill(Vec3::ZERO);
+    for triangle in self.indices.chunks_exact(3) {
+      let (a, b, c) = (triangle[0] as usize, triangle[1] as usize, triangle[2] as usize);
+      let normal = (self.positions[b] - self.positions[a]).cross(self.positions[c] - self.positions[a]);
+      self.normals[a] += normal;
+      self.normals[b] += normal;
+      self.normals[c] += normal;
+    }
+    for normal in &mut self.normals {
+      *normal = normal.normalize_or_zero();
+    }
+  }
+}
+
+// Steps every `Cloth` with its entity's `Transform`, using the `PhysicsWorld` gravity when the
+// world has one. The engine calls it each fixed step, after the physics step.
+pub fn simulate_cloth(world: &World, dt: f32) {
+  let gravity = world.get_resource::<PhysicsWorld>()
+    .map(|physics| physics.gravity)
+    .unwrap_or(Vec3::new(0.0, -9.81, 0.0));
+
+  world.query::<(&Transform, &mut Cloth)>().for_each(|_, (transform, mut cloth)| {
+    cloth.step(transform, gravity, dt);
+  });
+}
@@ -0,0 +1,24 @@
+use glam::{Quat, Vec3};
+use rapier3d::na::{Quaternion, UnitQuaternion};
+use rapier3d::prelude::{Isometry, Vector};
+
+use crate::game_engine::ecs::Transform;
+
+// Engine code speaks glam, rapier speaks nalgebra - these are the only places the two meet.
+
+pub(crate) fn to_vector(v: Vec3) -> Vector<f32> {
+  Vector::new(v.x, v.y, v.z)
+}
+
+pub(crate) fn from_vector(v: &Vector<f32>) -> Vec3 {
+  Vec3::new(v.x, v.y, v.z)
+}
+
+pub(crate) fn to_rotation(q: Quat) -> UnitQuaternion<f32> {
+  UnitQuaternion::new_normalize(Quaternion::new(q.w, q.x, q.y, q.z))
+}
+
+// Scale isn't representable in an isometry and is dropped.
+pub(crate) fn to_isometry(transform: &Transform) -> Isometry<f32> {
+  Isometry::from_parts(to_vector(transform.translation).into(), to_rotation(transform.rotation))
+}
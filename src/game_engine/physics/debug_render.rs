@@ -0,0 +1,87 @@
+use glam::Vec3;
+use rapier3d::prelude::*;
+
+use crate::game_engine::color::Color;
+use crate::game_engine::debug_draw::DebugDraw;
+use crate::game_engine::ecs::World;
+use super::convert::from_vector;
+use super::physics_world::PhysicsWorld;
+
+// What the physics debug view draws. Insert it as a world resource and flip `enabled`, or the
+// `physics_debug` cvar, to toggle the whole overlay.
+pub struct PhysicsDebugSettings {
+  pub enabled: bool,
+  pub colliders: bool,
+  pub contacts: bool,
+  pub joints: bool,
+  pub velocities: bool,
+  pub velocity_scale: f32, // world units drawn per m/s
+}
+
+impl Default for PhysicsDebugSettings {
+  fn default() -> Self {
+    PhysicsDebugSettings {
+      enabled: false,
+      colliders: true,
+      contacts: true,
+      joints: true,
+      velocities: true,
+      velocity_scale: 0.1,
+    }
+  }
+}
+
+struct DebugDrawBackend<'a>(&'a mut DebugDraw);
+
+impl DebugRenderBackend for DebugDrawBackend<'_> {
+  fn draw_line(&mut self, _object: DebugRenderObject, a: Point<Real>, b: Point<Real>, color: [f32; 4]) {
+    // Rapier's debug colors are HSLA.
+    let color = Color::hsla(color[0], color[1], color[2], color[3]);
+    self.0.line(from_vector(&a.coords), from_vector(&b.coords), color);
+  }
+}
+
+// Draws the world's `PhysicsWorld` into its `DebugDraw` according to `PhysicsDebugSettings`. The
+// engine calls it every frame once physics has stepped.
+pub fn draw_physics_debug(world: &World) {
+  let settings = match world.get_resource::<PhysicsDebugSettings>() {
+    Some(settings) if settings.enabled => settings,
+    _ => return,
+  };
+  let (physics, mut debug_draw) = match (world.get_resource::<PhysicsWorld>(), world.get_resource_mut::<DebugDraw>()) {
+    (Some(physics), Some(debug_draw)) => (physics, debug_draw),
+    _ => return,
+  };
+
+  let mut mode = DebugRenderMode::empty();
+  if settings.colliders {
+    mode |= DebugRenderMode::COLLIDER_SHAPES;
+  }
+  if settings.contacts {
+    mode |= DebugRenderMode::CONTACTS;
+  }
+  if settings.joints {
+    mode |= DebugRenderMode::JOINTS;
+  }
+
+  let mut pipeline = DebugRenderPipeline::new(DebugRenderStyle::default(), mode);
+  pipeline.render(
+    &mut DebugDrawBackend(&mut debug_draw),
+    &physics.bodies,
+    &physics.colliders,
+    &physics.impulse_joints,
+    &physics.multibody_joints,
+    physics.narrow_phase(),
+  );
+
+  if settings.velocities {
+    for (_, body) in physics.bodies.iter() {
+      if body.is_fixed() {
+        continue;
+      }
+      let origin = from_vector(body.translation());
+      let velocity: Vec3 = from_vector(body.linvel());
+      debug_draw.ray(origin, velocity * settings.velocity_scale, Color::YELLOW);
+    }
+  }
+}
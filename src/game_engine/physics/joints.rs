@@ -0,0 +1,144 @@
+use glam::Vec3;
+use rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::game_engine::ecs::{Entity, World};
+use super::convert::to_vector;
+use super::physics_world::PhysicsWorld;
+
+// Links an entity to its rigid body in the `PhysicsWorld`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BodyHandle(pub RigidBodyHandle);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct JointMotor {
+  pub target_position: f32,
+  pub target_velocity: f32,
+  pub stiffness: f32,
+  pub damping: f32,
+  pub max_force: f32,
+}
+
+// Limits are in radians for revolute joints and world units for prismatic ones.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum JointKind {
+  Fixed,
+  Revolute { axis: Vec3, limits: Option<(f32, f32)>, motor: Option<JointMotor> },
+  Prismatic { axis: Vec3, limits: Option<(f32, f32)>, motor: Option<JointMotor> },
+  Spring { rest_length: f32, stiffness: f32, damping: f32 },
+  Rope { max_length: f32 },
+}
+
+// Connects this entity's body to `connected`'s body. Both entities need a `BodyHandle`; anchors are
+// in each body's local space. Editing the component updates the joint on the next `sync_joints`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Joint {
+  pub connected: Entity,
+  pub kind: JointKind,
+  pub anchor: Vec3,
+  pub connected_anchor: Vec3,
+  pub contacts_enabled: bool,
+}
+
+impl Joint {
+  pub fn new(connected: Entity, kind: JointKind) -> Self {
+    Joint {
+      connected,
+      kind,
+      anchor: Vec3::ZERO,
+      connected_anchor: Vec3::ZERO,
+      contacts_enabled: false,
+    }
+  }
+
+  pub fn with_anchors(mut self, anchor: Vec3, connected_anchor: Vec3) -> Self {
+    self.anchor = anchor;
+    self.connected_anchor = connected_anchor;
+    self
+  }
+
+  fn to_rapier(&self) -> GenericJoint {
+    let mut joint: GenericJoint = match &self.kind {
+      JointKind::Fixed => FixedJointBuilder::new().into(),
+      JointKind::Revolute { axis, limits, motor } => {
+        let mut joint: GenericJoint = RevoluteJointBuilder::new(unit(*axis)).into();
+        apply_limits_and_motor(&mut joint, JointAxis::AngX, *limits, *motor);
+        joint
+      }
+      JointKind::Prismatic { axis, limits, motor } => {
+        let mut joint: GenericJoint = PrismaticJointBuilder::new(unit(*axis)).into();
+        apply_limits_and_motor(&mut joint, JointAxis::X, *limits, *motor);
+        joint
+      }
+      JointKind::Spring { rest_length, stiffness, damping } =>
+        SpringJointBuilder::new(*rest_length, *stiffness, *damping).into(),
+      JointKind::Rope { max_length } => RopeJointBuilder::new(*max_length).into(),
+    };
+    joint
+      .set_local_anchor1(to_vector(self.anchor).into())
+      .set_local_anchor2(to_vector(self.connected_anchor).into())
+      .set_contacts_enabled(self.contacts_enabled);
+    joint
+  }
+}
+
+fn unit(axis: Vec3) -> UnitVector<Real> {
+  UnitVector::new_normalize(to_vector(axis))
+}
+
+fn apply_limits_and_motor(joint: &mut GenericJoint, axis: JointAxis, limits: Option<(f32, f32)>, motor: Option<JointMotor>) {
+  if let Some((min, max)) = limits {
+    joint.set_limits(axis, [min, max]);
+  }
+  if let Some(motor) = motor {
+    joint
+      .set_motor(axis, motor.target_position, motor.target_velocity, motor.stiffness, motor.damping)
+      .set_motor_max_force(axis, motor.max_force);
+  }
+}
+
+// Mirrors `Joint` components into the world's `PhysicsWorld`: creates joints for new components,
+// rebuilds ones whose component changed, and removes joints whose entity or component is gone.
+pub fn sync_joints(world: &World) {
+  let mut physics = match world.get_resource_mut::<PhysicsWorld>() {
+    Some(physics) => physics,
+    None => return,
+  };
+  let physics = &mut *physics;
+
+  let stale: Vec<Entity> = physics.joint_handles.keys()
+    .copied()
+    .filter(|&entity| !world.has::<Joint>(entity))
+    .collect();
+  for entity in stale {
+    if let Some(handle) = physics.joint_handles.remove(&entity) {
+      physics.impulse_joints.remove(handle, true);
+    }
+  }
+
+  world.query::<&Joint>().for_each(|entity, joint| {
+    let (body, connected) = match (body_of(world, physics, entity), body_of(world, physics, joint.connected)) {
+      (Some(body), Some(connected)) => (body, connected),
+      _ => return,
+    };
+
+    match physics.joint_handles.get(&entity) {
+      Some(&handle) if world.is_changed::<Joint>(entity) => {
+        // The connected entity may have changed too, so re-insert rather than patch the data.
+        physics.impulse_joints.remove(handle, true);
+        let handle = physics.impulse_joints.insert(body, connected, joint.to_rapier(), true);
+        physics.joint_handles.insert(entity, handle);
+      }
+      Some(_) => {}
+      None => {
+        let handle = physics.impulse_joints.insert(body, connected, joint.to_rapier(), true);
+        physics.joint_handles.insert(entity, handle);
+      }
+    }
+  });
+}
+
+// Bodies made for `RigidBody` components only get their `BodyHandle` once commands are applied.
+fn body_of(world: &World, physics: &PhysicsWorld, entity: Entity) -> Option<RigidBodyHandle> {
+  world.get::<BodyHandle>(entity).map(|body| body.0).or_else(|| physics.body_of(entity))
+}
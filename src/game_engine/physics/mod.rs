@@ -0,0 +1,18 @@
+mod bodies;
+mod character_controller;
+mod cloth;
+mod convert;
+mod debug_render;
+mod joints;
+mod physics_world;
+
+pub use rapier3d;
+
+pub use self::{
+  bodies::*,
+  character_controller::*,
+  cloth::*,
+  debug_render::*,
+  joints::*,
+  physics_world::*
+};
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use glam::Vec3;
+use rapier3d::prelude::*;
+
+use crate::game_engine::ecs::Entity;
+
+use super::convert::to_vector;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CollisionPhase {
+  Begin,
+  End,
+}
+
+// Two entities' `Collider`s starting or stopping touching. Colliders added straight to the
+// `PhysicsWorld` rather than through components don't report any.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Collision {
+  pub phase: CollisionPhase,
+  pub a: Entity,
+  pub b: Entity,
+  pub sensor: bool, // one of them is a sensor, so nothing was pushed
+}
+
+impl Collision {
+  pub fn involves(&self, entity: Entity) -> bool {
+    self.a == entity || self.b == entity
+  }
+
+  // The entity on the other side from `entity`, if it's in this collision at all.
+  pub fn other(&self, entity: Entity) -> Option<Entity> {
+    match entity {
+      _ if entity == self.a => Some(self.b),
+      _ if entity == self.b => Some(self.a),
+      _ => None,
+    }
+  }
+}
+
+// Rapier reports collisions through a shared reference during the step, hence the lock.
+#[derive(Default)]
+struct CollisionCollector(Mutex<Vec<CollisionEvent>>);
+
+impl EventHandler for CollisionCollector {
+  fn handle_collision_event(&self, _bodies: &RigidBodySet, _colliders: &ColliderSet, event: CollisionEvent, _contact_pair: Option<&ContactPair>) {
+    self.0.lock().unwrap().push(event);
+  }
+
+  fn handle_contact_force_event(&self, _dt: Real, _bodies: &RigidBodySet, _colliders: &ColliderSet, _contact_pair: &ContactPair, _total_force_magnitude: Real) {}
+}
+
+// Owns the rapier simulation. Lives in the ECS as a world resource so systems can reach it with
+// `world.resource_mut::<PhysicsWorld>()`.
+pub struct PhysicsWorld {
+  pub gravity: Vec3,
+  pub integration_parameters: IntegrationParameters,
+
+  pub bodies: RigidBodySet,
+  pub colliders: ColliderSet,
+  pub impulse_joints: ImpulseJointSet,
+  pub multibody_joints: MultibodyJointSet,
+  pub query_pipeline: QueryPipeline,
+
+  pub(crate) joint_handles: HashMap<Entity, ImpulseJointHandle>,
+  body_handles: HashMap<Entity, RigidBodyHandle>, // for `RigidBody` components
+  collider_handles: HashMap<Entity, ColliderHandle>, // for `Collider` components
+  collider_entities: HashMap<ColliderHandle, Entity>,
+  removed_colliders: Vec<ColliderHandle>, // still in `collider_entities` until their end events are out
+  collisions: Vec<Collision>,
+  // The world and change tick of the last component sync, so an edit is only applied once.
+  pub(crate) last_sync: Option<(u64, u32)>,
+
+  pipeline: PhysicsPipeline,
+  islands: IslandManager,
+  broad_phase: BroadPhase,
+  narrow_phase: NarrowPhase,
+  ccd_solver: CCDSolver,
+}
+
+impl PhysicsWorld {
+  pub fn new() -> Self {
+    PhysicsWorld {
+      gravity: Vec3::new(0.0, -9.81, 0.0),
+      integration_parameters: IntegrationParameters::default(),
+      bodies: RigidBodySet::new(),
+      colliders: ColliderSet::new(),
+      impulse_joints: ImpulseJointSet::new(),
+      multibody_joints: MultibodyJointSet::new(),
+      query_pipeline: QueryPipeline::new(),
+      joint_handles: HashMap::new(),
+      body_handles: HashMap::new(),
+      collider_handles: HashMap::new(),
+      collider_entities: HashMap::new(),
+      removed_colliders: Vec::new(),
+      collisions: Vec::new(),
+      last_sync: None,
+      pipeline: PhysicsPipeline::new(),
+      islands: IslandManager::new(),
+      broad_phase: BroadPhase::new(),
+      narrow_phase: NarrowPhase::new(),
+      ccd_solver: CCDSolver::new(),
+    }
+  }
+
+  // Convenience for level geometry that never moves: floors, walls, ramps.
+  pub fn add_static_collider(&mut self, collider: impl Into<Collider>) -> ColliderHandle {
+    let handle = self.colliders.insert(collider);
+    self.update_queries();
+    handle
+  }
+
+  pub fn remove_collider(&mut self, handle: ColliderHandle) -> Option<Collider> {
+    let collider = self.colliders.remove(handle, &mut self.islands, &mut self.bodies, true);
+    self.update_queries();
+    collider
+  }
+
+  pub fn step(&mut self, dt: f32) {
+    let collector = CollisionCollector::default();
+    self.integration_parameters.dt = dt;
+    self.pipeline.step(
+      &to_vector(self.gravity),
+      &self.integration_parameters,
+      &mut self.islands,
+      &mut self.broad_phase,
+      &mut self.narrow_phase,
+      &mut self.bodies,
+      &mut self.colliders,
+      &mut self.impulse_joints,
+      &mut self.multibody_joints,
+      &mut self.ccd_solver,
+      Some(&mut self.query_pipeline),
+      &(),
+      &collector,
+    );
+
+    for event in collector.0.into_inner().unwrap() {
+      let (a, b) = match (self.collider_entities.get(&event.collider1()), self.collider_entities.get(&event.collider2())) {
+        (Some(&a), Some(&b)) => (a, b),
+        _ => continue,
+      };
+      let phase = if event.started() { CollisionPhase::Begin } else { CollisionPhase::End };
+      self.collisions.push(Collision { phase, a, b, sensor: event.sensor() });
+    }
+    for handle in self.removed_colliders.drain(..) {
+      self.collider_entities.remove(&handle);
+    }
+  }
+
+  // The collisions since the last call. The engine delivers them to `Engine::on_collision` handlers
+  // every frame, so only call this on a `PhysicsWorld` you step yourself.
+  pub fn take_collisions(&mut self) -> Vec<Collision> {
+    std::mem::take(&mut self.collisions)
+  }
+
+  // The rapier body made for an entity's `RigidBody` component.
+  pub fn body_of(&self, entity: Entity) -> Option<RigidBodyHandle> {
+    self.body_handles.get(&entity).copied()
+  }
+
+  // The rapier collider made for an entity's `Collider` component.
+  pub fn collider_of(&self, entity: Entity) -> Option<ColliderHandle> {
+    self.collider_handles.get(&entity).copied()
+  }
+
+  // The entity whose `Collider` component made this collider, e.g. for a scene query's hit.
+  pub fn entity_of(&self, collider: ColliderHandle) -> Option<Entity> {
+    self.collider_entities.get(&collider).copied()
+  }
+
+  pub(crate) fn entity_bodies(&self) -> impl Iterator<Item = Entity> + '_ {
+    self.body_handles.keys().copied()
+  }
+
+  pub(crate) fn entity_colliders(&self) -> impl Iterator<Item = Entity> + '_ {
+    self.collider_handles.keys().copied()
+  }
+
+  pub(crate) fn insert_body(&mut self, entity: Entity, body: RigidBody) -> RigidBodyHandle {
+    let handle = self.bodies.insert(body);
+    self.body_handles.insert(entity, handle);
+    handle
+  }
+
+  // Also removes the entity's collider, which goes with the body.
+  pub(crate) fn remove_body(&mut self, entity: Entity) {
+    self.remove_entity_collider(entity);
+    if let Some(handle) = self.body_handles.remove(&entity) {
+      self.bodies.remove(handle, &mut self.islands, &mut self.colliders, &mut self.impulse_joints, &mut self.multibody_joints, true);
+    }
+  }
+
+  pub(crate) fn insert_collider(&mut self, entity: Entity, collider: Collider, parent: Option<RigidBodyHandle>) -> ColliderHandle {
+    let handle = match parent {
+      Some(parent) => self.colliders.insert_with_parent(collider, parent, &mut self.bodies),
+      None => self.colliders.insert(collider),
+    };
+    self.collider_handles.insert(entity, handle);
+    self.collider_entities.insert(handle, entity);
+    handle
+  }
+
+  pub(crate) fn remove_entity_collider(&mut self, entity: Entity) {
+    if let Some(handle) = self.collider_handles.remove(&entity) {
+      self.colliders.remove(handle, &mut self.islands, &mut self.bodies, true);
+      self.removed_colliders.push(handle);
+    }
+  }
+
+  // The rapier joint created for an entity's `Joint` component.
+  pub fn joint_handle(&self, entity: Entity) -> Option<ImpulseJointHandle> {
+    self.joint_handles.get(&entity).copied()
+  }
+
+  pub fn narrow_phase(&self) -> &NarrowPhase {
+    &self.narrow_phase
+  }
+
+  // Scene queries (and the character controller) only see colliders once the query pipeline has
+  // been updated, which `step` does automatically.
+  pub fn update_queries(&mut self) {
+    self.query_pipeline.update(&self.bodies, &self.colliders);
+  }
+}
+
+impl Default for PhysicsWorld {
+  fn default() -> Self {
+    PhysicsWorld::new()
+  }
+}
@@ -0,0 +1,310 @@
+use std::path::{Path, PathBuf};
+use ron::Value;
+use wasmtime::{Caller, Config, Extern, Instance, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+use super::assets::FileWatcher;
+use super::ecs::{Entity, TypeRegistry, World};
+use super::error::EngineError;
+use super::scripting::ScriptEvent;
+
+// Changes whenever the functions below change in a way existing plugins would notice.
+pub const PLUGIN_ABI_VERSION: i32 = 1;
+
+// What a plugin can see of the engine while one of its functions runs.
+struct PluginState {
+  name: String, // for log messages
+  world: World, // the active world, swapped in for the length of a call
+  registry: TypeRegistry,
+  events: Vec<ScriptEvent>,
+  limits: StoreLimits,
+}
+
+struct Plugin {
+  path: PathBuf,
+  store: Store<PluginState>,
+  instance: Instance,
+  started: bool, // whether `init` has been called
+}
+
+// Game logic compiled to WebAssembly and loaded at runtime, for mods. A plugin can only reach the
+// engine through the functions it imports, can't use more than `memory_limit` bytes and is stopped
+// after `fuel` instructions or so per call, so a broken or hostile mod can't take the game down.
+//
+// A plugin exports:
+//
+//   memory                                        its linear memory
+//   engine_abi_version() -> i32                   must return `PLUGIN_ABI_VERSION`
+//   alloc(len: i32) -> i32                        room for the engine to write `len` bytes, for events
+//   init()                                        optional; once, at the first update
+//   update(dt: f32)                               optional; every frame
+//   on_event(name: i32, name_len: i32, data: i32, data_len: i32)
+//                                                 optional; for every event pushed by a plugin or script
+//
+// and can import from the `engine` module, with strings passed as a pointer and a length in bytes
+// and entities as `(generation << 32) | index`, or -1 for none:
+//
+//   log(text, text_len)
+//   spawn() -> i64
+//   despawn(entity: i64) -> i32                   1 if it was alive; takes its children too
+//   alive(entity: i64) -> i32
+//   find(name, name_len) -> i64                   by `Name`
+//   has(entity: i64, component, component_len) -> i32
+//   get(entity: i64, component, component_len, out, out_len) -> i32
+//                                                 writes the component as RON text to `out` if it fits,
+//                                                 returning its length, or -1 if the entity hasn't got one
+//   set(entity: i64, component, component_len, ron, ron_len)
+//                                                 patches the component with RON text, like a scene does
+//   remove(entity: i64, component, component_len) -> i32
+//   push_event(name, name_len, data, data_len)    `data` is RON text, or empty
+//
+// Components are the ones registered in the `TypeRegistry`. Misusing one of these, e.g. naming a
+// component that isn't registered, stops the call with an error in the log.
+//
+// Plugins are loaded again when their files change, starting over from `init`.
+pub struct Plugins {
+  pub watcher: FileWatcher,
+  pub fuel: u64, // per call into a plugin
+  pub memory_limit: usize, // in bytes, per plugin; applies to plugins loaded after it's changed
+  engine: wasmtime::Engine,
+  linker: Linker<PluginState>,
+  plugins: Vec<Plugin>,
+}
+
+impl Plugins {
+  pub fn new() -> Self {
+    let mut config = Config::new();
+    config.consume_fuel(true);
+    let engine = wasmtime::Engine::new(&config).expect("the default compiler settings are supported");
+    let mut linker = Linker::new(&engine);
+    define_host_functions(&mut linker).expect("host functions are only defined once");
+    Plugins { watcher: FileWatcher::new(), fuel: 50_000_000, memory_limit: 64 << 20, engine, linker, plugins: Vec::new() }
+  }
+
+  // Loads the plugin at `path`, which starts at the next update.
+  pub fn load(&mut self, path: impl AsRef<Path>) -> Result<(), EngineError> {
+    let path = path.as_ref();
+    let plugin = self.instantiate(path).map_err(|err| EngineError::Asset { path: path.to_path_buf(), message: format!("{:#}", err) })?;
+    self.watcher.watch(path);
+    match self.plugins.iter_mut().find(|existing| existing.path == path) {
+      Some(existing) => *existing = plugin,
+      None => self.plugins.push(plugin),
+    }
+    Ok(())
+  }
+
+  pub fn remove(&mut self, path: impl AsRef<Path>) -> bool {
+    let path = path.as_ref();
+    let count = self.plugins.len();
+    self.plugins.retain(|plugin| plugin.path != path);
+    self.watcher.unwatch(path);
+    self.plugins.len() != count
+  }
+
+  pub fn paths(&self) -> impl Iterator<Item = &Path> {
+    self.plugins.iter().map(|plugin| plugin.path.as_path())
+  }
+
+  // Reloads changed plugins, starts new ones and calls every `update`. Returns the events the
+  // plugins pushed.
+  pub fn update(&mut self, world: &mut World, registry: &TypeRegistry, delta: f32) -> Vec<ScriptEvent> {
+    for path in self.watcher.poll() {
+      match self.instantiate(&path) {
+        Ok(plugin) => {
+          if let Some(existing) = self.plugins.iter_mut().find(|existing| existing.path == path) {
+            *existing = plugin;
+            log::info!("reloaded {}", path.display());
+          }
+        }
+        Err(err) => log::warn!("couldn't reload {}: {:#}", path.display(), err),
+      }
+    }
+
+    let mut events = Vec::new();
+    for plugin in &mut self.plugins {
+      if !plugin.started {
+        plugin.started = true;
+        events.extend(plugin.call(world, registry, self.fuel, |store, instance| {
+          match instance.get_typed_func::<(), ()>(&mut *store, "init") {
+            Ok(init) => init.call(store, ()),
+            Err(_) => Ok(()),
+          }
+        }));
+      }
+      events.extend(plugin.call(world, registry, self.fuel, |store, instance| {
+        match instance.get_typed_func::<f32, ()>(&mut *store, "update") {
+          Ok(update) => update.call(store, delta),
+          Err(_) => Ok(()),
+        }
+      }));
+    }
+    events
+  }
+
+  // Calls every plugin's `on_event` with `name` and `data`. Returns the events they pushed.
+  pub fn dispatch(&mut self, name: &str, data: &Value, world: &mut World, registry: &TypeRegistry) -> Vec<ScriptEvent> {
+    let data = match data {
+      Value::Unit => String::new(),
+      data => match ron::to_string(data) {
+        Ok(data) => data,
+        Err(err) => {
+          log::warn!("can't give the {} event to plugins: {}", name, err);
+          return Vec::new();
+        }
+      },
+    };
+    let mut events = Vec::new();
+    for plugin in self.plugins.iter_mut().filter(|plugin| plugin.started) {
+      events.extend(plugin.call(world, registry, self.fuel, |store, instance| {
+        let on_event = match instance.get_typed_func::<(i32, i32, i32, i32), ()>(&mut *store, "on_event") {
+          Ok(on_event) => on_event,
+          Err(_) => return Ok(()),
+        };
+        let (name_ptr, name_len) = write_to_guest(store, instance, name.as_bytes())?;
+        let (data_ptr, data_len) = write_to_guest(store, instance, data.as_bytes())?;
+        on_event.call(store, (name_ptr, name_len, data_ptr, data_len))
+      }));
+    }
+    events
+  }
+
+  fn instantiate(&self, path: &Path) -> wasmtime::Result<Plugin> {
+    let module = Module::from_binary(&self.engine, &std::fs::read(path)?)?;
+    let state = PluginState {
+      name: path.file_stem().map_or_else(|| path.display().to_string(), |stem| stem.to_string_lossy().into_owned()),
+      world: World::new(),
+      registry: TypeRegistry::default(),
+      events: Vec::new(),
+      limits: StoreLimitsBuilder::new().memory_size(self.memory_limit).build(),
+    };
+    let mut store = Store::new(&self.engine, state);
+    store.limiter(|state| &mut state.limits);
+    store.set_fuel(self.fuel)?;
+    let instance = self.linker.instantiate(&mut store, &module)?;
+    let version = instance.get_typed_func::<(), i32>(&mut store, "engine_abi_version")?.call(&mut store, ())?;
+    if version != PLUGIN_ABI_VERSION {
+      return Err(wasmtime::Error::msg(format!("built for engine ABI version {}, but this is version {}", version, PLUGIN_ABI_VERSION)));
+    }
+    Ok(Plugin { path: path.to_path_buf(), store, instance, started: false })
+  }
+}
+
+impl Default for Plugins {
+  fn default() -> Self {
+    Plugins::new()
+  }
+}
+
+impl Plugin {
+  // Runs `f` with the world swapped into the plugin's store, logging any error (including running
+  // out of fuel) against the plugin. Returns the events it pushed.
+  fn call(&mut self, world: &mut World, registry: &TypeRegistry, fuel: u64, f: impl FnOnce(&mut Store<PluginState>, &Instance) -> wasmtime::Result<()>) -> Vec<ScriptEvent> {
+    let state = self.store.data_mut();
+    if state.registry.version() != registry.version() {
+      state.registry = registry.clone();
+    }
+    std::mem::swap(world, &mut state.world);
+    let result = self.store.set_fuel(fuel).and_then(|_| f(&mut self.store, &self.instance));
+    std::mem::swap(world, &mut self.store.data_mut().world);
+    if let Err(err) = result {
+      log::warn!("{}: {:#}", self.path.display(), err);
+    }
+    std::mem::take(&mut self.store.data_mut().events)
+  }
+}
+
+fn define_host_functions(linker: &mut Linker<PluginState>) -> wasmtime::Result<()> {
+  linker.func_wrap("engine", "log", |mut caller: Caller<'_, PluginState>, text: i32, len: i32| {
+    let text = read_string(&mut caller, text, len)?;
+    log::info!("{}: {}", caller.data().name, text);
+    Ok(())
+  })?;
+  linker.func_wrap("engine", "spawn", |mut caller: Caller<'_, PluginState>| pack(caller.data_mut().world.spawn()))?;
+  linker.func_wrap("engine", "despawn", |mut caller: Caller<'_, PluginState>, entity: i64| {
+    caller.data_mut().world.despawn_recursive(unpack(entity)) as i32
+  })?;
+  linker.func_wrap("engine", "alive", |caller: Caller<'_, PluginState>, entity: i64| caller.data().world.is_alive(unpack(entity)) as i32)?;
+  linker.func_wrap("engine", "find", |mut caller: Caller<'_, PluginState>, name: i32, len: i32| {
+    let name = read_string(&mut caller, name, len)?;
+    Ok(caller.data().world.find_by_name(&name).map_or(-1, pack))
+  })?;
+  linker.func_wrap("engine", "has", |mut caller: Caller<'_, PluginState>, entity: i64, name: i32, len: i32| {
+    let name = read_string(&mut caller, name, len)?;
+    let state = caller.data();
+    Ok(component(&state.registry, &name)?.has(&state.world, unpack(entity)) as i32)
+  })?;
+  linker.func_wrap("engine", "get", |mut caller: Caller<'_, PluginState>, entity: i64, name: i32, len: i32, out: i32, out_len: i32| {
+    let name = read_string(&mut caller, name, len)?;
+    let state = caller.data();
+    let value = match component(&state.registry, &name)?.serialize(&state.world, unpack(entity)) {
+      Some(value) => value?,
+      None => return Ok(-1),
+    };
+    let text = ron::to_string(&value)?;
+    if text.len() <= out_len as u32 as usize {
+      memory(&mut caller)?.write(&mut caller, out as u32 as usize, text.as_bytes())?;
+    }
+    Ok(text.len() as i32)
+  })?;
+  linker.func_wrap("engine", "set", |mut caller: Caller<'_, PluginState>, entity: i64, name: i32, len: i32, text: i32, text_len: i32| {
+    let name = read_string(&mut caller, name, len)?;
+    let patch: Value = ron::from_str(&read_string(&mut caller, text, text_len)?)?;
+    let entity = unpack(entity);
+    let state = caller.data_mut();
+    if !state.world.is_alive(entity) {
+      return Err(wasmtime::Error::msg(format!("can't set {} on a despawned entity", name)));
+    }
+    let info = component(&state.registry, &name)?.clone();
+    info.patch(&mut state.world, entity, patch).map_err(|err| wasmtime::Error::msg(format!("bad {} component: {}", name, err)))
+  })?;
+  linker.func_wrap("engine", "remove", |mut caller: Caller<'_, PluginState>, entity: i64, name: i32, len: i32| {
+    let name = read_string(&mut caller, name, len)?;
+    let state = caller.data_mut();
+    let info = component(&state.registry, &name)?.clone();
+    Ok(info.remove(&mut state.world, unpack(entity)) as i32)
+  })?;
+  linker.func_wrap("engine", "push_event", |mut caller: Caller<'_, PluginState>, name: i32, len: i32, data: i32, data_len: i32| {
+    let name = read_string(&mut caller, name, len)?;
+    let data = read_string(&mut caller, data, data_len)?;
+    let data = if data.trim().is_empty() { Value::Unit } else { ron::from_str(&data)? };
+    caller.data_mut().events.push(ScriptEvent { name, data });
+    Ok(())
+  })?;
+  Ok(())
+}
+
+fn component<'a>(registry: &'a TypeRegistry, name: &str) -> wasmtime::Result<&'a super::ecs::ComponentInfo> {
+  registry.get(name).ok_or_else(|| wasmtime::Error::msg(format!("{} isn't a registered component", name)))
+}
+
+fn memory(caller: &mut Caller<'_, PluginState>) -> wasmtime::Result<Memory> {
+  caller.get_export("memory").and_then(Extern::into_memory).ok_or_else(|| wasmtime::Error::msg("the plugin doesn't export its memory"))
+}
+
+// Checks the range against the guest's memory before copying anything, so a bogus length can't
+// make the host allocate more than the plugin actually has.
+fn read_string(caller: &mut Caller<'_, PluginState>, ptr: i32, len: i32) -> wasmtime::Result<String> {
+  let memory = memory(caller)?;
+  let start = ptr as u32 as usize;
+  let bytes = start.checked_add(len as u32 as usize)
+    .and_then(|end| memory.data(&*caller).get(start..end))
+    .ok_or_else(|| wasmtime::Error::msg(format!("{} bytes at {} is outside the plugin's memory", len as u32, start)))?;
+  Ok(String::from_utf8(bytes.to_vec())?)
+}
+
+// Copies `bytes` into memory the plugin hands out from its `alloc`.
+fn write_to_guest(store: &mut Store<PluginState>, instance: &Instance, bytes: &[u8]) -> wasmtime::Result<(i32, i32)> {
+  let alloc = instance.get_typed_func::<i32, i32>(&mut *store, "alloc")?;
+  let len = bytes.len() as i32;
+  let ptr = alloc.call(&mut *store, len)?;
+  let memory = instance.get_memory(&mut *store, "memory").ok_or_else(|| wasmtime::Error::msg("the plugin doesn't export its memory"))?;
+  memory.write(&mut *store, ptr as u32 as usize, bytes)?;
+  Ok((ptr, len))
+}
+
+fn pack(entity: Entity) -> i64 {
+  entity.to_bits() as i64
+}
+
+fn unpack(bits: i64) -> Entity {
+  Entity::from_bits(bits as u64)
+}
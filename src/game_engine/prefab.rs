@@ -0,0 +1,149 @@
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+
+use super::assets::FileWatcher;
+use super::ecs::{Component, Entity, TypeRegistry, World};
+use super::error::EngineError;
+use super::scene::{Scene, SceneEntity};
+
+// Marks the root entity of a prefab instance with the prefab's name, so instances can be found
+// again, e.g. to respawn them after their prefab is reloaded.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrefabInstance(pub String);
+
+struct Prefab {
+  scene: Scene,
+  path: Option<PathBuf>,
+}
+
+// Named templates to spawn entities from, as a resource. A prefab is written like a scene (see
+// `Scene`) with a single root entity, which can have children and can itself be an instance of
+// another prefab. Spawn one with `World::spawn_prefab`, or use one from a scene with
+// `prefab: Some("name")`.
+//
+// Prefabs loaded from files are loaded again when the files change. Entities already spawned from
+// them are left alone; `reloaded` says which prefabs changed this frame.
+pub struct Prefabs {
+  pub watcher: FileWatcher,
+  registry: Option<TypeRegistry>, // the engine's, for looking up components when spawning
+  prefabs: HashMap<String, Prefab>,
+  reloaded: Vec<String>,
+}
+
+impl Prefabs {
+  pub fn new() -> Self {
+    Prefabs { watcher: FileWatcher::new(), registry: None, prefabs: HashMap::new(), reloaded: Vec::new() }
+  }
+
+  pub fn load(&mut self, name: &str, path: impl AsRef<Path>) -> Result<(), EngineError> {
+    let path = path.as_ref();
+    let scene = Scene::load(path)?;
+    check_root(&scene).map_err(|message| EngineError::Asset { path: path.to_path_buf(), message })?;
+    self.watcher.watch(path);
+    self.prefabs.insert(name.to_string(), Prefab { scene, path: Some(path.to_path_buf()) });
+    Ok(())
+  }
+
+  // Adds a prefab made in code, replacing any with the same name.
+  pub fn insert(&mut self, name: &str, scene: Scene) -> Result<(), String> {
+    check_root(&scene)?;
+    if let Some(Prefab { path: Some(path), .. }) = self.prefabs.insert(name.to_string(), Prefab { scene, path: None }) {
+      self.watcher.unwatch(&path);
+    }
+    Ok(())
+  }
+
+  pub fn remove(&mut self, name: &str) -> bool {
+    match self.prefabs.remove(name) {
+      Some(prefab) => {
+        if let Some(path) = prefab.path {
+          self.watcher.unwatch(&path);
+        }
+        true
+      }
+      None => false,
+    }
+  }
+
+  pub fn get(&self, name: &str) -> Option<&Scene> {
+    self.prefabs.get(name).map(|prefab| &prefab.scene)
+  }
+
+  pub fn contains(&self, name: &str) -> bool {
+    self.prefabs.contains_key(name)
+  }
+
+  pub fn names(&self) -> impl Iterator<Item = &str> {
+    self.prefabs.keys().map(String::as_str)
+  }
+
+  // The prefabs loaded again by the last `reload_changed`.
+  pub fn reloaded(&self) -> &[String] {
+    &self.reloaded
+  }
+
+  // Loads prefabs whose files changed again. A broken file keeps the last version that loaded.
+  pub fn reload_changed(&mut self) {
+    self.reloaded.clear();
+    for path in self.watcher.poll() {
+      for (name, prefab) in self.prefabs.iter_mut().filter(|(_, prefab)| prefab.path.as_ref() == Some(&path)) {
+        match Scene::load(&path).map_err(|err| err.to_string()).and_then(|scene| check_root(&scene).map(|_| scene)) {
+          Ok(scene) => {
+            prefab.scene = scene;
+            self.reloaded.push(name.clone());
+            log::info!("reloaded {}", path.display());
+          }
+          Err(err) => log::warn!("couldn't reload {}: {}", path.display(), err),
+        }
+      }
+    }
+  }
+
+  // Keeps a copy of `registry` for spawning with, if it's changed since the last call. The engine
+  // does this every frame with its own.
+  pub fn sync_registry(&mut self, registry: &TypeRegistry) {
+    if self.registry.as_ref().is_none_or(|current| current.version() != registry.version()) {
+      self.registry = Some(registry.clone());
+    }
+  }
+
+  // Spawns an instance of the prefab `name`, returning its root entity.
+  pub fn spawn(&self, name: &str, world: &mut World) -> Result<Entity, String> {
+    let registry = self.registry.as_ref().ok_or("prefabs have no TypeRegistry to spawn with")?;
+    let instance = Scene { entities: vec![SceneEntity { prefab: Some(name.to_string()), ..SceneEntity::default() }], ..Scene::default() };
+    let spawned = instance.expand(Some(self))?.spawn_expanded(world, registry)?;
+    Ok(spawned[0])
+  }
+}
+
+impl Default for Prefabs {
+  fn default() -> Self {
+    Prefabs::new()
+  }
+}
+
+fn check_root(scene: &Scene) -> Result<(), String> {
+  match scene.entities.len() {
+    1 => Ok(()),
+    count => Err(format!("a prefab needs exactly one root entity, not {}", count)),
+  }
+}
+
+impl World {
+  // Spawns an instance of a prefab from the world's `Prefabs`, then inserts `overrides` on its root,
+  // e.g. `world.spawn_prefab("enemy_grunt", Transform::from_xyz(4.0, 0.0, 0.0))`. Pass `()` to
+  // override nothing.
+  pub fn spawn_prefab<T: Component>(&mut self, name: &str, overrides: T) -> Result<Entity, String> {
+    // Taken out of the world while spawning, which needs the world mutably.
+    let prefabs = self.remove_resource::<Prefabs>().ok_or("the world has no Prefabs")?;
+    let spawned = prefabs.spawn(name, self);
+    self.insert_resource(prefabs);
+    let entity = spawned?;
+    if TypeId::of::<T>() != TypeId::of::<()>() {
+      self.insert(entity, overrides);
+    }
+    Ok(entity)
+  }
+}
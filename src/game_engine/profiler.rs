@@ -0,0 +1,164 @@
+use std::cell::Cell;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use serde_json::{json, Value};
+
+use super::error::EngineError;
+use super::time::Instant;
+
+// Times the rest of the enclosing block under `name`, as `profile_scope!("pathfinding")`. Costs next
+// to nothing unless a capture's running or, built with the `tracy` feature, Tracy is connected.
+#[macro_export]
+macro_rules! profile_scope {
+  ($name:expr) => {
+    let _profile_scope = $crate::game_engine::profiler::ProfileScope::new($name, module_path!(), file!(), line!());
+  };
+}
+
+// Enough for a few minutes of a busy game; the capture stops growing after this.
+const MAX_EVENTS: usize = 1 << 20;
+
+// A finished scope from a capture.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileEvent {
+  pub name: &'static str,
+  pub thread: u64,
+  pub start: Duration, // since the capture started
+  pub duration: Duration,
+}
+
+struct Capture {
+  start: Instant,
+  events: Vec<ProfileEvent>,
+  dropped: usize,
+}
+
+static CAPTURING: AtomicBool = AtomicBool::new(false);
+static CAPTURE: Mutex<Option<Capture>> = Mutex::new(None);
+static NEXT_THREAD: AtomicU64 = AtomicU64::new(1);
+static THREADS: Mutex<Vec<(u64, String)>> = Mutex::new(Vec::new());
+
+thread_local! {
+  static THREAD: Cell<u64> = const { Cell::new(0) };
+}
+
+// Numbers threads in the order they first record something, remembering their names for the trace.
+fn thread_id() -> u64 {
+  THREAD.with(|thread| {
+    if thread.get() == 0 {
+      let id = NEXT_THREAD.fetch_add(1, Ordering::Relaxed);
+      let current = std::thread::current();
+      let name = current.name().map_or_else(|| format!("thread {}", id), str::to_string);
+      THREADS.lock().unwrap().push((id, name));
+      thread.set(id);
+    }
+    thread.get()
+  })
+}
+
+pub struct ProfileScope {
+  name: &'static str,
+  start: Option<Instant>, // when there's a capture to add to
+  #[cfg(feature = "tracy")]
+  _span: Option<tracy_client::Span>,
+}
+
+impl ProfileScope {
+  pub fn new(name: &'static str, function: &str, file: &str, line: u32) -> Self {
+    #[cfg(not(feature = "tracy"))]
+    let _ = (function, file, line);
+    ProfileScope {
+      name,
+      start: CAPTURING.load(Ordering::Relaxed).then(Instant::now),
+      #[cfg(feature = "tracy")]
+      _span: tracy_client::Client::running().map(|client| client.span_alloc(Some(name), function, file, line, 0)),
+    }
+  }
+}
+
+impl Drop for ProfileScope {
+  fn drop(&mut self) {
+    let Some(start) = self.start else { return };
+    let duration = start.elapsed();
+    let thread = thread_id();
+    let mut capture = CAPTURE.lock().unwrap();
+    // Scopes that began before the capture did are left out.
+    let Some(capture) = capture.as_mut() else { return };
+    let Some(since) = start.checked_duration_since(capture.start) else { return };
+    if capture.events.len() == MAX_EVENTS {
+      capture.dropped += 1;
+      return;
+    }
+    capture.events.push(ProfileEvent { name: self.name, thread, start: since, duration });
+  }
+}
+
+// Starts recording every scope on every thread, throwing away anything recorded so far.
+pub fn start_capture() {
+  *CAPTURE.lock().unwrap() = Some(Capture { start: Instant::now(), events: Vec::new(), dropped: 0 });
+  CAPTURING.store(true, Ordering::Relaxed);
+}
+
+pub fn is_capturing() -> bool {
+  CAPTURING.load(Ordering::Relaxed)
+}
+
+// Stops recording, returning the scopes that finished, in the order they did.
+pub fn stop_capture() -> Vec<ProfileEvent> {
+  CAPTURING.store(false, Ordering::Relaxed);
+  let Some(capture) = CAPTURE.lock().unwrap().take() else { return Vec::new() };
+  if capture.dropped > 0 {
+    log::warn!("the profiler capture filled up; {} scopes were left out", capture.dropped);
+  }
+  capture.events
+}
+
+// Writes `events` in the Chrome trace format, which chrome://tracing, Perfetto and speedscope open
+// as a timeline or a flamegraph.
+pub fn save_chrome_trace(path: impl AsRef<Path>, events: &[ProfileEvent]) -> Result<(), EngineError> {
+  let path = path.as_ref();
+  let error = |message: String| EngineError::Save { path: path.to_path_buf(), message };
+  let threads = THREADS.lock().unwrap().clone();
+  let names = threads.into_iter()
+    .filter(|(id, _)| events.iter().any(|event| event.thread == *id))
+    .map(|(id, name)| json!({ "name": "thread_name", "ph": "M", "pid": 1, "tid": id, "args": { "name": name } }));
+  let scopes = events.iter().map(|event| json!({
+    "name": event.name,
+    "cat": "engine",
+    "ph": "X",
+    "ts": event.start.as_secs_f64() * 1e6,
+    "dur": event.duration.as_secs_f64() * 1e6,
+    "pid": 1,
+    "tid": event.thread,
+  }));
+  let trace = json!({ "traceEvents": names.chain(scopes).collect::<Vec<Value>>(), "displayTimeUnit": "ms" });
+  if let Some(directory) = path.parent().filter(|directory| !directory.as_os_str().is_empty()) {
+    std::fs::create_dir_all(directory).map_err(|err| error(err.to_string()))?;
+  }
+  let text = serde_json::to_string(&trace).map_err(|err| error(err.to_string()))?;
+  std::fs::write(path, text).map_err(|err| error(err.to_string()))
+}
+
+// Stops the capture and saves it with `save_chrome_trace`, returning how many scopes it had.
+pub fn save_capture(path: impl AsRef<Path>) -> Result<usize, EngineError> {
+  let events = stop_capture();
+  save_chrome_trace(path, &events)?;
+  Ok(events.len())
+}
+
+// Built with the `tracy` feature, starts the Tracy client so a running Tracy can connect. Called by
+// the engine at start-up.
+pub(crate) fn init() {
+  #[cfg(feature = "tracy")]
+  tracy_client::Client::start();
+}
+
+// Marks the end of a frame for Tracy's frame graph. Called by the engine every frame.
+pub(crate) fn frame_mark() {
+  #[cfg(feature = "tracy")]
+  if let Some(client) = tracy_client::Client::running() {
+    client.frame_mark();
+  }
+}
@@ -0,0 +1,104 @@
+use std::ops::Range;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::fixed::Fixed;
+
+// A small seedable PCG32 generator. The engine puts one in the main world as a resource, so
+// everything random in a session (noise seeds, particles, AI) can be replayed from one seed.
+// Not suitable for anything security related.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rng {
+  state: u64,
+  increment: u64,
+}
+
+impl Rng {
+  const MULTIPLIER: u64 = 6364136223846793005;
+
+  pub fn new(seed: u64) -> Self {
+    Rng::with_stream(seed, 0xda3e39cb94b95bdb)
+  }
+
+  fn with_stream(seed: u64, stream: u64) -> Self {
+    let mut rng = Rng { state: 0, increment: (stream << 1) | 1 };
+    rng.next_u32();
+    rng.state = rng.state.wrapping_add(seed);
+    rng.next_u32();
+    rng
+  }
+
+  // Seeded from the clock, for when runs don't need to be reproducible.
+  pub fn from_time() -> Self {
+    cfg_if::cfg_if! {
+      if #[cfg(target_arch = "wasm32")] {
+        let nanos = (instant::now() * 1_000_000.0) as u64;
+      } else {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos() as u64);
+      }
+    }
+    Rng::new(nanos)
+  }
+
+  pub fn next_u32(&mut self) -> u32 {
+    let old = self.state;
+    self.state = old.wrapping_mul(Rng::MULTIPLIER).wrapping_add(self.increment);
+    let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+    xorshifted.rotate_right((old >> 59) as u32)
+  }
+
+  pub fn next_u64(&mut self) -> u64 {
+    ((self.next_u32() as u64) << 32) | self.next_u32() as u64
+  }
+
+  // Uniform in [0, 1).
+  pub fn next_f32(&mut self) -> f32 {
+    (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+  }
+
+  pub fn range_f32(&mut self, range: Range<f32>) -> f32 {
+    range.start + (range.end - range.start) * self.next_f32()
+  }
+
+  // `range_f32` without floats, for lockstep games.
+  pub fn range_fixed(&mut self, range: Range<Fixed>) -> Fixed {
+    let span = range.end.0.wrapping_sub(range.start.0) as u64;
+    if span == 0 {
+      return range.start;
+    }
+    Fixed(range.start.0.wrapping_add((self.next_u64() % span) as i64))
+  }
+
+  pub fn range_i32(&mut self, range: Range<i32>) -> i32 {
+    let span = range.end.wrapping_sub(range.start) as u32;
+    if span == 0 {
+      return range.start;
+    }
+    range.start.wrapping_add((self.next_u64() % span as u64) as i32)
+  }
+
+  pub fn chance(&mut self, probability: f32) -> bool {
+    self.next_f32() < probability
+  }
+
+  pub fn shuffle<T>(&mut self, items: &mut [T]) {
+    for i in (1..items.len()).rev() {
+      let j = (self.next_u64() % (i as u64 + 1)) as usize;
+      items.swap(i, j);
+    }
+  }
+
+  // An independent generator derived from this one, e.g. to give a subsystem its own sequence
+  // that doesn't shift when other code draws more numbers.
+  pub fn fork(&mut self) -> Rng {
+    let seed = self.next_u64();
+    let stream = self.next_u64();
+    Rng::with_stream(seed, stream)
+  }
+}
+
+impl Default for Rng {
+  fn default() -> Self {
+    Rng::from_time()
+  }
+}
@@ -0,0 +1,159 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+use winit::event::{ElementState, MouseScrollDelta};
+
+use super::error::EngineError;
+use super::gamepad::{GamepadEvent, Gamepads};
+use super::input::{Composition, Input, KeyCode, MouseButton};
+use super::random::Rng;
+
+// Bumped when the layout of replay files changes.
+const REPLAY_VERSION: u32 = 1;
+
+// One thing the player did, as it reached `Input` or `Gamepads`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum InputEvent {
+  Key(KeyCode, ElementState),
+  MouseButton(MouseButton, ElementState),
+  CursorMoved(Vec2),
+  MouseMotion(Vec2),
+  MouseWheel(MouseScrollDelta),
+  ReleaseAll, // the window lost focus
+  Text(String),
+  Composition(Option<Composition>),
+  Gamepad(GamepadEvent),
+}
+
+impl InputEvent {
+  pub fn apply(&self, input: &mut Input, gamepads: &mut Gamepads) {
+    match self {
+      InputEvent::Key(key, state) => input.key_event(*key, *state),
+      InputEvent::MouseButton(button, state) => input.mouse_button_event(*button, *state),
+      InputEvent::CursorMoved(position) => input.cursor_moved(*position),
+      InputEvent::MouseMotion(delta) => input.mouse_motion(*delta),
+      InputEvent::MouseWheel(delta) => input.mouse_wheel(*delta),
+      InputEvent::ReleaseAll => input.release_all(),
+      InputEvent::Text(text) => input.text_event(text),
+      InputEvent::Composition(composition) => input.composition_event(composition.clone()),
+      InputEvent::Gamepad(event) => gamepads.handle(event.clone()),
+    }
+  }
+}
+
+// Everything that went into one frame from outside the game: how long it took, the seed the world's
+// `Rng` started it with, and the input that arrived before it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayFrame {
+  pub delta: u64, // in nanoseconds, so the fixed steps come out the same
+  pub seed: u64,
+  pub events: Vec<InputEvent>,
+}
+
+impl ReplayFrame {
+  pub fn delta(&self) -> Duration {
+    Duration::from_nanos(self.delta)
+  }
+}
+
+// A recorded session, frame by frame, stored as gzipped RON. Made with `Engine::run_recording` and
+// played back with `Engine::run_replay`, which gives the game the same frame times, random numbers
+// and input, so it does the same thing again as long as it doesn't read the clock or anything else
+// from outside. The window should be the same size as when it was recorded.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+  version: u32,
+  pub frames: Vec<ReplayFrame>,
+}
+
+impl Replay {
+  pub fn new() -> Self {
+    Replay { version: REPLAY_VERSION, frames: Vec::new() }
+  }
+
+  pub fn load(path: impl AsRef<Path>) -> Result<Self, EngineError> {
+    let path = path.as_ref();
+    let error = |message: String| EngineError::Asset { path: path.to_path_buf(), message };
+    let file = File::open(path).map_err(|err| error(err.to_string()))?;
+    let mut text = String::new();
+    GzDecoder::new(BufReader::new(file)).read_to_string(&mut text).map_err(|err| error(err.to_string()))?;
+    let replay: Replay = ron::from_str(&text).map_err(|err| error(err.to_string()))?;
+    if replay.version != REPLAY_VERSION {
+      return Err(error(format!("the replay is version {}, but this engine plays version {}", replay.version, REPLAY_VERSION)));
+    }
+    Ok(replay)
+  }
+
+  pub fn save(&self, path: impl AsRef<Path>) -> Result<(), EngineError> {
+    let path = path.as_ref();
+    let error = |message: String| EngineError::Save { path: path.to_path_buf(), message };
+    if let Some(directory) = path.parent().filter(|directory| !directory.as_os_str().is_empty()) {
+      std::fs::create_dir_all(directory).map_err(|err| error(err.to_string()))?;
+    }
+    let text = ron::to_string(self).map_err(|err| error(err.to_string()))?;
+    let file = File::create(path).map_err(|err| error(err.to_string()))?;
+    let mut encoder = GzEncoder::new(BufWriter::new(file), Compression::default());
+    encoder.write_all(text.as_bytes()).map_err(|err| error(err.to_string()))?;
+    encoder.finish().and_then(|mut writer| writer.flush()).map_err(|err| error(err.to_string()))
+  }
+}
+
+// What the engine is doing with a replay.
+pub(crate) enum ReplayMode {
+  Recording { path: PathBuf, replay: Replay, seeds: Rng, events: Vec<InputEvent> },
+  Playing { frames: VecDeque<ReplayFrame> },
+}
+
+impl ReplayMode {
+  pub fn record(path: impl Into<PathBuf>) -> Self {
+    ReplayMode::Recording { path: path.into(), replay: Replay::new(), seeds: Rng::from_time(), events: Vec::new() }
+  }
+
+  pub fn play(replay: Replay) -> Self {
+    ReplayMode::Playing { frames: replay.frames.into() }
+  }
+
+  pub fn is_playing(&self) -> bool {
+    matches!(self, ReplayMode::Playing { .. })
+  }
+
+  // Notes input that arrived from the window, for the next frame. Returns false while playing,
+  // when it should be ignored.
+  pub fn input(&mut self, event: &InputEvent) -> bool {
+    match self {
+      ReplayMode::Recording { events, .. } => {
+        events.push(event.clone());
+        true
+      }
+      ReplayMode::Playing { .. } => false,
+    }
+  }
+
+  // Starts a frame that took `delta`, giving the seed to start the world's `Rng` with and, while
+  // playing, the recorded frame to use instead. `None` once playback has run out.
+  pub fn frame(&mut self, delta: Duration) -> Option<ReplayFrame> {
+    match self {
+      ReplayMode::Recording { replay, seeds, events, .. } => {
+        let frame = ReplayFrame { delta: delta.as_nanos() as u64, seed: seeds.next_u64(), events: std::mem::take(events) };
+        replay.frames.push(frame.clone());
+        Some(frame)
+      }
+      ReplayMode::Playing { frames } => frames.pop_front(),
+    }
+  }
+
+  // Writes out a recording. Does nothing for playback.
+  pub fn finish(self) -> Result<(), EngineError> {
+    match self {
+      ReplayMode::Recording { path, replay, .. } => replay.save(path),
+      ReplayMode::Playing { .. } => Ok(()),
+    }
+  }
+}
@@ -0,0 +1,395 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use ron::Value;
+use serde::{Deserialize, Serialize};
+
+use super::ecs::{with_entity_map, Entity, EntityMap, TypeRegistry, World};
+use super::error::EngineError;
+use super::graphics::texture::{with_handle_map, HandleMap, TextureHandle, TextureManager};
+
+// Bumped when the layout of save files changes.
+const SAVE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveFormat {
+  Ron, // readable, and what scenes use
+  Json,
+  Compressed, // gzipped RON, for small files that aren't meant to be edited by hand
+  Bincode, // compact binary, the quickest to write and read
+}
+
+impl SaveFormat {
+  pub fn extension(&self) -> &'static str {
+    match self {
+      SaveFormat::Ron => "ron",
+      SaveFormat::Json => "json",
+      SaveFormat::Compressed => "sav",
+      SaveFormat::Bincode => "bin",
+    }
+  }
+}
+
+// `C` is how components are stored: as RON values, or as RON text in JSON and bincode saves, since
+// JSON can't tell `Some(x)` from `x` or a tuple from a list, and bincode can't hold a RON value.
+#[derive(Serialize, Deserialize)]
+struct SaveData<C> {
+  version: u32,
+  textures: Vec<(u32, PathBuf)>, // handles that were in use and the files they were loaded from
+  entities: Vec<SavedEntity<C>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedEntity<C> {
+  id: Entity,
+  components: Vec<(String, C)>, // by registered name
+}
+
+impl<C> SaveData<C> {
+  fn map_components<D>(self, mut f: impl FnMut(C) -> Result<D, String>) -> Result<SaveData<D>, String> {
+    let entities = self.entities.into_iter().map(|saved| {
+      let components = saved.components.into_iter().map(|(name, component)| Ok((name, f(component)?))).collect::<Result<_, String>>()?;
+      Ok(SavedEntity { id: saved.id, components })
+    }).collect::<Result<_, String>>()?;
+    Ok(SaveData { version: self.version, textures: self.textures, entities })
+  }
+}
+
+// Writes the world's entities to save slots and reads them back. Components go through the
+// `TypeRegistry`, so only registered ones are saved, and `only` narrows that down further.
+//
+// Entities get new ids when a save is loaded, and textures are loaded again from their files, so any
+// `Entity` or `TextureHandle` inside a saved component is pointed at its replacement. Textures made
+// at runtime, without a file, can't be restored and keep their old handle.
+pub struct SaveGame {
+  pub directory: PathBuf,
+  pub format: SaveFormat,
+  components: Option<Vec<String>>,
+}
+
+impl SaveGame {
+  // Saves under the platform's usual place for application data, in a folder named after `game`.
+  pub fn new(game: &str) -> Self {
+    SaveGame::in_directory(save_directory(game))
+  }
+
+  pub fn in_directory(directory: impl Into<PathBuf>) -> Self {
+    SaveGame { directory: directory.into(), format: SaveFormat::Ron, components: None }
+  }
+
+  pub fn with_format(mut self, format: SaveFormat) -> Self {
+    self.format = format;
+    self
+  }
+
+  // Saves just these registered components instead of all of them. Entities with none of them
+  // aren't saved.
+  pub fn only(mut self, components: &[&str]) -> Self {
+    self.components = Some(components.iter().map(|name| name.to_string()).collect());
+    self
+  }
+
+  // The file `slot` is saved in. Slots are plain file names: an empty one, or one with `..` or a
+  // path separator in it, is an error rather than a path outside the save directory.
+  pub fn path(&self, slot: &str) -> Result<PathBuf, EngineError> {
+    let plain = matches!(Path::new(slot).components().collect::<Vec<_>>()[..], [Component::Normal(_)]);
+    if !plain || slot.contains(['/', '\\']) || slot.contains("..") {
+      return Err(EngineError::Save { path: self.directory.join(slot), message: format!("`{}` isn't a valid save slot name", slot) });
+    }
+    Ok(self.directory.join(format!("{}.{}", slot, self.format.extension())))
+  }
+
+  pub fn exists(&self, slot: &str) -> bool {
+    self.path(slot).is_ok_and(|path| path.is_file())
+  }
+
+  // The slots saved in this format, sorted by name.
+  pub fn slots(&self) -> Vec<String> {
+    let mut slots: Vec<String> = std::fs::read_dir(&self.directory).into_iter().flatten().flatten()
+      .map(|entry| entry.path())
+      .filter(|path| path.extension().is_some_and(|extension| extension == self.format.extension()))
+      .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+      .collect();
+    slots.sort();
+    slots
+  }
+
+  pub fn delete(&self, slot: &str) -> Result<(), EngineError> {
+    let path = self.path(slot)?;
+    std::fs::remove_file(&path).map_err(|err| save_error(&path, err))
+  }
+
+  // Writes the world to `slot`. The old save is only replaced once the new one is fully written, so
+  // a crash part way through can't leave a broken file behind.
+  pub fn save(&self, slot: &str, world: &World, registry: &TypeRegistry) -> Result<PathBuf, EngineError> {
+    let path = self.path(slot)?;
+    let bytes = self.to_bytes(world, registry).map_err(|message| EngineError::Save { path: path.clone(), message })?;
+    write_atomically(&path, &bytes).map_err(|err| save_error(&path, err))?;
+    Ok(path)
+  }
+
+  // Replaces every entity in the world with the ones saved in `slot`. Returns the saved entities'
+  // new ids.
+  pub fn load(&self, slot: &str, world: &mut World, registry: &TypeRegistry) -> Result<HashMap<Entity, Entity>, EngineError> {
+    let path = self.path(slot)?;
+    let bytes = std::fs::read(&path).map_err(|err| save_error(&path, err))?;
+    self.from_bytes(&bytes, world, registry).map_err(|message| EngineError::Save { path, message })
+  }
+
+  // The save as it would be written to a file, for keeping it somewhere else.
+  pub fn to_bytes(&self, world: &World, registry: &TypeRegistry) -> Result<Vec<u8>, String> {
+    let mut entities = Vec::new();
+    for entity in world.entities() {
+      let components: Vec<(String, Value)> = registry.serialize_entity(world, entity).map_err(|err| err.to_string())?
+        .into_iter()
+        .filter(|(name, _)| self.components.as_ref().is_none_or(|only| only.iter().any(|wanted| wanted == name)))
+        .map(|(name, value)| (name.to_string(), value))
+        .collect();
+      if !components.is_empty() {
+        entities.push(SavedEntity { id: entity, components });
+      }
+    }
+    let textures = world.get_resource::<TextureManager>().map_or_else(Vec::new, |manager| {
+      manager.iter().filter_map(|(handle, info)| Some((handle.0, info.path.clone()?))).collect()
+    });
+    let data = SaveData { version: SAVE_VERSION, textures, entities };
+
+    match self.format {
+      SaveFormat::Ron => ron::ser::to_string_pretty(&data, ron::ser::PrettyConfig::default()).map(String::into_bytes).map_err(|err| err.to_string()),
+      SaveFormat::Json => {
+        let data = data.map_components(|value| ron::to_string(&value).map_err(|err| err.to_string()))?;
+        serde_json::to_vec_pretty(&data).map_err(|err| err.to_string())
+      }
+      SaveFormat::Compressed => {
+        let text = ron::to_string(&data).map_err(|err| err.to_string())?;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(text.as_bytes()).and_then(|_| encoder.finish()).map_err(|err| err.to_string())
+      }
+      SaveFormat::Bincode => {
+        let data = data.map_components(|value| ron::to_string(&value).map_err(|err| err.to_string()))?;
+        bincode::serialize(&data).map_err(|err| err.to_string())
+      }
+    }
+  }
+
+  // Restores a save made by `to_bytes` in the same format, like `load`.
+  pub fn from_bytes(&self, bytes: &[u8], world: &mut World, registry: &TypeRegistry) -> Result<HashMap<Entity, Entity>, String> {
+    let data: SaveData<Value> = match self.format {
+      SaveFormat::Ron => ron::de::from_bytes(bytes).map_err(|err| err.to_string())?,
+      SaveFormat::Json => serde_json::from_slice::<SaveData<String>>(bytes).map_err(|err| err.to_string())?
+        .map_components(|text| ron::from_str(&text).map_err(|err| err.to_string()))?,
+      SaveFormat::Compressed => {
+        let mut text = String::new();
+        GzDecoder::new(bytes).read_to_string(&mut text).map_err(|err| err.to_string())?;
+        ron::from_str(&text).map_err(|err| err.to_string())?
+      }
+      SaveFormat::Bincode => bincode::deserialize::<SaveData<String>>(bytes).map_err(|err| err.to_string())?
+        .map_components(|text| ron::from_str(&text).map_err(|err| err.to_string()))?,
+    };
+    if data.version > SAVE_VERSION {
+      return Err(format!("save is version {}, newer than this game supports ({})", data.version, SAVE_VERSION));
+    }
+
+    // Reload the textures and read every component before touching the entities, so nothing's been
+    // thrown away if a texture is missing or a component is bad. Textures that weren't loaded
+    // already are freed again on a failure.
+    let mut handles = HashMap::new();
+    let mut fresh = Vec::new();
+    if let Some(mut manager) = world.get_resource_mut::<TextureManager>() {
+      for (handle, path) in &data.textures {
+        let count = manager.len();
+        match manager.load(path) {
+          Ok(loaded) => {
+            if manager.len() > count {
+              fresh.push(loaded);
+            }
+            handles.insert(TextureHandle(*handle), loaded);
+          }
+          Err(err) => {
+            release(&mut manager, &fresh);
+            return Err(err);
+          }
+        }
+      }
+    }
+
+    // The new entities are spawned alongside the old ones, as their ids are needed to read the
+    // components; they're empty, so a failure only has to despawn them again.
+    let old: Vec<Entity> = world.entities().collect();
+    let entities: HashMap<Entity, Entity> = data.entities.iter().map(|saved| (saved.id, world.spawn())).collect();
+    let components = with_entity_map(EntityMap { ids: entities.clone(), ..EntityMap::default() }, || with_handle_map(HandleMap { handles, ..HandleMap::default() }, || {
+      let mut components = Vec::new();
+      for saved in data.entities {
+        let entity = entities[&saved.id];
+        for (name, value) in saved.components {
+          match registry.get(&name) {
+            Some(info) => components.push((entity, info.deserialize(value).map_err(|err| format!("bad {} component: {}", name, err))?)),
+            None => log::warn!("skipping saved component {}, which isn't registered", name),
+          }
+        }
+      }
+      Ok::<_, String>(components)
+    }));
+    let components = match components {
+      Ok(components) => components,
+      Err(err) => {
+        entities.values().for_each(|&entity| { world.despawn(entity); });
+        if let Some(mut manager) = world.get_resource_mut::<TextureManager>() {
+          release(&mut manager, &fresh);
+        }
+        return Err(err);
+      }
+    };
+
+    for entity in old {
+      world.despawn(entity);
+    }
+    for (entity, component) in components {
+      component.insert(world, entity);
+    }
+    Ok(entities)
+  }
+}
+
+fn release(manager: &mut TextureManager, handles: &[TextureHandle]) {
+  for &handle in handles {
+    manager.remove(handle);
+  }
+}
+
+// Where this platform keeps application data, with a folder for `game` in it: `%APPDATA%` on
+// Windows, `~/Library/Application Support` on macOS and `$XDG_DATA_HOME` (`~/.local/share`)
+// elsewhere. Falls back to the working directory when none of those are set.
+pub fn save_directory(game: &str) -> PathBuf {
+  let var = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+  let base = if cfg!(target_os = "windows") {
+    var("APPDATA")
+  } else if cfg!(target_os = "macos") {
+    var("HOME").map(|home| home.join("Library").join("Application Support"))
+  } else {
+    var("XDG_DATA_HOME").or_else(|| var("HOME").map(|home| home.join(".local").join("share")))
+  };
+  base.unwrap_or_else(|| PathBuf::from(".")).join(game)
+}
+
+// Writes to a temporary file next to `path` and renames it over the top once it's on disk.
+fn write_atomically(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+  if let Some(parent) = path.parent() {
+    std::fs::create_dir_all(parent)?;
+  }
+  let mut temporary = path.as_os_str().to_owned();
+  temporary.push(".tmp");
+  let temporary = PathBuf::from(temporary);
+  {
+    let mut file = std::fs::File::create(&temporary)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+  }
+  std::fs::rename(&temporary, path)
+}
+
+fn save_error(path: &Path, err: std::io::Error) -> EngineError {
+  EngineError::Save { path: path.to_path_buf(), message: err.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::game_engine::ecs::Name;
+
+  #[derive(Debug, Serialize, Deserialize)]
+  struct Badge {
+    owner: Entity,
+    texture: TextureHandle,
+  }
+
+  impl Default for Badge {
+    fn default() -> Self {
+      Badge { owner: Entity::PLACEHOLDER, texture: TextureHandle(0) }
+    }
+  }
+
+  #[derive(Debug, Default, Serialize, Deserialize)]
+  struct Strict {
+    count: u32,
+  }
+
+  fn registry() -> TypeRegistry {
+    let mut registry = TypeRegistry::new();
+    registry.register::<Badge>("Badge");
+    registry
+  }
+
+  // A world whose entity ids and texture handles are already taken, so loading has to remap them.
+  fn busy_world() -> (World, Entity) {
+    let mut world = World::new();
+    let mut textures = TextureManager::new();
+    textures.from_rgba(glam::UVec2::ONE, vec![0; 4], false).unwrap();
+    world.insert_resource(textures);
+    let existing = world.spawn();
+    world.insert(existing, Name::new("existing"));
+    (world, existing)
+  }
+
+  #[test]
+  fn saves_round_trip_in_every_format() {
+    let directory = std::env::temp_dir().join(format!("save-round-trip-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let image = directory.join("badge.png");
+    image::RgbaImage::from_pixel(1, 1, image::Rgba([255, 0, 0, 255])).save(&image).unwrap();
+
+    let registry = registry();
+    let mut world = World::new();
+    let mut textures = TextureManager::new();
+    let texture = textures.load(&image).unwrap();
+    world.insert_resource(textures);
+    let owner = world.spawn();
+    world.insert(owner, Name::new("owner"));
+    let badge = world.spawn();
+    world.insert(badge, Badge { owner, texture });
+
+    for format in [SaveFormat::Ron, SaveFormat::Json, SaveFormat::Compressed, SaveFormat::Bincode] {
+      let save = SaveGame::in_directory(&directory).with_format(format);
+      let bytes = save.to_bytes(&world, &registry).unwrap();
+      let (mut loaded, _) = busy_world();
+      let ids = save.from_bytes(&bytes, &mut loaded, &registry).unwrap();
+
+      assert_eq!(loaded.entities().count(), 2, "{:?}", format);
+      assert_eq!(loaded.get::<Name>(ids[&owner]).as_deref(), Some(&Name::new("owner")), "{:?}", format);
+      let loaded_badge = loaded.get::<Badge>(ids[&badge]).unwrap();
+      assert_eq!(loaded_badge.owner, ids[&owner], "{:?}", format);
+      let manager = loaded.resource::<TextureManager>();
+      assert_ne!(loaded_badge.texture, texture, "{:?}", format);
+      assert_eq!(manager.get(loaded_badge.texture).and_then(|info| info.path.clone()), Some(image.clone()), "{:?}", format);
+    }
+    std::fs::remove_dir_all(&directory).unwrap();
+  }
+
+  #[test]
+  fn a_bad_component_leaves_the_world_alone() {
+    let image = std::env::temp_dir().join(format!("save-bad-component-{}.png", std::process::id()));
+    image::RgbaImage::from_pixel(1, 1, image::Rgba([255, 0, 0, 255])).save(&image).unwrap();
+    let mut world = World::new();
+    let mut textures = TextureManager::new();
+    let texture = textures.load(&image).unwrap();
+    world.insert_resource(textures);
+    let saved = world.spawn();
+    world.insert(saved, Badge { owner: Entity::PLACEHOLDER, texture });
+    let save = SaveGame::in_directory(std::env::temp_dir());
+    let bytes = save.to_bytes(&world, &registry()).unwrap();
+
+    // Read back as a type the saved fields don't fit.
+    let mut strict = TypeRegistry::new();
+    strict.register::<Strict>("Badge");
+    let (mut loaded, existing) = busy_world();
+    assert!(save.from_bytes(&bytes, &mut loaded, &strict).is_err());
+    std::fs::remove_file(&image).unwrap();
+    assert_eq!(loaded.entities().collect::<Vec<_>>(), vec![existing]);
+    assert_eq!(loaded.get::<Name>(existing).as_deref(), Some(&Name::new("existing")));
+    // The saved texture was loaded for the attempt, then freed again.
+    assert_eq!(loaded.resource::<TextureManager>().len(), 1);
+  }
+}
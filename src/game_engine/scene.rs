@@ -0,0 +1,194 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use ron::Value;
+use serde::{Deserialize, Serialize};
+
+use super::assets::Asset;
+use super::ecs::{merge, with_entity_map, Entity, EntityMap, Name, TypeRegistry, World};
+use super::error::EngineError;
+use super::graphics::texture::{with_handle_map, HandleMap, TextureManager};
+use super::prefab::{PrefabInstance, Prefabs};
+
+// How many prefabs deep instances can go, which only a prefab that contains itself should reach.
+const MAX_PREFAB_DEPTH: usize = 16;
+
+// Entities written as data, usually in a `.scene.ron` file:
+//
+// (
+//   textures: { "hero": "hero.png" },
+//   entities: [
+//     (
+//       name: Some("player"),
+//       components: {
+//         "Transform": (translation: (0.0, 1.0, 0.0)),
+//         "Health": (current: 10),
+//       },
+//       children: [
+//         (name: Some("sword"), components: { "Transform": (translation: (0.5, 0.0, 0.0)) }),
+//       ],
+//     ),
+//     (parent: Some("player"), components: { "Light": () }),
+//   ],
+// )
+//
+// Components are looked up by their name in the `TypeRegistry`, so a game's own components can be
+// used once they're registered. Fields left out keep the component's default. Inside a component an
+// `Entity` can be written as the name of one in the scene (or already in the world), and a
+// `TextureHandle` as the name of one of the scene's `textures`, whose paths are relative to the file.
+//
+// An entity with `prefab: Some("enemy_grunt")` starts as a copy of that prefab from the world's
+// `Prefabs`, with its own components laid over the prefab's and its children added to the prefab's.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Scene {
+  pub textures: BTreeMap<String, PathBuf>,
+  pub entities: Vec<SceneEntity>,
+  #[serde(skip)]
+  pub directory: PathBuf, // where texture paths are relative to
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SceneEntity {
+  pub name: Option<String>, // given to the entity as a `Name`
+  pub parent: Option<String>, // the name of an entity outside this one's `children`
+  pub prefab: Option<String>, // a prefab in the world's `Prefabs` this entity is an instance of
+  pub components: BTreeMap<String, Value>,
+  pub children: Vec<SceneEntity>,
+}
+
+impl Asset for Scene {
+  fn from_bytes(bytes: &[u8], path: &Path) -> Result<Self, String> {
+    let text = std::str::from_utf8(bytes).map_err(|err| err.to_string())?;
+    let mut scene = Scene::from_ron(text)?;
+    scene.directory = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    Ok(scene)
+  }
+}
+
+impl Scene {
+  pub fn from_ron(text: &str) -> Result<Self, String> {
+    ron::from_str(text).map_err(|err| err.to_string())
+  }
+
+  pub fn load(path: impl AsRef<Path>) -> Result<Self, EngineError> {
+    let path = path.as_ref();
+    let asset_error = |message: String| EngineError::Asset { path: path.to_path_buf(), message };
+    let bytes = std::fs::read(path).map_err(|err| asset_error(err.to_string()))?;
+    Scene::from_bytes(&bytes, path).map_err(asset_error)
+  }
+
+  // Spawns the scene's entities into `world`, which needs a `TextureManager` if the scene has
+  // textures. Returns them in the order they're written, parents before their children.
+  pub fn spawn(&self, world: &mut World, registry: &TypeRegistry) -> Result<Vec<Entity>, String> {
+    let scene = self.expand(world.get_resource::<Prefabs>().as_deref())?;
+    scene.spawn_expanded(world, registry)
+  }
+
+  // The scene with every prefab instance replaced by the prefab's entities, and texture paths made
+  // relative to the working directory instead of the file.
+  pub(crate) fn expand(&self, prefabs: Option<&Prefabs>) -> Result<Scene, String> {
+    let mut textures: BTreeMap<String, PathBuf> = self.textures.iter().map(|(name, path)| (name.clone(), self.directory.join(path))).collect();
+    let entities = self.entities.iter().map(|entity| expand_entity(entity, prefabs, &mut textures, 0)).collect::<Result<_, _>>()?;
+    Ok(Scene { textures, entities, directory: PathBuf::new() })
+  }
+
+  pub(crate) fn spawn_expanded(&self, world: &mut World, registry: &TypeRegistry) -> Result<Vec<Entity>, String> {
+    let mut handles = HandleMap::default();
+    if !self.textures.is_empty() {
+      let mut manager = world.get_resource_mut::<TextureManager>().ok_or("the world has no TextureManager for the scene's textures")?;
+      for (name, path) in &self.textures {
+        handles.names.insert(name.clone(), manager.load(self.directory.join(path))?);
+      }
+    }
+
+    // Names already in the world can be referred to too, unless the scene reuses them.
+    let mut entities = EntityMap::default();
+    world.query::<&Name>().for_each(|entity, name| {
+      entities.names.insert(name.as_str().to_string(), entity);
+    });
+    // Every entity exists before any component is read, so they can refer to each other.
+    let mut spawned = Vec::new();
+    for entity in &self.entities {
+      spawn_tree(world, entity, None, &mut spawned);
+    }
+    // Where a name's used more than once, e.g. by several instances of a prefab, the first wins.
+    let mut scene_names = HashSet::new();
+    for (entity, scene_entity, _) in &spawned {
+      if let Some(name) = scene_entity.name.as_ref().filter(|name| scene_names.insert(name.as_str())) {
+        entities.names.insert(name.clone(), *entity);
+      }
+    }
+
+    let result = with_entity_map(entities.clone(), || with_handle_map(handles, || {
+      for (entity, scene_entity, parent) in &spawned {
+        if let Some(name) = &scene_entity.name {
+          world.insert(*entity, Name::new(name.clone()));
+        }
+        if let Some(prefab) = &scene_entity.prefab {
+          world.insert(*entity, PrefabInstance(prefab.clone()));
+        }
+        for (component, value) in &scene_entity.components {
+          let info = registry.get(component).ok_or_else(|| format!("{} isn't a registered component", component))?;
+          info.patch(world, *entity, value.clone()).map_err(|err| format!("bad {} component: {}", component, err))?;
+        }
+        let parent = match (parent, &scene_entity.parent) {
+          (Some(parent), _) => Some(*parent),
+          (None, Some(name)) => Some(*entities.names.get(name).ok_or_else(|| format!("no entity named {}", name))?),
+          (None, None) => None,
+        };
+        if let Some(parent) = parent {
+          world.set_parent(*entity, parent);
+        }
+      }
+      Ok(())
+    }));
+    let spawned: Vec<Entity> = spawned.into_iter().map(|(entity, _, _)| entity).collect();
+    // Don't leave half a scene behind.
+    if let Err(err) = result {
+      for entity in spawned {
+        world.despawn(entity);
+      }
+      return Err(err);
+    }
+    Ok(spawned)
+  }
+}
+
+// Lays `entity` over the prefab it's an instance of, if it is one, all the way down. Textures from
+// prefabs are added to `textures` unless the name's already taken.
+fn expand_entity(entity: &SceneEntity, prefabs: Option<&Prefabs>, textures: &mut BTreeMap<String, PathBuf>, depth: usize) -> Result<SceneEntity, String> {
+  let children = entity.children.iter().map(|child| expand_entity(child, prefabs, textures, depth)).collect::<Result<Vec<_>, _>>()?;
+  let name = match &entity.prefab {
+    Some(name) => name,
+    None => return Ok(SceneEntity { children, ..entity.clone() }),
+  };
+  if depth >= MAX_PREFAB_DEPTH {
+    return Err(format!("prefab {} is nested too deeply; does it contain itself?", name));
+  }
+  let prefab = prefabs.and_then(|prefabs| prefabs.get(name)).ok_or_else(|| format!("no prefab named {}", name))?;
+  for (texture, path) in &prefab.textures {
+    textures.entry(texture.clone()).or_insert_with(|| prefab.directory.join(path));
+  }
+  let mut expanded = expand_entity(&prefab.entities[0], prefabs, textures, depth + 1)?;
+  for (component, value) in &entity.components {
+    let value = match expanded.components.remove(component) {
+      Some(base) => merge(base, value.clone()),
+      None => value.clone(),
+    };
+    expanded.components.insert(component.clone(), value);
+  }
+  expanded.name = entity.name.clone().or(expanded.name);
+  expanded.parent = entity.parent.clone();
+  expanded.prefab = Some(name.clone());
+  expanded.children.extend(children);
+  Ok(expanded)
+}
+
+fn spawn_tree<'s>(world: &mut World, entity: &'s SceneEntity, parent: Option<Entity>, spawned: &mut Vec<(Entity, &'s SceneEntity, Option<Entity>)>) {
+  let id = world.spawn();
+  spawned.push((id, entity, parent));
+  for child in &entity.children {
+    spawn_tree(world, child, Some(id), spawned);
+  }
+}
@@ -0,0 +1,384 @@
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use mlua::{FromLua, Function, Lua, LuaOptions, MetaMethod, RegistryKey, StdLib, Table, UserData, UserDataMethods, Variadic};
+use ron::value::{Map, Number};
+use ron::Value;
+
+use super::assets::FileWatcher;
+use super::ecs::{Entity, TypeRegistry, World};
+use super::error::EngineError;
+
+type LuaValue<'lua> = mlua::Value<'lua>;
+
+// An event a script pushed with `events.push` (or a plugin with `push_event`), for the engine to put
+// on its `EventQueue`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptEvent {
+  pub name: String,
+  pub data: Value,
+}
+
+struct Script {
+  path: PathBuf,
+  source: Option<String>, // waiting to be run at the next update
+  callbacks: Option<RegistryKey>, // what the script registered the last time it ran without errors
+}
+
+// Gameplay logic written in Lua. Each script runs in a sandbox with no access to files or the OS,
+// and gets its own globals, so scripts can't trip over each other's variables. From a script:
+//
+//   on_update(function(dt) ... end)          -- called every frame
+//   on_event("door_opened", function(data) ... end)
+//   events.push("door_opened", { door = 3 }) -- goes through the engine's EventQueue
+//
+//   local player = world.find("player")
+//   local transform = world.get(player, "Transform")
+//   transform.translation[1] = transform.translation[1] + 10 * dt
+//   world.set(player, "Transform", transform)
+//
+// `world` can spawn, despawn, find, query (`world.query("Transform", "Velocity")` gives the
+// entities with both) and get, set, check or remove any component registered in the
+// `TypeRegistry`. Components come out as tables and are patched with whatever fields are set, like
+// prefab overrides. The world is only there while a script is being run by the engine: at the top
+// of a script and in its callbacks.
+//
+// Scripts are run again when their files change. Their callbacks are replaced, and any local state
+// starts over; a script that fails to run keeps its old callbacks.
+pub struct Scripts {
+  pub watcher: FileWatcher,
+  lua: Lua,
+  scripts: Vec<Script>,
+}
+
+impl Scripts {
+  pub fn new() -> Self {
+    let lua = Lua::new_with(StdLib::TABLE | StdLib::STRING | StdLib::UTF8 | StdLib::MATH, LuaOptions::default())
+      .expect("the safe standard libraries always load");
+    // The base library is always there, but these two read files.
+    for name in ["dofile", "loadfile"] {
+      lua.globals().raw_remove(name).expect("globals can always be changed");
+    }
+    Scripts { watcher: FileWatcher::new(), lua, scripts: Vec::new() }
+  }
+
+  // Adds the script at `path`, which first runs at the next update. Syntax errors are caught here.
+  pub fn load(&mut self, path: impl AsRef<Path>) -> Result<(), EngineError> {
+    let path = path.as_ref();
+    let source = std::fs::read_to_string(path).map_err(|err| EngineError::Asset { path: path.to_path_buf(), message: err.to_string() })?;
+    self.check(path, &source).map_err(|message| EngineError::Asset { path: path.to_path_buf(), message })?;
+    self.watcher.watch(path);
+    match self.scripts.iter_mut().find(|script| script.path == path) {
+      Some(script) => script.source = Some(source),
+      None => self.scripts.push(Script { path: path.to_path_buf(), source: Some(source), callbacks: None }),
+    }
+    Ok(())
+  }
+
+  pub fn remove(&mut self, path: impl AsRef<Path>) -> bool {
+    let path = path.as_ref();
+    let count = self.scripts.len();
+    self.scripts.retain(|script| script.path != path);
+    self.watcher.unwatch(path);
+    self.lua.expire_registry_values();
+    self.scripts.len() != count
+  }
+
+  pub fn paths(&self) -> impl Iterator<Item = &Path> {
+    self.scripts.iter().map(|script| script.path.as_path())
+  }
+
+  // Runs scripts that are new or changed, then every `on_update` callback. Returns the events the
+  // scripts pushed.
+  pub fn update(&mut self, world: &mut World, registry: &TypeRegistry, delta: f32) -> Vec<ScriptEvent> {
+    for path in self.watcher.poll() {
+      match std::fs::read_to_string(&path).map_err(|err| err.to_string()).and_then(|source| self.check(&path, &source).map(|_| source)) {
+        Ok(source) => {
+          if let Some(script) = self.scripts.iter_mut().find(|script| script.path == path) {
+            script.source = Some(source);
+          }
+        }
+        Err(err) => log::warn!("couldn't reload {}: {}", path.display(), err),
+      }
+    }
+
+    let Scripts { lua, scripts, .. } = self;
+    let events = with_api(lua, world, registry, || {
+      for script in scripts.iter_mut() {
+        if let Some(source) = script.source.take() {
+          match run(lua, &script.path, &source) {
+            Ok(callbacks) => {
+              if script.callbacks.replace(callbacks).is_some() {
+                log::info!("reloaded {}", script.path.display());
+              }
+            }
+            Err(err) => log::warn!("{}", err),
+          }
+        }
+        call(lua, script, |callbacks| callbacks.get("update"), delta);
+      }
+    });
+    lua.expire_registry_values();
+    events
+  }
+
+  // Calls the `on_event` callbacks for `name` with `data`. Returns the events they pushed.
+  pub fn dispatch(&mut self, name: &str, data: &Value, world: &mut World, registry: &TypeRegistry) -> Vec<ScriptEvent> {
+    let Scripts { lua, scripts, .. } = self;
+    let data = match to_lua(lua, data) {
+      Ok(data) => data,
+      Err(err) => {
+        log::warn!("can't give the {} event to scripts: {}", name, err);
+        return Vec::new();
+      }
+    };
+    with_api(lua, world, registry, || {
+      for script in scripts.iter() {
+        call(lua, script, |callbacks| callbacks.get::<_, Table>("events")?.get(name), data.clone());
+      }
+    })
+  }
+
+  fn check(&self, path: &Path, source: &str) -> Result<(), String> {
+    self.lua.load(source).set_name(format!("@{}", path.display())).into_function().map(|_| ()).map_err(|err| err.to_string())
+  }
+}
+
+impl Default for Scripts {
+  fn default() -> Self {
+    Scripts::new()
+  }
+}
+
+// Runs a script's top level in globals of its own, returning the callbacks it registered.
+fn run(lua: &Lua, path: &Path, source: &str) -> mlua::Result<RegistryKey> {
+  let callbacks = lua.create_table()?;
+  callbacks.set("update", lua.create_table()?)?;
+  callbacks.set("events", lua.create_table()?)?;
+  let env = lua.create_table()?;
+  let meta = lua.create_table()?;
+  meta.set("__index", lua.globals())?;
+  env.set_metatable(Some(meta));
+  let on_update = lua.create_function(|_, (callbacks, callback): (Table, Function)| {
+    callbacks.get::<_, Table>("update")?.push(callback)
+  })?;
+  env.set("on_update", on_update.bind(callbacks.clone())?)?;
+  let on_event = lua.create_function(|lua, (callbacks, name, callback): (Table, String, Function)| {
+    let events: Table = callbacks.get("events")?;
+    if !events.contains_key(name.as_str())? {
+      events.set(name.as_str(), lua.create_table()?)?;
+    }
+    events.get::<_, Table>(name)?.push(callback)
+  })?;
+  env.set("on_event", on_event.bind(callbacks.clone())?)?;
+  lua.load(source).set_name(format!("@{}", path.display())).set_environment(env).exec()?;
+  lua.create_registry_value(callbacks)
+}
+
+// Calls every function in the list of a script's callbacks that `list` picks out, logging errors.
+fn call<'lua>(lua: &'lua Lua, script: &Script, list: impl FnOnce(Table<'lua>) -> mlua::Result<Option<Table<'lua>>>, argument: impl mlua::IntoLua<'lua> + Clone) {
+  let Some(callbacks) = &script.callbacks else { return };
+  let result = lua.registry_value::<Table>(callbacks).and_then(list).and_then(|functions| {
+    for function in functions.into_iter().flat_map(|functions| functions.sequence_values::<Function>()) {
+      function?.call::<_, ()>(argument.clone())?;
+    }
+    Ok(())
+  });
+  if let Err(err) = result {
+    log::warn!("{}: {}", script.path.display(), err);
+  }
+}
+
+// Makes `world` and `events` available to scripts while `f` runs.
+fn with_api(lua: &Lua, world: &mut World, registry: &TypeRegistry, f: impl FnOnce()) -> Vec<ScriptEvent> {
+  let world = RefCell::new(world);
+  let events = RefCell::new(Vec::new());
+  let component = |name: &str| registry.get(name).ok_or_else(|| mlua::Error::RuntimeError(format!("{} isn't a registered component", name)));
+  let result = lua.scope(|scope| {
+    let api = lua.create_table()?;
+    api.set("spawn", scope.create_function(|_, ()| Ok(LuaEntity(world.borrow_mut().spawn())))?)?;
+    api.set("despawn", scope.create_function(|_, entity: LuaEntity| Ok(world.borrow_mut().despawn_recursive(entity.0)))?)?;
+    api.set("alive", scope.create_function(|_, entity: LuaEntity| Ok(world.borrow().is_alive(entity.0)))?)?;
+    api.set("find", scope.create_function(|_, name: String| Ok(world.borrow().find_by_name(&name).map(LuaEntity)))?)?;
+    api.set("query", scope.create_function(|_, names: Variadic<String>| {
+      let infos = names.iter().map(|name| component(name)).collect::<mlua::Result<Vec<_>>>()?;
+      let world = world.borrow();
+      Ok(world.entities().filter(|entity| infos.iter().all(|info| info.has(&world, *entity))).map(LuaEntity).collect::<Vec<_>>())
+    })?)?;
+    api.set("has", scope.create_function(|_, (entity, name): (LuaEntity, String)| Ok(component(&name)?.has(&world.borrow(), entity.0)))?)?;
+    api.set("get", scope.create_function(|lua, (entity, name): (LuaEntity, String)| {
+      match component(&name)?.serialize(&world.borrow(), entity.0) {
+        Some(value) => to_lua(lua, &value.map_err(mlua::Error::external)?),
+        None => Ok(LuaValue::Nil),
+      }
+    })?)?;
+    api.set("set", scope.create_function(|_, (entity, name, value): (LuaEntity, String, LuaValue)| {
+      let info = component(&name)?;
+      let mut world = world.borrow_mut();
+      if !world.is_alive(entity.0) {
+        return Err(mlua::Error::RuntimeError(format!("can't set {} on a despawned entity", name)));
+      }
+      let current = match info.serialize(&world, entity.0) {
+        Some(current) => current,
+        None => info.default_value(),
+      }.map_err(mlua::Error::external)?;
+      let patch = from_lua(value, Some(&current))?;
+      info.patch(&mut world, entity.0, patch).map_err(|err| mlua::Error::RuntimeError(format!("bad {} component: {}", name, err)))
+    })?)?;
+    api.set("remove", scope.create_function(|_, (entity, name): (LuaEntity, String)| Ok(component(&name)?.remove(&mut world.borrow_mut(), entity.0)))?)?;
+    lua.globals().set("world", api)?;
+
+    let events_api = lua.create_table()?;
+    events_api.set("push", scope.create_function(|_, (name, data): (String, LuaValue)| {
+      events.borrow_mut().push(ScriptEvent { name, data: from_lua(data, None)? });
+      Ok(())
+    })?)?;
+    lua.globals().set("events", events_api)?;
+
+    f();
+    lua.globals().raw_remove("world")?;
+    lua.globals().raw_remove("events")
+  });
+  if let Err(err) = result {
+    log::warn!("couldn't run scripts: {}", err);
+  }
+  events.into_inner()
+}
+
+// How an `Entity` looks to scripts. Entities inside components are handed over like this too.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LuaEntity(Entity);
+
+impl UserData for LuaEntity {
+  fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    methods.add_meta_method(MetaMethod::Eq, |_, entity, other: LuaEntity| Ok(*entity == other));
+    methods.add_meta_method(MetaMethod::ToString, |_, entity, ()| Ok(format!("Entity({}v{})", entity.0.index, entity.0.generation)));
+  }
+}
+
+impl<'lua> FromLua<'lua> for LuaEntity {
+  fn from_lua(value: LuaValue<'lua>, _: &'lua Lua) -> mlua::Result<Self> {
+    match value {
+      LuaValue::UserData(data) => Ok(*data.borrow::<LuaEntity>()?),
+      value => Err(mlua::Error::FromLuaConversionError { from: value.type_name(), to: "Entity", message: None }),
+    }
+  }
+}
+
+fn to_lua<'lua>(lua: &'lua Lua, value: &Value) -> mlua::Result<LuaValue<'lua>> {
+  Ok(match value {
+    Value::Bool(value) => LuaValue::Boolean(*value),
+    Value::Char(value) => LuaValue::String(lua.create_string(value.to_string())?),
+    Value::String(value) => LuaValue::String(lua.create_string(value)?),
+    Value::Number(Number::Integer(value)) => LuaValue::Integer(*value),
+    Value::Number(Number::Float(value)) => LuaValue::Number(value.get()),
+    Value::Option(None) => LuaValue::Nil,
+    Value::Option(Some(value)) => to_lua(lua, value)?,
+    Value::Unit => LuaValue::Table(lua.create_table()?),
+    Value::Seq(values) => {
+      let table = lua.create_table_with_capacity(values.len(), 0)?;
+      for (index, value) in values.iter().enumerate() {
+        table.raw_set(index + 1, to_lua(lua, value)?)?;
+      }
+      LuaValue::Table(table)
+    }
+    Value::Map(map) => match as_entity(map) {
+      Some(entity) => lua.pack(LuaEntity(entity))?,
+      None => {
+        let table = lua.create_table_with_capacity(0, map.len())?;
+        for (key, value) in map.iter() {
+          table.raw_set(to_lua(lua, key)?, to_lua(lua, value)?)?;
+        }
+        LuaValue::Table(table)
+      }
+    },
+  })
+}
+
+// Turns a script's value back into a component value, shaped like `like` where there is one, since
+// Lua doesn't tell `Some(x)` from `x` or whole floats from integers.
+fn from_lua(value: LuaValue, like: Option<&Value>) -> mlua::Result<Value> {
+  if let Some(Value::Option(inner)) = like {
+    return Ok(match value {
+      LuaValue::Nil => Value::Option(None),
+      value => Value::Option(Some(Box::new(from_lua(value, inner.as_deref())?))),
+    });
+  }
+  // A newtype like `Name` is a tuple of one, but can be set from its one value.
+  if let (Some(Value::Seq(items)), false) = (like, matches!(value, LuaValue::Table(_) | LuaValue::Nil)) {
+    if items.len() == 1 {
+      return Ok(Value::Seq(vec![from_lua(value, items.first())?]));
+    }
+  }
+  Ok(match value {
+    LuaValue::Nil => Value::Unit,
+    LuaValue::Boolean(value) => Value::Bool(value),
+    LuaValue::Integer(value) => match like {
+      Some(Value::Number(Number::Float(_))) => Value::Number(Number::new(value as f64)),
+      _ => Value::Number(Number::new(value)),
+    },
+    LuaValue::Number(value) => match like {
+      Some(Value::Number(Number::Integer(_))) if value.fract() == 0.0 => Value::Number(Number::new(value as i64)),
+      _ => Value::Number(Number::new(value)),
+    },
+    LuaValue::String(value) => {
+      let value = value.to_str()?;
+      let mut chars = value.chars();
+      match (like, chars.next(), chars.next()) {
+        (Some(Value::Char(_)), Some(char), None) => Value::Char(char),
+        _ => Value::String(value.to_string()),
+      }
+    }
+    LuaValue::UserData(data) => entity_value(data.borrow::<LuaEntity>()?.0),
+    LuaValue::Table(table) => {
+      let is_seq = match like {
+        Some(Value::Seq(_)) => true,
+        Some(Value::Map(_)) => false,
+        _ => table.raw_len() > 0,
+      };
+      if is_seq {
+        let item = match like {
+          Some(Value::Seq(items)) => items.first(),
+          _ => None,
+        };
+        Value::Seq(table.sequence_values::<LuaValue>().map(|value| from_lua(value?, item)).collect::<mlua::Result<_>>()?)
+      } else {
+        let mut map = Map::new();
+        for pair in table.pairs::<LuaValue, LuaValue>() {
+          let (key, value) = pair?;
+          let key = from_lua(key, None)?;
+          let like = match like {
+            Some(Value::Map(fields)) => get(fields, &key),
+            _ => None,
+          };
+          let value = from_lua(value, like)?;
+          map.insert(key, value);
+        }
+        // An empty table is also how a script writes a component with no fields.
+        if map.is_empty() && like.is_none() { Value::Unit } else { Value::Map(map) }
+      }
+    }
+    value => return Err(mlua::Error::RuntimeError(format!("a {} can't go in a component", value.type_name()))),
+  })
+}
+
+fn get<'a>(map: &'a Map, key: &Value) -> Option<&'a Value> {
+  map.iter().find(|(candidate, _)| *candidate == key).map(|(_, value)| value)
+}
+
+fn as_entity(map: &Map) -> Option<Entity> {
+  let field = |name: &str| match get(map, &Value::String(name.to_string())) {
+    Some(Value::Number(Number::Integer(value))) => u32::try_from(*value).ok(),
+    _ => None,
+  };
+  match map.len() {
+    2 => Some(Entity { index: field("index")?, generation: field("generation")? }),
+    _ => None,
+  }
+}
+
+fn entity_value(entity: Entity) -> Value {
+  let mut map = Map::new();
+  map.insert(Value::String("index".to_string()), Value::Number(Number::new(entity.index as i64)));
+  map.insert(Value::String("generation".to_string()), Value::Number(Number::new(entity.generation as i64)));
+  Value::Map(map)
+}
@@ -0,0 +1,118 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+use glam::Vec2;
+
+use super::color::Color;
+use super::time::Instant;
+use super::ui::{FontId, UiDraw};
+
+// Frames kept for the rolling numbers: a couple of seconds at typical rates.
+const WINDOW: usize = 120;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct FrameSample {
+  frame: Duration, // start of this frame to the start of the next
+  cpu: Duration, // updating the game: the task, schedules and events
+  render: Duration, // recording, submitting and presenting on the CPU
+  gpu: Option<Duration>,
+  draw_calls: u32,
+}
+
+// How the last couple of seconds of frames went, kept by the engine (`engine.stats()`). Times are
+// averaged over the window; `gpu_time` is from submit to the GPU finishing, so it includes any
+// time the work spent queued.
+#[derive(Debug, Clone, Default)]
+pub struct FrameStats {
+  samples: VecDeque<FrameSample>,
+  last_start: Option<Instant>,
+  pending: Option<FrameSample>, // the newest frame, until the next start tells us how long it was
+}
+
+impl FrameStats {
+  pub fn new() -> Self {
+    FrameStats::default()
+  }
+
+  // Called by the engine once per frame with when it started and where the time went.
+  pub fn record(&mut self, start: Instant, cpu: Duration, render: Duration, gpu: Option<Duration>, draw_calls: u32) {
+    if let (Some(last_start), Some(mut sample)) = (self.last_start, self.pending.take()) {
+      sample.frame = start.duration_since(last_start);
+      if self.samples.len() == WINDOW {
+        self.samples.pop_front();
+      }
+      self.samples.push_back(sample);
+    }
+    self.last_start = Some(start);
+    self.pending = Some(FrameSample { frame: Duration::ZERO, cpu, render, gpu, draw_calls });
+  }
+
+  pub fn fps(&self) -> f32 {
+    let average = self.frame_time_avg().as_secs_f32();
+    if average > 0.0 { 1.0 / average } else { 0.0 }
+  }
+
+  pub fn frame_time_avg(&self) -> Duration {
+    self.average(|sample| sample.frame)
+  }
+
+  pub fn frame_time_min(&self) -> Duration {
+    self.samples.iter().map(|sample| sample.frame).min().unwrap_or_default()
+  }
+
+  pub fn frame_time_max(&self) -> Duration {
+    self.samples.iter().map(|sample| sample.frame).max().unwrap_or_default()
+  }
+
+  pub fn cpu_time(&self) -> Duration {
+    self.average(|sample| sample.cpu)
+  }
+
+  pub fn render_time(&self) -> Duration {
+    self.average(|sample| sample.render)
+  }
+
+  // `None` when the renderer can't tell.
+  pub fn gpu_time(&self) -> Option<Duration> {
+    let times: Vec<Duration> = self.samples.iter().filter_map(|sample| sample.gpu).collect();
+    if times.is_empty() {
+      return None;
+    }
+    Some(times.iter().sum::<Duration>() / times.len() as u32)
+  }
+
+  // In the latest frame.
+  pub fn draw_calls(&self) -> u32 {
+    self.pending.map_or(0, |sample| sample.draw_calls)
+  }
+
+  pub fn frame_count(&self) -> usize {
+    self.samples.len()
+  }
+
+  fn average(&self, value: impl Fn(&FrameSample) -> Duration) -> Duration {
+    if self.samples.is_empty() {
+      return Duration::ZERO;
+    }
+    self.samples.iter().map(value).sum::<Duration>() / self.samples.len() as u32
+  }
+
+  // A few lines of text with the numbers, top-left at `position`.
+  pub fn draw_overlay(&self, ui: &mut UiDraw, font: FontId, position: Vec2) {
+    let ms = |duration: Duration| duration.as_secs_f32() * 1000.0;
+    let lines = [
+      format!("{:.0} fps", self.fps()),
+      format!("frame {:.1} ms (min {:.1}, max {:.1})", ms(self.frame_time_avg()), ms(self.frame_time_min()), ms(self.frame_time_max())),
+      format!("cpu {:.1} ms  render {:.1} ms", ms(self.cpu_time()), ms(self.render_time())),
+      match self.gpu_time() {
+        Some(gpu) => format!("gpu {:.1} ms", ms(gpu)),
+        None => "gpu n/a".to_string(),
+      },
+      format!("{} draw calls", self.draw_calls()),
+    ];
+    let size = 14.0;
+    ui.rect(position - Vec2::splat(4.0), position + Vec2::new(260.0, lines.len() as f32 * size * 1.25 + 4.0), Color::rgba(0.0, 0.0, 0.0, 0.6));
+    for (i, line) in lines.into_iter().enumerate() {
+      ui.text(font, line, position + Vec2::new(0.0, i as f32 * size * 1.25), size, Color::WHITE);
+    }
+  }
+}
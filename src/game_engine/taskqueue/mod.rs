@@ -1,2 +1,3 @@
 pub mod task;
+#[allow(clippy::module_inception)]
 pub mod taskqueue;
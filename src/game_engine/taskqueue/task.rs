@@ -1,12 +1,302 @@
-#[derive(Debug)]
+use std::fmt;
+
+use crate::game_engine::Engine;
+
+pub type EventTask = Box<dyn FnMut(&mut Engine)>;
+pub type WaitCondition = Box<dyn FnMut(&mut Engine) -> bool>;
+
 pub struct GameEvent {
   pub name: String,
-  pub frames: u32,
-  pub task: fn(),
+  pub frames: u32, // frames left to run
+  pub repeat: Option<u32>, // re-armed with this many frames when `frames` runs out, instead of expiring
+  pub cycles: u32, // how many times it has been re-armed
+  pub task: EventTask,
+  pub sequence: Option<Sequence>, // when set, the event lasts until this finishes instead of counting frames
 }
 
 impl GameEvent {
+  pub fn new(name: impl Into<String>, frames: u32, task: impl FnMut(&mut Engine) + 'static) -> Self {
+    GameEvent { name: name.into(), frames, repeat: None, cycles: 0, task: Box::new(task), sequence: None }
+  }
+
+  // Runs every frame until cancelled, counting `frames` down and starting again at zero.
+  pub fn repeating(name: impl Into<String>, frames: u32, task: impl FnMut(&mut Engine) + 'static) -> Self {
+    GameEvent { repeat: Some(frames.max(1)), ..GameEvent::new(name, frames.max(1), task) }
+  }
+
+  // Works through `sequence` a step at a time, over as many frames as its waits take.
+  pub fn sequence(name: impl Into<String>, sequence: Sequence) -> Self {
+    GameEvent { sequence: Some(sequence), ..GameEvent::new(name, 1, |_| {}) }
+  }
+
   pub fn dec(&mut self) {
-    self.frames -= 1
+    self.frames = self.frames.saturating_sub(1);
+    if let (0, Some(frames)) = (self.frames, self.repeat) {
+      self.frames = frames;
+      self.cycles += 1;
+    }
+  }
+
+  pub fn is_finished(&self) -> bool {
+    self.frames == 0
+  }
+
+  // One frame of the event: runs its task, then either counts its frames down or moves its
+  // sequence on by `delta` seconds.
+  pub fn run(&mut self, engine: &mut Engine, delta: f32) {
+    (self.task)(engine);
+    match &mut self.sequence {
+      Some(sequence) => {
+        if sequence.advance(engine, delta) {
+          self.frames = 0;
+        }
+      }
+      None => self.dec(),
+    }
+  }
+}
+
+impl fmt::Debug for GameEvent {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("GameEvent").field("name", &self.name).field("frames", &self.frames).field("repeat", &self.repeat).finish_non_exhaustive()
+  }
+}
+
+// A step of a `Sequence`. Waits hold the sequence up; runs happen straight away, so a run after a
+// wait goes in the frame the wait ends.
+pub enum Step {
+  WaitFrames(u32),
+  WaitSeconds(f32), // of `Time::delta`, so it follows the engine clock
+  WaitUntil(WaitCondition), // checked once a frame, starting the frame it's reached
+  Run(EventTask),
+}
+
+impl Step {
+  pub fn wait_frames(frames: u32) -> Self {
+    Step::WaitFrames(frames)
+  }
+
+  pub fn wait_seconds(seconds: f32) -> Self {
+    Step::WaitSeconds(seconds)
+  }
+
+  pub fn wait_until(condition: impl FnMut(&mut Engine) -> bool + 'static) -> Self {
+    Step::WaitUntil(Box::new(condition))
+  }
+
+  pub fn run(task: impl FnMut(&mut Engine) + 'static) -> Self {
+    Step::Run(Box::new(task))
+  }
+
+  // A run that only ever happens once, even in a looping sequence.
+  pub fn run_once(task: impl FnOnce(&mut Engine) + 'static) -> Self {
+    let mut task = Some(task);
+    Step::run(move |engine| {
+      if let Some(task) = task.take() {
+        task(engine);
+      }
+    })
+  }
+}
+
+impl fmt::Debug for Step {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Step::WaitFrames(frames) => f.debug_tuple("WaitFrames").field(frames).finish(),
+      Step::WaitSeconds(seconds) => f.debug_tuple("WaitSeconds").field(seconds).finish(),
+      Step::WaitUntil(_) => f.write_str("WaitUntil(..)"),
+      Step::Run(_) => f.write_str("Run(..)"),
+    }
+  }
+}
+
+// How far the current wait has to go.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Waiting {
+  Frames(u32),
+  Seconds(f32),
+}
+
+// A script of waits and runs for cutscenes, cooldowns and the like, run a frame at a time by a
+// `GameEvent`. Looping sequences start over when they reach the end, until cancelled.
+#[derive(Debug)]
+pub struct Sequence {
+  steps: Vec<Step>,
+  looping: bool,
+  current: usize,
+  waiting: Option<Waiting>, // set once the current wait step has started
+  overshoot: f32, // seconds the last timed wait ran past its end, taken off the next one
+}
+
+impl Sequence {
+  pub fn new(steps: impl IntoIterator<Item = Step>) -> Self {
+    Sequence { steps: steps.into_iter().collect(), looping: false, current: 0, waiting: None, overshoot: 0.0 }
+  }
+
+  pub fn looping(steps: impl IntoIterator<Item = Step>) -> Self {
+    Sequence { looping: true, ..Sequence::new(steps) }
+  }
+
+  pub fn is_looping(&self) -> bool {
+    self.looping
+  }
+
+  // The index of the step it's on.
+  pub fn current(&self) -> usize {
+    self.current
+  }
+
+  // Runs steps until one has to wait for a later frame. Returns true once the sequence is over.
+  pub fn advance(&mut self, engine: &mut Engine, delta: f32) -> bool {
+    self.advance_with(delta, |step| match step {
+      Step::Run(task) => {
+        task(engine);
+        true
+      }
+      Step::WaitUntil(condition) => condition(engine),
+      _ => true,
+    })
+  }
+
+  // `advance`, with runs and conditions handled by `visit` so the timing works without an engine.
+  fn advance_with(&mut self, delta: f32, mut visit: impl FnMut(&mut Step) -> bool) -> bool {
+    let mut visited = 0;
+    loop {
+      if self.current >= self.steps.len() {
+        if !self.looping || self.steps.is_empty() {
+          return true;
+        }
+        self.current = 0;
+      }
+      // Each step gets one visit a frame, so a loop with no waits in it runs once a frame. After a
+      // full lap only the next wait is started, so its count begins this frame.
+      if visited == self.steps.len() {
+        if self.waiting.is_none() && matches!(self.steps[self.current], Step::WaitFrames(_) | Step::WaitSeconds(_)) {
+          self.step(delta, &mut visit);
+        }
+        return false;
+      }
+      visited += 1;
+      if !self.step(delta, &mut visit) {
+        return false;
+      }
+      self.waiting = None;
+      self.current += 1;
+    }
+  }
+
+  // Works on the current step; true if the sequence can move past it.
+  fn step(&mut self, delta: f32, visit: &mut impl FnMut(&mut Step) -> bool) -> bool {
+    match (&mut self.steps[self.current], self.waiting) {
+      (Step::WaitFrames(frames), None) => {
+        self.waiting = Some(Waiting::Frames(*frames));
+        *frames == 0
+      }
+      (Step::WaitFrames(_), Some(Waiting::Frames(remaining))) => {
+        self.waiting = Some(Waiting::Frames(remaining.saturating_sub(1)));
+        remaining <= 1
+      }
+      (Step::WaitSeconds(seconds), None) => {
+        let remaining = *seconds - std::mem::take(&mut self.overshoot);
+        self.waiting = Some(Waiting::Seconds(remaining));
+        remaining <= 0.0
+      }
+      (Step::WaitSeconds(_), Some(Waiting::Seconds(remaining))) => {
+        let remaining = remaining - delta;
+        self.waiting = Some(Waiting::Seconds(remaining));
+        if remaining <= 0.0 {
+          self.overshoot = -remaining;
+        }
+        remaining <= 0.0
+      }
+      (step, _) => visit(step),
+    }
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Advances a frame, counting the runs it reaches instead of calling them.
+  fn frame(sequence: &mut Sequence, delta: f32, runs: &mut u32) -> bool {
+    sequence.advance_with(delta, |step| {
+      if let Step::Run(_) = step {
+        *runs += 1;
+      }
+      true
+    })
+  }
+
+  #[test]
+  fn frame_waits_run_the_task_that_many_frames_later() {
+    let mut sequence = Sequence::new([Step::wait_frames(3), Step::run(|_| {})]);
+    let mut runs = 0;
+    for _ in 0..3 {
+      assert!(!frame(&mut sequence, 0.0, &mut runs));
+      assert_eq!(runs, 0);
+    }
+    assert!(frame(&mut sequence, 0.0, &mut runs));
+    assert_eq!(runs, 1);
+  }
+
+  #[test]
+  fn looping_frame_waits_keep_their_period() {
+    let mut sequence = Sequence::looping([Step::wait_frames(2), Step::run(|_| {})]);
+    let mut runs = 0;
+    let fired: Vec<u32> = (0..7).map(|_| {
+      frame(&mut sequence, 0.0, &mut runs);
+      runs
+    }).collect();
+    assert_eq!(fired, [0, 0, 1, 1, 2, 2, 3]);
+  }
+
+  #[test]
+  fn looping_timers_carry_overshoot_into_the_next_period() {
+    let mut sequence = Sequence::looping([Step::wait_seconds(1.0), Step::run(|_| {})]);
+    let mut runs = 0;
+    // 0.3s frames: due at 1.0s and 2.0s, which land on the frames at 1.2s and 2.1s.
+    let fired: Vec<u32> = (0..8).map(|_| {
+      assert!(!frame(&mut sequence, 0.3, &mut runs));
+      runs
+    }).collect();
+    assert_eq!(fired, [0, 0, 0, 0, 1, 1, 1, 2]);
+  }
+
+  #[test]
+  fn steps_between_waits_run_in_the_same_frame() {
+    let mut sequence = Sequence::new([Step::run(|_| {}), Step::run(|_| {}), Step::wait_frames(1), Step::run(|_| {})]);
+    let mut runs = 0;
+    assert!(!frame(&mut sequence, 0.0, &mut runs));
+    assert_eq!(runs, 2);
+    assert!(frame(&mut sequence, 0.0, &mut runs));
+    assert_eq!(runs, 3);
+  }
+
+  #[test]
+  fn a_loop_without_waits_runs_once_a_frame() {
+    let mut sequence = Sequence::looping([Step::run(|_| {})]);
+    let mut runs = 0;
+    for _ in 0..3 {
+      assert!(!frame(&mut sequence, 0.0, &mut runs));
+    }
+    assert_eq!(runs, 3);
+  }
+
+  #[test]
+  fn wait_until_holds_until_the_condition_passes() {
+    let mut sequence = Sequence::new([Step::wait_until(|_| false), Step::run(|_| {})]);
+    let mut checks = 0;
+    for pass in [false, false, true] {
+      let done = sequence.advance_with(0.0, |step| match step {
+        Step::WaitUntil(_) => {
+          checks += 1;
+          pass
+        }
+        _ => true,
+      });
+      assert_eq!(done, pass);
+    }
+    assert_eq!(checks, 3);
+  }
+}
@@ -1,34 +1,229 @@
-use crate::game_engine::task::GameEvent;
+use std::sync::atomic::{AtomicU64, Ordering};
 
-pub trait GameEventQueue {
-  fn remove(&mut self, name: String);
-  fn run_all(&mut self);
-  fn prune(&mut self);
+use crate::game_engine::Engine;
+use crate::game_engine::task::{GameEvent, Sequence, Step};
+
+// Ids come from one counter for every queue, so an id stays unique when queues are merged.
+static NEXT_EVENT_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EventId(pub u64);
+
+#[derive(Debug)]
+struct QueuedEvent {
+  id: EventId,
+  priority: i32,
+  event: GameEvent,
 }
 
-impl GameEventQueue for Vec<GameEvent> {
-  fn remove(&mut self, name: String) {
-    match self.iter().position(|event| event.name == name) {
-      None => {},
-      Some(i) => {self.swap_remove(i);}
-    }
+// The engine's pending `GameEvent`s. Every frame each one runs once, highest priority first and in
+// push order within a priority, and counts down its frames; `prune` then drops the finished ones.
+#[derive(Debug, Default)]
+pub struct EventQueue {
+  events: Vec<QueuedEvent>,
+  cancelled: Vec<EventId>, // ids cancelled while not in this queue, e.g. while it was running
+}
+
+impl EventQueue {
+  pub fn new() -> Self {
+    EventQueue::default()
+  }
+
+  pub fn push(&mut self, event: GameEvent) -> EventId {
+    self.push_with_priority(event, 0)
   }
 
-  fn run_all(&mut self) {
-    self.iter_mut().for_each(|event| {
-      (event.task)();
-      event.dec();
-    });
+  pub fn push_with_priority(&mut self, event: GameEvent, priority: i32) -> EventId {
+    let id = EventId(NEXT_EVENT_ID.fetch_add(1, Ordering::Relaxed));
+    let index = self.events.partition_point(|queued| queued.priority >= priority);
+    self.events.insert(index, QueuedEvent { id, priority, event });
+    id
   }
 
-  fn prune(&mut self) {
-    for n in 0..self.len() {
-      match self.get(n) {
-        None => (),
-        Some(event) => if event.frames == 0 {
-          self.remove(n);
-        }
+  // Returns whether the event was still queued. Cancelling an event from inside a running event
+  // takes effect when the engine puts the queue back together at the end of the frame.
+  pub fn cancel(&mut self, id: EventId) -> bool {
+    match self.events.iter().position(|queued| queued.id == id) {
+      Some(index) => {
+        self.events.remove(index);
+        true
+      }
+      None => {
+        self.cancelled.push(id);
+        false
       }
     }
   }
+
+  pub fn contains(&self, id: EventId) -> bool {
+    self.events.iter().any(|queued| queued.id == id)
+  }
+
+  pub fn get(&self, id: EventId) -> Option<&GameEvent> {
+    self.events.iter().find(|queued| queued.id == id).map(|queued| &queued.event)
+  }
+
+  pub fn get_mut(&mut self, id: EventId) -> Option<&mut GameEvent> {
+    self.events.iter_mut().find(|queued| queued.id == id).map(|queued| &mut queued.event)
+  }
+
+  // The first queued event called `name`, for finding events pushed without keeping their id.
+  pub fn find(&self, name: &str) -> Option<EventId> {
+    self.events.iter().find(|queued| queued.event.name == name).map(|queued| queued.id)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (EventId, &GameEvent)> {
+    self.events.iter().map(|queued| (queued.id, &queued.event))
+  }
+
+  pub fn len(&self) -> usize {
+    self.events.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.events.is_empty()
+  }
+
+  // Calls `task` once, `seconds` from now by the engine clock.
+  pub fn after_seconds(&mut self, seconds: f32, task: impl FnOnce(&mut Engine) + 'static) -> EventId {
+    self.push(GameEvent::sequence("after-seconds", Sequence::new([Step::wait_seconds(seconds), Step::run_once(task)])))
+  }
+
+  // Calls `task` once, `frames` frames from now.
+  pub fn after_frames(&mut self, frames: u32, task: impl FnOnce(&mut Engine) + 'static) -> EventId {
+    self.push(GameEvent::sequence("after-frames", Sequence::new([Step::wait_frames(frames), Step::run_once(task)])))
+  }
+
+  // Calls `task` every `seconds` until cancelled, starting `seconds` from now. Time left over from
+  // one period comes off the next, so it doesn't drift.
+  pub fn every_seconds(&mut self, seconds: f32, task: impl FnMut(&mut Engine) + 'static) -> EventId {
+    self.push(GameEvent::sequence("every-seconds", Sequence::looping([Step::wait_seconds(seconds), Step::run(task)])))
+  }
+
+  // Calls `task` every `frames` frames until cancelled, starting `frames` from now.
+  pub fn every_frames(&mut self, frames: u32, task: impl FnMut(&mut Engine) + 'static) -> EventId {
+    self.push(GameEvent::sequence("every-frames", Sequence::looping([Step::wait_frames(frames.max(1)), Step::run(task)])))
+  }
+
+  // Runs `steps` in order, starting this frame, e.g. for a cutscene:
+  // `sequence([Step::run(fade_out), Step::wait_seconds(1.0), Step::run(load_level)])`.
+  pub fn sequence(&mut self, steps: impl IntoIterator<Item = Step>) -> EventId {
+    self.push(GameEvent::sequence("sequence", Sequence::new(steps)))
+  }
+
+  pub fn run_all(&mut self, engine: &mut Engine) {
+    let delta = engine.time.delta_seconds();
+    self.events.iter_mut().for_each(|queued| queued.event.run(engine, delta));
+  }
+
+  // Drops every event whose frames have run out. Repeating events re-arm in `dec` and never do.
+  pub fn prune(&mut self) {
+    self.events.retain(|queued| !queued.event.is_finished());
+  }
+
+  // Moves `other`'s events in, keeping priority order, and applies its cancellations to ours.
+  pub fn append(&mut self, other: &mut EventQueue) {
+    for queued in other.events.drain(..) {
+      let index = self.events.partition_point(|existing| existing.priority >= queued.priority);
+      self.events.insert(index, queued);
+    }
+    for id in other.cancelled.drain(..) {
+      self.cancel(id);
+    }
+    self.cancelled.clear();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn event(name: &str, frames: u32) -> GameEvent {
+    GameEvent::new(name, frames, |_| {})
+  }
+
+  // What `run_all` does to the counters, without an engine to run the tasks on.
+  fn count_down(queue: &mut EventQueue) {
+    queue.events.iter_mut().for_each(|queued| queued.event.dec());
+  }
+
+  fn names(queue: &EventQueue) -> Vec<&str> {
+    queue.iter().map(|(_, event)| event.name.as_str()).collect()
+  }
+
+  #[test]
+  fn events_expiring_on_the_same_frame_are_all_pruned() {
+    let mut queue = EventQueue::new();
+    queue.push(event("a", 1));
+    queue.push(event("b", 1));
+    queue.push(event("c", 2));
+    queue.push(event("d", 1));
+
+    count_down(&mut queue);
+    queue.prune();
+    assert_eq!(names(&queue), ["c"]);
+
+    count_down(&mut queue);
+    queue.prune();
+    assert!(queue.is_empty());
+  }
+
+  #[test]
+  fn zero_frame_events_are_pruned_without_underflowing() {
+    let mut queue = EventQueue::new();
+    queue.push(event("a", 0));
+    count_down(&mut queue);
+    queue.prune();
+    assert!(queue.is_empty());
+  }
+
+  #[test]
+  fn repeating_events_re_arm_instead_of_expiring() {
+    let mut queue = EventQueue::new();
+    let id = queue.push(GameEvent::repeating("tick", 2, |_| {}));
+    for _ in 0..5 {
+      count_down(&mut queue);
+      queue.prune();
+    }
+    let event = queue.get(id).unwrap();
+    assert_eq!(event.frames, 1);
+    assert_eq!(event.cycles, 2);
+
+    assert!(queue.cancel(id));
+    assert!(queue.is_empty());
+  }
+
+  #[test]
+  fn higher_priorities_run_first_and_ties_keep_push_order() {
+    let mut queue = EventQueue::new();
+    queue.push(event("low", 1));
+    queue.push_with_priority(event("high", 1), 10);
+    queue.push(event("low-2", 1));
+    queue.push_with_priority(event("high-2", 1), 10);
+    assert_eq!(names(&queue), ["high", "high-2", "low", "low-2"]);
+  }
+
+  #[test]
+  fn ids_are_unique_and_cancel_only_their_event() {
+    let mut queue = EventQueue::new();
+    let a = queue.push(event("same", 3));
+    let b = queue.push(event("same", 3));
+    assert_ne!(a, b);
+    assert!(queue.cancel(a));
+    assert!(!queue.contains(a));
+    assert!(queue.contains(b));
+    assert!(!queue.cancel(a));
+  }
+
+  #[test]
+  fn cancels_made_while_running_apply_when_merged_back() {
+    let mut running = EventQueue::new();
+    let id = running.push(event("a", 3));
+    let mut pushed_meanwhile = EventQueue::new();
+    pushed_meanwhile.push(event("b", 3));
+    assert!(!pushed_meanwhile.cancel(id));
+
+    running.append(&mut pushed_meanwhile);
+    assert_eq!(names(&running), ["b"]);
+  }
 }
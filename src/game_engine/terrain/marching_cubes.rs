@@ -0,0 +1,75 @@
+use glam::{IVec3, Vec2, Vec3};
+
+use crate::game_engine::graphics::mesh::MeshBuilder;
+use super::tables::TRIANGLES;
+
+const CORNERS: [IVec3; 8] = [
+  IVec3::new(0, 0, 0),
+  IVec3::new(1, 0, 0),
+  IVec3::new(1, 1, 0),
+  IVec3::new(0, 1, 0),
+  IVec3::new(0, 0, 1),
+  IVec3::new(1, 0, 1),
+  IVec3::new(1, 1, 1),
+  IVec3::new(0, 1, 1),
+];
+
+// The two corners each of a cube's 12 edges runs between.
+const EDGES: [(usize, usize); 12] = [
+  (0, 1), (1, 2), (2, 3), (3, 0),
+  (4, 5), (5, 6), (6, 7), (7, 4),
+  (0, 4), (1, 5), (2, 6), (3, 7),
+];
+
+// Polygonises the `cells` cube of a density field, where samples above `iso_level` are solid.
+// `density` is sampled at integer coordinates from `min` to `min + cells` inclusive, and each
+// sample is `voxel_size` apart in the output. Normals come from the density gradient, so they stay
+// smooth across chunk borders.
+pub fn marching_cubes(min: IVec3, cells: IVec3, voxel_size: f32, iso_level: f32, density: impl Fn(IVec3) -> f32) -> MeshBuilder {
+  let mut builder = MeshBuilder::new();
+  let gradient = |point: IVec3| Vec3::new(
+    density(point + IVec3::X) - density(point - IVec3::X),
+    density(point + IVec3::Y) - density(point - IVec3::Y),
+    density(point + IVec3::Z) - density(point - IVec3::Z),
+  );
+
+  for z in 0..cells.z {
+    for y in 0..cells.y {
+      for x in 0..cells.x {
+        let cell = min + IVec3::new(x, y, z);
+        let values = CORNERS.map(|corner| density(cell + corner));
+
+        let mut configuration = 0;
+        for (i, value) in values.iter().enumerate() {
+          if *value < iso_level {
+            configuration |= 1 << i;
+          }
+        }
+        if configuration == 0 || configuration == 255 {
+          continue;
+        }
+
+        let edge_vertex = |edge: usize| {
+          let (a, b) = EDGES[edge];
+          let (value_a, value_b) = (values[a], values[b]);
+          let t = if (value_b - value_a).abs() > f32::EPSILON { (iso_level - value_a) / (value_b - value_a) } else { 0.5 };
+          let (point_a, point_b) = (cell + CORNERS[a], cell + CORNERS[b]);
+          let position = point_a.as_vec3().lerp(point_b.as_vec3(), t);
+          let normal = -gradient(point_a).lerp(gradient(point_b), t).normalize_or_zero();
+          (position * voxel_size, normal)
+        };
+
+        for triangle in TRIANGLES[configuration].chunks_exact(3).take_while(|triangle| triangle[0] >= 0) {
+          let mut indices = [0; 3];
+          for (index, &edge) in indices.iter_mut().zip(triangle) {
+            let (position, normal) = edge_vertex(edge as usize);
+            *index = builder.vertex(position, normal, Vec2::new(position.x, position.z));
+          }
+          builder.triangle(indices[0], indices[1], indices[2]);
+        }
+      }
+    }
+  }
+
+  builder
+}
@@ -0,0 +1,8 @@
+mod marching_cubes;
+mod tables;
+mod voxel;
+
+pub use self::{
+  marching_cubes::*,
+  voxel::*
+};
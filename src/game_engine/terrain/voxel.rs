@@ -0,0 +1,227 @@
+use std::collections::{HashMap, HashSet};
+use glam::{IVec3, Vec3};
+use rapier3d::prelude::{ColliderBuilder, ColliderHandle, Point};
+
+use crate::game_engine::ecs::World;
+use crate::game_engine::graphics::mesh::{Mesh, MeshBuilder};
+use crate::game_engine::jobs::Jobs;
+use crate::game_engine::physics::PhysicsWorld;
+use super::marching_cubes::marching_cubes;
+
+struct Chunk {
+  densities: Vec<f32>,
+  mesh: Option<Mesh>,
+  collider: Option<ColliderHandle>,
+  dirty: bool,
+  collider_dirty: bool,
+}
+
+// A density field split into cubic chunks, meshed with marching cubes. Densities above
+// `iso_level` are solid. Edits mark the touched chunks dirty, and `remesh` rebuilds only those.
+// Meant to live in the world as a resource, updated by `update_voxel_terrain`.
+pub struct VoxelTerrain {
+  pub chunk_size: i32, // voxels per chunk side
+  pub voxel_size: f32, // world units between samples
+  pub iso_level: f32,
+  pub empty_density: f32, // density of space no chunk covers
+
+  chunks: HashMap<IVec3, Chunk>,
+}
+
+impl VoxelTerrain {
+  pub fn new(chunk_size: i32, voxel_size: f32) -> Self {
+    VoxelTerrain {
+      chunk_size: chunk_size.max(1),
+      voxel_size,
+      iso_level: 0.0,
+      empty_density: -1.0,
+      chunks: HashMap::new(),
+    }
+  }
+
+  pub fn world_to_voxel(&self, position: Vec3) -> IVec3 {
+    (position / self.voxel_size).round().as_ivec3()
+  }
+
+  pub fn voxel_to_world(&self, voxel: IVec3) -> Vec3 {
+    voxel.as_vec3() * self.voxel_size
+  }
+
+  fn locate(&self, voxel: IVec3) -> (IVec3, usize) {
+    let size = self.chunk_size;
+    let chunk = voxel.div_euclid(IVec3::splat(size));
+    let local = voxel.rem_euclid(IVec3::splat(size));
+    (chunk, ((local.z * size + local.y) * size + local.x) as usize)
+  }
+
+  pub fn density(&self, voxel: IVec3) -> f32 {
+    let (chunk, index) = self.locate(voxel);
+    self.chunks.get(&chunk).map_or(self.empty_density, |chunk| chunk.densities[index])
+  }
+
+  pub fn set_density(&mut self, voxel: IVec3, density: f32) {
+    let (coord, index) = self.locate(voxel);
+    self.chunk_or_insert(coord).densities[index] = density;
+    self.mark_dirty_around(voxel);
+  }
+
+  fn chunk_or_insert(&mut self, coord: IVec3) -> &mut Chunk {
+    let samples = (self.chunk_size * self.chunk_size * self.chunk_size) as usize;
+    let empty_density = self.empty_density;
+    self.chunks.entry(coord).or_insert_with(|| Chunk {
+      densities: vec![empty_density; samples],
+      mesh: None,
+      collider: None,
+      dirty: true,
+      collider_dirty: true,
+    })
+  }
+
+  // A chunk's mesh reads one sample past each side (cells reach the next chunk, and normals need a
+  // neighbour), so an edit can dirty up to eight chunks. Neighbours that don't exist yet are
+  // created empty, since the cells between them and the edited voxel hold part of the surface.
+  fn mark_dirty_around(&mut self, voxel: IVec3) {
+    let mut touched = HashSet::new();
+    for dz in -1..=1 {
+      for dy in -1..=1 {
+        for dx in -1..=1 {
+          touched.insert(self.locate(voxel + IVec3::new(dx, dy, dz)).0);
+        }
+      }
+    }
+    for coord in touched {
+      self.chunk_or_insert(coord).dirty = true;
+    }
+  }
+
+  // Samples `density` (a function of world position) into every voxel between `min` and `max`.
+  pub fn fill(&mut self, min: IVec3, max: IVec3, density: impl Fn(Vec3) -> f32) {
+    for z in min.z..=max.z {
+      for y in min.y..=max.y {
+        for x in min.x..=max.x {
+          let voxel = IVec3::new(x, y, z);
+          self.set_density(voxel, density(self.voxel_to_world(voxel)));
+        }
+      }
+    }
+  }
+
+  // Adds `strength` to the density inside a sphere, fading out towards its edge. Positive strength
+  // builds terrain up, negative digs it out.
+  pub fn apply_sphere(&mut self, center: Vec3, radius: f32, strength: f32) {
+    let reach = (radius / self.voxel_size).ceil() as i32;
+    let middle = self.world_to_voxel(center);
+    for z in -reach..=reach {
+      for y in -reach..=reach {
+        for x in -reach..=reach {
+          let voxel = middle + IVec3::new(x, y, z);
+          let distance = self.voxel_to_world(voxel).distance(center);
+          if distance <= radius {
+            let falloff = 1.0 - distance / radius;
+            self.set_density(voxel, self.density(voxel) + strength * falloff);
+          }
+        }
+      }
+    }
+  }
+
+  // Rebuilds the mesh of every dirty chunk, spreading the work across `jobs` if given. Returns how
+  // many chunks were remeshed.
+  pub fn remesh(&mut self, jobs: Option<&Jobs>) -> usize {
+    let dirty: Vec<IVec3> = self.chunks.iter()
+      .filter(|(_, chunk)| chunk.dirty)
+      .map(|(coord, _)| *coord)
+      .collect();
+    if dirty.is_empty() {
+      return 0;
+    }
+
+    let terrain = &*self;
+    let mesh = |coord: &IVec3| terrain.mesh_chunk(*coord);
+    let meshes: Vec<MeshBuilder> = match jobs {
+      Some(jobs) => jobs.map(&dirty, mesh),
+      None => dirty.iter().map(mesh).collect(),
+    };
+
+    for (&coord, builder) in dirty.iter().zip(meshes) {
+      let chunk = self.chunks.get_mut(&coord).unwrap();
+      chunk.mesh = Some(builder.build());
+      chunk.dirty = false;
+      chunk.collider_dirty = true;
+    }
+    dirty.len()
+  }
+
+  fn mesh_chunk(&self, coord: IVec3) -> MeshBuilder {
+    marching_cubes(coord * self.chunk_size, IVec3::splat(self.chunk_size), self.voxel_size, self.iso_level, |voxel| self.density(voxel))
+  }
+
+  // Replaces the static trimesh colliders of chunks whose mesh changed since the last sync.
+  pub fn sync_colliders(&mut self, physics: &mut PhysicsWorld) {
+    for chunk in self.chunks.values_mut().filter(|chunk| chunk.collider_dirty) {
+      chunk.collider_dirty = false;
+      if let Some(handle) = chunk.collider.take() {
+        physics.remove_collider(handle);
+      }
+      let mesh = match &chunk.mesh {
+        Some(mesh) if !mesh.indices().is_empty() => mesh,
+        _ => continue,
+      };
+      let vertices = mesh.vertices().iter().map(|vertex| Point::from(vertex.position)).collect();
+      let indices = mesh.indices().chunks_exact(3).map(|triangle| [triangle[0], triangle[1], triangle[2]]).collect();
+      chunk.collider = Some(physics.add_static_collider(ColliderBuilder::trimesh(vertices, indices)));
+    }
+  }
+
+  pub fn chunk_mesh(&self, coord: IVec3) -> Option<&Mesh> {
+    self.chunks.get(&coord).and_then(|chunk| chunk.mesh.as_ref())
+  }
+
+  // Every meshed chunk, for rendering. Mesh positions are already in terrain space.
+  pub fn meshes(&self) -> impl Iterator<Item = (IVec3, &Mesh)> {
+    self.chunks.iter().filter_map(|(coord, chunk)| chunk.mesh.as_ref().map(|mesh| (*coord, mesh)))
+  }
+
+  pub fn meshes_mut(&mut self) -> impl Iterator<Item = (IVec3, &mut Mesh)> {
+    self.chunks.iter_mut().filter_map(|(coord, chunk)| chunk.mesh.as_mut().map(|mesh| (*coord, mesh)))
+  }
+}
+
+// Remeshes the world's dirty `VoxelTerrain` chunks on its `Jobs`, and keeps their colliders in the
+// `PhysicsWorld` up to date, if the world has one. The engine calls it every frame after the game's
+// update.
+pub fn update_voxel_terrain(world: &World) {
+  let mut terrain = match world.get_resource_mut::<VoxelTerrain>() {
+    Some(terrain) => terrain,
+    None => return,
+  };
+  let jobs = world.get_resource::<Jobs>().map(|jobs| jobs.clone());
+  terrain.remesh(jobs.as_ref());
+  if let Some(mut physics) = world.get_resource_mut::<PhysicsWorld>() {
+    terrain.sync_colliders(&mut physics);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn triangles(terrain: &VoxelTerrain) -> usize {
+    terrain.meshes().map(|(_, mesh)| mesh.indices().len() / 3).sum()
+  }
+
+  #[test]
+  fn an_edit_on_a_chunk_border_meshes_the_neighbour() {
+    let mut inside = VoxelTerrain::new(4, 1.0);
+    inside.set_density(IVec3::new(2, 2, 2), 1.0);
+    inside.remesh(None);
+
+    let mut border = VoxelTerrain::new(4, 1.0);
+    border.set_density(IVec3::new(0, 2, 2), 1.0);
+    border.remesh(None);
+
+    assert!(border.chunk_mesh(IVec3::new(-1, 0, 0)).is_some_and(|mesh| !mesh.indices().is_empty()));
+    assert!(triangles(&inside) > 0);
+    assert_eq!(triangles(&border), triangles(&inside));
+  }
+}
@@ -0,0 +1,94 @@
+use glam::{IVec2, Mat4, UVec2, Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::game_engine::ecs::Transform;
+use super::projection::TileProjection;
+
+// A grid of tile ids (indices into a tileset), laid out by `projection`. As a component it lives
+// in the XY plane of its entity's `Transform`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tilemap {
+  pub size: UVec2, // in tiles
+  pub tile_size: Vec2, // in world units
+  pub projection: TileProjection,
+  tiles: Vec<Option<u32>>,
+}
+
+impl Tilemap {
+  pub fn new(size: UVec2, tile_size: Vec2, projection: TileProjection) -> Self {
+    Tilemap { size, tile_size, projection, tiles: vec![None; (size.x * size.y) as usize] }
+  }
+
+  pub fn in_bounds(&self, tile: IVec2) -> bool {
+    tile.x >= 0 && tile.y >= 0 && (tile.x as u32) < self.size.x && (tile.y as u32) < self.size.y
+  }
+
+  fn index(&self, tile: IVec2) -> Option<usize> {
+    self.in_bounds(tile).then(|| (tile.y as u32 * self.size.x + tile.x as u32) as usize)
+  }
+
+  pub fn get(&self, tile: IVec2) -> Option<u32> {
+    self.index(tile).and_then(|index| self.tiles[index])
+  }
+
+  // Out-of-bounds writes are ignored.
+  pub fn set(&mut self, tile: IVec2, id: Option<u32>) {
+    if let Some(index) = self.index(tile) {
+      self.tiles[index] = id;
+    }
+  }
+
+  pub fn fill(&mut self, id: Option<u32>) {
+    self.tiles.fill(id);
+  }
+
+  // Centre of `tile` in the map's local space.
+  pub fn tile_to_local(&self, tile: IVec2) -> Vec2 {
+    self.projection.tile_to_local(tile, self.tile_size)
+  }
+
+  // The in-bounds tile under a local-space point.
+  pub fn local_to_tile(&self, point: Vec2) -> Option<IVec2> {
+    let tile = self.projection.local_to_tile(point, self.tile_size);
+    self.in_bounds(tile).then_some(tile)
+  }
+
+  pub fn tile_to_world(&self, tile: IVec2, transform: &Transform) -> Vec3 {
+    transform.matrix().transform_point3(self.tile_to_local(tile).extend(0.0))
+  }
+
+  pub fn world_to_tile(&self, point: Vec3, transform: &Transform) -> Option<IVec2> {
+    self.local_to_tile(transform.matrix().inverse().transform_point3(point).truncate())
+  }
+
+  // The tile under a screen position (pixels, origin top-left), found by casting the cursor ray
+  // from the camera onto the map's plane.
+  pub fn pick(&self, screen: Vec2, viewport: Vec2, view_projection: Mat4, transform: &Transform) -> Option<IVec2> {
+    let ndc = Vec2::new(screen.x / viewport.x * 2.0 - 1.0, 1.0 - screen.y / viewport.y * 2.0);
+    let to_local = transform.matrix().inverse() * view_projection.inverse();
+    let near = to_local.project_point3(ndc.extend(0.0));
+    let far = to_local.project_point3(ndc.extend(1.0));
+    let direction = far - near;
+    if direction.z.abs() <= f32::EPSILON {
+      return None;
+    }
+    let t = -near.z / direction.z;
+    self.local_to_tile((near + direction * t).truncate())
+  }
+
+  pub fn neighbours(&self, tile: IVec2) -> Vec<IVec2> {
+    let mut neighbours = self.projection.neighbours(tile);
+    neighbours.retain(|&neighbour| self.in_bounds(neighbour));
+    neighbours
+  }
+
+  // Every non-empty tile with its id, sorted back to front for drawing.
+  pub fn draw_order(&self) -> Vec<(IVec2, u32)> {
+    let mut tiles: Vec<(IVec2, u32)> = (0..self.size.y as i32)
+      .flat_map(|y| (0..self.size.x as i32).map(move |x| IVec2::new(x, y)))
+      .filter_map(|tile| self.get(tile).map(|id| (tile, id)))
+      .collect();
+    tiles.sort_by_key(|(tile, _)| self.projection.draw_key(*tile));
+    tiles
+  }
+}
@@ -0,0 +1,9 @@
+mod map;
+mod projection;
+mod tmx;
+
+pub use self::{
+  map::*,
+  projection::*,
+  tmx::*
+};
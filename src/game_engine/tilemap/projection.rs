@@ -0,0 +1,104 @@
+use glam::{IVec2, Vec2};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum HexOrientation {
+  #[default]
+  PointyTop, // odd rows shifted right
+  FlatTop, // odd columns shifted down
+}
+
+// How tile coordinates are laid out in the map's local space. Tile (0, 0) is at the origin, columns
+// run along +x and rows run down the screen (-y), matching how Tiled and most editors number them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TileProjection {
+  #[default]
+  Orthogonal,
+  Isometric, // diamond layout; `tile_size` is the diamond's full width and height
+  Hexagonal(HexOrientation), // `tile_size` is the hexagon's bounding box
+}
+
+const SQRT_3: f32 = 1.732_050_8;
+
+impl TileProjection {
+  // Centre of `tile` in map space (y up).
+  pub fn tile_to_local(&self, tile: IVec2, tile_size: Vec2) -> Vec2 {
+    let (column, row) = (tile.x as f32, tile.y as f32);
+    let down = match self {
+      TileProjection::Orthogonal => Vec2::new((column + 0.5) * tile_size.x, (row + 0.5) * tile_size.y),
+      TileProjection::Isometric => Vec2::new((column - row) * tile_size.x / 2.0, (column + row) * tile_size.y / 2.0),
+      TileProjection::Hexagonal(HexOrientation::PointyTop) => {
+        let shift = (tile.y & 1) as f32 * 0.5;
+        Vec2::new((column + shift) * tile_size.x, row * tile_size.y * 0.75)
+      }
+      TileProjection::Hexagonal(HexOrientation::FlatTop) => {
+        let shift = (tile.x & 1) as f32 * 0.5;
+        Vec2::new(column * tile_size.x * 0.75, (row + shift) * tile_size.y)
+      }
+    };
+    Vec2::new(down.x, -down.y)
+  }
+
+  // The tile whose area contains `point` (map space, y up). Not bounds checked.
+  pub fn local_to_tile(&self, point: Vec2, tile_size: Vec2) -> IVec2 {
+    let down = Vec2::new(point.x, -point.y);
+    match self {
+      TileProjection::Orthogonal => (down / tile_size).floor().as_ivec2(),
+      TileProjection::Isometric => {
+        // A diamond grid is a square grid in (column, row) space, so round to the nearest centre.
+        let (x, y) = (down.x / (tile_size.x / 2.0), down.y / (tile_size.y / 2.0));
+        Vec2::new((x + y) / 2.0, (y - x) / 2.0).round().as_ivec2()
+      }
+      TileProjection::Hexagonal(HexOrientation::PointyTop) => {
+        // Scale to a regular hexagon with unit circumradius, then go through axial coordinates.
+        let (x, y) = (down.x * SQRT_3 / tile_size.x, down.y * 2.0 / tile_size.y);
+        let (q, r) = hex_round(SQRT_3 / 3.0 * x - y / 3.0, 2.0 / 3.0 * y);
+        IVec2::new(q + (r - (r & 1)) / 2, r)
+      }
+      TileProjection::Hexagonal(HexOrientation::FlatTop) => {
+        let (x, y) = (down.x * 2.0 / tile_size.x, down.y * SQRT_3 / tile_size.y);
+        let (q, r) = hex_round(2.0 / 3.0 * x, -x / 3.0 + SQRT_3 / 3.0 * y);
+        IVec2::new(q, r + (q - (q & 1)) / 2)
+      }
+    }
+  }
+
+  // Tiles sharing an edge with `tile` (four for square grids, six for hexes).
+  pub fn neighbours(&self, tile: IVec2) -> Vec<IVec2> {
+    let offsets: &[(i32, i32)] = match self {
+      TileProjection::Orthogonal | TileProjection::Isometric => &[(1, 0), (-1, 0), (0, 1), (0, -1)],
+      TileProjection::Hexagonal(HexOrientation::PointyTop) if tile.y & 1 == 0 =>
+        &[(1, 0), (-1, 0), (-1, -1), (0, -1), (-1, 1), (0, 1)],
+      TileProjection::Hexagonal(HexOrientation::PointyTop) =>
+        &[(1, 0), (-1, 0), (0, -1), (1, -1), (0, 1), (1, 1)],
+      TileProjection::Hexagonal(HexOrientation::FlatTop) if tile.x & 1 == 0 =>
+        &[(0, 1), (0, -1), (-1, -1), (-1, 0), (1, -1), (1, 0)],
+      TileProjection::Hexagonal(HexOrientation::FlatTop) =>
+        &[(0, 1), (0, -1), (-1, 0), (-1, 1), (1, 0), (1, 1)],
+    };
+    offsets.iter().map(|&(x, y)| tile + IVec2::new(x, y)).collect()
+  }
+
+  // Orders tiles back to front for the painter's algorithm: anything drawn later may overlap
+  // what's already there, which matters once tiles are taller than their footprint.
+  pub fn draw_key(&self, tile: IVec2) -> (i32, i32) {
+    match self {
+      TileProjection::Isometric => (tile.x + tile.y, tile.x),
+      TileProjection::Hexagonal(HexOrientation::FlatTop) => (tile.y * 2 + (tile.x & 1), tile.x),
+      _ => (tile.y, tile.x),
+    }
+  }
+}
+
+// Rounds fractional axial hex coordinates to the nearest hex, via cube coordinates.
+fn hex_round(q: f32, r: f32) -> (i32, i32) {
+  let s = -q - r;
+  let (mut rq, mut rr, rs) = (q.round(), r.round(), s.round());
+  let (dq, dr, ds) = ((rq - q).abs(), (rr - r).abs(), (rs - s).abs());
+  if dq > dr && dq > ds {
+    rq = -rr - rs;
+  } else if dr > ds {
+    rr = -rq - rs;
+  }
+  (rq as i32, rr as i32)
+}
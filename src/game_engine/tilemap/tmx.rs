@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use glam::{IVec2, UVec2, Vec2};
+
+use crate::game_engine::assets::xml::{xml_elements, XmlElement};
+use crate::game_engine::assets::Asset;
+use super::map::Tilemap;
+use super::projection::{HexOrientation, TileProjection};
+
+// The top bits of a Tiled gid flip or rotate the tile. We don't draw flipped tiles, so they're
+// stripped on load.
+const GID_FLAGS: u32 = 0xF000_0000;
+
+// One frame of an animated tile: which tile to show (a global id) and for how long.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TileFrame {
+  pub tile: u32,
+  pub duration: f32, // seconds
+}
+
+// A grid of tiles cut from one image. Ids are global like Tiled's gids: the tileset covers
+// `first_id..first_id + tile_count`, so several tilesets can feed the same `Tilemap`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tileset {
+  pub name: String,
+  pub first_id: u32,
+  pub tile_size: UVec2, // in pixels
+  pub tile_count: u32,
+  pub columns: u32,
+  pub margin: u32, // pixels around the edge of the image
+  pub spacing: u32, // pixels between tiles
+  pub image: PathBuf,
+  pub image_size: UVec2,
+  pub animations: HashMap<u32, Vec<TileFrame>>, // by the animated tile's global id
+}
+
+impl Tileset {
+  // A tightly packed sheet with as many tiles as fit in the image.
+  pub fn new(first_id: u32, tile_size: UVec2, image: impl Into<PathBuf>, image_size: UVec2) -> Self {
+    let grid = image_size / tile_size.max(UVec2::ONE);
+    Tileset {
+      name: String::new(),
+      first_id,
+      tile_size,
+      tile_count: grid.x * grid.y,
+      columns: grid.x,
+      margin: 0,
+      spacing: 0,
+      image: image.into(),
+      image_size,
+      animations: HashMap::new(),
+    }
+  }
+
+  // Reads a `.tsx` file, with the image path made relative to it.
+  pub fn load(path: impl AsRef<Path>, first_id: u32) -> Result<Self, String> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).map_err(|err| format!("couldn't read {}: {}", path.display(), err))?;
+    Tileset::from_tsx(&text, first_id, path.parent().unwrap_or(Path::new("")))
+      .map_err(|err| format!("{}: {}", path.display(), err))
+  }
+
+  pub fn from_tsx(text: &str, first_id: u32, directory: &Path) -> Result<Self, String> {
+    parse_tileset(&xml_elements(text), first_id, directory)
+  }
+
+  pub fn contains(&self, id: u32) -> bool {
+    id >= self.first_id && id - self.first_id < self.tile_count
+  }
+
+  // Where tile `id` is in the image, as uv min/max.
+  pub fn uv_rect(&self, id: u32) -> (Vec2, Vec2) {
+    let local = id.saturating_sub(self.first_id);
+    let columns = self.columns.max(1);
+    let cell = UVec2::new(local % columns, local / columns);
+    let min = UVec2::splat(self.margin) + cell * (self.tile_size + UVec2::splat(self.spacing));
+    let size = self.image_size.max(UVec2::ONE).as_vec2();
+    (min.as_vec2() / size, (min + self.tile_size).as_vec2() / size)
+  }
+
+  pub fn is_animated(&self, id: u32) -> bool {
+    self.animations.contains_key(&id)
+  }
+
+  // The tile to show for `id` `time` seconds into its animation, or `id` itself if it isn't animated.
+  pub fn animated_tile(&self, id: u32, time: f32) -> u32 {
+    let frames = match self.animations.get(&id) {
+      Some(frames) if !frames.is_empty() => frames,
+      _ => return id,
+    };
+    let length: f32 = frames.iter().map(|frame| frame.duration).sum();
+    if length <= 0.0 {
+      return frames[0].tile;
+    }
+    let mut time = time.rem_euclid(length);
+    for frame in frames {
+      if time < frame.duration {
+        return frame.tile;
+      }
+      time -= frame.duration;
+    }
+    frames[frames.len() - 1].tile
+  }
+}
+
+// One tile layer of a Tiled map.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileLayer {
+  pub name: String,
+  pub visible: bool,
+  pub opacity: f32,
+  pub offset: Vec2, // in pixels, y down like Tiled
+  pub tiles: Tilemap, // global tile ids
+}
+
+// A map made in Tiled (https://www.mapeditor.org), from a `.tmx` file with its tilesets inline or
+// in `.tsx` files next to it. Tile layers become `Tilemap`s sized in pixels; object and image layers,
+// layer groups' offsets and infinite maps aren't supported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TiledMap {
+  pub size: UVec2, // in tiles
+  pub tile_size: UVec2, // in pixels
+  pub projection: TileProjection,
+  pub tilesets: Vec<Tileset>,
+  pub layers: Vec<TileLayer>,
+}
+
+impl TiledMap {
+  pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).map_err(|err| format!("couldn't read {}: {}", path.display(), err))?;
+    TiledMap::from_tmx(&text, path.parent().unwrap_or(Path::new("")))
+      .map_err(|err| format!("{}: {}", path.display(), err))
+  }
+
+  // External tilesets and images are looked up relative to `directory`, normally the map's own.
+  pub fn from_tmx(text: &str, directory: &Path) -> Result<Self, String> {
+    let elements = xml_elements(text);
+    let map = elements.iter().find(|element| element.name == "map" && !element.closing).ok_or("no <map>")?;
+    if map.attributes.get("infinite").is_some_and(|infinite| *infinite == "1") {
+      return Err("infinite maps aren't supported".to_string());
+    }
+    let projection = match map.attributes.get("orientation").copied().unwrap_or("orthogonal") {
+      "orthogonal" => TileProjection::Orthogonal,
+      "isometric" => TileProjection::Isometric,
+      "hexagonal" if map.attributes.get("staggeraxis").is_some_and(|axis| *axis == "x") =>
+        TileProjection::Hexagonal(HexOrientation::FlatTop),
+      "hexagonal" => TileProjection::Hexagonal(HexOrientation::PointyTop),
+      other => return Err(format!("{} maps aren't supported", other)),
+    };
+    let mut tiled = TiledMap {
+      size: UVec2::new(map.attribute("width").unwrap_or(0), map.attribute("height").unwrap_or(0)),
+      tile_size: UVec2::new(map.attribute("tilewidth").unwrap_or(0), map.attribute("tileheight").unwrap_or(0)),
+      projection,
+      tilesets: Vec::new(),
+      layers: Vec::new(),
+    };
+
+    let mut index = 0;
+    while index < elements.len() {
+      let element = &elements[index];
+      index += 1;
+      if element.closing {
+        continue;
+      }
+      match element.name {
+        "tileset" => {
+          let first_id = element.attribute("firstgid").unwrap_or(1);
+          match element.attributes.get("source") {
+            Some(source) => tiled.tilesets.push(Tileset::load(directory.join(source), first_id)?),
+            None => {
+              let end = closing(&elements, index, "tileset");
+              tiled.tilesets.push(parse_tileset(&elements[index - 1..end], first_id, directory)?);
+              index = end;
+            }
+          }
+        }
+        "layer" => {
+          let end = closing(&elements, index, "layer");
+          tiled.layers.push(tiled.parse_layer(element, &elements[index..end])?);
+          index = end;
+        }
+        _ => {}
+      }
+    }
+    Ok(tiled)
+  }
+
+  // The tileset tile `id` comes from.
+  pub fn tileset(&self, id: u32) -> Option<&Tileset> {
+    self.tilesets.iter().find(|tileset| tileset.contains(id))
+  }
+
+  fn parse_layer(&self, layer: &XmlElement, children: &[XmlElement]) -> Result<TileLayer, String> {
+    let name = layer.attributes.get("name").copied().unwrap_or("").to_string();
+    let size = UVec2::new(layer.attribute("width").unwrap_or(self.size.x), layer.attribute("height").unwrap_or(self.size.y));
+    let data = children.iter().position(|element| element.name == "data" && !element.closing)
+      .ok_or_else(|| format!("layer {} has no data", name))?;
+    let ids = match children[data].attributes.get("encoding").copied() {
+      Some("csv") => children[data].text.split(',')
+        .map(|id| id.trim().parse::<u32>().map_err(|err| format!("layer {}: {}", name, err)))
+        .collect::<Result<Vec<u32>, String>>()?,
+      Some("base64") => {
+        let bytes = base64(children[data].text.trim()).ok_or_else(|| format!("layer {} has bad base64", name))?;
+        let bytes = decompress(bytes, children[data].attributes.get("compression").copied())
+          .map_err(|err| format!("layer {}: {}", name, err))?;
+        bytes.chunks_exact(4).map(|id| u32::from_le_bytes([id[0], id[1], id[2], id[3]])).collect()
+      }
+      Some(other) => return Err(format!("layer {} has unknown encoding {}", name, other)),
+      // Plain XML: a <tile gid=".."/> per cell.
+      None => children[data + 1..].iter()
+        .take_while(|element| !(element.name == "data" && element.closing))
+        .filter(|element| element.name == "tile" && !element.closing)
+        .map(|element| element.attribute("gid").unwrap_or(0))
+        .collect(),
+    };
+    if ids.len() != (size.x * size.y) as usize {
+      return Err(format!("layer {} has {} tiles, expected {}", name, ids.len(), size.x * size.y));
+    }
+
+    let mut tiles = Tilemap::new(size, self.tile_size.as_vec2(), self.projection);
+    for (i, id) in ids.into_iter().enumerate() {
+      let id = id & !GID_FLAGS;
+      if id != 0 {
+        tiles.set(IVec2::new(i as i32 % size.x as i32, i as i32 / size.x as i32), Some(id));
+      }
+    }
+    Ok(TileLayer {
+      name,
+      visible: layer.attributes.get("visible").is_none_or(|visible| *visible != "0"),
+      opacity: layer.attribute("opacity").unwrap_or(1.0),
+      offset: Vec2::new(layer.attribute("offsetx").unwrap_or(0.0), layer.attribute("offsety").unwrap_or(0.0)),
+      tiles,
+    })
+  }
+}
+
+impl Asset for TiledMap {
+  fn from_bytes(bytes: &[u8], path: &Path) -> Result<Self, String> {
+    let text = std::str::from_utf8(bytes).map_err(|err| format!("map isn't utf-8: {}", err))?;
+    TiledMap::from_tmx(text, path.parent().unwrap_or(Path::new("")))
+  }
+}
+
+// The index of the `</name>` closing the element opened just before `start`.
+fn closing(elements: &[XmlElement], start: usize, name: &str) -> usize {
+  let mut depth = 0;
+  for (index, element) in elements.iter().enumerate().skip(start) {
+    if element.name == name {
+      if !element.closing {
+        depth += 1;
+      } else if depth == 0 {
+        return index;
+      } else {
+        depth -= 1;
+      }
+    }
+  }
+  elements.len()
+}
+
+// `elements` starts at the <tileset> tag, from a .tsx file or inline in a map.
+fn parse_tileset(elements: &[XmlElement], first_id: u32, directory: &Path) -> Result<Tileset, String> {
+  let tileset = elements.iter().find(|element| element.name == "tileset" && !element.closing).ok_or("no <tileset>")?;
+  let image = elements.iter().find(|element| element.name == "image" && !element.closing)
+    .ok_or("only tilesets made from a single image are supported")?;
+  let tile_size = UVec2::new(tileset.attribute("tilewidth").unwrap_or(0), tileset.attribute("tileheight").unwrap_or(0));
+  let image_size = UVec2::new(image.attribute("width").unwrap_or(0), image.attribute("height").unwrap_or(0));
+  let mut result = Tileset {
+    name: tileset.attributes.get("name").copied().unwrap_or("").to_string(),
+    tile_count: tileset.attribute("tilecount").unwrap_or(0),
+    columns: tileset.attribute("columns").unwrap_or(0),
+    margin: tileset.attribute("margin").unwrap_or(0),
+    spacing: tileset.attribute("spacing").unwrap_or(0),
+    ..Tileset::new(first_id, tile_size, directory.join(image.attributes.get("source").copied().unwrap_or("")), image_size)
+  };
+
+  let mut tile = None;
+  for element in elements.iter().filter(|element| !element.closing) {
+    match element.name {
+      "tile" => tile = element.attribute::<u32>("id").map(|id| first_id + id),
+      "frame" => if let (Some(tile), Some(frame)) = (tile, element.attribute::<u32>("tileid")) {
+        let duration = element.attribute::<f32>("duration").unwrap_or(100.0) / 1000.0;
+        result.animations.entry(tile).or_default().push(TileFrame { tile: first_id + frame, duration });
+      },
+      _ => {}
+    }
+  }
+  Ok(result)
+}
+
+fn decompress(bytes: Vec<u8>, compression: Option<&str>) -> Result<Vec<u8>, String> {
+  let mut out = Vec::new();
+  match compression {
+    None => return Ok(bytes),
+    Some("zlib") => ZlibDecoder::new(bytes.as_slice()).read_to_end(&mut out),
+    Some("gzip") => GzDecoder::new(bytes.as_slice()).read_to_end(&mut out),
+    Some(other) => return Err(format!("unsupported compression {}", other)),
+  }.map_err(|err| err.to_string())?;
+  Ok(out)
+}
+
+// Standard base64, ignoring whitespace.
+fn base64(text: &str) -> Option<Vec<u8>> {
+  let value = |c: u8| match c {
+    b'A'..=b'Z' => Some(c - b'A'),
+    b'a'..=b'z' => Some(c - b'a' + 26),
+    b'0'..=b'9' => Some(c - b'0' + 52),
+    b'+' => Some(62),
+    b'/' => Some(63),
+    _ => None,
+  };
+  let digits: Vec<u8> = text.bytes().filter(|c| !c.is_ascii_whitespace() && *c != b'=').map(value).collect::<Option<_>>()?;
+  let mut out = Vec::with_capacity(digits.len() * 3 / 4);
+  for chunk in digits.chunks(4) {
+    let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, digit)| bits | (*digit as u32) << (18 - 6 * i));
+    out.extend_from_slice(&bits.to_be_bytes()[1..chunk.len()]);
+  }
+  Some(out)
+}
@@ -0,0 +1,167 @@
+use std::time::Duration;
+
+use super::ecs::{Entity, Transform, World};
+
+// `std::time::Instant` panics in the browser, so the web build reads `performance.now()` instead.
+cfg_if::cfg_if! {
+  if #[cfg(target_arch = "wasm32")] {
+    pub use instant::Instant;
+  } else {
+    pub use std::time::Instant;
+  }
+}
+
+// Longest frame the clock will own up to, so a breakpoint or a dragged window doesn't make the
+// game try to catch up on seconds of fixed updates.
+const MAX_FRAME_DELTA: Duration = Duration::from_millis(250);
+
+// The game clock, as a resource. The engine advances it once per frame, then runs the fixed
+// schedule as many times as `fixed_steps` says so game logic moves at `fixed_hz` whatever the frame
+// rate, and leaves `alpha` for the renderer to blend between the last two fixed steps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Time {
+  pub max_fixed_steps: u32, // per frame; any time beyond this is dropped
+  delta: Duration,
+  elapsed: Duration,
+  frame_count: u64,
+  fixed_delta: Duration,
+  accumulator: Duration,
+  fixed_steps: u32,
+  last_update: Option<Instant>,
+}
+
+impl Time {
+  pub fn new(fixed_hz: f64) -> Self {
+    Time {
+      max_fixed_steps: 8,
+      delta: Duration::ZERO,
+      elapsed: Duration::ZERO,
+      frame_count: 0,
+      fixed_delta: Duration::from_secs_f64(1.0 / fixed_hz),
+      accumulator: Duration::ZERO,
+      fixed_steps: 0,
+      last_update: None,
+    }
+  }
+
+  // Time since the previous frame.
+  pub fn delta(&self) -> Duration {
+    self.delta
+  }
+
+  pub fn delta_seconds(&self) -> f32 {
+    self.delta.as_secs_f32()
+  }
+
+  // Time since the first frame, not counting anything clamped away.
+  pub fn elapsed(&self) -> Duration {
+    self.elapsed
+  }
+
+  pub fn elapsed_seconds(&self) -> f32 {
+    self.elapsed.as_secs_f32()
+  }
+
+  pub fn frame_count(&self) -> u64 {
+    self.frame_count
+  }
+
+  // The step the fixed schedule advances by; use it instead of `delta` in fixed systems.
+  pub fn fixed_delta(&self) -> Duration {
+    self.fixed_delta
+  }
+
+  pub fn fixed_delta_seconds(&self) -> f32 {
+    self.fixed_delta.as_secs_f32()
+  }
+
+  pub fn fixed_hz(&self) -> f64 {
+    1.0 / self.fixed_delta.as_secs_f64()
+  }
+
+  pub fn set_fixed_hz(&mut self, hz: f64) {
+    self.fixed_delta = Duration::from_secs_f64(1.0 / hz);
+  }
+
+  // How many fixed steps run this frame.
+  pub fn fixed_steps(&self) -> u32 {
+    self.fixed_steps
+  }
+
+  // How far between the last fixed step and the next one this frame is drawn, in 0..1.
+  pub fn alpha(&self) -> f32 {
+    (self.accumulator.as_secs_f64() / self.fixed_delta.as_secs_f64()) as f32
+  }
+
+  // Starts a frame at `now` and returns the number of fixed steps to run.
+  pub fn update(&mut self, now: Instant) -> u32 {
+    let delta = self.last_update.map_or(Duration::ZERO, |last| now.saturating_duration_since(last));
+    self.last_update = Some(now);
+    self.update_with_delta(delta)
+  }
+
+  // `update` with a given frame time, for replays and tests.
+  pub fn update_with_delta(&mut self, delta: Duration) -> u32 {
+    self.delta = delta.min(MAX_FRAME_DELTA);
+    self.elapsed += self.delta;
+    self.frame_count += 1;
+
+    self.accumulator += self.delta;
+    let mut steps = 0;
+    while self.accumulator >= self.fixed_delta {
+      self.accumulator -= self.fixed_delta;
+      steps += 1;
+    }
+    if steps > self.max_fixed_steps {
+      steps = self.max_fixed_steps;
+      self.accumulator = Duration::ZERO;
+    }
+    self.fixed_steps = steps;
+    steps
+  }
+}
+
+impl Default for Time {
+  fn default() -> Self {
+    Time::new(60.0)
+  }
+}
+
+// Add to an entity moved by fixed systems so it can be drawn smoothly between steps. The engine
+// records its transform before every fixed step.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PreviousTransform(pub Transform);
+
+pub fn record_previous_transforms(world: &World) {
+  world.query::<(&Transform, &mut PreviousTransform)>().for_each(|_, (transform, mut previous)| {
+    previous.0 = *transform;
+  });
+}
+
+// Where to draw `entity` this frame: its transform blended from the previous fixed step by the
+// clock's `alpha`. Entities without a `PreviousTransform` are drawn where they are.
+pub fn interpolated_transform(world: &World, entity: Entity) -> Option<Transform> {
+  let transform = *world.get::<Transform>(entity)?;
+  Some(Interpolation::new(world).transform(world, entity, &transform))
+}
+
+// `interpolated_transform` for the renderer's loops over entities, reading the clock once.
+pub(crate) struct Interpolation {
+  alpha: f32,
+}
+
+impl Interpolation {
+  pub(crate) fn new(world: &World) -> Self {
+    Interpolation { alpha: world.get_resource::<Time>().map_or(1.0, |time| time.alpha()) }
+  }
+
+  // `transform`, which is `entity`'s, blended from its `PreviousTransform` if it has one.
+  pub(crate) fn transform(&self, world: &World, entity: Entity, transform: &Transform) -> Transform {
+    let Some(previous) = world.get::<PreviousTransform>(entity).map(|previous| previous.0) else { return *transform };
+    Transform {
+      translation: previous.translation.lerp(transform.translation, self.alpha),
+      rotation: previous.rotation.slerp(transform.rotation, self.alpha),
+      scale: previous.scale.lerp(transform.scale, self.alpha),
+    }
+  }
+}
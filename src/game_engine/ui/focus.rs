@@ -0,0 +1,290 @@
+use glam::Vec2;
+use winit::event::VirtualKeyCode;
+
+use crate::game_engine::ecs::World;
+use crate::game_engine::gamepad::GamepadButton;
+use crate::game_engine::graphics::color::Color;
+use super::ui_draw::UiDraw;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct WidgetId(pub u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NavDirection {
+  Up,
+  Down,
+  Left,
+  Right,
+}
+
+impl NavDirection {
+  fn vector(self) -> Vec2 {
+    match self {
+      NavDirection::Up => Vec2::new(0.0, -1.0),
+      NavDirection::Down => Vec2::new(0.0, 1.0),
+      NavDirection::Left => Vec2::new(-1.0, 0.0),
+      NavDirection::Right => Vec2::new(1.0, 0.0),
+    }
+  }
+}
+
+// What a d-pad, stick or keyboard press means to the focused UI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NavAction {
+  Move(NavDirection),
+  Next, // registration order, like tab
+  Previous,
+  Activate,
+  Cancel,
+}
+
+impl NavAction {
+  // The default keyboard bindings: arrows move, tab cycles, enter/space activate, backspace cancels.
+  pub fn from_key(key: VirtualKeyCode) -> Option<Self> {
+    Some(match key {
+      VirtualKeyCode::Up => NavAction::Move(NavDirection::Up),
+      VirtualKeyCode::Down => NavAction::Move(NavDirection::Down),
+      VirtualKeyCode::Left => NavAction::Move(NavDirection::Left),
+      VirtualKeyCode::Right => NavAction::Move(NavDirection::Right),
+      VirtualKeyCode::Tab => NavAction::Next,
+      VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter | VirtualKeyCode::Space => NavAction::Activate,
+      VirtualKeyCode::Back => NavAction::Cancel,
+      _ => return None,
+    })
+  }
+
+  // The default gamepad bindings: the d-pad moves, the bumpers cycle, south activates and east cancels.
+  pub fn from_gamepad_button(button: GamepadButton) -> Option<Self> {
+    Some(match button {
+      GamepadButton::DPadUp => NavAction::Move(NavDirection::Up),
+      GamepadButton::DPadDown => NavAction::Move(NavDirection::Down),
+      GamepadButton::DPadLeft => NavAction::Move(NavDirection::Left),
+      GamepadButton::DPadRight => NavAction::Move(NavDirection::Right),
+      GamepadButton::RightBumper => NavAction::Next,
+      GamepadButton::LeftBumper => NavAction::Previous,
+      GamepadButton::South => NavAction::Activate,
+      GamepadButton::East => NavAction::Cancel,
+      _ => return None,
+    })
+  }
+}
+
+// A widget that can take focus, in window pixels. Neighbours left unset are found from the
+// widgets' positions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Focusable {
+  pub id: WidgetId,
+  pub min: Vec2,
+  pub max: Vec2,
+  pub up: Option<WidgetId>,
+  pub down: Option<WidgetId>,
+  pub left: Option<WidgetId>,
+  pub right: Option<WidgetId>,
+}
+
+impl Focusable {
+  pub fn new(id: WidgetId, min: Vec2, max: Vec2) -> Self {
+    Focusable { id, min, max, up: None, down: None, left: None, right: None }
+  }
+
+  pub fn with_neighbor(mut self, direction: NavDirection, neighbor: WidgetId) -> Self {
+    *self.neighbor_mut(direction) = Some(neighbor);
+    self
+  }
+
+  fn neighbor(&self, direction: NavDirection) -> Option<WidgetId> {
+    match direction {
+      NavDirection::Up => self.up,
+      NavDirection::Down => self.down,
+      NavDirection::Left => self.left,
+      NavDirection::Right => self.right,
+    }
+  }
+
+  fn neighbor_mut(&mut self, direction: NavDirection) -> &mut Option<WidgetId> {
+    match direction {
+      NavDirection::Up => &mut self.up,
+      NavDirection::Down => &mut self.down,
+      NavDirection::Left => &mut self.left,
+      NavDirection::Right => &mut self.right,
+    }
+  }
+
+  fn center(&self) -> Vec2 {
+    (self.min + self.max) / 2.0
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WidgetState {
+  pub focused: bool,
+  pub activated: bool, // activated this frame
+}
+
+// Keyboard and gamepad focus for immediate-mode menus, as a resource. The engine runs
+// `update_ui_focus` each frame before the game's update; register every focusable widget with
+// `widget` while building the UI and style it from the state it returns. Navigation runs against
+// the widgets registered the frame before.
+pub struct UiFocus {
+  pub enabled: bool,
+  pub wrap: bool, // moving off an edge comes back in on the other side
+  focused: Option<WidgetId>,
+  activated: Option<WidgetId>,
+  cancelled: bool,
+  widgets: Vec<Focusable>,
+  previous: Vec<Focusable>,
+  pending: Vec<NavAction>,
+}
+
+impl UiFocus {
+  pub fn new() -> Self {
+    UiFocus {
+      enabled: true,
+      wrap: true,
+      focused: None,
+      activated: None,
+      cancelled: false,
+      widgets: Vec::new(),
+      previous: Vec::new(),
+      pending: Vec::new(),
+    }
+  }
+
+  // Queues a navigation input, handled by the next `update`.
+  pub fn navigate(&mut self, action: NavAction) {
+    if self.enabled {
+      self.pending.push(action);
+    }
+  }
+
+  pub fn widget(&mut self, widget: Focusable) -> WidgetState {
+    let id = widget.id;
+    self.widgets.push(widget);
+    WidgetState { focused: self.focused == Some(id), activated: self.activated == Some(id) }
+  }
+
+  pub fn focused(&self) -> Option<WidgetId> {
+    self.focused
+  }
+
+  pub fn set_focus(&mut self, id: Option<WidgetId>) {
+    self.focused = id;
+  }
+
+  pub fn activated(&self) -> Option<WidgetId> {
+    self.activated
+  }
+
+  // Whether cancel was pressed this frame, e.g. to back out of a menu.
+  pub fn cancelled(&self) -> bool {
+    self.cancelled
+  }
+
+  // The focused widget's rectangle from this frame's widgets.
+  pub fn focused_rect(&self) -> Option<(Vec2, Vec2)> {
+    let focused = self.focused?;
+    self.widgets.iter().find(|widget| widget.id == focused).map(|widget| (widget.min, widget.max))
+  }
+
+  // Handles the inputs queued since the last update and starts a new frame of widgets.
+  pub fn update(&mut self) {
+    self.previous = std::mem::take(&mut self.widgets);
+    self.activated = None;
+    self.cancelled = false;
+
+    // Drop focus from a widget that went away.
+    if self.focused.is_some_and(|focused| !self.previous.iter().any(|widget| widget.id == focused)) {
+      self.focused = None;
+    }
+
+    for action in std::mem::take(&mut self.pending) {
+      if self.previous.is_empty() {
+        break;
+      }
+      let current = self.focused.and_then(|focused| self.previous.iter().position(|widget| widget.id == focused));
+      let current = match (current, action) {
+        (Some(current), _) => current,
+        // The first input into an unfocused menu just focuses it.
+        (None, NavAction::Cancel) => {
+          self.cancelled = true;
+          continue;
+        }
+        (None, _) => {
+          self.focused = Some(self.previous[0].id);
+          continue;
+        }
+      };
+      let count = self.previous.len();
+      match action {
+        NavAction::Move(direction) => {
+          if let Some(next) = self.neighbor(current, direction) {
+            self.focused = Some(next);
+          }
+        }
+        NavAction::Next => self.focused = Some(self.previous[(current + 1) % count].id),
+        NavAction::Previous => self.focused = Some(self.previous[(current + count - 1) % count].id),
+        NavAction::Activate => self.activated = self.focused,
+        NavAction::Cancel => self.cancelled = true,
+      }
+    }
+  }
+
+  fn neighbor(&self, current: usize, direction: NavDirection) -> Option<WidgetId> {
+    let from = &self.previous[current];
+    if let Some(explicit) = from.neighbor(direction) {
+      return Some(explicit);
+    }
+
+    // The closest widget in that direction, favouring ones in line with this one.
+    let axis = direction.vector();
+    let candidates: Vec<(WidgetId, f32, f32)> = self.previous.iter()
+      .filter(|widget| widget.id != from.id)
+      .map(|widget| {
+        let offset = widget.center() - from.center();
+        let along = offset.dot(axis);
+        (widget.id, along, (offset - axis * along).length())
+      })
+      .collect();
+    let ahead = candidates.iter()
+      .filter(|(_, along, _)| *along > 0.5)
+      .min_by(|a, b| (a.1 + a.2 * 2.0).total_cmp(&(b.1 + b.2 * 2.0)));
+    if let Some((id, _, _)) = ahead {
+      return Some(*id);
+    }
+    if !self.wrap {
+      return None;
+    }
+    // Wrap round to the widget furthest back, again favouring ones in line.
+    candidates.iter()
+      .filter(|(_, along, _)| *along < -0.5)
+      .min_by(|a, b| (a.1 + a.2 * 2.0).total_cmp(&(b.1 + b.2 * 2.0)))
+      .map(|(id, _, _)| *id)
+  }
+
+  // Outlines the focused widget.
+  pub fn draw_outline(&self, ui: &mut UiDraw, color: Color, thickness: f32) {
+    let (min, max) = match self.focused_rect() {
+      Some(rect) => rect,
+      None => return,
+    };
+    let (min, max) = (min - Vec2::splat(thickness), max + Vec2::splat(thickness));
+    ui.rect(min, Vec2::new(max.x, min.y + thickness), color);
+    ui.rect(Vec2::new(min.x, max.y - thickness), max, color);
+    ui.rect(Vec2::new(min.x, min.y + thickness), Vec2::new(min.x + thickness, max.y - thickness), color);
+    ui.rect(Vec2::new(max.x - thickness, min.y + thickness), Vec2::new(max.x, max.y - thickness), color);
+  }
+}
+
+impl Default for UiFocus {
+  fn default() -> Self {
+    UiFocus::new()
+  }
+}
+
+// Runs the world's `UiFocus` navigation for this frame. The engine calls it once the frame's input
+// is in, before the game's update.
+pub fn update_ui_focus(world: &World) {
+  if let Some(mut focus) = world.get_resource_mut::<UiFocus>() {
+    focus.update();
+  }
+}
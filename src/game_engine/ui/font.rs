@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::path::Path;
+use glam::{UVec2, Vec2};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct FontId(pub usize);
+
+// A TrueType/OpenType font, rasterized on demand.
+pub struct Font {
+  inner: fontdue::Font,
+}
+
+impl Font {
+  pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path).map_err(|err| format!("couldn't read {}: {}", path.display(), err))?;
+    Font::from_bytes(&bytes)
+  }
+
+  pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+    fontdue::Font::from_bytes(bytes, fontdue::FontSettings::default())
+      .map(|inner| Font { inner })
+      .map_err(|err| format!("bad font: {}", err))
+  }
+
+  // Distance from the baseline up to the tallest glyphs at `size` pixels.
+  pub fn ascent(&self, size: f32) -> f32 {
+    self.inner.horizontal_line_metrics(size).map_or(size * 0.8, |metrics| metrics.ascent)
+  }
+
+  // Baseline-to-baseline distance at `size` pixels.
+  pub fn line_height(&self, size: f32) -> f32 {
+    self.inner.horizontal_line_metrics(size).map_or(size * 1.2, |metrics| metrics.new_line_size)
+  }
+
+  // How far the pen moves after `character`, including kerning against the next one.
+  pub fn advance(&self, character: char, next: Option<char>, size: f32) -> f32 {
+    let kerning = next.and_then(|next| self.inner.horizontal_kern(character, next, size)).unwrap_or(0.0);
+    self.inner.metrics(character, size).advance_width + kerning
+  }
+
+  // Width of one line of text (newlines are not handled here).
+  pub fn line_width(&self, text: &str, size: f32) -> f32 {
+    let mut characters = text.chars().peekable();
+    let mut width = 0.0;
+    while let Some(character) = characters.next() {
+      width += self.advance(character, characters.peek().copied(), size);
+    }
+    width
+  }
+
+  // The size of `text` laid out with a line per `\n`.
+  pub fn measure(&self, text: &str, size: f32) -> Vec2 {
+    let lines = text.split('\n');
+    let width = lines.clone().map(|line| self.line_width(line, size)).fold(0.0, f32::max);
+    Vec2::new(width, lines.count() as f32 * self.line_height(size))
+  }
+}
+
+// The bold and italic faces that go with a regular font, used by rich text markup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FontVariants {
+  pub bold: Option<FontId>,
+  pub italic: Option<FontId>,
+  pub bold_italic: Option<FontId>,
+}
+
+// An inline image for rich text, drawn as a glyph from an icon font so it shares the atlas and
+// takes the colour of the text around it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextIcon {
+  pub font: FontId,
+  pub character: char,
+}
+
+// Every font the game has loaded, as a resource. UI draw calls refer to them by `FontId`.
+#[derive(Default)]
+pub struct Fonts {
+  fonts: Vec<Font>,
+  by_name: HashMap<String, FontId>,
+  variants: HashMap<FontId, FontVariants>,
+  icons: HashMap<String, TextIcon>,
+}
+
+impl Fonts {
+  pub fn new() -> Self {
+    Fonts::default()
+  }
+
+  pub fn add(&mut self, name: impl Into<String>, font: Font) -> FontId {
+    let id = FontId(self.fonts.len());
+    self.fonts.push(font);
+    self.by_name.insert(name.into(), id);
+    id
+  }
+
+  pub fn load(&mut self, name: impl Into<String>, path: impl AsRef<Path>) -> Result<FontId, String> {
+    Ok(self.add(name, Font::load(path)?))
+  }
+
+  pub fn get(&self, id: FontId) -> Option<&Font> {
+    self.fonts.get(id.0)
+  }
+
+  pub fn find(&self, name: &str) -> Option<FontId> {
+    self.by_name.get(name).copied()
+  }
+
+  pub fn set_variants(&mut self, regular: FontId, variants: FontVariants) {
+    self.variants.insert(regular, variants);
+  }
+
+  // The face of `regular` to use for bold and/or italic text, and whether it really is italic.
+  // Missing faces fall back to the closest one there is.
+  pub fn variant(&self, regular: FontId, bold: bool, italic: bool) -> (FontId, bool) {
+    let variants = self.variants.get(&regular).copied().unwrap_or_default();
+    let face = match (bold, italic) {
+      (true, true) => variants.bold_italic.map(|face| (face, true))
+        .or(variants.italic.map(|face| (face, true)))
+        .or(variants.bold.map(|face| (face, false))),
+      (true, false) => variants.bold.map(|face| (face, false)),
+      (false, true) => variants.italic.map(|face| (face, true)),
+      (false, false) => None,
+    };
+    face.unwrap_or((regular, false))
+  }
+
+  // Makes `[icon=name]` in markup draw `character` from `font`.
+  pub fn add_icon(&mut self, name: impl Into<String>, font: FontId, character: char) {
+    self.icons.insert(name.into(), TextIcon { font, character });
+  }
+
+  pub fn icon(&self, name: &str) -> Option<TextIcon> {
+    self.icons.get(name).copied()
+  }
+}
+
+// Where a rasterized glyph sits in the atlas and how to place it relative to the pen position on
+// the baseline (y down).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AtlasGlyph {
+  pub uv_min: Vec2,
+  pub uv_max: Vec2,
+  pub size: Vec2, // in pixels
+  pub offset: Vec2, // from the pen position to the glyph's top-left
+}
+
+// A single-channel texture of every glyph drawn so far, packed in shelves. Glyphs are rasterized
+// the first time they're used at a size; when it fills up it starts over.
+pub struct GlyphAtlas {
+  pub size: u32,
+  pixels: Vec<u8>,
+  glyphs: HashMap<(FontId, char, u32), AtlasGlyph>,
+  cursor: UVec2,
+  shelf_height: u32,
+  dirty: bool,
+}
+
+impl GlyphAtlas {
+  pub fn new(size: u32) -> Self {
+    GlyphAtlas { size, pixels: vec![0; (size * size) as usize], glyphs: HashMap::new(), cursor: UVec2::ZERO, shelf_height: 0, dirty: true }
+  }
+
+  // Sizes are rounded to a quarter pixel so slightly different scales share glyphs.
+  pub fn glyph(&mut self, fonts: &Fonts, font: FontId, character: char, size: f32) -> Option<AtlasGlyph> {
+    let quantized = (size * 4.0).round() as u32;
+    if let Some(glyph) = self.glyphs.get(&(font, character, quantized)) {
+      return Some(*glyph);
+    }
+
+    let (metrics, bitmap) = fonts.get(font)?.inner.rasterize(character, quantized as f32 / 4.0);
+    let (width, height) = (metrics.width as u32, metrics.height as u32);
+    if width + 2 > self.size || height + 2 > self.size {
+      return None;
+    }
+    if self.cursor.x + width + 1 > self.size {
+      self.cursor = UVec2::new(0, self.cursor.y + self.shelf_height + 1);
+      self.shelf_height = 0;
+    }
+    if self.cursor.y + height + 1 > self.size {
+      log::warn!("glyph atlas full, clearing it");
+      self.clear();
+      return self.glyph(fonts, font, character, size);
+    }
+
+    let origin = self.cursor + UVec2::ONE;
+    for row in 0..height {
+      let start = ((origin.y + row) * self.size + origin.x) as usize;
+      self.pixels[start..start + width as usize].copy_from_slice(&bitmap[(row * width) as usize..((row + 1) * width) as usize]);
+    }
+    self.cursor.x += width + 1;
+    self.shelf_height = self.shelf_height.max(height + 1);
+    self.dirty = true;
+
+    let atlas_size = self.size as f32;
+    let glyph = AtlasGlyph {
+      uv_min: origin.as_vec2() / atlas_size,
+      uv_max: (origin + UVec2::new(width, height)).as_vec2() / atlas_size,
+      size: Vec2::new(width as f32, height as f32),
+      offset: Vec2::new(metrics.xmin as f32, -(metrics.ymin as f32 + height as f32)),
+    };
+    self.glyphs.insert((font, character, quantized), glyph);
+    Some(glyph)
+  }
+
+  pub fn clear(&mut self) {
+    self.pixels.fill(0);
+    self.glyphs.clear();
+    self.cursor = UVec2::ZERO;
+    self.shelf_height = 0;
+    self.dirty = true;
+  }
+
+  pub fn pixels(&self) -> &[u8] {
+    &self.pixels
+  }
+
+  // Whether glyphs were added since the last call.
+  pub fn take_dirty(&mut self) -> bool {
+    std::mem::take(&mut self.dirty)
+  }
+}
@@ -0,0 +1,15 @@
+mod focus;
+mod font;
+mod renderer;
+mod rich_text;
+mod subtitles;
+mod ui_draw;
+
+pub use self::{
+  focus::*,
+  font::*,
+  renderer::*,
+  rich_text::*,
+  subtitles::*,
+  ui_draw::*
+};
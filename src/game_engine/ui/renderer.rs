@@ -0,0 +1,330 @@
+use std::borrow::Cow;
+use std::mem::size_of;
+use bytemuck::{Pod, Zeroable};
+use glam::Vec2;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, BlendState, Buffer, BufferAddress, BufferBindingType, BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites, Device, Extent3d, FilterMode, FragmentState, ImageCopyTexture, ImageDataLayout, MultisampleState, Origin3d, PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPass, RenderPipeline, RenderPipelineDescriptor, SamplerBindingType, SamplerDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, Texture, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureViewDescriptor, TextureViewDimension, VertexAttribute, VertexBufferLayout, VertexFormat, VertexState, VertexStepMode};
+
+use crate::game_engine::graphics::color::Color;
+use super::font::{FontId, Fonts, GlyphAtlas};
+use super::rich_text::{RichText, TextEffect};
+use super::ui_draw::UiCommand;
+
+const ATLAS_SIZE: u32 = 1024;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct UiVertex {
+  pub position: [f32; 2], // window pixels
+  pub uv: [f32; 2], // into the glyph atlas; negative for solid colour
+  pub color: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct UiUniforms {
+  viewport: [f32; 4],
+}
+
+// Draws `UiDraw` rectangles and text as alpha-blended quads over the scene.
+pub struct UiRenderer {
+  pipeline: RenderPipeline,
+  uniform_buffer: Buffer,
+  bind_group: BindGroup,
+  atlas: GlyphAtlas,
+  atlas_texture: Texture,
+  vertices: Vec<UiVertex>, // this frame's quads, kept to reuse the allocation
+  vertex_buffer: Option<Buffer>,
+  vertex_count: u32,
+}
+
+impl UiRenderer {
+  pub fn new(device: &Device, format: TextureFormat) -> Self {
+    let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+      label: Some("ui-shader"),
+      source: ShaderSource::Wgsl(Cow::Borrowed(
+"
+struct Uniforms {
+    viewport: vec4<f32>,
+};
+
+@group(0) @binding(0) var<uniform> uniforms: Uniforms;
+@group(0) @binding(1) var atlas: texture_2d<f32>;
+@group(0) @binding(2) var atlas_sampler: sampler;
+
+struct VertexInput {
+    @location(0) position: vec2<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    let ndc = in.position / uniforms.viewport.xy * 2.0 - 1.0;
+    out.clip_position = vec4<f32>(ndc.x, -ndc.y, 0.0, 1.0);
+    out.uv = in.uv;
+    out.color = in.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coverage = select(textureSample(atlas, atlas_sampler, in.uv).r, 1.0, in.uv.x < 0.0);
+    return vec4<f32>(in.color.rgb, in.color.a * coverage);
+}
+"
+      ))
+    });
+
+    let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("ui-uniforms"),
+      usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+      contents: bytemuck::bytes_of(&UiUniforms { viewport: [800.0, 600.0, 0.0, 0.0] })
+    });
+
+    let atlas_texture = device.create_texture(&TextureDescriptor {
+      label: Some("ui-glyph-atlas"),
+      size: Extent3d { width: ATLAS_SIZE, height: ATLAS_SIZE, depth_or_array_layers: 1 },
+      mip_level_count: 1,
+      sample_count: 1,
+      dimension: TextureDimension::D2,
+      format: TextureFormat::R8Unorm,
+      usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST
+    });
+    let atlas_view = atlas_texture.create_view(&TextureViewDescriptor::default());
+    let sampler = device.create_sampler(&SamplerDescriptor {
+      label: Some("ui-glyph-sampler"),
+      address_mode_u: AddressMode::ClampToEdge,
+      address_mode_v: AddressMode::ClampToEdge,
+      mag_filter: FilterMode::Linear,
+      min_filter: FilterMode::Linear,
+      ..SamplerDescriptor::default()
+    });
+
+    let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("ui-bind-group-layout"),
+      entries: &[
+        BindGroupLayoutEntry {
+          binding: 0,
+          visibility: ShaderStages::VERTEX,
+          ty: BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None
+          },
+          count: None
+        },
+        BindGroupLayoutEntry {
+          binding: 1,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: true },
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false
+          },
+          count: None
+        },
+        BindGroupLayoutEntry {
+          binding: 2,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Sampler(SamplerBindingType::Filtering),
+          count: None
+        }
+      ]
+    });
+
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+      label: Some("ui-bind-group"),
+      layout: &bind_group_layout,
+      entries: &[
+        BindGroupEntry { binding: 0, resource: uniform_buffer.as_entire_binding() },
+        BindGroupEntry { binding: 1, resource: BindingResource::TextureView(&atlas_view) },
+        BindGroupEntry { binding: 2, resource: BindingResource::Sampler(&sampler) }
+      ]
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+      label: Some("ui-pipeline-layout"),
+      bind_group_layouts: &[&bind_group_layout],
+      push_constant_ranges: &[]
+    });
+
+    let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+      label: Some("ui-pipeline"),
+      layout: Some(&pipeline_layout),
+      vertex: VertexState {
+        module: &shader_module,
+        entry_point: "vs_main",
+        buffers: &[VertexBufferLayout {
+          array_stride: size_of::<UiVertex>() as BufferAddress,
+          step_mode: VertexStepMode::Vertex,
+          attributes: &[
+            VertexAttribute { format: VertexFormat::Float32x2, shader_location: 0, offset: 0 },
+            VertexAttribute { format: VertexFormat::Float32x2, shader_location: 1, offset: 8 },
+            VertexAttribute { format: VertexFormat::Float32x4, shader_location: 2, offset: 16 }
+          ]
+        }]
+      },
+      fragment: Some(FragmentState {
+        module: &shader_module,
+        entry_point: "fs_main",
+        targets: &[Some(ColorTargetState {
+          format,
+          blend: Some(BlendState::ALPHA_BLENDING),
+          write_mask: ColorWrites::ALL
+        })]
+      }),
+      primitive: PrimitiveState::default(),
+      depth_stencil: None,
+      multisample: MultisampleState::default(),
+      multiview: None
+    });
+
+    UiRenderer {
+      pipeline,
+      uniform_buffer,
+      bind_group,
+      atlas: GlyphAtlas::new(ATLAS_SIZE),
+      atlas_texture,
+      vertices: Vec::new(),
+      vertex_buffer: None,
+      vertex_count: 0,
+    }
+  }
+
+  // Builds this frame's quads, rasterizing any new glyphs, and uploads them. `viewport` is in
+  // pixels and the commands in UI units of `scale` pixels; glyphs are rasterized at their scaled
+  // size so scaled-up text stays sharp.
+  pub fn prepare(&mut self, device: &Device, queue: &Queue, fonts: Option<&Fonts>, commands: &[UiCommand], viewport: Vec2, scale: f32) {
+    queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&UiUniforms { viewport: [viewport.x, viewport.y, 0.0, 0.0] }));
+
+    self.vertices.clear();
+    for command in commands {
+      match command {
+        UiCommand::Rect { min, max, color } => push_quad(&mut self.vertices, *min * scale, *max * scale, Vec2::splat(-1.0), Vec2::splat(-1.0), *color),
+        UiCommand::Text { font, text, position, size, color } => {
+          if let Some(fonts) = fonts {
+            self.push_text(fonts, *font, text, *position * scale, *size * scale, *color);
+          }
+        }
+        UiCommand::RichText { text, position, time } => {
+          if let Some(fonts) = fonts {
+            self.push_rich_text(fonts, text, *position, *time, scale);
+          }
+        }
+      }
+    }
+
+    if self.atlas.take_dirty() {
+      queue.write_texture(
+        ImageCopyTexture { texture: &self.atlas_texture, mip_level: 0, origin: Origin3d::ZERO, aspect: TextureAspect::All },
+        self.atlas.pixels(),
+        ImageDataLayout { offset: 0, bytes_per_row: std::num::NonZeroU32::new(ATLAS_SIZE), rows_per_image: None },
+        Extent3d { width: ATLAS_SIZE, height: ATLAS_SIZE, depth_or_array_layers: 1 }
+      );
+    }
+
+    self.vertex_count = self.vertices.len() as u32;
+    if self.vertices.is_empty() {
+      return;
+    }
+    let size = std::mem::size_of_val(self.vertices.as_slice()) as BufferAddress;
+    if self.vertex_buffer.as_ref().is_none_or(|buffer| buffer.size() < size) {
+      self.vertex_buffer = Some(device.create_buffer(&BufferDescriptor {
+        label: Some("ui-vertices"),
+        size: size.next_power_of_two(),
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false
+      }));
+    }
+    queue.write_buffer(self.vertex_buffer.as_ref().unwrap(), 0, bytemuck::cast_slice(&self.vertices));
+  }
+
+  // Lays `text` out from its top-left, one line per `\n`.
+  fn push_text(&mut self, fonts: &Fonts, font_id: FontId, text: &str, position: Vec2, size: f32, color: Color) {
+    let font = match fonts.get(font_id) {
+      Some(font) => font,
+      None => return,
+    };
+    let mut baseline = position.y + font.ascent(size);
+    for line in text.split('\n') {
+      let mut pen = position.x;
+      let mut characters = line.chars().peekable();
+      while let Some(character) = characters.next() {
+        if let Some(glyph) = self.atlas.glyph(fonts, font_id, character, size) {
+          let min = Vec2::new(pen, baseline) + glyph.offset;
+          if glyph.size.x > 0.0 && glyph.size.y > 0.0 {
+            push_quad(&mut self.vertices, min, min + glyph.size, glyph.uv_min, glyph.uv_max, color);
+          }
+        }
+        pen += font.advance(character, characters.peek().copied(), size);
+      }
+      baseline += font.line_height(size);
+    }
+  }
+
+  fn push_rich_text(&mut self, fonts: &Fonts, text: &RichText, position: Vec2, time: f32, scale: f32) {
+    for laid_out in text.layout(fonts).glyphs {
+      let glyph = match self.atlas.glyph(fonts, laid_out.font, laid_out.character, laid_out.size * scale) {
+        Some(glyph) if glyph.size.x > 0.0 && glyph.size.y > 0.0 => glyph,
+        _ => continue,
+      };
+      let index = laid_out.index as f32;
+      let offset = match laid_out.effect {
+        TextEffect::None => Vec2::ZERO,
+        TextEffect::Wave => Vec2::new(0.0, (time * 6.0 - index * 0.6).sin() * laid_out.size * 0.12),
+        TextEffect::Shake => {
+          let tick = (time * 20.0) as u32;
+          Vec2::new(jitter(laid_out.index as u32, tick), jitter(laid_out.index as u32 ^ 0x5bd1, tick)) * laid_out.size * 0.05
+        }
+      };
+      let min = (position + laid_out.pen + offset) * scale + glyph.offset;
+      let shear = if laid_out.slant { glyph.size.y * 0.2 } else { 0.0 };
+      push_sheared_quad(&mut self.vertices, min, min + glyph.size, glyph.uv_min, glyph.uv_max, laid_out.color, shear);
+    }
+  }
+
+  pub fn draw_calls(&self) -> u32 {
+    (self.vertex_buffer.is_some() && self.vertex_count > 0) as u32
+  }
+
+  pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+    let buffer = match &self.vertex_buffer {
+      Some(buffer) if self.vertex_count > 0 => buffer,
+      _ => return,
+    };
+    render_pass.set_pipeline(&self.pipeline);
+    render_pass.set_bind_group(0, &self.bind_group, &[]);
+    render_pass.set_vertex_buffer(0, buffer.slice(..));
+    render_pass.draw(0..self.vertex_count, 0..1);
+  }
+}
+
+fn push_quad(vertices: &mut Vec<UiVertex>, min: Vec2, max: Vec2, uv_min: Vec2, uv_max: Vec2, color: Color) {
+  push_sheared_quad(vertices, min, max, uv_min, uv_max, color, 0.0);
+}
+
+// `shear` moves the top edge right by that many pixels, for faux italics.
+fn push_sheared_quad(vertices: &mut Vec<UiVertex>, min: Vec2, max: Vec2, uv_min: Vec2, uv_max: Vec2, color: Color, shear: f32) {
+  let color = color.to_array();
+  let vertex = |x: f32, y: f32, u: f32, v: f32| UiVertex { position: [x, y], uv: [u, v], color };
+  let (a, b) = (vertex(min.x + shear, min.y, uv_min.x, uv_min.y), vertex(max.x + shear, min.y, uv_max.x, uv_min.y));
+  let (c, d) = (vertex(max.x, max.y, uv_max.x, uv_max.y), vertex(min.x, max.y, uv_min.x, uv_max.y));
+  vertices.extend_from_slice(&[a, b, c, a, c, d]);
+}
+
+// A repeatable value in -1..1 for a character and animation tick.
+fn jitter(index: u32, tick: u32) -> f32 {
+  let mut hash = index.wrapping_mul(0x9e37_79b9) ^ tick.wrapping_mul(0x85eb_ca6b);
+  hash ^= hash >> 15;
+  hash = hash.wrapping_mul(0x2c1b_3c6d);
+  hash ^= hash >> 12;
+  (hash & 0xffff) as f32 / 32767.5 - 1.0
+}
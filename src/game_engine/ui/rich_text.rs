@@ -0,0 +1,301 @@
+use glam::Vec2;
+
+use crate::game_engine::graphics::color::Color;
+use super::font::{FontId, Fonts};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextEffect {
+  #[default]
+  None,
+  Wave, // characters bob up and down in turn
+  Shake, // characters jitter in place
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextAlign {
+  #[default]
+  Left,
+  Center,
+  Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RunStyle {
+  pub color: Option<Color>, // the text's base colour when unset
+  pub bold: bool,
+  pub italic: bool,
+  pub effect: TextEffect,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RunContent {
+  Text(String),
+  Icon(String), // a name registered with `Fonts::add_icon`
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StyledRun {
+  pub content: RunContent,
+  pub style: RunStyle,
+}
+
+// Splits markup into runs of one style each. Tags are
+// `[color=#rrggbb]` / `[color=red]`, `[b]`, `[i]`, `[wave]`, `[shake]`, each closed with `[/tag]`,
+// and `[icon=name]` which stands alone. `[[` is a literal `[`; anything that isn't a known tag is
+// kept as text.
+pub fn parse_markup(markup: &str) -> Vec<StyledRun> {
+  let mut runs = Vec::new();
+  let mut stack: Vec<(&str, RunStyle)> = Vec::new();
+  let mut style = RunStyle::default();
+  let mut text = String::new();
+  let mut rest = markup;
+
+  let flush = |text: &mut String, runs: &mut Vec<StyledRun>, style: RunStyle| {
+    if !text.is_empty() {
+      runs.push(StyledRun { content: RunContent::Text(std::mem::take(text)), style });
+    }
+  };
+
+  while let Some(start) = rest.find('[') {
+    text.push_str(&rest[..start]);
+    rest = &rest[start..];
+    if let Some(after) = rest.strip_prefix("[[") {
+      text.push('[');
+      rest = after;
+      continue;
+    }
+    let tag = match rest.find(']') {
+      Some(end) => &rest[1..end],
+      None => break,
+    };
+    let (name, value) = match tag.split_once('=') {
+      Some((name, value)) => (name.trim(), Some(value.trim())),
+      None => (tag.trim(), None),
+    };
+
+    let mut next = style;
+    let handled = if let Some(closing) = name.strip_prefix('/') {
+      match stack.iter().rposition(|(open, _)| *open == closing) {
+        Some(index) => {
+          flush(&mut text, &mut runs, style);
+          style = stack[index].1;
+          stack.truncate(index);
+          true
+        }
+        None => false,
+      }
+    } else {
+      let known = match (name, value) {
+        ("b", None) => { next.bold = true; true }
+        ("i", None) => { next.italic = true; true }
+        ("wave", None) => { next.effect = TextEffect::Wave; true }
+        ("shake", None) => { next.effect = TextEffect::Shake; true }
+        ("color", Some(value)) => match parse_color(value) {
+          Some(color) => { next.color = Some(color); true }
+          None => false,
+        },
+        ("icon", Some(value)) => {
+          flush(&mut text, &mut runs, style);
+          runs.push(StyledRun { content: RunContent::Icon(value.to_string()), style });
+          rest = &rest[tag.len() + 2..];
+          continue;
+        }
+        _ => false,
+      };
+      if known {
+        flush(&mut text, &mut runs, style);
+        stack.push((name, style));
+        style = next;
+      }
+      known
+    };
+
+    if handled {
+      rest = &rest[tag.len() + 2..];
+    } else {
+      text.push('[');
+      rest = &rest[1..];
+    }
+  }
+  text.push_str(rest);
+  flush(&mut text, &mut runs, style);
+  runs
+}
+
+fn parse_color(value: &str) -> Option<Color> {
+  Color::from_hex(value).or(match value {
+    "white" => Some(Color::WHITE),
+    "black" => Some(Color::BLACK),
+    "red" => Some(Color::RED),
+    "green" => Some(Color::GREEN),
+    "blue" => Some(Color::BLUE),
+    "yellow" => Some(Color::YELLOW),
+    "cyan" => Some(Color::CYAN),
+    "magenta" => Some(Color::MAGENTA),
+    _ => None,
+  })
+}
+
+// Styled text with its wrapping and alignment, ready to hand to `UiDraw::rich_text`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RichText {
+  pub runs: Vec<StyledRun>,
+  pub font: FontId, // the regular face; bold and italic come from its `FontVariants`
+  pub size: f32,
+  pub color: Color,
+  pub max_width: Option<f32>, // wrap lines at word boundaries past this many pixels
+  pub align: TextAlign, // lines are aligned within the widest one
+}
+
+impl RichText {
+  pub fn new(font: FontId, size: f32, color: Color) -> Self {
+    RichText { runs: Vec::new(), font, size, color, max_width: None, align: TextAlign::Left }
+  }
+
+  pub fn parse(markup: &str, font: FontId, size: f32, color: Color) -> Self {
+    RichText { runs: parse_markup(markup), ..RichText::new(font, size, color) }
+  }
+
+  pub fn with_max_width(mut self, max_width: f32) -> Self {
+    self.max_width = Some(max_width);
+    self
+  }
+
+  pub fn with_align(mut self, align: TextAlign) -> Self {
+    self.align = align;
+    self
+  }
+
+  pub fn push(&mut self, content: RunContent, style: RunStyle) {
+    self.runs.push(StyledRun { content, style });
+  }
+
+  pub fn push_markup(&mut self, markup: &str) {
+    self.runs.extend(parse_markup(markup));
+  }
+
+  pub fn layout(&self, fonts: &Fonts) -> TextLayout {
+    layout(self, fonts)
+  }
+}
+
+// One positioned character of laid out rich text.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LaidOutGlyph {
+  pub font: FontId,
+  pub character: char,
+  pub size: f32,
+  pub pen: Vec2, // on the baseline, relative to the text's top-left
+  pub color: Color,
+  pub effect: TextEffect,
+  pub slant: bool, // italic asked for but the font has no italic face, so it's sheared instead
+  pub index: usize, // position in the text, to stagger effects
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TextLayout {
+  pub glyphs: Vec<LaidOutGlyph>,
+  pub size: Vec2,
+}
+
+struct Item {
+  glyph: LaidOutGlyph,
+  advance: f32,
+  ascent: f32,
+  line_height: f32,
+}
+
+fn layout(text: &RichText, fonts: &Fonts) -> TextLayout {
+  let mut items: Vec<Item> = Vec::new();
+  for run in &text.runs {
+    let (font_id, italic) = fonts.variant(text.font, run.style.bold, run.style.italic);
+    let glyph = |font: FontId, character: char, index: usize| LaidOutGlyph {
+      font,
+      character,
+      size: text.size,
+      pen: Vec2::ZERO,
+      color: run.style.color.unwrap_or(text.color),
+      effect: run.style.effect,
+      slant: run.style.italic && !italic,
+      index,
+    };
+    let characters: Vec<(FontId, char)> = match &run.content {
+      RunContent::Text(string) => string.chars().map(|character| (font_id, character)).collect(),
+      RunContent::Icon(name) => match fonts.icon(name) {
+        Some(icon) => vec![(icon.font, icon.character)],
+        None => {
+          log::warn!("unknown text icon {}", name);
+          Vec::new()
+        }
+      },
+    };
+    for (font, character) in characters {
+      let (advance, ascent, line_height) = match fonts.get(font) {
+        Some(face) => (face.advance(character, None, text.size), face.ascent(text.size), face.line_height(text.size)),
+        None => continue,
+      };
+      // Kern against the previous character when they share a face.
+      if let Some(previous) = items.last_mut().filter(|previous| previous.glyph.font == font) {
+        let face = fonts.get(font).unwrap();
+        previous.advance = face.advance(previous.glyph.character, Some(character), text.size);
+      }
+      let index = items.len();
+      items.push(Item { glyph: glyph(font, character, index), advance, ascent, line_height });
+    }
+  }
+
+  // Break into lines at newlines and, when wrapping, at the last space that fits.
+  let mut lines: Vec<Vec<Item>> = vec![Vec::new()];
+  let mut width = 0.0;
+  for item in items {
+    let line = lines.last_mut().unwrap();
+    if item.glyph.character == '\n' {
+      lines.push(Vec::new());
+      width = 0.0;
+      continue;
+    }
+    let is_space = item.glyph.character.is_whitespace();
+    width += item.advance;
+    line.push(item);
+    let overflowing = matches!(text.max_width, Some(max_width) if width > max_width);
+    if !overflowing || is_space || line.len() < 2 {
+      continue;
+    }
+    let split = match line.iter().rposition(|item| item.glyph.character.is_whitespace()) {
+      Some(space) => space + 1,
+      None => line.len() - 1, // one long word: break it between characters
+    };
+    let carried = line.split_off(split);
+    width = carried.iter().map(|item| item.advance).sum();
+    lines.push(carried);
+  }
+
+  let line_width = |line: &[Item]| {
+    let end = line.iter().rposition(|item| !item.glyph.character.is_whitespace()).map_or(0, |last| last + 1);
+    line[..end].iter().map(|item| item.advance).sum::<f32>()
+  };
+  let (base_ascent, base_line_height) = fonts.get(text.font)
+    .map_or((text.size * 0.8, text.size * 1.2), |font| (font.ascent(text.size), font.line_height(text.size)));
+  let widest = lines.iter().map(|line| line_width(line)).fold(0.0, f32::max);
+
+  let mut layout = TextLayout { glyphs: Vec::new(), size: Vec2::new(widest, 0.0) };
+  let mut top = 0.0;
+  for line in &lines {
+    let ascent = line.iter().map(|item| item.ascent).fold(base_ascent, f32::max);
+    let line_height = line.iter().map(|item| item.line_height).fold(base_line_height, f32::max);
+    let mut pen = match text.align {
+      TextAlign::Left => 0.0,
+      TextAlign::Center => (widest - line_width(line)) / 2.0,
+      TextAlign::Right => widest - line_width(line),
+    };
+    for item in line {
+      if !item.glyph.character.is_whitespace() {
+        layout.glyphs.push(LaidOutGlyph { pen: Vec2::new(pen, top + ascent), ..item.glyph });
+      }
+      pen += item.advance;
+    }
+    top += line_height;
+  }
+  layout.size.y = top;
+  layout
+}
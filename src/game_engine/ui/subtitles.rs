@@ -0,0 +1,220 @@
+use std::collections::{HashMap, VecDeque};
+use glam::Vec2;
+
+use crate::game_engine::audio::SoundId;
+use crate::game_engine::ecs::World;
+use crate::game_engine::graphics::color::Color;
+use super::font::{FontId, Fonts};
+use super::rich_text::{RichText, RunContent, RunStyle, TextAlign};
+use super::ui_draw::UiDraw;
+
+// One line of dialogue or a sound description. `text` is a key into the subtitles' string table,
+// so captions follow the game's language; keys with no translation are shown as they are. The
+// text may use rich text markup (see `parse_markup`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Caption {
+  pub text: String,
+  pub speaker: Option<String>, // shown before the line, in the speaker's colour
+  pub duration: f32, // seconds
+  pub audio: Option<SoundId>, // the sound voicing the line; see `Subtitles::audio_finished`
+}
+
+impl Caption {
+  pub fn new(text: impl Into<String>, duration: f32) -> Self {
+    Caption { text: text.into(), speaker: None, duration, audio: None }
+  }
+
+  pub fn with_speaker(mut self, speaker: impl Into<String>) -> Self {
+    self.speaker = Some(speaker.into());
+    self
+  }
+
+  // Ties the caption to a playing sound: it stays up until the sound finishes rather than for
+  // `duration`, and disappears if the sound is stopped early.
+  pub fn with_audio(mut self, audio: SoundId) -> Self {
+    self.audio = Some(audio);
+    self
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubtitleStyle {
+  pub font: FontId,
+  pub size: f32, // in UI units, which `AccessibilitySettings::ui_scale` scales
+  pub text_color: Color,
+  pub background: Color, // a box behind each line; fully transparent for none
+  pub padding: f32,
+  pub bottom_margin: f32, // from the bottom of the window to the lowest caption
+  pub max_width: f32, // fraction of the window width before a caption wraps
+  pub max_visible: usize, // captions shown at once; older ones are pushed off
+  pub speaker_colors: HashMap<String, Color>,
+}
+
+impl Default for SubtitleStyle {
+  fn default() -> Self {
+    SubtitleStyle {
+      font: FontId(0),
+      size: 24.0,
+      text_color: Color::WHITE,
+      background: Color::rgba(0.0, 0.0, 0.0, 0.6),
+      padding: 6.0,
+      bottom_margin: 48.0,
+      max_width: 0.8,
+      max_visible: 2,
+      speaker_colors: HashMap::new(),
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+struct ActiveCaption {
+  caption: Caption,
+  remaining: f32,
+  elapsed: f32, // drives text effects
+}
+
+// Queues captions and shows them one after another at the bottom of the screen. Lives in the
+// world as a resource; `update_subtitles` advances and draws it each frame.
+#[derive(Debug, Clone)]
+pub struct Subtitles {
+  pub enabled: bool,
+  pub style: SubtitleStyle,
+  strings: HashMap<String, String>,
+  queue: VecDeque<Caption>,
+  active: VecDeque<ActiveCaption>,
+}
+
+impl Subtitles {
+  pub fn new() -> Self {
+    Subtitles {
+      enabled: true,
+      style: SubtitleStyle::default(),
+      strings: HashMap::new(),
+      queue: VecDeque::new(),
+      active: VecDeque::new(),
+    }
+  }
+
+  // Replaces the string table captions are looked up in, e.g. when the player changes language.
+  pub fn set_strings(&mut self, strings: HashMap<String, String>) {
+    self.strings = strings;
+  }
+
+  // Loads a string table from RON: `{ "key": "text", ... }`.
+  pub fn load_strings(&mut self, path: impl AsRef<std::path::Path>) -> Result<(), String> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).map_err(|err| format!("couldn't read {}: {}", path.display(), err))?;
+    self.strings = ron::from_str(&text).map_err(|err| format!("bad string table {}: {}", path.display(), err))?;
+    Ok(())
+  }
+
+  // Shows `caption` once the ones before it have finished.
+  pub fn queue(&mut self, caption: Caption) {
+    self.queue.push_back(caption);
+  }
+
+  // Shows `caption` straight away, alongside whatever is already up.
+  pub fn show(&mut self, caption: Caption) {
+    let remaining = caption.duration;
+    self.active.push_back(ActiveCaption { caption, remaining, elapsed: 0.0 });
+    while self.active.len() > self.style.max_visible.max(1) {
+      self.active.pop_front();
+    }
+  }
+
+  // Ends the oldest caption on screen early.
+  pub fn skip(&mut self) {
+    self.active.pop_front();
+  }
+
+  pub fn clear(&mut self) {
+    self.queue.clear();
+    self.active.clear();
+  }
+
+  // Removes captions voiced by `audio`, whether it finished or was stopped. The engine calls it for
+  // every sound the world's `Audio` finishes.
+  pub fn audio_finished(&mut self, audio: SoundId) {
+    self.active.retain(|active| active.caption.audio != Some(audio));
+    self.queue.retain(|caption| caption.audio != Some(audio));
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.queue.is_empty() && self.active.is_empty()
+  }
+
+  // Looks a caption's text up in the string table.
+  pub fn localize<'a>(&'a self, key: &'a str) -> &'a str {
+    self.strings.get(key).map_or(key, String::as_str)
+  }
+
+  pub fn update(&mut self, dt: f32) {
+    for active in &mut self.active {
+      active.elapsed += dt;
+      // Voiced captions last as long as their audio.
+      if active.caption.audio.is_none() {
+        active.remaining -= dt;
+      }
+    }
+    self.active.retain(|active| active.remaining > 0.0);
+    if self.active.is_empty() {
+      if let Some(caption) = self.queue.pop_front() {
+        self.show(caption);
+      }
+    }
+  }
+
+  // The captions on screen, oldest first, as (speaker, localized text).
+  pub fn visible(&self) -> impl Iterator<Item = (Option<&str>, &str)> {
+    self.active.iter().map(|active| (active.caption.speaker.as_deref(), self.localize(&active.caption.text)))
+  }
+
+  // Queues the visible captions into `ui`, centred above the bottom of the window.
+  pub fn draw(&self, ui: &mut UiDraw, fonts: &Fonts) {
+    let style = &self.style;
+    if !self.enabled || fonts.get(style.font).is_none() {
+      return;
+    }
+    let (size, padding) = (style.size, style.padding);
+    let max_width = (ui.viewport.x * style.max_width - padding * 2.0).max(size);
+    let mut bottom = ui.viewport.y - style.bottom_margin;
+
+    for active in self.active.iter().rev() {
+      let mut text = RichText::new(style.font, size, style.text_color)
+        .with_max_width(max_width)
+        .with_align(TextAlign::Center);
+      if let Some(speaker) = &active.caption.speaker {
+        let color = style.speaker_colors.get(speaker).copied();
+        text.push(RunContent::Text(format!("{}: ", speaker)), RunStyle { color, ..RunStyle::default() });
+      }
+      text.push_markup(self.localize(&active.caption.text));
+
+      let box_size = text.layout(fonts).size + Vec2::splat(padding * 2.0);
+      let top_left = Vec2::new((ui.viewport.x - box_size.x) / 2.0, bottom - box_size.y);
+      if style.background.a > 0.0 {
+        ui.rect(top_left, top_left + box_size, style.background);
+      }
+      ui.rich_text(text, top_left + Vec2::splat(padding), active.elapsed);
+      bottom = top_left.y - padding;
+    }
+  }
+}
+
+impl Default for Subtitles {
+  fn default() -> Self {
+    Subtitles::new()
+  }
+}
+
+// Advances the world's `Subtitles` and draws them into its `UiDraw`. The engine calls it every
+// frame.
+pub fn update_subtitles(world: &World, dt: f32) {
+  let mut subtitles = match world.get_resource_mut::<Subtitles>() {
+    Some(subtitles) => subtitles,
+    None => return,
+  };
+  subtitles.update(dt);
+  if let (Some(mut ui), Some(fonts)) = (world.get_resource_mut::<UiDraw>(), world.get_resource::<Fonts>()) {
+    subtitles.draw(&mut ui, &fonts);
+  }
+}
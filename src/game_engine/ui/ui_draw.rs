@@ -0,0 +1,69 @@
+use glam::Vec2;
+
+use crate::game_engine::graphics::color::Color;
+use super::font::FontId;
+use super::rich_text::RichText;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UiCommand {
+  Rect { min: Vec2, max: Vec2, color: Color },
+  // `position` is the top-left of the first line.
+  Text { font: FontId, text: String, position: Vec2, size: f32, color: Color },
+  // `time` drives wave and shake effects, in seconds.
+  RichText { text: RichText, position: Vec2, time: f32 },
+}
+
+// Immediate-mode 2D drawing in UI units (origin top-left), on top of everything else. Lives in the
+// world as a resource: anything can queue rectangles and text during the frame, and the renderer
+// draws them in order and clears the list. A UI unit is `scale` window pixels, so everything drawn
+// here grows with `AccessibilitySettings::ui_scale`.
+pub struct UiDraw {
+  pub enabled: bool,
+  pub viewport: Vec2, // the window size in UI units, kept up to date by the renderer
+  pub scale: f32, // window pixels per UI unit, kept up to date from the accessibility settings
+  commands: Vec<UiCommand>,
+}
+
+impl UiDraw {
+  pub fn new() -> Self {
+    UiDraw { enabled: true, viewport: Vec2::new(800.0, 600.0), scale: 1.0, commands: Vec::new() }
+  }
+
+  pub fn rect(&mut self, min: Vec2, max: Vec2, color: Color) {
+    if self.enabled {
+      self.commands.push(UiCommand::Rect { min, max, color });
+    }
+  }
+
+  pub fn text(&mut self, font: FontId, text: impl Into<String>, position: Vec2, size: f32, color: Color) {
+    if self.enabled {
+      self.commands.push(UiCommand::Text { font, text: text.into(), position, size, color });
+    }
+  }
+
+  pub fn rich_text(&mut self, text: RichText, position: Vec2, time: f32) {
+    if self.enabled {
+      self.commands.push(UiCommand::RichText { text, position, time });
+    }
+  }
+
+  pub fn commands(&self) -> &[UiCommand] {
+    &self.commands
+  }
+
+  pub fn clear(&mut self) {
+    self.commands.clear();
+  }
+
+  // Lays the UI out over a `window` pixels wide and high, `scale` pixels to a UI unit.
+  pub(crate) fn fit(&mut self, window: Vec2, scale: f32) {
+    self.scale = scale;
+    self.viewport = window / scale;
+  }
+}
+
+impl Default for UiDraw {
+  fn default() -> Self {
+    UiDraw::new()
+  }
+}
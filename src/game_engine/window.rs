@@ -0,0 +1,430 @@
+use std::path::{Path, PathBuf};
+use glam::{IVec2, UVec2, Vec2};
+use winit::dpi::{LogicalSize, PhysicalPosition};
+use winit::monitor::{MonitorHandle, VideoMode};
+use winit::window::{CursorGrabMode, Fullscreen, Icon, Window, WindowBuilder};
+
+use super::graphics::texture::TextureHandle;
+use super::input::Input;
+
+pub use winit::window::CursorIcon;
+
+// How the window opens, for `EngineConfig::window`. Sizes are in logical pixels, so they're the
+// same physical size on high-DPI screens.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowConfig {
+  pub title: String,
+  pub size: UVec2,
+  pub min_size: Option<UVec2>,
+  pub resizable: bool,
+  pub fullscreen: FullscreenMode,
+  pub monitor: Option<usize>, // which of `GameWindow::monitors` to go fullscreen on; `None` is the primary
+  pub display_mode: Option<DisplayMode>, // for exclusive fullscreen; `None` is the monitor's best
+  pub icon: Option<PathBuf>, // an image file; ignored on the web, where the page has its own
+}
+
+impl Default for WindowConfig {
+  fn default() -> Self {
+    WindowConfig {
+      title: "Basic Game Engine".to_string(),
+      size: UVec2::new(800, 600),
+      min_size: None,
+      resizable: true,
+      fullscreen: FullscreenMode::Windowed,
+      monitor: None,
+      display_mode: None,
+      icon: None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FullscreenMode {
+  #[default]
+  Windowed,
+  Borderless, // a window without decorations covering the screen, which switches away quickly
+  // Takes over the screen at `GameWindow::display_mode`. Falls back to borderless where that isn't
+  // possible, like on the web.
+  Exclusive,
+}
+
+// One of the displays the window can go on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonitorInfo {
+  pub name: Option<String>,
+  pub position: IVec2, // of its top-left corner on the desktop, in physical pixels
+  pub size: UVec2, // in physical pixels
+  pub scale_factor: f32,
+  pub refresh_rate: Option<f32>, // in hertz, where the platform says
+  pub display_modes: Vec<DisplayMode>, // what exclusive fullscreen can switch it to, best first
+}
+
+impl MonitorInfo {
+  fn new(monitor: &MonitorHandle) -> Self {
+    let mut display_modes: Vec<DisplayMode> = monitor.video_modes().map(|mode| DisplayMode::new(&mode)).collect();
+    display_modes.sort_by_key(|mode| std::cmp::Reverse(mode.key()));
+    display_modes.dedup();
+    let (position, size) = (monitor.position(), monitor.size());
+    MonitorInfo {
+      name: monitor.name(),
+      position: IVec2::new(position.x, position.y),
+      size: UVec2::new(size.width, size.height),
+      scale_factor: monitor.scale_factor() as f32,
+      refresh_rate: monitor.refresh_rate_millihertz().map(|rate| rate as f32 / 1000.0),
+      display_modes,
+    }
+  }
+}
+
+// A resolution and refresh rate a monitor can be switched to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayMode {
+  pub size: UVec2, // in physical pixels
+  pub refresh_rate: f32, // in hertz
+  pub bit_depth: u16,
+}
+
+impl DisplayMode {
+  fn new(mode: &VideoMode) -> Self {
+    let size = mode.size();
+    DisplayMode { size: UVec2::new(size.width, size.height), refresh_rate: mode.refresh_rate_millihertz() as f32 / 1000.0, bit_depth: mode.bit_depth() }
+  }
+
+  // Bigger is better: more pixels, then a faster refresh, then more colour.
+  fn key(&self) -> (u64, u32, u16) {
+    (self.size.x as u64 * self.size.y as u64, (self.refresh_rate * 1000.0).round() as u32, self.bit_depth)
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CursorMode {
+  #[default]
+  Free,
+  Confined, // kept inside the window
+  // Held in place for mouse-look, with movement read from `Input::mouse_delta`. Where the platform
+  // can't lock the cursor it's confined and put back in the middle of the window every frame.
+  Relative,
+}
+
+// An image drawn as the cursor in place of the system's, from one of the world's `TextureManager`
+// textures. `hotspot` is the pixel of the image that points.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CursorImage {
+  pub texture: TextureHandle,
+  pub hotspot: Vec2,
+  pub scale: f32,
+}
+
+impl CursorImage {
+  pub fn new(texture: TextureHandle, hotspot: Vec2) -> Self {
+    CursorImage { texture, hotspot, scale: 1.0 }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct WindowState {
+  title: String,
+  min_size: Option<UVec2>,
+  resizable: bool,
+  fullscreen: FullscreenMode,
+  monitor: Option<usize>,
+  display_mode: Option<DisplayMode>,
+  icon: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct CursorState {
+  mode: CursorMode,
+  visible: bool,
+  icon: CursorIcon,
+}
+
+// How the game wants its window, as `Engine::window`. The engine applies it after every frame and
+// copies it into the active world as a resource. While the window doesn't have focus the cursor is
+// let go and shown, so the player can use other windows, and taken back as soon as focus returns.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameWindow {
+  pub title: String,
+  pub min_size: Option<UVec2>, // in logical pixels
+  pub resizable: bool,
+  pub fullscreen: FullscreenMode,
+  pub monitor: Option<usize>, // which of `monitors` to go fullscreen on; `None` is the one the window's on
+  pub display_mode: Option<DisplayMode>, // for exclusive fullscreen, from the monitor's `display_modes`
+  pub icon: Option<PathBuf>,
+  pub cursor_mode: CursorMode,
+  pub cursor_visible: bool,
+  pub cursor_icon: CursorIcon,
+  pub cursor_image: Option<CursorImage>, // hides the system cursor and draws this instead
+  focused: bool,
+  size: UVec2, // drawable, in physical pixels
+  scale_factor: f32,
+  monitors: Vec<MonitorInfo>,
+  current_monitor: Option<usize>, // the one the window's on
+  applied_window: Option<WindowState>, // what the window was last set to
+  applied: Option<CursorState>,
+  applied_text_input: Option<(bool, Option<Vec2>)>,
+  locked: bool, // whether relative mode got a real lock, or has to recentre
+}
+
+impl GameWindow {
+  pub fn new(config: &WindowConfig) -> Self {
+    GameWindow {
+      title: config.title.clone(),
+      min_size: config.min_size,
+      resizable: config.resizable,
+      fullscreen: config.fullscreen,
+      monitor: config.monitor,
+      display_mode: config.display_mode,
+      icon: config.icon.clone(),
+      cursor_mode: CursorMode::Free,
+      cursor_visible: true,
+      cursor_icon: CursorIcon::Default,
+      cursor_image: None,
+      focused: true,
+      size: UVec2::ZERO,
+      scale_factor: 1.0,
+      monitors: Vec::new(),
+      current_monitor: None,
+      applied_window: None,
+      applied: None,
+      applied_text_input: None,
+      locked: false,
+    }
+  }
+
+  // The drawable size, in physical pixels, which is what the scene is rendered at.
+  pub fn size(&self) -> UVec2 {
+    self.size
+  }
+
+  // The size in logical pixels, which on high-DPI screens is smaller than `size`.
+  pub fn logical_size(&self) -> Vec2 {
+    self.size.as_vec2() / self.scale_factor
+  }
+
+  // Physical pixels per logical pixel on the window's monitor.
+  pub fn scale_factor(&self) -> f32 {
+    self.scale_factor
+  }
+
+  // The displays attached, as of when the window last moved or got focus. Empty until the window's
+  // open, and always on the web.
+  pub fn monitors(&self) -> &[MonitorInfo] {
+    &self.monitors
+  }
+
+  // The index in `monitors` of the one the window's on.
+  pub fn current_monitor(&self) -> Option<usize> {
+    self.current_monitor
+  }
+
+  // The refresh rate of the monitor the window's on, in hertz.
+  pub fn refresh_rate(&self) -> Option<f32> {
+    self.current_monitor.and_then(|index| self.monitors.get(index)?.refresh_rate)
+  }
+
+  pub fn set_fullscreen(&mut self, mode: FullscreenMode) {
+    self.fullscreen = mode;
+  }
+
+  // Switches between windowed and `mode`.
+  pub fn toggle_fullscreen(&mut self, mode: FullscreenMode) {
+    self.fullscreen = if self.fullscreen == FullscreenMode::Windowed { mode } else { FullscreenMode::Windowed };
+  }
+
+  pub fn is_fullscreen(&self) -> bool {
+    self.fullscreen != FullscreenMode::Windowed
+  }
+
+  // Hides the cursor and switches to relative mode, for first-person cameras.
+  pub fn grab_cursor(&mut self) {
+    self.cursor_mode = CursorMode::Relative;
+    self.cursor_visible = false;
+  }
+
+  // Undoes `grab_cursor`.
+  pub fn release_cursor(&mut self) {
+    self.cursor_mode = CursorMode::Free;
+    self.cursor_visible = true;
+  }
+
+  pub fn is_cursor_grabbed(&self) -> bool {
+    self.cursor_mode == CursorMode::Relative
+  }
+
+  pub fn is_focused(&self) -> bool {
+    self.focused
+  }
+
+  // The custom cursor to draw this frame, if there is one and the cursor's on show.
+  pub fn visible_cursor_image(&self) -> Option<CursorImage> {
+    self.cursor_image.filter(|_| self.cursor_visible && self.cursor_mode != CursorMode::Relative)
+  }
+
+  pub(crate) fn set_focused(&mut self, focused: bool) {
+    self.focused = focused;
+  }
+
+  // Lists the monitors again, returning whether the current one's refresh rate changed.
+  pub(crate) fn update_monitors(&mut self, window: &Window) -> bool {
+    let refresh_rate = self.refresh_rate();
+    let current = window.current_monitor();
+    let handles: Vec<MonitorHandle> = window.available_monitors().collect();
+    self.monitors = handles.iter().map(MonitorInfo::new).collect();
+    self.current_monitor = current.and_then(|current| handles.iter().position(|handle| *handle == current));
+    self.refresh_rate() != refresh_rate
+  }
+
+  // Whether the window's gone onto another monitor, cheaply enough to check while it's dragged.
+  pub(crate) fn changed_monitor(&self, window: &Window) -> bool {
+    let known = self.current_monitor.and_then(|index| self.monitors.get(index)).map(|monitor| monitor.position);
+    let current = window.current_monitor().map(|monitor| IVec2::new(monitor.position().x, monitor.position().y));
+    known != current
+  }
+
+  pub(crate) fn set_size(&mut self, size: UVec2, scale_factor: f32) {
+    self.size = size;
+    self.scale_factor = scale_factor;
+  }
+
+  // A builder for the window as it's set up now, of `size`, which `apply` then leaves alone.
+  pub(crate) fn builder(&mut self, size: UVec2, monitors: Vec<MonitorHandle>, primary: Option<MonitorHandle>) -> WindowBuilder {
+    let state = self.window_state();
+    let monitor = state.monitor.and_then(|index| monitors.get(index).cloned()).or(primary);
+    let builder = WindowBuilder::new()
+      .with_title(&state.title)
+      .with_inner_size(LogicalSize::new(size.x, size.y))
+      .with_resizable(state.resizable)
+      .with_fullscreen(fullscreen(state.fullscreen, monitor, state.display_mode))
+      .with_window_icon(state.icon.as_deref().and_then(load_icon));
+    let builder = match state.min_size {
+      Some(min_size) => builder.with_min_inner_size(LogicalSize::new(min_size.x, min_size.y)),
+      None => builder,
+    };
+    self.applied_window = Some(state);
+    builder
+  }
+
+  fn window_state(&self) -> WindowState {
+    WindowState {
+      title: self.title.clone(),
+      min_size: self.min_size,
+      resizable: self.resizable,
+      fullscreen: self.fullscreen,
+      monitor: self.monitor,
+      display_mode: self.display_mode,
+      icon: self.icon.clone(),
+    }
+  }
+
+  // Turns the platform's input method on and off with `Input`'s text input.
+  pub(crate) fn apply_text_input(&mut self, window: &Window, input: &Input) {
+    let wanted = (input.is_text_input(), input.text_input_position());
+    let applied = self.applied_text_input.replace(wanted);
+    if applied.is_none_or(|applied| applied.0 != wanted.0) {
+      window.set_ime_allowed(wanted.0);
+    }
+    if let (true, Some(position)) = (wanted.0, wanted.1) {
+      if applied != Some(wanted) {
+        window.set_ime_position(PhysicalPosition::new(position.x, position.y));
+      }
+    }
+  }
+
+  // Makes the window match, touching only what changed.
+  pub(crate) fn apply(&mut self, window: &Window) {
+    let state = self.window_state();
+    let applied_window = self.applied_window.replace(state.clone());
+    if applied_window.as_ref().is_none_or(|applied| applied.title != state.title) {
+      window.set_title(&state.title);
+    }
+    if applied_window.as_ref().is_none_or(|applied| applied.min_size != state.min_size) {
+      window.set_min_inner_size(state.min_size.map(|min_size| LogicalSize::new(min_size.x, min_size.y)));
+    }
+    if applied_window.as_ref().is_none_or(|applied| applied.resizable != state.resizable) {
+      window.set_resizable(state.resizable);
+    }
+    let fullscreen_changed = applied_window.as_ref().is_none_or(|applied| {
+      applied.fullscreen != state.fullscreen || (state.fullscreen != FullscreenMode::Windowed && (applied.monitor != state.monitor || applied.display_mode != state.display_mode))
+    });
+    if fullscreen_changed {
+      let monitor = state.monitor.and_then(|index| window.available_monitors().nth(index)).or_else(|| window.current_monitor());
+      window.set_fullscreen(fullscreen(state.fullscreen, monitor, state.display_mode));
+    }
+    if applied_window.as_ref().is_none_or(|applied| applied.icon != state.icon) {
+      window.set_window_icon(state.icon.as_deref().and_then(load_icon));
+    }
+
+    let wanted = if self.focused {
+      CursorState { mode: self.cursor_mode, visible: self.cursor_visible && self.cursor_image.is_none(), icon: self.cursor_icon }
+    } else {
+      CursorState { mode: CursorMode::Free, visible: true, icon: CursorIcon::Default }
+    };
+    let applied = self.applied.replace(wanted);
+    if applied.is_none_or(|applied| applied.mode != wanted.mode) {
+      self.locked = false;
+      // Each platform has one of the grab modes but not always the other.
+      let result = match wanted.mode {
+        CursorMode::Free => window.set_cursor_grab(CursorGrabMode::None),
+        CursorMode::Confined => window.set_cursor_grab(CursorGrabMode::Confined).or_else(|_| window.set_cursor_grab(CursorGrabMode::Locked)),
+        CursorMode::Relative => match window.set_cursor_grab(CursorGrabMode::Locked) {
+          Ok(()) => {
+            self.locked = true;
+            Ok(())
+          }
+          Err(_) => window.set_cursor_grab(CursorGrabMode::Confined),
+        },
+      };
+      if let Err(err) = result {
+        log::warn!("couldn't set the cursor to {:?}: {}", wanted.mode, err);
+      }
+    }
+    if applied.is_none_or(|applied| applied.visible != wanted.visible) {
+      window.set_cursor_visible(wanted.visible);
+    }
+    if applied.is_none_or(|applied| applied.icon != wanted.icon) {
+      window.set_cursor_icon(wanted.icon);
+    }
+    if wanted.mode == CursorMode::Relative && !self.locked {
+      let size = window.inner_size();
+      // Not every platform can move the cursor; then it's only confined.
+      let _ = window.set_cursor_position(PhysicalPosition::new(size.width / 2, size.height / 2));
+    }
+  }
+}
+
+impl Default for GameWindow {
+  fn default() -> Self {
+    GameWindow::new(&WindowConfig::default())
+  }
+}
+
+fn fullscreen(mode: FullscreenMode, monitor: Option<MonitorHandle>, display_mode: Option<DisplayMode>) -> Option<Fullscreen> {
+  match mode {
+    FullscreenMode::Windowed => None,
+    FullscreenMode::Borderless => Some(Fullscreen::Borderless(monitor)),
+    FullscreenMode::Exclusive => {
+      let modes: Vec<VideoMode> = monitor.as_ref().map_or(Vec::new(), |monitor| monitor.video_modes().collect());
+      // The asked-for size at the nearest refresh rate, or else the best the monitor has.
+      let chosen = display_mode.and_then(|wanted| modes.iter().filter(|mode| DisplayMode::new(mode).size == wanted.size).min_by(|a, b| {
+        let distance = |mode: &VideoMode| (DisplayMode::new(mode).refresh_rate - wanted.refresh_rate).abs();
+        distance(a).total_cmp(&distance(b))
+      }));
+      let best = chosen.or_else(|| modes.iter().max_by_key(|mode| DisplayMode::new(mode).key()));
+      Some(best.cloned().map_or(Fullscreen::Borderless(monitor), Fullscreen::Exclusive))
+    }
+  }
+}
+
+// A missing icon is logged rather than stopping the game.
+fn load_icon(path: &Path) -> Option<Icon> {
+  if cfg!(target_arch = "wasm32") {
+    return None;
+  }
+  let icon = image::open(path).map_err(|err| err.to_string()).and_then(|image| {
+    let image = image.to_rgba8();
+    let (width, height) = image.dimensions();
+    Icon::from_rgba(image.into_raw(), width, height).map_err(|err| err.to_string())
+  });
+  icon.map_err(|err| log::warn!("couldn't load the window icon {}: {}", path.display(), err)).ok()
+}
@@ -1,14 +1,15 @@
-mod game_engine;
+pub mod game_engine;
 
 #[cfg(target_arch="wasm32")]
 use wasm_bindgen::prelude::*;
 
 use game_engine::Engine;
-use crate::game_engine::MainLoopFn;
 
 #[cfg_attr(target_arch="wasm32", wasm_bindgen(start))]
 pub fn main() {
-  Engine::run(|engine| {
+  if let Err(err) = Engine::run(|_engine| {
     Ok(())
-  });
+  }) {
+    log::error!("{}", err);
+  }
 }